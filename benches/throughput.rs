@@ -0,0 +1,252 @@
+//! Benchmarks of the per-packet costs, for tracking regressions with `cargo bench`.
+//!
+//! * `token` - generating a connect token, and encrypting and decrypting its private part.
+//! * `packet` - a payload packet from a client to a server, encrypted, sent in memory, decrypted and received.
+//! * `echo` - payloads echoed by the server back to the client, in bytes per second.
+//! * `server_tick` - one server update while every connected client sends a payload, by the number of clients.
+//!
+//! Compare against a saved baseline with `cargo bench -- --save-baseline main` and `cargo bench -- --baseline main`.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::hint::black_box;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use netcode::testing::{channel_pair, ChannelTransceiver};
+use netcode::token_crypto::{self, PrivateConnectToken};
+use netcode::{Client, ClientConfig, ConnectToken, Server, ServerConfig, Transceiver};
+
+const PROTOCOL_ID: u64 = 0x11223344;
+const TICK: f64 = 1.0 / 60.0;
+const PAYLOAD_SIZES: [usize; 3] = [64, 512, 1100];
+
+fn token(c: &mut Criterion) {
+    let mut group = c.benchmark_group("token");
+    let private_key = netcode::generate_key();
+    let server_addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+    group.bench_function("generate", |b| {
+        b.iter(|| {
+            ConnectToken::build(server_addr, PROTOCOL_ID, black_box(123), private_key)
+                .generate_at(1_700_000_000)
+                .unwrap()
+        })
+    });
+    let private = PrivateConnectToken::new(123, vec![server_addr]);
+    let nonce = token_crypto::generate_nonce();
+    group.bench_function("encrypt_private", |b| {
+        b.iter(|| {
+            black_box(&private)
+                .encrypt(PROTOCOL_ID, 1_700_000_030, &nonce, &private_key)
+                .unwrap()
+        })
+    });
+    let encrypted = private
+        .encrypt(PROTOCOL_ID, 1_700_000_030, &nonce, &private_key)
+        .unwrap();
+    group.bench_function("decrypt_private", |b| {
+        b.iter(|| {
+            PrivateConnectToken::decrypt(
+                black_box(&encrypted),
+                PROTOCOL_ID,
+                1_700_000_030,
+                &nonce,
+                &private_key,
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+// A server with one connected client over an in-memory link.
+fn connected_pair() -> (Server<ChannelTransceiver>, Client<ChannelTransceiver>, f64) {
+    let (server_trx, client_trx) = channel_pair();
+    let mut server = Server::with_config_and_transceiver(
+        PROTOCOL_ID,
+        netcode::generate_key(),
+        ServerConfig::default(),
+        server_trx,
+    )
+    .unwrap();
+    let token = server
+        .token(1)
+        .generate()
+        .unwrap()
+        .try_into_bytes()
+        .unwrap();
+    let mut client =
+        Client::with_config_and_transceiver(&token, ClientConfig::default(), client_trx).unwrap();
+    client.connect();
+    let mut time = 0.0;
+    while !client.is_connected() {
+        client.update(time);
+        server.update(time);
+        time += TICK;
+    }
+    (server, client, time)
+}
+
+fn packet(c: &mut Criterion) {
+    let mut group = c.benchmark_group("packet");
+    for size in PAYLOAD_SIZES {
+        let (mut server, mut client, mut time) = connected_pair();
+        let payload = vec![7u8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(
+            BenchmarkId::new("round_trip", size),
+            &payload,
+            |b, payload| {
+                b.iter(|| {
+                    client.send(payload).unwrap();
+                    server.update(time);
+                    time += 1e-6;
+                    black_box(server.recv().expect("the payload arrives"))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn echo(c: &mut Criterion) {
+    const BATCH: usize = 32;
+    let mut group = c.benchmark_group("echo");
+    for size in PAYLOAD_SIZES {
+        let (mut server, mut client, mut time) = connected_pair();
+        let payload = vec![7u8; size];
+        group.throughput(Throughput::Bytes((BATCH * size) as u64));
+        group.bench_with_input(
+            BenchmarkId::new("throughput", size),
+            &payload,
+            |b, payload| {
+                b.iter(|| {
+                    for _ in 0..BATCH {
+                        client.send(payload).unwrap();
+                    }
+                    server.update(time);
+                    while let Some((payload, idx)) = server.recv() {
+                        server.send(&payload, idx).unwrap();
+                    }
+                    client.update(time);
+                    time += 1e-6;
+                    let mut echoed = 0;
+                    while client.recv().is_some() {
+                        echoed += 1;
+                    }
+                    assert_eq!(echoed, BATCH);
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+// An in-memory network of many endpoints, for a server with more than one client.
+type Inboxes = Rc<RefCell<HashMap<SocketAddr, VecDeque<(Vec<u8>, SocketAddr)>>>>;
+
+struct HubTransceiver {
+    addr: SocketAddr,
+    inboxes: Inboxes,
+}
+
+impl HubTransceiver {
+    fn new(addr: SocketAddr, inboxes: &Inboxes) -> Self {
+        inboxes.borrow_mut().insert(addr, VecDeque::new());
+        Self {
+            addr,
+            inboxes: inboxes.clone(),
+        }
+    }
+}
+
+impl Transceiver for HubTransceiver {
+    type IntoError = std::io::Error;
+
+    fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+    fn recv(&self, buf: &mut [u8]) -> std::io::Result<Option<(usize, SocketAddr)>> {
+        let mut inboxes = self.inboxes.borrow_mut();
+        let Some((packet, from)) = inboxes.get_mut(&self.addr).and_then(VecDeque::pop_front) else {
+            return Ok(None);
+        };
+        buf[..packet.len()].copy_from_slice(&packet);
+        Ok(Some((packet.len(), from)))
+    }
+    fn send(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+        if let Some(inbox) = self.inboxes.borrow_mut().get_mut(&addr) {
+            inbox.push_back((buf.to_vec(), self.addr));
+        }
+        Ok(buf.len())
+    }
+}
+
+fn server_tick(c: &mut Criterion) {
+    let mut group = c.benchmark_group("server_tick");
+    for num_clients in [1, 16, 64, 128, 256] {
+        let inboxes = Inboxes::default();
+        let server_addr = SocketAddr::from(([10, 0, 0, 1], 40000));
+        let mut server = Server::with_config_and_transceiver(
+            PROTOCOL_ID,
+            netcode::generate_key(),
+            ServerConfig::default(),
+            HubTransceiver::new(server_addr, &inboxes),
+        )
+        .unwrap();
+        let mut clients: Vec<_> = (0..num_clients)
+            .map(|i| {
+                let token = server
+                    .token(i)
+                    .generate()
+                    .unwrap()
+                    .try_into_bytes()
+                    .unwrap();
+                let addr = SocketAddr::from(([10, 0, 1, 1], 50000 + i as u16));
+                let trx = HubTransceiver::new(addr, &inboxes);
+                let mut client =
+                    Client::with_config_and_transceiver(&token, ClientConfig::default(), trx)
+                        .unwrap();
+                client.connect();
+                client
+            })
+            .collect();
+        let mut time = 0.0;
+        while clients.iter().any(|client| !client.is_connected()) {
+            for client in &mut clients {
+                client.update(time);
+            }
+            server.update(time);
+            time += TICK;
+        }
+        assert_eq!(server.num_connected_clients(), num_clients as usize);
+
+        let payload = [7u8; 64];
+        group.throughput(Throughput::Elements(num_clients));
+        group.bench_function(BenchmarkId::from_parameter(num_clients), |b| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    for client in &mut clients {
+                        client.send(&payload).unwrap();
+                        client.update(time);
+                    }
+                    let start = Instant::now();
+                    server.update(time);
+                    while let Some(received) = server.recv() {
+                        black_box(received);
+                    }
+                    elapsed += start.elapsed();
+                    time += TICK;
+                }
+                elapsed
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, token, packet, echo, server_tick);
+criterion_main!(benches);
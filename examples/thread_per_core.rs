@@ -0,0 +1,91 @@
+//! Runs one server shard per core, each owning its own socket, and directs clients to their shard.
+//!
+//! With a thread-per-core runtime the shard loop below runs inside the runtime's executor for that core instead,
+//! e.g. `glommio::LocalExecutorBuilder::new(Placement::Fixed(shard)).spawn(..)` or a `monoio` runtime per thread.
+//! Each executor owns its `Server` exclusively, so no locking is needed.
+use std::{
+    net::SocketAddr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use netcode::{Client, Server, ShardMap};
+
+const PROTOCOL_ID: u64 = 0x11223344;
+const NUM_CLIENTS: u64 = 8;
+
+fn main() {
+    env_logger::Builder::new()
+        .filter(None, log::LevelFilter::Info)
+        .init();
+
+    let num_shards = thread::available_parallelism().map_or(1, |n| n.get().min(4));
+    let shards = ShardMap::new(
+        (0..num_shards).map(|i| SocketAddr::from(([127, 0, 0, 1], 42000 + i as u16))),
+    );
+    let private_key = netcode::generate_key();
+
+    let start = Instant::now();
+    let tick_rate = Duration::from_secs_f64(1.0 / 60.0);
+
+    let shard_threads: Vec<_> = (0..shards.len())
+        .map(|shard| {
+            let addr = shards.addr(shard);
+            let expected = (0..NUM_CLIENTS)
+                .filter(|&id| shards.shard(id) == shard)
+                .count();
+            thread::spawn(move || {
+                let mut server = Server::new(addr, PROTOCOL_ID, private_key).unwrap();
+                let mut received = 0;
+                while received < expected {
+                    server.update(start.elapsed().as_secs_f64());
+                    while let Some((packet, client_idx)) = server.recv() {
+                        println!(
+                            "shard {shard} received: {}",
+                            String::from_utf8_lossy(&packet)
+                        );
+                        server.send(&packet, client_idx).unwrap();
+                        received += 1;
+                    }
+                    thread::sleep(tick_rate);
+                }
+                // keep serving a little longer so the echoes get delivered
+                for _ in 0..10 {
+                    server.update(start.elapsed().as_secs_f64());
+                    thread::sleep(tick_rate);
+                }
+            })
+        })
+        .collect();
+
+    let mut clients: Vec<_> = (0..NUM_CLIENTS)
+        .map(|client_id| {
+            let token = shards
+                .token(client_id, PROTOCOL_ID, private_key)
+                .generate()
+                .unwrap();
+            let mut client = Client::new(&token.try_into_bytes().unwrap()).unwrap();
+            client.connect();
+            (client_id, client, false)
+        })
+        .collect();
+
+    while clients.iter().any(|(_, _, echoed)| !echoed) {
+        for (client_id, client, echoed) in clients.iter_mut() {
+            client.update(start.elapsed().as_secs_f64());
+            if client.is_connected() && !*echoed {
+                client
+                    .send(format!("hello from client {client_id}").as_bytes())
+                    .unwrap();
+            }
+            if client.recv().is_some() {
+                *echoed = true;
+            }
+        }
+        thread::sleep(tick_rate);
+    }
+
+    for thread in shard_threads {
+        thread.join().unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+//! A browser client that sends its netcode packets as WebTransport datagrams.
+//!
+//! Browsers can't send UDP packets, so a gateway in front of the server accepts a WebTransport session per player
+//! and forwards the datagrams of the session to the server as UDP packets (and back), one UDP socket per session.
+//! The netcode packets are already encrypted and signed, so the gateway doesn't need any of the keys.
+//!
+//! Build with `cargo build --example webtransport --target wasm32-unknown-unknown`
+//! and load it in a page with [`wasm-bindgen`](https://wasm-bindgen.github.io/wasm-bindgen/).
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::{
+        cell::{Cell, RefCell},
+        collections::VecDeque,
+        net::{Ipv4Addr, SocketAddr},
+        rc::Rc,
+    };
+
+    use js_sys::{Promise, Reflect, Uint8Array};
+    use netcode::{Client, ClientConfig, Transceiver};
+    use wasm_bindgen::{prelude::*, JsCast};
+    use wasm_bindgen_futures::{spawn_local, JsFuture};
+    use web_sys::{
+        ReadableStream, ReadableStreamDefaultReader, Response, WritableStream,
+        WritableStreamDefaultWriter,
+    };
+
+    const GATEWAY_URL: &str = "https://gateway.example.com:4433/netcode";
+    const TOKEN_URL: &str = "/api/connect-token";
+    const TICK_RATE_MS: i32 = 1000 / 60;
+
+    // The `web-sys` bindings of WebTransport are behind `--cfg=web_sys_unstable_apis`, these are the few parts we use.
+    #[wasm_bindgen]
+    extern "C" {
+        type WebTransport;
+        #[wasm_bindgen(constructor, catch)]
+        fn new(url: &str) -> Result<WebTransport, JsValue>;
+        #[wasm_bindgen(method, getter)]
+        fn ready(this: &WebTransport) -> Promise;
+        #[wasm_bindgen(method, getter)]
+        fn datagrams(this: &WebTransport) -> DatagramDuplexStream;
+
+        #[wasm_bindgen(js_name = WebTransportDatagramDuplexStream)]
+        type DatagramDuplexStream;
+        #[wasm_bindgen(method, getter)]
+        fn readable(this: &DatagramDuplexStream) -> ReadableStream;
+        #[wasm_bindgen(method, getter)]
+        fn writable(this: &DatagramDuplexStream) -> WritableStream;
+    }
+
+    /// A [`Transceiver`] over the datagrams of a WebTransport session.
+    ///
+    /// The gateway only ever forwards to one server, so received datagrams are reported as coming from the address the
+    /// client last sent to, which is the server address from the connect token the client is currently using.
+    pub struct WebTransportTransceiver {
+        writer: WritableStreamDefaultWriter,
+        received: Rc<RefCell<VecDeque<Vec<u8>>>>,
+        server_addr: Cell<SocketAddr>,
+    }
+
+    impl WebTransportTransceiver {
+        pub async fn connect(url: &str) -> Result<Self, JsValue> {
+            let transport = WebTransport::new(url)?;
+            JsFuture::from(transport.ready()).await?;
+            let datagrams = transport.datagrams();
+            let writer = datagrams.writable().get_writer()?;
+            let reader: ReadableStreamDefaultReader =
+                datagrams.readable().get_reader().unchecked_into();
+            let received = Rc::new(RefCell::new(VecDeque::new()));
+            // The datagrams arrive asynchronously, queue them until the client polls for them in `update`
+            let queue = received.clone();
+            spawn_local(async move {
+                while let Ok(result) = JsFuture::from(reader.read()).await {
+                    if Reflect::get(&result, &"done".into()).is_ok_and(|done| done.is_truthy()) {
+                        break;
+                    }
+                    if let Ok(value) = Reflect::get(&result, &"value".into()) {
+                        queue
+                            .borrow_mut()
+                            .push_back(Uint8Array::new(&value).to_vec());
+                    }
+                }
+            });
+            Ok(Self {
+                writer,
+                received,
+                server_addr: Cell::new(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))),
+            })
+        }
+    }
+
+    impl Transceiver for WebTransportTransceiver {
+        type IntoError = std::io::Error;
+
+        fn addr(&self) -> SocketAddr {
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+        }
+        fn recv(&self, buf: &mut [u8]) -> std::io::Result<Option<(usize, SocketAddr)>> {
+            let Some(datagram) = self.received.borrow_mut().pop_front() else {
+                return Ok(None);
+            };
+            let len = datagram.len().min(buf.len());
+            buf[..len].copy_from_slice(&datagram[..len]);
+            Ok(Some((len, self.server_addr.get())))
+        }
+        fn send(&self, buf: &[u8], addr: SocketAddr) -> std::io::Result<usize> {
+            self.server_addr.set(addr);
+            // Datagrams are unreliable anyway, so the promise is not awaited
+            let _ = self.writer.write_with_chunk(&Uint8Array::from(buf));
+            Ok(buf.len())
+        }
+    }
+
+    async fn run() -> Result<(), JsValue> {
+        let window = web_sys::window().ok_or("no window")?;
+        // The web backend authenticates the player and responds with the connect token bytes
+        let response: Response = JsFuture::from(window.fetch_with_str(TOKEN_URL))
+            .await?
+            .dyn_into()?;
+        let token = Uint8Array::new(&JsFuture::from(response.array_buffer()?).await?).to_vec();
+
+        let trx = WebTransportTransceiver::connect(GATEWAY_URL).await?;
+        let mut client = Client::with_config_and_transceiver(&token, ClientConfig::default(), trx)
+            .map_err(|e| e.to_string())?;
+        client.connect();
+
+        // The client never reads the system clock, its time is whatever is passed to `update`
+        let performance = window.performance().ok_or("no performance")?;
+        let tick = Closure::<dyn FnMut()>::new(move || {
+            client.update(performance.now() / 1000.0);
+            if client.is_connected() {
+                client.send(b"Hello from the browser!").ok();
+            }
+            while let Some(payload) = client.recv() {
+                web_sys::console::log_1(&format!("received {} bytes", payload.len()).into());
+            }
+        });
+        window.set_interval_with_callback_and_timeout_and_arguments_0(
+            tick.as_ref().unchecked_ref(),
+            TICK_RATE_MS,
+        )?;
+        tick.forget();
+        Ok(())
+    }
+
+    pub fn start() {
+        spawn_local(async {
+            if let Err(e) = run().await {
+                web_sys::console::error_1(&e);
+            }
+        });
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    web::start();
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    eprintln!("this example runs in the browser, build it with `--target wasm32-unknown-unknown`");
+}
@@ -0,0 +1,18 @@
+//! Reads arbitrary bytes as a connect token the way a client does before it connects.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use netcode::{testing::channel_pair, Client, ClientConfig, CONNECT_TOKEN_BYTES};
+
+fuzz_target!(|data: &[u8]| {
+    let mut token = [0u8; CONNECT_TOKEN_BYTES];
+    let len = data.len().min(CONNECT_TOKEN_BYTES);
+    token[..len].copy_from_slice(&data[..len]);
+    let (_, trx) = channel_pair();
+    if let Ok(mut client) =
+        Client::with_config_and_transceiver(&token, ClientConfig::default(), trx)
+    {
+        client.connect();
+        client.update(0.0);
+    }
+});
@@ -0,0 +1,21 @@
+//! Parses arbitrary datagrams as both a server (connection requests, with its private key) and a client would.
+//!
+//! The corpus seeds in `corpus/parse_packet` are valid packets written with `KEY` and `PROTOCOL_ID`.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use netcode::parse::{parse_packet, ParseConfig, ReplayWindow};
+
+const PROTOCOL_ID: u64 = 0x1122334455667788;
+const KEY: [u8; 32] = [0x42; 32];
+
+fuzz_target!(|data: &[u8]| {
+    let mut replay = ReplayWindow::new();
+    for strict in [false, true] {
+        let cfg = ParseConfig::new(PROTOCOL_ID, KEY).strict_netcode_1_02(strict);
+        let mut buf = data.to_vec();
+        let _ = parse_packet(&mut buf, &cfg, Some(&mut replay));
+        let mut buf = data.to_vec();
+        let _ = parse_packet(&mut buf, &cfg, None);
+    }
+});
@@ -0,0 +1,280 @@
+#ifndef NETCODE_H
+#define NETCODE_H
+
+/* Generated from src/capi.rs with cbindgen (see cbindgen.toml), do not edit by hand. */
+
+#include <stdint.h>
+
+/**
+ * Returned by functions that succeed.
+ */
+#define NETCODE_OK 0
+
+/**
+ * Returned by functions that fail, and by the receive functions if no packet is available.
+ */
+#define NETCODE_ERROR -1
+
+/**
+ * The size of a connect token.
+ */
+#define NETCODE_CONNECT_TOKEN_BYTES 2048
+
+/**
+ * The size of a private key.
+ */
+#define NETCODE_KEY_BYTES 32
+
+/**
+ * The largest payload of a packet.
+ */
+#define NETCODE_MAX_PACKET_SIZE 1200
+
+/**
+ * The size of the user data in a connect token.
+ */
+#define NETCODE_USER_DATA_BYTES 256
+
+/**
+ * The connect token has expired.
+ */
+#define NETCODE_CLIENT_STATE_CONNECT_TOKEN_EXPIRED -6
+
+/**
+ * The client timed out while connected.
+ */
+#define NETCODE_CLIENT_STATE_CONNECTION_TIMED_OUT -4
+
+/**
+ * The client timed out waiting for a reply to its challenge response.
+ */
+#define NETCODE_CLIENT_STATE_CONNECTION_RESPONSE_TIMED_OUT -3
+
+/**
+ * The client timed out waiting for a reply to its connection request.
+ */
+#define NETCODE_CLIENT_STATE_CONNECTION_REQUEST_TIMED_OUT -2
+
+/**
+ * The server denied the connection.
+ */
+#define NETCODE_CLIENT_STATE_CONNECTION_DENIED -1
+
+/**
+ * The client is disconnected.
+ */
+#define NETCODE_CLIENT_STATE_DISCONNECTED 0
+
+/**
+ * The client is sending connection requests.
+ */
+#define NETCODE_CLIENT_STATE_SENDING_CONNECTION_REQUEST 1
+
+/**
+ * The client is sending challenge responses.
+ */
+#define NETCODE_CLIENT_STATE_SENDING_CONNECTION_RESPONSE 2
+
+/**
+ * The client is connected.
+ */
+#define NETCODE_CLIENT_STATE_CONNECTED 3
+
+/**
+ * Nothing is listening on the server's address, not a state of the reference implementation.
+ */
+#define NETCODE_CLIENT_STATE_SERVER_UNREACHABLE -7
+
+/**
+ * A client created by `netcode_client_create`.
+ */
+typedef struct NetcodeClient NetcodeClient;
+
+/**
+ * A server created by `netcode_server_create`.
+ */
+typedef struct NetcodeServer NetcodeServer;
+
+/**
+ * Writes a random private key to `key`.
+ *
+ * # Safety
+ * `key` must point to `NETCODE_KEY_BYTES` writable bytes.
+ */
+int netcode_generate_key(uint8_t *key);
+
+/**
+ * Creates a client from a connect token, on a socket bound to any local port. Returns `NULL` on failure.
+ *
+ * # Safety
+ * `connect_token` must point to `NETCODE_CONNECT_TOKEN_BYTES` bytes.
+ */
+NetcodeClient *netcode_client_create(const uint8_t *connect_token);
+
+/**
+ * Destroys a client, without sending disconnect packets (see `netcode_client_disconnect`).
+ *
+ * # Safety
+ * `client` must be `NULL` or a client from `netcode_client_create` that wasn't destroyed yet.
+ */
+void netcode_client_destroy(NetcodeClient *client);
+
+/**
+ * Starts connecting to the server, from the next update.
+ *
+ * # Safety
+ * `client` must be a client from `netcode_client_create`.
+ */
+void netcode_client_connect(NetcodeClient *client);
+
+/**
+ * Updates the client to `time` (in seconds), see `Client::try_update`.
+ *
+ * # Safety
+ * `client` must be a client from `netcode_client_create`.
+ */
+int netcode_client_update(NetcodeClient *client, double time);
+
+/**
+ * Sends a packet of `bytes` bytes to the server.
+ *
+ * # Safety
+ * `client` must be a client from `netcode_client_create` and `data` must point to `bytes` bytes.
+ */
+int netcode_client_send_packet(NetcodeClient *client, const uint8_t *data, int bytes);
+
+/**
+ * Receives a packet from the server into `buf`, returns its size or `NETCODE_ERROR` if no packet is available.
+ * A packet larger than `buf_len` is truncated, a buffer of `NETCODE_MAX_PACKET_SIZE` bytes fits any packet.
+ *
+ * # Safety
+ * `client` must be a client from `netcode_client_create` and `buf` must point to `buf_len` writable bytes.
+ */
+int netcode_client_receive_packet(NetcodeClient *client,
+                                  uint8_t *buf,
+                                  int buf_len);
+
+/**
+ * Sends disconnect packets to the server and disconnects the client.
+ *
+ * # Safety
+ * `client` must be a client from `netcode_client_create`.
+ */
+int netcode_client_disconnect(NetcodeClient *client);
+
+/**
+ * Gets the state of the client, one of the `NETCODE_CLIENT_STATE_*` constants.
+ *
+ * # Safety
+ * `client` must be a client from `netcode_client_create`.
+ */
+int netcode_client_state(const NetcodeClient *client);
+
+/**
+ * Creates a server bound to `address` (e.g. `"0.0.0.0:40000"`). Returns `NULL` on failure.
+ *
+ * # Safety
+ * `address` must be a NUL-terminated string and `private_key` must point to `NETCODE_KEY_BYTES` bytes.
+ */
+NetcodeServer *netcode_server_create(const char *address,
+                                     uint64_t protocol_id,
+                                     const uint8_t *private_key);
+
+/**
+ * Destroys a server, without sending disconnect packets to its clients (see `netcode_server_disconnect_all_clients`).
+ *
+ * # Safety
+ * `server` must be `NULL` or a server from `netcode_server_create` that wasn't destroyed yet.
+ */
+void netcode_server_destroy(NetcodeServer *server);
+
+/**
+ * Updates the server to `time` (in seconds), see `Server::try_update`.
+ *
+ * # Safety
+ * `server` must be a server from `netcode_server_create`.
+ */
+int netcode_server_update(NetcodeServer *server, double time);
+
+/**
+ * Writes a connect token for `client_id` to `connect_token`, listing the server's address.
+ * `user_data` can be `NULL`, a negative `expire_seconds` or `timeout_seconds` never expires or times out.
+ *
+ * # Safety
+ * `server` must be a server from `netcode_server_create`, `user_data` must be `NULL` or point to `NETCODE_USER_DATA_BYTES` bytes
+ * and `connect_token` must point to `NETCODE_CONNECT_TOKEN_BYTES` writable bytes.
+ */
+int netcode_server_generate_connect_token(NetcodeServer *server,
+                                          uint64_t client_id,
+                                          int expire_seconds,
+                                          int timeout_seconds,
+                                          const uint8_t *user_data,
+                                          uint8_t *connect_token);
+
+/**
+ * Sends a packet of `bytes` bytes to a connected client.
+ *
+ * # Safety
+ * `server` must be a server from `netcode_server_create` and `data` must point to `bytes` bytes.
+ */
+int netcode_server_send_packet(NetcodeServer *server,
+                               int client_index,
+                               const uint8_t *data,
+                               int bytes);
+
+/**
+ * Receives a packet from any client into `buf` and the index of the client into `client_index`,
+ * returns its size or `NETCODE_ERROR` if no packet is available.
+ * A packet larger than `buf_len` is truncated, a buffer of `NETCODE_MAX_PACKET_SIZE` bytes fits any packet.
+ *
+ * # Safety
+ * `server` must be a server from `netcode_server_create`, `client_index` must point to a writable `int`
+ * and `buf` must point to `buf_len` writable bytes.
+ */
+int netcode_server_receive_packet(NetcodeServer *server,
+                                  int *client_index,
+                                  uint8_t *buf,
+                                  int buf_len);
+
+/**
+ * Returns 1 if a client is connected at `client_index`, 0 otherwise.
+ *
+ * # Safety
+ * `server` must be a server from `netcode_server_create`.
+ */
+int netcode_server_client_connected(const NetcodeServer *server, int client_index);
+
+/**
+ * Gets the client id (from its connect token) of the client connected at `client_index`, 0 if none is.
+ *
+ * # Safety
+ * `server` must be a server from `netcode_server_create`.
+ */
+uint64_t netcode_server_client_id(const NetcodeServer *server,
+                                  int client_index);
+
+/**
+ * Gets the number of connected clients.
+ *
+ * # Safety
+ * `server` must be a server from `netcode_server_create`.
+ */
+int netcode_server_num_connected_clients(const NetcodeServer *server);
+
+/**
+ * Sends disconnect packets to a client and disconnects it.
+ *
+ * # Safety
+ * `server` must be a server from `netcode_server_create`.
+ */
+int netcode_server_disconnect_client(NetcodeServer *server, int client_index);
+
+/**
+ * Sends disconnect packets to all clients and disconnects them.
+ *
+ * # Safety
+ * `server` must be a server from `netcode_server_create`.
+ */
+int netcode_server_disconnect_all_clients(NetcodeServer *server);
+
+#endif  /* NETCODE_H */
@@ -0,0 +1,6 @@
+//! The C API of netcode-rs (its `capi` module) built as a shared and a static library,
+//! `libnetcode.so`/`netcode.dll` and `libnetcode.a`/`netcode.lib`, for the `include/netcode.h` header.
+//!
+//! The netcode-rs crate itself stays an rlib, so it builds without `std` (which these libraries need).
+
+pub use netcode_rs::capi::*;
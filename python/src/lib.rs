@@ -0,0 +1,246 @@
+//! Python bindings for issuing connect tokens, for web backends (e.g. Django or FastAPI) that hand out tokens
+//! to clients of servers written with `netcode-rs`.
+//!
+//! Build and install the `netcode` module with `maturin develop` (or `pip install ./python`):
+//!
+//! ```python
+//! import netcode
+//!
+//! private_key = netcode.generate_key()  # shared with the servers
+//! token = (
+//!     netcode.ConnectToken.build(["203.0.113.7:40000"], 0x11223344, 123, private_key)
+//!     .expire_seconds(30)
+//!     .user_data(b"player")
+//!     .generate()
+//! )
+//! assert len(bytes(token)) == netcode.CONNECT_TOKEN_BYTES  # send these to the client
+//! ```
+
+// `#[pyfunction]`/`#[pymethods]` expand to an `Into::into` on `PyResult` errors that clippy flags.
+#![allow(clippy::useless_conversion)]
+
+use std::net::SocketAddr;
+
+use pyo3::{
+    exceptions::{PyRuntimeError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+
+use netcode::{CONNECT_TOKEN_BYTES, PRIVATE_KEY_BYTES, USER_DATA_BYTES};
+
+fn parse_addresses(addresses: &[String]) -> PyResult<Vec<SocketAddr>> {
+    addresses
+        .iter()
+        .map(|addr| {
+            addr.parse()
+                .map_err(|e| PyValueError::new_err(format!("invalid server address {addr:?}: {e}")))
+        })
+        .collect()
+}
+
+/// Generates a random private key (32 bytes), for signing connect tokens and configuring the servers.
+#[pyfunction]
+fn generate_key(py: Python<'_>) -> PyResult<Bound<'_, PyBytes>> {
+    let key = netcode::try_generate_key().map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+    Ok(PyBytes::new_bound(py, &key))
+}
+
+/// Builds a connect token, see `ConnectToken.build`. The setters return the builder, so calls can be chained.
+#[pyclass(module = "netcode")]
+struct ConnectTokenBuilder {
+    server_addresses: Vec<SocketAddr>,
+    internal_addresses: Option<Vec<SocketAddr>>,
+    protocol_id: u64,
+    client_id: u64,
+    private_key: [u8; PRIVATE_KEY_BYTES],
+    expire_seconds: Option<i32>,
+    timeout_seconds: Option<i32>,
+    user_data: [u8; USER_DATA_BYTES],
+    strict_netcode_1_02: bool,
+}
+
+#[pymethods]
+impl ConnectTokenBuilder {
+    /// Sets the time in seconds the token is valid for, negative to never expire. The default is 30 seconds.
+    fn expire_seconds(mut slf: PyRefMut<'_, Self>, expire_seconds: i32) -> PyRefMut<'_, Self> {
+        slf.expire_seconds = Some(expire_seconds);
+        slf
+    }
+    /// Sets the time in seconds a connection is kept without packets from the other side, negative to never time out.
+    /// The default is 15 seconds.
+    fn timeout_seconds(mut slf: PyRefMut<'_, Self>, timeout_seconds: i32) -> PyRefMut<'_, Self> {
+        slf.timeout_seconds = Some(timeout_seconds);
+        slf
+    }
+    /// Sets the user data the server receives with the token, up to 256 bytes (padded with zeros).
+    fn user_data<'py>(
+        mut slf: PyRefMut<'py, Self>,
+        user_data: &[u8],
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        if user_data.len() > USER_DATA_BYTES {
+            return Err(PyValueError::new_err(format!(
+                "user data must be at most {USER_DATA_BYTES} bytes, got {}",
+                user_data.len()
+            )));
+        }
+        slf.user_data = [0; USER_DATA_BYTES];
+        slf.user_data[..user_data.len()].copy_from_slice(user_data);
+        Ok(slf)
+    }
+    /// Sets the addresses the servers are bound to, if they differ from the public addresses clients connect to.
+    fn internal_addresses(
+        mut slf: PyRefMut<'_, Self>,
+        addresses: Vec<String>,
+    ) -> PyResult<PyRefMut<'_, Self>> {
+        slf.internal_addresses = Some(parse_addresses(&addresses)?);
+        Ok(slf)
+    }
+    /// Encodes IPv6 addresses like the netcode 1.02 reference implementation, for C clients and servers.
+    fn strict_netcode_1_02(mut slf: PyRefMut<'_, Self>, strict: bool) -> PyRefMut<'_, Self> {
+        slf.strict_netcode_1_02 = strict;
+        slf
+    }
+    /// Generates the token, created now.
+    fn generate(&self) -> PyResult<ConnectToken> {
+        ConnectToken::new(self.builder()?.generate())
+    }
+    /// Generates the token as if it was created at `now` (in seconds since the unix epoch).
+    fn generate_at(&self, now: u64) -> PyResult<ConnectToken> {
+        ConnectToken::new(self.builder()?.generate_at(now))
+    }
+}
+
+impl ConnectTokenBuilder {
+    fn builder(&self) -> PyResult<netcode::ConnectTokenBuilder<&[SocketAddr]>> {
+        let mut builder = netcode::ConnectToken::build(
+            &self.server_addresses[..],
+            self.protocol_id,
+            self.client_id,
+            self.private_key,
+        )
+        .user_data(self.user_data)
+        .strict_netcode_1_02(self.strict_netcode_1_02);
+        if let Some(expire_seconds) = self.expire_seconds {
+            builder = builder.expire_seconds(expire_seconds);
+        }
+        if let Some(timeout_seconds) = self.timeout_seconds {
+            builder = builder.timeout_seconds(timeout_seconds);
+        }
+        if let Some(ref addresses) = self.internal_addresses {
+            builder = builder
+                .internal_addresses(&addresses[..])
+                .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        }
+        Ok(builder)
+    }
+}
+
+/// A connect token, `bytes(token)` gives the 2048 bytes to send to the client.
+#[pyclass(module = "netcode", frozen)]
+struct ConnectToken {
+    bytes: [u8; CONNECT_TOKEN_BYTES],
+    protocol_id: u64,
+    create_timestamp: u64,
+    expire_timestamp: u64,
+    timeout_seconds: i32,
+    server_addresses: Vec<String>,
+}
+
+impl ConnectToken {
+    fn new(token: netcode::Result<netcode::ConnectToken>) -> PyResult<Self> {
+        let token = token.map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(Self {
+            protocol_id: token.protocol_id(),
+            create_timestamp: token.create_timestamp(),
+            expire_timestamp: token.expire_timestamp(),
+            timeout_seconds: token.timeout_seconds(),
+            server_addresses: token.server_addresses().map(|a| a.to_string()).collect(),
+            bytes: token
+                .try_into_bytes()
+                .map_err(|e| PyRuntimeError::new_err(e.to_string()))?,
+        })
+    }
+}
+
+#[pymethods]
+impl ConnectToken {
+    /// Starts building a token for `client_id`, listing the public `server_addresses` (e.g. `["203.0.113.7:40000"]`)
+    /// the client tries in order, signed with the servers' 32 byte `private_key`.
+    #[staticmethod]
+    fn build(
+        server_addresses: Vec<String>,
+        protocol_id: u64,
+        client_id: u64,
+        private_key: &[u8],
+    ) -> PyResult<ConnectTokenBuilder> {
+        let private_key = private_key.try_into().map_err(|_| {
+            PyValueError::new_err(format!(
+                "private key must be {PRIVATE_KEY_BYTES} bytes, got {}",
+                private_key.len()
+            ))
+        })?;
+        Ok(ConnectTokenBuilder {
+            server_addresses: parse_addresses(&server_addresses)?,
+            internal_addresses: None,
+            protocol_id,
+            client_id,
+            private_key,
+            expire_seconds: None,
+            timeout_seconds: None,
+            user_data: [0; USER_DATA_BYTES],
+            strict_netcode_1_02: false,
+        })
+    }
+    /// The protocol id the token was generated for.
+    #[getter]
+    fn protocol_id(&self) -> u64 {
+        self.protocol_id
+    }
+    /// When the token was generated, in seconds since the unix epoch.
+    #[getter]
+    fn create_timestamp(&self) -> u64 {
+        self.create_timestamp
+    }
+    /// When the token expires, in seconds since the unix epoch (`2**64 - 1` if it never expires).
+    #[getter]
+    fn expire_timestamp(&self) -> u64 {
+        self.expire_timestamp
+    }
+    /// The connection timeout of clients using the token, negative for no timeout.
+    #[getter]
+    fn timeout_seconds(&self) -> i32 {
+        self.timeout_seconds
+    }
+    /// The public server addresses the client tries to connect to, in order.
+    #[getter]
+    fn server_addresses(&self) -> Vec<String> {
+        self.server_addresses.clone()
+    }
+    /// The serialized token (2048 bytes).
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.bytes)
+    }
+    fn __bytes__<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        self.to_bytes(py)
+    }
+    fn __repr__(&self) -> String {
+        format!(
+            "ConnectToken(protocol_id={:#x}, server_addresses={:?}, expire_timestamp={})",
+            self.protocol_id, self.server_addresses, self.expire_timestamp
+        )
+    }
+}
+
+/// Issue connect tokens for netcode servers written in Rust.
+#[pymodule]
+#[pyo3(name = "netcode")]
+fn netcode_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(generate_key, m)?)?;
+    m.add_class::<ConnectToken>()?;
+    m.add_class::<ConnectTokenBuilder>()?;
+    m.add("CONNECT_TOKEN_BYTES", CONNECT_TOKEN_BYTES)?;
+    m.add("PRIVATE_KEY_BYTES", PRIVATE_KEY_BYTES)?;
+    m.add("USER_DATA_BYTES", USER_DATA_BYTES)?;
+    Ok(())
+}
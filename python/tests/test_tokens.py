@@ -0,0 +1,36 @@
+import struct
+
+import netcode
+
+
+def test_tokens_have_the_wire_layout():
+    key = netcode.generate_key()
+    assert len(key) == netcode.PRIVATE_KEY_BYTES
+
+    token = (
+        netcode.ConnectToken.build(["127.0.0.1:40000", "[::1]:40000"], 0x11223344, 123, key)
+        .expire_seconds(60)
+        .timeout_seconds(-1)
+        .user_data(b"player")
+        .generate_at(1_000)
+    )
+    data = bytes(token)
+    assert len(data) == netcode.CONNECT_TOKEN_BYTES
+    assert data[:13] == b"NETCODE 1.02\0"
+    assert struct.unpack_from("<QQQ", data, 13) == (0x11223344, 1_000, 1_060)
+    assert token.server_addresses == ["127.0.0.1:40000", "[::1]:40000"]
+    assert token.timeout_seconds == -1
+
+
+def test_invalid_input_raises():
+    key = netcode.generate_key()
+    for build in [
+        lambda: netcode.ConnectToken.build(["not an address"], 0, 0, key),
+        lambda: netcode.ConnectToken.build(["127.0.0.1:40000"], 0, 0, key[:16]),
+        lambda: netcode.ConnectToken.build(["127.0.0.1:40000"], 0, 0, key).user_data(bytes(257)),
+    ]:
+        try:
+            build()
+        except ValueError:
+            continue
+        raise AssertionError("expected a ValueError")
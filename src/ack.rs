@@ -0,0 +1,165 @@
+//! Acknowledgement of payload packets, see [`ClientConfig::ack_payloads`](crate::ClientConfig::ack_payloads).
+//!
+//! Each payload is prefixed with the most recent packet sequence received from the peer (8 bytes),
+//! and a bitfield of which of the 32 sequences before it were received as well (4 bytes).
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+use crate::replay::ReplayProtection;
+
+/// The bytes acks add to each payload.
+pub(crate) const HEADER_SIZE: usize = 12;
+
+const NUM_ACK_BITS: u64 = 32;
+const NOTHING_RECEIVED: u64 = u64::MAX;
+// Sent payloads that are never acked (e.g. because the peer sends no payloads) are forgotten, oldest first.
+const MAX_PENDING: usize = 256;
+// Acks that aren't read are dropped, oldest first.
+const MAX_ACKED: usize = 1024;
+// The weight of each new sample in the smoothed RTT and packet loss.
+const SMOOTHING_FACTOR: f64 = 0.1;
+
+/// The payload packets sent to a peer that it hasn't acked yet, and the ones it has.
+#[derive(Debug, Default)]
+pub(crate) struct AckTracker {
+    // sequences and the time they were sent at
+    pending: VecDeque<(u64, f64)>,
+    acked: VecDeque<u64>,
+    last_sent: Option<u64>,
+    rtt: Option<f64>,
+    packet_loss: f64,
+}
+
+impl AckTracker {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+    /// Records a payload packet sent with `sequence` at `time`.
+    pub(crate) fn sent(&mut self, sequence: u64, time: f64) {
+        if self.pending.len() == MAX_PENDING {
+            self.pending.pop_front();
+        }
+        self.pending.push_back((sequence, time));
+        self.last_sent = Some(sequence);
+    }
+    pub(crate) fn last_sent(&self) -> Option<u64> {
+        self.last_sent
+    }
+    /// The smoothed round trip time (in seconds) of acked payloads, `None` until one is acked.
+    pub(crate) fn rtt(&self) -> Option<f64> {
+        self.rtt
+    }
+    /// The smoothed fraction of payloads that were never acked.
+    pub(crate) fn packet_loss(&self) -> f64 {
+        self.packet_loss
+    }
+    /// Reads the ack header of a received payload at `time`, returning the rest of the payload.
+    ///
+    /// Returns `None` if the payload is too small to have an ack header.
+    pub(crate) fn recv<'p>(&mut self, payload: &'p [u8], time: f64) -> Option<&'p [u8]> {
+        let (header, rest) = payload.split_at_checked(HEADER_SIZE)?;
+        let ack = u64::from_le_bytes(header[..8].try_into().ok()?);
+        let bits = u32::from_le_bytes(header[8..].try_into().ok()?);
+        if ack == NOTHING_RECEIVED {
+            return Some(rest);
+        }
+        let mut rtt_samples = Vec::new();
+        let mut loss_samples = Vec::new();
+        let acked = &mut self.acked;
+        self.pending.retain(|&(sequence, sent_time)| {
+            if is_acked(ack, bits, sequence) {
+                if acked.len() == MAX_ACKED {
+                    acked.pop_front();
+                }
+                acked.push_back(sequence);
+                rtt_samples.push(time - sent_time);
+                loss_samples.push(0.0);
+                return false;
+            }
+            // older than the bitfield, later headers can't ack it anymore
+            let pending = sequence + NUM_ACK_BITS >= ack;
+            if !pending {
+                loss_samples.push(1.0);
+            }
+            pending
+        });
+        for sample in rtt_samples {
+            let rtt = self.rtt.get_or_insert(sample);
+            *rtt += (sample - *rtt) * SMOOTHING_FACTOR;
+        }
+        for sample in loss_samples {
+            self.packet_loss += (sample - self.packet_loss) * SMOOTHING_FACTOR;
+        }
+        Some(rest)
+    }
+    /// Takes the sequences of the payload packets acked since the last call, oldest first.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = u64> + '_ {
+        self.acked.drain(..)
+    }
+    pub(crate) fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// The ack header for the packets received so far.
+pub(crate) fn header(replay_protection: &ReplayProtection) -> [u8; HEADER_SIZE] {
+    let mut header = [0u8; HEADER_SIZE];
+    let Some(ack) = replay_protection.most_recent_sequence() else {
+        header[..8].copy_from_slice(&NOTHING_RECEIVED.to_le_bytes());
+        return header;
+    };
+    let bits = (0..NUM_ACK_BITS)
+        .filter(|&i| {
+            ack.checked_sub(i + 1)
+                .is_some_and(|sequence| replay_protection.was_received(sequence))
+        })
+        .fold(0u32, |bits, i| bits | 1 << i);
+    header[..8].copy_from_slice(&ack.to_le_bytes());
+    header[8..].copy_from_slice(&bits.to_le_bytes());
+    header
+}
+
+fn is_acked(ack: u64, bits: u32, sequence: u64) -> bool {
+    match ack.checked_sub(sequence) {
+        Some(0) => true,
+        Some(distance) if distance <= NUM_ACK_BITS => bits & 1 << (distance - 1) != 0,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acks_from_received_sequences() {
+        let mut replay_protection = ReplayProtection::new();
+        let mut tracker = AckTracker::new();
+        let payload = [header(&replay_protection), [7; HEADER_SIZE]].concat();
+        assert_eq!(tracker.recv(&payload, 0.0), Some(&[7; HEADER_SIZE][..]));
+        assert_eq!(tracker.recv(&[0; HEADER_SIZE - 1], 0.0), None);
+        assert_eq!(tracker.rtt(), None);
+
+        for sequence in [10, 11, 12, 13, 50] {
+            tracker.sent(sequence, 1.0);
+        }
+        assert_eq!(tracker.last_sent(), Some(50));
+        // 12 was lost
+        for sequence in [10, 11, 13] {
+            replay_protection.advance_sequence(sequence);
+        }
+        tracker.recv(&header(&replay_protection), 1.2).unwrap();
+        assert_eq!(tracker.drain().collect::<Vec<_>>(), [10, 11, 13]);
+        assert_eq!(tracker.drain().count(), 0);
+        assert!((tracker.rtt().unwrap() - 0.2).abs() < 1e-9);
+        assert_eq!(tracker.packet_loss(), 0.0);
+
+        // once the bitfield moves past 12 it is forgotten
+        replay_protection.advance_sequence(50);
+        tracker.recv(&header(&replay_protection), 1.2).unwrap();
+        assert_eq!(tracker.drain().collect::<Vec<_>>(), [50]);
+        assert!(tracker.pending.is_empty());
+        // 12 counts as lost
+        assert!(tracker.packet_loss() > 0.0);
+    }
+}
@@ -0,0 +1,312 @@
+//! The admin console of a server, see [`Server::admin_command`](crate::Server::admin_command)
+//! and [`ServerConfig::admin_socket`](crate::ServerConfig::admin_socket).
+//!
+//! Commands are lines of text. Each is answered with its output (if any) and a last line of `ok` or `error: <reason>`,
+//! so scripts can tell where an answer ends.
+
+#[cfg(unix)]
+use std::{
+    io::{self, Read, Write},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+use crate::server::ClientId;
+
+/// The answer to `help`.
+pub(crate) const HELP: &str = "\
+help                      list the commands
+clients                   list the connected clients
+stats                     print the server's statistics
+kick <client index>       disconnect a client
+ban <client id>           disconnect a client id and deny it from connecting again
+unban <client id>         lift a ban
+pace <packets per tick>   set the budget of paced packets per tick
+limit <client index> <bytes per second>|off
+                          limit the payload bytes sent to a client, or lift the limit
+rotate-keys               replace the key challenge tokens are encrypted with
+log on|off                resume or pause the packet logger
+";
+
+// A connection that sends a longer line is dropped.
+#[cfg(unix)]
+const MAX_LINE_BYTES: usize = 1024;
+// A connection that leaves more of its answers unread is dropped.
+#[cfg(unix)]
+const MAX_OUTPUT_BYTES: usize = 1 << 20;
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum AdminCommand {
+    Help,
+    Clients,
+    Stats,
+    Kick(usize),
+    Ban(ClientId),
+    Unban(ClientId),
+    Pace(usize),
+    Limit(usize, Option<usize>),
+    RotateKeys,
+    Log(bool),
+}
+
+impl AdminCommand {
+    pub(crate) fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let arg = words.next();
+        // only `limit` takes a second argument
+        let second_arg = if command == "limit" {
+            words.next()
+        } else {
+            None
+        };
+        if let Some(extra) = words.next() {
+            return Err(format!("unexpected argument {extra:?}"));
+        }
+        let parse = |arg: Option<&str>, what: &str| -> Result<u64, String> {
+            let arg = arg.ok_or_else(|| format!("{command} takes a {what}"))?;
+            arg.parse()
+                .map_err(|_| format!("{arg:?} is not a valid {what}"))
+        };
+        let number = |what: &str| parse(arg, what);
+        let command = match command {
+            "help" => Self::Help,
+            "clients" => Self::Clients,
+            "stats" => Self::Stats,
+            "kick" => Self::Kick(number("client index")? as usize),
+            "ban" => Self::Ban(number("client id")?),
+            "unban" => Self::Unban(number("client id")?),
+            "pace" => Self::Pace(number("number of packets")? as usize),
+            "limit" => {
+                let client_idx = number("client index")? as usize;
+                match second_arg {
+                    Some("off") => Self::Limit(client_idx, None),
+                    _ => Self::Limit(
+                        client_idx,
+                        Some(parse(second_arg, "number of bytes per second")? as usize),
+                    ),
+                }
+            }
+            "rotate-keys" => Self::RotateKeys,
+            "log" => match arg {
+                Some("on") => Self::Log(true),
+                Some("off") => Self::Log(false),
+                _ => return Err("log takes on or off".to_string()),
+            },
+            "" => return Err("empty command, try help".to_string()),
+            _ => return Err(format!("unknown command {command:?}, try help")),
+        };
+        let takes_arg = matches!(
+            command,
+            Self::Kick(_)
+                | Self::Ban(_)
+                | Self::Unban(_)
+                | Self::Pace(_)
+                | Self::Limit(..)
+                | Self::Log(_)
+        );
+        match arg {
+            Some(arg) if !takes_arg => Err(format!("unexpected argument {arg:?}")),
+            _ => Ok(command),
+        }
+    }
+}
+
+/// A Unix domain socket the admin console listens on, see `ServerConfig::admin_socket`.
+#[cfg(unix)]
+pub(crate) struct AdminSocket {
+    path: PathBuf,
+    listener: UnixListener,
+    connections: Vec<AdminConnection>,
+}
+
+#[cfg(unix)]
+struct AdminConnection {
+    stream: UnixStream,
+    // the bytes received after the last complete line
+    input: Vec<u8>,
+    // the answers the socket didn't take yet, sent from the next polls
+    output: Vec<u8>,
+    // the other side is done sending, the connection is closed once its answers are sent
+    read_closed: bool,
+    failed: bool,
+}
+
+#[cfg(unix)]
+impl AdminConnection {
+    fn new(stream: UnixStream) -> Self {
+        Self {
+            stream,
+            input: Vec::new(),
+            output: Vec::new(),
+            read_closed: false,
+            failed: false,
+        }
+    }
+    fn read(&mut self) {
+        let mut chunk = [0u8; 256];
+        while !self.read_closed {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => self.read_closed = true,
+                Ok(len) => self.input.extend_from_slice(&chunk[..len]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    self.failed = true;
+                    return;
+                }
+            }
+        }
+        let last_line = (self.input.iter())
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |pos| pos + 1);
+        if self.input.len() - last_line > MAX_LINE_BYTES {
+            self.failed = true;
+        }
+    }
+    fn flush(&mut self) {
+        while !self.output.is_empty() && !self.failed {
+            match self.stream.write(&self.output) {
+                Ok(0) => self.failed = true,
+                Ok(len) => {
+                    self.output.drain(..len);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    log::debug!("server admin console dropped a connection: {e}");
+                    self.failed = true;
+                }
+            }
+        }
+    }
+    fn is_done(&self) -> bool {
+        self.failed || (self.read_closed && self.output.is_empty())
+    }
+}
+
+#[cfg(unix)]
+impl AdminSocket {
+    pub(crate) fn bind(path: &Path) -> io::Result<Self> {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another server listens on {}", path.display()),
+            ));
+        }
+        // the socket file of a server that didn't shut down cleanly, anything else at the path is left alone
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and isn't a socket", path.display()),
+                ))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        log::info!("server admin console listening on {}", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            listener,
+            connections: Vec::new(),
+        })
+    }
+    /// Sends what is left of earlier answers, accepts new connections and returns the complete lines received since the last poll,
+    /// with the index of their connection to [`respond`](AdminSocket::respond) to.
+    pub(crate) fn poll(&mut self) -> Vec<(usize, String)> {
+        for conn in &mut self.connections {
+            conn.flush();
+        }
+        self.connections.retain(|conn| !conn.is_done());
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match stream.set_nonblocking(true) {
+                    Ok(()) => self.connections.push(AdminConnection::new(stream)),
+                    Err(e) => log::debug!("server admin console dropped a connection: {e}"),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::debug!("server admin console failed to accept a connection: {e}");
+                    break;
+                }
+            }
+        }
+        let mut lines = Vec::new();
+        for (idx, conn) in self.connections.iter_mut().enumerate() {
+            conn.read();
+            if conn.failed {
+                continue;
+            }
+            while let Some(end) = conn.input.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = conn.input.drain(..=end).collect();
+                lines.push((idx, String::from_utf8_lossy(&line).trim().to_string()));
+            }
+        }
+        lines
+    }
+    /// Sends an answer to a connection returned by the last [`poll`](AdminSocket::poll),
+    /// what the socket doesn't take right away is sent from the next polls.
+    pub(crate) fn respond(&mut self, conn: usize, answer: &str) {
+        let conn = &mut self.connections[conn];
+        if conn.failed {
+            return;
+        }
+        conn.output.extend_from_slice(answer.as_bytes());
+        conn.flush();
+        if conn.output.len() > MAX_OUTPUT_BYTES {
+            log::debug!("server admin console dropped a connection that doesn't read its answers");
+            conn.failed = true;
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for AdminSocket {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(AdminCommand::parse(" kick 3 "), Ok(AdminCommand::Kick(3)));
+        assert_eq!(AdminCommand::parse("log off"), Ok(AdminCommand::Log(false)));
+        assert_eq!(
+            AdminCommand::parse("limit 2 64000"),
+            Ok(AdminCommand::Limit(2, Some(64000)))
+        );
+        assert_eq!(
+            AdminCommand::parse("limit 2 off"),
+            Ok(AdminCommand::Limit(2, None))
+        );
+        assert_eq!(
+            AdminCommand::parse("rotate-keys"),
+            Ok(AdminCommand::RotateKeys)
+        );
+        for (line, error) in [
+            ("", "empty command, try help"),
+            ("reboot", "unknown command \"reboot\", try help"),
+            ("ban", "ban takes a client id"),
+            ("ban x", "\"x\" is not a valid client id"),
+            ("kick 1 2", "unexpected argument \"2\""),
+            ("clients 1", "unexpected argument \"1\""),
+            ("log maybe", "log takes on or off"),
+            ("limit 2", "limit takes a number of bytes per second"),
+            ("limit 2 100 3", "unexpected argument \"3\""),
+            ("pace 2 3", "unexpected argument \"3\""),
+        ] {
+            assert_eq!(AdminCommand::parse(line), Err(error.to_string()));
+        }
+    }
+}
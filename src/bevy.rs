@@ -0,0 +1,357 @@
+//! [Bevy](https://bevyengine.org) plugins that run a [`Server`] or a [`Client`] as part of the app's schedule.
+//!
+//! Insert a [`NetcodeServer`] (or [`NetcodeClient`]) resource and add the matching plugin. Every frame, in `PreUpdate`,
+//! the plugin updates it with the app's real time and turns what happened during the update into
+//! [messages](bevy_ecs::message) that systems in `Update` read with a `MessageReader`:
+//! * The server sends [`ClientConnected`], [`ClientDisconnected`] and a [`PayloadReceived`] for each payload from a client.
+//! * The client sends [`StateChanged`] and a [`ServerPayloadReceived`] for each payload from the server.
+//!
+//! The resources deref to the [`Server`] and [`Client`] they wrap, to send payloads, disconnect clients and so on.
+//! Connect and disconnect callbacks set on the config are still called, before the messages are sent. <br>
+//! Payloads are only drained into messages, so don't call `recv` on the wrapped server or client yourself.
+//!
+//! The plugins read [`Time<Real>`](bevy_time::Real), which needs bevy's `TimePlugin` (part of `MinimalPlugins` and `DefaultPlugins`).
+//!
+//! # Example
+//! ```no_run
+//! use bevy_app::{App, Update};
+//! use bevy_ecs::prelude::*;
+//! use netcode::bevy::{ClientConnected, NetcodeServer, NetcodeServerPlugin, PayloadReceived};
+//!
+//! fn echo(
+//!     mut server: ResMut<NetcodeServer>,
+//!     mut connected: MessageReader<ClientConnected>,
+//!     mut payloads: MessageReader<PayloadReceived>,
+//! ) {
+//!     for ClientConnected(client_idx) in connected.read() {
+//!         println!("client {} connected", client_idx);
+//!     }
+//!     for PayloadReceived { client_idx, payload } in payloads.read() {
+//!         server.send(payload, *client_idx).unwrap();
+//!     }
+//! }
+//!
+//! let server = NetcodeServer::new("0.0.0.0:5555", 0x11223344, netcode::generate_key()).unwrap();
+//! App::new()
+//!     .add_plugins((bevy_time::TimePlugin, NetcodeServerPlugin))
+//!     .insert_resource(server)
+//!     .add_systems(Update, echo)
+//!     .run();
+//! ```
+
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+};
+
+use bevy_app::{App, Plugin, PreUpdate};
+use bevy_ecs::prelude::*;
+use bevy_time::{Real, Time};
+
+use crate::{
+    client::{Client, ClientConfig, ClientState},
+    error::Result,
+    io::ToSocketAddrs,
+    server::{Callback, ClientIndex, Server, ServerConfig},
+    socket::NetcodeSocket,
+    Key,
+};
+
+/// Updates a [`NetcodeServer`] resource every frame and sends [`ClientConnected`], [`ClientDisconnected`] and [`PayloadReceived`] messages.
+///
+/// The systems only run while the resource exists, so it can be inserted after the plugin is added (e.g. when hosting a game from a menu).
+pub struct NetcodeServerPlugin;
+
+impl Plugin for NetcodeServerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<ClientConnected>()
+            .add_message::<ClientDisconnected>()
+            .add_message::<PayloadReceived>()
+            .add_systems(
+                PreUpdate,
+                update_server.run_if(resource_exists::<NetcodeServer>),
+            );
+    }
+}
+
+/// Updates a [`NetcodeClient`] resource every frame and sends [`StateChanged`] and [`ServerPayloadReceived`] messages.
+///
+/// The systems only run while the resource exists, so it can be inserted after the plugin is added (e.g. when joining a game from a menu).
+pub struct NetcodeClientPlugin;
+
+impl Plugin for NetcodeClientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_message::<StateChanged>()
+            .add_message::<ServerPayloadReceived>()
+            .add_systems(
+                PreUpdate,
+                update_client.run_if(resource_exists::<NetcodeClient>),
+            );
+    }
+}
+
+/// A client connected to the [`NetcodeServer`].
+#[derive(Message, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientConnected(pub ClientIndex);
+
+/// A client disconnected from the [`NetcodeServer`], or timed out.
+#[derive(Message, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientDisconnected(pub ClientIndex);
+
+/// The [`NetcodeServer`] received a payload from a client.
+#[derive(Message, Debug, Clone, PartialEq, Eq)]
+pub struct PayloadReceived {
+    pub client_idx: ClientIndex,
+    pub payload: Vec<u8>,
+}
+
+/// The [`NetcodeClient`] changed state, e.g. from [`ClientState::SendingChallengeResponse`] to [`ClientState::Connected`].
+#[derive(Message, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateChanged {
+    pub from: ClientState,
+    pub to: ClientState,
+}
+
+/// The [`NetcodeClient`] received a payload from the server.
+#[derive(Message, Debug, Clone, PartialEq, Eq)]
+pub struct ServerPayloadReceived(pub Vec<u8>);
+
+enum ServerEvent {
+    Connected(ClientIndex),
+    Disconnected(ClientIndex),
+}
+
+/// A [`Server`] resource, updated by the [`NetcodeServerPlugin`].
+#[derive(Resource)]
+pub struct NetcodeServer {
+    server: Server<NetcodeSocket>,
+    // resources have to be `Sync`, the receiver is only used through `&mut self`
+    events: Mutex<Receiver<ServerEvent>>,
+}
+
+impl NetcodeServer {
+    /// Creates a server bound to `bind_addr`, like [`Server::new`].
+    pub fn new(bind_addr: impl ToSocketAddrs, protocol_id: u64, private_key: Key) -> Result<Self> {
+        Self::with_config(bind_addr, protocol_id, private_key, ServerConfig::default())
+    }
+    /// Creates a server bound to `bind_addr` with a custom configuration, like [`Server::with_config`].
+    pub fn with_config(
+        bind_addr: impl ToSocketAddrs,
+        protocol_id: u64,
+        private_key: Key,
+        mut cfg: ServerConfig<()>,
+    ) -> Result<Self> {
+        let (tx, events) = mpsc::channel();
+        cfg.on_connect = Some(forward(
+            cfg.on_connect.take(),
+            tx.clone(),
+            ServerEvent::Connected,
+        ));
+        cfg.on_disconnect = Some(forward(
+            cfg.on_disconnect.take(),
+            tx,
+            ServerEvent::Disconnected,
+        ));
+        let server = Server::with_config(bind_addr, protocol_id, private_key, cfg)?;
+        Ok(Self {
+            server,
+            events: Mutex::new(events),
+        })
+    }
+}
+
+impl Deref for NetcodeServer {
+    type Target = Server<NetcodeSocket>;
+    fn deref(&self) -> &Self::Target {
+        &self.server
+    }
+}
+
+impl DerefMut for NetcodeServer {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.server
+    }
+}
+
+/// A [`Client`] resource, updated by the [`NetcodeClientPlugin`].
+///
+/// The client isn't connected when it's created, call [`Client::connect`] to start connecting.
+#[derive(Resource)]
+pub struct NetcodeClient {
+    client: Client<NetcodeSocket>,
+    events: Mutex<Receiver<StateChanged>>,
+}
+
+impl NetcodeClient {
+    /// Creates a client from a serialized connect token, like [`Client::new`].
+    pub fn new(token_bytes: &[u8]) -> Result<Self> {
+        Self::with_config(token_bytes, ClientConfig::default())
+    }
+    /// Creates a client from a serialized connect token with a custom configuration, like [`Client::with_config`].
+    pub fn with_config(token_bytes: &[u8], mut cfg: ClientConfig<()>) -> Result<Self> {
+        let (tx, events) = mpsc::channel();
+        let mut on_state_change = cfg.on_state_change.take();
+        cfg.on_state_change = Some(Box::new(move |from, to, ctx| {
+            if let Some(cb) = on_state_change.as_mut() {
+                cb(from, to, ctx);
+            }
+            tx.send(StateChanged { from, to }).ok();
+        }));
+        let client = Client::with_config(token_bytes, cfg)?;
+        Ok(Self {
+            client,
+            events: Mutex::new(events),
+        })
+    }
+}
+
+impl Deref for NetcodeClient {
+    type Target = Client<NetcodeSocket>;
+    fn deref(&self) -> &Self::Target {
+        &self.client
+    }
+}
+
+impl DerefMut for NetcodeClient {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.client
+    }
+}
+
+// chains a connect or disconnect callback with one that hands the client to the plugin's system
+fn forward(
+    mut cb: Option<Callback<()>>,
+    tx: Sender<ServerEvent>,
+    event: fn(ClientIndex) -> ServerEvent,
+) -> Callback<()> {
+    Box::new(move |client_idx, ctx| {
+        if let Some(cb) = cb.as_mut() {
+            cb(client_idx, ctx);
+        }
+        tx.send(event(client_idx)).ok();
+    })
+}
+
+fn update_server(
+    time: Res<Time<Real>>,
+    mut netcode: ResMut<NetcodeServer>,
+    mut connected: MessageWriter<ClientConnected>,
+    mut disconnected: MessageWriter<ClientDisconnected>,
+    mut payloads: MessageWriter<PayloadReceived>,
+) {
+    let netcode = &mut *netcode;
+    if let Err(e) = netcode.server.try_update(time.elapsed_secs_f64()) {
+        log::error!("failed to update netcode server: {e}");
+    }
+    for event in netcode.events.get_mut().unwrap().try_iter() {
+        match event {
+            ServerEvent::Connected(client_idx) => {
+                connected.write(ClientConnected(client_idx));
+            }
+            ServerEvent::Disconnected(client_idx) => {
+                disconnected.write(ClientDisconnected(client_idx));
+            }
+        }
+    }
+    while let Some((payload, client_idx)) = netcode.server.recv() {
+        payloads.write(PayloadReceived {
+            client_idx,
+            payload,
+        });
+    }
+}
+
+fn update_client(
+    time: Res<Time<Real>>,
+    mut netcode: ResMut<NetcodeClient>,
+    mut state_changes: MessageWriter<StateChanged>,
+    mut payloads: MessageWriter<ServerPayloadReceived>,
+) {
+    let netcode = &mut *netcode;
+    if let Err(e) = netcode.client.try_update(time.elapsed_secs_f64()) {
+        log::error!("failed to update netcode client: {e}");
+    }
+    state_changes.write_batch(netcode.events.get_mut().unwrap().try_iter());
+    while let Some(payload) = netcode.client.recv() {
+        payloads.write(ServerPayloadReceived(payload));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::ConnectToken;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn plugins_connect_and_exchange_payloads() {
+        let protocol_id = 0x11223344;
+        let private_key = crate::generate_key();
+        let server = NetcodeServer::new("127.0.0.1:0", protocol_id, private_key).unwrap();
+        let token = ConnectToken::build(server.addr(), protocol_id, 7, private_key)
+            .generate()
+            .unwrap();
+        let mut client = NetcodeClient::new(&token.try_into_bytes().unwrap()).unwrap();
+        client.connect();
+
+        let mut server_app = App::new();
+        server_app
+            .add_plugins((bevy_time::TimePlugin, NetcodeServerPlugin))
+            .insert_resource(server);
+        let mut client_app = App::new();
+        client_app
+            .add_plugins((bevy_time::TimePlugin, NetcodeClientPlugin))
+            .insert_resource(client);
+
+        let mut connected = Vec::new();
+        let mut states = Vec::new();
+        let mut received = Vec::new();
+        for _ in 0..200 {
+            client_app.update();
+            server_app.update();
+            connected.extend(read::<ClientConnected>(&mut server_app));
+            for PayloadReceived {
+                client_idx,
+                payload,
+            } in read(&mut server_app)
+            {
+                let mut server = server_app.world_mut().resource_mut::<NetcodeServer>();
+                server.send(&payload, client_idx).unwrap();
+            }
+            states.extend(read::<StateChanged>(&mut client_app));
+            received.extend(read::<ServerPayloadReceived>(&mut client_app));
+            let mut client = client_app.world_mut().resource_mut::<NetcodeClient>();
+            if client.is_connected() && received.is_empty() {
+                client.send(b"ping").unwrap();
+            }
+            if !received.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(connected.len(), 1);
+        assert_eq!(states.last().unwrap().to, ClientState::Connected);
+        assert_eq!(received[0], ServerPayloadReceived(b"ping".to_vec()));
+
+        let client_idx = connected[0].0;
+        server_app
+            .world_mut()
+            .resource_mut::<NetcodeServer>()
+            .disconnect(client_idx)
+            .unwrap();
+        server_app.update();
+        assert_eq!(
+            read::<ClientDisconnected>(&mut server_app),
+            vec![ClientDisconnected(client_idx)]
+        );
+    }
+
+    fn read<M: Message + Clone>(app: &mut App) -> Vec<M> {
+        let mut messages = app.world_mut().resource_mut::<Messages<M>>();
+        messages.drain().collect()
+    }
+}
@@ -0,0 +1,577 @@
+//! A C API for engines written in C or C++ (or C# through P/Invoke), requires the `capi` feature.
+//!
+//! The functions are modeled after the [reference implementation](https://github.com/networkprotocol/netcode)'s `netcode.h`,
+//! the header is at `include/netcode.h` (regenerate it with `cbindgen --config cbindgen.toml --output include/netcode.h src/capi.rs`).
+//! Build the shared and static libraries (`libnetcode.so` and `libnetcode.a`, or `netcode.dll` and `netcode.lib` on Windows)
+//! with `cargo build --release --manifest-path netcode-sys/Cargo.toml`.
+//!
+//! Clients and servers are opaque pointers created by `netcode_client_create` and `netcode_server_create`,
+//! which return `NULL` on failure, and freed with `netcode_client_destroy` and `netcode_server_destroy`.
+//! Functions that can fail return [`NETCODE_OK`] or [`NETCODE_ERROR`], the error itself is logged through the `log` crate.
+//!
+//! ```c
+//! uint8_t private_key[NETCODE_KEY_BYTES];
+//! netcode_generate_key(private_key);
+//! NetcodeServer *server = netcode_server_create("127.0.0.1:40000", 0x11223344, private_key);
+//!
+//! uint8_t connect_token[NETCODE_CONNECT_TOKEN_BYTES];
+//! netcode_server_generate_connect_token(server, 123, 30, 15, NULL, connect_token);
+//! NetcodeClient *client = netcode_client_create(connect_token);
+//! netcode_client_connect(client);
+//!
+//! uint8_t packet[NETCODE_MAX_PACKET_SIZE];
+//! for (double time = 0.0; ; time += 1.0 / 60.0) {
+//!     netcode_client_update(client, time);
+//!     netcode_server_update(server, time);
+//!     int client_index, bytes;
+//!     while ((bytes = netcode_server_receive_packet(server, &client_index, packet, sizeof(packet))) >= 0) {
+//!         netcode_server_send_packet(server, client_index, packet, bytes);
+//!     }
+//! }
+//! ```
+
+use std::{
+    ffi::{c_char, c_int, CStr},
+    slice,
+};
+
+use crate::{
+    Client, ClientIndex, ClientState, NetcodeSocket, SendOutcome, Server, CONNECT_TOKEN_BYTES,
+    MAX_PACKET_SIZE, PRIVATE_KEY_BYTES, USER_DATA_BYTES,
+};
+
+/// Returned by functions that succeed.
+pub const NETCODE_OK: c_int = 0;
+/// Returned by functions that fail, and by the receive functions if no packet is available.
+pub const NETCODE_ERROR: c_int = -1;
+
+// Spelled out for cbindgen, which doesn't follow the constants of the crate.
+/// The size of a connect token.
+pub const NETCODE_CONNECT_TOKEN_BYTES: usize = 2048;
+/// The size of a private key.
+pub const NETCODE_KEY_BYTES: usize = 32;
+/// The largest payload of a packet.
+pub const NETCODE_MAX_PACKET_SIZE: usize = 1200;
+/// The size of the user data in a connect token.
+pub const NETCODE_USER_DATA_BYTES: usize = 256;
+const _: () = assert!(
+    NETCODE_CONNECT_TOKEN_BYTES == CONNECT_TOKEN_BYTES
+        && NETCODE_KEY_BYTES == PRIVATE_KEY_BYTES
+        && NETCODE_MAX_PACKET_SIZE == MAX_PACKET_SIZE
+        && NETCODE_USER_DATA_BYTES == USER_DATA_BYTES
+);
+
+/// The connect token has expired.
+pub const NETCODE_CLIENT_STATE_CONNECT_TOKEN_EXPIRED: c_int = -6;
+/// The client timed out while connected.
+pub const NETCODE_CLIENT_STATE_CONNECTION_TIMED_OUT: c_int = -4;
+/// The client timed out waiting for a reply to its challenge response.
+pub const NETCODE_CLIENT_STATE_CONNECTION_RESPONSE_TIMED_OUT: c_int = -3;
+/// The client timed out waiting for a reply to its connection request.
+pub const NETCODE_CLIENT_STATE_CONNECTION_REQUEST_TIMED_OUT: c_int = -2;
+/// The server denied the connection.
+pub const NETCODE_CLIENT_STATE_CONNECTION_DENIED: c_int = -1;
+/// The client is disconnected.
+pub const NETCODE_CLIENT_STATE_DISCONNECTED: c_int = 0;
+/// The client is sending connection requests.
+pub const NETCODE_CLIENT_STATE_SENDING_CONNECTION_REQUEST: c_int = 1;
+/// The client is sending challenge responses.
+pub const NETCODE_CLIENT_STATE_SENDING_CONNECTION_RESPONSE: c_int = 2;
+/// The client is connected.
+pub const NETCODE_CLIENT_STATE_CONNECTED: c_int = 3;
+/// Nothing is listening on the server's address, not a state of the reference implementation.
+pub const NETCODE_CLIENT_STATE_SERVER_UNREACHABLE: c_int = -7;
+
+/// A client created by `netcode_client_create`.
+pub struct NetcodeClient(Client<NetcodeSocket>);
+
+/// A server created by `netcode_server_create`.
+pub struct NetcodeServer(Server<NetcodeSocket>);
+
+// `NULL` is an empty slice, as long as no bytes are expected.
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+fn status(result: crate::Result<()>) -> c_int {
+    match result {
+        Ok(()) => NETCODE_OK,
+        Err(e) => {
+            log::error!("netcode C API call failed: {e}");
+            NETCODE_ERROR
+        }
+    }
+}
+
+fn client_index(client_index: c_int) -> Option<ClientIndex> {
+    usize::try_from(client_index).ok().map(ClientIndex)
+}
+
+/// Writes a random private key to `key`.
+///
+/// # Safety
+/// `key` must point to `NETCODE_KEY_BYTES` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_generate_key(key: *mut u8) -> c_int {
+    if key.is_null() {
+        return NETCODE_ERROR;
+    }
+    match crate::try_generate_key() {
+        Ok(generated) => {
+            slice::from_raw_parts_mut(key, NETCODE_KEY_BYTES).copy_from_slice(&generated);
+            NETCODE_OK
+        }
+        Err(e) => status(Err(e.into())),
+    }
+}
+
+/// Creates a client from a connect token, on a socket bound to any local port. Returns `NULL` on failure.
+///
+/// # Safety
+/// `connect_token` must point to `NETCODE_CONNECT_TOKEN_BYTES` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_client_create(connect_token: *const u8) -> *mut NetcodeClient {
+    let Some(token) = bytes(connect_token, NETCODE_CONNECT_TOKEN_BYTES) else {
+        return std::ptr::null_mut();
+    };
+    match Client::new(token) {
+        Ok(client) => Box::into_raw(Box::new(NetcodeClient(client))),
+        Err(e) => {
+            log::error!("netcode C API failed to create client: {e}");
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Destroys a client, without sending disconnect packets (see `netcode_client_disconnect`).
+///
+/// # Safety
+/// `client` must be `NULL` or a client from `netcode_client_create` that wasn't destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_client_destroy(client: *mut NetcodeClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Starts connecting to the server, from the next update.
+///
+/// # Safety
+/// `client` must be a client from `netcode_client_create`.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_client_connect(client: *mut NetcodeClient) {
+    if let Some(client) = client.as_mut() {
+        client.0.connect();
+    }
+}
+
+/// Updates the client to `time` (in seconds), see `Client::try_update`.
+///
+/// # Safety
+/// `client` must be a client from `netcode_client_create`.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_client_update(client: *mut NetcodeClient, time: f64) -> c_int {
+    let Some(client) = client.as_mut() else {
+        return NETCODE_ERROR;
+    };
+    status(client.0.try_update(time))
+}
+
+/// Sends a packet of `bytes` bytes to the server.
+///
+/// # Safety
+/// `client` must be a client from `netcode_client_create` and `data` must point to `bytes` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_client_send_packet(
+    client: *mut NetcodeClient,
+    data: *const u8,
+    bytes: c_int,
+) -> c_int {
+    let (Some(client), Ok(len)) = (client.as_mut(), usize::try_from(bytes)) else {
+        return NETCODE_ERROR;
+    };
+    let Some(buf) = self::bytes(data, len) else {
+        return NETCODE_ERROR;
+    };
+    status(client.0.send(buf).map(drop))
+}
+
+/// Receives a packet from the server into `buf`, returns its size or `NETCODE_ERROR` if no packet is available.
+/// A packet larger than `buf_len` is truncated, a buffer of `NETCODE_MAX_PACKET_SIZE` bytes fits any packet.
+///
+/// # Safety
+/// `client` must be a client from `netcode_client_create` and `buf` must point to `buf_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_client_receive_packet(
+    client: *mut NetcodeClient,
+    buf: *mut u8,
+    buf_len: c_int,
+) -> c_int {
+    let (Some(client), Ok(len)) = (client.as_mut(), usize::try_from(buf_len)) else {
+        return NETCODE_ERROR;
+    };
+    if buf.is_null() {
+        return NETCODE_ERROR;
+    }
+    let buf = slice::from_raw_parts_mut(buf, len);
+    client
+        .0
+        .recv_into(buf)
+        .map_or(NETCODE_ERROR, |len| len as c_int)
+}
+
+/// Sends disconnect packets to the server and disconnects the client.
+///
+/// # Safety
+/// `client` must be a client from `netcode_client_create`.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_client_disconnect(client: *mut NetcodeClient) -> c_int {
+    let Some(client) = client.as_mut() else {
+        return NETCODE_ERROR;
+    };
+    status(client.0.disconnect())
+}
+
+/// Gets the state of the client, one of the `NETCODE_CLIENT_STATE_*` constants.
+///
+/// # Safety
+/// `client` must be a client from `netcode_client_create`.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_client_state(client: *const NetcodeClient) -> c_int {
+    let Some(client) = client.as_ref() else {
+        return NETCODE_CLIENT_STATE_DISCONNECTED;
+    };
+    match client.0.state() {
+        ClientState::ConnectTokenExpired => NETCODE_CLIENT_STATE_CONNECT_TOKEN_EXPIRED,
+        ClientState::ConnectionTimedOut => NETCODE_CLIENT_STATE_CONNECTION_TIMED_OUT,
+        ClientState::ConnectionRequestTimedOut => NETCODE_CLIENT_STATE_CONNECTION_REQUEST_TIMED_OUT,
+        ClientState::ChallengeResponseTimedOut => {
+            NETCODE_CLIENT_STATE_CONNECTION_RESPONSE_TIMED_OUT
+        }
+        ClientState::ConnectionDenied => NETCODE_CLIENT_STATE_CONNECTION_DENIED,
+        ClientState::ServerUnreachable => NETCODE_CLIENT_STATE_SERVER_UNREACHABLE,
+        ClientState::Disconnected => NETCODE_CLIENT_STATE_DISCONNECTED,
+        ClientState::SendingConnectionRequest => NETCODE_CLIENT_STATE_SENDING_CONNECTION_REQUEST,
+        ClientState::SendingChallengeResponse => NETCODE_CLIENT_STATE_SENDING_CONNECTION_RESPONSE,
+        ClientState::Connected => NETCODE_CLIENT_STATE_CONNECTED,
+    }
+}
+
+/// Creates a server bound to `address` (e.g. `"0.0.0.0:40000"`). Returns `NULL` on failure.
+///
+/// # Safety
+/// `address` must be a NUL-terminated string and `private_key` must point to `NETCODE_KEY_BYTES` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_create(
+    address: *const c_char,
+    protocol_id: u64,
+    private_key: *const u8,
+) -> *mut NetcodeServer {
+    if address.is_null() {
+        return std::ptr::null_mut();
+    }
+    let Ok(address) = CStr::from_ptr(address).to_str() else {
+        return std::ptr::null_mut();
+    };
+    let Some(private_key) = bytes(private_key, NETCODE_KEY_BYTES) else {
+        return std::ptr::null_mut();
+    };
+    let private_key = private_key.try_into().expect("the key has the right size");
+    match Server::new(address, protocol_id, private_key) {
+        Ok(server) => Box::into_raw(Box::new(NetcodeServer(server))),
+        Err(e) => {
+            log::error!("netcode C API failed to create server on {address}: {e}");
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Destroys a server, without sending disconnect packets to its clients (see `netcode_server_disconnect_all_clients`).
+///
+/// # Safety
+/// `server` must be `NULL` or a server from `netcode_server_create` that wasn't destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_destroy(server: *mut NetcodeServer) {
+    if !server.is_null() {
+        drop(Box::from_raw(server));
+    }
+}
+
+/// Updates the server to `time` (in seconds), see `Server::try_update`.
+///
+/// # Safety
+/// `server` must be a server from `netcode_server_create`.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_update(server: *mut NetcodeServer, time: f64) -> c_int {
+    let Some(server) = server.as_mut() else {
+        return NETCODE_ERROR;
+    };
+    status(server.0.try_update(time))
+}
+
+/// Writes a connect token for `client_id` to `connect_token`, listing the server's address.
+/// `user_data` can be `NULL`, a negative `expire_seconds` or `timeout_seconds` never expires or times out.
+///
+/// # Safety
+/// `server` must be a server from `netcode_server_create`, `user_data` must be `NULL` or point to `NETCODE_USER_DATA_BYTES` bytes
+/// and `connect_token` must point to `NETCODE_CONNECT_TOKEN_BYTES` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_generate_connect_token(
+    server: *mut NetcodeServer,
+    client_id: u64,
+    expire_seconds: c_int,
+    timeout_seconds: c_int,
+    user_data: *const u8,
+    connect_token: *mut u8,
+) -> c_int {
+    let Some(server) = server.as_mut() else {
+        return NETCODE_ERROR;
+    };
+    if connect_token.is_null() {
+        return NETCODE_ERROR;
+    }
+    let mut builder = server
+        .0
+        .token(client_id)
+        .expire_seconds(expire_seconds)
+        .timeout_seconds(timeout_seconds);
+    if !user_data.is_null() {
+        let user_data = slice::from_raw_parts(user_data, NETCODE_USER_DATA_BYTES);
+        builder = builder.user_data(
+            user_data
+                .try_into()
+                .expect("the user data has the right size"),
+        );
+    }
+    let token = match builder.generate() {
+        Ok(token) => token,
+        Err(e) => {
+            log::error!("netcode C API failed to generate connect token: {e}");
+            return NETCODE_ERROR;
+        }
+    };
+    let Ok(token) = token.try_into_bytes() else {
+        return NETCODE_ERROR;
+    };
+    slice::from_raw_parts_mut(connect_token, NETCODE_CONNECT_TOKEN_BYTES).copy_from_slice(&token);
+    NETCODE_OK
+}
+
+/// Sends a packet of `bytes` bytes to a connected client.
+///
+/// # Safety
+/// `server` must be a server from `netcode_server_create` and `data` must point to `bytes` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_send_packet(
+    server: *mut NetcodeServer,
+    client_index: c_int,
+    data: *const u8,
+    bytes: c_int,
+) -> c_int {
+    let (Some(server), Some(idx), Ok(len)) = (
+        server.as_mut(),
+        self::client_index(client_index),
+        usize::try_from(bytes),
+    ) else {
+        return NETCODE_ERROR;
+    };
+    let Some(buf) = self::bytes(data, len) else {
+        return NETCODE_ERROR;
+    };
+    match server.0.send(buf, idx) {
+        Ok(SendOutcome::NotConnected) => NETCODE_ERROR,
+        result => status(result.map(drop)),
+    }
+}
+
+/// Receives a packet from any client into `buf` and the index of the client into `client_index`,
+/// returns its size or `NETCODE_ERROR` if no packet is available.
+/// A packet larger than `buf_len` is truncated, a buffer of `NETCODE_MAX_PACKET_SIZE` bytes fits any packet.
+///
+/// # Safety
+/// `server` must be a server from `netcode_server_create`, `client_index` must point to a writable `int`
+/// and `buf` must point to `buf_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_receive_packet(
+    server: *mut NetcodeServer,
+    client_index: *mut c_int,
+    buf: *mut u8,
+    buf_len: c_int,
+) -> c_int {
+    let (Some(server), Ok(len)) = (server.as_mut(), usize::try_from(buf_len)) else {
+        return NETCODE_ERROR;
+    };
+    if client_index.is_null() || buf.is_null() {
+        return NETCODE_ERROR;
+    }
+    let buf = slice::from_raw_parts_mut(buf, len);
+    let Some((idx, len)) = server.0.recv_into(buf) else {
+        return NETCODE_ERROR;
+    };
+    *client_index = idx.0 as c_int;
+    len as c_int
+}
+
+/// Returns 1 if a client is connected at `client_index`, 0 otherwise.
+///
+/// # Safety
+/// `server` must be a server from `netcode_server_create`.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_client_connected(
+    server: *const NetcodeServer,
+    client_index: c_int,
+) -> c_int {
+    let (Some(server), Some(idx)) = (server.as_ref(), self::client_index(client_index)) else {
+        return 0;
+    };
+    server.0.client_token_expiry(idx).is_some() as c_int
+}
+
+/// Gets the client id (from its connect token) of the client connected at `client_index`, 0 if none is.
+///
+/// # Safety
+/// `server` must be a server from `netcode_server_create`.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_client_id(
+    server: *const NetcodeServer,
+    client_index: c_int,
+) -> u64 {
+    let (Some(server), Some(idx)) = (server.as_ref(), self::client_index(client_index)) else {
+        return 0;
+    };
+    server
+        .0
+        .client_token_expiry(idx)
+        .and_then(|_| server.0.client_id(idx))
+        .unwrap_or_default()
+}
+
+/// Gets the number of connected clients.
+///
+/// # Safety
+/// `server` must be a server from `netcode_server_create`.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_num_connected_clients(
+    server: *const NetcodeServer,
+) -> c_int {
+    server
+        .as_ref()
+        .map_or(0, |server| server.0.num_connected_clients() as c_int)
+}
+
+/// Sends disconnect packets to a client and disconnects it.
+///
+/// # Safety
+/// `server` must be a server from `netcode_server_create`.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_disconnect_client(
+    server: *mut NetcodeServer,
+    client_index: c_int,
+) -> c_int {
+    let (Some(server), Some(idx)) = (server.as_mut(), self::client_index(client_index)) else {
+        return NETCODE_ERROR;
+    };
+    status(server.0.disconnect(idx))
+}
+
+/// Sends disconnect packets to all clients and disconnects them.
+///
+/// # Safety
+/// `server` must be a server from `netcode_server_create`.
+#[no_mangle]
+pub unsafe extern "C" fn netcode_server_disconnect_all_clients(
+    server: *mut NetcodeServer,
+) -> c_int {
+    let Some(server) = server.as_mut() else {
+        return NETCODE_ERROR;
+    };
+    status(server.0.disconnect_all())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+
+    #[test]
+    fn client_and_server_exchange_packets() {
+        unsafe {
+            let mut key = [0; NETCODE_KEY_BYTES];
+            assert_eq!(netcode_generate_key(key.as_mut_ptr()), NETCODE_OK);
+            let server = netcode_server_create(c"127.0.0.1:0".as_ptr(), 7, key.as_ptr());
+            assert!(!server.is_null());
+            assert!(netcode_server_create(c"not an address".as_ptr(), 7, key.as_ptr()).is_null());
+
+            let mut token = [0; NETCODE_CONNECT_TOKEN_BYTES];
+            let status = netcode_server_generate_connect_token(
+                server,
+                42,
+                30,
+                15,
+                ptr::null(),
+                token.as_mut_ptr(),
+            );
+            assert_eq!(status, NETCODE_OK);
+            let client = netcode_client_create(token.as_ptr());
+            assert!(!client.is_null());
+            assert_eq!(
+                netcode_client_state(client),
+                NETCODE_CLIENT_STATE_DISCONNECTED
+            );
+            netcode_client_connect(client);
+
+            let mut time = 0.0;
+            while netcode_client_state(client) != NETCODE_CLIENT_STATE_CONNECTED {
+                assert!(time < 5.0, "client failed to connect");
+                assert_eq!(netcode_client_update(client, time), NETCODE_OK);
+                assert_eq!(netcode_server_update(server, time), NETCODE_OK);
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                time += 1.0 / 60.0;
+            }
+            assert_eq!(netcode_server_num_connected_clients(server), 1);
+            assert_eq!(netcode_server_client_connected(server, 0), 1);
+            assert_eq!(netcode_server_client_connected(server, -1), 0);
+            assert_eq!(netcode_server_client_id(server, 0), 42);
+
+            let payload = b"hello";
+            let status =
+                netcode_client_send_packet(client, payload.as_ptr(), payload.len() as c_int);
+            assert_eq!(status, NETCODE_OK);
+            let mut buf = [0; NETCODE_MAX_PACKET_SIZE];
+            let mut client_index = -1;
+            let mut len = NETCODE_ERROR;
+            for _ in 0..100 {
+                netcode_server_update(server, time);
+                len = netcode_server_receive_packet(
+                    server,
+                    &mut client_index,
+                    buf.as_mut_ptr(),
+                    buf.len() as c_int,
+                );
+                if len != NETCODE_ERROR {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            assert_eq!((client_index, &buf[..len as usize]), (0, &payload[..]));
+            let receive =
+                netcode_client_receive_packet(client, buf.as_mut_ptr(), buf.len() as c_int);
+            assert_eq!(receive, NETCODE_ERROR);
+            // the server can't send to an index with no client
+            assert_eq!(
+                netcode_server_send_packet(server, 1, payload.as_ptr(), 5),
+                NETCODE_ERROR
+            );
+
+            assert_eq!(netcode_client_disconnect(client), NETCODE_OK);
+            netcode_client_destroy(client);
+            netcode_server_destroy(server);
+            netcode_client_destroy(ptr::null_mut());
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use alloc::boxed::Box;
+use core::net::SocketAddr;
+
+use crate::{packet::Packet, phase::PacketType};
+
+/// Whether a logged packet was sent or received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PacketDirection {
+    Sent,
+    Received,
+}
+
+/// A raw packet seen by a client or server, passed to a [`PacketLogger`](PacketLogger).
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct PacketRecord<'a> {
+    pub direction: PacketDirection,
+    /// The client or server time (as passed to `update`) when the packet was sent or received.
+    pub time: f64,
+    pub local_addr: SocketAddr,
+    pub remote_addr: SocketAddr,
+    /// The packet type from the prefix byte, `None` if it is not a valid type.
+    pub packet_type: Option<PacketType>,
+    /// The sequence number from the packet header, `None` for connection requests (which have no sequence).
+    pub sequence: Option<u64>,
+    /// The packet exactly as it was sent or received on the wire (encrypted).
+    pub raw: &'a [u8],
+    /// The decrypted payload of payload packets.
+    /// `None` for other packet types, or for received packets that failed to decrypt or were rejected.
+    pub payload: Option<&'a [u8]>,
+}
+
+impl<'a> PacketRecord<'a> {
+    pub(crate) fn new(
+        direction: PacketDirection,
+        time: f64,
+        local_addr: SocketAddr,
+        remote_addr: SocketAddr,
+        raw: &'a [u8],
+        payload: Option<&'a [u8]>,
+    ) -> Self {
+        let (packet_type, sequence) = match raw.first() {
+            None => (None, None),
+            Some(&Packet::REQUEST) => (Some(PacketType::Request), None),
+            Some(&prefix) => {
+                let (sequence_len, kind) = Packet::get_prefix(prefix);
+                let sequence = raw.get(1..1 + sequence_len).map(|bytes| {
+                    bytes
+                        .iter()
+                        .rev()
+                        .fold(0u64, |seq, &byte| seq << 8 | byte as u64)
+                });
+                (PacketType::from_kind(kind), sequence)
+            }
+        };
+        Self {
+            direction,
+            time,
+            local_addr,
+            remote_addr,
+            packet_type,
+            sequence,
+            raw,
+            payload,
+        }
+    }
+}
+
+/// A hook that receives every raw packet sent or received by a client or server,
+/// set with [`ClientConfig::packet_logger`](crate::ClientConfig::packet_logger) or [`ServerConfig::packet_logger`](crate::ServerConfig::packet_logger).
+///
+/// Implemented for closures, and by [`PcapWriter`](crate::PcapWriter) to write `.pcap` files that can be opened in Wireshark.
+///
+/// # Example
+/// ```
+/// use netcode::{ClientConfig, PacketRecord};
+///
+/// let cfg = ClientConfig::default().packet_logger(|record: &PacketRecord| {
+///     println!(
+///         "{:?} {:?} #{:?} ({} bytes) {} -> {}",
+///         record.direction, record.packet_type, record.sequence, record.raw.len(), record.local_addr, record.remote_addr
+///     );
+/// });
+/// ```
+pub trait PacketLogger {
+    fn log(&mut self, record: &PacketRecord<'_>);
+}
+
+impl<F> PacketLogger for F
+where
+    F: FnMut(&PacketRecord<'_>),
+{
+    fn log(&mut self, record: &PacketRecord<'_>) {
+        self(record)
+    }
+}
+
+pub(crate) type BoxedPacketLogger = Box<dyn PacketLogger + Send + Sync + 'static>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_header() {
+        let local = SocketAddr::from(([127, 0, 0, 1], 40000));
+        let remote = SocketAddr::from(([10, 0, 0, 2], 50000));
+        // keep-alive with a 2 byte sequence of 0x0102
+        let raw = [0x24, 0x02, 0x01, 0xAA, 0xBB];
+        let record = PacketRecord::new(PacketDirection::Sent, 1.0, local, remote, &raw, None);
+        assert_eq!(record.packet_type, Some(PacketType::KeepAlive));
+        assert_eq!(record.sequence, Some(0x0102));
+        let request =
+            PacketRecord::new(PacketDirection::Received, 1.0, local, remote, &[0; 8], None);
+        assert_eq!(request.packet_type, Some(PacketType::Request));
+        assert_eq!(request.sequence, None);
+    }
+}
@@ -1,31 +1,86 @@
-use std::{
-    collections::VecDeque,
-    net::{Ipv4Addr, SocketAddr},
-    time::{SystemTime, UNIX_EPOCH},
-};
+use alloc::{boxed::Box, vec::Vec};
+use core::net::SocketAddr;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+use std::net::Ipv4Addr;
 
 use crate::{
+    ack::{self, AckTracker},
     bytes::Bytes,
+    capture::{BoxedPacketLogger, PacketDirection, PacketLogger, PacketRecord},
+    coalesce::{self, SendQueue, MESSAGE_HEADER_SIZE},
+    config,
+    congestion::{Congestion, ConnectionQuality},
+    connect::ConnectConfig,
+    crypto::Cipher,
+    diagnostics::{LinkCheck, LinkCheckConfig, LinkCheckReport},
     error::{Error, Result},
+    metrics::{self, Side},
+    otel::ConnectSpan,
     packet::{
-        DisconnectPacket, KeepAlivePacket, Packet, PayloadPacket, RequestPacket, ResponsePacket,
+        DisconnectPacket, KeepAliveIntervalPacket, KeepAlivePacket, Packet, PathChallengePacket,
+        PathResponsePacket, PayloadLimitPacket, PayloadPacket, RequestPacket, ResponsePacket,
+        TransferPacket,
     },
-    replay::ReplayProtection,
-    socket::NetcodeSocket,
+    padding,
+    phase::{ClientPhaseTable, PacketAllowList},
+    pool::PayloadQueue,
+    rekey::{self, Rekey},
+    replay::{is_sequence_gap, ReplayProtection},
+    stats::ClientStats,
+    tick_sync::{ServerStamp, TickSync, CLIENT_STAMP_BYTES},
     token::{ChallengeToken, ConnectToken},
-    transceiver::Transceiver,
-    MAX_PACKET_SIZE, MAX_PKT_BUF_SIZE, PACKET_SEND_RATE_SEC,
+    trace,
+    transceiver::{SendBacklog, SendOutcome, Transceiver},
+    transfer::TransferAssembler,
+    MAX_HEARTBEAT_BYTES, MAX_PACKET_SIZE, MAX_PKT_BUF_SIZE, PACKET_SEND_RATE_SEC,
 };
 
-const RECV_BUF_SIZE: usize = 256 * 1024;
-const SEND_BUF_SIZE: usize = 256 * 1024;
+#[cfg(feature = "compression")]
+use crate::compression;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+use crate::socket::{NetcodeSocket, SocketOptions};
+
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+pub(crate) const RECV_BUF_SIZE: usize = 256 * 1024;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+pub(crate) const SEND_BUF_SIZE: usize = 256 * 1024;
+
+const GOOD_SEND_RATE: f64 = 30.0;
+const BAD_SEND_RATE: f64 = 10.0;
 
 type Callback<Ctx> = Box<dyn FnMut(ClientState, ClientState, &mut Ctx) + Send + Sync + 'static>;
+type TokenRenewCallback<Ctx> = Box<dyn FnMut(f64, &mut Ctx) + Send + Sync + 'static>;
+type HeartbeatCallback<Ctx> = Box<dyn FnMut(&[u8], &mut Ctx) + Send + Sync + 'static>;
+type TransferCallback<Ctx> = Box<dyn FnMut(&[u8], &mut Ctx) + Send + Sync + 'static>;
+type RebindCallback<Ctx> = Box<dyn FnMut(SocketAddr, SocketAddr, &mut Ctx) + Send + Sync + 'static>;
 /// Configuration for a client.
 ///
 /// * `num_disconnect_packets` - The number of redundant disconnect packets that will be sent to a server when the clients wants to disconnect.
 /// * `packet_send_rate` - The rate at which periodic packets will be sent to the server.
+/// * `keep_alive_interval` - Overrides the keep-alive interval recommended by the server, e.g. for mobile networks.
+/// * `send_on_update` - Whether periodic packets are sent from [`update`](Client::update), or only from [`flush`](Client::flush).
+/// * `max_payload_size` - The largest payload the client will send, for networks with a smaller MTU.
+/// * `coalesce_payloads` - Whether payloads sent in the same tick are combined into one packet.
+/// * `compress_payloads` - The size above which payloads are compressed, requires the `compression` feature.
+/// * `ack_payloads` - Whether payloads carry acks of the packets received from the server, see [`Client::acks`](Client::acks).
+/// * `tick_rate` - The rate of the server's tick, if tick sync is enabled, see [`Client::estimated_server_tick`](Client::estimated_server_tick).
+/// * `pad_payloads` - The sizes payloads are padded to, so the size of a packet doesn't tell what it carries.
+/// * `congestion_send_rates` - The good and bad send rates recommended by [`Client::send_budget`](Client::send_budget).
+/// * `socket_options` - Options of the socket the client creates, e.g. DSCP marking, see [`SocketOptions`](crate::SocketOptions).
+/// * `timeout_seconds` - Overrides the connection timeout from the connect token.
+/// * `connect_config` - How often handshake packets are resent and how long connecting may take, see [`ConnectConfig`](ConnectConfig).
+/// * `rekey_sessions` - Whether the session key is replaced on long connections, which the server has to agree on.
+/// * `rekey_interval` - How often the session key is replaced on long connections.
+/// * `rebind_on_error` - Whether the socket is replaced when it fails, instead of failing the update.
 /// * `on_state_change` - A callback that will be called when the client changes states.
+/// * `on_token_renew` - A callback that will be called when the connect token is about to expire.
+/// * `on_heartbeat` - A callback that will be called with the heartbeats the server attaches to its keep-alive packets.
+/// * `on_transfer` - A callback that will be called when the server hands the client over to another server.
+/// * `on_rebind` - A callback that will be called when the client replaced its failed socket.
+/// * `allowed_packets` - The packet types accepted in each client state.
+/// * `strict_netcode_1_02` - Whether to only accept the exact wire format of the netcode 1.02 reference implementation.
+/// * `ack_on_sequence_gap` - Whether a keep-alive is sent right away when packets from the server were lost.
+/// * `packet_logger` - A hook that receives every raw packet sent and received, see [`PacketLogger`](PacketLogger).
 ///
 /// # Example
 /// ```
@@ -51,8 +106,35 @@ type Callback<Ctx> = Box<dyn FnMut(ClientState, ClientState, &mut Ctx) + Send +
 pub struct ClientConfig<Ctx> {
     num_disconnect_packets: usize,
     packet_send_rate: f64,
+    keep_alive_interval: Option<f64>,
+    send_on_update: bool,
+    max_payload_size: usize,
+    coalesce_payloads: bool,
+    #[cfg(feature = "compression")]
+    compression_threshold: Option<usize>,
+    ack_payloads: bool,
+    tick_rate: Option<f64>,
+    padding_buckets: Vec<usize>,
+    good_send_rate: f64,
+    bad_send_rate: f64,
+    #[cfg(all(feature = "std", not(target_family = "wasm")))]
+    socket_options: SocketOptions,
+    timeout_seconds: Option<i32>,
+    connect_config: ConnectConfig,
+    rekey_sessions: bool,
+    rekey_interval: Option<f64>,
+    rebind_on_error: bool,
     context: Ctx,
-    on_state_change: Option<Callback<Ctx>>,
+    pub(crate) on_state_change: Option<Callback<Ctx>>,
+    token_renew_before: f64,
+    on_token_renew: Option<TokenRenewCallback<Ctx>>,
+    on_heartbeat: Option<HeartbeatCallback<Ctx>>,
+    on_transfer: Option<TransferCallback<Ctx>>,
+    on_rebind: Option<RebindCallback<Ctx>>,
+    allowed_packets: ClientPhaseTable,
+    strict_netcode_1_02: bool,
+    ack_on_sequence_gap: bool,
+    packet_logger: Option<BoxedPacketLogger>,
 }
 
 impl Default for ClientConfig<()> {
@@ -60,12 +142,63 @@ impl Default for ClientConfig<()> {
         Self {
             num_disconnect_packets: 10,
             packet_send_rate: PACKET_SEND_RATE_SEC,
+            keep_alive_interval: None,
+            send_on_update: true,
+            max_payload_size: MAX_PACKET_SIZE,
+            coalesce_payloads: false,
+            #[cfg(feature = "compression")]
+            compression_threshold: None,
+            ack_payloads: false,
+            tick_rate: None,
+            padding_buckets: Vec::new(),
+            good_send_rate: GOOD_SEND_RATE,
+            bad_send_rate: BAD_SEND_RATE,
+            #[cfg(all(feature = "std", not(target_family = "wasm")))]
+            socket_options: SocketOptions::default(),
+            timeout_seconds: None,
+            connect_config: ConnectConfig::default(),
+            rekey_sessions: false,
+            rekey_interval: None,
+            rebind_on_error: true,
             context: (),
             on_state_change: None,
+            token_renew_before: 0.0,
+            on_token_renew: None,
+            on_heartbeat: None,
+            on_transfer: None,
+            on_rebind: None,
+            allowed_packets: ClientPhaseTable::DEFAULT,
+            strict_netcode_1_02: false,
+            ack_on_sequence_gap: false,
+            packet_logger: None,
         }
     }
 }
 
+impl ClientConfig<()> {
+    /// A preset for fast-paced games: the client gives up on a silent server after 5 seconds instead of the token's timeout,
+    /// sends a keep-alive as soon as the server's packets are lost, and [`Client::send_budget`](Client::send_budget)
+    /// recommends 60 packets per second on a good connection and 20 on a bad one. <br>
+    /// It only changes options local to the client, so it can connect to servers with any configuration.
+    /// Adjust it further with the setters, e.g. `ClientConfig::competitive().tick_sync(60.0)`.
+    pub fn competitive() -> Self {
+        Self::default()
+            .timeout_seconds(5)
+            .ack_on_sequence_gap(true)
+            .congestion_send_rates(60.0, 20.0)
+    }
+    /// A preset for slow-paced games, e.g. turn-based or social games: the client waits 30 seconds for a silent server,
+    /// so short outages on mobile networks don't disconnect it, and [`Client::send_budget`](Client::send_budget)
+    /// recommends 20 packets per second on a good connection and 5 on a bad one. <br>
+    /// It only changes options local to the client, so it can connect to servers with any configuration.
+    /// Adjust it further with the setters, e.g. `ClientConfig::casual().keep_alive_interval(5.0)`.
+    pub fn casual() -> Self {
+        Self::default()
+            .timeout_seconds(30)
+            .congestion_send_rates(20.0, 5.0)
+    }
+}
+
 impl<Ctx> ClientConfig<Ctx> {
     /// Create a new, default client configuration with no context.
     pub fn new() -> ClientConfig<()> {
@@ -76,11 +209,41 @@ impl<Ctx> ClientConfig<Ctx> {
         Self {
             num_disconnect_packets: 10,
             packet_send_rate: PACKET_SEND_RATE_SEC,
+            keep_alive_interval: None,
+            send_on_update: true,
+            max_payload_size: MAX_PACKET_SIZE,
+            coalesce_payloads: false,
+            #[cfg(feature = "compression")]
+            compression_threshold: None,
+            ack_payloads: false,
+            tick_rate: None,
+            padding_buckets: Vec::new(),
+            good_send_rate: GOOD_SEND_RATE,
+            bad_send_rate: BAD_SEND_RATE,
+            #[cfg(all(feature = "std", not(target_family = "wasm")))]
+            socket_options: SocketOptions::default(),
+            timeout_seconds: None,
+            connect_config: ConnectConfig::default(),
+            rekey_sessions: false,
+            rekey_interval: None,
+            rebind_on_error: true,
             context: ctx,
             on_state_change: None,
+            token_renew_before: 0.0,
+            on_token_renew: None,
+            on_heartbeat: None,
+            on_transfer: None,
+            on_rebind: None,
+            allowed_packets: ClientPhaseTable::DEFAULT,
+            strict_netcode_1_02: false,
+            ack_on_sequence_gap: false,
+            packet_logger: None,
         }
     }
-    /// Set the number of redundant disconnect packets that will be sent to a server when the clients wants to disconnect.
+    /// Set the number of redundant disconnect packets that will be sent to a server when the clients wants to disconnect. <br>
+    /// Unlike the reference implementation, the client also answers a disconnect from the server with as many disconnect packets,
+    /// so a server that is [shutting down](crate::Server::shutdown) can count it as acknowledged (other servers ignore them).
+    /// A [strict](ClientConfig::strict_netcode_1_02) client doesn't answer.
     /// The default is 10 packets.
     pub fn num_disconnect_packets(mut self, num_disconnect_packets: usize) -> Self {
         self.num_disconnect_packets = num_disconnect_packets;
@@ -92,6 +255,159 @@ impl<Ctx> ClientConfig<Ctx> {
         self.packet_send_rate = rate_seconds;
         self
     }
+    /// Override the interval (in seconds) at which keep-alives are sent while connected and no payloads are sent,
+    /// instead of the one the server recommends with [`ServerConfig::keep_alive_interval`](crate::ServerConfig::keep_alive_interval). <br>
+    /// Carrier-grade NATs on mobile networks drop idle mappings after as little as 30 seconds, so a client that knows it is on
+    /// such a network can keep a shorter interval than the server recommends for everyone, see [`Client::keep_alive_interval`](Client::keep_alive_interval). <br>
+    /// By default the server's interval is used, or the [`packet_send_rate`](ClientConfig::packet_send_rate) if it doesn't recommend one.
+    ///
+    /// # Example
+    /// ```
+    /// use netcode::ClientConfig;
+    ///
+    /// let mobile = cfg!(any(target_os = "android", target_os = "ios"));
+    /// let cfg = ClientConfig::default();
+    /// let cfg = if mobile { cfg.keep_alive_interval(5.0) } else { cfg };
+    /// ```
+    pub fn keep_alive_interval(mut self, interval_seconds: f64) -> Self {
+        self.keep_alive_interval = Some(interval_seconds);
+        self
+    }
+    /// Set whether [`Client::update`](Client::update) sends the periodic (connection request/response and keep-alive) packets. <br>
+    /// Disable it to drive all sends from an external frame callback (e.g. right after sampling input on vsync)
+    /// with [`Client::send_at`](Client::send_at) and [`Client::flush`](Client::flush), instead of from a fixed-interval update loop.
+    /// The default is `true`.
+    pub fn send_on_update(mut self, send_on_update: bool) -> Self {
+        self.send_on_update = send_on_update;
+        self
+    }
+    /// Override the connection timeout (in seconds) from the connect token, negative for no timeout. <br>
+    /// The client times out if it doesn't receive any packets from the server for this long, both while connecting and while connected. <br>
+    /// Mobile clients may want a shorter timeout together with a shorter [`keep_alive_interval`](ClientConfig::keep_alive_interval) to hold NAT mappings.
+    /// By default the timeout from the connect token is used.
+    pub fn timeout_seconds(mut self, timeout_seconds: i32) -> Self {
+        self.timeout_seconds = Some(timeout_seconds);
+        self
+    }
+    /// Disable connection timeouts, e.g. to pause a client on a breakpoint without losing the connection. <br>
+    /// Equivalent to `timeout_seconds(-1)`, the server has to disable timeouts as well to keep the connection alive,
+    /// see [`ServerConfig::disable_timeout`](crate::ServerConfig::disable_timeout).
+    pub fn disable_timeout(self) -> Self {
+        self.timeout_seconds(-1)
+    }
+    /// Set how often connection requests and challenge responses are resent, and how long the client tries each server address
+    /// and all of them, see [`ConnectConfig`](ConnectConfig). <br>
+    /// The default resends at the [`packet_send_rate`](ClientConfig::packet_send_rate) and gives up on an address after the connection timeout.
+    pub fn connect_config(mut self, connect_config: ConnectConfig) -> Self {
+        self.connect_config = connect_config;
+        self
+    }
+    /// Set whether the session keys are replaced every 2^32 packets, with keys derived from the session key and the epoch
+    /// in the upper 32 bits of the packet sequence, for connections that stay up for days. <br>
+    /// This changes the wire format, so the server has to enable [`ServerConfig::rekey_sessions`](crate::ServerConfig::rekey_sessions) too:
+    /// servers that don't (and other implementations of the protocol) drop the rekeyed packets, and the connection times out.
+    /// The default is `false`, every packet is encrypted with the session key like the reference implementation does.
+    pub fn rekey_sessions(mut self, rekey_sessions: bool) -> Self {
+        self.rekey_sessions = rekey_sessions;
+        self
+    }
+    /// Set the interval (in seconds) after which the client replaces the key it encrypts packets with, and enable
+    /// [`rekey_sessions`](ClientConfig::rekey_sessions). <br>
+    /// The client jumps its packet sequence to the next epoch, so the server follows without a handshake.
+    /// Regardless of the interval, the key is replaced every 2^32 packets. The default is no interval.
+    pub fn rekey_interval(mut self, interval_seconds: f64) -> Self {
+        self.rekey_interval = Some(interval_seconds);
+        self.rekey_sessions = true;
+        self
+    }
+    /// Set whether the client replaces its socket with one bound to a new ephemeral port when the socket fails with an error
+    /// that retrying won't fix, e.g. when the network interface goes down or a phone moves from Wi-Fi to mobile data. <br>
+    /// The session continues from the new port, which the server only accepts with
+    /// [`ServerConfig::allow_migration`](crate::ServerConfig::allow_migration) (otherwise the client times out as before).
+    /// The client rebinds at most once per update, and [`on_rebind`](ClientConfig::on_rebind) is called when it does.
+    /// If the transceiver can't rebind (see [`Transceiver::rebind`](crate::Transceiver::rebind)) or rebinding fails,
+    /// [`Client::try_update`](Client::try_update) returns the error. The default is `true`.
+    pub fn rebind_on_error(mut self, rebind_on_error: bool) -> Self {
+        self.rebind_on_error = rebind_on_error;
+        self
+    }
+    /// Set the largest payload (in bytes) the client will send, [`Client::send`](Client::send) returns an error for larger payloads. <br>
+    /// Lower it on networks with a smaller MTU (VPNs, mobile) where full size packets would be silently dropped,
+    /// each packet adds up to 25 bytes of netcode overhead on top of the payload, plus the IP and UDP headers. <br>
+    /// The value is clamped to `1..=MAX_PACKET_SIZE`, the default is [`MAX_PACKET_SIZE`](crate::MAX_PACKET_SIZE) (1200 bytes).
+    pub fn max_payload_size(mut self, max_payload_size: usize) -> Self {
+        self.max_payload_size = max_payload_size.clamp(1, MAX_PACKET_SIZE);
+        self
+    }
+    /// Set whether payloads are queued and sent together instead of one packet per [`Client::send`](Client::send). <br>
+    /// Queued payloads are combined into as few packets as the [max payload size](Client::max_payload_size) allows,
+    /// each prefixed with its length (2 bytes), and sent from the next [`update`](Client::update) or [`flush`](Client::flush).
+    /// Many small sends per tick then cost one packet (and one encryption) instead of one each. <br>
+    /// Received payloads are split back into the payloads that were sent, so the server has to enable
+    /// [`ServerConfig::coalesce_payloads`](crate::ServerConfig::coalesce_payloads) as well. The default is `false`.
+    pub fn coalesce_payloads(mut self, coalesce_payloads: bool) -> Self {
+        self.coalesce_payloads = coalesce_payloads;
+        self
+    }
+    /// Compress payloads of at least `threshold` bytes with LZ4 before they are encrypted, e.g. for snapshot-heavy games. <br>
+    /// Each payload gets a 1 byte header that says whether it is compressed, payloads that don't get smaller are sent as they are.
+    /// If [payloads are coalesced](ClientConfig::coalesce_payloads), the combined payload is compressed. <br>
+    /// Received payloads are decompressed, so the server has to enable
+    /// [`ServerConfig::compress_payloads`](crate::ServerConfig::compress_payloads) as well. By default payloads are not compressed.
+    #[cfg(feature = "compression")]
+    pub fn compress_payloads(mut self, threshold: usize) -> Self {
+        self.compression_threshold = Some(threshold);
+        self
+    }
+    /// Set whether payloads carry acks of the packets received from the server, so the client learns which of its payloads
+    /// the server received (see [`Client::acks`](Client::acks)), e.g. for reliability or delta compression layered on top. <br>
+    /// Each payload gets a 12 byte header with the latest sequence received from the server and which of the 32 before it were received.
+    /// Acks only travel on payloads (not keep-alives), so the server has to send payloads for the client to get acks, and vice versa. <br>
+    /// The server has to enable [`ServerConfig::ack_payloads`](crate::ServerConfig::ack_payloads) as well. The default is `false`.
+    pub fn ack_payloads(mut self, ack_payloads: bool) -> Self {
+        self.ack_payloads = ack_payloads;
+        self
+    }
+    /// Enable tick sync: the client estimates the server's tick from the stamps on its keep-alives,
+    /// see [`Client::estimated_server_tick`](Client::estimated_server_tick). <br>
+    /// A stamped keep-alive is sent at least once per second, also while sending payloads, and the stamp takes 8 bytes of the
+    /// [heartbeat](Client::set_heartbeat_payload). The server has to enable [`ServerConfig::tick_sync`](crate::ServerConfig::tick_sync)
+    /// with the same rate. It is ignored with [`strict_netcode_1_02`](ClientConfig::strict_netcode_1_02). Tick sync is disabled by default.
+    pub fn tick_sync(mut self, tick_rate: f64) -> Self {
+        self.tick_rate = Some(tick_rate);
+        self
+    }
+    /// Pad payloads to the smallest of the `buckets` sizes (in bytes) they fit in before they are encrypted, so the size of a packet
+    /// doesn't leak what it carries (e.g. that a player fired or came into view). Payloads larger than every bucket are padded
+    /// to the [max payload size](ClientConfig::max_payload_size). <br>
+    /// Each payload gets a 2 byte length header, the padding is stripped on receive. Padding costs bandwidth,
+    /// a few buckets such as `[128, 512, 1200]` keep it in check. Sizes are clamped to [`MAX_PACKET_SIZE`](crate::MAX_PACKET_SIZE). <br>
+    /// The server has to enable [`ServerConfig::pad_payloads`](crate::ServerConfig::pad_payloads) as well. Payloads are not padded by default.
+    pub fn pad_payloads(mut self, buckets: &[usize]) -> Self {
+        self.padding_buckets = buckets
+            .iter()
+            .map(|&size| size.min(MAX_PACKET_SIZE))
+            .collect();
+        self.padding_buckets.sort_unstable();
+        self.padding_buckets.dedup();
+        self
+    }
+    /// Set the send rates (in packets per second) recommended by [`Client::send_budget`](Client::send_budget)
+    /// when the [connection quality](Client::connection_quality) is good and bad. <br>
+    /// The client doesn't throttle sends itself, the budget is a recommendation for the game's own send rate.
+    /// The default is 30 packets per second when good, and 10 when bad.
+    pub fn congestion_send_rates(mut self, good_rate: f64, bad_rate: f64) -> Self {
+        self.good_send_rate = good_rate;
+        self.bad_send_rate = bad_rate;
+        self
+    }
+    /// Set the options of the socket the client creates in [`Client::with_config`](Client::with_config), e.g. DSCP marking or `SO_REUSEPORT`. <br>
+    /// They are ignored by custom transceivers. The default leaves every option at the OS default.
+    #[cfg(all(feature = "std", not(target_family = "wasm")))]
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = socket_options;
+        self
+    }
     /// Set a callback that will be called when the client changes states.
     pub fn on_state_change<F>(mut self, cb: F) -> Self
     where
@@ -100,6 +416,144 @@ impl<Ctx> ClientConfig<Ctx> {
         self.on_state_change = Some(Box::new(cb));
         self
     }
+    /// Set a callback that will be called once when the connect token has `renew_before_seconds` or less left before it expires,
+    /// see [`Client::token_time_remaining`](Client::token_time_remaining).
+    ///
+    /// The callback will be called with the remaining time in seconds, use it to request a new connect token
+    /// before the current one expires instead of reacting to a failed (re)connect. <br>
+    /// It is never called for tokens that don't expire.
+    pub fn on_token_renew<F>(mut self, renew_before_seconds: f64, cb: F) -> Self
+    where
+        F: FnMut(f64, &mut Ctx) + Send + Sync + 'static,
+    {
+        self.token_renew_before = renew_before_seconds;
+        self.on_token_renew = Some(Box::new(cb));
+        self
+    }
+    /// Set a callback that will be called with the heartbeat of every keep-alive packet from the server that carries one,
+    /// see [`Server::set_heartbeat_payload`](crate::Server::set_heartbeat_payload). <br>
+    /// Keep-alives without a heartbeat don't call it.
+    pub fn on_heartbeat<F>(mut self, cb: F) -> Self
+    where
+        F: FnMut(&[u8], &mut Ctx) + Send + Sync + 'static,
+    {
+        self.on_heartbeat = Some(Box::new(cb));
+        self
+    }
+    /// Set a callback that will be called with a connect token for another server when the server hands the client over to it,
+    /// see [`Server::transfer_client`](crate::Server::transfer_client). <br>
+    /// The client stays connected, pass the token to [`Client::reconnect`](Client::reconnect) to move to the other server,
+    /// it is kept until [`Client::take_transfer_token`](Client::take_transfer_token) is called.
+    pub fn on_transfer<F>(mut self, cb: F) -> Self
+    where
+        F: FnMut(&[u8], &mut Ctx) + Send + Sync + 'static,
+    {
+        self.on_transfer = Some(Box::new(cb));
+        self
+    }
+    /// Set a callback that will be called with the old and the new local address when the client replaced its failed socket,
+    /// see [`rebind_on_error`](ClientConfig::rebind_on_error).
+    pub fn on_rebind<F>(mut self, cb: F) -> Self
+    where
+        F: FnMut(SocketAddr, SocketAddr, &mut Ctx) + Send + Sync + 'static,
+    {
+        self.on_rebind = Some(Box::new(cb));
+        self
+    }
+    /// Restrict the packet types the client accepts from the server while in `state`. <br>
+    /// Packets of other types are dropped before decryption and counted in [`ClientStats::out_of_phase`](crate::ClientStats::out_of_phase).
+    ///
+    /// The list can only narrow the protocol defaults, which are:
+    /// * [`SendingConnectionRequest`](ClientState::SendingConnectionRequest) - denied and challenge packets.
+    /// * [`SendingChallengeResponse`](ClientState::SendingChallengeResponse) - denied and keep-alive packets.
+    /// * [`Connected`](ClientState::Connected) - keep-alive, payload and disconnect packets.
+    ///
+    /// In any other state no packets are accepted.
+    pub fn allowed_packets(mut self, state: ClientState, list: PacketAllowList) -> Self {
+        self.allowed_packets.set_for_state(state, list);
+        self
+    }
+    /// Restrict the wire format to the netcode 1.02 reference implementation, to interoperate with servers written in C. <br>
+    /// Packets the reference implementation would reject (connection requests that aren't exactly 1078 bytes,
+    /// control packets with missing or trailing bytes and empty payloads) are dropped, sending an empty payload returns an error,
+    /// and IPv6 addresses in connect tokens are encoded as 8 little-endian 16-bit segments like the reference implementation does.
+    /// A disconnect from the server isn't confirmed, see [`num_disconnect_packets`](ClientConfig::num_disconnect_packets). <br>
+    /// Tokens for a strict client have to be generated with [`ConnectTokenBuilder::strict_netcode_1_02`](crate::ConnectTokenBuilder::strict_netcode_1_02)
+    /// if they contain IPv6 addresses.
+    /// The default is `false`.
+    pub fn strict_netcode_1_02(mut self, strict: bool) -> Self {
+        self.strict_netcode_1_02 = strict;
+        self
+    }
+    /// Send a keep-alive to the server right away when a packet from it skips ahead in sequence (i.e. packets were lost),
+    /// instead of waiting for the next scheduled one. <br>
+    /// This lets the server (and any retransmission logic layered on top of the payloads) notice the loss sooner.
+    /// Gaps are counted in [`ClientStats::sequence_gaps`](crate::ClientStats::sequence_gaps) either way. The default is `false`.
+    pub fn ack_on_sequence_gap(mut self, ack_on_sequence_gap: bool) -> Self {
+        self.ack_on_sequence_gap = ack_on_sequence_gap;
+        self
+    }
+    /// Set a hook that receives every raw packet the client sends and receives, for debugging. <br>
+    /// Received packets are logged before they are filtered or decrypted, so rejected packets are logged as well.
+    /// Use a [`PcapWriter`](crate::PcapWriter) to write them to a capture file.
+    pub fn packet_logger(mut self, logger: impl PacketLogger + Send + Sync + 'static) -> Self {
+        self.packet_logger = Some(Box::new(logger));
+        self
+    }
+    /// Check that the options fit together, e.g. that the timeout is longer than the keep-alive interval
+    /// and that payloads still fit in the max payload size after the enabled headers. <br>
+    /// The client checks its configuration when it is created, call this to check one earlier.
+    ///
+    /// # Example
+    /// ```
+    /// use netcode::{ClientConfig, ConfigError, Error};
+    ///
+    /// let cfg = ClientConfig::default().max_payload_size(12).ack_payloads(true);
+    /// assert!(matches!(
+    ///     cfg.validate(),
+    ///     Err(Error::InvalidConfig(ConfigError::PayloadTooSmall { .. }))
+    /// ));
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        for (option, value) in [
+            ("packet_send_rate", Some(self.packet_send_rate)),
+            ("keep_alive_interval", self.keep_alive_interval),
+            ("rekey_interval", self.rekey_interval),
+            ("tick_rate", self.tick_rate),
+            ("good_send_rate", Some(self.good_send_rate)),
+            ("bad_send_rate", Some(self.bad_send_rate)),
+        ] {
+            if let Some(value) = value {
+                config::check_positive(option, value)?;
+            }
+        }
+        match self.keep_alive_interval {
+            Some(interval) => {
+                config::check_timeout(self.timeout_seconds, "keep_alive_interval", interval)?
+            }
+            None => config::check_timeout(
+                self.timeout_seconds,
+                "packet_send_rate",
+                self.packet_send_rate,
+            )?,
+        }
+        let mut overhead = 0;
+        if self.ack_payloads {
+            overhead += ack::HEADER_SIZE;
+        }
+        if !self.padding_buckets.is_empty() {
+            overhead += padding::HEADER_SIZE;
+        }
+        #[cfg(feature = "compression")]
+        if self.compression_threshold.is_some() {
+            overhead += compression::HEADER_SIZE;
+        }
+        if self.coalesce_payloads {
+            overhead += MESSAGE_HEADER_SIZE;
+        }
+        config::check_payload_size(self.max_payload_size, overhead, &self.padding_buckets)?;
+        Ok(())
+    }
 }
 
 /// The states in the client state machine.
@@ -120,13 +574,14 @@ impl<Ctx> ClientConfig<Ctx> {
 ///  - The client application may send payload packets to the server.
 ///  - In the absence of payload packets sent by the client application, the client generates and sends connection keep-alive packets
 ///    to the server at some rate (default is 10HZ, can be overridden in [`ClientConfig`](ClientConfig)).
-///  - If no payload or keep-alive packets are received from the server within the timeout period specified in the connect token,
-///    the client transitions to `ConnectionTimedOut`.
+///  - If no payload or keep-alive packets are received from the server within the timeout period specified in the connect token
+///    (can be overridden in [`ClientConfig`](ClientConfig)), the client transitions to `ConnectionTimedOut`.
 ///  - While `Connected`, if the client receives a disconnect packet from the server, it transitions to `Disconnected`.
 ///    If the client wishes to disconnect from the server,
 ///    it sends a number of redundant connection disconnect packets (default is 10, can be overridden in [`ClientConfig`](ClientConfig))
 ///    before transitioning to `Disconnected`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[non_exhaustive]
 pub enum ClientState {
     /// The connect token has expired.
     ConnectTokenExpired,
@@ -139,6 +594,10 @@ pub enum ClientState {
     ChallengeResponseTimedOut,
     /// The server has denied the client's connection request, most likely due to the server being full.
     ConnectionDenied,
+    /// Nothing is listening on the server's address, reported by an ICMP port unreachable for a packet sent to it
+    /// while connecting or connected. <br>
+    /// Only some platforms report these to the client's socket (e.g. Windows), on others an unreachable server times out instead.
+    ServerUnreachable,
     /// The client is disconnected from the server.
     Disconnected,
     /// The client is waiting for a response from the server after sending a connection request packet.
@@ -149,6 +608,26 @@ pub enum ClientState {
     Connected,
 }
 
+impl ClientState {
+    /// Returns true if this is one of the error states.
+    pub fn is_error(self) -> bool {
+        self < ClientState::Disconnected
+    }
+    /// Returns true if this is one of the pending states, i.e. the client is still connecting.
+    pub fn is_pending(self) -> bool {
+        self == ClientState::SendingConnectionRequest
+            || self == ClientState::SendingChallengeResponse
+    }
+    /// Returns true if this is the connected state.
+    pub fn is_connected(self) -> bool {
+        self == ClientState::Connected
+    }
+    /// Returns true if this is the disconnected state.
+    pub fn is_disconnected(self) -> bool {
+        self == ClientState::Disconnected
+    }
+}
+
 /// The `netcode` client.
 ///
 /// To create a client one should obtain a connection token from a web backend (by REST API or other means). <br>
@@ -186,61 +665,120 @@ pub struct Client<T: Transceiver, Ctx = ()> {
     state: ClientState,
     time: f64,
     start_time: f64,
+    connect_start_time: f64,
     last_send_time: f64,
     last_receive_time: f64,
+    last_rebind_time: f64,
+    // packets the transceiver would block on, sent again before any new ones
+    send_backlog: SendBacklog,
+    // the servers the transceiver opened a connection to, see `Transceiver::open_connection`
+    open_connections: Vec<SocketAddr>,
     server_addr_idx: usize,
+    // the address of the other IP family raced against the current one, see `ConnectConfig::happy_eyeballs`
+    race_addr_idx: Option<usize>,
     sequence: u64,
+    rekey: Rekey,
     challenge_token_sequence: u64,
     challenge_token_data: [u8; ChallengeToken::SIZE],
     client_index: i32,
     max_clients: i32,
     token: ConnectToken,
+    // the session keys of the token, with their AEADs set up
+    send_cipher: Cipher,
+    receive_cipher: Cipher,
+    token_start_time: Option<f64>,
+    token_renew_notified: bool,
     replay_protection: ReplayProtection,
     should_disconnect: bool,
     should_disconnect_state: ClientState,
-    packet_queue: VecDeque<Vec<u8>>,
+    packet_queue: PayloadQueue<()>,
+    send_queue: SendQueue,
+    acks: AckTracker,
+    congestion: Congestion,
+    link_check: Option<LinkCheck>,
+    link_check_report: Option<LinkCheckReport>,
+    heartbeat: Vec<u8>,
+    server_max_payload_size: Option<usize>,
+    server_keep_alive_interval: Option<f64>,
+    transfer: TransferAssembler,
+    transfer_token: Option<Vec<u8>>,
+    tick_sync: Option<TickSync>,
+    stats: ClientStats,
+    connect_span: ConnectSpan,
     cfg: ClientConfig<Ctx>,
 }
 
 impl<Trx: Transceiver, Ctx> Client<Trx, Ctx> {
-    fn from_token(token_bytes: &[u8], cfg: ClientConfig<Ctx>, trx: Trx) -> Result<Self> {
+    fn read_token(token_bytes: &[u8], strict_netcode_1_02: bool) -> Result<ConnectToken> {
         if token_bytes.len() != ConnectToken::SIZE {
             return Err(Error::SizeMismatch(ConnectToken::SIZE, token_bytes.len()));
         }
         let mut buf = [0u8; ConnectToken::SIZE];
         buf.copy_from_slice(token_bytes);
-        let mut cursor = std::io::Cursor::new(&mut buf[..]);
-        let token = match ConnectToken::read_from(&mut cursor) {
+        let mut cursor = crate::io::Cursor::new(&mut buf[..]);
+        let mut token = match <ConnectToken as Bytes>::read_from(&mut cursor) {
             Ok(token) => token,
             Err(err) => {
                 log::error!("invalid connect token: {err}");
                 return Err(Error::InvalidToken(err));
             }
         };
+        if strict_netcode_1_02 {
+            token.server_addresses = token.server_addresses.swap_ipv6_segments();
+        }
+        Ok(token)
+    }
+    fn from_token(token_bytes: &[u8], cfg: ClientConfig<Ctx>, trx: Trx) -> Result<Self> {
+        cfg.validate()?;
+        let token = Self::read_token(token_bytes, cfg.strict_netcode_1_02)?;
         log::info!("client started on {}", trx.addr());
         Ok(Self {
+            send_cipher: Cipher::with_rekeying(*token.client_to_server_key, cfg.rekey_sessions),
+            receive_cipher: Cipher::with_rekeying(*token.server_to_client_key, cfg.rekey_sessions),
             transceiver: trx,
             state: ClientState::Disconnected,
             time: 0.0,
             start_time: 0.0,
+            connect_start_time: 0.0,
             last_send_time: f64::NEG_INFINITY,
             last_receive_time: f64::NEG_INFINITY,
+            last_rebind_time: f64::NEG_INFINITY,
+            send_backlog: SendBacklog::default(),
+            open_connections: Vec::new(),
             server_addr_idx: 0,
+            race_addr_idx: None,
             sequence: 0,
+            rekey: Rekey::new(0, 0.0),
             challenge_token_sequence: 0,
             challenge_token_data: [0u8; ChallengeToken::SIZE],
             client_index: 0,
             max_clients: 0,
             token,
+            token_start_time: None,
+            token_renew_notified: false,
             replay_protection: ReplayProtection::new(),
             should_disconnect: false,
             should_disconnect_state: ClientState::Disconnected,
-            packet_queue: VecDeque::new(),
+            packet_queue: PayloadQueue::with_capacity(0),
+            send_queue: SendQueue::new(),
+            acks: AckTracker::new(),
+            congestion: Congestion::new(),
+            link_check: None,
+            link_check_report: None,
+            heartbeat: Vec::new(),
+            server_max_payload_size: None,
+            server_keep_alive_interval: None,
+            transfer: TransferAssembler::new(),
+            transfer_token: None,
+            tick_sync: None,
+            stats: ClientStats::new(cfg.max_payload_size),
+            connect_span: ConnectSpan::default(),
             cfg,
         })
     }
 }
 
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
 impl Client<NetcodeSocket> {
     /// Create a new client with a default configuration.
     ///
@@ -262,12 +800,50 @@ impl Client<NetcodeSocket> {
             NetcodeSocket::new((Ipv4Addr::UNSPECIFIED, 0), SEND_BUF_SIZE, RECV_BUF_SIZE)?;
         Client::from_token(token_bytes, ClientConfig::default(), netcode_sock)
     }
+    /// Create a client with a default configuration and start connecting to `server_addr` without a connect token from a matchmaker,
+    /// for local development. <br>
+    /// The client makes its own token with a well-known key, which the server only accepts with
+    /// [`ServerConfig::allow_insecure`](crate::ServerConfig::allow_insecure). Like the insecure connect of yojimbo, this skips
+    /// the authentication connect tokens exist for: anyone can connect as any client id. Requires the `insecure` feature.
+    ///
+    /// # Example
+    /// ```
+    /// use netcode::{Client, Server, ServerConfig};
+    ///
+    /// let cfg = ServerConfig::default().allow_insecure(true);
+    /// let server = Server::with_config("127.0.0.1:0", 0x11223344, netcode::generate_key(), cfg).unwrap();
+    ///
+    /// let client = Client::connect_insecure(server.addr(), 123, 0x11223344).unwrap();
+    /// assert!(client.is_pending());
+    /// ```
+    #[cfg(feature = "insecure")]
+    pub fn connect_insecure(
+        server_addr: SocketAddr,
+        client_id: u64,
+        protocol_id: u64,
+    ) -> Result<Self> {
+        log::warn!(
+            "client is connecting to {server_addr} insecurely, never do this in a release build"
+        );
+        let token = ConnectToken::build(
+            server_addr,
+            protocol_id,
+            client_id,
+            crate::crypto::INSECURE_KEY,
+        )
+        .generate()?
+        .try_into_bytes()?;
+        let mut client = Client::new(&token)?;
+        client.connect();
+        Ok(client)
+    }
 }
 
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
 impl<Ctx> Client<NetcodeSocket, Ctx> {
     /// Create a new client with a custom configuration. <br>
     /// Callbacks with context can be registered with the client to be notified when the client changes states. <br>
-    /// See [`ClientConfig`](ClientConfig) for more details.
+    /// See [`ClientConfig`](ClientConfig) for more details, an invalid configuration returns the error of [`ClientConfig::validate`](ClientConfig::validate).
     ///
     /// # Example
     /// ```
@@ -287,26 +863,63 @@ impl<Ctx> Client<NetcodeSocket, Ctx> {
     /// let mut client = Client::with_config(&token_bytes, cfg).unwrap();
     /// ```
     pub fn with_config(token_bytes: &[u8], cfg: ClientConfig<Ctx>) -> Result<Self> {
-        let netcode_sock =
-            NetcodeSocket::new((Ipv4Addr::UNSPECIFIED, 0), SEND_BUF_SIZE, RECV_BUF_SIZE)?;
+        let netcode_sock = NetcodeSocket::with_options(
+            (Ipv4Addr::UNSPECIFIED, 0),
+            SEND_BUF_SIZE,
+            RECV_BUF_SIZE,
+            &cfg.socket_options,
+        )?;
         Client::from_token(token_bytes, cfg, netcode_sock)
     }
 }
 
 impl<T: Transceiver, Ctx> Client<T, Ctx> {
-    const ALLOWED_PACKETS: u8 = 1 << Packet::DENIED
-        | 1 << Packet::CHALLENGE
-        | 1 << Packet::KEEP_ALIVE
-        | 1 << Packet::PAYLOAD
-        | 1 << Packet::DISCONNECT;
-
     fn set_state(&mut self, state: ClientState) {
         log::debug!("client state changing from {:?} to {:?}", self.state, state);
+        trace::event!(DEBUG, from = ?self.state, to = ?state, "client state changed");
         if let Some(ref mut cb) = self.cfg.on_state_change {
             cb(self.state, state, &mut self.cfg.context)
         }
         self.state = state;
     }
+    fn on_heartbeat(&mut self, heartbeat: &[u8]) {
+        if heartbeat.is_empty() {
+            return;
+        }
+        if let Some(ref mut cb) = self.cfg.on_heartbeat {
+            cb(heartbeat, &mut self.cfg.context)
+        }
+    }
+    // Takes the server's stamp in front of its heartbeat if tick sync is enabled, returns the heartbeat after it.
+    fn receive_tick_stamp<'a>(&mut self, heartbeat: &'a [u8]) -> &'a [u8] {
+        let Some(ref mut sync) = self.tick_sync else {
+            return heartbeat;
+        };
+        let Some((stamp, heartbeat)) = ServerStamp::read(heartbeat) else {
+            return heartbeat;
+        };
+        sync.receive(self.time, stamp);
+        heartbeat
+    }
+    fn on_transfer(&mut self, fragment: u8, data: &[u8]) {
+        let Some(token_bytes) = self.transfer.receive(fragment, data) else {
+            return;
+        };
+        let token = match Self::read_token(token_bytes, self.cfg.strict_netcode_1_02) {
+            Ok(token) => token,
+            Err(e) => {
+                log::debug!("client ignored transfer to another server: {e}");
+                return;
+            }
+        };
+        let server = token.server_addresses[0];
+        log::info!("client transferred to server {server} by its server");
+        trace::event!(INFO, server = %server, "client transferred");
+        if let Some(ref mut cb) = self.cfg.on_transfer {
+            cb(token_bytes, &mut self.cfg.context)
+        }
+        self.transfer_token = Some(token_bytes.to_vec());
+    }
     fn reset_connection(&mut self) {
         self.start_time = self.time;
         self.last_send_time = self.time - 1.0; // force a packet to be sent immediately
@@ -314,7 +927,18 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
         self.should_disconnect = false;
         self.should_disconnect_state = ClientState::Disconnected;
         self.challenge_token_sequence = 0;
+        self.rekey = Rekey::new(self.sequence, self.time);
         self.replay_protection = ReplayProtection::new();
+        self.set_server_max_payload_size(None);
+        self.server_keep_alive_interval = None;
+        self.transfer.clear();
+        self.tick_sync = (self.cfg.tick_rate)
+            .filter(|_| !self.cfg.strict_netcode_1_02)
+            .map(TickSync::new);
+    }
+    fn set_server_max_payload_size(&mut self, max_payload_size: Option<usize>) {
+        self.server_max_payload_size = max_payload_size;
+        self.stats.max_payload_size = self.max_payload_size();
     }
     fn reset(&mut self, new_state: ClientState) {
         self.sequence = 0;
@@ -322,12 +946,32 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
         self.max_clients = 0;
         self.start_time = 0.0;
         self.server_addr_idx = 0;
+        self.link_check = None;
+        self.send_queue.clear();
+        self.acks.clear();
+        self.congestion = Congestion::new();
+        self.close_connections(|_| true);
+        self.connect_span.fail("disconnected");
         self.set_state(new_state);
         self.reset_connection();
         log::debug!("client disconnected");
     }
+    // Handshake packets are resent at their own interval, if one is configured, and keep-alives at the keep-alive interval.
+    fn send_interval(&self) -> f64 {
+        match self.state {
+            ClientState::SendingConnectionRequest | ClientState::SendingChallengeResponse => self
+                .cfg
+                .connect_config
+                .resend_interval
+                .unwrap_or(self.cfg.packet_send_rate),
+            ClientState::Connected => self.keep_alive_interval(),
+            _ => self.cfg.packet_send_rate,
+        }
+    }
     fn send_packets(&mut self) -> Result<()> {
-        if self.last_send_time + self.cfg.packet_send_rate >= self.time {
+        let stamp_due = self.state == ClientState::Connected
+            && (self.tick_sync.as_ref()).is_some_and(|sync| sync.is_due(self.time));
+        if self.last_send_time + self.send_interval() >= self.time && !stamp_due {
             return Ok(());
         }
         let packet = match self.state {
@@ -346,38 +990,155 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
             }
             ClientState::Connected => {
                 log::trace!("client sending connection keep-alive packet to server");
-                KeepAlivePacket::create(0, 0)
+                return self.send_keep_alive();
             }
             _ => return Ok(()),
         };
-        self.send_packet(packet)
+        let race_addr = self
+            .race_addr_idx
+            .filter(|_| self.state == ClientState::SendingConnectionRequest)
+            .filter(|_| {
+                let stagger = self.cfg.connect_config.happy_eyeballs.unwrap_or_default();
+                self.time - self.start_time >= stagger
+            })
+            .map(|idx| self.token.server_addresses[idx]);
+        if let Some(race_addr) = race_addr {
+            log::debug!("client racing connection request to {race_addr}");
+            // a broken path of the raced family must not fail the connect attempt
+            if let Err(e) = self.send_packet_to(&packet, race_addr) {
+                log::debug!("client failed to send connection request to {race_addr}: {e}");
+            }
+        }
+        self.send_packet(packet)?;
+        Ok(())
+    }
+    // The next address of the other IP family than the current one, to race against it.
+    fn race_addr_idx(&self) -> Option<usize> {
+        self.cfg.connect_config.happy_eyeballs?;
+        let addresses = &self.token.server_addresses;
+        let current = addresses[self.server_addr_idx].is_ipv6();
+        (self.server_addr_idx + 1..addresses.len()).find(|&idx| addresses[idx].is_ipv6() != current)
     }
-    fn connect_to_next_server(&mut self) -> std::result::Result<(), ()> {
+    fn connect_to_next_server(&mut self) -> core::result::Result<(), ()> {
         if self.server_addr_idx + 1 >= self.token.server_addresses.len() {
             log::debug!("no more servers to connect to");
             return Err(());
         }
+        let server_addr = self.token.server_addresses[self.server_addr_idx];
+        self.close_connections(|addr| addr == server_addr);
         self.server_addr_idx += 1;
         self.connect();
         Ok(())
     }
-    fn send_packet(&mut self, packet: Packet) -> Result<()> {
+    // Tears down the connections the transceiver opened to the servers that match `filter`.
+    fn close_connections(&mut self, filter: impl Fn(SocketAddr) -> bool) {
+        let transceiver = &self.transceiver;
+        self.open_connections.retain(|&addr| {
+            if !filter(addr) {
+                return true;
+            }
+            if let Err(e) = transceiver
+                .close_connection(addr)
+                .map_err(Into::<Error>::into)
+            {
+                log::debug!("client failed to close the connection to {addr}: {e}");
+            }
+            false
+        });
+    }
+    // Moves the sequence to the next epoch when the rekey interval passed, before a session packet takes it.
+    fn rekey(&mut self) {
+        if !self.cfg.rekey_sessions {
+            return;
+        }
+        let (sequence, rekeyed) =
+            self.rekey
+                .next_sequence(self.sequence, self.time, self.cfg.rekey_interval);
+        self.sequence = sequence;
+        if rekeyed {
+            log::debug!("client rekeyed its session at sequence {sequence}");
+            self.stats.rekeys += 1;
+        }
+    }
+    fn send_keep_alive(&mut self) -> Result<()> {
+        let heartbeat = core::mem::take(&mut self.heartbeat);
+        let mut stamped = Vec::new();
+        let attached = match self.tick_sync {
+            Some(ref mut sync) => {
+                sync.stamp(self.time, &heartbeat, &mut stamped);
+                &stamped
+            }
+            None => &heartbeat,
+        };
+        let result = self.send_packet(KeepAlivePacket::with_heartbeat(0, 0, attached));
+        self.heartbeat = heartbeat;
+        result.map(drop)
+    }
+    fn send_packet(&mut self, packet: Packet) -> Result<SendOutcome> {
+        let server_addr = self.token.server_addresses[self.server_addr_idx];
+        self.send_packet_to(&packet, server_addr)
+    }
+    fn send_packet_to(&mut self, packet: &Packet, server_addr: SocketAddr) -> Result<SendOutcome> {
+        if packet.kind() >= Packet::KEEP_ALIVE {
+            self.rekey();
+        }
+        if !self.open_connections.contains(&server_addr) {
+            self.transceiver
+                .open_connection(server_addr)
+                .map_err(Into::<Error>::into)?;
+            self.open_connections.push(server_addr);
+        }
         let mut buf = [0u8; MAX_PKT_BUF_SIZE];
-        let size = packet.write(
+        let size = packet.write_with(
             &mut buf,
             self.sequence,
-            &self.token.client_to_server_key,
+            &mut self.send_cipher,
             self.token.protocol_id,
         )?;
-        let server_addr = self.token.server_addresses[self.server_addr_idx];
-        self.transceiver
-            .send(&buf[..size], server_addr)
-            .map_err(|e| e.into())?;
+        let outcome = match self
+            .send_backlog
+            .send(&self.transceiver, &buf[..size], server_addr)
+        {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                self.rebind(e)?;
+                self.send_backlog
+                    .send(&self.transceiver, &buf[..size], server_addr)?
+            }
+        };
+        metrics::packet_sent(Side::Client, packet.kind(), size);
+        let payload = match packet {
+            Packet::Payload(PayloadPacket { buf }) => Some(*buf),
+            _ => None,
+        };
+        self.log_packet(PacketDirection::Sent, server_addr, &buf[..size], payload);
         self.last_send_time = self.time;
         self.sequence += 1;
-        Ok(())
+        Ok(outcome)
+    }
+    fn log_packet(
+        &mut self,
+        direction: PacketDirection,
+        addr: SocketAddr,
+        raw: &[u8],
+        payload: Option<&[u8]>,
+    ) {
+        if let Some(logger) = self.cfg.packet_logger.as_mut() {
+            let local_addr = self.transceiver.addr();
+            logger.log(&PacketRecord::new(
+                direction, self.time, local_addr, addr, raw, payload,
+            ));
+        }
     }
     fn process_packet(&mut self, addr: SocketAddr, packet: Packet) -> Result<()> {
+        if let (Some(race_idx), Packet::Challenge(_), ClientState::SendingConnectionRequest) =
+            (self.race_addr_idx, &packet, self.state)
+        {
+            if addr == self.token.server_addresses[race_idx] {
+                log::debug!("client won the connection race with {addr}");
+                self.server_addr_idx = race_idx;
+            }
+        }
         if addr != self.token.server_addresses[self.server_addr_idx] {
             return Ok(());
         }
@@ -386,17 +1147,28 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
                 Packet::Denied(_),
                 ClientState::SendingConnectionRequest | ClientState::SendingChallengeResponse,
             ) => {
+                trace::event!(INFO, server = %addr, "client connection denied by server");
                 self.should_disconnect = true;
                 self.should_disconnect_state = ClientState::ConnectionDenied;
             }
             (Packet::Challenge(pkt), ClientState::SendingConnectionRequest) => {
                 log::debug!("client received connection challenge packet from server");
+                trace::event!(
+                    DEBUG,
+                    server = %addr,
+                    challenge_sequence = pkt.sequence,
+                    "client received connection challenge"
+                );
+                self.connect_span.event("challenge received");
+                self.race_addr_idx = None;
                 self.challenge_token_sequence = pkt.sequence;
                 self.challenge_token_data = pkt.token;
                 self.set_state(ClientState::SendingChallengeResponse);
             }
-            (Packet::KeepAlive(_), ClientState::Connected) => {
+            (Packet::KeepAlive(pkt), ClientState::Connected) => {
                 log::trace!("client received connection keep-alive packet from server");
+                let heartbeat = self.receive_tick_stamp(pkt.heartbeat);
+                self.on_heartbeat(heartbeat);
             }
             (Packet::KeepAlive(pkt), ClientState::SendingChallengeResponse) => {
                 log::debug!("client received connection keep-alive packet from server");
@@ -404,13 +1176,61 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
                 self.max_clients = pkt.max_clients;
                 self.set_state(ClientState::Connected);
                 log::info!("client connected to server");
+                trace::event!(
+                    INFO,
+                    server = %addr,
+                    client_index = pkt.client_index,
+                    max_clients = pkt.max_clients,
+                    "client connected"
+                );
+                metrics::connect_succeeded(Side::Client);
+                self.connect_span.succeed();
+                let heartbeat = self.receive_tick_stamp(pkt.heartbeat);
+                self.on_heartbeat(heartbeat);
             }
             (Packet::Payload(pkt), ClientState::Connected) => {
                 log::debug!("client received payload packet from server");
-                self.packet_queue.push_back(pkt.buf.to_vec());
+                self.stats.payload_received(pkt.buf.len());
+                self.recv_payload_packet(pkt.buf);
+            }
+            (
+                Packet::PayloadLimit(PayloadLimitPacket { max_payload_size }),
+                ClientState::Connected,
+            ) => {
+                let max_payload_size = (max_payload_size as usize).clamp(1, MAX_PACKET_SIZE);
+                if self.server_max_payload_size != Some(max_payload_size) {
+                    log::debug!("client max payload size limited to {max_payload_size} by server");
+                    self.set_server_max_payload_size(Some(max_payload_size));
+                }
+            }
+            (
+                Packet::KeepAliveInterval(KeepAliveIntervalPacket { interval_ms }),
+                ClientState::Connected,
+            ) => {
+                let interval = interval_ms.max(1) as f64 / 1000.0;
+                if self.server_keep_alive_interval != Some(interval) {
+                    log::debug!("client keep-alive interval set to {interval}s by server");
+                    self.server_keep_alive_interval = Some(interval);
+                }
+            }
+            (Packet::PathChallenge(PathChallengePacket { nonce }), ClientState::Connected) => {
+                log::debug!("client received path challenge from server");
+                self.send_packet(PathResponsePacket::create(nonce))?;
+            }
+            (Packet::Transfer(TransferPacket { fragment, data }), ClientState::Connected) => {
+                log::debug!("client received transfer packet from server");
+                self.on_transfer(fragment, data);
             }
             (Packet::Disconnect(_), ClientState::Connected) => {
                 log::debug!("client received disconnect packet from server");
+                trace::event!(INFO, server = %addr, "client disconnected by server");
+                if !self.should_disconnect && !self.cfg.strict_netcode_1_02 {
+                    // confirm the disconnect, for a server that is shutting down (other servers ignore these).
+                    // this is an extension of the protocol, the reference implementation doesn't answer
+                    for _ in 0..self.cfg.num_disconnect_packets {
+                        self.send_packet(DisconnectPacket::create())?;
+                    }
+                }
                 self.should_disconnect = true;
                 self.should_disconnect_state = ClientState::Disconnected;
             }
@@ -419,16 +1239,160 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
         self.last_receive_time = self.time;
         Ok(())
     }
+    fn recv_payload_packet(&mut self, buf: &[u8]) {
+        let buf = if self.cfg.padding_buckets.is_empty() {
+            buf
+        } else {
+            let Some(buf) = padding::unpad(buf) else {
+                log::debug!("client dropped payload with an invalid padding header");
+                return;
+            };
+            buf
+        };
+        let buf = if self.cfg.ack_payloads {
+            let Some(buf) = self.acks.recv(buf, self.time) else {
+                log::debug!("client dropped payload without an ack header");
+                return;
+            };
+            buf
+        } else {
+            buf
+        };
+        #[cfg(feature = "compression")]
+        if self.cfg.compression_threshold.is_some() {
+            match compression::decode(buf) {
+                Some(payload) => self.recv_messages(&payload),
+                None => log::debug!("client dropped payload that failed to decompress"),
+            }
+            return;
+        }
+        self.recv_messages(buf);
+    }
+    fn recv_messages(&mut self, buf: &[u8]) {
+        if self.cfg.coalesce_payloads {
+            for msg in coalesce::messages(buf) {
+                self.recv_payload(msg);
+            }
+        } else {
+            self.recv_payload(buf);
+        }
+    }
+    fn recv_payload(&mut self, buf: &[u8]) {
+        let is_probe = self
+            .link_check
+            .as_mut()
+            .is_some_and(|link_check| link_check.process_echo(buf, self.time));
+        if !is_probe {
+            self.packet_queue.push(buf, ());
+        }
+    }
+    fn flush_send_queue(&mut self) -> Result<()> {
+        if let Err(e) = self.send_backlog.flush(&self.transceiver) {
+            self.rebind(e)?;
+        }
+        if let Some(payload) = self.send_queue.take() {
+            self.send_payload_packet(&payload)?;
+        }
+        Ok(())
+    }
+    fn send_payload_packet(&mut self, buf: &[u8]) -> Result<SendOutcome> {
+        #[cfg(feature = "compression")]
+        if let Some(threshold) = self.cfg.compression_threshold {
+            let payload = compression::encode(buf, threshold);
+            return self.send_acked_payload(&payload);
+        }
+        self.send_acked_payload(buf)
+    }
+    fn send_acked_payload(&mut self, buf: &[u8]) -> Result<SendOutcome> {
+        if !self.cfg.ack_payloads {
+            return self.send_padded_payload(buf);
+        }
+        let payload = [&ack::header(&self.replay_protection)[..], buf].concat();
+        // the payload takes the sequence after a rekey
+        self.rekey();
+        self.acks.sent(self.sequence, self.time);
+        self.send_padded_payload(&payload)
+    }
+    fn send_padded_payload(&mut self, buf: &[u8]) -> Result<SendOutcome> {
+        if self.cfg.padding_buckets.is_empty() {
+            return self.send_packet(PayloadPacket::create(buf));
+        }
+        let payload = padding::pad(buf, &self.cfg.padding_buckets, self.max_payload_size());
+        self.send_packet(PayloadPacket::create(&payload))
+    }
+    fn update_link_check(&mut self) -> Result<()> {
+        if self.state != ClientState::Connected {
+            return Ok(());
+        }
+        let Some(link_check) = self.link_check.as_mut() else {
+            return Ok(());
+        };
+        if link_check.is_done(self.time) {
+            let report = link_check.report();
+            log::debug!("client link check done: {report:?}");
+            self.link_check_report = Some(report);
+            self.link_check = None;
+            return Ok(());
+        }
+        let probes: Vec<_> = core::iter::from_fn(|| link_check.next_probe(self.time)).collect();
+        for probe in probes {
+            if self.cfg.coalesce_payloads {
+                // probes are timed, so they skip the queue and are sent in a payload of their own
+                self.send_payload_packet(&coalesce::single(&probe))?;
+            } else {
+                self.send_payload_packet(&probe)?;
+            }
+        }
+        Ok(())
+    }
+    fn update_token_renew(&mut self) {
+        if self.token_renew_notified || self.token_start_time.is_none() {
+            return;
+        }
+        let remaining = self.token_time_remaining();
+        if !remaining.is_finite() || remaining > self.cfg.token_renew_before {
+            return;
+        }
+        self.token_renew_notified = true;
+        log::debug!("client connect token expires in {remaining:.1}s, requesting renewal");
+        trace::event!(
+            DEBUG,
+            remaining = remaining,
+            "client connect token renewal requested"
+        );
+        if let Some(ref mut cb) = self.cfg.on_token_renew {
+            cb(remaining, &mut self.cfg.context)
+        }
+    }
     fn update_state(&mut self) {
         let is_token_expired = self.time - self.start_time
             >= self.token.expire_timestamp as f64 - self.token.create_timestamp as f64;
-        let is_connection_timed_out = self.token.timeout_seconds.is_positive()
-            && (self.last_receive_time + (self.token.timeout_seconds as f64) < self.time);
+        let timeout_seconds = self
+            .cfg
+            .timeout_seconds
+            .unwrap_or(self.token.timeout_seconds);
+        let is_connection_timed_out = timeout_seconds.is_positive()
+            && (self.last_receive_time + (timeout_seconds as f64) < self.time);
+        let connect_config = self.cfg.connect_config;
+        let is_address_timed_out = match connect_config.address_timeout {
+            Some(timeout) => self.time - self.start_time >= timeout,
+            None => is_connection_timed_out,
+        };
+        let is_connect_timed_out = connect_config
+            .total_timeout
+            .is_some_and(|timeout| self.time - self.connect_start_time >= timeout);
         let new_state = match self.state {
             ClientState::SendingConnectionRequest | ClientState::SendingChallengeResponse
                 if is_token_expired =>
             {
                 log::info!("client connect failed. connect token expired");
+                trace::event!(
+                    INFO,
+                    reason = "connect token expired",
+                    "client connect failed"
+                );
+                metrics::connect_failed(Side::Client, "connect token expired");
+                self.connect_span.fail("connect token expired");
                 ClientState::ConnectTokenExpired
             }
             _ if self.should_disconnect => {
@@ -436,66 +1400,218 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
                     "client should disconnect -> {:?}",
                     self.should_disconnect_state
                 );
+                let reason = match self.should_disconnect_state {
+                    ClientState::ConnectionDenied => Some("connection denied"),
+                    ClientState::ServerUnreachable if self.state.is_pending() => {
+                        Some("server unreachable")
+                    }
+                    _ => None,
+                };
+                if let Some(reason) = reason {
+                    self.connect_span.fail(reason);
+                }
                 if self.connect_to_next_server().is_ok() {
                     return;
                 };
+                if let Some(reason) = reason {
+                    metrics::connect_failed(Side::Client, reason);
+                }
                 self.should_disconnect_state
             }
-            ClientState::SendingConnectionRequest if is_connection_timed_out => {
+            ClientState::SendingConnectionRequest | ClientState::SendingChallengeResponse
+                if is_connect_timed_out =>
+            {
+                log::info!("client connect failed. connect attempt timed out");
+                trace::event!(
+                    INFO,
+                    server_addr_idx = self.server_addr_idx,
+                    reason = "connect attempt timed out",
+                    "client connect failed"
+                );
+                self.connect_span.fail("connect attempt timed out");
+                metrics::connect_failed(Side::Client, "connect attempt timed out");
+                if self.state == ClientState::SendingConnectionRequest {
+                    ClientState::ConnectionRequestTimedOut
+                } else {
+                    ClientState::ChallengeResponseTimedOut
+                }
+            }
+            ClientState::SendingConnectionRequest if is_address_timed_out => {
                 log::info!("client connect failed. connection request timed out");
+                trace::event!(
+                    INFO,
+                    server_addr_idx = self.server_addr_idx,
+                    reason = "connection request timed out",
+                    "client connect failed"
+                );
+                self.connect_span.fail("connection request timed out");
                 if self.connect_to_next_server().is_ok() {
                     return;
                 };
+                metrics::connect_failed(Side::Client, "connection request timed out");
                 ClientState::ConnectionRequestTimedOut
             }
-            ClientState::SendingChallengeResponse if is_connection_timed_out => {
+            ClientState::SendingChallengeResponse if is_address_timed_out => {
                 log::info!("client connect failed. connection response timed out");
+                trace::event!(
+                    INFO,
+                    server_addr_idx = self.server_addr_idx,
+                    reason = "connection response timed out",
+                    "client connect failed"
+                );
+                self.connect_span.fail("connection response timed out");
                 if self.connect_to_next_server().is_ok() {
                     return;
                 };
+                metrics::connect_failed(Side::Client, "connection response timed out");
                 ClientState::ChallengeResponseTimedOut
             }
             ClientState::Connected if is_connection_timed_out => {
                 log::info!("client connection timed out");
+                trace::event!(INFO, "client connection timed out");
                 ClientState::ConnectionTimedOut
             }
             _ => return,
         };
         self.reset(new_state);
     }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "client_recv_packet", level = "trace", skip_all, fields(from = %addr, len = buf.len()))
+    )]
     fn recv_packet(&mut self, buf: &mut [u8], now: u64, addr: SocketAddr) -> Result<()> {
         if buf.len() <= 1 {
             // Too small to be a packet
             return Ok(());
         }
-        let packet = match Packet::read(
+        let len = buf.len();
+        // the packet is decrypted in place, keep a copy of the raw bytes for the logger
+        let raw = self.cfg.packet_logger.is_some().then(|| buf.to_vec());
+        let previous_sequence = self.replay_protection.most_recent_sequence();
+        let result = Packet::read_with(
             buf,
             self.token.protocol_id,
             now,
-            self.token.server_to_client_key,
+            &mut self.receive_cipher,
             Some(&mut self.replay_protection),
-            Self::ALLOWED_PACKETS,
-        ) {
+            self.cfg.allowed_packets.for_state(self.state).bits(),
+            self.cfg.strict_netcode_1_02,
+        );
+        if let Some(raw) = raw {
+            let payload = match &result {
+                Ok(Packet::Payload(PayloadPacket { buf })) => Some(*buf),
+                _ => None,
+            };
+            self.log_packet(PacketDirection::Received, addr, &raw, payload);
+        }
+        let packet = match result {
             Ok(packet) => packet,
+            Err(Error::Packet(crate::packet::Error::NotAllowed(kind))) => {
+                log::debug!(
+                    "client ignored packet of type {kind}, not allowed while {:?}",
+                    self.state
+                );
+                trace::event!(DEBUG, from = %addr, kind = kind, state = ?self.state, "client packet out of phase");
+                metrics::packet_dropped(Side::Client, "out of phase");
+                self.stats.out_of_phase.increment(kind);
+                return Ok(());
+            }
             Err(Error::Crypto(_)) => {
                 log::debug!("client ignored packet because it failed to decrypt");
+                trace::event!(DEBUG, from = %addr, "client packet decryption failed");
+                metrics::packet_dropped(Side::Client, "decryption failed");
+                return Ok(());
+            }
+            Err(Error::Packet(crate::packet::Error::AlreadyReceived(_sequence))) => {
+                log::debug!("client ignored packet because it was already received");
+                trace::event!(DEBUG, from = %addr, sequence = _sequence, "client packet replay rejected");
+                metrics::packet_dropped(Side::Client, "already received");
                 return Ok(());
             }
             Err(e) => {
                 log::error!("client ignored packet: {e}");
+                trace::event!(DEBUG, from = %addr, error = %e, "client ignored invalid packet");
+                metrics::packet_dropped(Side::Client, "invalid");
                 return Ok(());
             }
         };
-        self.process_packet(addr, packet)
+        metrics::packet_received(Side::Client, packet.kind(), len);
+        let most_recent_sequence = self.replay_protection.most_recent_sequence();
+        let gap = is_sequence_gap(previous_sequence, most_recent_sequence);
+        if self.cfg.rekey_sessions && rekey::is_new_epoch(previous_sequence, most_recent_sequence) {
+            log::debug!("server rekeyed the session");
+            self.stats.rekeys += 1;
+        }
+        self.process_packet(addr, packet)?;
+        if gap {
+            self.stats.sequence_gaps += 1;
+            if self.cfg.ack_on_sequence_gap && self.state == ClientState::Connected {
+                log::trace!("client sending immediate keep-alive after a sequence gap");
+                self.send_keep_alive()?;
+            }
+        }
+        Ok(())
     }
     fn recv_packets(&mut self) -> Result<()> {
-        let mut buf = [0u8; MAX_PACKET_SIZE];
-        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-        while let Some((size, addr)) = self.transceiver.recv(&mut buf).map_err(|e| e.into())? {
+        let mut buf = [0u8; MAX_PKT_BUF_SIZE];
+        // the wall clock is only used to validate connection requests, which a client never accepts
+        let now = 0;
+        loop {
+            let (size, addr) = match self.transceiver.recv(&mut buf).map_err(Into::<Error>::into) {
+                Ok(Some(received)) => received,
+                Ok(None) => return Ok(()),
+                Err(e) if e.is_port_unreachable() => {
+                    self.server_unreachable(e);
+                    continue;
+                }
+                Err(e) => {
+                    self.rebind(e)?;
+                    continue;
+                }
+            };
             self.recv_packet(&mut buf[..size], now, addr)?;
         }
+    }
+    // Replaces a failed socket, see `ClientConfig::rebind_on_error`. Returns the error if the client can't rebind.
+    fn rebind(&mut self, e: Error) -> Result<()> {
+        if !self.cfg.rebind_on_error || !e.is_socket_failure() || self.last_rebind_time == self.time
+        {
+            return Err(e);
+        }
+        let old_addr = self.transceiver.addr();
+        if !self.transceiver.rebind().map_err(Into::<Error>::into)? {
+            return Err(e);
+        }
+        self.last_rebind_time = self.time;
+        let new_addr = self.transceiver.addr();
+        log::info!("client rebound from {old_addr} to {new_addr} after socket error: {e}");
+        trace::event!(INFO, old_addr = %old_addr, new_addr = %new_addr, "client rebound its socket");
+        self.stats.rebinds += 1;
+        // let the server see the new address right away
+        self.last_send_time = f64::NEG_INFINITY;
+        if let Some(ref mut cb) = self.cfg.on_rebind {
+            cb(old_addr, new_addr, &mut self.cfg.context);
+        }
         Ok(())
     }
+    // An ICMP port unreachable for an earlier send, instead of waiting for the server to time out.
+    fn server_unreachable(&mut self, e: Error) {
+        // the error doesn't say which address it is for, it may be the one raced against the current one
+        let racing =
+            self.race_addr_idx.is_some() && self.state == ClientState::SendingConnectionRequest;
+        if !(self.state.is_pending() || self.state.is_connected()) || racing {
+            log::debug!(
+                "client ignored port unreachable in state {:?}: {e}",
+                self.state
+            );
+            return;
+        }
+        let server_addr = self.token.server_addresses[self.server_addr_idx];
+        log::info!("client found server {server_addr} unreachable: {e}");
+        trace::event!(INFO, server = %server_addr, "client found server unreachable");
+        self.should_disconnect = true;
+        self.should_disconnect_state = ClientState::ServerUnreachable;
+    }
     /// Creates a new client instance with the given configuration and transceiver.
     ///
     /// This is useful if you want to use a custom transceiver implementation,
@@ -541,6 +1657,17 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
     /// This function does not perform any IO, it only readies the client to send/receive packets on the next call to [`update`](Client::update). <br>
     pub fn connect(&mut self) {
         self.reset_connection();
+        // moving on to the next address continues the same connect attempt
+        if self.server_addr_idx == 0 {
+            self.connect_start_time = self.time;
+        }
+        self.connect_span = ConnectSpan::start(
+            self.token.server_addresses[self.server_addr_idx],
+            self.server_addr_idx + 1,
+            self.token.server_addresses.len(),
+        );
+        self.token_start_time.get_or_insert(self.time);
+        self.race_addr_idx = self.race_addr_idx();
         self.set_state(ClientState::SendingConnectionRequest);
         log::info!(
             "client connecting to server {} [{}/{}]",
@@ -548,12 +1675,48 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
             self.server_addr_idx + 1,
             self.token.server_addresses.len()
         );
+        trace::event!(
+            INFO,
+            server = %self.token.server_addresses[self.server_addr_idx],
+            attempt = self.server_addr_idx + 1,
+            num_servers = self.token.server_addresses.len(),
+            "client connection attempt"
+        );
+    }
+    /// Connects with a fresh connect token, reusing the client's transceiver (and so its local port).
+    ///
+    /// Unlike creating a new client, this preserves NAT mappings of the local port, and the transceiver doesn't have to be created again.
+    /// If the client is connected it first disconnects from its current server, then it resets its state
+    /// and begins connecting like [`connect`](Client::connect) does, to the servers of the new token.
+    ///
+    /// Returns an error (and leaves the client as it was) if the token is invalid.
+    pub fn reconnect(&mut self, token_bytes: &[u8]) -> Result<()> {
+        let token = Self::read_token(token_bytes, self.cfg.strict_netcode_1_02)?;
+        if self.is_connected() {
+            self.disconnect()?;
+        } else if !self.is_disconnected() {
+            self.reset(ClientState::Disconnected);
+        }
+        self.send_cipher =
+            Cipher::with_rekeying(*token.client_to_server_key, self.cfg.rekey_sessions);
+        self.receive_cipher =
+            Cipher::with_rekeying(*token.server_to_client_key, self.cfg.rekey_sessions);
+        self.token = token;
+        self.token_start_time = None;
+        self.token_renew_notified = false;
+        self.transfer_token = None;
+        self.server_addr_idx = 0;
+        self.sequence = 0;
+        self.packet_queue.clear();
+        self.connect();
+        Ok(())
     }
     /// Updates the client.
     ///
     /// * Updates the client's elapsed time.
     /// * Receives packets from the server, any received payload packets will be queued.
-    /// * Sends keep-alive or request/response packets to the server to establish/maintain a connection.
+    /// * Sends keep-alive or request/response packets to the server to establish/maintain a connection,
+    ///   unless disabled with [`ClientConfig::send_on_update`](ClientConfig::send_on_update).
     /// * Updates the client's state - checks for timeouts, errors and transitions to new states.
     ///
     /// This method should be called regularly, probably at a fixed rate (e.g., 60Hz).
@@ -568,11 +1731,28 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
     /// The fallible version of [`update`](Client::update).
     ///
     /// Returns an error if the client can't send or receive packets.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "client_update", level = "trace", skip_all, fields(time = time, state = ?self.state))
+    )]
     pub fn try_update(&mut self, time: f64) -> Result<()> {
+        let _timer = metrics::UpdateTimer::start(Side::Client);
         self.time = time;
         self.recv_packets()?;
-        self.send_packets()?;
+        if let Some(ref mut sync) = self.tick_sync {
+            sync.update(time);
+        }
+        if self.state == ClientState::Connected {
+            self.congestion
+                .update(self.time, self.acks.rtt(), self.acks.packet_loss());
+        }
+        self.update_link_check()?;
+        if self.cfg.send_on_update {
+            self.flush_send_queue()?;
+            self.send_packets()?;
+        }
         self.update_state();
+        self.update_token_renew();
         Ok(())
     }
     /// Receives a packet from the server, if one is available in the queue.
@@ -603,25 +1783,352 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
     /// }
     /// ```
     pub fn recv(&mut self) -> Option<Vec<u8>> {
-        self.packet_queue.pop_front()
+        self.packet_queue.pop().map(|(packet, ())| packet)
+    }
+    /// Receives a packet from the server into `buf`, if one is available in the queue.
+    ///
+    /// Returns the length of the packet. Unlike [`recv`](Client::recv), this doesn't hand out a new `Vec<u8>` per packet:
+    /// the queue's buffers are reused for later packets. <br>
+    /// `buf` should be at least as large as the [max payload size](Client::max_payload_size), a longer packet is truncated.
+    ///
+    /// If no packet is available, `None` will be returned.
+    ///
+    /// # Example
+    /// ```
+    /// # use netcode::Client;
+    /// # let mut server = netcode::Server::new("127.0.0.1:0", 0, [0; 32]).unwrap();
+    /// # let token_bytes = server.token(0).generate().unwrap().try_into_bytes().unwrap();
+    /// let mut client = Client::new(&token_bytes).unwrap();
+    /// client.connect();
+    ///
+    /// let mut buf = [0u8; netcode::MAX_PACKET_SIZE];
+    /// client.update(0.0);
+    /// while let Some(len) = client.recv_into(&mut buf) {
+    ///     let packet = &buf[..len];
+    ///     // ...
+    /// }
+    /// ```
+    pub fn recv_into(&mut self, buf: &mut [u8]) -> Option<usize> {
+        self.packet_queue.pop_into(buf).map(|((), len)| len)
+    }
+    /// Sets a small application heartbeat (e.g. a game tick or a load figure) that is attached to every keep-alive packet
+    /// sent to the server from now on, and delivered to [`ServerConfig::on_heartbeat`](crate::ServerConfig::on_heartbeat). <br>
+    /// Keep-alives are sent anyway, so this costs no extra packets. An empty payload stops attaching one.
+    ///
+    /// Returns an error if the payload is larger than [`MAX_HEARTBEAT_BYTES`](crate::MAX_HEARTBEAT_BYTES)
+    /// (minus 8 bytes with [tick sync](ClientConfig::tick_sync)).
+    /// Heartbeats aren't attached with [`strict_netcode_1_02`](ClientConfig::strict_netcode_1_02), as the reference implementation
+    /// rejects keep-alives with trailing bytes.
+    pub fn set_heartbeat_payload(&mut self, payload: &[u8]) -> Result<()> {
+        let max_heartbeat_bytes = if self.cfg.tick_rate.is_some() && !self.cfg.strict_netcode_1_02 {
+            MAX_HEARTBEAT_BYTES - CLIENT_STAMP_BYTES
+        } else {
+            MAX_HEARTBEAT_BYTES
+        };
+        if payload.len() > max_heartbeat_bytes {
+            return Err(Error::SizeMismatch(max_heartbeat_bytes, payload.len()));
+        }
+        self.heartbeat.clear();
+        if !self.cfg.strict_netcode_1_02 {
+            self.heartbeat.extend_from_slice(payload);
+        }
+        Ok(())
     }
     /// Sends a packet to the server.
     ///
-    /// The provided buffer must be smaller than [`MAX_PACKET_SIZE`](crate::MAX_PACKET_SIZE).
-    pub fn send(&mut self, buf: &[u8]) -> Result<()> {
+    /// The provided buffer must not be larger than the [max payload size](Client::max_payload_size),
+    /// [`MAX_PACKET_SIZE`](crate::MAX_PACKET_SIZE) by default, minus 2 bytes if [payloads are coalesced](ClientConfig::coalesce_payloads),
+    /// 1 byte if they are compressed, 12 bytes if they are [acked](ClientConfig::ack_payloads) and 2 bytes if they are [padded](ClientConfig::pad_payloads).
+    ///
+    /// If payloads are coalesced, the packet is queued until the next [`update`](Client::update) or [`flush`](Client::flush).
+    ///
+    /// Returns how the packet was sent, see [`SendOutcome`], e.g. [`SendOutcome::NotConnected`] if the client isn't connected.
+    pub fn send(&mut self, buf: &[u8]) -> Result<SendOutcome> {
         if self.state != ClientState::Connected {
-            return Ok(());
+            return Ok(SendOutcome::NotConnected);
         }
-        if buf.len() > MAX_PACKET_SIZE {
-            return Err(Error::SizeMismatch(MAX_PACKET_SIZE, buf.len()));
+        let max_message_size = self.max_message_size();
+        if buf.len() > max_message_size {
+            return Err(Error::SizeMismatch(max_message_size, buf.len()));
+        }
+        if self.cfg.coalesce_payloads {
+            let max_size = self.max_uncompressed_size();
+            return match self.send_queue.push(buf, max_size) {
+                Some(payload) => self.send_payload_packet(&payload),
+                None => Ok(SendOutcome::Coalesced),
+            };
+        }
+        if buf.is_empty() && self.cfg.strict_netcode_1_02 {
+            return Err(crate::packet::Error::TooSmall.into());
+        }
+        self.send_payload_packet(buf)
+    }
+    /// Sends a packet to the server right away, at `time`. <br>
+    /// Use it to send from a frame callback, right after sampling input, instead of waiting for the next [`update`](Client::update).
+    /// `time` is on the same clock as the time passed to `update` and should be the precise time of the callback,
+    /// it advances the client's time so the payload counts as the periodic packet and no redundant keep-alive is sent.
+    ///
+    /// # Example
+    /// ```
+    /// # use netcode::{Client, ClientConfig};
+    /// # let mut server = netcode::Server::new("127.0.0.1:0", 0, [0; 32]).unwrap();
+    /// # let token_bytes = server.token(0).generate().unwrap().try_into_bytes().unwrap();
+    /// # let sample_input = || vec![0u8; 8];
+    /// let cfg = ClientConfig::default().send_on_update(false);
+    /// let mut client = Client::with_config(&token_bytes, cfg).unwrap();
+    /// client.connect();
+    ///
+    /// let start = std::time::Instant::now();
+    /// // in the frame callback:
+    /// let time = start.elapsed().as_secs_f64();
+    /// client.update(time);
+    /// if client.is_connected() {
+    ///     client.send_at(&sample_input(), start.elapsed().as_secs_f64()).unwrap();
+    /// }
+    /// client.flush(start.elapsed().as_secs_f64()).unwrap();
+    /// ```
+    pub fn send_at(&mut self, buf: &[u8], time: f64) -> Result<SendOutcome> {
+        self.advance_time(time);
+        self.send(buf)
+    }
+    /// Sends the queued payloads (see [`ClientConfig::coalesce_payloads`](ClientConfig::coalesce_payloads)),
+    /// and the periodic packet (connection request/response or keep-alive) if one is due at `time`, without receiving.
+    ///
+    /// Call it at the end of every frame when [`ClientConfig::send_on_update`](ClientConfig::send_on_update) is disabled,
+    /// otherwise the client won't connect and the server will time it out while no payloads are sent.
+    /// Call it after sending to transmit coalesced payloads right away instead of from the next [`update`](Client::update).
+    pub fn flush(&mut self, time: f64) -> Result<()> {
+        self.advance_time(time);
+        self.flush_send_queue()?;
+        self.send_packets()
+    }
+    /// Gets the time at which the next periodic packet is due, on the clock passed to [`update`](Client::update). <br>
+    /// Sending a payload pushes it back by [`ClientConfig::packet_send_rate`](ClientConfig::packet_send_rate)
+    /// (or the [resend interval](ConnectConfig::resend_interval) while connecting).
+    pub fn next_send_time(&self) -> f64 {
+        self.last_send_time + self.send_interval()
+    }
+    fn advance_time(&mut self, time: f64) {
+        // never move backwards, e.g. if the frame callback's timestamp is slightly behind the last update
+        self.time = self.time.max(time);
+    }
+    /// Starts a link check, measuring round-trip time, loss and effective throughput to the server
+    /// before relying on the connection (e.g. before a match starts).
+    ///
+    /// The server must be running in diagnostics mode (see [`EchoMode`](crate::EchoMode)), otherwise no probes will be echoed
+    /// and the check will report 100% loss. Probes are sent from [`update`](Client::update) while the client is connected,
+    /// and echoed probes are not returned from [`recv`](Client::recv).
+    ///
+    /// Once the check is done, the result is available from [`link_check_report`](Client::link_check_report).
+    /// Starting a new link check discards the previous report.
+    pub fn start_link_check(&mut self, cfg: LinkCheckConfig) {
+        self.link_check = Some(LinkCheck::new(cfg, self.time, self.max_message_size()));
+        self.link_check_report = None;
+    }
+    /// Returns true if a link check is in progress.
+    pub fn is_link_check_running(&self) -> bool {
+        self.link_check.is_some()
+    }
+    /// Gets the report of the last completed link check, if any.
+    pub fn link_check_report(&self) -> Option<LinkCheckReport> {
+        self.link_check_report
+    }
+    /// Gets the time in seconds left before the connect token expires, or `f64::INFINITY` if it never expires.
+    ///
+    /// The lifetime of the token is counted from the first call to [`connect`](Client::connect) using the time passed to
+    /// [`update`](Client::update), so it is not affected by differences between the client's and the token issuer's clocks. <br>
+    /// Once the token has expired the client can no longer (re)connect with it, but an established connection is not affected.
+    ///
+    /// See [`ClientConfig::on_token_renew`](ClientConfig::on_token_renew) to be notified before the token expires.
+    pub fn token_time_remaining(&self) -> f64 {
+        if self.token.expire_timestamp == u64::MAX {
+            return f64::INFINITY;
+        }
+        let lifetime = self
+            .token
+            .expire_timestamp
+            .saturating_sub(self.token.create_timestamp) as f64;
+        let elapsed = self.token_start_time.map_or(0.0, |start| self.time - start);
+        (lifetime - elapsed).max(0.0)
+    }
+    /// Takes the connect token for another server that the server handed the client over to, if it did,
+    /// see [`Server::transfer_client`](crate::Server::transfer_client).
+    ///
+    /// The client stays connected to its current server until the token is passed to [`reconnect`](Client::reconnect),
+    /// which disconnects it and connects to the other server on the same local port.
+    /// Transfer tokens are short-lived, so move soon after it arrives. See also [`ClientConfig::on_transfer`](ClientConfig::on_transfer).
+    ///
+    /// # Example
+    /// ```
+    /// # let mut server = netcode::Server::new("127.0.0.1:0", 0, [0; 32]).unwrap();
+    /// # let token_bytes = server.token(0).generate().unwrap().try_into_bytes().unwrap();
+    /// # let mut client = netcode::Client::new(&token_bytes).unwrap();
+    /// # client.connect();
+    /// client.update(0.0);
+    /// if let Some(token) = client.take_transfer_token() {
+    ///     client.reconnect(&token).unwrap();
+    /// }
+    /// ```
+    pub fn take_transfer_token(&mut self) -> Option<Vec<u8>> {
+        self.transfer_token.take()
+    }
+    /// Gets the client's estimate of the server's [tick](crate::Server::tick) as of the last update, with the fraction of the tick
+    /// that passed, if [tick sync](ClientConfig::tick_sync) is enabled. `None` until the first stamp came back from the server,
+    /// which takes about a round trip after connecting.
+    ///
+    /// The estimate is the server's last stamped tick plus half the round trip, and it advances at the tick rate in between.
+    /// When the clocks drift apart it is sped up or slowed down slightly, so it doesn't jump (or go back) from one update to the next.
+    ///
+    /// # Example
+    /// ```
+    /// # let mut server = netcode::Server::new("127.0.0.1:0", 0, [0; 32]).unwrap();
+    /// # let token_bytes = server.token(0).generate().unwrap().try_into_bytes().unwrap();
+    /// let cfg = netcode::ClientConfig::default().tick_sync(60.0);
+    /// let mut client = netcode::Client::with_config(&token_bytes, cfg).unwrap();
+    /// client.connect();
+    /// client.update(0.0);
+    /// if let Some(tick) = client.estimated_server_tick() {
+    ///     println!("server tick {tick:.1}");
+    /// }
+    /// ```
+    pub fn estimated_server_tick(&self) -> Option<f64> {
+        self.tick_sync.as_ref()?.estimated_tick(self.time)
+    }
+    /// Gets the interval (in seconds) at which the client sends keep-alives while connected and not sending payloads.
+    ///
+    /// This is the [overridden interval](ClientConfig::keep_alive_interval) if one is configured, else the one the server recommended
+    /// for this connection with [`ServerConfig::keep_alive_interval`](crate::ServerConfig::keep_alive_interval),
+    /// else the [`packet_send_rate`](ClientConfig::packet_send_rate). The server's interval is cleared when the client disconnects.
+    pub fn keep_alive_interval(&self) -> f64 {
+        self.cfg
+            .keep_alive_interval
+            .or(self.server_keep_alive_interval)
+            .unwrap_or(self.cfg.packet_send_rate)
+    }
+    /// Gets the largest payload the client can currently [`send`](Client::send).
+    ///
+    /// This is the configured [max payload size](ClientConfig::max_payload_size), unless the server lowered it for
+    /// this connection (e.g. for a path known to fragment), see [`Server::set_client_max_payload_size`](crate::Server::set_client_max_payload_size).
+    /// The server's limit is cleared when the client disconnects.
+    pub fn max_payload_size(&self) -> usize {
+        self.server_max_payload_size
+            .map_or(self.cfg.max_payload_size, |limit| {
+                limit.min(self.cfg.max_payload_size)
+            })
+    }
+    // The largest payload before compression, acks and padding (if enabled) add their headers.
+    fn max_uncompressed_size(&self) -> usize {
+        let mut max_size = self.max_payload_size();
+        if self.cfg.ack_payloads {
+            max_size = max_size.saturating_sub(ack::HEADER_SIZE);
+        }
+        if !self.cfg.padding_buckets.is_empty() {
+            max_size = max_size.saturating_sub(padding::HEADER_SIZE);
+        }
+        #[cfg(feature = "compression")]
+        if self.cfg.compression_threshold.is_some() {
+            max_size = max_size.saturating_sub(compression::HEADER_SIZE);
+        }
+        max_size
+    }
+    // The largest payload that can be sent, less the message header if payloads are coalesced.
+    fn max_message_size(&self) -> usize {
+        if self.cfg.coalesce_payloads {
+            self.max_uncompressed_size()
+                .saturating_sub(MESSAGE_HEADER_SIZE)
+        } else {
+            self.max_uncompressed_size()
+        }
+    }
+    /// Takes the sequences of the payload packets the server acknowledged since the last call, oldest first. <br>
+    /// Only payloads sent while [`ClientConfig::ack_payloads`](ClientConfig::ack_payloads) is enabled are acked,
+    /// get the sequence of a payload with [`last_payload_sequence`](Client::last_payload_sequence) after sending it.
+    /// Payloads that aren't acked shortly after newer ones are, were lost (or are still in flight out of order).
+    ///
+    /// # Example
+    /// ```
+    /// # use netcode::{Client, ClientConfig};
+    /// # let mut server = netcode::Server::new("127.0.0.1:0", 0, [0; 32]).unwrap();
+    /// # let token_bytes = server.token(0).generate().unwrap().try_into_bytes().unwrap();
+    /// # let mut in_flight = std::collections::HashMap::new();
+    /// # let input = vec![0u8; 8];
+    /// let cfg = ClientConfig::default().ack_payloads(true);
+    /// let mut client = Client::with_config(&token_bytes, cfg).unwrap();
+    /// // ...
+    /// client.send(&input).unwrap();
+    /// if let Some(sequence) = client.last_payload_sequence() {
+    ///     in_flight.insert(sequence, input);
+    /// }
+    /// for sequence in client.acks() {
+    ///     in_flight.remove(&sequence);
+    /// }
+    /// ```
+    pub fn acks(&mut self) -> impl Iterator<Item = u64> + '_ {
+        self.acks.drain()
+    }
+    /// Gets the sequence of the last payload packet sent while [`ClientConfig::ack_payloads`](ClientConfig::ack_payloads) is enabled,
+    /// to match it with the sequences from [`acks`](Client::acks). <br>
+    /// If [payloads are coalesced](ClientConfig::coalesce_payloads), payloads get their sequence when the queue is sent.
+    pub fn last_payload_sequence(&self) -> Option<u64> {
+        self.acks.last_sent()
+    }
+    /// Gets the smoothed round trip time (in seconds) of the payloads acked by the server,
+    /// `None` until one is acked or if [`ClientConfig::ack_payloads`](ClientConfig::ack_payloads) is disabled.
+    pub fn rtt(&self) -> Option<f64> {
+        self.acks.rtt()
+    }
+    /// Gets the smoothed fraction (`0.0..=1.0`) of payloads that the server never acked,
+    /// always `0.0` if [`ClientConfig::ack_payloads`](ClientConfig::ack_payloads) is disabled.
+    pub fn packet_loss(&self) -> f64 {
+        self.acks.packet_loss()
+    }
+    /// Gets the quality of the connection as measured by congestion avoidance. <br>
+    /// The connection drops to [`Bad`](ConnectionQuality::Bad) as soon as the [RTT](Client::rtt) goes above 250ms
+    /// or the [packet loss](Client::packet_loss) above 10%, and goes back to [`Good`](ConnectionQuality::Good) once
+    /// conditions have been good for a penalty time (4 seconds at first, doubled each time the connection drops back soon
+    /// after recovering, up to a minute).
+    ///
+    /// RTT and packet loss are measured with payload acks, so the quality stays good unless
+    /// [`ClientConfig::ack_payloads`](ClientConfig::ack_payloads) is enabled (on the server as well).
+    pub fn connection_quality(&self) -> ConnectionQuality {
+        self.congestion.quality()
+    }
+    /// Gets the recommended number of packets per second to send for the current [connection quality](Client::connection_quality),
+    /// see [`ClientConfig::congestion_send_rates`](ClientConfig::congestion_send_rates).
+    ///
+    /// # Example
+    /// ```
+    /// # use netcode::{Client, ClientConfig};
+    /// # let mut server = netcode::Server::new("127.0.0.1:0", 0, [0; 32]).unwrap();
+    /// # let token_bytes = server.token(0).generate().unwrap().try_into_bytes().unwrap();
+    /// let cfg = ClientConfig::default().ack_payloads(true).congestion_send_rates(60.0, 20.0);
+    /// let mut client = Client::with_config(&token_bytes, cfg).unwrap();
+    ///
+    /// // send the player's input every tick while the connection is good, every third tick while it is bad
+    /// let send_interval = 1.0 / client.send_budget();
+    /// assert_eq!(send_interval, 1.0 / 60.0);
+    /// ```
+    pub fn send_budget(&self) -> f64 {
+        match self.congestion.quality() {
+            ConnectionQuality::Good => self.cfg.good_send_rate,
+            ConnectionQuality::Bad => self.cfg.bad_send_rate,
+        }
+    }
+    /// Gets the statistics collected by the client since it was created.
+    pub fn stats(&self) -> ClientStats {
+        ClientStats {
+            packets_would_block: self.send_backlog.queued,
+            would_block_drops: self.send_backlog.dropped,
+            ..self.stats
         }
-        self.send_packet(PayloadPacket::create(buf))?;
-        Ok(())
     }
     /// Disconnects the client from the server.
     ///
     /// The client will send a number of redundant disconnect packets to the server before transitioning to `Disconnected`.
     pub fn disconnect(&mut self) -> Result<()> {
+        if self.state == ClientState::Connected {
+            self.flush_send_queue()?;
+        }
         log::debug!(
             "client sending {} disconnect packets to server",
             self.cfg.num_disconnect_packets
@@ -636,30 +2143,38 @@ impl<T: Transceiver, Ctx> Client<T, Ctx> {
     pub fn addr(&self) -> SocketAddr {
         self.transceiver.addr()
     }
+    pub(crate) fn transceiver(&self) -> &T {
+        &self.transceiver
+    }
+    // Whether `addr` is the server the client is connecting or connected to, or the address raced against it.
+    pub(crate) fn is_server_addr(&self, addr: SocketAddr) -> bool {
+        let addresses = &self.token.server_addresses;
+        addresses[self.server_addr_idx] == addr
+            || self.race_addr_idx.is_some_and(|idx| addresses[idx] == addr)
+    }
     /// Gets the current state of the client.
     pub fn state(&self) -> ClientState {
         self.state
     }
     /// Returns true if the client is in an error state.
     pub fn is_error(&self) -> bool {
-        self.state < ClientState::Disconnected
+        self.state.is_error()
     }
     /// Returns true if the client is in a pending state.
     pub fn is_pending(&self) -> bool {
-        self.state == ClientState::SendingConnectionRequest
-            || self.state == ClientState::SendingChallengeResponse
+        self.state.is_pending()
     }
     /// Returns true if the client is connected to a server.
     pub fn is_connected(&self) -> bool {
-        self.state == ClientState::Connected
+        self.state.is_connected()
     }
     /// Returns true if the client is disconnected from the server.
     pub fn is_disconnected(&self) -> bool {
-        self.state == ClientState::Disconnected
+        self.state.is_disconnected()
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use byteorder::{LittleEndian, WriteBytesExt};
     use chacha20poly1305::XNonce;
@@ -678,6 +2193,10 @@ mod tests {
                 sim,
             )
         }
+        // Replaces the client's network endpoint, as if its address changed.
+        pub(crate) fn replace_simulator(&mut self, sim: NetworkSimulator) {
+            self.transceiver = sim;
+        }
     }
 
     #[test]
@@ -0,0 +1,92 @@
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// A source of wall-clock time, in whole seconds since the unix epoch.
+///
+/// The client and server are driven by the (monotonic) time passed to their `update` methods,
+/// the wall clock is only used by the server to check the expiry of [`ConnectTokens`](crate::ConnectToken),
+/// to date the tokens of [`Server::token`](crate::Server::token), and to measure time in the blocking calls that update the server themselves.
+/// Set with [`ServerConfig::clock`](crate::ServerConfig::clock), the default is [`SystemClock`](SystemClock).
+///
+/// Implemented for closures, and by [`ManualClock`](ManualClock) for tests and deterministic replays.
+pub trait Clock {
+    /// Returns the current time in seconds since the unix epoch.
+    fn unix_time(&self) -> u64;
+    /// Returns the current time in seconds on a clock that never goes backwards, with sub-second precision if the clock has it. <br>
+    /// The server measures the time passing in [`Server::run_pacer`](crate::Server::run_pacer) and [`Server::shutdown`](crate::Server::shutdown)
+    /// with it. The default is [`unix_time`](Clock::unix_time).
+    fn monotonic_time(&self) -> f64 {
+        self.unix_time() as f64
+    }
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> u64,
+{
+    fn unix_time(&self) -> u64 {
+        self()
+    }
+}
+
+/// The system clock, the default [`Clock`](Clock).
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn unix_time(&self) -> u64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+    fn monotonic_time(&self) -> f64 {
+        static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        START
+            .get_or_init(std::time::Instant::now)
+            .elapsed()
+            .as_secs_f64()
+    }
+}
+
+/// A [`Clock`](Clock) that only moves when told to.
+///
+/// Clones share the same time, so one can be given to a server while the test (or replay) keeps another to advance it.
+///
+/// # Example
+/// ```
+/// use netcode::{Clock, ManualClock};
+///
+/// let clock = ManualClock::new(1_700_000_000);
+/// let server_clock = clock.clone();
+/// clock.advance(30);
+/// assert_eq!(server_clock.unix_time(), 1_700_000_030);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    /// Creates a clock stopped at `unix_time` (in seconds since the unix epoch).
+    pub fn new(unix_time: u64) -> Self {
+        Self(Arc::new(AtomicU64::new(unix_time)))
+    }
+    /// Sets the time of the clock (and all of its clones).
+    pub fn set(&self, unix_time: u64) {
+        self.0.store(unix_time, Ordering::Relaxed);
+    }
+    /// Moves the clock (and all of its clones) forward by `seconds`.
+    pub fn advance(&self, seconds: u64) {
+        self.0.fetch_add(seconds, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn unix_time(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "std")]
+pub(crate) type BoxedClock = alloc::boxed::Box<dyn Clock + Send + Sync + 'static>;
@@ -0,0 +1,237 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use zeroize::Zeroizing;
+
+use crate::{
+    crypto::Key, server::ClientId, token::ConnectTokenBuilder, transceiver::Transceiver,
+    ConnectToken, Result,
+};
+
+/// A group of server shards sharing one address with `SO_REUSEPORT`, e.g. one shard per core.
+///
+/// Every shard is a [`Server`](crate::Server) created with [`Server::with_cluster`](crate::Server::with_cluster),
+/// bound to the cluster's address with its own socket. The OS spreads the incoming packets between the sockets
+/// with a hash of the client's address, so all the packets of a client reach the same shard,
+/// as long as the number of shards doesn't change. <br>
+/// The shards share the private key and protocol id, so a token from [`ServerCluster::token`](ServerCluster::token)
+/// is accepted by whichever shard the client ends up on, and the cluster tracks the clients connected to each shard,
+/// so a cluster-wide limit (see [`ServerCluster::set_max_clients`](ServerCluster::set_max_clients)) decides when the server is full.
+///
+/// The cluster is a cheap handle that can be cloned into each shard's thread. Client counts are updated when clients
+/// connect or disconnect and on every update, so shards accepting clients at the same time may briefly exceed the limit
+/// by a few clients. A shard's clients stop counting when the shard is dropped. <br>
+/// `SO_REUSEPORT` is only supported on unix. For shards on separate ports instead, see [`ShardMap`](crate::ShardMap).
+///
+/// # Example
+/// ```
+/// use std::net::SocketAddr;
+/// use netcode::{Server, ServerCluster, ServerConfig};
+///
+/// let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+/// let cluster = ServerCluster::new(addr, 0x11223344, netcode::generate_key(), 4);
+/// cluster.set_max_clients(512);
+///
+/// let shards: Vec<_> = (0..cluster.num_shards())
+///     .map(|shard| Server::with_cluster(&cluster, shard, ServerConfig::default()).unwrap())
+///     .collect();
+/// // run each shard on its own thread...
+///
+/// // the first shard picked the port
+/// assert_ne!(cluster.addr().port(), 0);
+/// let token = cluster.token(123).generate().unwrap();
+/// assert_eq!(cluster.num_connected_clients(), 0);
+/// ```
+#[derive(Clone)]
+pub struct ServerCluster {
+    state: Arc<ClusterState>,
+}
+
+struct ClusterState {
+    addr: Mutex<SocketAddr>,
+    protocol_id: u64,
+    private_key: Zeroizing<Key>,
+    max_clients: AtomicUsize,
+    connected_clients: Vec<AtomicUsize>,
+}
+
+impl ServerCluster {
+    /// Create a cluster of `num_shards` shards serving `addr`.
+    ///
+    /// With port 0, the first shard created binds a free port, which the other shards and the tokens then use.
+    /// The cluster-wide client limit defaults to `usize::MAX`, i.e. only the shards' own limits apply.
+    ///
+    /// # Panics
+    /// Panics if `num_shards` is zero.
+    pub fn new(addr: SocketAddr, protocol_id: u64, private_key: Key, num_shards: usize) -> Self {
+        assert!(num_shards > 0, "a server cluster needs at least one shard");
+        Self {
+            state: Arc::new(ClusterState {
+                addr: Mutex::new(addr),
+                protocol_id,
+                private_key: Zeroizing::new(private_key),
+                max_clients: AtomicUsize::new(usize::MAX),
+                connected_clients: (0..num_shards).map(|_| AtomicUsize::new(0)).collect(),
+            }),
+        }
+    }
+    /// Gets the address every shard is bound to.
+    pub fn addr(&self) -> SocketAddr {
+        *self
+            .state
+            .addr
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+    /// Gets the protocol id of the cluster.
+    pub fn protocol_id(&self) -> u64 {
+        self.state.protocol_id
+    }
+    pub(crate) fn private_key(&self) -> Key {
+        *self.state.private_key
+    }
+    /// Gets the number of shards.
+    pub fn num_shards(&self) -> usize {
+        self.state.connected_clients.len()
+    }
+    /// Sets the maximum number of clients connected to the whole cluster, shards deny new clients once it is reached. <br>
+    /// Each shard still has its own limit as well, see [`Server::set_max_clients`](crate::Server::set_max_clients).
+    pub fn set_max_clients(&self, max_clients: usize) {
+        self.state.max_clients.store(max_clients, Ordering::Relaxed);
+    }
+    /// Gets the maximum number of clients connected to the whole cluster.
+    pub fn max_clients(&self) -> usize {
+        self.state.max_clients.load(Ordering::Relaxed)
+    }
+    /// Gets the number of clients connected to all the shards.
+    pub fn num_connected_clients(&self) -> usize {
+        self.state
+            .connected_clients
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .sum()
+    }
+    /// Gets the number of clients connected to a shard.
+    ///
+    /// # Panics
+    /// Panics if `shard` is out of range.
+    pub fn shard_connected_clients(&self, shard: usize) -> usize {
+        self.state.connected_clients[shard].load(Ordering::Relaxed)
+    }
+    /// Returns true if the cluster reached its client limit.
+    pub fn is_full(&self) -> bool {
+        self.num_connected_clients() >= self.max_clients()
+    }
+    /// Creates a connect token builder for a client, for the cluster's address.
+    pub fn token(&self, client_id: ClientId) -> ConnectTokenBuilder<SocketAddr> {
+        ConnectToken::build(
+            self.addr(),
+            self.state.protocol_id,
+            client_id,
+            *self.state.private_key,
+        )
+    }
+    // Binds the socket of a shard to the cluster's address, the first shard binding port 0 picks the port for the others.
+    pub(crate) fn bind<T: Transceiver>(
+        &self,
+        bind: impl FnOnce(SocketAddr) -> Result<T>,
+    ) -> Result<T> {
+        let mut addr = self
+            .state
+            .addr
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let trx = bind(*addr)?;
+        *addr = trx.addr();
+        Ok(trx)
+    }
+}
+
+// A server's place in a cluster, its clients stop counting towards the cluster's limit when it is dropped.
+pub(crate) struct Shard {
+    cluster: ServerCluster,
+    index: usize,
+}
+
+impl Shard {
+    pub(crate) fn new(cluster: ServerCluster, index: usize) -> Self {
+        Self { cluster, index }
+    }
+    pub(crate) fn set_connected_clients(&self, num_clients: usize) {
+        self.cluster.state.connected_clients[self.index].store(num_clients, Ordering::Relaxed);
+    }
+    pub(crate) fn is_cluster_full(&self) -> bool {
+        self.cluster.is_full()
+    }
+}
+
+impl Drop for Shard {
+    fn drop(&mut self) {
+        self.set_connected_clients(0);
+    }
+}
+
+impl fmt::Debug for ServerCluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ServerCluster")
+            .field("addr", &self.addr())
+            .field("protocol_id", &self.state.protocol_id)
+            .field("num_shards", &self.num_shards())
+            .field("max_clients", &self.max_clients())
+            .field("num_connected_clients", &self.num_connected_clients())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::{Client, Server, ServerConfig};
+
+    #[test]
+    fn cluster_limit_spans_shards() {
+        let addr = SocketAddr::from(([127, 0, 0, 1], 0));
+        let cluster = ServerCluster::new(addr, 0x11, crate::generate_key(), 2);
+        cluster.set_max_clients(2);
+        let mut shards: Vec<_> = (0..2)
+            .map(|shard| Server::with_cluster(&cluster, shard, ServerConfig::default()).unwrap())
+            .collect();
+        assert_ne!(cluster.addr().port(), 0);
+        let mut clients: Vec<_> = (0..4)
+            .map(|client_id| {
+                let token = cluster.token(client_id).generate().unwrap();
+                let mut client = Client::new(&token.try_into_bytes().unwrap()).unwrap();
+                client.connect();
+                client
+            })
+            .collect();
+        let mut time = 0.0;
+        while clients.iter().any(|c| c.state().is_pending()) && time < 5.0 {
+            for client in clients.iter_mut() {
+                client.update(time);
+            }
+            for shard in shards.iter_mut() {
+                shard.update(time);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            time += 0.01;
+        }
+        let connected = clients.iter().filter(|c| c.is_connected()).count();
+        assert_eq!(connected, 2);
+        assert_eq!(cluster.num_connected_clients(), 2);
+        assert_eq!(
+            cluster.shard_connected_clients(0) + cluster.shard_connected_clients(1),
+            2
+        );
+        assert!(cluster.is_full());
+
+        let remaining = cluster.shard_connected_clients(1);
+        shards[0].disconnect_all().unwrap();
+        assert_eq!(cluster.num_connected_clients(), remaining);
+        // a dropped shard's clients don't count anymore
+        shards.pop();
+        assert_eq!(cluster.num_connected_clients(), 0);
+    }
+}
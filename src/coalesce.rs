@@ -0,0 +1,116 @@
+//! Coalescing of small payloads into one payload packet, see [`ClientConfig::coalesce_payloads`](crate::ClientConfig::coalesce_payloads).
+//!
+//! A coalesced payload is a sequence of messages, each prefixed with its length as a little-endian `u16`.
+
+use alloc::vec::Vec;
+
+/// The bytes each message adds to a coalesced payload.
+pub(crate) const MESSAGE_HEADER_SIZE: usize = 2;
+
+/// The messages queued for one peer since the last flush.
+#[derive(Debug, Default)]
+pub(crate) struct SendQueue {
+    buf: Vec<u8>,
+}
+
+impl SendQueue {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+    /// Queues a message, which must be at most `max_payload_size - MESSAGE_HEADER_SIZE` bytes.
+    ///
+    /// Returns the previously queued messages if the new one doesn't fit in the same payload with them,
+    /// they have to be sent before the next flush.
+    pub(crate) fn push(&mut self, msg: &[u8], max_payload_size: usize) -> Option<Vec<u8>> {
+        let full = if self.buf.len() + MESSAGE_HEADER_SIZE + msg.len() > max_payload_size {
+            self.take()
+        } else {
+            None
+        };
+        self.buf
+            .extend_from_slice(&(msg.len() as u16).to_le_bytes());
+        self.buf.extend_from_slice(msg);
+        full
+    }
+    /// Takes the queued messages as one payload, `None` if nothing is queued.
+    pub(crate) fn take(&mut self) -> Option<Vec<u8>> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        Some(core::mem::take(&mut self.buf))
+    }
+    pub(crate) fn clear(&mut self) {
+        self.buf.clear();
+    }
+}
+
+/// A coalesced payload with one message.
+pub(crate) fn single(msg: &[u8]) -> Vec<u8> {
+    let mut queue = SendQueue::new();
+    queue.push(msg, usize::MAX);
+    queue.buf
+}
+
+/// Splits a coalesced payload into its messages.
+///
+/// A truncated message ends the iteration, the rest of the payload is dropped.
+pub(crate) fn messages(payload: &[u8]) -> Messages<'_> {
+    Messages(payload)
+}
+
+pub(crate) struct Messages<'p>(&'p [u8]);
+
+impl<'p> Iterator for Messages<'p> {
+    type Item = &'p [u8];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (header, rest) = self.0.split_at_checked(MESSAGE_HEADER_SIZE)?;
+        let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+        let Some((msg, rest)) = rest.split_at_checked(len) else {
+            log::debug!("dropping truncated message from coalesced payload");
+            self.0 = &[];
+            return None;
+        };
+        self.0 = rest;
+        Some(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coalesce_and_split() {
+        let mut queue = SendQueue::new();
+        assert_eq!(queue.take(), None);
+        assert_eq!(queue.push(b"hello", 16), None);
+        assert_eq!(queue.push(b"", 16), None);
+        assert_eq!(queue.push(b"world", 16), None);
+        let payload = queue.take().unwrap();
+        assert_eq!(payload.len(), 16);
+        assert_eq!(
+            messages(&payload).collect::<Vec<_>>(),
+            [&b"hello"[..], b"", b"world"]
+        );
+        assert_eq!(queue.take(), None);
+
+        // a message that doesn't fit returns the queued ones
+        queue.push(b"hello", 8);
+        let full = queue.push(b"world", 8).unwrap();
+        assert_eq!(messages(&full).collect::<Vec<_>>(), [b"hello"]);
+        assert_eq!(
+            messages(&queue.take().unwrap()).collect::<Vec<_>>(),
+            [b"world"]
+        );
+    }
+
+    #[test]
+    fn truncated_payloads() {
+        assert_eq!(messages(&[]).count(), 0);
+        assert_eq!(messages(&[5]).count(), 0);
+        assert_eq!(messages(&[5, 0, b'a']).count(), 0);
+        let payload = [1, 0, b'a', 3, 0, b'b'];
+        assert_eq!(messages(&payload).collect::<Vec<_>>(), [b"a"]);
+    }
+}
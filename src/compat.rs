@@ -0,0 +1,473 @@
+//! Wire-format compatibility with the netcode 1.02 reference implementation.
+//!
+//! Every vector below is assembled byte by byte following the encoders of the reference implementation
+//! (`netcode.c`: `netcode_write_connect_token`, `netcode_write_packet`, `netcode_encrypt_challenge_token`, ...),
+//! and encrypted with the raw AEAD primitives using the nonce and associated data layout of the reference,
+//! so nothing here goes through this crate's own encoders. The crate's output must match them byte for byte.
+//!
+//! The same vectors written by `netcode.c` itself with `tests/netcode_c/generate.sh` are committed as
+//! `tests/netcode_c/vectors.txt` and checked by `netcode_c_vectors`. The `netcode.c` CI job regenerates them
+//! from the reference implementation and fails if they differ from the committed file.
+
+use std::collections::HashMap;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    ChaCha20Poly1305, KeyInit, XChaCha20Poly1305, XNonce,
+};
+
+use crate::{
+    bytes::Bytes,
+    error::Error as NetcodeError,
+    packet::{
+        ChallengePacket, DisconnectPacket, Error, KeepAlivePacket, Packet, PayloadPacket,
+        RequestPacket,
+    },
+    token::{AddressList, ChallengeToken, ConnectToken, ConnectTokenPrivate},
+    CONNECT_TOKEN_BYTES, MAC_BYTES, NETCODE_VERSION, USER_DATA_BYTES,
+};
+
+const PROTOCOL_ID: u64 = 0x1122_3344_5566_7788;
+const CREATE_TIMESTAMP: u64 = 1_700_000_000;
+const EXPIRE_TIMESTAMP: u64 = 1_700_000_030;
+const TIMEOUT_SECONDS: i32 = 15;
+const CLIENT_ID: u64 = 0x0102_0304_0506_0708;
+
+fn key(seed: u8) -> [u8; 32] {
+    std::array::from_fn(|i| seed.wrapping_add(i as u8))
+}
+
+fn token_nonce() -> XNonce {
+    *XNonce::from_slice(&std::array::from_fn::<u8, 24, _>(|i| 0xA0 + i as u8))
+}
+
+fn user_data() -> [u8; USER_DATA_BYTES] {
+    std::array::from_fn(|i| i as u8)
+}
+
+fn addresses() -> AddressList {
+    let addrs = [
+        SocketAddr::from(([127, 0, 0, 1], 40000)),
+        SocketAddr::V6(SocketAddrV6::new(
+            Ipv6Addr::new(0xfe80, 0, 0, 0, 0x0202, 0xb3ff, 0xfe1e, 0x8329),
+            50000,
+            0,
+            0,
+        )),
+    ];
+    AddressList::new(&addrs[..]).unwrap()
+}
+
+/// `netcode_write_connect_token` address list, ipv6 addresses are written as 8 `uint16` segments.
+fn reference_addresses(out: &mut Vec<u8>) {
+    out.extend_from_slice(&2u32.to_le_bytes());
+    out.push(1); // NETCODE_ADDRESS_IPV4
+    out.extend_from_slice(&[127, 0, 0, 1]);
+    out.extend_from_slice(&40000u16.to_le_bytes());
+    out.push(2); // NETCODE_ADDRESS_IPV6
+    for segment in [0xfe80u16, 0, 0, 0, 0x0202, 0xb3ff, 0xfe1e, 0x8329] {
+        out.extend_from_slice(&segment.to_le_bytes());
+    }
+    out.extend_from_slice(&50000u16.to_le_bytes());
+}
+
+/// `netcode_generate_nonce` for packets and challenge tokens: 4 zero bytes followed by the little-endian sequence.
+fn reference_nonce(sequence: u64) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&sequence.to_le_bytes());
+    nonce
+}
+
+fn reference_packet(prefix: u8, sequence: u64, body: &[u8], key: &[u8; 32]) -> Vec<u8> {
+    let sequence_bytes = (prefix >> 4) as usize;
+    let mut aad = NETCODE_VERSION.to_vec();
+    aad.extend_from_slice(&PROTOCOL_ID.to_le_bytes());
+    aad.push(prefix);
+    let mut packet = vec![prefix];
+    packet.extend_from_slice(&sequence.to_le_bytes()[..sequence_bytes]);
+    packet.extend(
+        ChaCha20Poly1305::new(key.into())
+            .encrypt(
+                &reference_nonce(sequence).into(),
+                Payload {
+                    msg: body,
+                    aad: &aad,
+                },
+            )
+            .unwrap(),
+    );
+    packet
+}
+
+fn private_token() -> ConnectTokenPrivate {
+    ConnectTokenPrivate {
+        client_id: CLIENT_ID,
+        timeout_seconds: TIMEOUT_SECONDS,
+        server_addresses: addresses(),
+        client_to_server_key: key(0x10).into(),
+        server_to_client_key: key(0x40).into(),
+        user_data: user_data(),
+    }
+}
+
+fn public_token(strict_netcode_1_02: bool) -> ConnectToken {
+    ConnectToken {
+        version_info: *NETCODE_VERSION,
+        protocol_id: PROTOCOL_ID,
+        create_timestamp: CREATE_TIMESTAMP,
+        expire_timestamp: EXPIRE_TIMESTAMP,
+        nonce: token_nonce(),
+        private_data: [0x5A; ConnectTokenPrivate::SIZE],
+        timeout_seconds: TIMEOUT_SECONDS,
+        server_addresses: addresses(),
+        client_to_server_key: key(0x10).into(),
+        server_to_client_key: key(0x40).into(),
+        strict_netcode_1_02,
+    }
+}
+
+#[test]
+fn connect_token_private() {
+    // netcode_write_connect_token_private, padded to 1024 bytes including the MAC
+    let mut plaintext = CLIENT_ID.to_le_bytes().to_vec();
+    plaintext.extend_from_slice(&TIMEOUT_SECONDS.to_le_bytes());
+    reference_addresses(&mut plaintext);
+    plaintext.extend_from_slice(&key(0x10));
+    plaintext.extend_from_slice(&key(0x40));
+    plaintext.extend_from_slice(&user_data());
+    plaintext.resize(ConnectTokenPrivate::SIZE - MAC_BYTES, 0);
+    // netcode_encrypt_connect_token_private
+    let mut aad = NETCODE_VERSION.to_vec();
+    aad.extend_from_slice(&PROTOCOL_ID.to_le_bytes());
+    aad.extend_from_slice(&EXPIRE_TIMESTAMP.to_le_bytes());
+    let expected = XChaCha20Poly1305::new(&key(0x70).into())
+        .encrypt(
+            &token_nonce(),
+            Payload {
+                msg: &plaintext,
+                aad: &aad,
+            },
+        )
+        .unwrap();
+
+    let ConnectTokenPrivate {
+        server_addresses, ..
+    } = private_token();
+    let strict = ConnectTokenPrivate {
+        server_addresses: server_addresses.swap_ipv6_segments(),
+        ..private_token()
+    };
+    let encrypted = strict
+        .encrypt(PROTOCOL_ID, EXPIRE_TIMESTAMP, token_nonce(), &key(0x70))
+        .unwrap();
+    assert_eq!(encrypted[..], expected[..]);
+
+    // the legacy encoding only differs in the ipv6 address
+    let legacy = private_token()
+        .encrypt(PROTOCOL_ID, EXPIRE_TIMESTAMP, token_nonce(), &key(0x70))
+        .unwrap();
+    assert_ne!(legacy[..], expected[..]);
+    let mut decrypted = expected.clone();
+    let token = ConnectTokenPrivate::decrypt(
+        &mut decrypted,
+        PROTOCOL_ID,
+        EXPIRE_TIMESTAMP,
+        token_nonce(),
+        &key(0x70),
+    )
+    .unwrap();
+    let addrs = token.server_addresses.swap_ipv6_segments();
+    assert_eq!(addrs[0], addresses()[0]);
+    assert_eq!(addrs[1], addresses()[1]);
+}
+
+#[test]
+fn connect_token_public() {
+    let private_data = [0x5A; ConnectTokenPrivate::SIZE];
+    // netcode_write_connect_token, padded to 2048 bytes
+    let mut expected = NETCODE_VERSION.to_vec();
+    expected.extend_from_slice(&PROTOCOL_ID.to_le_bytes());
+    expected.extend_from_slice(&CREATE_TIMESTAMP.to_le_bytes());
+    expected.extend_from_slice(&EXPIRE_TIMESTAMP.to_le_bytes());
+    expected.extend_from_slice(&token_nonce());
+    expected.extend_from_slice(&private_data);
+    expected.extend_from_slice(&TIMEOUT_SECONDS.to_le_bytes());
+    reference_addresses(&mut expected);
+    expected.extend_from_slice(&key(0x10));
+    expected.extend_from_slice(&key(0x40));
+    expected.resize(CONNECT_TOKEN_BYTES, 0);
+
+    assert_eq!(
+        public_token(true).try_into_bytes().unwrap()[..],
+        expected[..]
+    );
+    assert_ne!(
+        public_token(false).try_into_bytes().unwrap()[..],
+        expected[..]
+    );
+
+    let parsed =
+        <ConnectToken as Bytes>::read_from(&mut crate::io::Cursor::new(&expected[..])).unwrap();
+    assert_eq!(parsed.server_addresses[0], addresses()[0]);
+    assert_eq!(
+        parsed.server_addresses.swap_ipv6_segments()[1],
+        addresses()[1]
+    );
+}
+
+#[test]
+fn challenge_token() {
+    // netcode_write_challenge_token, padded to 300 bytes including the MAC, encrypted without associated data
+    let mut plaintext = CLIENT_ID.to_le_bytes().to_vec();
+    plaintext.extend_from_slice(&user_data());
+    plaintext.resize(ChallengeToken::SIZE - MAC_BYTES, 0);
+    let sequence = 0x0A0B;
+    let expected = ChaCha20Poly1305::new(&key(0x70).into())
+        .encrypt(&reference_nonce(sequence).into(), &plaintext[..])
+        .unwrap();
+
+    let token = ChallengeToken {
+        client_id: CLIENT_ID,
+        user_data: user_data(),
+        custom_data: [0; crate::CHALLENGE_DATA_BYTES],
+    };
+    assert_eq!(
+        token.encrypt(sequence, &key(0x70)).unwrap()[..],
+        expected[..]
+    );
+}
+
+#[test]
+fn challenge_packet() {
+    let token = [0x3C; ChallengeToken::SIZE];
+    let mut body = 77u64.to_le_bytes().to_vec();
+    body.extend_from_slice(&token);
+    // 1 sequence byte, NETCODE_CONNECTION_CHALLENGE_PACKET
+    let expected = reference_packet(0x12, 9, &body, &key(0x40));
+
+    let mut buf = [0; crate::MAX_PKT_BUF_SIZE];
+    let size = ChallengePacket::create(77, token)
+        .write(&mut buf, 9, &key(0x40), PROTOCOL_ID)
+        .unwrap();
+    assert_eq!(buf[..size], expected[..]);
+}
+
+#[test]
+fn payload_and_keep_alive_packets() {
+    let payload = b"netcode interop";
+    // 3 sequence bytes, NETCODE_CONNECTION_PAYLOAD_PACKET
+    let expected = reference_packet(0x35, 0x03_0201, payload, &key(0x10));
+    let mut buf = [0; crate::MAX_PKT_BUF_SIZE];
+    let size = PayloadPacket::create(payload)
+        .write(&mut buf, 0x03_0201, &key(0x10), PROTOCOL_ID)
+        .unwrap();
+    assert_eq!(buf[..size], expected[..]);
+
+    let mut body = 3i32.to_le_bytes().to_vec();
+    body.extend_from_slice(&64i32.to_le_bytes());
+    // sequence 0 still takes 1 byte, NETCODE_CONNECTION_KEEP_ALIVE_PACKET
+    let expected = reference_packet(0x14, 0, &body, &key(0x40));
+    let size = KeepAlivePacket::create(3, 64)
+        .write(&mut buf, 0, &key(0x40), PROTOCOL_ID)
+        .unwrap();
+    assert_eq!(buf[..size], expected[..]);
+
+    let expected = reference_packet(0x16, 1, &[], &key(0x40));
+    let size = DisconnectPacket::create()
+        .write(&mut buf, 1, &key(0x40), PROTOCOL_ID)
+        .unwrap();
+    assert_eq!(buf[..size], expected[..]);
+}
+
+#[test]
+fn connection_request_packet() {
+    let private_data = [0x5A; ConnectTokenPrivate::SIZE];
+    // netcode_write_packet for NETCODE_CONNECTION_REQUEST_PACKET, sent unencrypted
+    let mut expected = vec![0];
+    expected.extend_from_slice(NETCODE_VERSION);
+    expected.extend_from_slice(&PROTOCOL_ID.to_le_bytes());
+    expected.extend_from_slice(&EXPIRE_TIMESTAMP.to_le_bytes());
+    expected.extend_from_slice(&token_nonce());
+    expected.extend_from_slice(&private_data);
+    assert_eq!(expected.len(), 1078);
+
+    let mut buf = [0; crate::MAX_PKT_BUF_SIZE];
+    let size = RequestPacket::create(PROTOCOL_ID, EXPIRE_TIMESTAMP, token_nonce(), private_data)
+        .write(&mut buf, 0, &key(0), PROTOCOL_ID)
+        .unwrap();
+    assert_eq!(buf[..size], expected[..]);
+
+    // the reference ignores requests with trailing bytes
+    let result = Packet::read(
+        &mut buf[..size + 1],
+        PROTOCOL_ID,
+        CREATE_TIMESTAMP,
+        key(0x70),
+        None,
+        0xff,
+        true,
+    );
+    assert!(matches!(
+        result,
+        Err(NetcodeError::Packet(Error::LengthMismatch {
+            expected: 1078,
+            actual: 1079
+        }))
+    ));
+}
+
+#[test]
+fn strict_reads_match_reference_validation() {
+    let allowed = 0xff;
+    // a keep-alive with a trailing byte
+    let mut body = 3i32.to_le_bytes().to_vec();
+    body.extend_from_slice(&64i32.to_le_bytes());
+    body.push(0);
+    let mut packet = reference_packet(0x14, 5, &body, &key(0x40));
+    assert!(Packet::read(
+        &mut packet.clone(),
+        PROTOCOL_ID,
+        0,
+        key(0x40),
+        None,
+        allowed,
+        false
+    )
+    .is_ok());
+    assert!(matches!(
+        Packet::read(&mut packet, PROTOCOL_ID, 0, key(0x40), None, allowed, true),
+        Err(NetcodeError::Packet(Error::LengthMismatch {
+            expected: 8,
+            actual: 9
+        }))
+    ));
+
+    // an empty payload
+    let mut packet = reference_packet(0x15, 6, &[], &key(0x40));
+    assert!(Packet::read(
+        &mut packet.clone(),
+        PROTOCOL_ID,
+        0,
+        key(0x40),
+        None,
+        allowed,
+        false
+    )
+    .is_ok());
+    assert!(matches!(
+        Packet::read(&mut packet, PROTOCOL_ID, 0, key(0x40), None, allowed, true),
+        Err(NetcodeError::Packet(Error::TooSmall))
+    ));
+
+    // the payload limit extension is unknown to the reference
+    let mut packet = reference_packet(0x17, 7, &500u16.to_le_bytes(), &key(0x40));
+    assert!(Packet::read(
+        &mut packet.clone(),
+        PROTOCOL_ID,
+        0,
+        key(0x40),
+        None,
+        allowed,
+        false
+    )
+    .is_ok());
+    assert!(matches!(
+        Packet::read(&mut packet, PROTOCOL_ID, 0, key(0x40), None, allowed, true),
+        Err(NetcodeError::Packet(Error::InvalidType(7)))
+    ));
+
+    // more than 8 sequence bytes is always invalid
+    let mut packet = reference_packet(0x15, 7, b"payload", &key(0x40));
+    packet[0] = 0x95;
+    packet.resize(40, 0);
+    assert!(matches!(
+        Packet::read(&mut packet, PROTOCOL_ID, 0, key(0x40), None, allowed, false),
+        Err(NetcodeError::Packet(Error::InvalidSequenceBytes(9)))
+    ));
+}
+
+#[test]
+fn netcode_c_vectors() {
+    let vectors: HashMap<_, _> = include_str!("../tests/netcode_c/vectors.txt")
+        .lines()
+        .map(|line| {
+            let (name, hex) = line.split_once(' ').unwrap();
+            let bytes: Vec<u8> = (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect();
+            (name, bytes)
+        })
+        .collect();
+    let vector = |name: &str| {
+        vectors
+            .get(name)
+            .unwrap_or_else(|| panic!("netcode.c wrote no {name} vector"))
+            .as_slice()
+    };
+    assert_eq!(vector("version"), NETCODE_VERSION);
+
+    let strict = ConnectTokenPrivate {
+        server_addresses: private_token().server_addresses.swap_ipv6_segments(),
+        ..private_token()
+    };
+    let encrypted = strict
+        .encrypt(PROTOCOL_ID, EXPIRE_TIMESTAMP, token_nonce(), &key(0x70))
+        .unwrap();
+    assert_eq!(encrypted[..], *vector("connect_token_private"));
+    assert_eq!(
+        public_token(true).try_into_bytes().unwrap()[..],
+        *vector("connect_token")
+    );
+    let challenge_token = ChallengeToken {
+        client_id: CLIENT_ID,
+        user_data: user_data(),
+        custom_data: [0; crate::CHALLENGE_DATA_BYTES],
+    };
+    assert_eq!(
+        challenge_token.encrypt(0x0A0B, &key(0x70)).unwrap()[..],
+        *vector("challenge_token")
+    );
+
+    let private_data = [0x5A; ConnectTokenPrivate::SIZE];
+    let payload = b"netcode interop";
+    for (name, packet, sequence, packet_key) in [
+        (
+            "request_packet",
+            RequestPacket::create(PROTOCOL_ID, EXPIRE_TIMESTAMP, token_nonce(), private_data),
+            0,
+            key(0),
+        ),
+        (
+            "challenge_packet",
+            ChallengePacket::create(77, [0x3C; ChallengeToken::SIZE]),
+            9,
+            key(0x40),
+        ),
+        (
+            "payload_packet",
+            PayloadPacket::create(payload),
+            0x03_0201,
+            key(0x10),
+        ),
+        (
+            "keep_alive_packet",
+            KeepAlivePacket::create(3, 64),
+            0,
+            key(0x40),
+        ),
+        (
+            "disconnect_packet",
+            DisconnectPacket::create(),
+            1,
+            key(0x40),
+        ),
+    ] {
+        let mut buf = [0; crate::MAX_PKT_BUF_SIZE];
+        let size = packet
+            .write(&mut buf, sequence, &packet_key, PROTOCOL_ID)
+            .unwrap();
+        assert_eq!(buf[..size], *vector(name), "{name}");
+    }
+}
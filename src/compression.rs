@@ -0,0 +1,252 @@
+//! Payload compression, see [`ClientConfig::compress_payloads`](crate::ClientConfig::compress_payloads).
+//!
+//! A payload is prefixed with a flags byte, and compressed payloads are in the [LZ4 block format](https://github.com/lz4/lz4/blob/dev/doc/lz4_Block_format.md).
+//! Compression happens before the payload is encrypted (encrypted data doesn't compress).
+
+use alloc::{borrow::Cow, vec::Vec};
+
+use crate::MAX_PACKET_SIZE;
+
+/// The bytes compression adds to each payload.
+pub(crate) const HEADER_SIZE: usize = 1;
+
+const FLAG_COMPRESSED: u8 = 1;
+
+const MIN_MATCH: usize = 4;
+// The last 5 bytes are always literals, and the last match starts at least 12 bytes before the end.
+const LAST_LITERALS: usize = 5;
+const MF_LIMIT: usize = 12;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_LOG: u32 = 10;
+
+/// Prefixes `payload` with the flags byte, compressing it if it is at least `threshold` bytes and compression makes it smaller.
+pub(crate) fn encode(payload: &[u8], threshold: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_SIZE + payload.len());
+    if payload.len() >= threshold {
+        out.push(FLAG_COMPRESSED);
+        compress(payload, &mut out);
+        if out.len() < HEADER_SIZE + payload.len() {
+            return out;
+        }
+        out.clear();
+    }
+    out.push(0);
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Strips the flags byte from `payload`, decompressing it if needed.
+///
+/// Returns `None` for payloads with unknown flags, that fail to decompress or that decompress to more than `MAX_PACKET_SIZE` bytes.
+pub(crate) fn decode(payload: &[u8]) -> Option<Cow<'_, [u8]>> {
+    let (&flags, rest) = payload.split_first()?;
+    match flags {
+        0 => Some(Cow::Borrowed(rest)),
+        FLAG_COMPRESSED => decompress(rest, MAX_PACKET_SIZE).map(Cow::Owned),
+        _ => None,
+    }
+}
+
+fn hash(seq: u32) -> usize {
+    (seq.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
+}
+
+fn compress(input: &[u8], out: &mut Vec<u8>) {
+    // positions + 1, so 0 is an empty slot
+    let mut table = [0usize; 1 << HASH_LOG];
+    let mut anchor = 0;
+    let mut pos = 0;
+    let match_limit = input.len().saturating_sub(MF_LIMIT);
+    let match_end = input.len().saturating_sub(LAST_LITERALS);
+    while pos < match_limit {
+        let seq = read_u32(input, pos);
+        let slot = &mut table[hash(seq)];
+        let candidate = slot.checked_sub(1);
+        *slot = pos + 1;
+        match candidate {
+            Some(candidate)
+                if pos - candidate <= MAX_OFFSET && read_u32(input, candidate) == seq =>
+            {
+                let mut len = MIN_MATCH;
+                while pos + len < match_end && input[candidate + len] == input[pos + len] {
+                    len += 1;
+                }
+                write_sequence(out, &input[anchor..pos], Some((pos - candidate, len)));
+                pos += len;
+                anchor = pos;
+            }
+            _ => pos += 1,
+        }
+    }
+    write_sequence(out, &input[anchor..], None);
+}
+
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_len = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push((literals.len().min(15) << 4) as u8 | match_len.min(15) as u8);
+    if literals.len() >= 15 {
+        write_len(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= 15 {
+            write_len(out, match_len - 15);
+        }
+    }
+}
+
+fn write_len(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn decompress(input: &[u8], max_size: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    loop {
+        let token = *input.get(pos)?;
+        pos += 1;
+        let literals_len = read_len(input, &mut pos, (token >> 4) as usize)?;
+        let literals = input.get(pos..pos.checked_add(literals_len)?)?;
+        if out.len() + literals.len() > max_size {
+            return None;
+        }
+        out.extend_from_slice(literals);
+        pos += literals_len;
+        if pos == input.len() {
+            return Some(out);
+        }
+        let offset = u16::from_le_bytes([*input.get(pos)?, *input.get(pos + 1)?]) as usize;
+        pos += 2;
+        let match_len = read_len(input, &mut pos, (token & 0xf) as usize)? + MIN_MATCH;
+        if offset == 0 || offset > out.len() || out.len() + match_len > max_size {
+            return None;
+        }
+        // the match may overlap the bytes it produces, so it is copied byte by byte
+        let start = out.len() - offset;
+        for i in start..start + match_len {
+            out.push(out[i]);
+        }
+    }
+}
+
+fn read_len(input: &[u8], pos: &mut usize, nibble: usize) -> Option<usize> {
+    let mut len = nibble;
+    if nibble == 15 {
+        loop {
+            let byte = *input.get(*pos)?;
+            *pos += 1;
+            len = len.checked_add(byte as usize)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+    Some(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let snapshot: Vec<u8> = (0..1000u32).map(|i| (i % 7) as u8).collect();
+        let random: Vec<u8> = (0..300u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+            .collect();
+        for payload in [&b""[..], b"hello", &snapshot, &random, &[0; 1199]] {
+            let encoded = encode(payload, 0);
+            assert!(encoded.len() <= HEADER_SIZE + payload.len());
+            assert_eq!(decode(&encoded).unwrap(), payload);
+        }
+        // repetitive payloads shrink
+        assert!(encode(&snapshot, 0).len() < snapshot.len() / 4);
+        // payloads below the threshold are not compressed
+        assert_eq!(encode(&snapshot, 1001)[1..], snapshot[..]);
+    }
+
+    // Blocks produced by the reference implementation (lz4 v1.9.4), taken from the frames of
+    // `lz4 -c -12 -B4 --no-frame-crc -BI`: the frame header and block size are stripped, the block is kept as it is.
+    const REFERENCE_BLOCKS: [(&str, &str); 3] = [
+        (
+            "the quick brown fox jumps over the lazy dog, the quick brown fox jumps over the lazy dog again",
+            "f01074686520717569636b2062726f776e20666f78206a756d7073206f766572201f00af6c617a7920646f672c202d00186020616761696e",
+        ),
+        // 0, 1, ..., 6, 0, 1, ... (1000 bytes)
+        ("snapshot", "7f000102030405060700ffffffcc500102030405"),
+        // 300 zero bytes followed by text
+        ("zeros", "1f000100ff19f003656e64206f6620746865207061796c6f6164"),
+    ];
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn decompresses_reference_blocks() {
+        for (name, block) in REFERENCE_BLOCKS {
+            let expected = match name {
+                "snapshot" => (0..1000u32).map(|i| (i % 7) as u8).collect(),
+                "zeros" => [&[0; 300][..], b"end of the payload"].concat(),
+                text => text.as_bytes().to_vec(),
+            };
+            assert_eq!(decompress(&hex(block), MAX_PACKET_SIZE).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn compresses_to_blocks_the_reference_decompresses() {
+        // This crate's blocks for the same input, which `lz4 -d` (v1.9.4) decompresses back to it once wrapped in a frame.
+        // They only differ from the reference blocks where the reference searches for longer matches.
+        let text = b"the quick brown fox jumps over the lazy dog, the quick brown fox jumps over the lazy dog again";
+        let mut compressed = Vec::new();
+        compress(text, &mut compressed);
+        assert_eq!(
+            compressed,
+            hex("f01074686520717569636b2062726f776e20666f78206a756d7073206f766572201f00916c617a7920646f672c0e000f2d00146020616761696e")
+        );
+        for (name, block) in &REFERENCE_BLOCKS[1..] {
+            let mut compressed = Vec::new();
+            compress(
+                &decompress(&hex(block), MAX_PACKET_SIZE).unwrap(),
+                &mut compressed,
+            );
+            assert_eq!(compressed, hex(block), "{name}");
+        }
+    }
+
+    #[test]
+    fn malformed_payloads() {
+        assert!(decode(&[]).is_none());
+        assert!(decode(&[2, 0]).is_none());
+        assert_eq!(decode(&[0]).unwrap(), &[][..]);
+        // truncated literals
+        assert!(decode(&[FLAG_COMPRESSED, 0x50, 1, 2]).is_none());
+        // offset pointing before the start
+        assert!(decode(&[FLAG_COMPRESSED, 0x10, 1, 2, 0]).is_none());
+        // zero offset
+        assert!(decode(&[FLAG_COMPRESSED, 0x10, 1, 0, 0]).is_none());
+        // a match that expands beyond the max size
+        let mut bomb = vec![FLAG_COMPRESSED, 0x1f, 1, 1, 0];
+        bomb.extend([255; 5]);
+        bomb.push(0);
+        assert!(decode(&bomb).is_none());
+        // every truncation of a valid payload
+        let encoded = encode(&[7; 500], 0);
+        for len in 2..encoded.len() {
+            assert!(decode(&encoded[..len]).is_none_or(|p| p.len() < 500));
+        }
+    }
+}
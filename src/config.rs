@@ -0,0 +1,180 @@
+//! Validation of [`ServerConfig`](crate::ServerConfig) and [`ClientConfig`](crate::ClientConfig).
+//!
+//! The setters only clamp single values, combinations of options are checked when the server or client is created,
+//! so a misconfiguration fails right away with a descriptive error instead of with dropped payloads or timeouts later.
+
+use thiserror::Error;
+
+/// An invalid option or combination of options in a [`ServerConfig`](crate::ServerConfig) or [`ClientConfig`](crate::ClientConfig),
+/// see [`ServerConfig::validate`](crate::ServerConfig::validate) and [`ClientConfig::validate`](crate::ClientConfig::validate).
+#[derive(Error, Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum ConfigError {
+    #[error("{option} must be positive, got {value}")]
+    NotPositive { option: &'static str, value: f64 },
+    #[error("the timeout of {timeout_seconds} seconds must be longer than the {option} of {interval} seconds, or idle connections time out")]
+    TimeoutTooShort {
+        timeout_seconds: i32,
+        option: &'static str,
+        interval: f64,
+    },
+    #[error("the max payload size of {max_payload_size} bytes leaves no room for payloads after {overhead} bytes of headers")]
+    PayloadTooSmall {
+        max_payload_size: usize,
+        overhead: usize,
+    },
+    #[error("the padding bucket of {bucket} bytes is larger than the max payload size of {max_payload_size} bytes")]
+    PaddingBucketTooLarge {
+        bucket: usize,
+        max_payload_size: usize,
+    },
+    #[error("max pending connections is 0, so no client can connect")]
+    NoPendingConnections,
+}
+
+/// Checks that an interval or rate is positive (NaN is not).
+pub(crate) fn check_positive(option: &'static str, value: f64) -> Result<(), ConfigError> {
+    if value > 0.0 {
+        Ok(())
+    } else {
+        Err(ConfigError::NotPositive { option, value })
+    }
+}
+
+/// Checks that a connection doesn't time out between two keep-alives sent every `interval` seconds.
+/// A negative timeout never expires.
+pub(crate) fn check_timeout(
+    timeout_seconds: Option<i32>,
+    option: &'static str,
+    interval: f64,
+) -> Result<(), ConfigError> {
+    match timeout_seconds {
+        Some(timeout_seconds) if timeout_seconds >= 0 && f64::from(timeout_seconds) <= interval => {
+            Err(ConfigError::TimeoutTooShort {
+                timeout_seconds,
+                option,
+                interval,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that payloads still fit in `max_payload_size` after the `overhead` of the enabled headers,
+/// and that no padding bucket is larger than it.
+pub(crate) fn check_payload_size(
+    max_payload_size: usize,
+    overhead: usize,
+    padding_buckets: &[usize],
+) -> Result<(), ConfigError> {
+    if max_payload_size <= overhead {
+        return Err(ConfigError::PayloadTooSmall {
+            max_payload_size,
+            overhead,
+        });
+    }
+    match padding_buckets.last() {
+        Some(&bucket) if bucket > max_payload_size => Err(ConfigError::PaddingBucketTooLarge {
+            bucket,
+            max_payload_size,
+        }),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{ClientConfig, Error, ServerConfig};
+
+    fn config_error(result: crate::Result<()>) -> ConfigError {
+        match result {
+            Err(Error::InvalidConfig(e)) => e,
+            other => panic!("expected a config error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn presets_are_valid() {
+        for cfg in [
+            ServerConfig::default(),
+            ServerConfig::competitive(),
+            ServerConfig::casual(),
+        ] {
+            cfg.validate().unwrap();
+        }
+        for cfg in [
+            ClientConfig::default(),
+            ClientConfig::competitive(),
+            ClientConfig::casual(),
+        ] {
+            cfg.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_combinations() {
+        assert_eq!(
+            config_error(ServerConfig::default().keep_alive_send_rate(0.0).validate()),
+            ConfigError::NotPositive {
+                option: "keep_alive_send_rate",
+                value: 0.0
+            }
+        );
+        assert_eq!(
+            config_error(ServerConfig::casual().timeout_seconds(1).validate()),
+            ConfigError::TimeoutTooShort {
+                timeout_seconds: 1,
+                option: "keep_alive_interval",
+                interval: 1.0
+            }
+        );
+        assert_eq!(
+            config_error(
+                ServerConfig::default()
+                    .max_pending_connections(0)
+                    .validate()
+            ),
+            ConfigError::NoPendingConnections
+        );
+        assert!(matches!(
+            ServerConfig::default().max_clients(usize::MAX).validate(),
+            Err(Error::MaxClients(_))
+        ));
+        assert_eq!(
+            config_error(
+                ClientConfig::default()
+                    .max_payload_size(14)
+                    .ack_payloads(true)
+                    .coalesce_payloads(true)
+                    .validate()
+            ),
+            ConfigError::PayloadTooSmall {
+                max_payload_size: 14,
+                overhead: 14
+            }
+        );
+        assert_eq!(
+            config_error(
+                ClientConfig::default()
+                    .max_payload_size(500)
+                    .pad_payloads(&[128, 1200])
+                    .validate()
+            ),
+            ConfigError::PaddingBucketTooLarge {
+                bucket: 1200,
+                max_payload_size: 500
+            }
+        );
+        assert!(matches!(
+            config_error(ClientConfig::default().packet_send_rate(f64::NAN).validate()),
+            ConfigError::NotPositive { option: "packet_send_rate", value } if value.is_nan()
+        ));
+        // no timeout never expires between keep-alives
+        ClientConfig::default()
+            .keep_alive_interval(60.0)
+            .disable_timeout()
+            .validate()
+            .unwrap();
+    }
+}
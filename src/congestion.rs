@@ -0,0 +1,132 @@
+//! Congestion avoidance with a good and a bad send rate, as described in
+//! [Reliability and Congestion Avoidance](https://gafferongames.com/post/reliability_ordering_and_congestion_avoidance_over_udp/).
+//!
+//! The connection drops to bad mode as soon as the RTT or packet loss is too high, and only goes back to good mode
+//! once conditions have been good for a penalty time. Dropping back to bad mode soon after recovering doubles the
+//! penalty, and every period spent in good mode halves it, so a flapping connection settles in bad mode.
+
+/// RTTs (in seconds) above this are bad conditions.
+const RTT_THRESHOLD: f64 = 0.25;
+/// Packet loss above this fraction is bad conditions.
+const PACKET_LOSS_THRESHOLD: f64 = 0.1;
+const INITIAL_PENALTY_TIME: f64 = 4.0;
+const MIN_PENALTY_TIME: f64 = 1.0;
+const MAX_PENALTY_TIME: f64 = 60.0;
+/// Dropping to bad mode sooner than this after switching to good mode doubles the penalty,
+/// and staying in good mode this long halves it.
+const PENALTY_PERIOD: f64 = 10.0;
+
+/// The quality of a connection, as measured by its congestion avoidance, see [`Client::connection_quality`](crate::Client::connection_quality).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ConnectionQuality {
+    /// The RTT and packet loss are low, the connection can take the good send rate.
+    #[default]
+    Good,
+    /// The RTT or packet loss are high, or were recently, sends should be throttled to the bad send rate.
+    Bad,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Congestion {
+    quality: ConnectionQuality,
+    last_update: Option<f64>,
+    good_since: f64,
+    good_conditions_time: f64,
+    penalty_time: f64,
+    penalty_reduction_time: f64,
+}
+
+impl Default for Congestion {
+    fn default() -> Self {
+        Self {
+            quality: ConnectionQuality::Good,
+            last_update: None,
+            good_since: 0.0,
+            good_conditions_time: 0.0,
+            penalty_time: INITIAL_PENALTY_TIME,
+            penalty_reduction_time: 0.0,
+        }
+    }
+}
+
+impl Congestion {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+    pub(crate) fn quality(&self) -> ConnectionQuality {
+        self.quality
+    }
+    /// Updates the mode with the current RTT (`None` if unknown, which counts as good) and packet loss.
+    pub(crate) fn update(&mut self, time: f64, rtt: Option<f64>, packet_loss: f64) {
+        let dt = self.last_update.map_or(0.0, |last| (time - last).max(0.0));
+        self.last_update = Some(time);
+        let bad_conditions =
+            rtt.is_some_and(|rtt| rtt > RTT_THRESHOLD) || packet_loss > PACKET_LOSS_THRESHOLD;
+        match self.quality {
+            ConnectionQuality::Good if bad_conditions => {
+                log::debug!("connection quality dropped to bad");
+                self.quality = ConnectionQuality::Bad;
+                if time - self.good_since < PENALTY_PERIOD {
+                    self.penalty_time = (self.penalty_time * 2.0).min(MAX_PENALTY_TIME);
+                }
+                self.good_conditions_time = 0.0;
+            }
+            ConnectionQuality::Good => {
+                self.penalty_reduction_time += dt;
+                if self.penalty_reduction_time >= PENALTY_PERIOD {
+                    self.penalty_time = (self.penalty_time / 2.0).max(MIN_PENALTY_TIME);
+                    self.penalty_reduction_time = 0.0;
+                }
+            }
+            ConnectionQuality::Bad if bad_conditions => self.good_conditions_time = 0.0,
+            ConnectionQuality::Bad => {
+                self.good_conditions_time += dt;
+                if self.good_conditions_time >= self.penalty_time {
+                    log::debug!("connection quality recovered to good");
+                    self.quality = ConnectionQuality::Good;
+                    self.good_since = time;
+                    self.penalty_reduction_time = 0.0;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bad_mode_penalty() {
+        let mut congestion = Congestion::new();
+        congestion.update(0.0, None, 0.0);
+        assert_eq!(congestion.quality(), ConnectionQuality::Good);
+
+        congestion.update(100.0, Some(0.3), 0.0);
+        assert_eq!(congestion.quality(), ConnectionQuality::Bad);
+        // conditions have to stay good for the penalty time
+        congestion.update(101.0, Some(0.05), 0.0);
+        congestion.update(103.0, Some(0.05), 0.0);
+        assert_eq!(congestion.quality(), ConnectionQuality::Bad);
+        congestion.update(104.0, Some(0.3), 0.0);
+        congestion.update(107.0, Some(0.05), 0.0);
+        assert_eq!(congestion.quality(), ConnectionQuality::Bad);
+        congestion.update(108.0, Some(0.05), 0.0);
+        assert_eq!(congestion.quality(), ConnectionQuality::Good);
+
+        // dropping back right away doubles the penalty
+        congestion.update(109.0, Some(0.05), 0.2);
+        assert_eq!(congestion.quality(), ConnectionQuality::Bad);
+        assert_eq!(congestion.penalty_time, 8.0);
+        congestion.update(110.0, Some(0.05), 0.0);
+        congestion.update(116.0, Some(0.05), 0.0);
+        assert_eq!(congestion.quality(), ConnectionQuality::Bad);
+        congestion.update(118.0, Some(0.05), 0.0);
+        assert_eq!(congestion.quality(), ConnectionQuality::Good);
+
+        // and staying in good mode halves it again
+        congestion.update(128.0, Some(0.05), 0.0);
+        assert_eq!(congestion.penalty_time, 4.0);
+    }
+}
@@ -0,0 +1,64 @@
+/// Configuration for how a client connects: how often it resends handshake packets,
+/// how long it tries each server address, and how long it tries in total.
+///
+/// The defaults fit most links. High-latency links (satellite, congested mobile networks) where a handshake
+/// legitimately takes several seconds may need longer timeouts, see [`ClientConfig::connect_config`](crate::ClientConfig::connect_config).
+///
+/// # Example
+/// ```
+/// use netcode::{ClientConfig, ConnectConfig};
+///
+/// let cfg = ClientConfig::default().connect_config(
+///     ConnectConfig::new()
+///         .resend_interval(0.25)
+///         .address_timeout(10.0)
+///         .total_timeout(30.0)
+///         .happy_eyeballs(0.25),
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ConnectConfig {
+    pub(crate) resend_interval: Option<f64>,
+    pub(crate) address_timeout: Option<f64>,
+    pub(crate) total_timeout: Option<f64>,
+    pub(crate) happy_eyeballs: Option<f64>,
+}
+
+impl ConnectConfig {
+    /// Create a new, default connect configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Set the interval (in seconds) at which connection requests and challenge responses are resent until the server answers. <br>
+    /// The default is the client's [`packet_send_rate`](crate::ClientConfig::packet_send_rate).
+    pub fn resend_interval(mut self, interval_seconds: f64) -> Self {
+        self.resend_interval = Some(interval_seconds);
+        self
+    }
+    /// Set how long (in seconds) the client tries to connect to each server address in the connect token
+    /// before moving on to the next one, whether or not the server answered in the meantime. <br>
+    /// The default is the connection timeout: the client moves on once it hasn't heard from the server
+    /// for [`timeout_seconds`](crate::ClientConfig::timeout_seconds).
+    pub fn address_timeout(mut self, timeout_seconds: f64) -> Self {
+        self.address_timeout = Some(timeout_seconds);
+        self
+    }
+    /// Set how long (in seconds) a connect attempt may take in total, over all server addresses in the connect token. <br>
+    /// Once it passes, the client stops in [`ConnectionRequestTimedOut`](crate::ClientState::ConnectionRequestTimedOut)
+    /// or [`ChallengeResponseTimedOut`](crate::ClientState::ChallengeResponseTimedOut), even if addresses are left.
+    /// The default is no limit, other than the connect token expiring.
+    pub fn total_timeout(mut self, timeout_seconds: f64) -> Self {
+        self.total_timeout = Some(timeout_seconds);
+        self
+    }
+    /// Race the server addresses of both IP families, like happy eyeballs (RFC 8305) does: when the connect token lists
+    /// IPv6 and IPv4 addresses, the client starts sending connection requests to the next address of the other family
+    /// `stagger_seconds` after the current one, and continues with whichever answers with a challenge first. <br>
+    /// Without it, a broken path of one family costs an [address timeout](ConnectConfig::address_timeout) before the next
+    /// address is tried. RFC 8305 recommends a stagger of 0.25 seconds. The client's transceiver has to reach both families,
+    /// e.g. a [dual-stack socket](crate::NetcodeSocket::dual_stack). The default is to try the addresses one at a time.
+    pub fn happy_eyeballs(mut self, stagger_seconds: f64) -> Self {
+        self.happy_eyeballs = Some(stagger_seconds);
+        self
+    }
+}
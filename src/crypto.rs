@@ -1,26 +1,40 @@
-use byteorder::{LittleEndian, WriteBytesExt};
+use crate::{
+    io::{self, WriteBytesExt},
+    MAC_BYTES, PRIVATE_KEY_BYTES,
+};
+use byteorder::LittleEndian;
 use chacha20poly1305::{
-    aead::{rand_core::RngCore, OsRng},
+    aead::{
+        rand_core::{CryptoRng, RngCore},
+        OsRng,
+    },
     AeadInPlace, ChaCha20Poly1305, KeyInit, Tag, XChaCha20Poly1305, XNonce,
 };
-use std::io;
-
-use crate::{MAC_BYTES, PRIVATE_KEY_BYTES};
+use zeroize::Zeroizing;
 
+/// An error encrypting, decrypting or generating keys, see [`Error::Crypto`](crate::Error::Crypto).
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error(transparent)]
-    Io(#[from] std::io::Error),
+    Io(#[from] io::Error),
     #[error("buffer size mismatch")]
     BufferSizeMismatch,
     #[error("failed to encrypt: {0}")]
-    Failed(#[from] chacha20poly1305::aead::Error),
+    Failed(#[cfg_attr(feature = "std", source)] chacha20poly1305::aead::Error),
     #[error("failed to generate key: {0}")]
     GenerateKey(chacha20poly1305::aead::rand_core::Error),
 }
 /// A 32-byte array, used as a key for encrypting and decrypting packets and connect tokens.
 pub type Key = [u8; crate::PRIVATE_KEY_BYTES];
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
+
+// `aead::Error` only implements `Error` with `std`, so it can't always be the source of `Failed`
+impl From<chacha20poly1305::aead::Error> for Error {
+    fn from(error: chacha20poly1305::aead::Error) -> Self {
+        Error::Failed(error)
+    }
+}
 
 /// Generates a random key for encrypting and decrypting packets and connect tokens.
 ///
@@ -35,8 +49,24 @@ pub type Result<T> = std::result::Result<T, Error>;
 /// assert_eq!(key.len(), 32);
 /// ```
 pub fn generate_key() -> Key {
+    generate_key_with(&mut OsRng)
+}
+/// Generates a key with the given RNG instead of the operating system's, e.g. a seeded one for reproducible tests.
+///
+/// Panics if the RNG fails. For a non-panicking version, see [`try_generate_key_with`](fn.try_generate_key_with.html).
+///
+/// # Example
+/// ```
+/// # #[cfg(feature = "testing")] {
+/// use netcode::testing::SeededRng;
+///
+/// let key = netcode::generate_key_with(&mut SeededRng::new(7));
+/// assert_eq!(key, netcode::generate_key_with(&mut SeededRng::new(7)));
+/// # }
+/// ```
+pub fn generate_key_with<R: CryptoRng + RngCore + ?Sized>(rng: &mut R) -> Key {
     let mut key: Key = [0; PRIVATE_KEY_BYTES];
-    OsRng.fill_bytes(&mut key);
+    rng.fill_bytes(&mut key);
     key
 }
 /// The fallible version of [`generate_key`](fn.generate_key.html).
@@ -51,26 +81,166 @@ pub fn generate_key() -> Key {
 /// assert_eq!(key.len(), 32);
 /// ```
 pub fn try_generate_key() -> Result<Key> {
+    try_generate_key_with(&mut OsRng)
+}
+/// The fallible version of [`generate_key_with`](fn.generate_key_with.html).
+///
+/// Returns an error if the RNG fails.
+pub fn try_generate_key_with<R: CryptoRng + RngCore + ?Sized>(rng: &mut R) -> Result<Key> {
     let mut key: Key = [0; PRIVATE_KEY_BYTES];
-    OsRng.try_fill_bytes(&mut key).map_err(Error::GenerateKey)?;
+    rng.try_fill_bytes(&mut key).map_err(Error::GenerateKey)?;
     Ok(key)
 }
 
+/// The well-known private key insecure connect tokens are encrypted with, see the `insecure` feature.
+#[cfg(feature = "insecure")]
+pub(crate) const INSECURE_KEY: Key = *b"netcode insecure development key";
+
+/// Derives a key for another purpose from `key`, as the ChaCha20 keystream of `key` with a nonce made of `label` and `context`.
+pub fn derive_key(key: &Key, label: &[u8; 16], context: u64) -> Result<Key> {
+    let mut nonce = [0; 24];
+    nonce[..16].copy_from_slice(label);
+    nonce[16..].copy_from_slice(&context.to_le_bytes());
+    let mut derived: Key = [0; PRIVATE_KEY_BYTES];
+    XChaCha20Poly1305::new(key.into()).encrypt_in_place_detached(
+        XNonce::from_slice(&nonce),
+        &[],
+        &mut derived,
+    )?;
+    Ok(derived)
+}
+
+/// Derives the key of a session epoch from the session's base key, see the `rekey` module. <br>
+/// Epoch 0 uses the base key itself.
+pub fn session_key(key: &Key, epoch: u64) -> Result<Key> {
+    if epoch == 0 {
+        return Ok(*key);
+    }
+    derive_key(key, b"netcode rekey\0\0\0", epoch)
+}
+
+/// A session key with the AEAD of its latest epoch set up, see the `rekey` module. <br>
+/// Packets of that epoch are encrypted and decrypted without deriving the epoch's key and setting up the AEAD again,
+/// which [`chacha_encrypt`](chacha_encrypt) and [`chacha_decrypt`](chacha_decrypt) do for every packet.
+pub struct Cipher {
+    key: Zeroizing<Key>,
+    epoch: u64,
+    aead: ChaCha20Poly1305,
+    rekeying: bool,
+}
+
+impl Cipher {
+    pub fn new(key: Key) -> Self {
+        Self {
+            aead: ChaCha20Poly1305::new((&key).into()),
+            key: Zeroizing::new(key),
+            epoch: 0,
+            rekeying: false,
+        }
+    }
+    /// A cipher for a session with rekeying enabled, whose packets are encrypted with the key of their epoch.
+    pub fn with_rekeying(key: Key, rekeying: bool) -> Self {
+        Self {
+            rekeying,
+            ..Self::new(key)
+        }
+    }
+    /// Whether packets of later epochs use derived keys, otherwise all packets use the session key.
+    pub fn is_rekeying(&self) -> bool {
+        self.rekeying
+    }
+    /// The session key, i.e. the key of epoch 0.
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+    pub fn encrypt(
+        &mut self,
+        buf: &mut [u8],
+        associated_data: Option<&[u8]>,
+        nonce: u64,
+        epoch: u64,
+    ) -> Result<()> {
+        self.with_aead(epoch, |aead| seal(aead, buf, associated_data, nonce))
+    }
+    pub fn decrypt(
+        &mut self,
+        buf: &mut [u8],
+        associated_data: Option<&[u8]>,
+        nonce: u64,
+        epoch: u64,
+    ) -> Result<()> {
+        self.with_aead(epoch, |aead| open(aead, buf, associated_data, nonce))
+    }
+    // A later epoch replaces the one that is set up once `f` succeeds, so a forged packet with a far-off sequence
+    // can't move the cipher to an epoch the peer never uses. Packets of an earlier one (reordered around a rekey) get a temporary AEAD.
+    fn with_aead(
+        &mut self,
+        epoch: u64,
+        f: impl FnOnce(&ChaCha20Poly1305) -> Result<()>,
+    ) -> Result<()> {
+        if epoch == self.epoch {
+            return f(&self.aead);
+        }
+        let epoch_key = Zeroizing::new(session_key(&self.key, epoch)?);
+        let aead = ChaCha20Poly1305::new(epoch_key.as_ref().into());
+        f(&aead)?;
+        if epoch > self.epoch {
+            self.aead = aead;
+            self.epoch = epoch;
+        }
+        Ok(())
+    }
+}
+
+impl core::fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Cipher")
+            .field("epoch", &self.epoch)
+            .finish_non_exhaustive()
+    }
+}
+
 pub fn chacha_encrypt(
     buf: &mut [u8],
     associated_data: Option<&[u8]>,
     nonce: u64,
     key: &Key,
+) -> Result<()> {
+    seal(
+        &ChaCha20Poly1305::new(key.into()),
+        buf,
+        associated_data,
+        nonce,
+    )
+}
+
+pub fn chacha_decrypt(
+    buf: &mut [u8],
+    associated_data: Option<&[u8]>,
+    nonce: u64,
+    key: &Key,
+) -> Result<()> {
+    open(
+        &ChaCha20Poly1305::new(key.into()),
+        buf,
+        associated_data,
+        nonce,
+    )
+}
+
+fn seal(
+    aead: &ChaCha20Poly1305,
+    buf: &mut [u8],
+    associated_data: Option<&[u8]>,
+    nonce: u64,
 ) -> Result<()> {
     let size = buf.len();
     if size < MAC_BYTES {
         // Should have 16 bytes of extra space for the MAC
         return Err(Error::BufferSizeMismatch);
     }
-    let mut final_nonce = [0; 12];
-    io::Cursor::new(&mut final_nonce[4..]).write_u64::<LittleEndian>(nonce)?;
-    let mac = ChaCha20Poly1305::new(key.into()).encrypt_in_place_detached(
-        &final_nonce.into(),
+    let mac = aead.encrypt_in_place_detached(
+        &chacha_nonce(nonce)?.into(),
         associated_data.unwrap_or_default(),
         &mut buf[..size - MAC_BYTES],
     )?;
@@ -78,21 +248,19 @@ pub fn chacha_encrypt(
     Ok(())
 }
 
-pub fn chacha_decrypt(
+fn open(
+    aead: &ChaCha20Poly1305,
     buf: &mut [u8],
     associated_data: Option<&[u8]>,
     nonce: u64,
-    key: &Key,
 ) -> Result<()> {
     if buf.len() < MAC_BYTES {
         // Should already include the MAC
         return Err(Error::BufferSizeMismatch);
     }
-    let mut final_nonce = [0; 12];
-    io::Cursor::new(&mut final_nonce[4..]).write_u64::<LittleEndian>(nonce)?;
     let (buf, mac) = buf.split_at_mut(buf.len() - MAC_BYTES);
-    ChaCha20Poly1305::new(key.into()).decrypt_in_place_detached(
-        &final_nonce.into(),
+    aead.decrypt_in_place_detached(
+        &chacha_nonce(nonce)?.into(),
         associated_data.unwrap_or_default(),
         buf,
         Tag::from_slice(mac),
@@ -100,6 +268,12 @@ pub fn chacha_decrypt(
     Ok(())
 }
 
+fn chacha_nonce(nonce: u64) -> Result<[u8; 12]> {
+    let mut final_nonce = [0; 12];
+    io::Cursor::new(&mut final_nonce[4..]).write_u64::<LittleEndian>(nonce)?;
+    Ok(final_nonce)
+}
+
 pub fn xchacha_encrypt(
     buf: &mut [u8],
     associated_data: Option<&[u8]>,
@@ -165,4 +339,50 @@ mod tests {
 
         chacha_decrypt(&mut buf, None, nonce, &key).unwrap();
     }
+
+    #[test]
+    fn session_keys_differ_per_epoch() {
+        let key = generate_key();
+        assert_eq!(session_key(&key, 0).unwrap(), key);
+        let first = session_key(&key, 1).unwrap();
+        assert_ne!(first, key);
+        assert_ne!(first, session_key(&key, 2).unwrap());
+        // both sides derive the same key
+        assert_eq!(first, session_key(&key, 1).unwrap());
+    }
+
+    #[test]
+    fn cipher_follows_epochs() {
+        let key = generate_key();
+        let mut cipher = Cipher::new(key);
+        for epoch in [0, 2, 1, 2, 0, 3] {
+            let mut buf = [7u8; 40];
+            cipher.encrypt(&mut buf, Some(b"aad"), 9, epoch).unwrap();
+            // the same as encrypting with the epoch's key on its own
+            let mut expected = [7u8; 40];
+            chacha_encrypt(
+                &mut expected,
+                Some(b"aad"),
+                9,
+                &session_key(&key, epoch).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(buf, expected);
+            cipher.decrypt(&mut buf, Some(b"aad"), 9, epoch).unwrap();
+            assert_eq!(buf[..24], [7; 24]);
+        }
+        // the latest epoch stays set up
+        assert_eq!(cipher.epoch, 3);
+        let mut buf = [0u8; 40];
+        cipher.encrypt(&mut buf, None, 1, 3).unwrap();
+        assert!(cipher.decrypt(&mut buf, None, 1, 2).is_err());
+    }
+
+    #[test]
+    fn forged_epoch_does_not_move_cipher() {
+        let mut cipher = Cipher::with_rekeying(generate_key(), true);
+        let mut forged = [7u8; 40];
+        assert!(cipher.decrypt(&mut forged, None, 1, 1 << 20).is_err());
+        assert_eq!(cipher.epoch, 0);
+    }
 }
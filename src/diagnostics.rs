@@ -0,0 +1,309 @@
+use alloc::{vec, vec::Vec};
+use core::mem::size_of;
+
+use byteorder::LittleEndian;
+
+use crate::{
+    io::{ReadBytesExt, WriteBytesExt},
+    MAX_PACKET_SIZE,
+};
+
+const PROBE_MAGIC: &[u8; 4] = b"NCLK";
+const PROBE_HEADER_SIZE: usize = PROBE_MAGIC.len() + size_of::<u32>() + size_of::<f64>();
+const TIMESTAMP_SIZE: usize = size_of::<f64>();
+
+/// How a server in diagnostics mode treats the payloads it receives.
+///
+/// Set with [`ServerConfig::echo_mode`](crate::ServerConfig::echo_mode).
+///
+/// While echoing, received payloads are sent straight back to the client they came from
+/// and are **not** queued for [`Server::recv`](crate::Server::recv).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum EchoMode {
+    /// Payloads are delivered to the application as usual (the default).
+    #[default]
+    Off,
+    /// Payloads are echoed back unchanged.
+    Echo,
+    /// Payloads are echoed back with the server time (an `f64` in little-endian, 8 bytes) appended,
+    /// as long as the result still fits in the server's [max payload size](crate::ServerConfig::max_payload_size).
+    EchoWithTimestamp,
+}
+
+impl EchoMode {
+    #[cfg(any(test, feature = "std"))]
+    pub(crate) fn echo(
+        self,
+        payload: &[u8],
+        server_time: f64,
+        max_payload_size: usize,
+    ) -> Option<Vec<u8>> {
+        let mut echoed = match self {
+            EchoMode::Off => return None,
+            EchoMode::Echo | EchoMode::EchoWithTimestamp => payload.to_vec(),
+        };
+        if self == EchoMode::EchoWithTimestamp && payload.len() + TIMESTAMP_SIZE <= max_payload_size
+        {
+            echoed.extend_from_slice(&server_time.to_le_bytes());
+        }
+        Some(echoed)
+    }
+}
+
+/// Configuration for a client link check.
+///
+/// A link check sends a burst of probe payloads to a server running in [`EchoMode`] diagnostics mode,
+/// and measures round-trip time, loss and effective throughput from the echoed probes.
+///
+/// # Example
+/// ```
+/// use netcode::LinkCheckConfig;
+///
+/// let cfg = LinkCheckConfig::new()
+///     .num_probes(50)
+///     .probe_size(1000)
+///     .probe_interval(0.01)
+///     .timeout(2.0);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct LinkCheckConfig {
+    num_probes: u32,
+    probe_size: usize,
+    probe_interval: f64,
+    timeout: f64,
+}
+
+impl Default for LinkCheckConfig {
+    fn default() -> Self {
+        Self {
+            num_probes: 100,
+            probe_size: 256,
+            probe_interval: 1.0 / 60.0,
+            timeout: 1.0,
+        }
+    }
+}
+
+impl LinkCheckConfig {
+    /// Create a new, default link check configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Set the number of probes that will be sent. <br>
+    /// The default is 100 probes.
+    pub fn num_probes(mut self, num_probes: u32) -> Self {
+        self.num_probes = num_probes;
+        self
+    }
+    /// Set the size of each probe payload in bytes, clamped between the probe header size (16 bytes) and
+    /// the client's [max payload size](crate::ClientConfig::max_payload_size) minus room for the server timestamp. <br>
+    /// The default is 256 bytes.
+    pub fn probe_size(mut self, probe_size: usize) -> Self {
+        self.probe_size = probe_size.clamp(PROBE_HEADER_SIZE, MAX_PACKET_SIZE - TIMESTAMP_SIZE);
+        self
+    }
+    /// Set the interval (in seconds) between consecutive probes. <br>
+    /// Probes are sent from [`Client::update`](crate::Client::update), so the effective interval is never shorter than the update rate.
+    /// The default is `1/60` seconds.
+    pub fn probe_interval(mut self, interval_seconds: f64) -> Self {
+        self.probe_interval = interval_seconds;
+        self
+    }
+    /// Set how long (in seconds) to wait for echoes after the last probe was sent. <br>
+    /// Probes that were not echoed back by then are counted as lost.
+    /// The default is `1.0` seconds.
+    pub fn timeout(mut self, timeout_seconds: f64) -> Self {
+        self.timeout = timeout_seconds;
+        self
+    }
+}
+
+/// The result of a completed link check.
+///
+/// All times are in seconds and are measured with the time passed to [`Client::update`](crate::Client::update),
+/// so their resolution is bounded by the client's update rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct LinkCheckReport {
+    /// The number of probes sent.
+    pub sent: u32,
+    /// The number of distinct probes echoed back by the server.
+    pub received: u32,
+    /// The percentage of probes that were never echoed back.
+    pub loss_percent: f64,
+    /// The smallest observed round-trip time.
+    pub rtt_min: f64,
+    /// The average observed round-trip time.
+    pub rtt_avg: f64,
+    /// The largest observed round-trip time.
+    pub rtt_max: f64,
+    /// The number of echoed bytes received per second, from the first probe sent until the last echo received.
+    pub throughput_bytes_per_sec: f64,
+    /// The server time stamped on the most recent echo, minus the estimated client time at which it was stamped.
+    ///
+    /// Only available if the server uses [`EchoMode::EchoWithTimestamp`].
+    pub server_time_offset: Option<f64>,
+}
+
+pub(crate) struct LinkCheck {
+    cfg: LinkCheckConfig,
+    start_time: f64,
+    next_probe: u32,
+    last_probe_time: f64,
+    received: Vec<bool>,
+    received_bytes: usize,
+    last_receive_time: f64,
+    rtts: Vec<f64>,
+    server_time_offset: Option<f64>,
+}
+
+impl LinkCheck {
+    pub(crate) fn new(mut cfg: LinkCheckConfig, time: f64, max_payload_size: usize) -> Self {
+        cfg.probe_size = cfg
+            .probe_size
+            .min(max_payload_size.saturating_sub(TIMESTAMP_SIZE))
+            .max(PROBE_HEADER_SIZE);
+        Self {
+            cfg,
+            start_time: time,
+            next_probe: 0,
+            last_probe_time: f64::NEG_INFINITY,
+            received: vec![false; cfg.num_probes as usize],
+            received_bytes: 0,
+            last_receive_time: time,
+            rtts: Vec::with_capacity(cfg.num_probes as usize),
+            server_time_offset: None,
+        }
+    }
+    /// Returns the next probe to send, if one is due at `time`.
+    pub(crate) fn next_probe(&mut self, time: f64) -> Option<Vec<u8>> {
+        if self.next_probe >= self.cfg.num_probes
+            || self.last_probe_time + self.cfg.probe_interval > time
+        {
+            return None;
+        }
+        let mut probe = Vec::with_capacity(self.cfg.probe_size);
+        probe.extend_from_slice(PROBE_MAGIC);
+        probe.write_u32::<LittleEndian>(self.next_probe).ok()?;
+        probe.write_f64::<LittleEndian>(time).ok()?;
+        probe.resize(self.cfg.probe_size, 0);
+        self.next_probe += 1;
+        self.last_probe_time = time;
+        Some(probe)
+    }
+    /// Consumes an echoed probe, returns `false` if the payload is not one of ours.
+    pub(crate) fn process_echo(&mut self, payload: &[u8], time: f64) -> bool {
+        if payload.len() < self.cfg.probe_size || !payload.starts_with(PROBE_MAGIC) {
+            return false;
+        }
+        let mut reader = &payload[PROBE_MAGIC.len()..];
+        let (Ok(id), Ok(sent_time)) = (
+            reader.read_u32::<LittleEndian>(),
+            reader.read_f64::<LittleEndian>(),
+        ) else {
+            return false;
+        };
+        let Some(received) = self.received.get_mut(id as usize) else {
+            return false;
+        };
+        if *received {
+            // duplicate echo, still ours
+            return true;
+        }
+        *received = true;
+        let rtt = time - sent_time;
+        self.rtts.push(rtt);
+        self.received_bytes += payload.len();
+        self.last_receive_time = time;
+        if payload.len() == self.cfg.probe_size + TIMESTAMP_SIZE {
+            let mut timestamp = &payload[self.cfg.probe_size..];
+            if let Ok(server_time) = timestamp.read_f64::<LittleEndian>() {
+                self.server_time_offset = Some(server_time - (sent_time + rtt / 2.0));
+            }
+        }
+        true
+    }
+    pub(crate) fn is_done(&self, time: f64) -> bool {
+        let all_sent = self.next_probe >= self.cfg.num_probes;
+        let all_received = self.rtts.len() == self.received.len();
+        all_sent && (all_received || self.last_probe_time + self.cfg.timeout <= time)
+    }
+    pub(crate) fn report(&self) -> LinkCheckReport {
+        let sent = self.next_probe;
+        let received = self.rtts.len() as u32;
+        let loss_percent = if sent == 0 {
+            0.0
+        } else {
+            100.0 * (sent - received) as f64 / sent as f64
+        };
+        let (rtt_min, rtt_max) = self
+            .rtts
+            .iter()
+            .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &rtt| {
+                (min.min(rtt), max.max(rtt))
+            });
+        let (rtt_min, rtt_avg, rtt_max) = if self.rtts.is_empty() {
+            (0.0, 0.0, 0.0)
+        } else {
+            let avg = self.rtts.iter().sum::<f64>() / self.rtts.len() as f64;
+            (rtt_min, avg, rtt_max)
+        };
+        let elapsed = self.last_receive_time - self.start_time;
+        let throughput_bytes_per_sec = if elapsed > 0.0 {
+            self.received_bytes as f64 / elapsed
+        } else {
+            0.0
+        };
+        LinkCheckReport {
+            sent,
+            received,
+            loss_percent,
+            rtt_min,
+            rtt_avg,
+            rtt_max,
+            throughput_bytes_per_sec,
+            server_time_offset: self.server_time_offset,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_check_probe_round_trip() {
+        let cfg = LinkCheckConfig::new()
+            .num_probes(2)
+            .probe_size(32)
+            .probe_interval(0.1);
+        let mut link_check = LinkCheck::new(cfg, 0.0, MAX_PACKET_SIZE);
+
+        let first = link_check.next_probe(0.0).unwrap();
+        assert_eq!(first.len(), 32);
+        assert!(link_check.next_probe(0.05).is_none()); // interval not elapsed yet
+        let second = link_check.next_probe(0.1).unwrap();
+        assert!(link_check.next_probe(0.2).is_none()); // all probes sent
+
+        let echoed = EchoMode::EchoWithTimestamp
+            .echo(&first, 10.0, MAX_PACKET_SIZE)
+            .unwrap();
+        assert_eq!(echoed.len(), 32 + TIMESTAMP_SIZE);
+        assert!(link_check.process_echo(&echoed, 0.2));
+        assert!(link_check.process_echo(&echoed, 0.3)); // duplicate
+        assert!(!link_check.process_echo(b"not a probe", 0.3));
+        assert!(!link_check.is_done(0.3));
+        let echoed = EchoMode::Echo.echo(&second, 10.0, MAX_PACKET_SIZE).unwrap();
+        assert!(link_check.process_echo(&echoed, 0.4));
+        assert!(link_check.is_done(0.4));
+
+        let report = link_check.report();
+        assert_eq!(report.sent, 2);
+        assert_eq!(report.received, 2);
+        assert_eq!(report.loss_percent, 0.0);
+        assert!((report.rtt_min - 0.2).abs() < 1e-9);
+        assert!((report.rtt_max - 0.3).abs() < 1e-9);
+        assert!((report.server_time_offset.unwrap() - 9.9).abs() < 1e-9);
+    }
+}
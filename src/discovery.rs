@@ -0,0 +1,435 @@
+//! LAN discovery, for local games that don't have a matchmaker to find servers and issue connect tokens.
+//!
+//! A server advertises itself with a [`Beacon`](Beacon), which broadcasts a [`ServerAd`](ServerAd) on the local network
+//! every [`interval`](BeaconConfig::interval). Clients collect the ads with [`discover`](discover) to fill a LAN game list,
+//! and can then ask the host for a connect token with [`request_token`](request_token), if the host
+//! [issues tokens](BeaconConfig::issue_tokens).
+//!
+//! Nothing here is encrypted or authenticated: ads can be spoofed and tokens are sent in the clear,
+//! so only use it on networks where every machine is trusted (a couch or an office), never on the internet. <br>
+//! The server has to be bound to its address on the local network (not `0.0.0.0`), since that is the address
+//! other machines connect to and the address its connect tokens are issued for.
+//!
+//! # Example
+//! ```
+//! use netcode::discovery::{self, Beacon, BeaconConfig, Listener};
+//! use netcode::{Client, Server};
+//! use std::{thread, time::{Duration, Instant}};
+//!
+//! // the host advertises its game next to the server, a real game broadcasts to the default address
+//! let cfg = BeaconConfig::new("Alice's game")
+//!     .interval(0.05)
+//!     .broadcast_addr(([127, 0, 0, 1], 40100).into())
+//!     .issue_tokens(true);
+//! let mut listener = Listener::bind(40100).unwrap();
+//! thread::spawn(move || {
+//!     let mut server = Server::new("127.0.0.1:0", 0x11223344, netcode::generate_key()).unwrap();
+//!     let mut beacon = Beacon::new(cfg).unwrap();
+//!     let start = Instant::now();
+//!     while start.elapsed() < Duration::from_secs(2) {
+//!         let time = start.elapsed().as_secs_f64();
+//!         server.update(time);
+//!         beacon.update(&mut server, time).unwrap();
+//!         thread::sleep(Duration::from_millis(10));
+//!     }
+//! });
+//!
+//! // the other machines list the games on the network (`discovery::discover` listens on the default port)...
+//! let ads = listener.recv_ads(Duration::from_millis(200)).unwrap();
+//! let ad = &ads[0];
+//! assert_eq!(ad.name, "Alice's game");
+//!
+//! // ...and join one
+//! let token = discovery::request_token(ad, 123, Duration::from_secs(1)).unwrap();
+//! let client = Client::new(&token).unwrap();
+//! ```
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::{
+    error::Result, query, server::Server, transceiver::Transceiver, CONNECT_TOKEN_BYTES,
+    NETCODE_VERSION,
+};
+
+/// The port beacons are broadcast to by default.
+pub const DISCOVERY_PORT: u16 = 40999;
+
+const AD: u8 = 0;
+const TOKEN_REQUEST: u8 = 1;
+const TOKEN: u8 = 2;
+const HEADER_BYTES: usize = NETCODE_VERSION.len() + 1;
+const MAX_AD_BYTES: usize = HEADER_BYTES + 8 + 17 + 2 + 2 * 2 + 1 + query::MAX_SERVER_NAME_BYTES;
+const TOKEN_REQUEST_BYTES: usize = HEADER_BYTES + 2 * 8;
+const TOKEN_RESPONSE_BYTES: usize = HEADER_BYTES + 8 + CONNECT_TOKEN_BYTES;
+
+/// Configuration for a [`Beacon`](Beacon).
+///
+/// * `name` - The name of the game shown in LAN game lists, at most [`MAX_SERVER_NAME_BYTES`](crate::query::MAX_SERVER_NAME_BYTES).
+/// * `interval` - How often the ad is broadcast.
+/// * `broadcast_addr` - Where the ad is sent.
+/// * `issue_tokens` - Whether the host answers [`request_token`](request_token).
+///
+/// # Example
+/// ```
+/// use netcode::discovery::BeaconConfig;
+///
+/// let cfg = BeaconConfig::new("Alice's game").interval(0.5).issue_tokens(true);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeaconConfig {
+    name: String,
+    interval: f64,
+    broadcast_addr: SocketAddr,
+    issue_tokens: bool,
+}
+
+impl BeaconConfig {
+    /// Create a configuration that advertises a game called `name`.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: query::truncate_name(name),
+            interval: 1.0,
+            broadcast_addr: SocketAddr::from((Ipv4Addr::BROADCAST, DISCOVERY_PORT)),
+            issue_tokens: false,
+        }
+    }
+    /// Set the interval (in seconds) at which the ad is broadcast. <br>
+    /// The default is 1 second.
+    pub fn interval(mut self, interval_seconds: f64) -> Self {
+        self.interval = interval_seconds;
+        self
+    }
+    /// Set the address the ad is sent to, e.g. the broadcast address of one subnet, or a multicast group. <br>
+    /// The default is `255.255.255.255:`[`DISCOVERY_PORT`](DISCOVERY_PORT).
+    pub fn broadcast_addr(mut self, addr: SocketAddr) -> Self {
+        self.broadcast_addr = addr;
+        self
+    }
+    /// Set whether the host issues a connect token to anyone on the network who asks with [`request_token`](request_token). <br>
+    /// The default is `false`, the host has to hand out tokens some other way.
+    pub fn issue_tokens(mut self, issue_tokens: bool) -> Self {
+        self.issue_tokens = issue_tokens;
+        self
+    }
+}
+
+/// A server's ad, as received by [`discover`](discover).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServerAd {
+    /// The address of the server.
+    pub addr: SocketAddr,
+    /// The address of the host's beacon, which [`request_token`](request_token) asks for a token.
+    pub beacon_addr: SocketAddr,
+    /// The name of the game.
+    pub name: String,
+    /// The protocol id of the server.
+    pub protocol_id: u64,
+    /// The number of connected clients.
+    pub num_clients: usize,
+    /// The maximum number of clients.
+    pub max_clients: usize,
+}
+
+/// Broadcasts a server's ad on the local network and answers token requests, see the [module](self) docs.
+#[derive(Debug)]
+pub struct Beacon {
+    socket: UdpSocket,
+    cfg: BeaconConfig,
+    next_ad_time: Option<f64>,
+}
+
+impl Beacon {
+    /// Creates a beacon with its own socket, bound to an ephemeral port.
+    pub fn new(cfg: BeaconConfig) -> Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            cfg,
+            next_ad_time: None,
+        })
+    }
+    /// Gets the local `SocketAddr` this beacon is bound to.
+    pub fn addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+    /// Broadcasts the server's ad if the interval passed since the last one, and answers token requests with tokens from
+    /// [`Server::token`](Server::token). Call it with the same time as [`Server::update`](Server::update).
+    pub fn update<T: Transceiver, Ctx>(
+        &mut self,
+        server: &mut Server<T, Ctx>,
+        time: f64,
+    ) -> Result<()> {
+        if self.next_ad_time.is_none_or(|next| time >= next) {
+            let ad = self.write_ad(server);
+            if let Err(e) = self.socket.send_to(&ad, self.cfg.broadcast_addr) {
+                log::debug!(
+                    "beacon failed to send ad to {}: {e}",
+                    self.cfg.broadcast_addr
+                );
+            }
+            self.next_ad_time = Some(time + self.cfg.interval);
+        }
+        let mut buf = [0; TOKEN_REQUEST_BYTES + 1];
+        loop {
+            let (len, addr) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                // an ICMP port unreachable from an earlier response, on Windows
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(e) => return Err(e.into()),
+            };
+            let Some((client_id, nonce)) = read_token_request(&buf[..len]) else {
+                log::debug!("beacon ignored invalid packet from {addr}");
+                continue;
+            };
+            if !self.cfg.issue_tokens {
+                log::debug!("beacon ignored token request from {addr}: tokens are not issued");
+                continue;
+            }
+            let token = server.token(client_id).generate()?.try_into_bytes()?;
+            let mut response = [0; TOKEN_RESPONSE_BYTES];
+            write_header(&mut response, TOKEN);
+            response[HEADER_BYTES..HEADER_BYTES + 8].copy_from_slice(&nonce.to_le_bytes());
+            response[HEADER_BYTES + 8..].copy_from_slice(&token);
+            log::debug!("beacon issued a token for client {client_id} to {addr}");
+            self.socket.send_to(&response, addr)?;
+        }
+    }
+    fn write_ad<T: Transceiver, Ctx>(&self, server: &Server<T, Ctx>) -> Vec<u8> {
+        let mut ad = Vec::with_capacity(MAX_AD_BYTES);
+        ad.resize(HEADER_BYTES, 0);
+        write_header(&mut ad, AD);
+        let addr = server.addr();
+        ad.extend_from_slice(
+            &server
+                .protocol_ids()
+                .next()
+                .unwrap_or_default()
+                .to_le_bytes(),
+        );
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                ad.push(4);
+                ad.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                ad.push(6);
+                ad.extend_from_slice(&ip.octets());
+            }
+        }
+        ad.extend_from_slice(&addr.port().to_le_bytes());
+        ad.extend_from_slice(&(server.num_connected_clients() as u16).to_le_bytes());
+        ad.extend_from_slice(&(server.max_clients() as u16).to_le_bytes());
+        ad.push(self.cfg.name.len() as u8);
+        ad.extend_from_slice(self.cfg.name.as_bytes());
+        ad
+    }
+}
+
+/// A socket that receives the ads of beacons, for games that keep listening while the LAN game list is shown
+/// rather than calling [`discover`](discover) again.
+#[derive(Debug)]
+pub struct Listener {
+    socket: UdpSocket,
+}
+
+impl Listener {
+    /// Binds a listener to `port` on all interfaces, [`DISCOVERY_PORT`](DISCOVERY_PORT) for beacons with the default configuration.
+    /// Several listeners on one machine can bind the same port.
+    pub fn bind(port: u16) -> Result<Self> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_broadcast(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)).into())?;
+        Ok(Self {
+            socket: socket.into(),
+        })
+    }
+    /// Receives ads for `timeout`, returning one ad per server, with the latest player counts.
+    pub fn recv_ads(&mut self, timeout: Duration) -> Result<Vec<ServerAd>> {
+        let deadline = Instant::now() + timeout;
+        let mut ads: Vec<ServerAd> = Vec::new();
+        let mut buf = [0; MAX_AD_BYTES + 1];
+        while let Some(remaining) = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+        {
+            self.socket.set_read_timeout(Some(remaining))?;
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let Some(ad) = read_ad(&buf[..len], from) else {
+                log::debug!("ignored invalid ad from {from}");
+                continue;
+            };
+            match ads.iter_mut().find(|known| known.addr == ad.addr) {
+                Some(known) => *known = ad,
+                None => ads.push(ad),
+            }
+        }
+        Ok(ads)
+    }
+}
+
+/// Listens for beacons on [`DISCOVERY_PORT`](DISCOVERY_PORT) for `timeout`, returning the servers found on the local network.
+pub fn discover(timeout: Duration) -> Result<Vec<ServerAd>> {
+    Listener::bind(DISCOVERY_PORT)?.recv_ads(timeout)
+}
+
+/// Asks the host of `ad` for a connect token for `client_id`, to pass to [`Client::new`](crate::Client::new). <br>
+/// Fails with a [`TimedOut`](std::io::ErrorKind::TimedOut) error if the host doesn't answer within `timeout`,
+/// e.g. because it doesn't [issue tokens](BeaconConfig::issue_tokens).
+pub fn request_token(
+    ad: &ServerAd,
+    client_id: u64,
+    timeout: Duration,
+) -> Result<[u8; CONNECT_TOKEN_BYTES]> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let nonce = u64::from_le_bytes(
+        crate::crypto::generate_key()[..8]
+            .try_into()
+            .expect("8 bytes"),
+    );
+    let mut request = [0; TOKEN_REQUEST_BYTES];
+    write_header(&mut request, TOKEN_REQUEST);
+    request[HEADER_BYTES..HEADER_BYTES + 8].copy_from_slice(&client_id.to_le_bytes());
+    request[HEADER_BYTES + 8..].copy_from_slice(&nonce.to_le_bytes());
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0; TOKEN_RESPONSE_BYTES + 1];
+    // the request is resent, in case it or the response was lost
+    let resend_interval = Duration::from_millis(250);
+    while let Some(remaining) = deadline
+        .checked_duration_since(Instant::now())
+        .filter(|d| !d.is_zero())
+    {
+        socket.send_to(&request, ad.beacon_addr)?;
+        socket.set_read_timeout(Some(remaining.min(resend_interval)))?;
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let response = &buf[..len];
+        if from != ad.beacon_addr
+            || len != TOKEN_RESPONSE_BYTES
+            || read_header(response) != Some(TOKEN)
+            || response[HEADER_BYTES..HEADER_BYTES + 8] != nonce.to_le_bytes()
+        {
+            log::debug!("ignored invalid token response from {from}");
+            continue;
+        }
+        return Ok(response[HEADER_BYTES + 8..].try_into().expect("token size"));
+    }
+    Err(io::Error::new(io::ErrorKind::TimedOut, "the host didn't issue a token").into())
+}
+
+fn write_header(buf: &mut [u8], kind: u8) {
+    buf[..NETCODE_VERSION.len()].copy_from_slice(NETCODE_VERSION);
+    buf[NETCODE_VERSION.len()] = kind;
+}
+
+fn read_header(buf: &[u8]) -> Option<u8> {
+    if buf.len() < HEADER_BYTES || &buf[..NETCODE_VERSION.len()] != NETCODE_VERSION {
+        return None;
+    }
+    Some(buf[NETCODE_VERSION.len()])
+}
+
+fn read_token_request(buf: &[u8]) -> Option<(u64, u64)> {
+    if buf.len() != TOKEN_REQUEST_BYTES || read_header(buf)? != TOKEN_REQUEST {
+        return None;
+    }
+    let client_id = u64::from_le_bytes(buf[HEADER_BYTES..HEADER_BYTES + 8].try_into().ok()?);
+    let nonce = u64::from_le_bytes(buf[HEADER_BYTES + 8..].try_into().ok()?);
+    Some((client_id, nonce))
+}
+
+fn read_ad(buf: &[u8], from: SocketAddr) -> Option<ServerAd> {
+    if read_header(buf)? != AD {
+        return None;
+    }
+    let mut rest = &buf[HEADER_BYTES..];
+    let mut take = |n: usize| {
+        let (head, tail) = rest.split_at_checked(n)?;
+        rest = tail;
+        Some(head)
+    };
+    let protocol_id = u64::from_le_bytes(take(8)?.try_into().ok()?);
+    let ip = match take(1)?[0] {
+        4 => IpAddr::from(<[u8; 4]>::try_from(take(4)?).ok()?),
+        6 => IpAddr::from(<[u8; 16]>::try_from(take(16)?).ok()?),
+        _ => return None,
+    };
+    let port = u16::from_le_bytes(take(2)?.try_into().ok()?);
+    let num_clients = u16::from_le_bytes(take(2)?.try_into().ok()?) as usize;
+    let max_clients = u16::from_le_bytes(take(2)?.try_into().ok()?) as usize;
+    let name_len = take(1)?[0] as usize;
+    let name = std::str::from_utf8(take(name_len)?).ok()?.to_string();
+    // a server bound to all interfaces is reached at the address its beacon sent from
+    let ip = if ip.is_unspecified() { from.ip() } else { ip };
+    Some(ServerAd {
+        addr: SocketAddr::new(ip, port),
+        beacon_addr: from,
+        name,
+        protocol_id,
+        num_clients,
+        max_clients,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ad_roundtrip() {
+        let server = Server::new("127.0.0.1:0", 0x11, crate::generate_key()).unwrap();
+        let beacon = Beacon::new(BeaconConfig::new("lan")).unwrap();
+        let from = SocketAddr::from(([192, 168, 1, 2], 5000));
+        let ad = read_ad(&beacon.write_ad(&server), from).unwrap();
+        assert_eq!(ad.addr, server.addr());
+        assert_eq!(ad.beacon_addr, from);
+        assert_eq!(
+            (ad.name.as_str(), ad.protocol_id, ad.num_clients),
+            ("lan", 0x11, 0)
+        );
+        assert_eq!(ad.max_clients, server.max_clients());
+
+        // truncated and foreign packets are ignored
+        let buf = beacon.write_ad(&server);
+        assert!(read_ad(&buf[..buf.len() - 1], from).is_none());
+        assert!(read_ad(&[0; 64], from).is_none());
+
+        let server = Server::new("0.0.0.0:0", 0x11, crate::generate_key()).unwrap();
+        let ad = read_ad(&beacon.write_ad(&server), from).unwrap();
+        assert_eq!(ad.addr, SocketAddr::new(from.ip(), server.addr().port()));
+    }
+}
@@ -1,9 +1,45 @@
 use thiserror::Error;
 
 /// The result type for all the public methods that can return an error in this crate.
-pub type Result<T> = std::result::Result<T, Error>;
+pub type Result<T> = core::result::Result<T, Error>;
 
 /// An error that can occur in the `netcode` crate.
+///
+/// Besides its message, every error has a stable numeric [`code`](Error::code), and is categorized as
+/// [transient](Error::is_transient) or not, and as caused by a [remote](Error::is_remote) peer or locally.
+/// Decide how to handle an error with those rather than by its message, which may change.
+///
+/// | Code | Error |
+/// |------|-------|
+/// | 1 | [`SizeMismatch`](Error::SizeMismatch) |
+/// | 2 | [`ClientNotFound`](Error::ClientNotFound) |
+/// | 3 | [`ClientNotConnected`](Error::ClientNotConnected) |
+/// | 4 | `MaxClients` |
+/// | 5 | `ServerDropped` |
+/// | 6 | `SystemTime` |
+/// | 7 | [`InvalidKey`](Error::InvalidKey) |
+/// | 8 | `ServerFull` |
+/// | 101-109 | [`InvalidToken`](Error::InvalidToken): address list length, IP address type, timestamp, version, I/O, file magic, file version, file length, file checksum |
+/// | 200 | `Socket` |
+/// | 301-304 | [`Crypto`](Error::Crypto): I/O, buffer size, encryption or decryption, key generation |
+/// | 401-410 | [`Packet`](Error::Packet): type, sequence bytes, too small, too large, length, version, protocol id, expired token, already received, not allowed |
+/// | 500 | [`Io`](Error::Io) |
+/// | 600 | [`InvalidQuery`](Error::InvalidQuery) |
+/// | 601 | [`InvalidRelayHeader`](Error::InvalidRelayHeader) |
+/// | 701-705 | [`InvalidConfig`](Error::InvalidConfig): not positive, timeout too short, payload too small, padding bucket too large, no pending connections |
+///
+/// # Example
+/// ```
+/// # fn update(client: &mut netcode::Client<netcode::NetcodeSocket>) {
+/// if let Err(e) = client.try_update(0.0) {
+///     if e.is_transient() {
+///         // e.g. a full socket buffer, try again on the next tick
+///     } else {
+///         log::error!("client failed with error {}: {e}", e.code());
+///     }
+/// }
+/// # }
+/// ```
 #[derive(Error, Debug)]
 #[non_exhaustive]
 pub enum Error {
@@ -13,16 +49,218 @@ pub enum Error {
     ClientNotFound,
     #[error("tried to send a packet to a client that isn't connected")]
     ClientNotConnected,
+    #[cfg(feature = "std")]
+    #[error("max clients must be at most {max}, got {0}", max = i32::MAX)]
+    MaxClients(usize),
+    #[cfg(feature = "std")]
+    #[error("the server was dropped, queued calls can't be run")]
+    ServerDropped,
+    #[cfg(feature = "std")]
     #[error("clock went backwards (did you invent a time machine?): {0}")]
     SystemTime(#[from] std::time::SystemTimeError),
+    #[error("invalid key, expected 32 bytes as raw bytes or base64")]
+    InvalidKey,
+    #[cfg(feature = "std")]
+    #[error("the server is full, there is no slot left to reserve")]
+    ServerFull,
     #[error("invalid connect token: {0}")]
     InvalidToken(crate::token::InvalidTokenError),
+    #[cfg(all(feature = "std", not(target_family = "wasm")))]
     #[error(transparent)]
     Socket(#[from] crate::socket::Error),
     #[error(transparent)]
     Crypto(#[from] crate::crypto::Error),
+    #[error("invalid query response")]
+    InvalidQuery,
+    #[error("invalid relay header")]
+    InvalidRelayHeader,
     #[error("invalid packet: {0}")]
     Packet(#[from] crate::packet::Error),
     #[error(transparent)]
-    Io(#[from] std::io::Error),
+    Io(#[from] crate::io::Error),
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(#[from] crate::config::ConfigError),
+}
+
+impl Error {
+    /// A stable numeric code identifying the error, see the table in the [`Error`](Error) docs. <br>
+    /// Codes of errors are never reused or changed, and new errors get new codes.
+    pub fn code(&self) -> u16 {
+        use crate::{
+            config::ConfigError, crypto::Error as Crypto, packet::Error as Packet,
+            token::InvalidTokenError,
+        };
+        match self {
+            Error::SizeMismatch(..) => 1,
+            Error::ClientNotFound => 2,
+            Error::ClientNotConnected => 3,
+            #[cfg(feature = "std")]
+            Error::MaxClients(_) => 4,
+            #[cfg(feature = "std")]
+            Error::ServerDropped => 5,
+            #[cfg(feature = "std")]
+            Error::SystemTime(_) => 6,
+            Error::InvalidKey => 7,
+            #[cfg(feature = "std")]
+            Error::ServerFull => 8,
+            Error::InvalidToken(e) => match e {
+                InvalidTokenError::AddressListLength(_) => 101,
+                InvalidTokenError::InvalidIpAddressType(_) => 102,
+                InvalidTokenError::InvalidTimestamp => 103,
+                InvalidTokenError::InvalidVersion => 104,
+                InvalidTokenError::Io(_) => 105,
+                InvalidTokenError::InvalidMagic => 106,
+                InvalidTokenError::UnsupportedFileVersion(_) => 107,
+                InvalidTokenError::InvalidFileLength(_) => 108,
+                InvalidTokenError::ChecksumMismatch => 109,
+            },
+            #[cfg(all(feature = "std", not(target_family = "wasm")))]
+            Error::Socket(_) => 200,
+            Error::Crypto(e) => match e {
+                Crypto::Io(_) => 301,
+                Crypto::BufferSizeMismatch => 302,
+                Crypto::Failed(_) => 303,
+                Crypto::GenerateKey(_) => 304,
+            },
+            Error::Packet(e) => match e {
+                Packet::InvalidType(_) => 401,
+                Packet::InvalidSequenceBytes(_) => 402,
+                Packet::TooSmall => 403,
+                Packet::TooLarge => 404,
+                Packet::LengthMismatch { .. } => 405,
+                Packet::BadVersion => 406,
+                Packet::BadProtocolId { .. } => 407,
+                Packet::TokenExpired => 408,
+                Packet::AlreadyReceived(_) => 409,
+                Packet::NotAllowed(_) => 410,
+            },
+            Error::Io(_) => 500,
+            Error::InvalidQuery => 600,
+            Error::InvalidRelayHeader => 601,
+            Error::InvalidConfig(e) => match e {
+                ConfigError::NotPositive { .. } => 701,
+                ConfigError::TimeoutTooShort { .. } => 702,
+                ConfigError::PayloadTooSmall { .. } => 703,
+                ConfigError::PaddingBucketTooLarge { .. } => 704,
+                ConfigError::NoPendingConnections => 705,
+            },
+        }
+    }
+    /// Whether the error is caused by a temporary condition, so the same call can succeed if it is retried later
+    /// (e.g. a full socket buffer, an interrupted syscall or a clock that went backwards). <br>
+    /// Other errors fail the same way until something changes (the arguments, the configuration or the data received).
+    pub fn is_transient(&self) -> bool {
+        match self {
+            #[cfg(feature = "std")]
+            Error::SystemTime(_) | Error::ServerFull => true,
+            #[cfg(all(feature = "std", not(target_family = "wasm")))]
+            Error::Socket(e) => is_transient_io(e.io_error()),
+            #[cfg(feature = "std")]
+            Error::Io(e) => is_transient_io(e),
+            Error::Crypto(crate::crypto::Error::GenerateKey(_)) => true,
+            _ => false,
+        }
+    }
+    // Whether the error is a full send buffer, see `Transceiver::send`.
+    pub(crate) fn is_would_block(&self) -> bool {
+        match self {
+            #[cfg(all(feature = "std", not(target_family = "wasm")))]
+            Error::Socket(e) => e.io_error().kind() == std::io::ErrorKind::WouldBlock,
+            #[cfg(feature = "std")]
+            Error::Io(e) => e.kind() == std::io::ErrorKind::WouldBlock,
+            _ => false,
+        }
+    }
+    // Whether the error is an ICMP port unreachable for an earlier send, which `recv_from` reports
+    // as `WSAECONNRESET` on Windows and as `ECONNREFUSED` on connected sockets elsewhere.
+    pub(crate) fn is_port_unreachable(&self) -> bool {
+        match self {
+            #[cfg(all(feature = "std", not(target_family = "wasm")))]
+            Error::Socket(e) => is_port_unreachable_io(e.io_error()),
+            #[cfg(feature = "std")]
+            Error::Io(e) => is_port_unreachable_io(e),
+            _ => false,
+        }
+    }
+    // Whether the error is a failure of the socket itself that retrying won't fix, e.g. its network interface went down.
+    pub(crate) fn is_socket_failure(&self) -> bool {
+        match self {
+            #[cfg(all(feature = "std", not(target_family = "wasm")))]
+            Error::Socket(e) => !is_transient_io(e.io_error()),
+            #[cfg(feature = "std")]
+            Error::Io(e) => !is_transient_io(e),
+            _ => false,
+        }
+    }
+    /// Whether the error is caused by data received from a remote peer (an invalid, tampered or replayed packet,
+    /// connect token, query response or relay header), rather than by the local use of the crate or the OS. <br>
+    /// A remote error doesn't affect the local state, the offending data is dropped.
+    pub fn is_remote(&self) -> bool {
+        matches!(
+            self,
+            Error::InvalidToken(_)
+                | Error::Packet(_)
+                | Error::Crypto(crate::crypto::Error::Failed(_))
+                | Error::InvalidQuery
+                | Error::InvalidRelayHeader
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+fn is_transient_io(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        e.kind(),
+        ErrorKind::WouldBlock
+            | ErrorKind::Interrupted
+            | ErrorKind::TimedOut
+            | ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::HostUnreachable
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::OutOfMemory
+    )
+}
+
+#[cfg(feature = "std")]
+fn is_port_unreachable_io(e: &std::io::Error) -> bool {
+    use std::io::ErrorKind;
+    matches!(
+        e.kind(),
+        ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset
+    )
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{config::ConfigError, crypto, packet, token::InvalidTokenError};
+
+    #[test]
+    fn codes_and_categories() {
+        let errors = [
+            Error::SizeMismatch(1, 2),
+            Error::ClientNotFound,
+            Error::InvalidToken(InvalidTokenError::InvalidVersion),
+            Error::Crypto(crypto::Error::BufferSizeMismatch),
+            Error::Crypto(chacha20poly1305::aead::Error.into()),
+            Error::Packet(packet::Error::TooSmall),
+            Error::Packet(packet::Error::AlreadyReceived(3)),
+            Error::InvalidRelayHeader,
+            Error::InvalidConfig(ConfigError::NoPendingConnections),
+            Error::Io(std::io::Error::from(std::io::ErrorKind::WouldBlock)),
+            Error::Io(std::io::Error::from(std::io::ErrorKind::PermissionDenied)),
+        ];
+        let codes: Vec<u16> = errors.iter().map(Error::code).collect();
+        assert_eq!(codes, [1, 2, 104, 302, 303, 403, 409, 601, 705, 500, 500]);
+        let remote: Vec<bool> = errors.iter().map(Error::is_remote).collect();
+        assert_eq!(
+            remote,
+            [false, false, true, false, true, true, true, true, false, false, false]
+        );
+        let transient: Vec<_> = errors.iter().filter(|e| e.is_transient()).collect();
+        assert!(matches!(transient[..], [Error::Io(_)]));
+        assert_eq!(transient[0].code(), 500);
+    }
 }
@@ -37,13 +37,6 @@ impl<T: Sized, const N: usize> FreeList<T, N> {
         index
     }
 
-    pub fn remove(&mut self, index: usize) {
-        if self.inner[index].is_some() {
-            self.len -= 1;
-            self.inner[index] = None;
-        }
-    }
-
     pub fn get(&self, index: usize) -> Option<&T> {
         self.inner.get(index).and_then(Option::as_ref)
     }
@@ -63,7 +56,7 @@ impl<T: Sized, const N: usize> FreeList<T, N> {
     }
 }
 
-impl<T: Sized, const N: usize> std::ops::Index<usize> for FreeList<T, N> {
+impl<T: Sized, const N: usize> core::ops::Index<usize> for FreeList<T, N> {
     type Output = T;
 
     fn index(&self, index: usize) -> &Self::Output {
@@ -71,7 +64,7 @@ impl<T: Sized, const N: usize> std::ops::Index<usize> for FreeList<T, N> {
     }
 }
 
-impl<T: Sized, const N: usize> std::ops::IndexMut<usize> for FreeList<T, N> {
+impl<T: Sized, const N: usize> core::ops::IndexMut<usize> for FreeList<T, N> {
     fn index_mut(&mut self, index: usize) -> &mut Self::Output {
         self.get_mut(index).expect("index out of bounds")
     }
@@ -91,3 +84,73 @@ impl<'a, T: Sized + Copy, const N: usize> Iterator for FreeListIter<'a, T, N> {
         None
     }
 }
+
+/// A free list like [`FreeList`] that grows as values are inserted instead of holding a fixed number of slots.
+/// The index of a value stays the same until it's removed, and trailing empty slots are released.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct GrowableFreeList<T: Sized> {
+    inner: Vec<Option<T>>,
+}
+
+#[cfg(feature = "std")]
+impl<T: Sized> GrowableFreeList<T> {
+    pub fn new() -> Self {
+        Self { inner: Vec::new() }
+    }
+    // The number of slots, every index of a value is below it.
+    pub fn slots(&self) -> usize {
+        self.inner.len()
+    }
+    pub fn insert(&mut self, value: T) -> usize {
+        if let Some(index) = self.inner.iter().position(Option::is_none) {
+            self.inner[index] = Some(value);
+            return index;
+        }
+        self.inner.push(Some(value));
+        self.inner.len() - 1
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if let Some(slot) = self.inner.get_mut(index) {
+            *slot = None;
+        }
+        while let Some(None) = self.inner.last() {
+            self.inner.pop();
+        }
+        if self.inner.capacity() > 2 * self.inner.len() {
+            self.inner.shrink_to(self.inner.len());
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.inner.get(index).and_then(Option::as_ref)
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        self.inner.get_mut(index).and_then(Option::as_mut)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &T)> {
+        self.inner
+            .iter()
+            .enumerate()
+            .filter_map(|(index, value)| value.as_ref().map(|value| (index, value)))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Sized> core::ops::Index<usize> for GrowableFreeList<T> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.get(index).expect("index out of bounds")
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Sized> core::ops::IndexMut<usize> for GrowableFreeList<T> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.get_mut(index).expect("index out of bounds")
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+use std::sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc, Mutex, PoisonError, Weak,
+};
+
+use crate::{
+    error::{Error, Result},
+    server::{ClientId, ClientIndex},
+};
+
+/// A call queued by a [`ServerHandle`](ServerHandle), run on the server's next update.
+/// Carries the id of the client in the slot when it was queued, the call is dropped if the slot changed hands since.
+#[derive(Debug)]
+pub(crate) enum Command {
+    Send(Vec<u8>, ClientIndex, ClientId),
+    Disconnect(ClientIndex, ClientId),
+}
+
+/// A cheap, cloneable handle to a [`Server`](crate::Server) that gameplay threads can queue sends and disconnects with,
+/// without holding a `&mut Server`.
+///
+/// Calls are queued on a channel and run in order on the server's next [`update`](crate::Server::update),
+/// after received packets are processed. <br>
+/// A queued call for a client that disconnected in the meantime, or a payload that is too large, is dropped (and logged),
+/// since there is no caller left to return the error to. <br>
+/// Client indices are reused, so every call remembers the id of the client it was queued for,
+/// and is dropped instead of reaching a new client in the same slot.
+///
+/// # Example
+/// ```
+/// use netcode::Server;
+/// use std::thread;
+///
+/// let mut server = Server::new("127.0.0.1:0", 0x11223344, netcode::generate_key()).unwrap();
+/// let handle = server.handle();
+///
+/// thread::spawn(move || {
+///     // e.g. a gameplay thread broadcasting a snapshot to some clients
+///     # let clients: Vec<netcode::ClientIndex> = vec![];
+///     for client_idx in clients {
+///         handle.send(b"snapshot", client_idx).unwrap();
+///     }
+/// })
+/// .join()
+/// .unwrap();
+///
+/// server.update(0.0); // runs the queued calls
+/// ```
+#[derive(Debug, Clone)]
+pub struct ServerHandle {
+    tx: Sender<Command>,
+    clients: Weak<Mutex<HashMap<ClientIndex, ClientId>>>,
+}
+
+/// The ids of the connected clients by slot, kept by the server for its handles.
+/// Updated whenever a client connects and on every update.
+#[derive(Debug, Default)]
+pub(crate) struct ClientIds(Arc<Mutex<HashMap<ClientIndex, ClientId>>>);
+
+impl ClientIds {
+    pub(crate) fn set(&self, clients: impl Iterator<Item = (ClientIndex, ClientId)>) {
+        let mut ids = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        ids.clear();
+        ids.extend(clients);
+    }
+}
+
+impl ServerHandle {
+    pub(crate) fn channel() -> (Self, Receiver<Command>, ClientIds) {
+        let (tx, rx) = mpsc::channel();
+        let ids = ClientIds::default();
+        let clients = Arc::downgrade(&ids.0);
+        (Self { tx, clients }, rx, ids)
+    }
+    // Returns `None` if the client isn't connected, or an error if the server was dropped.
+    fn client_id(&self, client_idx: ClientIndex) -> Result<Option<ClientId>> {
+        let ids = self.clients.upgrade().ok_or(Error::ServerDropped)?;
+        let ids = ids.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(ids.get(&client_idx).copied())
+    }
+    /// Queues a packet to be sent to a client on the server's next update,
+    /// see [`Server::send`](crate::Server::send).
+    ///
+    /// Returns an error if the server was dropped.
+    pub fn send(&self, buf: &[u8], client_idx: ClientIndex) -> Result<()> {
+        let Some(client_id) = self.client_id(client_idx)? else {
+            log::debug!("server handle dropped a send to client {client_idx}, not connected");
+            return Ok(());
+        };
+        self.queue(Command::Send(buf.to_vec(), client_idx, client_id))
+    }
+    /// Queues a client to be disconnected on the server's next update,
+    /// see [`Server::disconnect`](crate::Server::disconnect).
+    ///
+    /// Returns an error if the server was dropped.
+    pub fn disconnect(&self, client_idx: ClientIndex) -> Result<()> {
+        let Some(client_id) = self.client_id(client_idx)? else {
+            log::debug!("server handle dropped a disconnect of client {client_idx}, not connected");
+            return Ok(());
+        };
+        self.queue(Command::Disconnect(client_idx, client_id))
+    }
+    fn queue(&self, command: Command) -> Result<()> {
+        self.tx.send(command).map_err(|_| Error::ServerDropped)
+    }
+}
@@ -0,0 +1,331 @@
+//! The byte buffer I/O used by the packet, token, crypto and diagnostics code.
+//!
+//! With the `std` feature these are the `std::io` and `byteorder` types. Without it, this module provides
+//! minimal `no_std` stand-ins with the same names and signatures (for the subset the crate uses),
+//! so the protocol code is written once for both.
+
+#[cfg(feature = "std")]
+pub(crate) use byteorder::{ReadBytesExt, WriteBytesExt};
+#[cfg(feature = "std")]
+pub(crate) use std::{
+    io::{Cursor, Error, Read, Write},
+    net::ToSocketAddrs,
+};
+
+#[cfg(not(feature = "std"))]
+pub(crate) use no_std::{Cursor, Read, ReadBytesExt, Write, WriteBytesExt};
+#[cfg(not(feature = "std"))]
+pub use no_std::{Error, ErrorKind, ToSocketAddrs};
+
+#[cfg(not(feature = "std"))]
+mod no_std {
+    use alloc::{
+        string::{String, ToString},
+        vec::Vec,
+    };
+    use core::{
+        fmt,
+        net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+        str::FromStr,
+    };
+
+    use byteorder::ByteOrder;
+
+    /// The kind of an [`Error`](Error).
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    #[non_exhaustive]
+    pub enum ErrorKind {
+        /// Tried to read past the end of a buffer.
+        UnexpectedEof,
+        /// Tried to write past the end of a buffer.
+        WriteZero,
+        /// An address could not be parsed.
+        InvalidInput,
+        Other,
+    }
+
+    /// An error reading from or writing to a byte buffer, the `no_std` stand-in for `std::io::Error`.
+    #[derive(Debug)]
+    pub struct Error {
+        kind: ErrorKind,
+        message: Option<String>,
+    }
+
+    impl Error {
+        pub(crate) fn new(kind: ErrorKind) -> Self {
+            Self {
+                kind,
+                message: None,
+            }
+        }
+        pub(crate) fn other(error: impl fmt::Display) -> Self {
+            Self {
+                kind: ErrorKind::Other,
+                message: Some(error.to_string()),
+            }
+        }
+        /// Gets the kind of the error.
+        pub fn kind(&self) -> ErrorKind {
+            self.kind
+        }
+    }
+
+    impl fmt::Display for Error {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match (&self.message, self.kind) {
+                (Some(message), _) => f.write_str(message),
+                (None, ErrorKind::UnexpectedEof) => f.write_str("failed to fill whole buffer"),
+                (None, ErrorKind::WriteZero) => f.write_str("failed to write whole buffer"),
+                (None, ErrorKind::InvalidInput) => f.write_str("invalid socket address"),
+                (None, ErrorKind::Other) => f.write_str("other error"),
+            }
+        }
+    }
+
+    impl core::error::Error for Error {}
+
+    pub(crate) type Result<T> = core::result::Result<T, Error>;
+
+    pub(crate) trait Read {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+        fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+            if self.read(buf)? != buf.len() {
+                return Err(Error::new(ErrorKind::UnexpectedEof));
+            }
+            Ok(())
+        }
+    }
+
+    pub(crate) trait Write {
+        fn write(&mut self, buf: &[u8]) -> Result<usize>;
+        fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+            if self.write(buf)? != buf.len() {
+                return Err(Error::new(ErrorKind::WriteZero));
+            }
+            Ok(())
+        }
+    }
+
+    impl Read for &[u8] {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let len = buf.len().min(self.len());
+            let (head, tail) = self.split_at(len);
+            buf[..len].copy_from_slice(head);
+            *self = tail;
+            Ok(len)
+        }
+    }
+
+    impl Write for Vec<u8> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    /// A position in a byte buffer, the `no_std` stand-in for `std::io::Cursor`.
+    pub(crate) struct Cursor<T> {
+        inner: T,
+        pos: u64,
+    }
+
+    impl<T> Cursor<T> {
+        pub(crate) fn new(inner: T) -> Self {
+            Self { inner, pos: 0 }
+        }
+        pub(crate) fn position(&self) -> u64 {
+            self.pos
+        }
+        pub(crate) fn set_position(&mut self, pos: u64) {
+            self.pos = pos;
+        }
+        #[cfg(any(test, feature = "std"))]
+        pub(crate) fn get_ref(&self) -> &T {
+            &self.inner
+        }
+        pub(crate) fn get_mut(&mut self) -> &mut T {
+            &mut self.inner
+        }
+    }
+
+    impl<T: AsRef<[u8]>> Read for Cursor<T> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let inner = self.inner.as_ref();
+            let start = (self.pos as usize).min(inner.len());
+            let len = (&inner[start..]).read(buf)?;
+            self.pos += len as u64;
+            Ok(len)
+        }
+    }
+
+    impl Write for Cursor<&mut [u8]> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let start = (self.pos as usize).min(self.inner.len());
+            let len = buf.len().min(self.inner.len() - start);
+            self.inner[start..start + len].copy_from_slice(&buf[..len]);
+            self.pos += len as u64;
+            Ok(len)
+        }
+    }
+
+    impl Write for Cursor<Vec<u8>> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            let start = self.pos as usize;
+            if self.inner.len() < start + buf.len() {
+                self.inner.resize(start + buf.len(), 0);
+            }
+            self.inner[start..start + buf.len()].copy_from_slice(buf);
+            self.pos += buf.len() as u64;
+            Ok(buf.len())
+        }
+    }
+
+    pub(crate) trait ReadBytesExt: Read {
+        fn read_u8(&mut self) -> Result<u8> {
+            let mut buf = [0; 1];
+            self.read_exact(&mut buf)?;
+            Ok(buf[0])
+        }
+        fn read_u16<B: ByteOrder>(&mut self) -> Result<u16> {
+            let mut buf = [0; 2];
+            self.read_exact(&mut buf)?;
+            Ok(B::read_u16(&buf))
+        }
+        fn read_u32<B: ByteOrder>(&mut self) -> Result<u32> {
+            let mut buf = [0; 4];
+            self.read_exact(&mut buf)?;
+            Ok(B::read_u32(&buf))
+        }
+        fn read_i32<B: ByteOrder>(&mut self) -> Result<i32> {
+            let mut buf = [0; 4];
+            self.read_exact(&mut buf)?;
+            Ok(B::read_i32(&buf))
+        }
+        fn read_u64<B: ByteOrder>(&mut self) -> Result<u64> {
+            let mut buf = [0; 8];
+            self.read_exact(&mut buf)?;
+            Ok(B::read_u64(&buf))
+        }
+        fn read_f64<B: ByteOrder>(&mut self) -> Result<f64> {
+            let mut buf = [0; 8];
+            self.read_exact(&mut buf)?;
+            Ok(B::read_f64(&buf))
+        }
+    }
+
+    impl<R: Read + ?Sized> ReadBytesExt for R {}
+
+    pub(crate) trait WriteBytesExt: Write {
+        fn write_u8(&mut self, n: u8) -> Result<()> {
+            self.write_all(&[n])
+        }
+        fn write_u16<B: ByteOrder>(&mut self, n: u16) -> Result<()> {
+            let mut buf = [0; 2];
+            B::write_u16(&mut buf, n);
+            self.write_all(&buf)
+        }
+        fn write_u32<B: ByteOrder>(&mut self, n: u32) -> Result<()> {
+            let mut buf = [0; 4];
+            B::write_u32(&mut buf, n);
+            self.write_all(&buf)
+        }
+        fn write_i32<B: ByteOrder>(&mut self, n: i32) -> Result<()> {
+            let mut buf = [0; 4];
+            B::write_i32(&mut buf, n);
+            self.write_all(&buf)
+        }
+        fn write_u64<B: ByteOrder>(&mut self, n: u64) -> Result<()> {
+            let mut buf = [0; 8];
+            B::write_u64(&mut buf, n);
+            self.write_all(&buf)
+        }
+        fn write_f64<B: ByteOrder>(&mut self, n: f64) -> Result<()> {
+            let mut buf = [0; 8];
+            B::write_f64(&mut buf, n);
+            self.write_all(&buf)
+        }
+    }
+
+    impl<W: Write + ?Sized> WriteBytesExt for W {}
+
+    /// Conversion into server addresses for [`ConnectToken::build`](crate::ConnectToken::build),
+    /// the `no_std` stand-in for `std::net::ToSocketAddrs`.
+    ///
+    /// Without `std` there is no DNS resolution, strings must be IP socket addresses like `"127.0.0.1:40000"`.
+    pub trait ToSocketAddrs {
+        type Iter: Iterator<Item = SocketAddr>;
+        fn to_socket_addrs(&self) -> Result<Self::Iter>;
+    }
+
+    impl ToSocketAddrs for SocketAddr {
+        type Iter = core::option::IntoIter<SocketAddr>;
+        fn to_socket_addrs(&self) -> Result<Self::Iter> {
+            Ok(Some(*self).into_iter())
+        }
+    }
+
+    impl ToSocketAddrs for SocketAddrV4 {
+        type Iter = core::option::IntoIter<SocketAddr>;
+        fn to_socket_addrs(&self) -> Result<Self::Iter> {
+            SocketAddr::V4(*self).to_socket_addrs()
+        }
+    }
+
+    impl ToSocketAddrs for SocketAddrV6 {
+        type Iter = core::option::IntoIter<SocketAddr>;
+        fn to_socket_addrs(&self) -> Result<Self::Iter> {
+            SocketAddr::V6(*self).to_socket_addrs()
+        }
+    }
+
+    impl ToSocketAddrs for (IpAddr, u16) {
+        type Iter = core::option::IntoIter<SocketAddr>;
+        fn to_socket_addrs(&self) -> Result<Self::Iter> {
+            SocketAddr::from(*self).to_socket_addrs()
+        }
+    }
+
+    impl ToSocketAddrs for (Ipv4Addr, u16) {
+        type Iter = core::option::IntoIter<SocketAddr>;
+        fn to_socket_addrs(&self) -> Result<Self::Iter> {
+            SocketAddr::from(*self).to_socket_addrs()
+        }
+    }
+
+    impl ToSocketAddrs for (Ipv6Addr, u16) {
+        type Iter = core::option::IntoIter<SocketAddr>;
+        fn to_socket_addrs(&self) -> Result<Self::Iter> {
+            SocketAddr::from(*self).to_socket_addrs()
+        }
+    }
+
+    impl ToSocketAddrs for str {
+        type Iter = core::option::IntoIter<SocketAddr>;
+        fn to_socket_addrs(&self) -> Result<Self::Iter> {
+            SocketAddr::from_str(self)
+                .map_err(|_| Error::new(ErrorKind::InvalidInput))?
+                .to_socket_addrs()
+        }
+    }
+
+    impl ToSocketAddrs for String {
+        type Iter = core::option::IntoIter<SocketAddr>;
+        fn to_socket_addrs(&self) -> Result<Self::Iter> {
+            self.as_str().to_socket_addrs()
+        }
+    }
+
+    impl<'a> ToSocketAddrs for &'a [SocketAddr] {
+        type Iter = core::iter::Copied<core::slice::Iter<'a, SocketAddr>>;
+        fn to_socket_addrs(&self) -> Result<Self::Iter> {
+            Ok(self.iter().copied())
+        }
+    }
+
+    impl<T: ToSocketAddrs + ?Sized> ToSocketAddrs for &T {
+        type Iter = T::Iter;
+        fn to_socket_addrs(&self) -> Result<Self::Iter> {
+            (**self).to_socket_addrs()
+        }
+    }
+}
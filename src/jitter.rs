@@ -0,0 +1,202 @@
+//! Steady playback of payloads that arrive unevenly, see [`JitterBuffer`](JitterBuffer).
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::stats::JitterStats;
+
+/// Holds received payloads (e.g. snapshots) and releases them one tick at a time, a fixed number of ticks behind
+/// the newest one received, so they can be played back at a steady rate although packets arrive unevenly.
+///
+/// The sender stamps each payload with its tick, e.g. the sequence of a [`SnapshotChannel`](crate::SnapshotChannel)
+/// or its simulation tick, and the receiver [pushes](JitterBuffer::push) payloads with their tick as they arrive.
+/// [`pop`](JitterBuffer::pop) is called once per tick of the receiver: it waits `delay` ticks after the first payload was received,
+/// then each call moves on to the next tick, returning its payload if it arrived in time.
+///
+/// A longer delay absorbs more jitter but adds latency, a delay of 2 or 3 ticks covers most connections. <br>
+/// If the sender gets more than twice the delay ahead (e.g. after the receiver stalled), the buffer skips ahead to `delay` ticks behind it again.
+/// If it runs out of payloads (e.g. the sender stalled), it waits at the next tick instead of moving on.
+///
+/// # Example
+/// ```
+/// use netcode::JitterBuffer;
+///
+/// let mut buffer = JitterBuffer::new(2);
+/// buffer.push(10, b"tick 10");
+/// buffer.push(12, b"tick 12"); // arrived before tick 11
+/// buffer.push(11, b"tick 11");
+///
+/// // tick 10 is released 2 ticks after it arrived
+/// assert_eq!(buffer.pop(), None);
+/// assert_eq!(buffer.pop(), None);
+/// assert_eq!(buffer.pop(), Some((10, b"tick 10".to_vec())));
+/// assert_eq!(buffer.pop(), Some((11, b"tick 11".to_vec())));
+/// assert_eq!(buffer.pop(), Some((12, b"tick 12".to_vec())));
+/// assert_eq!(buffer.pop(), None); // nothing newer yet
+///
+/// assert!(!buffer.push(11, b"tick 11")); // late
+/// assert_eq!(buffer.stats().late, 1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct JitterBuffer {
+    delay: u32,
+    payloads: BTreeMap<u32, Vec<u8>>,
+    // the tick released by the next pop, `None` until a payload was received
+    playout: Option<u32>,
+    first: u32,
+    newest: u32,
+    stats: JitterStats,
+}
+
+impl JitterBuffer {
+    /// Creates a buffer that releases payloads `delay` ticks behind the newest one received.
+    pub fn new(delay: u32) -> Self {
+        Self {
+            delay,
+            payloads: BTreeMap::new(),
+            playout: None,
+            first: 0,
+            newest: 0,
+            stats: JitterStats::default(),
+        }
+    }
+    /// Adds a payload the sender stamped with `tick`.
+    ///
+    /// Returns `false` if the payload is dropped, because its tick was already released (it is late)
+    /// or a payload with the same tick is already held (it is a duplicate).
+    pub fn push(&mut self, tick: u32, payload: &[u8]) -> bool {
+        self.stats.received += 1;
+        let playout = match self.playout {
+            Some(playout) => playout,
+            None => {
+                self.first = tick;
+                *self.playout.insert(tick.saturating_sub(self.delay))
+            }
+        };
+        if tick < playout {
+            self.stats.late += 1;
+            return false;
+        }
+        if self.payloads.contains_key(&tick) {
+            self.stats.duplicates += 1;
+            return false;
+        }
+        self.newest = self.newest.max(tick);
+        self.payloads.insert(tick, payload.to_vec());
+        true
+    }
+    /// Moves on to the next tick, to be called once per tick of the receiver.
+    ///
+    /// Returns the tick and its payload, or `None` if it was lost or is late (then it is dropped if it arrives after all),
+    /// or if the buffer is waiting for the sender.
+    pub fn pop(&mut self) -> Option<(u32, Vec<u8>)> {
+        let mut playout = self.playout?;
+        if self.newest.saturating_sub(playout) > self.delay.saturating_mul(2) {
+            playout = self.newest - self.delay;
+            self.stats.resyncs += 1;
+            self.stats.skipped += self.payloads.range(..playout).count() as u64;
+            self.payloads = self.payloads.split_off(&playout);
+        }
+        if playout > self.newest {
+            self.stats.underruns += 1;
+            self.playout = Some(playout);
+            return None;
+        }
+        self.playout = Some(playout + 1);
+        if playout < self.first {
+            // still waiting out the delay after the first payload
+            self.stats.underruns += 1;
+            return None;
+        }
+        let Some(payload) = self.payloads.remove(&playout) else {
+            self.stats.missing += 1;
+            return None;
+        };
+        self.stats.released += 1;
+        Some((playout, payload))
+    }
+    /// Gets the number of ticks payloads are released behind the newest one received.
+    pub fn delay(&self) -> u32 {
+        self.delay
+    }
+    /// Sets the number of ticks payloads are released behind the newest one received, e.g. from the measured jitter. <br>
+    /// The buffer catches up once it is more than twice the new delay behind, a longer delay takes effect once it ran out of payloads.
+    pub fn set_delay(&mut self, delay: u32) {
+        self.delay = delay;
+    }
+    /// Gets the tick the next [`pop`](JitterBuffer::pop) releases, `None` until a payload was received.
+    pub fn next_tick(&self) -> Option<u32> {
+        self.playout
+    }
+    /// Gets the number of payloads held.
+    pub fn len(&self) -> usize {
+        self.payloads.len()
+    }
+    /// Returns true if no payloads are held.
+    pub fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+    /// Gets the statistics of the buffer.
+    pub fn stats(&self) -> JitterStats {
+        self.stats
+    }
+    /// Drops all payloads and starts over with the next payload received, e.g. after reconnecting to a server whose ticks started over.
+    pub fn clear(&mut self) {
+        self.payloads.clear();
+        self.playout = None;
+        self.first = 0;
+        self.newest = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn releases_in_order_behind_the_newest() {
+        let mut buffer = JitterBuffer::new(2);
+        assert_eq!(buffer.pop(), None);
+        assert_eq!(buffer.stats().underruns, 0);
+
+        assert!(buffer.push(1, b"1"));
+        assert!(buffer.push(3, b"3"));
+        assert!(!buffer.push(3, b"3"));
+        assert_eq!(buffer.next_tick(), Some(0));
+        // waits for tick 1, then tick 2 is lost
+        assert_eq!(buffer.pop(), None);
+        assert_eq!(buffer.pop(), Some((1, b"1".to_vec())));
+        assert_eq!(buffer.pop(), None);
+        assert!(!buffer.push(2, b"2"));
+        assert_eq!(buffer.pop(), Some((3, b"3".to_vec())));
+        // the sender stalls
+        assert_eq!(buffer.pop(), None);
+        assert_eq!(buffer.pop(), None);
+        assert!(buffer.push(4, b"4"));
+        assert_eq!(buffer.pop(), Some((4, b"4".to_vec())));
+
+        let stats = buffer.stats();
+        assert_eq!(
+            (stats.received, stats.released, stats.late, stats.duplicates),
+            (5, 3, 1, 1)
+        );
+        assert_eq!((stats.missing, stats.underruns), (1, 3));
+    }
+
+    #[test]
+    fn skips_ahead_when_far_behind() {
+        let mut buffer = JitterBuffer::new(2);
+        for tick in 10..20 {
+            buffer.push(tick, &[tick as u8]);
+        }
+        // 19 is more than 4 ticks ahead of 8, so 17 is released next
+        assert_eq!(buffer.pop(), Some((17, vec![17])));
+        assert_eq!(buffer.len(), 2);
+        let stats = buffer.stats();
+        assert_eq!((stats.resyncs, stats.skipped), (1, 7));
+
+        buffer.clear();
+        assert!(buffer.is_empty());
+        buffer.push(1, b"restarted");
+        assert_eq!(buffer.next_tick(), Some(0));
+    }
+}
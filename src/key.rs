@@ -0,0 +1,200 @@
+//! Loading private keys, from files, base64 strings or a [`KeyProvider`](KeyProvider) such as a secrets manager.
+//!
+//! # Example
+//! ```
+//! use netcode::{Key, KeyExt};
+//!
+//! let key = netcode::generate_key();
+//! let encoded = key.to_base64();
+//! assert_eq!(Key::from_base64(&encoded).unwrap(), key);
+//! ```
+
+use alloc::string::String;
+
+use zeroize::Zeroize;
+
+use crate::{
+    crypto::Key,
+    error::{Error, Result},
+    PRIVATE_KEY_BYTES,
+};
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Constructors and encodings for [`Key`](Key), which is a plain array. Import the trait to call them as `Key::from_base64(..)`.
+///
+/// A `Key` is `Copy` and isn't wiped when dropped, so keep keys that live long in a [`Zeroizing`](zeroize::Zeroizing)
+/// (as the server does with its private key) and avoid copying them around.
+pub trait KeyExt: Sized {
+    /// Decodes a key from standard base64 (with or without padding), ignoring surrounding whitespace.
+    ///
+    /// Returns [`Error::InvalidKey`](Error::InvalidKey) if the string isn't base64 or doesn't decode to exactly 32 bytes.
+    fn from_base64(encoded: &str) -> Result<Self>;
+    /// Reads a key from a file, either its 32 raw bytes or a base64 string, see [`from_base64`](KeyExt::from_base64).
+    ///
+    /// The file contents are wiped from memory once the key is decoded.
+    #[cfg(feature = "std")]
+    fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self>;
+    /// Encodes the key in standard base64 with padding, e.g. to store it in a file or a secrets manager.
+    fn to_base64(&self) -> String;
+}
+
+impl KeyExt for Key {
+    fn from_base64(encoded: &str) -> Result<Self> {
+        let encoded = encoded.trim().trim_end_matches('=').as_bytes();
+        // 32 bytes are 43 base64 digits, the last of which only carries 4 bits
+        if encoded.len() != 43 {
+            return Err(Error::InvalidKey);
+        }
+        // decoded straight into the returned key, which is wiped if the string turns out to be invalid
+        let mut key = [0; PRIVATE_KEY_BYTES];
+        let (mut acc, mut bits, mut len) = (0u32, 0, 0);
+        for &digit in encoded {
+            let Some(value) = ALPHABET.iter().position(|&c| c == digit) else {
+                key.zeroize();
+                acc.zeroize();
+                return Err(Error::InvalidKey);
+            };
+            acc = (acc << 6) | value as u32;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                key[len] = (acc >> bits) as u8;
+                len += 1;
+            }
+        }
+        // the leftover bits of the last digit have to be zero, so every key has a single encoding
+        let valid = acc & ((1 << bits) - 1) == 0;
+        acc.zeroize();
+        if !valid {
+            key.zeroize();
+            return Err(Error::InvalidKey);
+        }
+        Ok(key)
+    }
+    #[cfg(feature = "std")]
+    fn from_file(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let contents = zeroize::Zeroizing::new(std::fs::read(path)?);
+        if let Ok(key) = Key::try_from(&contents[..]) {
+            return Ok(key);
+        }
+        let encoded = core::str::from_utf8(&contents).map_err(|_| Error::InvalidKey)?;
+        Key::from_base64(encoded)
+    }
+    fn to_base64(&self) -> String {
+        let mut encoded = String::with_capacity(44);
+        for chunk in self.chunks(3) {
+            let mut group = [0; 3];
+            group[..chunk.len()].copy_from_slice(chunk);
+            let n = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+            for i in 0..=chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            }
+        }
+        encoded.push('=');
+        encoded
+    }
+}
+
+/// A source of the server's private key, e.g. a secrets manager (Vault, a cloud KMS) or a file that is rotated on disk.
+///
+/// The key is fetched once to create the server, and again whenever it is rotated with
+/// [`Server::rotate_private_key`](crate::Server::rotate_private_key). <br>
+/// A [`Key`](Key) provides itself, and any `FnMut() -> Result<Key>` closure is a provider.
+/// Errors of other crates can be returned as an [`Error::Io`](Error::Io), e.g. with `std::io::Error::other`.
+///
+/// # Example
+/// ```
+/// use netcode::{Key, KeyExt, KeyProvider, Server};
+///
+/// struct Vault {
+///     // a client for the secrets manager
+/// }
+///
+/// impl KeyProvider for Vault {
+///     fn private_key(&mut self) -> netcode::Result<Key> {
+///         // fetch the current key from the secrets manager
+///         # Ok(netcode::generate_key())
+///     }
+/// }
+///
+/// let mut vault = Vault {};
+/// let mut server = Server::new("127.0.0.1:0", 0x11223344, vault.private_key()?)?;
+/// // later, once the key was rotated in the secrets manager
+/// server.rotate_private_key(&mut vault)?;
+/// # Ok::<(), netcode::Error>(())
+/// ```
+pub trait KeyProvider {
+    /// Fetches the current private key.
+    fn private_key(&mut self) -> Result<Key>;
+}
+
+impl KeyProvider for Key {
+    fn private_key(&mut self) -> Result<Key> {
+        Ok(*self)
+    }
+}
+
+impl<F: FnMut() -> Result<Key>> KeyProvider for F {
+    fn private_key(&mut self) -> Result<Key> {
+        self()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_roundtrip() {
+        let key: Key = core::array::from_fn(|i| (i * 37) as u8);
+        let encoded = key.to_base64();
+        assert_eq!(encoded, "ACVKb5S53gMoTXKXvOEGK1B1mr/kCS5TeJ3C5wwxVns=");
+        assert_eq!(Key::from_base64(&encoded).unwrap(), key);
+        assert_eq!(
+            Key::from_base64(&format!(" {}\n", &encoded[..43])).unwrap(),
+            key
+        );
+
+        let last_changed = format!("{}9=", &encoded[..42]);
+        for invalid in [
+            "",
+            &encoded[..40],
+            &encoded[1..],
+            "!".repeat(43).as_str(),
+            &last_changed,
+        ] {
+            assert!(matches!(Key::from_base64(invalid), Err(Error::InvalidKey)));
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn key_from_file() {
+        let key = crate::generate_key();
+        let dir = std::env::temp_dir();
+        let raw = dir.join(format!("netcode-key-{}.bin", std::process::id()));
+        let text = dir.join(format!("netcode-key-{}.txt", std::process::id()));
+        std::fs::write(&raw, key).unwrap();
+        std::fs::write(&text, key.to_base64() + "\n").unwrap();
+        assert_eq!(Key::from_file(&raw).unwrap(), key);
+        assert_eq!(Key::from_file(&text).unwrap(), key);
+        std::fs::remove_file(&raw).unwrap();
+        std::fs::remove_file(&text).unwrap();
+        assert!(matches!(Key::from_file(&raw), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn providers() {
+        let key = crate::generate_key();
+        let mut fetches = 0;
+        let mut provider = || {
+            fetches += 1;
+            Ok(key)
+        };
+        assert_eq!(provider.private_key().unwrap(), key);
+        let mut fixed = key;
+        assert_eq!(fixed.private_key().unwrap(), key);
+        assert_eq!(fetches, 1);
+    }
+}
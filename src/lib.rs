@@ -1,3 +1,4 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 //! # netcode
 //!
 //! The `netcode` crate implements the [netcode](https://github.com/networkprotocol/netcode)
@@ -106,20 +107,122 @@
 //!     thread::sleep(tick_rate);
 //! }
 //! ```
+//!
+//! ## Feature flags
+//!
+//! * `bevy` - [Bevy](https://bevyengine.org) plugins that update a server or client every frame and send what happened as messages,
+//!   see the [`bevy`] module.
+//! * `capi` - A C API for engines written in C or C++ (or C# through P/Invoke), with the `include/netcode.h` header,
+//!   see the [`capi`](capi) module. Build the shared and static libraries with `cargo build --release --manifest-path netcode-sys/Cargo.toml`.
+//! * `compression` - Compresses payloads with LZ4 before they are encrypted,
+//!   enabled per connection with `ClientConfig::compress_payloads` and
+//!   `ServerConfig::compress_payloads`.
+//! * `insecure` - Connections without a matchmaker for local development, with `Client::connect_insecure` and
+//!   `ServerConfig::allow_insecure`. Anyone can connect to a server that allows them, never enable it in release builds.
+//! * `io-uring` - An [`IoUringSocket`](IoUringSocket) transceiver on Linux, a drop-in replacement for `NetcodeSocket` that sends and
+//!   receives through an io_uring instead of a syscall per packet.
+//! * `metrics` - Reports packet/byte counters, connect successes and failures (by reason) and per-update processing time
+//!   through the [`metrics`](https://docs.rs/metrics) facade, to be scraped by any installed exporter (e.g. Prometheus).
+//! * `opentelemetry` - Exports the same metrics, plus a `netcode.connect` span per client connection attempt,
+//!   through the global [OpenTelemetry](https://docs.rs/opentelemetry) meter and tracer providers.
+//! * `quic` - A [`QuicTransceiver`](QuicTransceiver) that sends packets as QUIC datagrams with [`quinn`](https://docs.rs/quinn),
+//!   for networks that block or throttle plain UDP.
+//! * `std` (enabled by default) - The [`Client`](Client), [`Server`](Server) and UDP socket, which need the standard library.
+//!   Without it the crate is `no_std` (but requires `alloc`), and only provides the core of the protocol that doesn't depend on the OS:
+//!   [`ConnectTokens`](ConnectToken) (see [`ConnectTokenBuilder::generate_at`](ConnectTokenBuilder::generate_at)), keys and the [`Transceiver`](Transceiver) trait.
+//!   Random keys and nonces come from [`getrandom`](https://docs.rs/getrandom/0.2), which needs a
+//!   [custom backend](https://docs.rs/getrandom/0.2/getrandom/macro.register_custom_getrandom.html) on targets without an OS.
+//! * `testing` - In-memory transceivers and a seeded RNG for testing code built on `netcode` without real sockets, see the [`testing`] module.
+//!   The seeded RNG makes every key predictable, so only enable it in `[dev-dependencies]`.
+//! * `tracing` - Emits [`tracing`](https://docs.rs/tracing) spans and structured events from the client and server state machines
+//!   (connection attempts, token rejections, decryption failures, replays, timeouts), in addition to the regular `log` output.
+//!
+//! ## WebAssembly
+//!
+//! The [`Client`](Client) builds for `wasm32-unknown-unknown`, for browser-based game clients.
+//! Browsers can't open UDP sockets, so `NetcodeSocket` isn't available there - create the client with
+//! [`Client::with_config_and_transceiver`](Client::with_config_and_transceiver) and a [`Transceiver`](Transceiver) over a datagram transport
+//! instead (see the `webtransport` example). The client never reads the system clock, its time is whatever is passed to
+//! [`Client::update`](Client::update), e.g. `performance.now()`. Randomness comes from the browser's `crypto.getRandomValues`.
 
+extern crate alloc;
+
+mod ack;
+#[cfg(feature = "std")]
+mod admin;
+#[cfg(all(feature = "bevy", not(target_family = "wasm")))]
+pub mod bevy;
 mod bytes;
+#[cfg(all(feature = "capi", not(target_family = "wasm")))]
+pub mod capi;
+mod capture;
 mod client;
+mod clock;
+#[cfg(feature = "std")]
+mod cluster;
+mod coalesce;
+#[cfg(test)]
+mod compat;
+#[cfg(feature = "compression")]
+mod compression;
+mod config;
+mod congestion;
+mod connect;
 mod crypto;
+mod diagnostics;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+pub mod discovery;
 mod error;
 mod free_list;
+#[cfg(feature = "std")]
+mod handle;
+mod io;
+mod jitter;
+mod key;
+mod metrics;
+mod multi;
+#[cfg(all(feature = "std", any(target_os = "linux", windows)))]
+mod offload;
+mod otel;
+#[cfg(feature = "std")]
+mod pacing;
 mod packet;
+mod padding;
+pub mod parse;
+#[cfg(feature = "std")]
+mod pcap;
+mod phase;
+mod pool;
+pub mod query;
+#[cfg(all(feature = "quic", not(target_family = "wasm")))]
+mod quic;
+#[cfg(feature = "std")]
+mod recording;
+mod rekey;
+pub mod relay;
 mod replay;
+#[cfg(feature = "std")]
 mod server;
+#[cfg(feature = "std")]
+mod shard;
+mod snapshot;
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
 mod socket;
+mod stats;
+#[cfg(any(feature = "testing", all(test, feature = "std")))]
+pub mod testing;
+mod tick_sync;
 mod token;
+pub mod token_crypto;
+#[cfg(feature = "std")]
+mod token_store;
+mod trace;
 mod transceiver;
+mod transfer;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod simulator;
 
 pub(crate) const MAC_BYTES: usize = 16;
@@ -127,18 +230,67 @@ pub(crate) const MAX_PKT_BUF_SIZE: usize = 1300;
 pub(crate) const CONNECTION_TIMEOUT_SEC: i32 = 15;
 pub(crate) const PACKET_SEND_RATE_SEC: f64 = 1.0 / 10.0;
 
+pub use crate::capture::{PacketDirection, PacketLogger, PacketRecord};
 pub use crate::client::{Client, ClientConfig, ClientState};
-pub use crate::crypto::{generate_key, try_generate_key, Key};
+#[cfg(feature = "std")]
+pub use crate::clock::SystemClock;
+pub use crate::clock::{Clock, ManualClock};
+#[cfg(feature = "std")]
+pub use crate::cluster::ServerCluster;
+pub use crate::config::ConfigError;
+pub use crate::congestion::ConnectionQuality;
+pub use crate::connect::ConnectConfig;
+pub use crate::crypto::Error as CryptoError;
+pub use crate::crypto::{
+    generate_key, generate_key_with, try_generate_key, try_generate_key_with, Key,
+};
+pub use crate::diagnostics::{EchoMode, LinkCheckConfig, LinkCheckReport};
 pub use crate::error::{Error, Result};
-pub use crate::server::{ClientId, ClientIndex, Server, ServerConfig};
-pub use crate::socket::NetcodeSocket;
+#[cfg(feature = "std")]
+pub use crate::handle::ServerHandle;
+#[cfg(not(feature = "std"))]
+pub use crate::io::{Error as IoError, ErrorKind as IoErrorKind, ToSocketAddrs};
+pub use crate::jitter::JitterBuffer;
+pub use crate::key::{KeyExt, KeyProvider};
+pub use crate::multi::{MultiClient, SessionHandle, SessionTransceiver};
+pub use crate::packet::Error as PacketError;
+#[cfg(feature = "std")]
+pub use crate::pcap::PcapWriter;
+pub use crate::phase::{ConnectionPhase, PacketAllowList, PacketType};
+#[cfg(feature = "std")]
+pub use crate::pool::QueueOverflow;
+#[cfg(all(feature = "quic", not(target_family = "wasm")))]
+pub use crate::quic::QuicTransceiver;
+#[cfg(feature = "std")]
+pub use crate::recording::{Recorder, Recording, ReplayTransceiver};
+#[cfg(feature = "std")]
+pub use crate::server::{
+    ClientId, ClientIndex, ConnectDecision, DuplicateClientId, PendingEviction, Server,
+    ServerConfig, ShutdownReport, MAX_CLIENTS,
+};
+#[cfg(feature = "std")]
+pub use crate::shard::ShardMap;
+pub use crate::snapshot::{ReceivedSnapshot, SnapshotChannel};
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+pub use crate::socket::{Error as SocketError, NetcodeSocket, SocketOptions};
+pub use crate::stats::{ClientStats, JitterStats, PacketCounts, ProtocolStats, ServerStats};
 pub use crate::token::{ConnectToken, ConnectTokenBuilder, InvalidTokenError};
-pub use crate::transceiver::Transceiver;
+#[cfg(feature = "std")]
+pub use crate::token_store::TokenReplayStore;
+pub use crate::transceiver::{SendOutcome, Transceiver};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+pub use crate::uring::IoUringSocket;
+/// The version of `rand_core` whose RNGs can be injected, e.g. into [`generate_key_with`](generate_key_with).
+pub use chacha20poly1305::aead::rand_core;
 
 /// The size of a private key in bytes.
 pub const PRIVATE_KEY_BYTES: usize = 32;
 /// The size of the user data in a connect token in bytes.
 pub const USER_DATA_BYTES: usize = 256;
+/// The size of the custom data a server can attach to its challenge tokens in bytes, see `ServerConfig::challenge_data`.
+pub const CHALLENGE_DATA_BYTES: usize = 20;
+/// The maximum size of a heartbeat attached to keep-alive packets in bytes, see `Client::set_heartbeat_payload`.
+pub const MAX_HEARTBEAT_BYTES: usize = 64;
 /// The size of the connect token in bytes.
 pub const CONNECT_TOKEN_BYTES: usize = 2048;
 /// The maximum size of a packet in bytes.
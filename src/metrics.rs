@@ -0,0 +1,167 @@
+//! Counters and histograms reported through the [`metrics`](https://docs.rs/metrics) facade.
+//!
+//! Every function here is a no-op unless the `metrics` feature is enabled, in which case the values
+//! are recorded with whatever recorder/exporter the application installed (e.g. `metrics-exporter-prometheus`).
+//!
+//! All metrics carry a `side` label (`"client"` or `"server"`):
+//!
+//! * `netcode_packets_sent_total` / `netcode_packets_received_total` (also labeled by packet `type`)
+//! * `netcode_bytes_sent_total` / `netcode_bytes_received_total`
+//! * `netcode_packets_dropped_total` (labeled by `reason`)
+//! * `netcode_connect_successes_total`
+//! * `netcode_connect_failures_total` (labeled by `reason`)
+//! * `netcode_update_duration_seconds` - histogram of the time spent inside `update`
+//!
+//! With the `opentelemetry` feature the same metrics are also exported through OpenTelemetry, see [`otel`](crate::otel).
+
+#[cfg(feature = "opentelemetry")]
+use crate::otel;
+#[cfg(any(feature = "metrics", feature = "opentelemetry"))]
+use crate::packet::Packet;
+use crate::packet::PacketKind;
+
+#[derive(Clone, Copy)]
+pub(crate) enum Side {
+    Client,
+    #[cfg(feature = "std")]
+    Server,
+}
+
+impl Side {
+    #[cfg_attr(
+        not(any(feature = "metrics", feature = "opentelemetry")),
+        allow(dead_code)
+    )]
+    fn as_str(self) -> &'static str {
+        match self {
+            Side::Client => "client",
+            #[cfg(feature = "std")]
+            Side::Server => "server",
+        }
+    }
+}
+
+#[cfg_attr(
+    not(any(feature = "metrics", feature = "opentelemetry")),
+    allow(unused_variables)
+)]
+pub(crate) fn packet_sent(side: Side, kind: PacketKind, len: usize) {
+    #[cfg(feature = "metrics")]
+    {
+        let (side, kind) = (side.as_str(), Packet::kind_name(kind));
+        ::metrics::counter!("netcode_packets_sent_total", "side" => side, "type" => kind)
+            .increment(1);
+        ::metrics::counter!("netcode_bytes_sent_total", "side" => side).increment(len as u64);
+    }
+    #[cfg(feature = "opentelemetry")]
+    {
+        let (side, kind) = (side.as_str(), Packet::kind_name(kind));
+        let otel = otel::instruments();
+        otel.packets_sent
+            .add(1, &otel::attrs([("side", side), ("type", kind)]));
+        otel.bytes_sent
+            .add(len as u64, &otel::attrs([("side", side)]));
+    }
+}
+
+#[cfg_attr(
+    not(any(feature = "metrics", feature = "opentelemetry")),
+    allow(unused_variables)
+)]
+pub(crate) fn packet_received(side: Side, kind: PacketKind, len: usize) {
+    #[cfg(feature = "metrics")]
+    {
+        let (side, kind) = (side.as_str(), Packet::kind_name(kind));
+        ::metrics::counter!("netcode_packets_received_total", "side" => side, "type" => kind)
+            .increment(1);
+        ::metrics::counter!("netcode_bytes_received_total", "side" => side).increment(len as u64);
+    }
+    #[cfg(feature = "opentelemetry")]
+    {
+        let (side, kind) = (side.as_str(), Packet::kind_name(kind));
+        let otel = otel::instruments();
+        otel.packets_received
+            .add(1, &otel::attrs([("side", side), ("type", kind)]));
+        otel.bytes_received
+            .add(len as u64, &otel::attrs([("side", side)]));
+    }
+}
+
+#[cfg_attr(
+    not(any(feature = "metrics", feature = "opentelemetry")),
+    allow(unused_variables)
+)]
+pub(crate) fn packet_dropped(side: Side, reason: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("netcode_packets_dropped_total", "side" => side.as_str(), "reason" => reason)
+        .increment(1);
+    #[cfg(feature = "opentelemetry")]
+    otel::instruments().packets_dropped.add(
+        1,
+        &otel::attrs([("side", side.as_str()), ("reason", reason)]),
+    );
+}
+
+#[cfg_attr(
+    not(any(feature = "metrics", feature = "opentelemetry")),
+    allow(unused_variables)
+)]
+pub(crate) fn connect_succeeded(side: Side) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("netcode_connect_successes_total", "side" => side.as_str()).increment(1);
+    #[cfg(feature = "opentelemetry")]
+    otel::instruments()
+        .connect_successes
+        .add(1, &otel::attrs([("side", side.as_str())]));
+}
+
+#[cfg_attr(
+    not(any(feature = "metrics", feature = "opentelemetry")),
+    allow(unused_variables)
+)]
+pub(crate) fn connect_failed(side: Side, reason: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!("netcode_connect_failures_total", "side" => side.as_str(), "reason" => reason)
+        .increment(1);
+    #[cfg(feature = "opentelemetry")]
+    otel::instruments().connect_failures.add(
+        1,
+        &otel::attrs([("side", side.as_str()), ("reason", reason)]),
+    );
+}
+
+/// Records the time spent in an `update` call into `netcode_update_duration_seconds` when dropped.
+pub(crate) struct UpdateTimer {
+    #[cfg(any(feature = "metrics", feature = "opentelemetry"))]
+    side: Side,
+    #[cfg(any(feature = "metrics", feature = "opentelemetry"))]
+    start: std::time::Instant,
+}
+
+impl UpdateTimer {
+    #[cfg_attr(
+        not(any(feature = "metrics", feature = "opentelemetry")),
+        allow(unused_variables)
+    )]
+    pub(crate) fn start(side: Side) -> Self {
+        Self {
+            #[cfg(any(feature = "metrics", feature = "opentelemetry"))]
+            side,
+            #[cfg(any(feature = "metrics", feature = "opentelemetry"))]
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+impl Drop for UpdateTimer {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        ::metrics::histogram!("netcode_update_duration_seconds", "side" => self.side.as_str())
+            .record(self.start.elapsed().as_secs_f64());
+        #[cfg(feature = "opentelemetry")]
+        otel::instruments().update_duration.record(
+            self.start.elapsed().as_secs_f64(),
+            &otel::attrs([("side", self.side.as_str())]),
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Several client sessions over one socket, see [`MultiClient`](MultiClient).
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::{cell::RefCell, net::SocketAddr};
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+use std::net::Ipv4Addr;
+
+use crate::{
+    client::{Client, ClientConfig},
+    error::{Error, Result},
+    pool::PayloadQueue,
+    transceiver::Transceiver,
+    MAX_PKT_BUF_SIZE,
+};
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+use crate::{
+    client::{RECV_BUF_SIZE, SEND_BUF_SIZE},
+    socket::NetcodeSocket,
+};
+
+/// Identifies one of the sessions of a [`MultiClient`](MultiClient).
+///
+/// Handles aren't reused, a handle of a session that was removed never refers to a later one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SessionHandle(u64);
+
+impl core::fmt::Display for SessionHandle {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// The transceiver of one session of a [`MultiClient`](MultiClient).
+///
+/// It sends through the transceiver shared by all sessions, and receives the packets the multi-client routed to its session.
+pub struct SessionTransceiver<T> {
+    shared: Arc<T>,
+    inbox: RefCell<PayloadQueue<SocketAddr>>,
+}
+
+impl<T: Transceiver> Transceiver for SessionTransceiver<T> {
+    type IntoError = T::IntoError;
+
+    fn addr(&self) -> SocketAddr {
+        self.shared.addr()
+    }
+    fn recv(
+        &self,
+        buf: &mut [u8],
+    ) -> core::result::Result<Option<(usize, SocketAddr)>, T::IntoError> {
+        Ok(self
+            .inbox
+            .borrow_mut()
+            .pop_into(buf)
+            .map(|(from, len)| (len, from)))
+    }
+    fn send(&self, buf: &[u8], addr: SocketAddr) -> core::result::Result<usize, T::IntoError> {
+        self.shared.send(buf, addr)
+    }
+    fn send_segments(
+        &self,
+        buf: &[u8],
+        segment_size: usize,
+        addr: SocketAddr,
+    ) -> core::result::Result<usize, T::IntoError> {
+        self.shared.send_segments(buf, segment_size, addr)
+    }
+    fn open_connection(&self, addr: SocketAddr) -> core::result::Result<(), T::IntoError> {
+        self.shared.open_connection(addr)
+    }
+    // The connection may be shared with other sessions to the same server, it is closed with the shared transceiver.
+    fn close_connection(&self, _addr: SocketAddr) -> core::result::Result<(), T::IntoError> {
+        Ok(())
+    }
+}
+
+/// A client that holds sessions to several servers at once over one socket, e.g. to a gateway and a zone server.
+///
+/// Each session is a [`Client`](Client) of its own, with its own connect token, keys, sequence numbers and configuration,
+/// addressed by the [`SessionHandle`](SessionHandle) returned when it is connected.
+/// [`update`](MultiClient::update) receives the packets of all sessions from the shared socket, hands each to the sessions
+/// talking to the server it came from, and updates every session.
+///
+/// Sessions to the same server address are each handed its packets, and drop the ones encrypted for another session.
+///
+/// # Example
+/// ```
+/// use netcode::{ClientConfig, MultiClient, Server};
+///
+/// let private_key = netcode::generate_key();
+/// let mut gateway = Server::new("127.0.0.1:0", 0x11223344, private_key).unwrap();
+/// let mut zone = Server::new("127.0.0.1:0", 0x11223344, private_key).unwrap();
+/// # let gateway_token = gateway.token(7).generate().unwrap().try_into_bytes().unwrap();
+/// # let zone_token = zone.token(7).generate().unwrap().try_into_bytes().unwrap();
+///
+/// let mut client = MultiClient::new().unwrap();
+/// // with connect tokens from the web backend for each server
+/// let to_gateway = client.connect(&gateway_token, ClientConfig::default()).unwrap();
+/// let to_zone = client.connect(&zone_token, ClientConfig::default()).unwrap();
+///
+/// client.update(0.0);
+/// if let Some(session) = client.session_mut(to_zone) {
+///     if session.is_connected() {
+///         session.send(b"enter zone").unwrap();
+///     }
+/// }
+/// assert_eq!(client.len(), 2);
+///
+/// client.disconnect(to_gateway).unwrap();
+/// assert_eq!(client.sessions().collect::<Vec<_>>(), [to_zone]);
+/// ```
+pub struct MultiClient<T: Transceiver, Ctx = ()> {
+    transceiver: Arc<T>,
+    sessions: BTreeMap<SessionHandle, Client<SessionTransceiver<T>, Ctx>>,
+    next_handle: u64,
+}
+
+#[cfg(all(feature = "std", not(target_family = "wasm")))]
+impl<Ctx> MultiClient<NetcodeSocket, Ctx> {
+    /// Create a multi-client with a socket bound to an ephemeral port, like [`Client::new`](Client::new).
+    pub fn new() -> Result<Self> {
+        let socket = NetcodeSocket::new((Ipv4Addr::UNSPECIFIED, 0), SEND_BUF_SIZE, RECV_BUF_SIZE)?;
+        Ok(MultiClient::with_transceiver(socket))
+    }
+}
+
+impl<T: Transceiver, Ctx> MultiClient<T, Ctx> {
+    /// Create a multi-client whose sessions share a custom transceiver.
+    pub fn with_transceiver(trx: T) -> Self {
+        Self {
+            transceiver: Arc::new(trx),
+            sessions: BTreeMap::new(),
+            next_handle: 0,
+        }
+    }
+    /// Adds a session with its own connect token and configuration, and starts connecting it on the next [`update`](MultiClient::update).
+    ///
+    /// Returns an error if the connect token is invalid.
+    pub fn connect(&mut self, token_bytes: &[u8], cfg: ClientConfig<Ctx>) -> Result<SessionHandle> {
+        let trx = SessionTransceiver {
+            shared: self.transceiver.clone(),
+            inbox: RefCell::new(PayloadQueue::with_capacity(0)),
+        };
+        let mut client = Client::with_config_and_transceiver(token_bytes, cfg, trx)?;
+        client.connect();
+        let handle = SessionHandle(self.next_handle);
+        self.next_handle += 1;
+        log::debug!("multi-client added session {handle}");
+        self.sessions.insert(handle, client);
+        Ok(handle)
+    }
+    /// Updates the multi-client.
+    ///
+    /// Receives the packets of all sessions, then updates each session like [`Client::update`](Client::update).
+    ///
+    /// # Panics
+    /// Panics if the socket can't send or receive packets.
+    /// For a non-panicking version, use [`try_update`](MultiClient::try_update).
+    pub fn update(&mut self, time: f64) {
+        self.try_update(time)
+            .expect("send/recv error while updating multi-client")
+    }
+    /// The fallible version of [`update`](MultiClient::update).
+    ///
+    /// Returns an error if the socket can't send or receive packets.
+    pub fn try_update(&mut self, time: f64) -> Result<()> {
+        self.route_packets()?;
+        for client in self.sessions.values_mut() {
+            client.try_update(time)?;
+        }
+        Ok(())
+    }
+    fn route_packets(&mut self) -> Result<()> {
+        let mut buf = [0u8; MAX_PKT_BUF_SIZE];
+        loop {
+            let (size, addr) = match self.transceiver.recv(&mut buf).map_err(Into::<Error>::into) {
+                Ok(Some(received)) => received,
+                Ok(None) => return Ok(()),
+                Err(e) if e.is_port_unreachable() => {
+                    // the error doesn't say which server it is for, the session times out instead
+                    log::debug!("multi-client ignored port unreachable for an earlier send: {e}");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let mut routed = false;
+            for client in self.sessions.values().filter(|c| c.is_server_addr(addr)) {
+                client
+                    .transceiver()
+                    .inbox
+                    .borrow_mut()
+                    .push(&buf[..size], addr);
+                routed = true;
+            }
+            if !routed {
+                log::debug!("multi-client ignored packet from {addr}, no session is talking to it");
+            }
+        }
+    }
+    /// Gets a session, or `None` if it was removed.
+    pub fn session(&self, handle: SessionHandle) -> Option<&Client<SessionTransceiver<T>, Ctx>> {
+        self.sessions.get(&handle)
+    }
+    /// Gets a session to send and receive payloads, or `None` if it was removed.
+    pub fn session_mut(
+        &mut self,
+        handle: SessionHandle,
+    ) -> Option<&mut Client<SessionTransceiver<T>, Ctx>> {
+        self.sessions.get_mut(&handle)
+    }
+    /// Gets the handles of all sessions, in the order they were added.
+    pub fn sessions(&self) -> impl Iterator<Item = SessionHandle> + '_ {
+        self.sessions.keys().copied()
+    }
+    /// Disconnects a session from its server, see [`Client::disconnect`](Client::disconnect), and removes it.
+    ///
+    /// Does nothing if the session was already removed.
+    pub fn disconnect(&mut self, handle: SessionHandle) -> Result<()> {
+        let Some(mut client) = self.sessions.remove(&handle) else {
+            return Ok(());
+        };
+        log::debug!("multi-client disconnecting session {handle}");
+        client.disconnect()
+    }
+    /// Removes a session without notifying its server, e.g. once it is in an error state, and returns it.
+    pub fn remove(&mut self, handle: SessionHandle) -> Option<Client<SessionTransceiver<T>, Ctx>> {
+        self.sessions.remove(&handle)
+    }
+    /// Gets the number of sessions.
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+    /// Returns true if the multi-client has no sessions.
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+    /// Gets the local `SocketAddr` that the sessions share.
+    pub fn addr(&self) -> SocketAddr {
+        self.transceiver.addr()
+    }
+}
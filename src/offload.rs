@@ -0,0 +1,452 @@
+//! UDP segmentation offload and receive offload, with `UDP_SEGMENT`/`UDP_GRO` on Linux and
+//! `UDP_SEND_MSG_SIZE`/`UDP_RECV_MAX_COALESCED_SIZE` on Windows,
+//! see [`SocketOptions::segmentation_offload`](crate::SocketOptions::segmentation_offload).
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+pub(crate) use sys::{enable_gro, is_unsupported, send_segments, supports_gso};
+
+// The most segments sent in one batch (`UDP_MAX_SEGMENTS` on Linux).
+pub(crate) const MAX_SEGMENTS: usize = 64;
+// The most bytes of one send, the largest UDP payload over IPv4.
+pub(crate) const MAX_BATCH_BYTES: usize = 65507;
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::mem::{self, size_of};
+    use std::net::{SocketAddr, UdpSocket};
+    use std::os::fd::AsRawFd;
+    use std::ptr;
+
+    use socket2::SockAddr;
+
+    fn getsockopt_int(
+        socket: &UdpSocket,
+        level: libc::c_int,
+        name: libc::c_int,
+    ) -> io::Result<i32> {
+        let mut value: libc::c_int = 0;
+        let mut len = size_of::<libc::c_int>() as libc::socklen_t;
+        // SAFETY: the kernel writes at most `len` bytes to `value`.
+        let res = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                ptr::addr_of_mut!(value).cast(),
+                &mut len,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(value)
+    }
+
+    fn setsockopt_int(
+        socket: &UdpSocket,
+        level: libc::c_int,
+        name: libc::c_int,
+        value: libc::c_int,
+    ) -> io::Result<()> {
+        // SAFETY: the kernel reads `size_of::<c_int>()` bytes from `value`.
+        let res = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                level,
+                name,
+                ptr::addr_of!(value).cast(),
+                size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Returns true if the kernel can send batches of segments (Linux 4.18 or later).
+    pub(crate) fn supports_gso(socket: &UdpSocket) -> bool {
+        getsockopt_int(socket, libc::SOL_UDP, libc::UDP_SEGMENT).is_ok()
+    }
+
+    /// Asks the kernel to coalesce received datagrams (Linux 5.0 or later), returns false if it can't.
+    pub(crate) fn enable_gro(socket: &UdpSocket) -> bool {
+        setsockopt_int(socket, libc::SOL_UDP, libc::UDP_GRO, 1).is_ok()
+    }
+
+    /// Whether a failed batch means the device can't offload (e.g. no checksum offload), or the segment is too large.
+    pub(crate) fn is_unsupported(e: &io::Error) -> bool {
+        matches!(e.raw_os_error(), Some(libc::EIO | libc::EINVAL))
+    }
+
+    /// Sends the segments of `buf` to `addr` with one `sendmsg`, each `segment_size` bytes except the last.
+    pub(crate) fn send_segments(
+        socket: &UdpSocket,
+        buf: &[u8],
+        segment_size: u16,
+        addr: SocketAddr,
+    ) -> io::Result<usize> {
+        let addr = SockAddr::from(addr);
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut _,
+            iov_len: buf.len(),
+        };
+        // room for one control message, aligned like a `cmsghdr`
+        let mut control = [0u64; 4];
+        // SAFETY: all zeros is a valid (empty) message header.
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = addr.as_ptr() as *mut _;
+        msg.msg_namelen = addr.len();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        // SAFETY: the control buffer has room for the header and a u16, which are written within it.
+        unsafe {
+            msg.msg_controllen = libc::CMSG_SPACE(size_of::<u16>() as u32) as _;
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_UDP;
+            (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(size_of::<u16>() as u32) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast::<u16>(), segment_size);
+        }
+        // SAFETY: the message points at the address, buffer and control message, which outlive the call.
+        let sent = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(sent as usize)
+    }
+
+    /// Receives a datagram into `buf`, returns its length, the size of the segments it coalesces and the sender.
+    pub(super) fn recv_coalesced(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, usize, Option<SocketAddr>)> {
+        // SAFETY: all zeros is a valid (empty) address.
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr().cast(),
+            iov_len: buf.len(),
+        };
+        let mut control = [0u64; 8];
+        // SAFETY: all zeros is a valid (empty) message header.
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = ptr::addr_of_mut!(storage).cast();
+        msg.msg_namelen = size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = mem::size_of_val(&control) as _;
+        // SAFETY: the message points at the address, buffer and control buffer, which outlive the call.
+        let len = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let len = len as usize;
+        let mut segment_size = len;
+        // SAFETY: the kernel wrote `msg_controllen` bytes of control messages, which the macros stay within.
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+                    segment_size =
+                        ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::c_int>()) as usize;
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        // SAFETY: the kernel wrote the sender's address and its length.
+        let from = unsafe { SockAddr::new(storage, msg.msg_namelen) }.as_socket();
+        Ok((len, segment_size, from))
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::io;
+    use std::mem::{self, align_of, size_of};
+    use std::net::{SocketAddr, UdpSocket};
+    use std::os::windows::io::AsRawSocket;
+    use std::ptr;
+    use std::sync::OnceLock;
+
+    use socket2::SockAddr;
+    use windows_sys::Win32::Networking::WinSock::{
+        getsockopt, setsockopt, WSAGetLastError, WSAIoctl, WSASendMsg, CMSGHDR, IPPROTO_UDP,
+        LPFN_WSARECVMSG, SIO_GET_EXTENSION_FUNCTION_POINTER, SOCKADDR_STORAGE, SOCKET,
+        SOCKET_ERROR, UDP_COALESCED_INFO, UDP_RECV_MAX_COALESCED_SIZE, UDP_SEND_MSG_SIZE, WSABUF,
+        WSAEINVAL, WSAENOPROTOOPT, WSAID_WSARECVMSG, WSAMSG,
+    };
+
+    // The `WSA_CMSG_*` macros of `ws2def.h`, which control messages are laid out with.
+    const fn cmsg_align(len: usize) -> usize {
+        (len + align_of::<CMSGHDR>() - 1) & !(align_of::<CMSGHDR>() - 1)
+    }
+    const fn cmsg_data_align(len: usize) -> usize {
+        (len + align_of::<usize>() - 1) & !(align_of::<usize>() - 1)
+    }
+    const fn cmsg_space(len: usize) -> usize {
+        cmsg_data_align(size_of::<CMSGHDR>() + cmsg_align(len))
+    }
+    const fn cmsg_len(len: usize) -> usize {
+        cmsg_data_align(size_of::<CMSGHDR>()) + len
+    }
+    // SAFETY: `cmsg` must point at a control message header.
+    unsafe fn cmsg_data(cmsg: *const CMSGHDR) -> *mut u8 {
+        (cmsg as *mut u8).add(cmsg_data_align(size_of::<CMSGHDR>()))
+    }
+    // SAFETY: `msg.Control` must point at `msg.Control.len` bytes of control messages, and `cmsg` at one of them.
+    unsafe fn cmsg_next(msg: &WSAMSG, cmsg: *const CMSGHDR) -> *const CMSGHDR {
+        let start = msg.Control.buf as usize;
+        let end = start + msg.Control.len as usize;
+        let next = match cmsg.is_null() {
+            true => start,
+            false => cmsg as usize + cmsg_align((*cmsg).cmsg_len),
+        };
+        match next + size_of::<CMSGHDR>() <= end {
+            true => next as *const CMSGHDR,
+            false => ptr::null(),
+        }
+    }
+
+    fn raw(socket: &UdpSocket) -> SOCKET {
+        socket.as_raw_socket() as SOCKET
+    }
+
+    fn last_error() -> io::Error {
+        // SAFETY: only reads the calling thread's last error.
+        io::Error::from_raw_os_error(unsafe { WSAGetLastError() })
+    }
+
+    fn getsockopt_u32(socket: &UdpSocket, level: i32, name: i32) -> io::Result<u32> {
+        let mut value = 0u32;
+        let mut len = size_of::<u32>() as i32;
+        // SAFETY: the socket writes at most `len` bytes to `value`.
+        let res = unsafe {
+            getsockopt(
+                raw(socket),
+                level,
+                name,
+                ptr::addr_of_mut!(value).cast(),
+                &mut len,
+            )
+        };
+        if res == SOCKET_ERROR {
+            return Err(last_error());
+        }
+        Ok(value)
+    }
+
+    fn setsockopt_u32(socket: &UdpSocket, level: i32, name: i32, value: u32) -> io::Result<()> {
+        // SAFETY: the socket reads `size_of::<u32>()` bytes from `value`.
+        let res = unsafe {
+            setsockopt(
+                raw(socket),
+                level,
+                name,
+                ptr::addr_of!(value).cast(),
+                size_of::<u32>() as i32,
+            )
+        };
+        if res == SOCKET_ERROR {
+            return Err(last_error());
+        }
+        Ok(())
+    }
+
+    // `WSARecvMsg` isn't exported by ws2_32, it is looked up through the socket (once, it's the same for every socket).
+    fn wsa_recv_msg(socket: &UdpSocket) -> LPFN_WSARECVMSG {
+        static RECV_MSG: OnceLock<LPFN_WSARECVMSG> = OnceLock::new();
+        *RECV_MSG.get_or_init(|| {
+            let guid = WSAID_WSARECVMSG;
+            let mut recv_msg: LPFN_WSARECVMSG = None;
+            let mut len = 0;
+            // SAFETY: the ioctl reads the guid and writes at most a function pointer to `recv_msg`.
+            let res = unsafe {
+                WSAIoctl(
+                    raw(socket),
+                    SIO_GET_EXTENSION_FUNCTION_POINTER,
+                    ptr::addr_of!(guid).cast(),
+                    mem::size_of_val(&guid) as u32,
+                    ptr::addr_of_mut!(recv_msg).cast(),
+                    mem::size_of_val(&recv_msg) as u32,
+                    &mut len,
+                    ptr::null_mut(),
+                    None,
+                )
+            };
+            if res == SOCKET_ERROR {
+                return None;
+            }
+            recv_msg
+        })
+    }
+
+    /// Returns true if the OS can send batches of segments (Windows 10 2004 or later, Windows Server 2022).
+    pub(crate) fn supports_gso(socket: &UdpSocket) -> bool {
+        getsockopt_u32(socket, IPPROTO_UDP, UDP_SEND_MSG_SIZE).is_ok()
+    }
+
+    /// Asks the OS to coalesce received datagrams, returns false if it can't.
+    pub(crate) fn enable_gro(socket: &UdpSocket) -> bool {
+        wsa_recv_msg(socket).is_some()
+            && setsockopt_u32(
+                socket,
+                IPPROTO_UDP,
+                UDP_RECV_MAX_COALESCED_SIZE,
+                u16::MAX as u32,
+            )
+            .is_ok()
+    }
+
+    /// Whether a failed batch means the socket can't offload it, or the segment is too large.
+    pub(crate) fn is_unsupported(e: &io::Error) -> bool {
+        matches!(e.raw_os_error(), Some(WSAEINVAL | WSAENOPROTOOPT))
+    }
+
+    /// Sends the segments of `buf` to `addr` with one `WSASendMsg`, each `segment_size` bytes except the last.
+    pub(crate) fn send_segments(
+        socket: &UdpSocket,
+        buf: &[u8],
+        segment_size: u16,
+        addr: SocketAddr,
+    ) -> io::Result<usize> {
+        let addr = SockAddr::from(addr);
+        let mut wsa_buf = WSABUF {
+            len: buf.len() as u32,
+            buf: buf.as_ptr() as *mut _,
+        };
+        // room for one control message, aligned like a `CMSGHDR`
+        let mut control = [0u64; 4];
+        let msg = WSAMSG {
+            name: addr.as_ptr() as *mut _,
+            namelen: addr.len(),
+            lpBuffers: &mut wsa_buf,
+            dwBufferCount: 1,
+            Control: WSABUF {
+                len: cmsg_space(size_of::<u32>()) as u32,
+                buf: control.as_mut_ptr().cast(),
+            },
+            dwFlags: 0,
+        };
+        // SAFETY: the control buffer has room for the header and a u32, which are written within it.
+        unsafe {
+            let cmsg = cmsg_next(&msg, ptr::null()) as *mut CMSGHDR;
+            (*cmsg).cmsg_level = IPPROTO_UDP;
+            (*cmsg).cmsg_type = UDP_SEND_MSG_SIZE;
+            (*cmsg).cmsg_len = cmsg_len(size_of::<u32>());
+            ptr::write_unaligned(cmsg_data(cmsg).cast::<u32>(), segment_size as u32);
+        }
+        let mut sent = 0;
+        // SAFETY: the message points at the address, buffer and control message, which outlive the call.
+        let res = unsafe { WSASendMsg(raw(socket), &msg, 0, &mut sent, ptr::null_mut(), None) };
+        if res == SOCKET_ERROR {
+            return Err(last_error());
+        }
+        Ok(sent as usize)
+    }
+
+    /// Receives a datagram into `buf`, returns its length, the size of the segments it coalesces and the sender.
+    pub(super) fn recv_coalesced(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, usize, Option<SocketAddr>)> {
+        let Some(recv_msg) = wsa_recv_msg(socket) else {
+            return Err(io::ErrorKind::Unsupported.into());
+        };
+        // SAFETY: all zeros is a valid (empty) address.
+        let mut storage: SOCKADDR_STORAGE = unsafe { mem::zeroed() };
+        let mut wsa_buf = WSABUF {
+            len: buf.len() as u32,
+            buf: buf.as_mut_ptr(),
+        };
+        let mut control = [0u64; 8];
+        let mut msg = WSAMSG {
+            name: ptr::addr_of_mut!(storage).cast(),
+            namelen: size_of::<SOCKADDR_STORAGE>() as i32,
+            lpBuffers: &mut wsa_buf,
+            dwBufferCount: 1,
+            Control: WSABUF {
+                len: mem::size_of_val(&control) as u32,
+                buf: control.as_mut_ptr().cast(),
+            },
+            dwFlags: 0,
+        };
+        let mut len = 0;
+        // SAFETY: the message points at the address, buffer and control buffer, which outlive the call.
+        let res = unsafe { recv_msg(raw(socket), &mut msg, &mut len, ptr::null_mut(), None) };
+        if res == SOCKET_ERROR {
+            return Err(last_error());
+        }
+        let len = len as usize;
+        let mut segment_size = len;
+        // SAFETY: the socket wrote `Control.len` bytes of control messages, which `cmsg_next` stays within.
+        unsafe {
+            let mut cmsg = cmsg_next(&msg, ptr::null());
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == IPPROTO_UDP
+                    && (*cmsg).cmsg_type == UDP_COALESCED_INFO as i32
+                {
+                    segment_size = ptr::read_unaligned(cmsg_data(cmsg).cast::<u32>()) as usize;
+                }
+                cmsg = cmsg_next(&msg, cmsg);
+            }
+        }
+        // SAFETY: the socket wrote the sender's address and its length.
+        let from = unsafe { SockAddr::new(storage, msg.namelen) }.as_socket();
+        Ok((len, segment_size, from))
+    }
+}
+
+/// A datagram received with receive offload, which may be several coalesced datagrams from the same sender
+/// that are returned one at a time.
+pub(crate) struct Coalesced {
+    buf: Box<[u8]>,
+    len: usize,
+    pos: usize,
+    segment_size: usize,
+    from: Option<SocketAddr>,
+}
+
+impl Coalesced {
+    pub(crate) fn new() -> Self {
+        Self {
+            buf: vec![0; u16::MAX as usize].into_boxed_slice(),
+            len: 0,
+            pos: 0,
+            segment_size: 0,
+            from: None,
+        }
+    }
+    /// Receives the next datagram into `buf`, from the coalesced ones if any are left.
+    pub(crate) fn recv(
+        &mut self,
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<Option<(usize, SocketAddr)>> {
+        if self.pos >= self.len {
+            self.fill(socket)?;
+        }
+        let end = (self.pos + self.segment_size).min(self.len);
+        let segment = &self.buf[self.pos..end];
+        self.pos = end;
+        let Some(from) = self.from else {
+            return Ok(None);
+        };
+        // like a UDP socket, the rest of a datagram that doesn't fit in the buffer is discarded
+        let len = segment.len().min(buf.len());
+        buf[..len].copy_from_slice(&segment[..len]);
+        Ok(Some((len, from)))
+    }
+    fn fill(&mut self, socket: &UdpSocket) -> io::Result<()> {
+        let (len, segment_size, from) = sys::recv_coalesced(socket, &mut self.buf)?;
+        self.len = len;
+        self.pos = 0;
+        self.segment_size = segment_size.max(1);
+        self.from = from.filter(|_| len > 0);
+        Ok(())
+    }
+}
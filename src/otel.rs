@@ -0,0 +1,107 @@
+//! Export of the connection metrics and client handshake spans to [OpenTelemetry](https://docs.rs/opentelemetry).
+//!
+//! Everything here is a no-op unless the `opentelemetry` feature is enabled, in which case the metrics
+//! (with the same names and attributes as in [`metrics`](crate::metrics)) are recorded with the global meter provider,
+//! and each client connection attempt is traced as a `netcode.connect` span with the global tracer provider.
+//! Install the providers (e.g. with `opentelemetry-otlp`) before creating clients and servers.
+
+use core::net::SocketAddr;
+
+#[cfg(feature = "opentelemetry")]
+use opentelemetry::{
+    global::{self, BoxedSpan},
+    metrics::{Counter, Histogram},
+    trace::{Span, Status, Tracer},
+    KeyValue,
+};
+
+#[cfg(feature = "opentelemetry")]
+const SCOPE: &str = "netcode";
+
+#[cfg(feature = "opentelemetry")]
+pub(crate) struct Instruments {
+    pub(crate) packets_sent: Counter<u64>,
+    pub(crate) bytes_sent: Counter<u64>,
+    pub(crate) packets_received: Counter<u64>,
+    pub(crate) bytes_received: Counter<u64>,
+    pub(crate) packets_dropped: Counter<u64>,
+    pub(crate) connect_successes: Counter<u64>,
+    pub(crate) connect_failures: Counter<u64>,
+    pub(crate) update_duration: Histogram<f64>,
+}
+
+#[cfg(feature = "opentelemetry")]
+pub(crate) fn instruments() -> &'static Instruments {
+    static INSTRUMENTS: std::sync::OnceLock<Instruments> = std::sync::OnceLock::new();
+    INSTRUMENTS.get_or_init(|| {
+        let meter = global::meter(SCOPE);
+        Instruments {
+            packets_sent: meter.u64_counter("netcode_packets_sent_total").build(),
+            bytes_sent: meter
+                .u64_counter("netcode_bytes_sent_total")
+                .with_unit("By")
+                .build(),
+            packets_received: meter.u64_counter("netcode_packets_received_total").build(),
+            bytes_received: meter
+                .u64_counter("netcode_bytes_received_total")
+                .with_unit("By")
+                .build(),
+            packets_dropped: meter.u64_counter("netcode_packets_dropped_total").build(),
+            connect_successes: meter.u64_counter("netcode_connect_successes_total").build(),
+            connect_failures: meter.u64_counter("netcode_connect_failures_total").build(),
+            update_duration: meter
+                .f64_histogram("netcode_update_duration_seconds")
+                .with_unit("s")
+                .build(),
+        }
+    })
+}
+
+#[cfg(feature = "opentelemetry")]
+pub(crate) fn attrs<const N: usize>(pairs: [(&'static str, &'static str); N]) -> [KeyValue; N] {
+    pairs.map(|(key, value)| KeyValue::new(key, value))
+}
+
+/// A `netcode.connect` span covering one connection attempt of a client to one of the servers in its token,
+/// ended when the client connects or the attempt fails.
+#[derive(Default)]
+pub(crate) struct ConnectSpan {
+    #[cfg(feature = "opentelemetry")]
+    span: Option<BoxedSpan>,
+}
+
+#[cfg_attr(not(feature = "opentelemetry"), allow(unused_variables))]
+impl ConnectSpan {
+    pub(crate) fn start(server: SocketAddr, attempt: usize, num_servers: usize) -> Self {
+        #[cfg(feature = "opentelemetry")]
+        {
+            let mut span = global::tracer(SCOPE).start("netcode.connect");
+            span.set_attribute(KeyValue::new("netcode.server", server.to_string()));
+            span.set_attribute(KeyValue::new("netcode.attempt", attempt as i64));
+            span.set_attribute(KeyValue::new("netcode.num_servers", num_servers as i64));
+            Self { span: Some(span) }
+        }
+        #[cfg(not(feature = "opentelemetry"))]
+        Self::default()
+    }
+    pub(crate) fn event(&mut self, name: &'static str) {
+        #[cfg(feature = "opentelemetry")]
+        if let Some(span) = self.span.as_mut() {
+            span.add_event(name, Vec::new());
+        }
+    }
+    pub(crate) fn succeed(&mut self) {
+        #[cfg(feature = "opentelemetry")]
+        if let Some(mut span) = self.span.take() {
+            span.set_status(Status::Ok);
+            span.end();
+        }
+    }
+    pub(crate) fn fail(&mut self, reason: &'static str) {
+        #[cfg(feature = "opentelemetry")]
+        if let Some(mut span) = self.span.take() {
+            span.set_status(Status::error(reason));
+            span.end();
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Pacing of the packets a server sends, see [`ServerConfig::pace_packets`](crate::ServerConfig::pace_packets).
+//!
+//! The packets sent in one tick (a snapshot and keep-alive for every client) would leave in one burst,
+//! which overflows the shallow buffers of consumer routers. The pacer queues them instead and lets each one out
+//! after an equal share of the time left in the tick, so the last one leaves before the next tick starts.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// How long before a deadline [`sleep_until`] stops sleeping and spins, as the OS wakes threads up late by up to
+/// a scheduler tick: about 1 ms on Linux and macOS, and 15.6 ms on Windows unless the timer resolution was raised.
+const SPIN_MARGIN: Duration = if cfg!(windows) {
+    Duration::from_millis(16)
+} else {
+    Duration::from_millis(1)
+};
+
+/// The packets queued in the current tick and when the next one is due.
+pub(crate) struct Pacer {
+    tick_interval: f64,
+    max_packets_per_tick: usize,
+    queue: VecDeque<(Vec<u8>, SocketAddr)>,
+    queued_this_tick: usize,
+    tick_end: f64,
+    next_release: f64,
+}
+
+impl Pacer {
+    pub(crate) fn new(tick_interval: f64, max_packets_per_tick: usize) -> Self {
+        Self {
+            tick_interval,
+            max_packets_per_tick,
+            queue: VecDeque::new(),
+            queued_this_tick: 0,
+            tick_end: 0.0,
+            next_release: 0.0,
+        }
+    }
+    /// Starts a tick at `time`, returns the packets left over from the last one, which are due right away.
+    pub(crate) fn start_tick(&mut self, time: f64) -> VecDeque<(Vec<u8>, SocketAddr)> {
+        self.tick_end = time + self.tick_interval;
+        self.next_release = time;
+        self.queued_this_tick = 0;
+        core::mem::take(&mut self.queue)
+    }
+    pub(crate) fn set_max_packets_per_tick(&mut self, max_packets_per_tick: usize) {
+        self.max_packets_per_tick = max_packets_per_tick;
+    }
+    /// Queues a packet, returns `false` if the tick's budget of packets is used up.
+    pub(crate) fn push(&mut self, buf: &[u8], addr: SocketAddr) -> bool {
+        if self.queued_this_tick >= self.max_packets_per_tick {
+            return false;
+        }
+        self.queued_this_tick += 1;
+        self.queue.push_back((buf.to_vec(), addr));
+        true
+    }
+    /// Takes the next packet if it is due at `time`.
+    pub(crate) fn pop_due(&mut self, time: f64) -> Option<(Vec<u8>, SocketAddr)> {
+        if time < self.next_release {
+            return None;
+        }
+        let packet = self.queue.pop_front()?;
+        // the packets left share the rest of the tick, all of them are due once it is over
+        let time_left = (self.tick_end - time).max(0.0);
+        self.next_release = time + time_left / (self.queue.len() + 1) as f64;
+        Some(packet)
+    }
+    /// The time the next packet is due, `None` if none are queued.
+    pub(crate) fn next_release(&self) -> Option<f64> {
+        (!self.queue.is_empty()).then_some(self.next_release)
+    }
+}
+
+/// The payload bytes a client may still be sent, see [`Server::set_client_bandwidth_limit`](crate::Server::set_client_bandwidth_limit).
+///
+/// A token bucket that refills at the limit and holds up to a second's worth, so short bursts (e.g. a full snapshot) pass.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ByteBudget {
+    bytes_per_second: usize,
+    available: f64,
+    last_refill: f64,
+}
+
+impl ByteBudget {
+    pub(crate) fn new(bytes_per_second: usize, time: f64) -> Self {
+        Self {
+            bytes_per_second,
+            available: bytes_per_second as f64,
+            last_refill: time,
+        }
+    }
+    pub(crate) fn bytes_per_second(&self) -> usize {
+        self.bytes_per_second
+    }
+    /// Takes `len` bytes at `time`, returns `false` (and takes nothing) if fewer are left.
+    pub(crate) fn take(&mut self, len: usize, time: f64) -> bool {
+        let rate = self.bytes_per_second as f64;
+        if time > self.last_refill {
+            self.available = (self.available + (time - self.last_refill) * rate).min(rate);
+            self.last_refill = time;
+        }
+        if len as f64 > self.available {
+            return false;
+        }
+        self.available -= len as f64;
+        true
+    }
+}
+
+/// Sleeps until `deadline` with a precision of microseconds: the OS sleeps until shortly before it, then the thread spins.
+pub(crate) fn sleep_until(deadline: Instant) {
+    let now = Instant::now();
+    if let Some(sleep) = deadline
+        .checked_duration_since(now)
+        .and_then(|left| left.checked_sub(SPIN_MARGIN))
+    {
+        std::thread::sleep(sleep);
+    }
+    while Instant::now() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreads_packets_over_the_tick() {
+        let addr = "127.0.0.1:40000".parse().unwrap();
+        let mut pacer = Pacer::new(0.75, 3);
+        assert!(pacer.start_tick(10.0).is_empty());
+        for i in 0..4u8 {
+            assert_eq!(pacer.push(&[i], addr), i < 3);
+        }
+        let mut released = Vec::new();
+        for step in 0..6 {
+            let time = 10.0 + f64::from(step) * 0.125;
+            while let Some((buf, _)) = pacer.pop_due(time) {
+                released.push((buf[0], step));
+            }
+        }
+        // a third of the tick apart
+        assert_eq!(released, [(0, 0), (1, 2), (2, 4)]);
+        assert_eq!(pacer.next_release(), None);
+
+        // once the tick is over, the packets left are due together
+        pacer.start_tick(11.0);
+        for i in 3..6 {
+            pacer.push(&[i], addr);
+        }
+        assert_eq!(pacer.pop_due(11.0).unwrap().0, [3]);
+        assert_eq!(pacer.next_release(), Some(11.25));
+        assert_eq!(pacer.pop_due(12.0).unwrap().0, [4]);
+        assert_eq!(pacer.pop_due(12.0).unwrap().0, [5]);
+
+        // leftovers are handed back when the next tick starts
+        pacer.start_tick(13.0);
+        pacer.push(&[6], addr);
+        let leftover: Vec<_> = pacer
+            .start_tick(14.0)
+            .into_iter()
+            .map(|(buf, _)| buf)
+            .collect();
+        assert_eq!(leftover, [[6]]);
+    }
+
+    #[test]
+    fn byte_budget_refills_up_to_a_second() {
+        let mut budget = ByteBudget::new(1000, 5.0);
+        assert!(budget.take(600, 5.0));
+        assert!(!budget.take(600, 5.0));
+        assert!(budget.take(400, 5.0));
+        // a quarter of a second refills a quarter of the limit
+        assert!(budget.take(250, 5.25));
+        assert!(!budget.take(1, 5.25));
+        // an idle client doesn't save up more than a second's worth
+        assert!(!budget.take(1001, 60.0));
+        assert!(budget.take(1000, 60.0));
+    }
+
+    #[test]
+    fn sleeps_until_the_deadline() {
+        let deadline = Instant::now() + Duration::from_millis(5);
+        sleep_until(deadline);
+        assert!(Instant::now() >= deadline);
+    }
+}
@@ -1,21 +1,23 @@
-use std::{
-    io::{self, Read, Write},
-    mem::size_of,
-};
+use alloc::boxed::Box;
+use core::mem::size_of;
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::LittleEndian;
 use chacha20poly1305::XNonce;
 
 use crate::{
     bytes::Bytes,
-    crypto::{self, Key},
+    crypto::{Cipher, Key},
     error::Error as NetcodeError,
+    io::{self, Read, ReadBytesExt, Write, WriteBytesExt},
+    rekey,
     replay::ReplayProtection,
     token::{ChallengeToken, ConnectTokenPrivate},
-    MAC_BYTES, MAX_PKT_BUF_SIZE, NETCODE_VERSION,
+    MAC_BYTES, MAX_HEARTBEAT_BYTES, MAX_PKT_BUF_SIZE, NETCODE_VERSION,
 };
 
+/// An error reading a packet, see [`Error::Packet`](crate::Error::Packet).
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("packet type {0} is invalid")]
     InvalidType(u8),
@@ -35,6 +37,8 @@ pub enum Error {
     TokenExpired,
     #[error("sequence {0} already received")]
     AlreadyReceived(u64),
+    #[error("packet type {0} is not allowed")]
+    NotAllowed(u8),
 }
 
 trait WriteSequence {
@@ -115,7 +119,7 @@ impl RequestPacket {
             self.token_nonce,
             &private_key,
         )?;
-        let mut token_data = std::io::Cursor::new(&mut self.token_data[..]);
+        let mut token_data = io::Cursor::new(&mut self.token_data[..]);
         decrypted.write_to(&mut token_data)?;
         Ok(())
     }
@@ -132,14 +136,14 @@ impl Bytes for RequestPacket {
         Ok(())
     }
 
-    fn read_from(reader: &mut impl byteorder::ReadBytesExt) -> Result<Self, io::Error> {
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
         let mut version_info = [0; NETCODE_VERSION.len()];
         reader.read_exact(&mut version_info)?;
         let protocol_id = reader.read_u64::<LittleEndian>()?;
         let expire_timestamp = reader.read_u64::<LittleEndian>()?;
         let mut nonce = [0; size_of::<XNonce>()];
         reader.read_exact(&mut nonce)?;
-        let token_nonce = XNonce::from_slice(&nonce).to_owned();
+        let token_nonce = XNonce::clone_from_slice(&nonce);
         let mut token_data = [0; ConnectTokenPrivate::SIZE];
         reader.read_exact(&mut token_data)?;
         Ok(Self {
@@ -154,6 +158,7 @@ impl Bytes for RequestPacket {
 
 pub struct DeniedPacket {}
 impl DeniedPacket {
+    #[cfg(feature = "std")]
     pub fn create() -> Packet<'static> {
         Packet::Denied(DeniedPacket {})
     }
@@ -164,7 +169,7 @@ impl Bytes for DeniedPacket {
         Ok(())
     }
 
-    fn read_from(_reader: &mut impl byteorder::ReadBytesExt) -> Result<Self, io::Error> {
+    fn read_from(_reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
         Ok(Self {})
     }
 }
@@ -173,6 +178,7 @@ pub struct ChallengePacket {
     pub sequence: u64,
     pub token: [u8; ChallengeToken::SIZE],
 }
+#[cfg(any(test, feature = "std"))]
 impl ChallengePacket {
     pub fn create(sequence: u64, token_bytes: [u8; ChallengeToken::SIZE]) -> Packet<'static> {
         Packet::Challenge(ChallengePacket {
@@ -190,7 +196,7 @@ impl Bytes for ChallengePacket {
         Ok(())
     }
 
-    fn read_from(reader: &mut impl byteorder::ReadBytesExt) -> Result<Self, io::Error> {
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
         let sequence = reader.read_u64::<LittleEndian>()?;
         let mut token = [0; ChallengeToken::SIZE];
         reader.read_exact(&mut token)?;
@@ -218,7 +224,7 @@ impl Bytes for ResponsePacket {
         Ok(())
     }
 
-    fn read_from(reader: &mut impl byteorder::ReadBytesExt) -> Result<Self, io::Error> {
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
         let sequence = reader.read_u64::<LittleEndian>()?;
         let mut token = [0; ChallengeToken::SIZE];
         reader.read_exact(&mut token)?;
@@ -226,32 +232,46 @@ impl Bytes for ResponsePacket {
     }
 }
 
-pub struct KeepAlivePacket {
+pub struct KeepAlivePacket<'p> {
     pub client_index: i32,
     pub max_clients: i32,
+    /// An application heartbeat appended after the two fields, empty in the reference implementation.
+    pub heartbeat: &'p [u8],
 }
-impl KeepAlivePacket {
+impl KeepAlivePacket<'_> {
+    #[cfg(any(test, feature = "std"))]
     pub fn create(client_index: i32, max_clients: i32) -> Packet<'static> {
         Packet::KeepAlive(KeepAlivePacket {
             client_index,
             max_clients,
+            heartbeat: &[],
+        })
+    }
+    pub fn with_heartbeat(client_index: i32, max_clients: i32, heartbeat: &[u8]) -> Packet<'_> {
+        Packet::KeepAlive(KeepAlivePacket {
+            client_index,
+            max_clients,
+            heartbeat,
         })
     }
 }
-impl Bytes for KeepAlivePacket {
+impl Bytes for KeepAlivePacket<'_> {
     type Error = io::Error;
     fn write_to(&self, writer: &mut impl WriteBytesExt) -> Result<(), Self::Error> {
         writer.write_i32::<LittleEndian>(self.client_index)?;
         writer.write_i32::<LittleEndian>(self.max_clients)?;
+        writer.write_all(self.heartbeat)?;
         Ok(())
     }
 
-    fn read_from(reader: &mut impl byteorder::ReadBytesExt) -> Result<Self, io::Error> {
+    // the heartbeat is the rest of the decrypted packet, see `Packet::read_with`
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
         let client_index = reader.read_i32::<LittleEndian>()?;
         let max_clients = reader.read_i32::<LittleEndian>()?;
         Ok(Self {
             client_index,
             max_clients,
+            heartbeat: &[],
         })
     }
 }
@@ -260,7 +280,7 @@ pub struct PayloadPacket<'p> {
     pub buf: &'p [u8],
 }
 impl PayloadPacket<'_> {
-    pub fn create(buf: &[u8]) -> Packet {
+    pub fn create(buf: &[u8]) -> Packet<'_> {
         Packet::Payload(PayloadPacket { buf })
     }
 }
@@ -277,23 +297,147 @@ impl Bytes for DisconnectPacket {
         Ok(())
     }
 
-    fn read_from(_reader: &mut impl byteorder::ReadBytesExt) -> Result<Self, io::Error> {
+    fn read_from(_reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
         Ok(Self {})
     }
 }
 
+pub struct PayloadLimitPacket {
+    pub max_payload_size: u16,
+}
+impl PayloadLimitPacket {
+    #[cfg(feature = "std")]
+    pub fn create(max_payload_size: u16) -> Packet<'static> {
+        Packet::PayloadLimit(Self { max_payload_size })
+    }
+}
+impl Bytes for PayloadLimitPacket {
+    type Error = io::Error;
+    fn write_to(&self, writer: &mut impl WriteBytesExt) -> Result<(), Self::Error> {
+        writer.write_u16::<LittleEndian>(self.max_payload_size)?;
+        Ok(())
+    }
+
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
+        let max_payload_size = reader.read_u16::<LittleEndian>()?;
+        Ok(Self { max_payload_size })
+    }
+}
+
+/// Not part of the netcode standard: the keep-alive interval the server recommends to a client, in milliseconds.
+pub struct KeepAliveIntervalPacket {
+    pub interval_ms: u32,
+}
+impl KeepAliveIntervalPacket {
+    #[cfg(feature = "std")]
+    pub fn create(interval_ms: u32) -> Packet<'static> {
+        Packet::KeepAliveInterval(Self { interval_ms })
+    }
+}
+impl Bytes for KeepAliveIntervalPacket {
+    type Error = io::Error;
+    fn write_to(&self, writer: &mut impl WriteBytesExt) -> Result<(), Self::Error> {
+        writer.write_u32::<LittleEndian>(self.interval_ms)?;
+        Ok(())
+    }
+
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
+        let interval_ms = reader.read_u32::<LittleEndian>()?;
+        Ok(Self { interval_ms })
+    }
+}
+
+/// Not part of the netcode standard: sent by the server to the new address of a migrating client, which has to echo the nonce.
+pub struct PathChallengePacket {
+    pub nonce: u64,
+}
+impl PathChallengePacket {
+    #[cfg(feature = "std")]
+    pub fn create(nonce: u64) -> Packet<'static> {
+        Packet::PathChallenge(Self { nonce })
+    }
+}
+impl Bytes for PathChallengePacket {
+    type Error = io::Error;
+    fn write_to(&self, writer: &mut impl WriteBytesExt) -> Result<(), Self::Error> {
+        writer.write_u64::<LittleEndian>(self.nonce)?;
+        Ok(())
+    }
+
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
+        let nonce = reader.read_u64::<LittleEndian>()?;
+        Ok(Self { nonce })
+    }
+}
+
+/// Not part of the netcode standard: the client's answer to a [`PathChallengePacket`], carrying the same nonce.
+pub struct PathResponsePacket {
+    pub nonce: u64,
+}
+impl PathResponsePacket {
+    pub fn create(nonce: u64) -> Packet<'static> {
+        Packet::PathResponse(Self { nonce })
+    }
+}
+impl Bytes for PathResponsePacket {
+    type Error = io::Error;
+    fn write_to(&self, writer: &mut impl WriteBytesExt) -> Result<(), Self::Error> {
+        writer.write_u64::<LittleEndian>(self.nonce)?;
+        Ok(())
+    }
+
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
+        let nonce = reader.read_u64::<LittleEndian>()?;
+        Ok(Self { nonce })
+    }
+}
+
+/// Not part of the netcode standard: a fragment of a connect token for another server, see `transfer`.
+pub struct TransferPacket<'p> {
+    pub fragment: u8,
+    pub data: &'p [u8],
+}
+impl TransferPacket<'_> {
+    #[cfg(feature = "std")]
+    pub fn create(fragment: u8, data: &[u8]) -> Packet<'_> {
+        Packet::Transfer(TransferPacket { fragment, data })
+    }
+}
+impl Bytes for TransferPacket<'_> {
+    type Error = io::Error;
+    fn write_to(&self, writer: &mut impl WriteBytesExt) -> Result<(), Self::Error> {
+        writer.write_u8(self.fragment)?;
+        writer.write_all(self.data)?;
+        Ok(())
+    }
+
+    // the data is the rest of the decrypted packet, see `Packet::read_with`
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
+        let fragment = reader.read_u8()?;
+        Ok(Self {
+            fragment,
+            data: &[],
+        })
+    }
+}
+
 pub enum Packet<'p> {
     Request(RequestPacket),
     Denied(DeniedPacket),
     Challenge(ChallengePacket),
     Response(ResponsePacket),
-    KeepAlive(KeepAlivePacket),
+    KeepAlive(KeepAlivePacket<'p>),
     Payload(PayloadPacket<'p>),
     Disconnect(DisconnectPacket),
+    PayloadLimit(PayloadLimitPacket),
+    Transfer(TransferPacket<'p>),
+    KeepAliveInterval(KeepAliveIntervalPacket),
+    PathChallenge(PathChallengePacket),
+    PathResponse(PathResponsePacket),
 }
 
-impl std::fmt::Display for Packet<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for Packet<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Packet::Request(_) => write!(f, "connection request"),
             Packet::Response(_) => write!(f, "connection response"),
@@ -302,6 +446,11 @@ impl std::fmt::Display for Packet<'_> {
             Packet::Disconnect(_) => write!(f, "disconnect packet"),
             Packet::Denied(_) => write!(f, "denied packet"),
             Packet::Challenge(_) => write!(f, "challenge packet"),
+            Packet::PayloadLimit(_) => write!(f, "payload limit packet"),
+            Packet::Transfer(_) => write!(f, "transfer packet"),
+            Packet::KeepAliveInterval(_) => write!(f, "keep-alive interval packet"),
+            Packet::PathChallenge(_) => write!(f, "path challenge packet"),
+            Packet::PathResponse(_) => write!(f, "path response packet"),
         }
     }
 }
@@ -316,7 +465,17 @@ impl<'p> Packet<'p> {
     pub const KEEP_ALIVE: PacketKind = 4;
     pub const PAYLOAD: PacketKind = 5;
     pub const DISCONNECT: PacketKind = 6;
-    fn kind(&self) -> PacketKind {
+    /// Not part of the netcode standard: sent by the server to lower the max payload size of a client.
+    pub const PAYLOAD_LIMIT: PacketKind = 7;
+    /// Not part of the netcode standard: sent by the server to hand a client over to another server.
+    pub const TRANSFER: PacketKind = 8;
+    /// Not part of the netcode standard: sent by the server to recommend a keep-alive interval to a client.
+    pub const KEEP_ALIVE_INTERVAL: PacketKind = 9;
+    /// Not part of the netcode standard: sent by the server to challenge the new address of a migrating client.
+    pub const PATH_CHALLENGE: PacketKind = 10;
+    /// Not part of the netcode standard: sent by a client to answer a path challenge from its new address.
+    pub const PATH_RESPONSE: PacketKind = 11;
+    pub fn kind(&self) -> PacketKind {
         match self {
             Packet::Request(_) => Packet::REQUEST,
             Packet::Denied(_) => Packet::DENIED,
@@ -325,6 +484,32 @@ impl<'p> Packet<'p> {
             Packet::KeepAlive(_) => Packet::KEEP_ALIVE,
             Packet::Payload(_) => Packet::PAYLOAD,
             Packet::Disconnect(_) => Packet::DISCONNECT,
+            Packet::PayloadLimit(_) => Packet::PAYLOAD_LIMIT,
+            Packet::Transfer(_) => Packet::TRANSFER,
+            Packet::KeepAliveInterval(_) => Packet::KEEP_ALIVE_INTERVAL,
+            Packet::PathChallenge(_) => Packet::PATH_CHALLENGE,
+            Packet::PathResponse(_) => Packet::PATH_RESPONSE,
+        }
+    }
+    #[cfg_attr(
+        not(any(feature = "metrics", feature = "opentelemetry")),
+        allow(dead_code)
+    )]
+    pub fn kind_name(kind: PacketKind) -> &'static str {
+        match kind {
+            Packet::REQUEST => "request",
+            Packet::DENIED => "denied",
+            Packet::CHALLENGE => "challenge",
+            Packet::RESPONSE => "response",
+            Packet::KEEP_ALIVE => "keep_alive",
+            Packet::PAYLOAD => "payload",
+            Packet::DISCONNECT => "disconnect",
+            Packet::PAYLOAD_LIMIT => "payload_limit",
+            Packet::TRANSFER => "transfer",
+            Packet::KEEP_ALIVE_INTERVAL => "keep_alive_interval",
+            Packet::PATH_CHALLENGE => "path_challenge",
+            Packet::PATH_RESPONSE => "path_response",
+            _ => "unknown",
         }
     }
     fn set_prefix(&self, sequence: u64) -> u8 {
@@ -338,7 +523,7 @@ impl<'p> Packet<'p> {
         // Encrypt the per-packet packet written with the prefix byte, protocol id and version as the associated data.
         // This must match to decrypt.
         let mut aead = [0u8; NETCODE_VERSION.len() + size_of::<u64>() + size_of::<u8>()];
-        let mut cursor = std::io::Cursor::new(&mut aead[..]);
+        let mut cursor = io::Cursor::new(&mut aead[..]);
         cursor.write_all(NETCODE_VERSION).unwrap();
         cursor.write_u64::<LittleEndian>(protocol_id).unwrap();
         cursor.write_u8(prefix).unwrap();
@@ -347,15 +532,26 @@ impl<'p> Packet<'p> {
     pub fn get_prefix(prefix_byte: u8) -> (usize, PacketKind) {
         ((prefix_byte >> 4) as usize, prefix_byte & 0xF)
     }
+    #[cfg(any(test, feature = "std"))]
     pub fn write(
         &self,
         out: &mut [u8],
         sequence: u64,
         packet_key: &Key,
         protocol_id: u64,
+    ) -> Result<usize, NetcodeError> {
+        self.write_with(out, sequence, &mut Cipher::new(*packet_key), protocol_id)
+    }
+    /// Like [`write`](Packet::write), with the session's cipher instead of its key, for the packets of a session.
+    pub fn write_with(
+        &self,
+        out: &mut [u8],
+        sequence: u64,
+        cipher: &mut Cipher,
+        protocol_id: u64,
     ) -> Result<usize, NetcodeError> {
         let len = out.len();
-        let mut cursor = std::io::Cursor::new(&mut out[..]);
+        let mut cursor = io::Cursor::new(&mut out[..]);
         if let Packet::Request(pkt) = self {
             cursor.write_u8(Packet::REQUEST)?;
             pkt.write_to(&mut cursor)?;
@@ -370,6 +566,11 @@ impl<'p> Packet<'p> {
             Packet::Response(pkt) => pkt.write_to(&mut cursor)?,
             Packet::KeepAlive(pkt) => pkt.write_to(&mut cursor)?,
             Packet::Disconnect(pkt) => pkt.write_to(&mut cursor)?,
+            Packet::PayloadLimit(pkt) => pkt.write_to(&mut cursor)?,
+            Packet::Transfer(pkt) => pkt.write_to(&mut cursor)?,
+            Packet::KeepAliveInterval(pkt) => pkt.write_to(&mut cursor)?,
+            Packet::PathChallenge(pkt) => pkt.write_to(&mut cursor)?,
+            Packet::PathResponse(pkt) => pkt.write_to(&mut cursor)?,
             Packet::Payload(PayloadPacket { buf }) => cursor.write_all(buf)?,
             _ => unreachable!(), // Packet::Request variant is handled above
         }
@@ -378,11 +579,11 @@ impl<'p> Packet<'p> {
         }
         let encryption_end = cursor.position() as usize + MAC_BYTES;
 
-        crypto::chacha_encrypt(
+        cipher.encrypt(
             &mut out[encryption_start..encryption_end],
             Some(&Packet::aead(protocol_id, self.set_prefix(sequence))?),
             sequence,
-            packet_key,
+            rekey::epoch(cipher, self.kind(), sequence),
         )?;
 
         Ok(encryption_end)
@@ -393,7 +594,28 @@ impl<'p> Packet<'p> {
         timestamp: u64,
         key: Key,
         replay_protection: Option<&mut ReplayProtection>,
-        allowed_packets: u8,
+        allowed_packets: u16,
+        strict: bool,
+    ) -> Result<Packet<'p>, NetcodeError> {
+        Packet::read_with(
+            buf,
+            protocol_id,
+            timestamp,
+            &mut Cipher::new(key),
+            replay_protection,
+            allowed_packets,
+            strict,
+        )
+    }
+    /// Like [`read`](Packet::read), with the session's cipher instead of its key (the server's private key for connection requests).
+    pub fn read_with(
+        buf: &'p mut [u8],
+        protocol_id: u64,
+        timestamp: u64,
+        cipher: &mut Cipher,
+        replay_protection: Option<&mut ReplayProtection>,
+        allowed_packets: u16,
+        strict: bool,
     ) -> Result<Packet<'p>, NetcodeError> {
         let buf_len = buf.len();
         if buf_len < 1 {
@@ -402,19 +624,30 @@ impl<'p> Packet<'p> {
         if buf_len > MAX_PKT_BUF_SIZE {
             return Err(Error::TooLarge.into());
         }
-        let mut cursor = std::io::Cursor::new(&mut buf[..]);
+        let mut cursor = io::Cursor::new(&mut buf[..]);
         let prefix_byte = cursor.read_u8()?;
         let (sequence_len, pkt_kind) = Packet::get_prefix(prefix_byte);
-        if allowed_packets & (1 << pkt_kind) == 0 {
+        if pkt_kind >= u16::BITS as u8 || allowed_packets & (1 << pkt_kind) == 0 {
             log::debug!("ignoring packet of type {}, not allowed", pkt_kind);
+            return Err(Error::NotAllowed(pkt_kind).into());
         }
         if prefix_byte == Packet::REQUEST {
             // connection request packet: first byte should be 0x00
+            if strict && buf_len != REQUEST_PACKET_BYTES {
+                return Err(Error::LengthMismatch {
+                    expected: REQUEST_PACKET_BYTES,
+                    actual: buf_len,
+                }
+                .into());
+            }
             let mut packet = RequestPacket::read_from(&mut cursor)?;
             packet.validate(protocol_id, timestamp)?;
-            packet.decrypt_token_data(key)?;
+            packet.decrypt_token_data(*cipher.key())?;
             return Ok(Packet::Request(packet));
         }
+        if !(1..=8).contains(&sequence_len) {
+            return Err(Error::InvalidSequenceBytes(sequence_len as u8).into());
+        }
         if buf_len < size_of::<u8>() + sequence_len + MAC_BYTES {
             // should at least have prefix byte, sequence and mac
             return Err(Error::TooSmall.into());
@@ -430,15 +663,47 @@ impl<'p> Packet<'p> {
 
         let decryption_start = cursor.position() as usize;
         let decryption_end = buf_len;
-        crypto::chacha_decrypt(
+        cipher.decrypt(
             &mut cursor.get_mut()[decryption_start..decryption_end],
             Some(&Packet::aead(protocol_id, prefix_byte)?),
             sequence,
-            &key,
+            rekey::epoch(cipher, pkt_kind, sequence),
         )?;
         // make sure cursor position is at the start of the decrypted data, so we can read it into a valid packet
         cursor.set_position(decryption_start as u64);
 
+        if strict {
+            // the reference implementation rejects packets with trailing (or missing) bytes, and empty payloads
+            let decrypted_len = decryption_end - decryption_start - MAC_BYTES;
+            let expected = match pkt_kind {
+                Packet::DENIED | Packet::DISCONNECT => Some(0),
+                Packet::CHALLENGE | Packet::RESPONSE => {
+                    Some(size_of::<u64>() + ChallengeToken::SIZE)
+                }
+                Packet::KEEP_ALIVE => Some(2 * size_of::<u32>()),
+                // extensions, the reference implementation doesn't know these packet types
+                Packet::PAYLOAD_LIMIT
+                | Packet::TRANSFER
+                | Packet::KEEP_ALIVE_INTERVAL
+                | Packet::PATH_CHALLENGE
+                | Packet::PATH_RESPONSE => return Err(Error::InvalidType(pkt_kind).into()),
+                _ => None,
+            };
+            match expected {
+                Some(expected) if expected != decrypted_len => {
+                    return Err(Error::LengthMismatch {
+                        expected,
+                        actual: decrypted_len,
+                    }
+                    .into())
+                }
+                None if pkt_kind == Packet::PAYLOAD && decrypted_len == 0 => {
+                    return Err(Error::TooSmall.into())
+                }
+                _ => {}
+            }
+        }
+
         if let Some(replay_protection) = replay_protection {
             if pkt_kind >= Packet::KEEP_ALIVE {
                 replay_protection.advance_sequence(sequence);
@@ -450,8 +715,35 @@ impl<'p> Packet<'p> {
             Packet::DENIED => Packet::Denied(DeniedPacket::read_from(&mut cursor)?),
             Packet::CHALLENGE => Packet::Challenge(ChallengePacket::read_from(&mut cursor)?),
             Packet::RESPONSE => Packet::Response(ResponsePacket::read_from(&mut cursor)?),
-            Packet::KEEP_ALIVE => Packet::KeepAlive(KeepAlivePacket::read_from(&mut cursor)?),
+            Packet::KEEP_ALIVE => {
+                let mut pkt = KeepAlivePacket::read_from(&mut cursor)?;
+                let heartbeat_start = cursor.position() as usize;
+                let heartbeat_end = decryption_end - MAC_BYTES;
+                // oversized heartbeats aren't ours to deliver, the keep-alive itself still counts
+                if heartbeat_end - heartbeat_start <= MAX_HEARTBEAT_BYTES {
+                    pkt.heartbeat = &buf[heartbeat_start..heartbeat_end];
+                }
+                Packet::KeepAlive(pkt)
+            }
             Packet::DISCONNECT => Packet::Disconnect(DisconnectPacket::read_from(&mut cursor)?),
+            Packet::PAYLOAD_LIMIT => {
+                Packet::PayloadLimit(PayloadLimitPacket::read_from(&mut cursor)?)
+            }
+            Packet::TRANSFER => {
+                let mut pkt = TransferPacket::read_from(&mut cursor)?;
+                let data_start = cursor.position() as usize;
+                pkt.data = &buf[data_start..decryption_end - MAC_BYTES];
+                Packet::Transfer(pkt)
+            }
+            Packet::KEEP_ALIVE_INTERVAL => {
+                Packet::KeepAliveInterval(KeepAliveIntervalPacket::read_from(&mut cursor)?)
+            }
+            Packet::PATH_CHALLENGE => {
+                Packet::PathChallenge(PathChallengePacket::read_from(&mut cursor)?)
+            }
+            Packet::PATH_RESPONSE => {
+                Packet::PathResponse(PathResponsePacket::read_from(&mut cursor)?)
+            }
             Packet::PAYLOAD => {
                 buf.copy_within(decryption_start..(decryption_end - MAC_BYTES), 0);
                 Packet::Payload(PayloadPacket {
@@ -464,8 +756,15 @@ impl<'p> Packet<'p> {
     }
 }
 
+/// The size of a connection request packet: prefix byte, version info, protocol id, expire timestamp, nonce and the encrypted private token data.
+const REQUEST_PACKET_BYTES: usize = size_of::<u8>()
+    + NETCODE_VERSION.len()
+    + 2 * size_of::<u64>()
+    + size_of::<XNonce>()
+    + ConnectTokenPrivate::SIZE;
+
 pub fn sequence_len(sequence: u64) -> u8 {
-    std::cmp::max(8 - sequence.leading_zeros() as u8 / 8, 1)
+    core::cmp::max(8 - sequence.leading_zeros() as u8 / 8, 1)
 }
 
 #[cfg(test)]
@@ -490,7 +789,7 @@ mod tests {
         assert_eq!(sequence_len(0x80_00_00_00_00_00_00_00), 8);
 
         let sequence = 1u64 << 63;
-        let cursor = &mut std::io::Cursor::new(Vec::new());
+        let cursor = &mut io::Cursor::new(Vec::new());
         cursor.write_sequence(sequence).unwrap();
         assert_eq!(cursor.get_ref().len(), 8);
         cursor.set_position(0);
@@ -515,8 +814,8 @@ mod tests {
             timeout_seconds,
             server_addresses,
             user_data,
-            client_to_server_key: generate_key(),
-            server_to_client_key: generate_key(),
+            client_to_server_key: generate_key().into(),
+            server_to_client_key: generate_key().into(),
         };
 
         let token_data = token_data
@@ -543,6 +842,7 @@ mod tests {
             private_key,
             Some(&mut replay_protection),
             0xff,
+            false,
         )
         .unwrap();
 
@@ -555,7 +855,7 @@ mod tests {
         assert_eq!(req_pkt.expire_timestamp, expire_timestamp);
         assert_eq!(req_pkt.token_nonce, nonce);
 
-        let mut reader = std::io::Cursor::new(&req_pkt.token_data[..]);
+        let mut reader = io::Cursor::new(&req_pkt.token_data[..]);
         let connect_token_private = ConnectTokenPrivate::read_from(&mut reader).unwrap();
         assert_eq!(connect_token_private.client_id, client_id);
         assert_eq!(connect_token_private.timeout_seconds, timeout_seconds);
@@ -590,6 +890,7 @@ mod tests {
             packet_key,
             Some(&mut replay_protection),
             0xff,
+            false,
         )
         .unwrap();
 
@@ -598,6 +899,48 @@ mod tests {
         };
     }
 
+    #[test]
+    fn not_allowed_packet() {
+        let packet_key = generate_key();
+        let protocol_id = 0x1234_5678_9abc_def0;
+        let mut replay_protection = ReplayProtection::new();
+
+        let packet = Packet::Denied(DeniedPacket {});
+
+        let mut buf = [0u8; MAX_PKT_BUF_SIZE];
+        let size = packet.write(&mut buf, 0, &packet_key, protocol_id).unwrap();
+
+        let result = Packet::read(
+            &mut buf[..size],
+            protocol_id,
+            0,
+            packet_key,
+            Some(&mut replay_protection),
+            !(1 << Packet::DENIED),
+            false,
+        );
+        assert!(matches!(
+            result,
+            Err(NetcodeError::Packet(Error::NotAllowed(Packet::DENIED)))
+        ));
+
+        // packet types beyond the allow-list bits are rejected instead of overflowing the shift
+        buf[0] = 0x1F;
+        let result = Packet::read(
+            &mut buf[..size],
+            protocol_id,
+            0,
+            packet_key,
+            None,
+            0xff,
+            false,
+        );
+        assert!(matches!(
+            result,
+            Err(NetcodeError::Packet(Error::NotAllowed(15)))
+        ));
+    }
+
     #[test]
     pub fn challenge_packet() {
         let token = [0u8; ChallengeToken::SIZE];
@@ -620,6 +963,7 @@ mod tests {
             packet_key,
             Some(&mut replay_protection),
             0xff,
+            false,
         )
         .unwrap();
 
@@ -643,6 +987,7 @@ mod tests {
         let packet = Packet::KeepAlive(KeepAlivePacket {
             client_index,
             max_clients,
+            heartbeat: b"tick 42",
         });
 
         let mut buf = [0u8; MAX_PKT_BUF_SIZE];
@@ -657,6 +1002,7 @@ mod tests {
             packet_key,
             Some(&mut replay_protection),
             0xff,
+            false,
         )
         .unwrap();
 
@@ -666,6 +1012,28 @@ mod tests {
 
         assert_eq!(keep_alive_pkt.client_index, client_index);
         assert_eq!(keep_alive_pkt.max_clients, max_clients);
+        assert_eq!(keep_alive_pkt.heartbeat, b"tick 42");
+
+        // a heartbeat longer than the limit is dropped, the keep-alive is still read
+        let heartbeat = [1; MAX_HEARTBEAT_BYTES + 1];
+        let size = KeepAlivePacket::with_heartbeat(client_index, max_clients, &heartbeat)
+            .write(&mut buf, sequence + 1, &packet_key, protocol_id)
+            .unwrap();
+        let packet = Packet::read(
+            &mut buf[..size],
+            protocol_id,
+            0,
+            packet_key,
+            Some(&mut replay_protection),
+            0xff,
+            false,
+        )
+        .unwrap();
+        let Packet::KeepAlive(keep_alive_pkt) = packet else {
+            panic!("wrong packet type");
+        };
+        assert_eq!(keep_alive_pkt.max_clients, max_clients);
+        assert!(keep_alive_pkt.heartbeat.is_empty());
     }
 
     #[test]
@@ -689,6 +1057,7 @@ mod tests {
             packet_key,
             Some(&mut replay_protection),
             0xff,
+            false,
         )
         .unwrap();
 
@@ -719,6 +1088,7 @@ mod tests {
             packet_key,
             Some(&mut replay_protection),
             0xff,
+            false,
         )
         .unwrap();
 
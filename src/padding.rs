@@ -0,0 +1,56 @@
+//! Padding of payloads to fixed sizes, see [`ClientConfig::pad_payloads`](crate::ClientConfig::pad_payloads).
+//!
+//! A payload is prefixed with its length (2 bytes) and followed by zeros up to the smallest bucket it fits in.
+//! Padding happens before the payload is encrypted, so an observer only learns which bucket a payload fits in,
+//! not how large it is.
+
+use alloc::vec::Vec;
+
+/// The bytes padding adds to each payload, besides the padding itself.
+pub(crate) const HEADER_SIZE: usize = 2;
+
+/// Prefixes `payload` with its length and pads it to the smallest of the (sorted) `buckets` it fits in,
+/// or to `max_size` if it fits in none of them.
+pub(crate) fn pad(payload: &[u8], buckets: &[usize], max_size: usize) -> Vec<u8> {
+    let len = HEADER_SIZE + payload.len();
+    let size = buckets
+        .iter()
+        .copied()
+        .find(|&bucket| bucket >= len)
+        .unwrap_or(max_size)
+        .max(len);
+    let mut out = Vec::with_capacity(size);
+    out.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    out.extend_from_slice(payload);
+    out.resize(size, 0);
+    out
+}
+
+/// Strips the length prefix and the padding from `payload`.
+///
+/// Returns `None` if the payload is shorter than its length prefix says.
+pub(crate) fn unpad(payload: &[u8]) -> Option<&[u8]> {
+    let (len, rest) = payload.split_first_chunk::<HEADER_SIZE>()?;
+    rest.get(..u16::from_le_bytes(*len) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pads_to_the_next_bucket() {
+        let buckets = [128, 512, 1200];
+        for (len, size) in [(0, 128), (126, 128), (127, 512), (600, 1200), (1198, 1200)] {
+            let payload = vec![7; len];
+            let padded = pad(&payload, &buckets, 1200);
+            assert_eq!(padded.len(), size);
+            assert_eq!(unpad(&padded), Some(&payload[..]));
+        }
+        // larger than every bucket, padded to the max size
+        assert_eq!(pad(&[7; 200], &[128], 1000).len(), 1000);
+
+        assert_eq!(unpad(&[5, 0, 1, 2]), None);
+        assert_eq!(unpad(&[0]), None);
+    }
+}
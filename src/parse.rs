@@ -0,0 +1,215 @@
+//! The packet parser on its own, without a client, server or socket.
+//!
+//! This is the code every datagram a server receives from the internet goes through before anything else,
+//! exposed so it can be fuzzed and tested independently (see the `fuzz` directory of the repository).
+
+use crate::{
+    crypto::Key,
+    error::Result,
+    packet::Packet,
+    phase::{PacketAllowList, PacketType},
+    replay::ReplayProtection,
+};
+
+/// What a packet is checked against while it is parsed, see [`parse_packet`](parse_packet).
+///
+/// # Example
+/// ```
+/// use netcode::parse::ParseConfig;
+/// use netcode::{PacketAllowList, PacketType};
+///
+/// let cfg = ParseConfig::new(0x11223344, [0; 32])
+///     .timestamp(1_700_000_000)
+///     .allowed_packets(PacketAllowList::new(&[PacketType::Request]));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct ParseConfig {
+    protocol_id: u64,
+    key: Key,
+    timestamp: u64,
+    allowed_packets: PacketAllowList,
+    strict_netcode_1_02: bool,
+}
+
+impl ParseConfig {
+    /// Create a config for packets of `protocol_id`.
+    ///
+    /// `key` decrypts the packets: the server's private key for connection requests (which carry an encrypted connect token),
+    /// otherwise the packet key of the direction the packet was sent in.
+    pub fn new(protocol_id: u64, key: Key) -> Self {
+        Self {
+            protocol_id,
+            key,
+            timestamp: 0,
+            allowed_packets: PacketAllowList::ALL,
+            strict_netcode_1_02: false,
+        }
+    }
+    /// Set the current time (in seconds since the unix epoch) that connection requests are checked for expiry against. <br>
+    /// The default is `0`, so only the protocol's own checks reject a request.
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+    /// Set the packet types that are parsed, packets of any other type are rejected before they are decrypted. <br>
+    /// The default is [`PacketAllowList::ALL`](PacketAllowList::ALL).
+    pub fn allowed_packets(mut self, allowed_packets: PacketAllowList) -> Self {
+        self.allowed_packets = allowed_packets;
+        self
+    }
+    /// Only accept the exact wire format of the netcode 1.02 reference implementation, see
+    /// [`ServerConfig::strict_netcode_1_02`](crate::ServerConfig::strict_netcode_1_02). The default is `false`.
+    pub fn strict_netcode_1_02(mut self, strict: bool) -> Self {
+        self.strict_netcode_1_02 = strict;
+        self
+    }
+}
+
+/// The sequences received on a connection, to reject replayed packets, see [`parse_packet`](parse_packet).
+#[derive(Clone)]
+pub struct ReplayWindow(ReplayProtection);
+
+impl ReplayWindow {
+    /// Create a window that hasn't received any packet yet.
+    pub fn new() -> Self {
+        Self(ReplayProtection::new())
+    }
+    /// The highest sequence received so far, `None` if nothing was received yet.
+    pub fn most_recent_sequence(&self) -> Option<u64> {
+        self.0.most_recent_sequence()
+    }
+}
+
+impl Default for ReplayWindow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A packet that passed every check of the parser.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct ParsedPacket<'p> {
+    pub packet_type: PacketType,
+    /// The sequence from the packet header, `None` for connection requests (which have no sequence).
+    pub sequence: Option<u64>,
+    /// The decrypted payload of payload packets, `None` for other packet types.
+    pub payload: Option<&'p [u8]>,
+}
+
+/// Parses, validates and decrypts a packet the way a client or server does when it receives `buf`.
+///
+/// The packet is decrypted in place, so `buf` is modified even if parsing fails.
+/// If `replay` is given, packets it has already seen are rejected and the packet's sequence is recorded in it.
+///
+/// The parser must return an error for any malformed input, and never panic.
+///
+/// # Example
+/// ```
+/// use netcode::parse::{parse_packet, ParseConfig, ReplayWindow};
+///
+/// let cfg = ParseConfig::new(0x11223344, netcode::generate_key());
+/// let mut replay = ReplayWindow::new();
+/// // a keep-alive with sequence 1, that wasn't encrypted with the key
+/// let mut buf = [0x14, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+/// assert!(parse_packet(&mut buf, &cfg, Some(&mut replay)).is_err());
+/// assert_eq!(replay.most_recent_sequence(), None);
+/// ```
+pub fn parse_packet<'p>(
+    buf: &'p mut [u8],
+    cfg: &ParseConfig,
+    replay: Option<&mut ReplayWindow>,
+) -> Result<ParsedPacket<'p>> {
+    let sequence = header_sequence(buf);
+    let packet = Packet::read(
+        buf,
+        cfg.protocol_id,
+        cfg.timestamp,
+        cfg.key,
+        replay.map(|replay| &mut replay.0),
+        cfg.allowed_packets.bits(),
+        cfg.strict_netcode_1_02,
+    )?;
+    let packet_type =
+        PacketType::from_kind(packet.kind()).expect("parsed packets have a valid type");
+    let payload = match packet {
+        Packet::Payload(payload) => Some(payload.buf),
+        _ => None,
+    };
+    Ok(ParsedPacket {
+        packet_type,
+        sequence: (packet_type != PacketType::Request)
+            .then_some(sequence)
+            .flatten(),
+        payload,
+    })
+}
+
+// Read before the packet is decrypted in place, only meaningful if the packet is parsed successfully.
+fn header_sequence(buf: &[u8]) -> Option<u64> {
+    let (sequence_len, _) = Packet::get_prefix(*buf.first()?);
+    let bytes = buf.get(1..1 + sequence_len)?;
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |seq, &byte| seq << 8 | byte as u64),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::generate_key,
+        packet::{KeepAlivePacket, PayloadPacket},
+        MAX_PKT_BUF_SIZE,
+    };
+
+    const PROTOCOL_ID: u64 = 0x1122334455667788;
+
+    #[test]
+    fn parse_written_packets() {
+        let key = generate_key();
+        let cfg = ParseConfig::new(PROTOCOL_ID, key);
+        let mut replay = ReplayWindow::new();
+
+        let mut buf = [0u8; MAX_PKT_BUF_SIZE];
+        let len = Packet::Payload(PayloadPacket { buf: b"hello" })
+            .write(&mut buf, 300, &key, PROTOCOL_ID)
+            .unwrap();
+        let packet = parse_packet(&mut buf[..len], &cfg, Some(&mut replay)).unwrap();
+        assert_eq!(packet.packet_type, PacketType::Payload);
+        assert_eq!(packet.sequence, Some(300));
+        assert_eq!(packet.payload, Some(&b"hello"[..]));
+        assert_eq!(replay.most_recent_sequence(), Some(300));
+
+        // the same sequence again is a replay
+        let len = KeepAlivePacket::create(0, 1)
+            .write(&mut buf, 300, &key, PROTOCOL_ID)
+            .unwrap();
+        assert!(parse_packet(&mut buf[..len], &cfg, Some(&mut replay)).is_err());
+
+        // the wrong type is rejected
+        let len = KeepAlivePacket::create(0, 1)
+            .write(&mut buf, 301, &key, PROTOCOL_ID)
+            .unwrap();
+        let payload_only = cfg.allowed_packets(PacketAllowList::new(&[PacketType::Payload]));
+        assert!(parse_packet(&mut buf[..len], &payload_only, None).is_err());
+    }
+
+    #[test]
+    fn malformed_packets_are_errors() {
+        let cfg = ParseConfig::new(PROTOCOL_ID, generate_key());
+        assert!(parse_packet(&mut [], &cfg, None).is_err());
+        assert!(parse_packet(&mut [0u8; MAX_PKT_BUF_SIZE + 1], &cfg, None).is_err());
+        // every prefix byte, on truncated and full size packets
+        for prefix in 0..=u8::MAX {
+            for len in [1, 2, 9, 17, 25, 1078, MAX_PKT_BUF_SIZE] {
+                let mut buf = [0u8; MAX_PKT_BUF_SIZE];
+                buf[0] = prefix;
+                assert!(parse_packet(&mut buf[..len], &cfg, None).is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+
+use crate::{
+    capture::{PacketDirection, PacketLogger, PacketRecord},
+    packet::Packet,
+};
+
+// https://www.tcpdump.org/linktypes.html
+const LINKTYPE_RAW: u32 = 101;
+const SNAPLEN: u32 = 65535;
+const UDP_PROTOCOL: u8 = 17;
+
+/// A [`PacketLogger`](PacketLogger) that writes packets to a pcap capture file.
+///
+/// Every packet is written as a raw IP/UDP datagram between the local and remote address,
+/// timestamped with the wall clock time at which it was logged.
+///
+/// # Example
+/// ```no_run
+/// use netcode::{PcapWriter, ServerConfig};
+///
+/// let pcap = PcapWriter::create("server.pcap").unwrap().decrypt_payloads(true);
+/// let cfg = ServerConfig::default().packet_logger(pcap);
+/// ```
+pub struct PcapWriter<W: Write> {
+    writer: W,
+    decrypt_payloads: bool,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    /// Creates (or truncates) a capture file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        PcapWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Creates a capture writer, writing the pcap file header to `writer`.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_u32::<LittleEndian>(0xa1b2_c3d4)?; // magic, microsecond timestamps
+        writer.write_u16::<LittleEndian>(2)?; // version major
+        writer.write_u16::<LittleEndian>(4)?; // version minor
+        writer.write_i32::<LittleEndian>(0)?; // timezone offset
+        writer.write_u32::<LittleEndian>(0)?; // timestamp accuracy
+        writer.write_u32::<LittleEndian>(SNAPLEN)?;
+        writer.write_u32::<LittleEndian>(LINKTYPE_RAW)?;
+        Ok(Self {
+            writer,
+            decrypt_payloads: false,
+        })
+    }
+    /// Write the decrypted payload of payload packets instead of their encrypted contents. <br>
+    /// The packet header (prefix byte and sequence) is kept, so the packets can still be told apart,
+    /// but the MAC is dropped. The default is `false`.
+    pub fn decrypt_payloads(mut self, decrypt_payloads: bool) -> Self {
+        self.decrypt_payloads = decrypt_payloads;
+        self
+    }
+    /// Consumes the writer, returning the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+    fn write_record(&mut self, record: &PacketRecord<'_>) -> io::Result<()> {
+        let mut udp_payload = record.raw.to_vec();
+        if let (true, Some(payload), Some(sequence_len)) = (
+            self.decrypt_payloads,
+            record.payload,
+            record.raw.first().map(|&p| Packet::get_prefix(p).0),
+        ) {
+            udp_payload.truncate(1 + sequence_len);
+            udp_payload.extend_from_slice(payload);
+        }
+        let (src, dst) = match record.direction {
+            PacketDirection::Sent => (record.local_addr, record.remote_addr),
+            PacketDirection::Received => (record.remote_addr, record.local_addr),
+        };
+        let datagram = ip_datagram(src, dst, &udp_payload);
+
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.writer
+            .write_u32::<LittleEndian>(since_epoch.as_secs() as u32)?;
+        self.writer
+            .write_u32::<LittleEndian>(since_epoch.subsec_micros())?;
+        self.writer
+            .write_u32::<LittleEndian>(datagram.len() as u32)?; // captured length
+        self.writer
+            .write_u32::<LittleEndian>(datagram.len() as u32)?; // original length
+        self.writer.write_all(&datagram)
+    }
+}
+
+impl<W: Write> PacketLogger for PcapWriter<W> {
+    fn log(&mut self, record: &PacketRecord<'_>) {
+        if let Err(e) = self.write_record(record) {
+            log::error!("failed to write packet capture record: {e}");
+        }
+    }
+}
+
+fn ip_datagram(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = 8 + payload.len();
+    let mut datagram = Vec::with_capacity(40 + udp_len);
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
+            let mut header = [0u8; 20];
+            header[0] = 0x45; // version 4, 5 words header
+            header[2..4].copy_from_slice(&((20 + udp_len) as u16).to_be_bytes());
+            header[8] = 64; // ttl
+            header[9] = UDP_PROTOCOL;
+            header[12..16].copy_from_slice(&src_ip.octets());
+            header[16..20].copy_from_slice(&dst_ip.octets());
+            let checksum = ipv4_checksum(&header);
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+            datagram.extend_from_slice(&header);
+        }
+        (src_ip, dst_ip) => {
+            let to_v6 = |ip: IpAddr| match ip {
+                IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+                IpAddr::V6(ip) => ip,
+            };
+            let (src_ip, dst_ip): (Ipv6Addr, Ipv6Addr) = (to_v6(src_ip), to_v6(dst_ip));
+            datagram.push(0x60); // version 6
+            datagram.extend_from_slice(&[0, 0, 0]); // traffic class and flow label
+            datagram.extend_from_slice(&(udp_len as u16).to_be_bytes());
+            datagram.push(UDP_PROTOCOL);
+            datagram.push(64); // hop limit
+            datagram.extend_from_slice(&src_ip.octets());
+            datagram.extend_from_slice(&dst_ip.octets());
+        }
+    }
+    // the UDP checksum is left empty, which is valid for IPv4 and ignored by most tools for IPv6
+    datagram.write_u16::<BigEndian>(src.port()).unwrap();
+    datagram.write_u16::<BigEndian>(dst.port()).unwrap();
+    datagram.write_u16::<BigEndian>(udp_len as u16).unwrap();
+    datagram.write_u16::<BigEndian>(0).unwrap();
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+fn ipv4_checksum(header: &[u8; 20]) -> u16 {
+    let sum = header
+        .chunks_exact(2)
+        .map(|word| u16::from_be_bytes([word[0], word[1]]) as u32)
+        .sum::<u32>();
+    let sum = (sum & 0xFFFF) + (sum >> 16);
+    !((sum & 0xFFFF) + (sum >> 16)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pcap_layout() {
+        let local = SocketAddr::from(([127, 0, 0, 1], 40000));
+        let remote = SocketAddr::from(([10, 0, 0, 2], 50000));
+        let raw = [0x24, 0x02, 0x01, 0xAA, 0xBB];
+        let record = PacketRecord::new(PacketDirection::Sent, 1.0, local, remote, &raw, None);
+
+        let mut pcap = PcapWriter::new(Vec::new()).unwrap();
+        pcap.log(&record);
+        let bytes = pcap.into_inner();
+        assert_eq!(&bytes[..4], &[0xd4, 0xc3, 0xb2, 0xa1]);
+        assert_eq!(bytes[20], LINKTYPE_RAW as u8);
+
+        let datagram = &bytes[24 + 16..];
+        assert_eq!(datagram.len(), 20 + 8 + raw.len());
+        assert_eq!(datagram[0], 0x45);
+        assert_eq!(ipv4_checksum(datagram[..20].try_into().unwrap()), 0);
+        assert_eq!(&datagram[12..16], &[127, 0, 0, 1]); // sent: local is the source
+        assert_eq!(&datagram[20..22], &40000u16.to_be_bytes());
+        assert_eq!(&datagram[28..], &raw);
+    }
+}
@@ -0,0 +1,273 @@
+use crate::{
+    client::ClientState,
+    packet::{Packet, PacketKind},
+};
+
+/// The types of packets in the netcode protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PacketType {
+    Request,
+    Denied,
+    Challenge,
+    Response,
+    KeepAlive,
+    Payload,
+    Disconnect,
+    /// Sent by the server to lower a client's max payload size, see [`Server::set_client_max_payload_size`](crate::Server::set_client_max_payload_size).
+    PayloadLimit,
+    /// Sent by the server to hand a client over to another server, see [`Server::transfer_client`](crate::Server::transfer_client).
+    Transfer,
+    /// Sent by the server to recommend a keep-alive interval, see [`ServerConfig::keep_alive_interval`](crate::ServerConfig::keep_alive_interval).
+    KeepAliveInterval,
+    /// Sent by the server to the new address of a client, see [`ServerConfig::allow_migration`](crate::ServerConfig::allow_migration).
+    PathChallenge,
+    /// Sent by a client to answer a path challenge, see [`ServerConfig::allow_migration`](crate::ServerConfig::allow_migration).
+    PathResponse,
+}
+
+impl PacketType {
+    pub(crate) const COUNT: usize = 12;
+
+    pub(crate) fn from_kind(kind: PacketKind) -> Option<Self> {
+        match kind {
+            Packet::REQUEST => Some(PacketType::Request),
+            Packet::DENIED => Some(PacketType::Denied),
+            Packet::CHALLENGE => Some(PacketType::Challenge),
+            Packet::RESPONSE => Some(PacketType::Response),
+            Packet::KEEP_ALIVE => Some(PacketType::KeepAlive),
+            Packet::PAYLOAD => Some(PacketType::Payload),
+            Packet::DISCONNECT => Some(PacketType::Disconnect),
+            Packet::PAYLOAD_LIMIT => Some(PacketType::PayloadLimit),
+            Packet::TRANSFER => Some(PacketType::Transfer),
+            Packet::KEEP_ALIVE_INTERVAL => Some(PacketType::KeepAliveInterval),
+            Packet::PATH_CHALLENGE => Some(PacketType::PathChallenge),
+            Packet::PATH_RESPONSE => Some(PacketType::PathResponse),
+            _ => None,
+        }
+    }
+    pub(crate) fn kind(self) -> PacketKind {
+        match self {
+            PacketType::Request => Packet::REQUEST,
+            PacketType::Denied => Packet::DENIED,
+            PacketType::Challenge => Packet::CHALLENGE,
+            PacketType::Response => Packet::RESPONSE,
+            PacketType::KeepAlive => Packet::KEEP_ALIVE,
+            PacketType::Payload => Packet::PAYLOAD,
+            PacketType::Disconnect => Packet::DISCONNECT,
+            PacketType::PayloadLimit => Packet::PAYLOAD_LIMIT,
+            PacketType::Transfer => Packet::TRANSFER,
+            PacketType::KeepAliveInterval => Packet::KEEP_ALIVE_INTERVAL,
+            PacketType::PathChallenge => Packet::PATH_CHALLENGE,
+            PacketType::PathResponse => Packet::PATH_RESPONSE,
+        }
+    }
+}
+
+/// A set of packet types that are accepted in a connection phase.
+///
+/// Packets of any other type are dropped before they are decrypted, and counted as out-of-phase in the
+/// [`ClientStats`](crate::ClientStats) / [`ServerStats`](crate::ServerStats).
+///
+/// # Example
+/// ```
+/// use netcode::{PacketAllowList, PacketType};
+///
+/// let list = PacketAllowList::new(&[PacketType::KeepAlive, PacketType::Payload]);
+/// assert!(list.allows(PacketType::Payload));
+/// assert!(!list.deny(PacketType::Payload).allows(PacketType::Payload));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PacketAllowList(u16);
+
+impl PacketAllowList {
+    /// An allow-list that rejects every packet.
+    pub const NONE: Self = Self(0);
+    /// An allow-list that accepts every packet type.
+    pub const ALL: Self = Self(u16::MAX >> (u16::BITS as usize - PacketType::COUNT));
+
+    /// Create an allow-list accepting the given packet types.
+    pub fn new(types: &[PacketType]) -> Self {
+        types.iter().fold(Self::NONE, |list, &ty| list.allow(ty))
+    }
+    /// Returns a copy of this allow-list that also accepts `ty`.
+    pub fn allow(self, ty: PacketType) -> Self {
+        Self(self.0 | 1 << ty.kind())
+    }
+    /// Returns a copy of this allow-list that rejects `ty`.
+    pub fn deny(self, ty: PacketType) -> Self {
+        Self(self.0 & !(1 << ty.kind()))
+    }
+    /// Returns true if packets of type `ty` are accepted.
+    pub fn allows(self, ty: PacketType) -> bool {
+        self.0 & (1 << ty.kind()) != 0
+    }
+    pub(crate) fn bits(self) -> u16 {
+        self.0
+    }
+    fn intersect(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+/// The phase of a connection as seen by the server, used to look up which packets it accepts from an address.
+///
+/// See [`ServerConfig::allowed_packets`](crate::ServerConfig::allowed_packets).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ConnectionPhase {
+    /// No connection exists for the address.
+    Unconnected,
+    /// The server sent a connection challenge to the address and is waiting for the challenge response.
+    Pending,
+    /// A client is connected from the address.
+    Connected,
+}
+
+/// Allow-lists per phase, starting from the protocol defaults.
+///
+/// Custom allow-lists can only restrict the defaults: a packet type that is never valid in a phase
+/// (e.g. a connection request from a client that is already connected) is always rejected.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PhaseTable<const N: usize> {
+    defaults: [PacketAllowList; N],
+    allowed: [PacketAllowList; N],
+}
+
+impl<const N: usize> PhaseTable<N> {
+    const fn new(defaults: [PacketAllowList; N]) -> Self {
+        Self {
+            defaults,
+            allowed: defaults,
+        }
+    }
+    fn get(&self, phase: usize) -> PacketAllowList {
+        self.allowed[phase]
+    }
+    fn set(&mut self, phase: usize, list: PacketAllowList) {
+        self.allowed[phase] = list.intersect(self.defaults[phase]);
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClientPhaseTable(PhaseTable<3>);
+
+#[cfg(any(test, feature = "std"))]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ServerPhaseTable(PhaseTable<3>);
+
+const fn list(kinds: &[PacketKind]) -> PacketAllowList {
+    let mut bits = 0;
+    let mut i = 0;
+    while i < kinds.len() {
+        bits |= 1 << kinds[i];
+        i += 1;
+    }
+    PacketAllowList(bits)
+}
+
+impl ClientPhaseTable {
+    pub(crate) const DEFAULT: Self = Self(PhaseTable::new([
+        // SendingConnectionRequest
+        list(&[Packet::DENIED, Packet::CHALLENGE]),
+        // SendingChallengeResponse
+        list(&[Packet::DENIED, Packet::KEEP_ALIVE]),
+        // Connected
+        list(&[
+            Packet::KEEP_ALIVE,
+            Packet::PAYLOAD,
+            Packet::DISCONNECT,
+            Packet::PAYLOAD_LIMIT,
+            Packet::TRANSFER,
+            Packet::KEEP_ALIVE_INTERVAL,
+            Packet::PATH_CHALLENGE,
+        ]),
+    ]));
+
+    fn client_phase(state: ClientState) -> Option<usize> {
+        match state {
+            ClientState::SendingConnectionRequest => Some(0),
+            ClientState::SendingChallengeResponse => Some(1),
+            ClientState::Connected => Some(2),
+            _ => None,
+        }
+    }
+    /// The packets accepted by a client in `state`, clients that are not connecting or connected accept none.
+    pub(crate) fn for_state(&self, state: ClientState) -> PacketAllowList {
+        Self::client_phase(state).map_or(PacketAllowList::NONE, |phase| self.0.get(phase))
+    }
+    pub(crate) fn set_for_state(&mut self, state: ClientState, list: PacketAllowList) {
+        if let Some(phase) = Self::client_phase(state) {
+            self.0.set(phase, list);
+        }
+    }
+}
+
+#[cfg(any(test, feature = "std"))]
+impl ServerPhaseTable {
+    pub(crate) const DEFAULT: Self = Self(PhaseTable::new([
+        // Unconnected
+        list(&[Packet::REQUEST]),
+        // Pending
+        list(&[Packet::REQUEST, Packet::RESPONSE]),
+        // Connected
+        list(&[Packet::KEEP_ALIVE, Packet::PAYLOAD, Packet::DISCONNECT]),
+    ]));
+    /// Packets from an unknown address that are tried against the keys of the connected clients, see `ServerConfig::allow_migration`.
+    #[cfg(feature = "std")]
+    pub(crate) const MIGRATION: PacketAllowList = list(&[Packet::PATH_RESPONSE]);
+
+    fn server_phase(phase: ConnectionPhase) -> usize {
+        match phase {
+            ConnectionPhase::Unconnected => 0,
+            ConnectionPhase::Pending => 1,
+            ConnectionPhase::Connected => 2,
+        }
+    }
+    pub(crate) fn for_phase(&self, phase: ConnectionPhase) -> PacketAllowList {
+        self.0.get(Self::server_phase(phase))
+    }
+    pub(crate) fn set_for_phase(&mut self, phase: ConnectionPhase, list: PacketAllowList) {
+        self.0.set(Self::server_phase(phase), list);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_allow_lists_only_restrict_defaults() {
+        let mut table = ServerPhaseTable::DEFAULT;
+        let everything = PacketAllowList::new(&[
+            PacketType::Request,
+            PacketType::Denied,
+            PacketType::Challenge,
+            PacketType::Response,
+            PacketType::KeepAlive,
+            PacketType::Payload,
+            PacketType::Disconnect,
+        ]);
+        table.set_for_phase(ConnectionPhase::Connected, everything);
+        let connected = table.for_phase(ConnectionPhase::Connected);
+        assert!(connected.allows(PacketType::Payload));
+        assert!(!connected.allows(PacketType::Request));
+
+        table.set_for_phase(
+            ConnectionPhase::Connected,
+            everything.deny(PacketType::Payload),
+        );
+        assert!(!table
+            .for_phase(ConnectionPhase::Connected)
+            .allows(PacketType::Payload));
+
+        let client = ClientPhaseTable::DEFAULT;
+        assert_eq!(
+            client.for_state(ClientState::Disconnected),
+            PacketAllowList::NONE
+        );
+        assert!(!client
+            .for_state(ClientState::SendingConnectionRequest)
+            .allows(PacketType::Payload));
+    }
+}
@@ -0,0 +1,229 @@
+//! The queues of received payloads, which reuse their buffers, see [`Server::recv_into`](crate::Server::recv_into).
+
+use alloc::{collections::VecDeque, vec::Vec};
+
+/// Received payloads waiting to be read, each tagged with a `T` (e.g. the sender's client index).
+///
+/// Payloads read with [`pop_into`](PayloadQueue::pop_into) leave their buffers in a pool, which later payloads are copied into,
+/// so a queue that is always read that way stops allocating once it has seen its largest backlog.
+#[derive(Debug)]
+pub(crate) struct PayloadQueue<T> {
+    queue: VecDeque<(Vec<u8>, T)>,
+    pool: Vec<Vec<u8>>,
+}
+
+impl<T> PayloadQueue<T> {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            queue: VecDeque::with_capacity(capacity),
+            pool: Vec::new(),
+        }
+    }
+    pub(crate) fn push(&mut self, payload: &[u8], tag: T) {
+        let mut buf = self.pool.pop().unwrap_or_default();
+        buf.extend_from_slice(payload);
+        self.queue.push_back((buf, tag));
+    }
+    /// Takes the next payload, its buffer isn't returned to the pool.
+    pub(crate) fn pop(&mut self) -> Option<(Vec<u8>, T)> {
+        self.queue.pop_front()
+    }
+    /// Copies the next payload into `buf`, truncated if it doesn't fit, and returns its tag and the number of bytes copied.
+    pub(crate) fn pop_into(&mut self, buf: &mut [u8]) -> Option<(T, usize)> {
+        let (payload, tag) = self.queue.pop_front()?;
+        let len = payload.len().min(buf.len());
+        buf[..len].copy_from_slice(&payload[..len]);
+        self.recycle(payload);
+        Some((tag, len))
+    }
+    pub(crate) fn clear(&mut self) {
+        while let Some((payload, _)) = self.queue.pop_front() {
+            self.recycle(payload);
+        }
+    }
+    fn recycle(&mut self, mut payload: Vec<u8>) {
+        payload.clear();
+        self.pool.push(payload);
+    }
+}
+
+/// What a server does with a payload from a client whose receive queue is full,
+/// see [`ServerConfig::recv_queue_depth`](crate::ServerConfig::recv_queue_depth).
+#[cfg(any(test, feature = "std"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum QueueOverflow {
+    /// Drop the new payload, keeping the ones already queued.
+    #[default]
+    DropNewest,
+    /// Drop the payload that has been queued the longest, to make room for the new one.
+    DropOldest,
+}
+
+/// Received payloads waiting to be read, in a bounded queue per key (the sender's client index).
+///
+/// Payloads can be read in the order they arrived over all queues, or from one key's queue.
+/// Buffers are reused like in [`PayloadQueue`](PayloadQueue).
+#[cfg(any(test, feature = "std"))]
+#[derive(Debug)]
+pub(crate) struct PayloadQueues {
+    queues: Vec<VecDeque<(u64, Vec<u8>)>>,
+    // the arrival order over all queues, entries of payloads that were read from or dropped by their own queue are skipped
+    order: VecDeque<(usize, u64)>,
+    pool: Vec<Vec<u8>>,
+    next_seq: u64,
+    len: usize,
+    depth: usize,
+    overflow: QueueOverflow,
+}
+
+#[cfg(any(test, feature = "std"))]
+impl PayloadQueues {
+    pub(crate) fn new(depth: usize, overflow: QueueOverflow) -> Self {
+        Self {
+            queues: Vec::new(),
+            order: VecDeque::new(),
+            pool: Vec::new(),
+            next_seq: 0,
+            len: 0,
+            depth: depth.max(1),
+            overflow,
+        }
+    }
+    /// Queues a payload for `key`, returns `false` if its queue was full and a payload was dropped.
+    pub(crate) fn push(&mut self, key: usize, payload: &[u8]) -> bool {
+        if self.queues.len() <= key {
+            self.queues.resize_with(key + 1, VecDeque::new);
+        }
+        let full = self.queues[key].len() >= self.depth;
+        if full {
+            match self.overflow {
+                QueueOverflow::DropNewest => return false,
+                QueueOverflow::DropOldest => {
+                    let (_, oldest) = self.queues[key].pop_front().expect("queue is full");
+                    self.len -= 1;
+                    self.recycle(oldest);
+                }
+            }
+        }
+        let mut buf = self.pool.pop().unwrap_or_default();
+        buf.extend_from_slice(payload);
+        self.queues[key].push_back((self.next_seq, buf));
+        self.order.push_back((key, self.next_seq));
+        self.next_seq += 1;
+        self.len += 1;
+        self.compact();
+        !full
+    }
+    /// Takes the payload that arrived first over all queues.
+    pub(crate) fn pop(&mut self) -> Option<(usize, Vec<u8>)> {
+        while let Some((key, seq)) = self.order.pop_front() {
+            let queue = &mut self.queues[key];
+            if queue.front().is_some_and(|(front, _)| *front == seq) {
+                let (_, payload) = queue.pop_front().expect("queue isn't empty");
+                self.len -= 1;
+                return Some((key, payload));
+            }
+        }
+        None
+    }
+    /// Copies the payload that arrived first over all queues into `buf`, see [`PayloadQueue::pop_into`](PayloadQueue::pop_into).
+    pub(crate) fn pop_into(&mut self, buf: &mut [u8]) -> Option<(usize, usize)> {
+        let (key, payload) = self.pop()?;
+        let len = payload.len().min(buf.len());
+        buf[..len].copy_from_slice(&payload[..len]);
+        self.recycle(payload);
+        Some((key, len))
+    }
+    /// Takes the next payload from `key`'s queue.
+    pub(crate) fn pop_key(&mut self, key: usize) -> Option<Vec<u8>> {
+        let (_, payload) = self.queues.get_mut(key)?.pop_front()?;
+        self.len -= 1;
+        self.compact();
+        Some(payload)
+    }
+    fn recycle(&mut self, mut payload: Vec<u8>) {
+        payload.clear();
+        self.pool.push(payload);
+    }
+    // Skipped entries pile up in the arrival order if payloads are only read with `pop_key`.
+    fn compact(&mut self) {
+        if self.order.len() > 2 * self.len + 64 {
+            let queues = &self.queues;
+            self.order
+                .retain(|(key, seq)| queues[*key].front().is_some_and(|(front, _)| front <= seq));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_are_reused() {
+        let mut queue = PayloadQueue::with_capacity(4);
+        queue.push(b"hello", 1);
+        queue.push(b"world!", 2);
+        let mut buf = [0; 8];
+        assert_eq!(queue.pop_into(&mut buf), Some((1, 5)));
+        assert_eq!(&buf[..5], b"hello");
+        let ptr = queue.pool[0].as_ptr();
+
+        queue.push(b"again", 3);
+        assert!(queue.pool.is_empty());
+        assert_eq!(queue.pop(), Some((b"world!".to_vec(), 2)));
+        let mut small = [0; 2];
+        assert_eq!(queue.pop_into(&mut small), Some((3, 2)));
+        assert_eq!(&small, b"ag");
+        assert_eq!(queue.pool[0].as_ptr(), ptr);
+        assert_eq!(queue.pop_into(&mut buf), None);
+
+        queue.push(b"x", 4);
+        queue.clear();
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.pool.len(), 1);
+    }
+
+    #[test]
+    fn queues_are_bounded_per_key() {
+        for overflow in [QueueOverflow::DropNewest, QueueOverflow::DropOldest] {
+            let mut queues = PayloadQueues::new(2, overflow);
+            assert!(queues.push(3, b"a1"));
+            assert!(queues.push(0, b"b1"));
+            assert!(queues.push(3, b"a2"));
+            // a full queue doesn't affect the others
+            assert!(!queues.push(3, b"a3"));
+            assert!(queues.push(0, b"b2"));
+            let expected: &[(usize, &[u8])] = match overflow {
+                QueueOverflow::DropNewest => &[(3, b"a1"), (0, b"b1"), (3, b"a2"), (0, b"b2")],
+                QueueOverflow::DropOldest => &[(0, b"b1"), (3, b"a2"), (3, b"a3"), (0, b"b2")],
+            };
+            for (key, payload) in expected {
+                assert_eq!(queues.pop(), Some((*key, payload.to_vec())));
+            }
+            assert_eq!(queues.pop(), None);
+        }
+    }
+
+    #[test]
+    fn queues_are_read_per_key() {
+        let mut queues = PayloadQueues::new(usize::MAX, QueueOverflow::default());
+        queues.push(1, b"a1");
+        queues.push(2, b"b1");
+        queues.push(1, b"a2");
+        assert_eq!(queues.pop_key(1), Some(b"a1".to_vec()));
+        assert_eq!(queues.pop_key(5), None);
+        let mut buf = [0; 4];
+        assert_eq!(queues.pop_into(&mut buf), Some((2, 2)));
+        assert_eq!(queues.pop(), Some((1, b"a2".to_vec())));
+        assert_eq!(queues.pop(), None);
+
+        // payloads only read per key don't leave the arrival order growing
+        for i in 0..1000u32 {
+            queues.push(1, &i.to_le_bytes());
+            assert_eq!(queues.pop_key(1), Some(i.to_le_bytes().to_vec()));
+        }
+        assert!(queues.order.len() <= 64);
+    }
+}
@@ -0,0 +1,296 @@
+//! An out-of-band query, so server browsers can ping a server and get its name and player count without a connect token.
+//!
+//! Queries are off by default, a server only answers them with [`ServerConfig::queries`](crate::ServerConfig::queries).
+//! They don't belong to any session: a query is a single datagram that is answered with a single datagram,
+//! starting with a prefix byte no netcode packet can start with, so servers that don't answer queries drop them as invalid packets.
+//!
+//! To keep a server from being used to amplify traffic, a query is larger than its response and the server answers at most
+//! [`rate_limit`](QueryConfig::rate_limit) queries per second. <br>
+//! Responses carry a MAC made with a key derived from the server's private key, so a backend that knows the key
+//! (e.g. the matchmaker that issues connect tokens) can verify that a response came from the server and wasn't spoofed.
+//! Without the key a response can't be verified, treat the info it carries as a hint.
+//!
+//! # Example
+//! ```
+//! use netcode::query::{self, QueryConfig};
+//! use netcode::{NetcodeSocket, Server, ServerConfig, Transceiver};
+//! # use std::{thread, time::Duration};
+//!
+//! let private_key = netcode::generate_key();
+//! let cfg = ServerConfig::default().queries(QueryConfig::new("EU #1"));
+//! let mut server = Server::with_config("127.0.0.1:0", 0x11223344, private_key, cfg).unwrap();
+//!
+//! // the browser sends a query with a random nonce...
+//! let socket = NetcodeSocket::new("127.0.0.1:0", 64 * 1024, 64 * 1024).unwrap();
+//! let nonce = 42;
+//! socket.send(&query::request(0x11223344, nonce), server.addr()).unwrap();
+//! # thread::sleep(Duration::from_millis(10));
+//! server.update(0.0);
+//!
+//! // ...and reads the response
+//! # thread::sleep(Duration::from_millis(10));
+//! let mut buf = [0; query::QUERY_REQUEST_BYTES];
+//! let (len, _) = socket.recv(&mut buf).unwrap().unwrap();
+//! let info = query::read_response(&buf[..len], nonce, Some(&private_key)).unwrap();
+//! assert_eq!(info.name, "EU #1");
+//! assert_eq!(info.num_clients, 0);
+//! ```
+
+use alloc::string::{String, ToString};
+use core::mem::size_of;
+
+use chacha20poly1305::XNonce;
+#[cfg(any(test, feature = "std"))]
+use chacha20poly1305::{aead::OsRng, AeadCore, XChaCha20Poly1305};
+
+use crate::{
+    crypto::{self, Key},
+    error::{Error, Result},
+    MAC_BYTES, NETCODE_VERSION,
+};
+
+// The upper 4 bits of a netcode packet's prefix byte are the sequence length (at most 8), so no packet starts with this.
+const PREFIX: u8 = 0xFF;
+const NONCE_BYTES: usize = 24;
+const HEADER_BYTES: usize = 1 + NETCODE_VERSION.len() + 2 * size_of::<u64>();
+const INFO_BYTES: usize = 2 * size_of::<u16>() + 1 + MAX_SERVER_NAME_BYTES;
+const RESPONSE_BYTES: usize = HEADER_BYTES + INFO_BYTES + NONCE_BYTES + MAC_BYTES;
+
+/// The size of a query, which is padded to be larger than the response.
+pub const QUERY_REQUEST_BYTES: usize = 256;
+/// The longest server name (in bytes) a response carries, longer names are truncated.
+pub const MAX_SERVER_NAME_BYTES: usize = 64;
+
+const _: () = assert!(
+    QUERY_REQUEST_BYTES > RESPONSE_BYTES,
+    "a response must not be larger than its query"
+);
+
+/// Configuration for answering queries, see [`ServerConfig::queries`](crate::ServerConfig::queries).
+///
+/// # Example
+/// ```
+/// use netcode::query::QueryConfig;
+///
+/// let cfg = QueryConfig::new("EU #1").rate_limit(50);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryConfig {
+    pub(crate) name: String,
+    pub(crate) rate_limit: u32,
+}
+
+impl QueryConfig {
+    /// Create a configuration that answers queries with the server's `name`,
+    /// truncated to [`MAX_SERVER_NAME_BYTES`](MAX_SERVER_NAME_BYTES).
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: truncate_name(name),
+            rate_limit: 100,
+        }
+    }
+    /// Set the number of queries answered per second, queries above it are dropped
+    /// and counted in [`ServerStats::queries_rate_limited`](crate::ServerStats::queries_rate_limited). <br>
+    /// The default is 100 queries per second.
+    pub fn rate_limit(mut self, queries_per_second: u32) -> Self {
+        self.rate_limit = queries_per_second;
+        self
+    }
+}
+
+/// What a server answers a query with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServerInfo {
+    /// The name from the server's [`QueryConfig`](QueryConfig).
+    pub name: String,
+    /// The protocol id the query was sent with.
+    pub protocol_id: u64,
+    /// The number of connected clients.
+    pub num_clients: usize,
+    /// The maximum number of clients, see [`Server::set_max_clients`](crate::Server::set_max_clients).
+    pub max_clients: usize,
+}
+
+/// Creates a query for a server that accepts `protocol_id`. <br>
+/// The server echoes `nonce` in its response, use a random one to match responses to queries and reject spoofed responses.
+pub fn request(protocol_id: u64, nonce: u64) -> [u8; QUERY_REQUEST_BYTES] {
+    let mut buf = [0; QUERY_REQUEST_BYTES];
+    write_header(&mut buf, protocol_id, nonce);
+    buf
+}
+
+/// Reads a server's response to the query sent with `nonce`.
+///
+/// If `private_key` is given, the response is rejected unless it was made by a server with that key.
+pub fn read_response(buf: &[u8], nonce: u64, private_key: Option<&Key>) -> Result<ServerInfo> {
+    if buf.len() != RESPONSE_BYTES {
+        return Err(Error::SizeMismatch(RESPONSE_BYTES, buf.len()));
+    }
+    let (protocol_id, response_nonce) = read_header(buf).ok_or(Error::InvalidQuery)?;
+    if response_nonce != nonce {
+        return Err(Error::InvalidQuery);
+    }
+    if let Some(private_key) = private_key {
+        let (signed, rest) = buf.split_at(HEADER_BYTES + INFO_BYTES);
+        let (mac_nonce, mac) = rest.split_at(NONCE_BYTES);
+        let mut mac: [u8; MAC_BYTES] = mac.try_into().expect("MAC size");
+        crypto::xchacha_decrypt(
+            &mut mac,
+            Some(signed),
+            *XNonce::from_slice(mac_nonce),
+            &query_key(private_key)?,
+        )?;
+    }
+    let info = &buf[HEADER_BYTES..HEADER_BYTES + INFO_BYTES];
+    let num_clients = u16::from_le_bytes([info[0], info[1]]) as usize;
+    let max_clients = u16::from_le_bytes([info[2], info[3]]) as usize;
+    let name_len = (info[4] as usize).min(MAX_SERVER_NAME_BYTES);
+    let name = core::str::from_utf8(&info[5..5 + name_len]).map_err(|_| Error::InvalidQuery)?;
+    Ok(ServerInfo {
+        name: name.to_string(),
+        protocol_id,
+        num_clients,
+        max_clients,
+    })
+}
+
+/// Truncates `name` to at most [`MAX_SERVER_NAME_BYTES`](MAX_SERVER_NAME_BYTES), at a char boundary.
+pub(crate) fn truncate_name(name: &str) -> String {
+    let mut len = name.len().min(MAX_SERVER_NAME_BYTES);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    name[..len].to_string()
+}
+
+/// Whether a datagram is a query, rather than a netcode packet.
+#[cfg(any(test, feature = "std"))]
+pub(crate) fn is_query(buf: &[u8]) -> bool {
+    buf.first() == Some(&PREFIX)
+}
+
+/// Reads the protocol id and nonce of a query.
+#[cfg(any(test, feature = "std"))]
+pub(crate) fn read_request(buf: &[u8]) -> Option<(u64, u64)> {
+    if buf.len() != QUERY_REQUEST_BYTES {
+        return None;
+    }
+    read_header(buf)
+}
+
+#[cfg(any(test, feature = "std"))]
+pub(crate) fn write_response(
+    cfg: &QueryConfig,
+    info: (u64, u64),
+    num_clients: usize,
+    max_clients: usize,
+    private_key: &Key,
+) -> Result<[u8; RESPONSE_BYTES]> {
+    let (protocol_id, nonce) = info;
+    let mut buf = [0; RESPONSE_BYTES];
+    write_header(&mut buf, protocol_id, nonce);
+    let info = &mut buf[HEADER_BYTES..HEADER_BYTES + INFO_BYTES];
+    info[..2].copy_from_slice(&(num_clients as u16).to_le_bytes());
+    info[2..4].copy_from_slice(&(max_clients as u16).to_le_bytes());
+    info[4] = cfg.name.len() as u8;
+    info[5..5 + cfg.name.len()].copy_from_slice(cfg.name.as_bytes());
+
+    let mac_nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let (signed, rest) = buf.split_at_mut(HEADER_BYTES + INFO_BYTES);
+    let (nonce_bytes, mac) = rest.split_at_mut(NONCE_BYTES);
+    nonce_bytes.copy_from_slice(&mac_nonce);
+    crypto::xchacha_encrypt(mac, Some(signed), mac_nonce, &query_key(private_key)?)?;
+    Ok(buf)
+}
+
+/// Limits the number of queries answered per second.
+#[cfg(any(test, feature = "std"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RateLimit {
+    window_start: f64,
+    answered: u32,
+}
+
+#[cfg(any(test, feature = "std"))]
+impl RateLimit {
+    /// Whether another query can be answered at `time`, counting it if so.
+    pub(crate) fn allow(&mut self, time: f64, per_second: u32) -> bool {
+        if time - self.window_start >= 1.0 {
+            self.window_start = time;
+            self.answered = 0;
+        }
+        if self.answered >= per_second {
+            return false;
+        }
+        self.answered += 1;
+        true
+    }
+}
+
+// Responses aren't made with the private key itself, which also encrypts connect tokens.
+fn query_key(private_key: &Key) -> Result<Key> {
+    Ok(crypto::derive_key(private_key, b"netcode query\0\0\0", 0)?)
+}
+
+fn write_header(buf: &mut [u8], protocol_id: u64, nonce: u64) {
+    buf[0] = PREFIX;
+    let (version, rest) = buf[1..HEADER_BYTES].split_at_mut(NETCODE_VERSION.len());
+    version.copy_from_slice(NETCODE_VERSION);
+    rest[..8].copy_from_slice(&protocol_id.to_le_bytes());
+    rest[8..].copy_from_slice(&nonce.to_le_bytes());
+}
+
+fn read_header(buf: &[u8]) -> Option<(u64, u64)> {
+    if buf.len() < HEADER_BYTES
+        || buf[0] != PREFIX
+        || &buf[1..1 + NETCODE_VERSION.len()] != NETCODE_VERSION
+    {
+        return None;
+    }
+    let rest = &buf[1 + NETCODE_VERSION.len()..HEADER_BYTES];
+    let protocol_id = u64::from_le_bytes(rest[..8].try_into().expect("8 bytes"));
+    let nonce = u64::from_le_bytes(rest[8..].try_into().expect("8 bytes"));
+    Some((protocol_id, nonce))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_roundtrip() {
+        let key = crypto::generate_key();
+        let query = request(0x11, 7);
+        assert!(is_query(&query));
+        assert_eq!(read_request(&query), Some((0x11, 7)));
+
+        let cfg = QueryConfig::new(
+            "a server name that is much longer than the sixty four bytes a response has room for",
+        );
+        let response = write_response(&cfg, (0x11, 7), 3, 16, &key).unwrap();
+        let info = read_response(&response, 7, Some(&key)).unwrap();
+        assert_eq!(info.name.len(), MAX_SERVER_NAME_BYTES);
+        assert_eq!(
+            (info.protocol_id, info.num_clients, info.max_clients),
+            (0x11, 3, 16)
+        );
+
+        // another nonce, another server's key, or a tampered response are rejected
+        assert!(read_response(&response, 8, None).is_err());
+        assert!(read_response(&response, 7, Some(&crypto::generate_key())).is_err());
+        let mut tampered = response;
+        tampered[HEADER_BYTES] = 200;
+        assert!(read_response(&tampered, 7, Some(&key)).is_err());
+        assert_eq!(read_response(&tampered, 7, None).unwrap().num_clients, 200);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut limit = RateLimit::default();
+        assert!(limit.allow(0.0, 2));
+        assert!(limit.allow(0.5, 2));
+        assert!(!limit.allow(0.9, 2));
+        assert!(limit.allow(1.0, 2));
+    }
+}
@@ -0,0 +1,368 @@
+//! A transceiver over QUIC datagrams, see [`QuicTransceiver`](QuicTransceiver).
+//!
+//! The netcode session and authentication run unchanged on top: each netcode packet is sent as one unreliable QUIC datagram.
+//! QUIC adds its own handshake and encryption underneath, which is what gets through networks that block or throttle plain UDP,
+//! and validates a client's new path when its address changes.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use quinn::{Connection, Endpoint, SendDatagramError};
+use tokio::runtime::Runtime;
+
+use crate::transceiver::Transceiver;
+
+/// How long a connection stays open after it is closed, so the packets sent right before (e.g. the disconnect packets) go out.
+const CLOSE_DELAY: Duration = Duration::from_secs(1);
+/// The received datagrams kept until [`recv`](Transceiver::recv) takes them, more are dropped like by a full socket buffer.
+const MAX_RECEIVED_DATAGRAMS: usize = 4096;
+
+enum Peer {
+    // the client's handshake with the server is in progress
+    Connecting,
+    Connected(Connection),
+}
+
+type Peers = Arc<Mutex<HashMap<SocketAddr, Peer>>>;
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// A [`Transceiver`](Transceiver) that sends netcode packets as unreliable [QUIC](https://www.rfc-editor.org/rfc/rfc9000) datagrams
+/// ([RFC 9221](https://www.rfc-editor.org/rfc/rfc9221)) with [`quinn`](https://docs.rs/quinn),
+/// for deployments where plain UDP is blocked or throttled, or that want QUIC's path validation. <br>
+/// Both ends have to use one: create the server's with [`server`](QuicTransceiver::server) and a certificate,
+/// and the clients' with [`client`](QuicTransceiver::client), which opens a QUIC connection to each server it tries to connect to.
+/// Peers are known by the address their QUIC connection started from, so a client that migrates to another address keeps its session.
+///
+/// The connections are driven by a worker thread the transceiver starts, [`send`](Transceiver::send) and [`recv`](Transceiver::recv)
+/// never block. Packets sent to a server before the QUIC handshake is done are [queued](crate::SendOutcome::QueuedWouldBlock).
+///
+/// QUIC spends about 40 bytes of each UDP datagram on its own headers. Until a larger MTU is discovered, datagrams have to fit
+/// in the QUIC minimum of 1200 bytes, so lower the max payload size of the client and server to 1100 bytes (or raise
+/// [`TransportConfig::initial_mtu`](quinn::TransportConfig::initial_mtu) if every path allows it), larger packets fail to send.
+///
+/// # Example
+/// ```no_run
+/// use netcode::{Client, ClientConfig, QuicTransceiver, Server, ServerConfig};
+/// # let (cert, key): (Vec<quinn::rustls::pki_types::CertificateDer<'static>>, quinn::rustls::pki_types::PrivateKeyDer<'static>) = todo!();
+/// # let roots = std::sync::Arc::new(quinn::rustls::RootCertStore::empty());
+///
+/// let quic = QuicTransceiver::server("0.0.0.0:40000", quinn::ServerConfig::with_single_cert(cert, key).unwrap()).unwrap();
+/// let cfg = ServerConfig::default().max_payload_size(1100);
+/// let mut server = Server::with_config_and_transceiver(0x11223344, netcode::generate_key(), cfg, quic).unwrap();
+/// # let token_bytes = [0; netcode::CONNECT_TOKEN_BYTES];
+///
+/// // on the client, with a connect token from the web backend
+/// let quic = QuicTransceiver::client(
+///     "0.0.0.0:0",
+///     quinn::ClientConfig::with_root_certificates(roots).unwrap(),
+///     "game.example.com",
+/// )
+/// .unwrap();
+/// let cfg = ClientConfig::default().max_payload_size(1100);
+/// let mut client = Client::with_config_and_transceiver(&token_bytes, cfg, quic).unwrap();
+/// client.connect();
+/// ```
+pub struct QuicTransceiver {
+    runtime: Runtime,
+    endpoint: Endpoint,
+    // the name the server's certificate is checked against
+    server_name: String,
+    peers: Peers,
+    received: Receiver<Datagram>,
+    received_tx: SyncSender<Datagram>,
+}
+
+impl QuicTransceiver {
+    /// Creates the transceiver of a server, which accepts QUIC connections on `addr` with the certificate in `config`.
+    pub fn server(addr: impl ToSocketAddrs, config: quinn::ServerConfig) -> io::Result<Self> {
+        let runtime = Self::runtime()?;
+        let endpoint = {
+            let _guard = runtime.enter();
+            Endpoint::server(config, Self::resolve(addr)?)?
+        };
+        let transceiver = Self::new(runtime, endpoint, String::new());
+        transceiver.runtime.spawn(accept(
+            transceiver.endpoint.clone(),
+            transceiver.peers.clone(),
+            transceiver.received_tx.clone(),
+        ));
+        Ok(transceiver)
+    }
+    /// Creates the transceiver of a client bound to `addr`, which connects to servers with `config`
+    /// and checks that their certificates are valid for `server_name`.
+    pub fn client(
+        addr: impl ToSocketAddrs,
+        config: quinn::ClientConfig,
+        server_name: &str,
+    ) -> io::Result<Self> {
+        let runtime = Self::runtime()?;
+        let mut endpoint = {
+            let _guard = runtime.enter();
+            Endpoint::client(Self::resolve(addr)?)?
+        };
+        endpoint.set_default_client_config(config);
+        Ok(Self::new(runtime, endpoint, server_name.to_string()))
+    }
+    fn new(runtime: Runtime, endpoint: Endpoint, server_name: String) -> Self {
+        let (received_tx, received) = mpsc::sync_channel(MAX_RECEIVED_DATAGRAMS);
+        Self {
+            runtime,
+            endpoint,
+            server_name,
+            peers: Arc::default(),
+            received,
+            received_tx,
+        }
+    }
+    fn runtime() -> io::Result<Runtime> {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("netcode-quic")
+            .enable_all()
+            .build()
+    }
+    fn resolve(addr: impl ToSocketAddrs) -> io::Result<SocketAddr> {
+        addr.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to bind to"))
+    }
+}
+
+impl Transceiver for QuicTransceiver {
+    type IntoError = io::Error;
+
+    fn addr(&self) -> SocketAddr {
+        self.endpoint.local_addr().expect("address should be bound")
+    }
+    fn recv(&self, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        let Ok((datagram, addr)) = self.received.try_recv() else {
+            return Ok(None);
+        };
+        // like a UDP socket, the rest of a datagram that doesn't fit in the buffer is discarded
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        Ok(Some((len, addr)))
+    }
+    fn send(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let peers = lock(&self.peers);
+        let conn = match peers.get(&addr) {
+            Some(Peer::Connected(conn)) => conn,
+            // sent again once the handshake is done
+            Some(Peer::Connecting) => return Ok(0),
+            // like a UDP datagram to an address nobody listens on, it is lost
+            None => return Ok(buf.len()),
+        };
+        // quinn makes room for a new datagram by dropping older ones, queue it instead
+        if conn.datagram_send_buffer_space() < buf.len() {
+            return Ok(0);
+        }
+        match conn.send_datagram(buf.to_vec().into()) {
+            Ok(()) | Err(SendDatagramError::ConnectionLost(_)) => Ok(buf.len()),
+            Err(SendDatagramError::TooLarge) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "a datagram of {} bytes is larger than the {} bytes the QUIC connection to {addr} allows",
+                    buf.len(),
+                    conn.max_datagram_size().unwrap_or_default()
+                ),
+            )),
+            Err(e) => Err(io::Error::new(io::ErrorKind::Unsupported, e)),
+        }
+    }
+    fn rebind(&mut self) -> io::Result<bool> {
+        let socket = UdpSocket::bind(SocketAddr::new(self.addr().ip(), 0))?;
+        let _guard = self.runtime.enter();
+        // the connections migrate to the new socket
+        self.endpoint.rebind(socket)?;
+        Ok(true)
+    }
+    fn open_connection(&self, addr: SocketAddr) -> io::Result<()> {
+        let mut peers = lock(&self.peers);
+        if peers.contains_key(&addr) {
+            return Ok(());
+        }
+        // the connection is driven by a task on the worker thread
+        let _guard = self.runtime.enter();
+        let connecting = self
+            .endpoint
+            .connect(addr, &self.server_name)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        peers.insert(addr, Peer::Connecting);
+        let peers = self.peers.clone();
+        let received = self.received_tx.clone();
+        self.runtime.spawn(async move {
+            let conn = match connecting.await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    log::debug!("quic transceiver failed to connect to {addr}: {e}");
+                    lock(&peers).remove(&addr);
+                    return;
+                }
+            };
+            {
+                let mut peers = lock(&peers);
+                if !matches!(peers.get(&addr), Some(Peer::Connecting)) {
+                    // closed while the handshake was in progress
+                    conn.close(0u32.into(), b"closed");
+                    return;
+                }
+                peers.insert(addr, Peer::Connected(conn.clone()));
+            }
+            read_datagrams(conn, addr, peers, received).await;
+        });
+        Ok(())
+    }
+    fn close_connection(&self, addr: SocketAddr) -> io::Result<()> {
+        if let Some(Peer::Connected(conn)) = lock(&self.peers).remove(&addr) {
+            self.runtime.spawn(async move {
+                tokio::time::sleep(CLOSE_DELAY).await;
+                conn.close(0u32.into(), b"closed");
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Drop for QuicTransceiver {
+    fn drop(&mut self) {
+        self.endpoint.close(0u32.into(), b"closed");
+    }
+}
+
+fn lock(peers: &Peers) -> MutexGuard<'_, HashMap<SocketAddr, Peer>> {
+    peers.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Accepts the connections of clients, on the server's worker thread.
+async fn accept(endpoint: Endpoint, peers: Peers, received: SyncSender<Datagram>) {
+    while let Some(incoming) = endpoint.accept().await {
+        let peers = peers.clone();
+        let received = received.clone();
+        tokio::spawn(async move {
+            match incoming.await {
+                Ok(conn) => {
+                    let addr = conn.remote_address();
+                    lock(&peers).insert(addr, Peer::Connected(conn.clone()));
+                    read_datagrams(conn, addr, peers, received).await;
+                }
+                Err(e) => log::debug!("quic transceiver failed to accept a connection: {e}"),
+            }
+        });
+    }
+}
+
+// Hands the datagrams received on a connection to `recv`, until the connection is closed.
+async fn read_datagrams(
+    conn: Connection,
+    addr: SocketAddr,
+    peers: Peers,
+    received: SyncSender<Datagram>,
+) {
+    loop {
+        match conn.read_datagram().await {
+            Ok(datagram) => match received.try_send((datagram.to_vec(), addr)) {
+                Ok(()) | Err(TrySendError::Full(_)) => {}
+                Err(TrySendError::Disconnected(_)) => return,
+            },
+            Err(e) => {
+                log::debug!("quic transceiver connection to {addr} closed: {e}");
+                break;
+            }
+        }
+    }
+    let mut peers = lock(&peers);
+    // a newer connection from the same address may have taken its place
+    if matches!(peers.get(&addr), Some(Peer::Connected(c)) if c.stable_id() == conn.stable_id()) {
+        peers.remove(&addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use quinn::rustls::pki_types::PrivatePkcs8KeyDer;
+    use quinn::rustls::RootCertStore;
+
+    use super::*;
+    use crate::{Client, ClientConfig, Server, ServerConfig};
+
+    fn transceivers() -> (QuicTransceiver, QuicTransceiver) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let key = PrivatePkcs8KeyDer::from(cert.signing_key.serialize_der());
+        let server_config =
+            quinn::ServerConfig::with_single_cert(vec![cert.cert.der().clone()], key.into())
+                .unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+        let client_config = quinn::ClientConfig::with_root_certificates(Arc::new(roots)).unwrap();
+        (
+            QuicTransceiver::server("127.0.0.1:0", server_config).unwrap(),
+            QuicTransceiver::client("127.0.0.1:0", client_config, "localhost").unwrap(),
+        )
+    }
+
+    #[test]
+    fn connects_over_quic() {
+        let (server_quic, client_quic) = transceivers();
+        let mut server = Server::with_config_and_transceiver(
+            0x11,
+            crate::generate_key(),
+            ServerConfig::default().max_payload_size(1100),
+            server_quic,
+        )
+        .unwrap();
+        let token = server
+            .token(7)
+            .generate()
+            .unwrap()
+            .try_into_bytes()
+            .unwrap();
+        let mut client = Client::with_config_and_transceiver(
+            &token,
+            ClientConfig::default().max_payload_size(1100),
+            client_quic,
+        )
+        .unwrap();
+        client.connect();
+
+        let start = Instant::now();
+        let update = |client: &mut Client<QuicTransceiver>,
+                      server: &mut Server<QuicTransceiver>| {
+            let time = start.elapsed().as_secs_f64();
+            client.update(time);
+            server.update(time);
+            std::thread::sleep(Duration::from_millis(5));
+            assert!(time < 10.0, "timed out");
+        };
+        while !client.is_connected() {
+            update(&mut client, &mut server);
+        }
+        client.send(b"hello").unwrap();
+        let (payload, idx) = loop {
+            update(&mut client, &mut server);
+            if let Some(received) = server.recv() {
+                break received;
+            }
+        };
+        assert_eq!(payload, b"hello");
+        server.send(&[1; 1000], idx).unwrap();
+        let payload = loop {
+            update(&mut client, &mut server);
+            if let Some(payload) = client.recv() {
+                break payload;
+            }
+        };
+        assert_eq!(payload, [1; 1000]);
+
+        // the client's connection stays open until its disconnect packets went out
+        client.disconnect().unwrap();
+        while server.connected_count() > 0 {
+            update(&mut client, &mut server);
+        }
+    }
+}
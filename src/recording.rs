@@ -0,0 +1,455 @@
+//! Recording the datagrams a server receives, and replaying them to reproduce what the server did,
+//! see [`ServerConfig::record`](crate::ServerConfig::record) and [`Server::replay`](crate::Server::replay).
+//!
+//! A recording is a header (magic, version, protocol id and server address) followed by records:
+//! each update (its time and the wall clock time), each datagram received in it (before it is decrypted),
+//! and the bytes the server drew from its RNG. With the same private key and configuration, a replay
+//! draws the same challenge keys and decrypts the same packets, so the server goes through the same states.
+
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    num::NonZeroU32,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use chacha20poly1305::aead::rand_core::{self, CryptoRng, CryptoRngCore, RngCore};
+
+use crate::{clock::ManualClock, transceiver::Transceiver};
+
+const MAGIC: &[u8; 8] = b"NETCDREC";
+const VERSION: u8 = 1;
+
+const UPDATE: u8 = 0;
+const DATAGRAM: u8 = 1;
+const RANDOM: u8 = 2;
+
+fn write_addr(writer: &mut impl Write, addr: SocketAddr) -> io::Result<()> {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            writer.write_u8(4)?;
+            writer.write_all(&ip.octets())?;
+        }
+        IpAddr::V6(ip) => {
+            writer.write_u8(6)?;
+            writer.write_all(&ip.octets())?;
+        }
+    }
+    writer.write_u16::<LittleEndian>(addr.port())
+}
+
+fn read_addr(reader: &mut impl Read) -> io::Result<SocketAddr> {
+    let ip = match reader.read_u8()? {
+        4 => {
+            let mut octets = [0; 4];
+            reader.read_exact(&mut octets)?;
+            IpAddr::V4(Ipv4Addr::from(octets))
+        }
+        6 => {
+            let mut octets = [0; 16];
+            reader.read_exact(&mut octets)?;
+            IpAddr::V6(Ipv6Addr::from(octets))
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid address",
+            ))
+        }
+    };
+    Ok(SocketAddr::new(ip, reader.read_u16::<LittleEndian>()?))
+}
+
+fn write_bytes(writer: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(bytes.len() as u32)?;
+    writer.write_all(bytes)
+}
+
+fn read_bytes(reader: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = reader.read_u32::<LittleEndian>()? as usize;
+    let mut bytes = Vec::new();
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+struct RecorderState {
+    writer: Box<dyn Write + Send>,
+    failed: bool,
+}
+
+impl RecorderState {
+    fn write(&mut self, record: impl FnOnce(&mut dyn Write) -> io::Result<()>) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = record(&mut self.writer) {
+            // a recording with a gap can't be replayed, so the rest isn't written either
+            log::error!("failed to write server recording, recording stopped: {e}");
+            self.failed = true;
+        }
+    }
+}
+
+/// Writes a recording of the datagrams a server receives, set with [`ServerConfig::record`](crate::ServerConfig::record).
+///
+/// Records are written as the server receives them, and flushed at the end of every update,
+/// so a recording is complete up to the last update even if the server process crashes.
+/// Load it with [`Recording::open`](Recording::open) and feed it back with [`Server::replay`](crate::Server::replay).
+///
+/// The recording holds every packet sent to the server and the challenge keys it generated, treat it like the
+/// traffic it captures: anyone with the recording and the private key can decrypt the sessions in it.
+///
+/// # Example
+/// ```no_run
+/// use netcode::{Recorder, ServerConfig};
+///
+/// let cfg = ServerConfig::default().record(Recorder::create("server.rec").unwrap());
+/// ```
+#[derive(Clone)]
+pub struct Recorder(Arc<Mutex<RecorderState>>);
+
+impl Recorder {
+    /// Creates (or truncates) a recording file at `path`.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Recorder::new(BufWriter::new(File::create(path)?)))
+    }
+    /// Creates a recorder writing to `writer`, the header is written when the server starts.
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self(Arc::new(Mutex::new(RecorderState {
+            writer: Box::new(writer),
+            failed: false,
+        })))
+    }
+    fn write(&self, record: impl FnOnce(&mut dyn Write) -> io::Result<()>) {
+        self.0.lock().expect("recorder lock poisoned").write(record);
+    }
+    pub(crate) fn start(&self, protocol_id: u64, addr: SocketAddr) {
+        self.write(|mut writer| {
+            writer.write_all(MAGIC)?;
+            writer.write_u8(VERSION)?;
+            writer.write_u64::<LittleEndian>(protocol_id)?;
+            write_addr(&mut writer, addr)
+        });
+    }
+    pub(crate) fn update(&self, time: f64, unix_time: u64) {
+        self.write(|writer| {
+            writer.write_u8(UPDATE)?;
+            writer.write_f64::<LittleEndian>(time)?;
+            writer.write_u64::<LittleEndian>(unix_time)
+        });
+    }
+    pub(crate) fn datagram(&self, from: SocketAddr, datagram: &[u8]) {
+        self.write(|mut writer| {
+            writer.write_u8(DATAGRAM)?;
+            write_addr(&mut writer, from)?;
+            write_bytes(&mut writer, datagram)
+        });
+    }
+    pub(crate) fn flush(&self) {
+        self.write(|writer| writer.flush());
+    }
+    /// Wraps the server's RNG, so the bytes drawn from it are recorded as well.
+    pub(crate) fn rng(&self, rng: Box<dyn CryptoRngCore + Send + Sync>) -> RecordingRng {
+        RecordingRng {
+            rng,
+            recorder: self.clone(),
+        }
+    }
+}
+
+impl core::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+pub(crate) struct RecordingRng {
+    rng: Box<dyn CryptoRngCore + Send + Sync>,
+    recorder: Recorder,
+}
+
+impl RngCore for RecordingRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest).expect("the server's RNG failed");
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.rng.try_fill_bytes(dest)?;
+        self.recorder.write(|mut writer| {
+            writer.write_u8(RANDOM)?;
+            write_bytes(&mut writer, dest)
+        });
+        Ok(())
+    }
+}
+
+impl CryptoRng for RecordingRng {}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RecordedUpdate {
+    time: f64,
+    unix_time: u64,
+    datagrams: Vec<(SocketAddr, Vec<u8>)>,
+}
+
+/// A recording written by a [`Recorder`](Recorder), to replay with [`Server::replay`](crate::Server::replay).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    protocol_id: u64,
+    addr: SocketAddr,
+    updates: Vec<RecordedUpdate>,
+    random: Vec<u8>,
+}
+
+impl Recording {
+    /// Reads a recording file.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Recording::read_from(BufReader::new(File::open(path)?))
+    }
+    /// Reads a recording from `reader`.
+    ///
+    /// A record cut off at the end, e.g. by a crash while it was written, ends the recording.
+    pub fn read_from(mut reader: impl Read) -> io::Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a server recording",
+            ));
+        }
+        let version = reader.read_u8()?;
+        if version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported recording version {version}"),
+            ));
+        }
+        let mut recording = Recording {
+            protocol_id: reader.read_u64::<LittleEndian>()?,
+            addr: read_addr(&mut reader)?,
+            updates: Vec::new(),
+            random: Vec::new(),
+        };
+        loop {
+            match recording.read_record(&mut reader) {
+                Ok(true) => {}
+                Ok(false) => return Ok(recording),
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    log::warn!("server recording ends in a partial record");
+                    return Ok(recording);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    // Returns false at the end of the recording.
+    fn read_record(&mut self, reader: &mut impl Read) -> io::Result<bool> {
+        let mut kind = [0];
+        if reader.read(&mut kind)? == 0 {
+            return Ok(false);
+        }
+        match kind[0] {
+            UPDATE => {
+                let time = reader.read_f64::<LittleEndian>()?;
+                let unix_time = reader.read_u64::<LittleEndian>()?;
+                self.updates.push(RecordedUpdate {
+                    time,
+                    unix_time,
+                    datagrams: Vec::new(),
+                });
+            }
+            DATAGRAM => {
+                let from = read_addr(reader)?;
+                let datagram = read_bytes(reader)?;
+                let Some(update) = self.updates.last_mut() else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "datagram recorded outside of an update",
+                    ));
+                };
+                update.datagrams.push((from, datagram));
+            }
+            RANDOM => self.random.extend(read_bytes(reader)?),
+            kind => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid record type {kind}"),
+                ))
+            }
+        }
+        Ok(true)
+    }
+    /// Gets the protocol id of the recorded server.
+    pub fn protocol_id(&self) -> u64 {
+        self.protocol_id
+    }
+    /// Gets the address of the recorded server.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+    /// Gets the number of recorded updates.
+    pub fn num_updates(&self) -> usize {
+        self.updates.len()
+    }
+    /// Gets the number of recorded datagrams.
+    pub fn num_datagrams(&self) -> usize {
+        self.updates
+            .iter()
+            .map(|update| update.datagrams.len())
+            .sum()
+    }
+    /// Gets the server time of the last recorded update, `None` if no update was recorded.
+    pub fn end_time(&self) -> Option<f64> {
+        self.updates.last().map(|update| update.time)
+    }
+}
+
+/// The [`Transceiver`](Transceiver) of a server replaying a [`Recording`](Recording), see [`Server::replay`](crate::Server::replay).
+///
+/// It delivers the datagrams recorded for each update, and drops the packets the server sends.
+#[derive(Debug)]
+pub struct ReplayTransceiver {
+    addr: SocketAddr,
+    updates: VecDeque<RecordedUpdate>,
+    inbox: RefCell<VecDeque<(SocketAddr, Vec<u8>)>>,
+    clock: ManualClock,
+}
+
+impl ReplayTransceiver {
+    /// Splits a recording into the transceiver, and the clock and RNG the server replays it with.
+    pub(crate) fn new(recording: Recording) -> (Self, ManualClock, ReplayRng) {
+        let clock = ManualClock::new(recording.updates.first().map_or(0, |u| u.unix_time));
+        let rng = ReplayRng {
+            random: recording.random,
+            pos: 0,
+        };
+        let trx = Self {
+            addr: recording.addr,
+            updates: recording.updates.into(),
+            inbox: RefCell::new(VecDeque::new()),
+            clock: clock.clone(),
+        };
+        (trx, clock, rng)
+    }
+    /// Queues the datagrams of the next update and sets the clock to its time, returns the server time of the update.
+    pub(crate) fn next_update(&mut self) -> Option<f64> {
+        let update = self.updates.pop_front()?;
+        self.clock.set(update.unix_time);
+        self.inbox.get_mut().clear();
+        self.inbox.get_mut().extend(update.datagrams);
+        Some(update.time)
+    }
+    /// Gets the number of recorded updates left to replay.
+    pub fn updates_left(&self) -> usize {
+        self.updates.len()
+    }
+}
+
+impl Transceiver for ReplayTransceiver {
+    type IntoError = io::Error;
+
+    fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+    fn recv(&self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>, Self::IntoError> {
+        let Some((from, datagram)) = self.inbox.borrow_mut().pop_front() else {
+            return Ok(None);
+        };
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        Ok(Some((len, from)))
+    }
+    fn send(&self, buf: &[u8], _addr: SocketAddr) -> Result<usize, Self::IntoError> {
+        Ok(buf.len())
+    }
+}
+
+/// Serves the recorded RNG bytes back in order.
+pub(crate) struct ReplayRng {
+    random: Vec<u8>,
+    pos: usize,
+}
+
+impl RngCore for ReplayRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.try_fill_bytes(dest)
+            .expect("the replay drew more random bytes than were recorded");
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        let Some(random) = self.random.get(self.pos..self.pos + dest.len()) else {
+            let code = NonZeroU32::new(rand_core::Error::CUSTOM_START).expect("non-zero");
+            return Err(code.into());
+        };
+        dest.copy_from_slice(random);
+        self.pos += dest.len();
+        Ok(())
+    }
+}
+
+impl CryptoRng for ReplayRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_truncated_recording() {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+        impl Write for SharedBuf {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let recorder = Recorder::new(SharedBuf(buf.clone()));
+        let addr = "[::1]:40000".parse().unwrap();
+        let client = "127.0.0.1:50000".parse().unwrap();
+        recorder.start(7, addr);
+        recorder
+            .rng(Box::new(crate::testing::SeededRng::new(1)))
+            .fill_bytes(&mut [0; 32]);
+        recorder.update(0.5, 1_700_000_000);
+        recorder.datagram(client, b"first");
+        recorder.update(0.6, 1_700_000_001);
+        recorder.datagram(client, b"second");
+
+        let bytes = buf.lock().unwrap().clone();
+        let recording = Recording::read_from(&bytes[..]).unwrap();
+        assert_eq!((recording.protocol_id(), recording.addr()), (7, addr));
+        assert_eq!(recording.num_updates(), 2);
+        assert_eq!(recording.num_datagrams(), 2);
+        assert_eq!(recording.end_time(), Some(0.6));
+        assert_eq!(recording.random.len(), 32);
+
+        // a crash in the middle of the last datagram loses only that datagram
+        let truncated = Recording::read_from(&bytes[..bytes.len() - 3]).unwrap();
+        assert_eq!(truncated.num_updates(), 2);
+        assert_eq!(truncated.num_datagrams(), 1);
+
+        assert!(Recording::read_from(&b"NOTAREC!"[..]).is_err());
+    }
+}
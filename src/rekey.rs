@@ -0,0 +1,120 @@
+//! Session rekeying for long-lived connections, opted into on both sides with `rekey_sessions`.
+//!
+//! Session packets (keep-alive, payload, disconnect and payload limit packets) split their sequence into an epoch (the upper 32 bits)
+//! and a counter (the lower 32 bits), and are encrypted with a key derived from the session key and the epoch,
+//! see [`crypto::session_key`](crate::crypto::session_key). Both sides compute the key from the sequence in the packet header,
+//! so there is no handshake, and epoch 0 uses the session key itself, like the reference implementation.
+//!
+//! The epoch changes when the counter wraps, or when the sender jumps to the next epoch because the rekey interval passed,
+//! so no key is used for more than 2^32 packets or longer than the interval. The nonce is the full sequence either way,
+//! so rekeying doesn't extend the nonce space, it only limits how much each key encrypts.
+//!
+//! This changes the wire format: the reference implementation encrypts every packet of a session with the session key,
+//! so a peer that doesn't rekey drops the packets of any epoch but 0, i.e. after 2^32 packets or the first rekey interval.
+//! Without `rekey_sessions` the sequence keeps counting with the session key, like the reference implementation.
+
+use crate::{
+    crypto::Cipher,
+    packet::{Packet, PacketKind},
+};
+
+const EPOCH_BITS: u32 = 32;
+const COUNTER_MASK: u64 = (1 << EPOCH_BITS) - 1;
+
+/// The epoch of a session packet's sequence, which selects its key. Always 0 if the session isn't rekeyed.
+pub fn epoch(cipher: &Cipher, kind: PacketKind, sequence: u64) -> u64 {
+    if cipher.is_rekeying() && kind >= Packet::KEEP_ALIVE {
+        sequence >> EPOCH_BITS
+    } else {
+        0
+    }
+}
+
+/// Whether `most_recent` is the first sequence of a later epoch than `previous`, i.e. a jump that isn't packet loss.
+pub fn is_epoch_start(previous: Option<u64>, most_recent: Option<u64>) -> bool {
+    matches!((previous, most_recent), (Some(previous), Some(most_recent))
+        if most_recent >> EPOCH_BITS > previous >> EPOCH_BITS && most_recent & COUNTER_MASK == 0)
+}
+
+/// Whether `most_recent` is in a later epoch than `previous`, i.e. the sender rekeyed.
+pub fn is_new_epoch(previous: Option<u64>, most_recent: Option<u64>) -> bool {
+    matches!((previous, most_recent), (Some(previous), Some(most_recent))
+        if most_recent >> EPOCH_BITS > previous >> EPOCH_BITS)
+}
+
+/// Tracks the current send epoch and when it started.
+#[derive(Debug, Clone, Copy)]
+pub struct Rekey {
+    epoch: u64,
+    epoch_start: f64,
+}
+
+impl Rekey {
+    pub fn new(sequence: u64, time: f64) -> Self {
+        Self {
+            epoch: sequence >> EPOCH_BITS,
+            epoch_start: time,
+        }
+    }
+    /// Returns the sequence to send the next session packet with, moved to the start of the next epoch if `interval` seconds
+    /// passed since the current one started, and whether it starts a new epoch.
+    pub fn next_sequence(
+        &mut self,
+        sequence: u64,
+        time: f64,
+        interval: Option<f64>,
+    ) -> (u64, bool) {
+        let due = interval.is_some_and(|interval| time - self.epoch_start >= interval);
+        let sequence = if due && sequence >> EPOCH_BITS == self.epoch {
+            (self.epoch + 1) << EPOCH_BITS
+        } else {
+            sequence
+        };
+        let rekeyed = sequence >> EPOCH_BITS != self.epoch;
+        if rekeyed {
+            self.epoch = sequence >> EPOCH_BITS;
+            self.epoch_start = time;
+        }
+        (sequence, rekeyed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn epochs_change_on_interval_and_wrap() {
+        let mut rekey = Rekey::new(0, 0.0);
+        assert_eq!(rekey.next_sequence(5, 1.0, Some(10.0)), (5, false));
+        assert_eq!(rekey.next_sequence(6, 10.0, Some(10.0)), (1 << 32, true));
+        // asking again for the same packet doesn't jump twice
+        assert_eq!(
+            rekey.next_sequence(1 << 32, 10.0, Some(10.0)),
+            (1 << 32, false)
+        );
+        assert_eq!(
+            rekey.next_sequence((1 << 32) + 1, 15.0, Some(10.0)),
+            ((1 << 32) + 1, false)
+        );
+        // the counter running out starts a new epoch without an interval
+        assert_eq!(rekey.next_sequence(2 << 32, 16.0, None), (2 << 32, true));
+        assert_eq!(
+            rekey.next_sequence((2 << 32) + 1, 100.0, None),
+            ((2 << 32) + 1, false)
+        );
+
+        let cipher = Cipher::with_rekeying(crate::generate_key(), true);
+        assert_eq!(epoch(&cipher, Packet::PAYLOAD, (2 << 32) + 1), 2);
+        // the challenge sequence of the server starts at 2^63 and is not a session packet
+        assert_eq!(epoch(&cipher, Packet::CHALLENGE, 1 << 63), 0);
+        // without rekeying the session key is used past 2^32 packets, like the reference implementation
+        let cipher = Cipher::new(crate::generate_key());
+        assert_eq!(epoch(&cipher, Packet::PAYLOAD, (2 << 32) + 1), 0);
+        assert!(is_epoch_start(Some(6), Some(1 << 32)));
+        assert!(!is_epoch_start(Some(6), Some((1 << 32) + 1)));
+        assert!(!is_epoch_start(Some(6), Some(8)));
+        assert!(is_new_epoch(Some(6), Some((1 << 32) + 1)));
+        assert!(!is_new_epoch(Some(6), Some(8)));
+    }
+}
@@ -0,0 +1,218 @@
+//! Running a server behind UDP relays (e.g. anycast edge relays or a load balancer), while its sessions still key off
+//! the real address of each client rather than the relay's.
+//!
+//! A relay prepends a header with the client's address to every packet it forwards to the server, see [`wrap_for_server`](wrap_for_server).
+//! A server configured with [`ServerConfig::relay`](crate::ServerConfig::relay) strips the header, handles the packet as if it came
+//! from the client, and sends its packets for that client back through the relay with the same kind of header,
+//! which the relay strips with [`unwrap_from_server`](unwrap_from_server) before forwarding them to the client.
+//!
+//! Headers carry a MAC made with a key shared by the server and its relays, so a client can't claim another address.
+//! The MAC doesn't prevent replays, a replayed header routes the client's packets through the replaying relay
+//! until the real one forwards a packet again. The netcode packet inside is encrypted and replay protected as usual.
+//!
+//! The header is `0xFE` (`0xFD` from the server), the address type (4 or 6), the IP address and the port (little-endian),
+//! then the packet, a 24 byte nonce, and a 16 byte XChaCha20-Poly1305 MAC over everything before the nonce.
+//!
+//! # Example
+//! ```
+//! use netcode::relay;
+//! use std::net::SocketAddr;
+//!
+//! let relay_key = netcode::generate_key();
+//! let client_addr = SocketAddr::from(([203, 0, 113, 7], 50000));
+//!
+//! // the relay forwards a packet it received from the client to the server
+//! let packet = [0u8; 100];
+//! let datagram = relay::wrap_for_server(&relay_key, client_addr, &packet).unwrap();
+//! assert_eq!(datagram.len(), packet.len() + relay::header_bytes(client_addr));
+//!
+//! // and packets it received from the server to the client
+//! # let from_server: Vec<u8> = Vec::new();
+//! if let Ok((client_addr, packet)) = relay::unwrap_from_server(&relay_key, &from_server) {
+//!     // socket.send_to(packet, client_addr)
+//! }
+//! ```
+
+use alloc::vec::Vec;
+use core::{
+    net::{IpAddr, SocketAddr},
+    ops::Range,
+};
+
+use chacha20poly1305::{aead::OsRng, AeadCore, XChaCha20Poly1305, XNonce};
+use zeroize::Zeroizing;
+
+use crate::{
+    crypto::{self, Key},
+    error::{Error, Result},
+    MAC_BYTES,
+};
+
+// The upper 4 bits of a netcode packet's prefix byte are the sequence length (at most 8), so no packet starts with these.
+const TO_SERVER: u8 = 0xFE;
+const TO_CLIENT: u8 = 0xFD;
+const NONCE_BYTES: usize = 24;
+
+/// The most bytes a relay header adds to a packet (for an IPv6 client address).
+pub const MAX_HEADER_BYTES: usize = 2 + 16 + 2 + NONCE_BYTES + MAC_BYTES;
+
+/// Configuration for running a server behind relays, see [`ServerConfig::relay`](crate::ServerConfig::relay).
+///
+/// # Example
+/// ```
+/// use netcode::relay::RelayConfig;
+/// use netcode::ServerConfig;
+///
+/// let relay_key = netcode::generate_key();
+/// let cfg = ServerConfig::default().relay(RelayConfig::new(relay_key).allow_direct(false));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelayConfig {
+    pub(crate) key: Zeroizing<Key>,
+    pub(crate) allow_direct: bool,
+}
+
+impl RelayConfig {
+    /// Create a configuration for relays that share `key` with the server.
+    pub fn new(key: Key) -> Self {
+        Self {
+            key: Zeroizing::new(key),
+            allow_direct: true,
+        }
+    }
+    /// Set whether the server also accepts packets sent to it directly, without a relay header. <br>
+    /// The default is `true`, so clients can connect both through relays and directly.
+    pub fn allow_direct(mut self, allow_direct: bool) -> Self {
+        self.allow_direct = allow_direct;
+        self
+    }
+}
+
+/// The number of bytes a relay header adds to a packet for `client_addr`.
+pub fn header_bytes(client_addr: SocketAddr) -> usize {
+    let ip_bytes = match client_addr.ip() {
+        IpAddr::V4(_) => 4,
+        IpAddr::V6(_) => 16,
+    };
+    2 + ip_bytes + 2 + NONCE_BYTES + MAC_BYTES
+}
+
+/// Wraps a packet the relay received from `client_addr` in a header, to forward to the server.
+pub fn wrap_for_server(key: &Key, client_addr: SocketAddr, packet: &[u8]) -> Result<Vec<u8>> {
+    wrap(TO_SERVER, key, client_addr, packet)
+}
+
+/// Unwraps a datagram the relay received from the server, returning the address of the client to forward the packet to.
+pub fn unwrap_from_server<'a>(key: &Key, datagram: &'a [u8]) -> Result<(SocketAddr, &'a [u8])> {
+    let (client_addr, packet) = unwrap(TO_CLIENT, key, datagram)?;
+    Ok((client_addr, &datagram[packet]))
+}
+
+/// Whether a datagram starts with a relay header for the server.
+#[cfg(any(test, feature = "std"))]
+pub(crate) fn is_relayed(buf: &[u8]) -> bool {
+    buf.first() == Some(&TO_SERVER)
+}
+
+#[cfg(any(test, feature = "std"))]
+pub(crate) fn wrap_for_client(
+    key: &Key,
+    client_addr: SocketAddr,
+    packet: &[u8],
+) -> Result<Vec<u8>> {
+    wrap(TO_CLIENT, key, client_addr, packet)
+}
+
+/// Unwraps a datagram a relay forwarded to the server, returning the client's address and where the packet is in the datagram.
+#[cfg(any(test, feature = "std"))]
+pub(crate) fn unwrap_for_server(key: &Key, datagram: &[u8]) -> Result<(SocketAddr, Range<usize>)> {
+    unwrap(TO_SERVER, key, datagram)
+}
+
+fn wrap(prefix: u8, key: &Key, client_addr: SocketAddr, packet: &[u8]) -> Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(packet.len() + header_bytes(client_addr));
+    buf.push(prefix);
+    match client_addr.ip() {
+        IpAddr::V4(ip) => {
+            buf.push(4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+    buf.extend_from_slice(&client_addr.port().to_le_bytes());
+    buf.extend_from_slice(packet);
+    let signed_len = buf.len();
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    buf.extend_from_slice(&nonce);
+    buf.resize(signed_len + NONCE_BYTES + MAC_BYTES, 0);
+    let (signed, mac) = buf.split_at_mut(signed_len + NONCE_BYTES);
+    crypto::xchacha_encrypt(mac, Some(&signed[..signed_len]), nonce, key)?;
+    Ok(buf)
+}
+
+fn unwrap(prefix: u8, key: &Key, datagram: &[u8]) -> Result<(SocketAddr, Range<usize>)> {
+    let ip_bytes = match datagram {
+        [p, 4, ..] if *p == prefix => 4,
+        [p, 6, ..] if *p == prefix => 16,
+        _ => return Err(Error::InvalidRelayHeader),
+    };
+    let packet_start = 2 + ip_bytes + 2;
+    if datagram.len() < packet_start + NONCE_BYTES + MAC_BYTES {
+        return Err(Error::InvalidRelayHeader);
+    }
+    let signed_len = datagram.len() - NONCE_BYTES - MAC_BYTES;
+    let (signed, rest) = datagram.split_at(signed_len);
+    let (nonce, mac) = rest.split_at(NONCE_BYTES);
+    let mut mac: [u8; MAC_BYTES] = mac.try_into().expect("MAC size");
+    crypto::xchacha_decrypt(&mut mac, Some(signed), *XNonce::from_slice(nonce), key)?;
+
+    let ip = match ip_bytes {
+        4 => IpAddr::from(<[u8; 4]>::try_from(&signed[2..6]).expect("4 bytes")),
+        _ => IpAddr::from(<[u8; 16]>::try_from(&signed[2..18]).expect("16 bytes")),
+    };
+    let port = u16::from_le_bytes([signed[packet_start - 2], signed[packet_start - 1]]);
+    Ok((SocketAddr::new(ip, port), packet_start..signed_len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_roundtrip() {
+        let key = crypto::generate_key();
+        for addr in ["203.0.113.7:50000", "[2001:db8::7]:50000"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let datagram = wrap_for_server(&key, addr, b"packet").unwrap();
+            assert!(is_relayed(&datagram));
+            assert_eq!(datagram.len(), 6 + header_bytes(addr));
+            let (client_addr, packet) = unwrap_for_server(&key, &datagram).unwrap();
+            assert_eq!((client_addr, &datagram[packet]), (addr, &b"packet"[..]));
+            // the direction is part of the header, so a relay can't be made to reflect packets
+            assert!(unwrap_from_server(&key, &datagram).is_err());
+
+            let datagram = wrap_for_client(&key, addr, b"").unwrap();
+            assert_eq!(
+                unwrap_from_server(&key, &datagram).unwrap(),
+                (addr, &b""[..])
+            );
+        }
+
+        let addr: SocketAddr = "203.0.113.7:50000".parse().unwrap();
+        let mut datagram = wrap_for_server(&key, addr, b"packet").unwrap();
+        assert!(unwrap_for_server(&crypto::generate_key(), &datagram).is_err());
+        // claiming another address fails the MAC
+        datagram[5] = 8;
+        assert!(matches!(
+            unwrap_for_server(&key, &datagram),
+            Err(Error::Crypto(_))
+        ));
+        assert!(matches!(
+            unwrap_for_server(&key, &datagram[..20]),
+            Err(Error::InvalidRelayHeader)
+        ));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::rekey;
+
 const REPLAY_PROTECTION_BUFFER_SIZE: usize = 256;
 const UNRECEIVED: u64 = u64::MAX;
 
@@ -24,6 +26,18 @@ impl ReplayProtection {
         self.received_packet[index] = sequence;
     }
 
+    /// The highest sequence received so far, `None` if nothing was received yet.
+    pub fn most_recent_sequence(&self) -> Option<u64> {
+        let index = self.most_recent_sequence as usize % self.received_packet.len();
+        (self.received_packet[index] == self.most_recent_sequence)
+            .then_some(self.most_recent_sequence)
+    }
+
+    /// Whether exactly `sequence` was received, as long as it is still in the buffer.
+    pub fn was_received(&self, sequence: u64) -> bool {
+        self.received_packet[sequence as usize % self.received_packet.len()] == sequence
+    }
+
     pub fn is_already_received(&self, sequence: u64) -> bool {
         if sequence + self.received_packet.len() as u64 <= self.most_recent_sequence {
             return true;
@@ -39,6 +53,14 @@ impl ReplayProtection {
     }
 }
 
+/// Returns true if the most recent sequence skipped ahead of the one after `previous`,
+/// i.e. the packets in between were lost (or will arrive out of order). <br>
+/// Jumping to the start of a new epoch (see `rekey`) is not a gap.
+pub fn is_sequence_gap(previous: Option<u64>, most_recent: Option<u64>) -> bool {
+    matches!((previous, most_recent), (Some(previous), Some(most_recent)) if most_recent > previous + 1)
+        && !rekey::is_epoch_start(previous, most_recent)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,4 +93,24 @@ mod tests {
             (REPLAY_PROTECTION_BUFFER_SIZE * 2 - 1) as u64
         );
     }
+
+    #[test]
+    fn sequence_gaps() {
+        let mut replay_protection = ReplayProtection::new();
+        assert_eq!(replay_protection.most_recent_sequence(), None);
+        replay_protection.advance_sequence(0);
+        assert_eq!(replay_protection.most_recent_sequence(), Some(0));
+        replay_protection.advance_sequence(3);
+        // an older packet arriving late does not change the most recent sequence
+        replay_protection.advance_sequence(2);
+        assert_eq!(replay_protection.most_recent_sequence(), Some(3));
+
+        assert!(!is_sequence_gap(None, Some(5)));
+        assert!(!is_sequence_gap(Some(4), Some(5)));
+        assert!(!is_sequence_gap(Some(5), Some(5)));
+        assert!(is_sequence_gap(Some(3), Some(5)));
+        // a rekey jumps to the start of the next epoch
+        assert!(!is_sequence_gap(Some(3), Some(1 << 32)));
+        assert!(is_sequence_gap(Some(3), Some((1 << 32) + 1)));
+    }
 }
@@ -1,84 +1,114 @@
-use std::collections::{HashMap, VecDeque};
-use std::net::{SocketAddr, ToSocketAddrs};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+#[cfg(not(target_family = "wasm"))]
+use std::net::ToSocketAddrs;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::sync::{mpsc::Receiver, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
+use chacha20poly1305::aead::{
+    rand_core::{CryptoRng, CryptoRngCore, RngCore},
+    OsRng,
+};
+use zeroize::Zeroizing;
+
+#[cfg(unix)]
+use crate::admin::AdminSocket;
 use crate::{
+    ack::{self, AckTracker},
+    admin::{self, AdminCommand},
     bytes::Bytes,
-    crypto::{self, Key},
+    capture::{BoxedPacketLogger, PacketDirection, PacketLogger, PacketRecord},
+    clock::{BoxedClock, Clock, SystemClock},
+    cluster::{ServerCluster, Shard},
+    coalesce::{self, SendQueue, MESSAGE_HEADER_SIZE},
+    config,
+    crypto::{self, Cipher, Key},
+    diagnostics::EchoMode,
     error::{Error, Result},
-    free_list::FreeList,
+    free_list::GrowableFreeList,
+    handle::{ClientIds, Command, ServerHandle},
+    key::KeyProvider,
+    metrics::{self, Side},
+    pacing::{self, ByteBudget, Pacer},
     packet::{
-        ChallengePacket, DeniedPacket, DisconnectPacket, KeepAlivePacket, Packet, PayloadPacket,
-        RequestPacket, ResponsePacket,
+        ChallengePacket, DeniedPacket, DisconnectPacket, KeepAliveIntervalPacket, KeepAlivePacket,
+        Packet, PathChallengePacket, PathResponsePacket, PayloadLimitPacket, PayloadPacket,
+        RequestPacket, ResponsePacket, TransferPacket,
     },
-    replay::ReplayProtection,
-    socket::NetcodeSocket,
+    padding,
+    phase::{ConnectionPhase, PacketAllowList, PacketType, ServerPhaseTable},
+    pool::{PayloadQueues, QueueOverflow},
+    query::{self, QueryConfig, RateLimit},
+    recording::{Recorder, Recording, ReplayTransceiver},
+    rekey::{self, Rekey},
+    relay::{self, RelayConfig},
+    replay::{is_sequence_gap, ReplayProtection},
+    stats::{ProtocolStats, ServerStats},
+    tick_sync::{self, ServerStamp},
     token::{ChallengeToken, ConnectToken, ConnectTokenBuilder, ConnectTokenPrivate},
-    transceiver::Transceiver,
-    MAC_BYTES, MAX_PACKET_SIZE, MAX_PKT_BUF_SIZE, PACKET_SEND_RATE_SEC,
+    token_store::{BoxedTokenReplayStore, TokenEntries, TokenReplayStore},
+    trace,
+    transceiver::{SendBacklog, SendOutcome, Transceiver},
+    transfer, CHALLENGE_DATA_BYTES, CONNECT_TOKEN_BYTES, MAC_BYTES, MAX_HEARTBEAT_BYTES,
+    MAX_PACKET_SIZE, MAX_PKT_BUF_SIZE, NETCODE_VERSION, PACKET_SEND_RATE_SEC, USER_DATA_BYTES,
 };
 
+#[cfg(feature = "compression")]
+use crate::compression;
+#[cfg(not(target_family = "wasm"))]
+use crate::socket::{NetcodeSocket, SocketOptions};
+
+/// The default maximum number of clients of a server, see [`ServerConfig::max_clients`](ServerConfig::max_clients). <br>
+/// Connection slots are allocated as clients connect, so the limit can be raised past it at runtime with [`Server::set_max_clients`](Server::set_max_clients).
 pub const MAX_CLIENTS: usize = 256;
+// keep-alive packets carry the client index and the max clients as `i32`
+const MAX_CLIENTS_LIMIT: usize = i32::MAX as usize;
+#[cfg(not(target_family = "wasm"))]
 const RECV_BUF_SIZE: usize = 4 * 1024 * 1024;
+#[cfg(not(target_family = "wasm"))]
 const SEND_BUF_SIZE: usize = 4 * 1024 * 1024;
+// Packets from unknown addresses are tried against every connected client's key, so only that many are tried per update.
+const MAX_MIGRATION_PROBES_PER_UPDATE: usize = 16;
+// A challenged address that doesn't send another packet for this long has to start over.
+const MIGRATION_TIMEOUT_SEC: f64 = 5.0;
+// How long the relay a client was last heard through is remembered, once the client isn't connected.
+const RELAY_ROUTE_TIMEOUT_SEC: f64 = 30.0;
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(1);
+// The number of keep-alives a payload limit is repeated with, since it is not acknowledged by the client.
+const NUM_PAYLOAD_LIMIT_PACKETS: usize = 10;
+// The number of times a transfer token is sent, with the keep-alives after the first, since it is not acknowledged either.
+const NUM_TRANSFER_PACKETS: usize = 10;
+// The number of keep-alives a recommended keep-alive interval is sent with after a client connects.
+const NUM_KEEP_ALIVE_INTERVAL_PACKETS: usize = 10;
+// The challenge sequence is the nonce of the challenge token, so the challenge key is replaced before it can wrap.
+const CHALLENGE_SEQUENCE_LIMIT: u64 = u64::MAX;
 
-#[derive(Clone, Copy)]
-struct TokenEntry {
-    time: f64,
-    mac: [u8; 16],
-    addr: SocketAddr,
-}
-
-struct TokenEntries {
-    inner: Vec<TokenEntry>,
-}
-
-impl TokenEntries {
-    fn new() -> Self {
-        Self { inner: Vec::new() }
-    }
-    fn find_or_insert(&mut self, entry: TokenEntry) -> bool {
-        let (mut oldest, mut matching) = (None, None);
-        let mut oldest_time = f64::INFINITY;
-        // Perform a linear search for the oldest and matching entries at the same time
-        for (idx, saved_entry) in self.inner.iter().enumerate() {
-            if entry.time < oldest_time {
-                oldest_time = saved_entry.time;
-                oldest = Some(idx);
-            }
-            if entry.mac == saved_entry.mac {
-                matching = Some(idx);
-            }
-        }
-        let Some(oldest) = oldest else {
-            // If there is no oldest entry then the list is empty, so just insert the entry
-            self.inner.push(entry);
-            return true;
-        };
-        if let Some(matching) = matching {
-            // Allow reusing tokens only if the address matches
-            self.inner[matching].addr == entry.addr
-        } else {
-            // If there is no matching entry, replace the oldest one
-            self.inner[oldest] = entry;
-            true
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug)]
 struct Connection {
     confirmed: bool,
     connected: bool,
     client_id: ClientId,
     addr: SocketAddr,
     timeout: i32,
+    expire_timestamp: u64,
+    pending_since: f64,
     last_access_time: f64,
     last_send_time: f64,
     last_receive_time: f64,
-    send_key: Key,
-    receive_key: Key,
+    send_key: Zeroizing<Key>,
+    receive_key: Zeroizing<Key>,
     sequence: u64,
+    rekey: Rekey,
+    protocol_id: u64,
+    max_payload_size: Option<u16>,
+    payload_limit_resends: usize,
+    keep_alive_interval_resends: usize,
+    // the time of the last stamp received from the client (on its clock) and when it was received, see `tick_sync`
+    client_stamp: f64,
+    client_stamp_time: f64,
+    last_stamp_time: f64,
 }
 
 impl Connection {
@@ -124,44 +154,148 @@ impl std::fmt::Display for ClientIndex {
     }
 }
 
+/// The outcome of a [`Server::shutdown`](Server::shutdown).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ShutdownReport {
+    /// The number of clients that confirmed the disconnect with disconnect packets of their own.
+    pub acknowledged: usize,
+    /// The number of clients that didn't confirm the disconnect before the drain period ended,
+    /// e.g. because the disconnect packets were lost or the client doesn't confirm them (like the reference implementation).
+    pub timed_out: usize,
+}
+
+struct SessionCiphers {
+    send: Cipher,
+    receive: Cipher,
+}
+
+impl SessionCiphers {
+    fn new(send_key: Key, receive_key: Key, rekeying: bool) -> Self {
+        Self {
+            send: Cipher::with_rekeying(send_key, rekeying),
+            receive: Cipher::with_rekeying(receive_key, rekeying),
+        }
+    }
+}
+
+// A client that was connected when the server began shutting down, and hasn't confirmed the disconnect yet.
+struct DrainingClient {
+    receive: Cipher,
+    protocol_id: u64,
+    replay_protection: ReplayProtection,
+}
+
+struct Shutdown {
+    deadline: f64,
+    draining: HashMap<SocketAddr, DrainingClient>,
+    acknowledged: usize,
+}
+
+impl Shutdown {
+    fn is_done(&self, time: f64) -> bool {
+        self.draining.is_empty() || time >= self.deadline
+    }
+    fn report(&self) -> ShutdownReport {
+        ShutdownReport {
+            acknowledged: self.acknowledged,
+            timed_out: self.draining.len(),
+        }
+    }
+}
+
+// A connect token for another server that a client is being handed over to, see `Server::transfer_client`.
+struct Transfer {
+    token: Box<[u8; CONNECT_TOKEN_BYTES]>,
+    sends_left: usize,
+}
+
+// A connected client whose packets arrived from a new address, which the server challenged.
+// The client has to echo the nonce from that address before the session moves.
+struct PendingMigration {
+    client_idx: ClientIndex,
+    client_id: ClientId,
+    nonce: u64,
+    challenge_time: f64,
+    sent_time: f64,
+}
+
 struct ConnectionCache {
     // this somewhat mimics the original C implementation,
     // the main difference being that `Connection` includes the encryption mapping as well.
-    clients: FreeList<Connection, MAX_CLIENTS>,
+    clients: GrowableFreeList<Connection>,
 
     // we are not using a free-list here to not allocate memory up-front, since `ReplayProtection` is biggish (~2kb)
     replay_protection: HashMap<ClientIndex, ReplayProtection>,
 
-    // packet queue for all clients
-    packet_queue: VecDeque<(Vec<u8>, ClientIndex)>,
+    // the session keys of all clients with their AEADs set up, apart from `Connection` so both can be borrowed at once
+    ciphers: HashMap<ClientIndex, SessionCiphers>,
+
+    // packet queues of all clients
+    packet_queue: PayloadQueues,
+
+    // payloads waiting to be coalesced, only used if `coalesce_payloads` is enabled
+    send_queues: HashMap<ClientIndex, SendQueue>,
+
+    // sent payloads and their acks, only used if `ack_payloads` is enabled
+    acks: HashMap<ClientIndex, AckTracker>,
+
+    // heartbeats attached to the keep-alives of clients, see `Server::set_heartbeat_payload`
+    heartbeats: HashMap<ClientIndex, Vec<u8>>,
+
+    // transfer tokens still being sent to clients, see `Server::transfer_client`
+    transfers: HashMap<ClientIndex, Transfer>,
+
+    // the payload bytes clients may still be sent, see `Server::set_client_bandwidth_limit`
+    bandwidth_limits: HashMap<ClientIndex, ByteBudget>,
+
+    // whether the ciphers rekey, see `ServerConfig::rekey_sessions`
+    rekey_sessions: bool,
 
     // corresponds to the server time
     time: f64,
 }
 
 impl ConnectionCache {
-    fn new(server_time: f64) -> Self {
+    fn new(server_time: f64, packet_queue: PayloadQueues, rekey_sessions: bool) -> Self {
         Self {
-            clients: FreeList::new(),
-            replay_protection: HashMap::with_capacity(MAX_CLIENTS),
-            packet_queue: VecDeque::with_capacity(MAX_CLIENTS * 2),
+            clients: GrowableFreeList::new(),
+            replay_protection: HashMap::new(),
+            ciphers: HashMap::new(),
+            packet_queue,
+            send_queues: HashMap::new(),
+            acks: HashMap::new(),
+            heartbeats: HashMap::new(),
+            transfers: HashMap::new(),
+            bandwidth_limits: HashMap::new(),
+            rekey_sessions,
             time: server_time,
         }
     }
     fn add(
         &mut self,
-        client_id: ClientId,
         addr: SocketAddr,
+        token: &ConnectTokenPrivate,
         timeout: i32,
-        send_key: Key,
-        receive_key: Key,
+        expire_timestamp: u64,
+        protocol_id: u64,
     ) {
-        if let Some((_, ref mut existing)) = self.find_by_addr(&addr) {
+        let (client_id, send_key, receive_key) = (
+            token.client_id,
+            &token.server_to_client_key,
+            &token.client_to_server_key,
+        );
+        let ciphers = SessionCiphers::new(**send_key, **receive_key, self.rekey_sessions);
+        if let Some((idx, _)) = self.find_by_addr(&addr) {
+            let existing = &mut self.clients[idx.0];
             existing.client_id = client_id;
             existing.timeout = timeout;
-            existing.send_key = send_key;
-            existing.receive_key = receive_key;
+            existing.expire_timestamp = expire_timestamp;
+            existing.send_key = send_key.clone();
+            existing.receive_key = receive_key.clone();
+            existing.protocol_id = protocol_id;
             existing.last_access_time = self.time;
+            self.ciphers.insert(idx, ciphers);
             return;
         }
         let conn = Connection {
@@ -170,16 +304,32 @@ impl ConnectionCache {
             client_id,
             addr,
             timeout,
+            expire_timestamp,
+            pending_since: self.time,
             last_access_time: self.time,
             last_send_time: f64::NEG_INFINITY,
             last_receive_time: f64::NEG_INFINITY,
-            send_key,
-            receive_key,
+            send_key: send_key.clone(),
+            receive_key: receive_key.clone(),
             sequence: 0,
+            rekey: Rekey::new(0, self.time),
+            protocol_id,
+            max_payload_size: None,
+            payload_limit_resends: 0,
+            keep_alive_interval_resends: 0,
+            client_stamp: f64::NAN,
+            client_stamp_time: f64::NAN,
+            last_stamp_time: f64::NEG_INFINITY,
         };
         let client_idx = ClientIndex(self.clients.insert(conn));
         self.replay_protection
             .insert(client_idx, ReplayProtection::new());
+        self.ciphers.insert(client_idx, ciphers);
+    }
+    fn cipher(&mut self, client_idx: ClientIndex) -> &mut SessionCiphers {
+        self.ciphers
+            .get_mut(&client_idx)
+            .expect("every connection has its ciphers")
     }
     fn remove(&mut self, client_idx: ClientIndex) {
         let Some(conn) = self.clients.get_mut(client_idx.0) else {
@@ -189,29 +339,174 @@ impl ConnectionCache {
             return;
         }
         self.replay_protection.remove(&client_idx);
-        self.clients.remove(client_idx.0);
+        self.send_queues.remove(&client_idx);
+        self.acks.remove(&client_idx);
+        self.free(client_idx.0);
     }
-    fn find_by_addr(&self, addr: &SocketAddr) -> Option<(ClientIndex, Connection)> {
+    // Freeing the slot drops the connection, which wipes its session keys.
+    fn free(&mut self, idx: usize) {
+        self.ciphers.remove(&ClientIndex(idx));
+        self.heartbeats.remove(&ClientIndex(idx));
+        self.transfers.remove(&ClientIndex(idx));
+        self.bandwidth_limits.remove(&ClientIndex(idx));
+        self.clients.remove(idx);
+    }
+    fn find_by_addr(&self, addr: &SocketAddr) -> Option<(ClientIndex, &Connection)> {
         self.clients
             .iter()
             .find_map(|(idx, conn)| (conn.addr == *addr).then_some((ClientIndex(idx), conn)))
     }
-    fn find_by_id(&self, client_id: ClientId) -> Option<(ClientIndex, Connection)> {
+    fn find_by_id(&self, client_id: ClientId) -> Option<(ClientIndex, &Connection)> {
         self.clients.iter().find_map(|(idx, conn)| {
             (conn.client_id == client_id).then_some((ClientIndex(idx), conn))
         })
     }
+    fn find_connected_by_id(&self, client_id: ClientId) -> Option<ClientIndex> {
+        self.clients.iter().find_map(|(idx, conn)| {
+            (conn.client_id == client_id && conn.is_connected()).then_some(ClientIndex(idx))
+        })
+    }
+    fn num_pending(&self) -> usize {
+        self.clients
+            .iter()
+            .filter(|(_, conn)| !conn.is_connected())
+            .count()
+    }
+    // Removes the pending connection the policy picks to make room for a new one, returns its client id and address.
+    fn evict_pending(&mut self, eviction: PendingEviction) -> Option<(ClientId, SocketAddr)> {
+        let key = |conn: &Connection| match eviction {
+            PendingEviction::Oldest => conn.pending_since,
+            PendingEviction::LeastRecentlyUsed => conn.last_access_time,
+        };
+        let (idx, conn) = self
+            .clients
+            .iter()
+            .filter(|(_, conn)| !conn.is_connected())
+            .min_by(|(_, a), (_, b)| key(a).total_cmp(&key(b)))?;
+        let evicted = (conn.client_id, conn.addr);
+        self.remove_pending(idx);
+        Some(evicted)
+    }
+    fn remove_pending(&mut self, idx: usize) {
+        self.replay_protection.remove(&ClientIndex(idx));
+        self.free(idx);
+    }
     fn update(&mut self, time: f64) {
         self.time = time;
     }
 }
-type Callback<Ctx> = Box<dyn FnMut(ClientIndex, &mut Ctx) + Send + Sync + 'static>;
+
+impl Drop for ConnectionCache {
+    fn drop(&mut self) {
+        for idx in 0..self.clients.slots() {
+            self.free(idx);
+        }
+    }
+}
+
+/// Which pending connection the server drops when its pending connection table is full,
+/// see [`ServerConfig::pending_eviction`](ServerConfig::pending_eviction).
+///
+/// A pending connection is a client that was sent a challenge, but hasn't answered it yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum PendingEviction {
+    /// Drop the connection that has been pending the longest.
+    #[default]
+    Oldest,
+    /// Drop the connection that sent its last connection request the longest ago,
+    /// so clients that keep retrying hold on to their slot.
+    LeastRecentlyUsed,
+}
+
+/// What the server does when a client connects with the client id of a client that is already connected,
+/// see [`ServerConfig::duplicate_client_id`](ServerConfig::duplicate_client_id).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum DuplicateClientId {
+    /// Ignore the new client's connection requests, the connected client keeps its session.
+    #[default]
+    RejectNew,
+    /// Disconnect the connected client once the new one answers its challenge, and connect the new one in its place,
+    /// e.g. for clients that reconnect after a crash while their stale session still holds a slot.
+    KickExisting,
+    /// Connect the new client as well, under its own client index, e.g. for several windows of one account.
+    AllowBoth,
+}
+
+/// Whether the server lets a client connect, returned by the [`connect_filter`](ServerConfig::connect_filter) callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectDecision {
+    /// Send the client a challenge, as if there was no filter.
+    Accept,
+    /// Send the client a connection denied packet, like when the server is full. <br>
+    /// The reason is logged by the server, the client only sees [`ClientState::ConnectionDenied`](crate::ClientState::ConnectionDenied).
+    Deny(String),
+}
+
+pub(crate) type Callback<Ctx> = Box<dyn FnMut(ClientIndex, &mut Ctx) + Send + Sync + 'static>;
+type ConnectFilterCallback<Ctx> = Box<
+    dyn FnMut(ClientId, SocketAddr, &[u8; USER_DATA_BYTES], &mut Ctx) -> ConnectDecision
+        + Send
+        + Sync
+        + 'static,
+>;
+type ChallengeDataCallback<Ctx> = Box<
+    dyn FnMut(ClientId, &[u8; USER_DATA_BYTES], &mut Ctx) -> [u8; CHALLENGE_DATA_BYTES]
+        + Send
+        + Sync
+        + 'static,
+>;
+type ConnectDataCallback<Ctx> =
+    Box<dyn FnMut(ClientIndex, &[u8; CHALLENGE_DATA_BYTES], &mut Ctx) + Send + Sync + 'static>;
+type HeartbeatCallback<Ctx> = Box<dyn FnMut(ClientIndex, &[u8], &mut Ctx) + Send + Sync + 'static>;
+type BoxedRng = Box<dyn CryptoRngCore + Send + Sync + 'static>;
 /// Configuration for a server.
 ///
 /// * `num_disconnect_packets` - The number of redundant disconnect packets that will be sent to a client when the server is disconnecting it.
 /// * `keep_alive_send_rate` - The rate at which keep-alive packets will be sent to clients.
+/// * `keep_alive_interval` - The interval clients are asked to send keep-alives at, to hold their NAT mappings.
+/// * `max_payload_size` - The largest payload the server will send, for networks with a smaller MTU.
+/// * `timeout_seconds` - Overrides the connection timeout from the clients' connect tokens.
+/// * `rekey_sessions` - Whether the key of each session is replaced on long connections, which the clients have to agree on.
+/// * `rekey_interval` - How often the key of each session is replaced on long connections.
+/// * `max_clients` - The number of clients that can be connected at the same time.
+/// * `max_pending_connections` - The number of clients that can be waiting to answer a challenge at once.
+/// * `pending_eviction` - Which pending connection is dropped when there are too many, see [`PendingEviction`](PendingEviction).
+/// * `pending_timeout` - How long a pending connection is kept while the client sends nothing.
+/// * `duplicate_client_id` - What happens when a client connects with the id of a connected client, see [`DuplicateClientId`](DuplicateClientId).
+/// * `public_addrs` - The addresses connect tokens may list for a server bound to an unspecified address.
+/// * `relay` - How the server runs behind UDP relays, see [`relay`](crate::relay).
+/// * `queries` - Whether the server answers out-of-band queries from server browsers, see [`query`](crate::query).
+/// * `recv_queue_depth` - The number of received payloads queued per client, see [`Server::recv_from`](Server::recv_from).
+/// * `recv_queue_overflow` - What happens to payloads from a client whose queue is full, see [`QueueOverflow`](QueueOverflow).
+/// * `echo_mode` - Whether received payloads are echoed back to their sender for diagnostics, see [`EchoMode`](EchoMode).
+/// * `allowed_packets` - The packet types accepted in each [`ConnectionPhase`](ConnectionPhase).
+/// * `protocol_ids` - Additional protocol ids accepted by the server, e.g. from older client builds during a rollout.
+/// * `strict_netcode_1_02` - Whether to only accept the exact wire format of the netcode 1.02 reference implementation.
+/// * `ack_on_sequence_gap` - Whether a keep-alive is sent right away when packets from a client were lost.
+/// * `allow_migration` - Whether connected clients can keep their session when their address changes.
+/// * `coalesce_payloads` - Whether payloads sent to a client in the same tick are combined into one packet.
+/// * `pace_packets` - Whether the packets of a tick are spread over the tick instead of sent in one burst, see [`Server::pace`](Server::pace).
+/// * `compress_payloads` - The size above which payloads are compressed, requires the `compression` feature.
+/// * `allow_insecure` - Whether clients can connect without a token from a matchmaker, requires the `insecure` feature.
+/// * `ack_payloads` - Whether payloads carry acks of the packets received from the client, see [`Server::acks`](Server::acks).
+/// * `tick_rate` - The rate of the server's tick shared with clients, if tick sync is enabled, see [`Server::tick`](Server::tick).
+/// * `pad_payloads` - The sizes payloads are padded to, so the size of a packet doesn't tell what it carries.
+/// * `socket_options` - Options of the socket the server creates, e.g. DSCP marking, see [`SocketOptions`](crate::SocketOptions).
+/// * `packet_logger` - A hook that receives every raw packet sent and received, see [`PacketLogger`](PacketLogger).
+/// * `record` - Where the datagrams the server receives are recorded, to replay them with [`Server::replay`](Server::replay).
+/// * `admin_socket` - The Unix domain socket the admin console listens on, see [`Server::admin_command`](Server::admin_command).
+/// * `clock` - The wall clock connect tokens are checked for expiry against, see [`Clock`](Clock).
+/// * `rng` - The RNG the server's challenge keys are generated with.
+/// * `token_replay_store` - Where the connect tokens that were already used are remembered, see [`TokenReplayStore`](TokenReplayStore).
+/// * `connect_filter` - A callback that decides whether a client with a valid connect token may connect, e.g. to check a ban list.
 /// * `on_connect` - A callback that will be called when a client is connected to the server.
 /// * `on_disconnect` - A callback that will be called when a client is disconnected from the server.
+/// * `challenge_data` - A callback that makes the custom data attached to each challenge token.
+/// * `on_connect_with_data` - A callback that will be called when a client is connected, with the custom data of its challenge token.
+/// * `on_heartbeat` - A callback that will be called with the heartbeats clients attach to their keep-alive packets.
 ///
 /// # Example
 /// ```
@@ -232,22 +527,134 @@ type Callback<Ctx> = Box<dyn FnMut(ClientIndex, &mut Ctx) + Send + Sync + 'stati
 pub struct ServerConfig<Ctx> {
     num_disconnect_packets: usize,
     keep_alive_send_rate: f64,
+    keep_alive_interval: Option<f64>,
+    max_payload_size: usize,
+    timeout_seconds: Option<i32>,
+    rekey_sessions: bool,
+    rekey_interval: Option<f64>,
+    max_clients: usize,
+    max_pending_connections: usize,
+    pending_eviction: PendingEviction,
+    pending_timeout: Option<f64>,
+    duplicate_client_id: DuplicateClientId,
+    public_addrs: Vec<SocketAddr>,
+    relay: Option<RelayConfig>,
+    queries: Option<QueryConfig>,
+    recv_queue_depth: usize,
+    recv_queue_overflow: QueueOverflow,
+    echo_mode: EchoMode,
+    allowed_packets: ServerPhaseTable,
+    protocol_ids: Vec<u64>,
+    strict_netcode_1_02: bool,
+    ack_on_sequence_gap: bool,
+    allow_migration: bool,
+    coalesce_payloads: bool,
+    pacing: Option<(f64, usize)>,
+    #[cfg(feature = "compression")]
+    compression_threshold: Option<usize>,
+    #[cfg(feature = "insecure")]
+    allow_insecure: bool,
+    ack_payloads: bool,
+    tick_rate: Option<f64>,
+    padding_buckets: Vec<usize>,
+    #[cfg(not(target_family = "wasm"))]
+    socket_options: SocketOptions,
+    packet_logger: Option<BoxedPacketLogger>,
+    recorder: Option<Recorder>,
+    #[cfg(unix)]
+    admin_socket: Option<PathBuf>,
+    clock: BoxedClock,
+    rng: BoxedRng,
+    token_replay_store: BoxedTokenReplayStore,
     context: Ctx,
-    on_connect: Option<Callback<Ctx>>,
-    on_disconnect: Option<Callback<Ctx>>,
+    connect_filter: Option<ConnectFilterCallback<Ctx>>,
+    pub(crate) on_connect: Option<Callback<Ctx>>,
+    pub(crate) on_disconnect: Option<Callback<Ctx>>,
+    challenge_data: Option<ChallengeDataCallback<Ctx>>,
+    on_connect_with_data: Option<ConnectDataCallback<Ctx>>,
+    on_heartbeat: Option<HeartbeatCallback<Ctx>>,
 }
 impl Default for ServerConfig<()> {
     fn default() -> Self {
         Self {
             num_disconnect_packets: 10,
             keep_alive_send_rate: PACKET_SEND_RATE_SEC,
+            keep_alive_interval: None,
+            max_payload_size: MAX_PACKET_SIZE,
+            timeout_seconds: None,
+            rekey_sessions: false,
+            rekey_interval: None,
+            max_clients: MAX_CLIENTS,
+            max_pending_connections: MAX_CLIENTS,
+            pending_eviction: PendingEviction::Oldest,
+            pending_timeout: None,
+            duplicate_client_id: DuplicateClientId::RejectNew,
+            public_addrs: Vec::new(),
+            relay: None,
+            queries: None,
+            recv_queue_depth: usize::MAX,
+            recv_queue_overflow: QueueOverflow::DropNewest,
+            echo_mode: EchoMode::Off,
+            allowed_packets: ServerPhaseTable::DEFAULT,
+            protocol_ids: Vec::new(),
+            strict_netcode_1_02: false,
+            ack_on_sequence_gap: false,
+            allow_migration: false,
+            coalesce_payloads: false,
+            pacing: None,
+            #[cfg(feature = "compression")]
+            compression_threshold: None,
+            #[cfg(feature = "insecure")]
+            allow_insecure: false,
+            ack_payloads: false,
+            tick_rate: None,
+            padding_buckets: Vec::new(),
+            #[cfg(not(target_family = "wasm"))]
+            socket_options: SocketOptions::default(),
+            packet_logger: None,
+            recorder: None,
+            #[cfg(unix)]
+            admin_socket: None,
+            clock: Box::new(SystemClock),
+            rng: Box::new(OsRng),
+            token_replay_store: Box::new(TokenEntries::new()),
             context: (),
+            connect_filter: None,
             on_connect: None,
             on_disconnect: None,
+            challenge_data: None,
+            on_connect_with_data: None,
+            on_heartbeat: None,
         }
     }
 }
 
+impl ServerConfig<()> {
+    /// A preset for fast-paced games: clients that stop sending are dropped after 5 seconds instead of the token's timeout,
+    /// a keep-alive goes out as soon as a client's packets are lost, and stale payloads are dropped first when a client's
+    /// receive queue (64 payloads) overflows. <br>
+    /// It only changes options local to the server, so clients with any configuration can connect.
+    /// Adjust it further with the setters, e.g. `ServerConfig::competitive().tick_sync(60.0)`.
+    pub fn competitive() -> Self {
+        Self::default()
+            .timeout_seconds(5)
+            .ack_on_sequence_gap(true)
+            .recv_queue_depth(64)
+            .recv_queue_overflow(QueueOverflow::DropOldest)
+    }
+    /// A preset for slow-paced games with many idle clients, e.g. turn-based or social games: keep-alives are sent to clients
+    /// 4 times per second, clients are recommended to send theirs once per second, and clients are dropped after 30 seconds
+    /// so short outages on mobile networks don't disconnect them. <br>
+    /// It only changes options local to the server, so clients with any configuration can connect.
+    /// Adjust it further with the setters, e.g. `ServerConfig::casual().allow_migration(true)`.
+    pub fn casual() -> Self {
+        Self::default()
+            .keep_alive_send_rate(0.25)
+            .keep_alive_interval(1.0)
+            .timeout_seconds(30)
+    }
+}
+
 impl<Ctx> ServerConfig<Ctx> {
     /// Create a new, default server configuration with no context.
     pub fn new() -> ServerConfig<()> {
@@ -258,9 +665,52 @@ impl<Ctx> ServerConfig<Ctx> {
         Self {
             num_disconnect_packets: 10,
             keep_alive_send_rate: PACKET_SEND_RATE_SEC,
+            keep_alive_interval: None,
+            max_payload_size: MAX_PACKET_SIZE,
+            timeout_seconds: None,
+            rekey_sessions: false,
+            rekey_interval: None,
+            max_clients: MAX_CLIENTS,
+            max_pending_connections: MAX_CLIENTS,
+            pending_eviction: PendingEviction::Oldest,
+            pending_timeout: None,
+            duplicate_client_id: DuplicateClientId::RejectNew,
+            public_addrs: Vec::new(),
+            relay: None,
+            queries: None,
+            recv_queue_depth: usize::MAX,
+            recv_queue_overflow: QueueOverflow::DropNewest,
+            echo_mode: EchoMode::Off,
+            allowed_packets: ServerPhaseTable::DEFAULT,
+            protocol_ids: Vec::new(),
+            strict_netcode_1_02: false,
+            ack_on_sequence_gap: false,
+            allow_migration: false,
+            coalesce_payloads: false,
+            pacing: None,
+            #[cfg(feature = "compression")]
+            compression_threshold: None,
+            #[cfg(feature = "insecure")]
+            allow_insecure: false,
+            ack_payloads: false,
+            tick_rate: None,
+            padding_buckets: Vec::new(),
+            #[cfg(not(target_family = "wasm"))]
+            socket_options: SocketOptions::default(),
+            packet_logger: None,
+            recorder: None,
+            #[cfg(unix)]
+            admin_socket: None,
+            clock: Box::new(SystemClock),
+            rng: Box::new(OsRng),
+            token_replay_store: Box::new(TokenEntries::new()),
             context: ctx,
+            connect_filter: None,
             on_connect: None,
             on_disconnect: None,
+            challenge_data: None,
+            on_connect_with_data: None,
+            on_heartbeat: None,
         }
     }
     /// Set the number of redundant disconnect packets that will be sent to a client when the server is disconnecting it. <br>
@@ -275,6 +725,377 @@ impl<Ctx> ServerConfig<Ctx> {
         self.keep_alive_send_rate = rate_seconds;
         self
     }
+    /// Recommend an interval (in seconds) at which clients send keep-alives while they have no payloads to send, instead of their
+    /// [`packet_send_rate`](crate::ClientConfig::packet_send_rate). <br>
+    /// NAT mappings are dropped when no packets pass for a while, after as little as 30 seconds on the carrier-grade NATs of mobile networks,
+    /// so keep the interval well below that and below the connection timeout. A longer interval saves idle clients bandwidth and battery.
+    /// Clients can still override it for their platform with [`ClientConfig::keep_alive_interval`](crate::ClientConfig::keep_alive_interval). <br>
+    /// The interval is sent with the first keep-alives of each connection, with a precision of milliseconds.
+    /// This is an extension of the netcode protocol: other implementations, or clients in
+    /// [strict netcode 1.02](crate::ClientConfig::strict_netcode_1_02) mode, ignore it, and it is not sent with
+    /// [`strict_netcode_1_02`](ServerConfig::strict_netcode_1_02). By default no interval is recommended.
+    pub fn keep_alive_interval(mut self, interval_seconds: f64) -> Self {
+        self.keep_alive_interval = Some(interval_seconds);
+        self
+    }
+    /// Override the connection timeout (in seconds) from the clients' connect tokens, negative for no timeout. <br>
+    /// A client is disconnected if the server doesn't receive any packets from it for this long.
+    /// By default the timeout from each client's connect token is used.
+    pub fn timeout_seconds(mut self, timeout_seconds: i32) -> Self {
+        self.timeout_seconds = Some(timeout_seconds);
+        self
+    }
+    /// Disable connection timeouts, e.g. to pause a client or the server on a breakpoint without losing the connection. <br>
+    /// Equivalent to `timeout_seconds(-1)`, clients have to disable timeouts as well to keep the connection alive,
+    /// see [`ClientConfig::disable_timeout`](crate::ClientConfig::disable_timeout).
+    pub fn disable_timeout(self) -> Self {
+        self.timeout_seconds(-1)
+    }
+    /// Set whether the session keys are replaced every 2^32 packets, with keys derived from the session key and the epoch
+    /// in the upper 32 bits of the packet sequence, for connections that stay up for days. <br>
+    /// This changes the wire format, so all clients have to enable [`ClientConfig::rekey_sessions`](crate::ClientConfig::rekey_sessions) too:
+    /// clients that don't (and other implementations of the protocol) drop the rekeyed packets, and their connections time out.
+    /// The default is `false`, every packet is encrypted with the session key like the reference implementation does.
+    pub fn rekey_sessions(mut self, rekey_sessions: bool) -> Self {
+        self.rekey_sessions = rekey_sessions;
+        self
+    }
+    /// Set the interval (in seconds) after which the server replaces the key it encrypts each client's packets with, and enable
+    /// [`rekey_sessions`](ServerConfig::rekey_sessions). <br>
+    /// The server jumps the client's packet sequence to the next epoch, so the client follows without a handshake.
+    /// Regardless of the interval, the key is replaced every 2^32 packets. The default is no interval.
+    pub fn rekey_interval(mut self, interval_seconds: f64) -> Self {
+        self.rekey_interval = Some(interval_seconds);
+        self.rekey_sessions = true;
+        self
+    }
+    /// Set the number of clients that can be connected at the same time, which can be changed later with
+    /// [`Server::set_max_clients`](Server::set_max_clients). <br>
+    /// Connection slots are allocated as clients connect, so the limit only bounds memory once clients are connected.
+    /// A limit that doesn't fit the `i32` of keep-alive packets makes creating the server fail with [`Error::MaxClients`](Error::MaxClients). <br>
+    /// The default is [`MAX_CLIENTS`](MAX_CLIENTS).
+    pub fn max_clients(mut self, max_clients: usize) -> Self {
+        self.max_clients = max_clients;
+        self
+    }
+    /// Set the number of clients that can be waiting to answer a challenge at once. <br>
+    /// A connection request that would exceed it evicts a pending connection picked by the [`pending_eviction`](ServerConfig::pending_eviction) policy,
+    /// and evictions are counted in [`ServerStats::pending_evictions`](crate::ServerStats::pending_evictions).
+    /// Pending connections take connection slots on top of the connected clients. <br>
+    /// The default is [`MAX_CLIENTS`](MAX_CLIENTS).
+    pub fn max_pending_connections(mut self, max_pending_connections: usize) -> Self {
+        self.max_pending_connections = max_pending_connections;
+        self
+    }
+    /// Set which pending connection is dropped when a connection request arrives while the pending connection table is full. <br>
+    /// The default is [`PendingEviction::Oldest`](PendingEviction::Oldest).
+    pub fn pending_eviction(mut self, pending_eviction: PendingEviction) -> Self {
+        self.pending_eviction = pending_eviction;
+        self
+    }
+    /// Set how long (in seconds) a pending connection is kept while the client sends nothing, after which its slot and
+    /// session keys are freed and it is counted in [`ServerStats::pending_timeouts`](crate::ServerStats::pending_timeouts). <br>
+    /// Clients resend their connection requests and challenge responses until they connect, so only clients that gave up time out.
+    /// A shorter timeout frees the pending connection table sooner under connection churn, a longer one keeps clients on slow
+    /// networks from losing their pending connection, `f64::INFINITY` keeps pending connections until they are evicted. <br>
+    /// The default is the timeout from each client's connect token, like the reference implementation.
+    pub fn pending_timeout(mut self, timeout_seconds: f64) -> Self {
+        self.pending_timeout = Some(timeout_seconds);
+        self
+    }
+    /// Set what the server does when a client connects with the client id of a client that is already connected. <br>
+    /// With [`KickExisting`](DuplicateClientId::KickExisting), the new client takes over the slot of the connected one,
+    /// so it can connect even when the server is full. With [`AllowBoth`](DuplicateClientId::AllowBoth), lookups by client id
+    /// such as [`Server::reserve_slot`](Server::reserve_slot) find either of the clients. <br>
+    /// The default is [`DuplicateClientId::RejectNew`](DuplicateClientId::RejectNew), like the reference implementation.
+    pub fn duplicate_client_id(mut self, policy: DuplicateClientId) -> Self {
+        self.duplicate_client_id = policy;
+        self
+    }
+    /// Set the addresses the server is reachable on, for a server bound to an unspecified address
+    /// (e.g. a [dual-stack](crate::NetcodeSocket::dual_stack) `[::]` socket, or `0.0.0.0`). <br>
+    /// A connect token is only accepted if it lists the address the server is bound to or one of these,
+    /// so a token issued for another server can't be used on this one. A server bound to `0.0.0.0:40000` doesn't know which of
+    /// the host's addresses its tokens list, list them here, e.g. `&["203.0.113.7:40000".parse().unwrap()]`. <br>
+    /// The default is no public addresses: tokens have to list the exact address the server is bound to.
+    pub fn public_addrs(mut self, addrs: &[SocketAddr]) -> Self {
+        self.public_addrs = addrs.to_vec();
+        self
+    }
+    /// Run the server behind UDP relays that forward packets with a header carrying the real client address,
+    /// see the [`relay`](crate::relay) module. <br>
+    /// Sessions key off the client address in the header, and packets to a client are sent back through the relay it was last heard through.
+    /// Datagrams with invalid headers are dropped and counted in [`ServerStats::invalid_relay_headers`](crate::ServerStats::invalid_relay_headers). <br>
+    /// Clients connect to a relay, so their connect tokens list the relay addresses, with the server's own address as the
+    /// [internal address](crate::ConnectTokenBuilder::internal_addresses). The default is no relays.
+    pub fn relay(mut self, cfg: RelayConfig) -> Self {
+        self.relay = Some(cfg);
+        self
+    }
+    /// Answer out-of-band queries for the server's name and player count, see the [`query`](crate::query) module. <br>
+    /// Queries are answered for any of the server's protocol ids, answered and rate limited queries are counted in
+    /// [`ServerStats::queries_answered`](crate::ServerStats::queries_answered) and
+    /// [`ServerStats::queries_rate_limited`](crate::ServerStats::queries_rate_limited). <br>
+    /// The default is not to answer queries, they are dropped like any other invalid packet.
+    pub fn queries(mut self, cfg: QueryConfig) -> Self {
+        self.queries = Some(cfg);
+        self
+    }
+    /// Set the largest payload (in bytes) the server will send, [`Server::send`](Server::send) returns an error for larger payloads. <br>
+    /// Lower it on networks with a smaller MTU (VPNs, mobile) where full size packets would be silently dropped,
+    /// each packet adds up to 25 bytes of netcode overhead on top of the payload, plus the IP and UDP headers. <br>
+    /// The value is clamped to `1..=MAX_PACKET_SIZE`, the default is [`MAX_PACKET_SIZE`](crate::MAX_PACKET_SIZE) (1200 bytes).
+    pub fn max_payload_size(mut self, max_payload_size: usize) -> Self {
+        self.max_payload_size = max_payload_size.clamp(1, MAX_PACKET_SIZE);
+        self
+    }
+    /// Set the number of received payloads queued for each client until they are [received](Server::recv_from). <br>
+    /// A payload from a client whose queue is full is dropped according to the [`recv_queue_overflow`](ServerConfig::recv_queue_overflow) policy,
+    /// and counted in [`ServerStats::recv_queue_overflows`](crate::ServerStats::recv_queue_overflows).
+    /// The value is clamped to at least 1, the default is no limit.
+    pub fn recv_queue_depth(mut self, recv_queue_depth: usize) -> Self {
+        self.recv_queue_depth = recv_queue_depth.max(1);
+        self
+    }
+    /// Set which payload is dropped when a payload arrives from a client whose receive queue is full,
+    /// see [`recv_queue_depth`](ServerConfig::recv_queue_depth). <br>
+    /// The default is [`QueueOverflow::DropNewest`](QueueOverflow::DropNewest).
+    pub fn recv_queue_overflow(mut self, recv_queue_overflow: QueueOverflow) -> Self {
+        self.recv_queue_overflow = recv_queue_overflow;
+        self
+    }
+    /// Set the diagnostics echo mode of the server. <br>
+    /// While echoing, received payloads are sent straight back to their sender instead of being queued,
+    /// which allows clients to run a [link check](crate::Client::start_link_check) before a match starts.
+    /// The default is [`EchoMode::Off`](EchoMode::Off).
+    pub fn echo_mode(mut self, echo_mode: EchoMode) -> Self {
+        self.echo_mode = echo_mode;
+        self
+    }
+    /// Restrict the packet types the server accepts from addresses in a connection phase. <br>
+    /// Packets of other types are dropped before decryption and counted in [`ServerStats::out_of_phase`](crate::ServerStats::out_of_phase).
+    ///
+    /// The list can only narrow the protocol defaults, which are:
+    /// * [`Unconnected`](ConnectionPhase::Unconnected) - connection requests.
+    /// * [`Pending`](ConnectionPhase::Pending) - connection requests and challenge responses.
+    /// * [`Connected`](ConnectionPhase::Connected) - keep-alive, payload and disconnect packets.
+    ///
+    /// # Example
+    /// ```
+    /// use netcode::{ConnectionPhase, PacketAllowList, ServerConfig};
+    ///
+    /// // stop accepting new connections, e.g. while draining the server
+    /// let cfg = ServerConfig::default().allowed_packets(ConnectionPhase::Unconnected, PacketAllowList::NONE);
+    /// ```
+    pub fn allowed_packets(mut self, phase: ConnectionPhase, list: PacketAllowList) -> Self {
+        self.allowed_packets.set_for_phase(phase, list);
+        self
+    }
+    /// Accept connections with `protocol_id` in addition to the server's own protocol id. <br>
+    /// Use it to roll out a protocol change without disconnecting the clients of older builds: the server keeps accepting tokens
+    /// and packets with the old protocol id, each client stays on the protocol id it connected with
+    /// (see [`Server::client_protocol_id`](Server::client_protocol_id)) and [`Server::protocol_stats`](Server::protocol_stats)
+    /// tracks how many clients are still connected with each of them. <br>
+    /// Connect tokens for older builds have to be generated with their protocol id, e.g. with [`ConnectToken::build`](ConnectToken::build).
+    pub fn accept_protocol_id(mut self, protocol_id: u64) -> Self {
+        if !self.protocol_ids.contains(&protocol_id) {
+            self.protocol_ids.push(protocol_id);
+        }
+        self
+    }
+    /// Restrict the wire format to the netcode 1.02 reference implementation, to interoperate with clients written in C. <br>
+    /// Packets the reference implementation would reject (connection requests that aren't exactly 1078 bytes,
+    /// control packets with missing or trailing bytes and empty payloads) are dropped, sending an empty payload returns an error,
+    /// and IPv6 addresses in connect tokens are encoded as 8 little-endian 16-bit segments like the reference implementation does.
+    /// Tokens for a strict server have to be generated with [`ConnectTokenBuilder::strict_netcode_1_02`](crate::ConnectTokenBuilder::strict_netcode_1_02)
+    /// if they contain IPv6 addresses.
+    /// The default is `false`.
+    pub fn strict_netcode_1_02(mut self, strict: bool) -> Self {
+        self.strict_netcode_1_02 = strict;
+        self
+    }
+    /// Send a keep-alive to the client right away when a packet from it skips ahead in sequence (i.e. packets were lost),
+    /// instead of waiting for the next scheduled one. <br>
+    /// This lets the client (and any retransmission logic layered on top of the payloads) notice the loss sooner.
+    /// Gaps are counted in [`ServerStats::sequence_gaps`](crate::ServerStats::sequence_gaps) either way. The default is `false`.
+    pub fn ack_on_sequence_gap(mut self, ack_on_sequence_gap: bool) -> Self {
+        self.ack_on_sequence_gap = ack_on_sequence_gap;
+        self
+    }
+    /// Let a connected client keep its session when its packets start arriving from a new address,
+    /// e.g. when a phone switches from Wi-Fi to LTE, instead of timing out. <br>
+    /// A packet from an unknown address is tried against the keys of the connected clients. If it decrypts (and isn't a replay),
+    /// the server sends a path challenge with a random nonce to the new address, and only moves the session over once the client
+    /// echoes that nonce back from it. A packet captured and replayed from another address can't do that.
+    /// Payloads received before the move are dropped. <br>
+    /// Path challenges are not part of the netcode standard: clients have to use this crate to migrate.
+    /// Moves are counted in [`ServerStats::migrations`](crate::ServerStats::migrations). The default is `false`.
+    pub fn allow_migration(mut self, allow_migration: bool) -> Self {
+        self.allow_migration = allow_migration;
+        self
+    }
+    /// Set whether payloads are queued and sent together instead of one packet per [`Server::send`](Server::send). <br>
+    /// The payloads queued for a client are combined into as few packets as the [max payload size](ServerConfig::max_payload_size)
+    /// allows, each prefixed with its length (2 bytes), and sent from the next [`update`](Server::update) or [`flush`](Server::flush).
+    /// Many small sends per tick then cost one packet (and one encryption) instead of one each. <br>
+    /// Received payloads are split back into the payloads that were sent, so clients have to enable
+    /// [`ClientConfig::coalesce_payloads`](crate::ClientConfig::coalesce_payloads) as well. The default is `false`.
+    pub fn coalesce_payloads(mut self, coalesce_payloads: bool) -> Self {
+        self.coalesce_payloads = coalesce_payloads;
+        self
+    }
+    /// Spread the packets sent in each tick evenly over `tick_interval` seconds (e.g. `1.0 / 60.0`), instead of sending them
+    /// in one burst from [`update`](Server::update) and [`send`](Server::send) that can overflow the shallow buffers of consumer routers. <br>
+    /// Packets are queued and sent by [`Server::pace`](Server::pace) (or [`Server::run_pacer`](Server::run_pacer)) between updates,
+    /// each after an equal share of the time left in the tick. Packets still queued when the next update starts are sent right away.
+    /// At most `max_packets_per_tick` are queued per tick, more are dropped with [`SendOutcome::DroppedOverBudget`]. <br>
+    /// The default is `None`, packets are sent right away.
+    pub fn pace_packets(mut self, tick_interval: f64, max_packets_per_tick: usize) -> Self {
+        self.pacing = Some((tick_interval, max_packets_per_tick.max(1)));
+        self
+    }
+    /// Compress payloads of at least `threshold` bytes with LZ4 before they are encrypted, e.g. for snapshot-heavy games. <br>
+    /// Each payload gets a 1 byte header that says whether it is compressed, payloads that don't get smaller are sent as they are.
+    /// If [payloads are coalesced](ServerConfig::coalesce_payloads), the combined payload is compressed. <br>
+    /// Received payloads are decompressed, so clients have to enable
+    /// [`ClientConfig::compress_payloads`](crate::ClientConfig::compress_payloads) as well. By default payloads are not compressed.
+    #[cfg(feature = "compression")]
+    pub fn compress_payloads(mut self, threshold: usize) -> Self {
+        self.compression_threshold = Some(threshold);
+        self
+    }
+    /// Set whether the server accepts clients that connect with [`Client::connect_insecure`](crate::Client::connect_insecure),
+    /// without a connect token from a matchmaker, for local development. <br>
+    /// Their tokens are encrypted with a well-known key instead of the server's private key, so anyone can connect as any client id.
+    /// Tokens encrypted with the private key are accepted as usual. Requires the `insecure` feature, the default is `false`.
+    #[cfg(feature = "insecure")]
+    pub fn allow_insecure(mut self, allow_insecure: bool) -> Self {
+        self.allow_insecure = allow_insecure;
+        self
+    }
+    /// Set whether payloads carry acks of the packets received from each client, so the server learns which of its payloads
+    /// a client received (see [`Server::acks`](Server::acks)), e.g. for reliability or delta compression layered on top. <br>
+    /// Each payload gets a 12 byte header with the latest sequence received from the client and which of the 32 before it were received.
+    /// Acks only travel on payloads (not keep-alives), so a client has to send payloads for the server to get acks, and vice versa. <br>
+    /// Clients have to enable [`ClientConfig::ack_payloads`](crate::ClientConfig::ack_payloads) as well. The default is `false`.
+    pub fn ack_payloads(mut self, ack_payloads: bool) -> Self {
+        self.ack_payloads = ack_payloads;
+        self
+    }
+    /// Enable tick sync: keep-alives to clients carry the server's [tick](Server::tick), which advances `tick_rate` times per second,
+    /// so clients can estimate it with [`Client::estimated_server_tick`](crate::Client::estimated_server_tick). <br>
+    /// Stamped keep-alives are sent at least once per second, also to clients that get payloads, and take 24 bytes of the
+    /// [heartbeat](Server::set_heartbeat_payload). Clients have to enable [`ClientConfig::tick_sync`](crate::ClientConfig::tick_sync)
+    /// with the same rate. It is ignored with [`strict_netcode_1_02`](ServerConfig::strict_netcode_1_02). Tick sync is disabled by default.
+    pub fn tick_sync(mut self, tick_rate: f64) -> Self {
+        self.tick_rate = Some(tick_rate);
+        self
+    }
+    /// Pad payloads to the smallest of the `buckets` sizes (in bytes) they fit in before they are encrypted, so the size of a packet
+    /// doesn't leak what it carries (e.g. that a player fired or came into view). Payloads larger than every bucket are padded
+    /// to the [max payload size](ServerConfig::max_payload_size). <br>
+    /// Each payload gets a 2 byte length header, the padding is stripped on receive. Padding costs bandwidth,
+    /// a few buckets such as `[128, 512, 1200]` keep it in check. Sizes are clamped to [`MAX_PACKET_SIZE`](crate::MAX_PACKET_SIZE). <br>
+    /// Clients have to enable [`ClientConfig::pad_payloads`](crate::ClientConfig::pad_payloads) as well. Payloads are not padded by default.
+    pub fn pad_payloads(mut self, buckets: &[usize]) -> Self {
+        self.padding_buckets = buckets
+            .iter()
+            .map(|&size| size.min(MAX_PACKET_SIZE))
+            .collect();
+        self.padding_buckets.sort_unstable();
+        self.padding_buckets.dedup();
+        self
+    }
+    /// Set the options of the socket the server creates in [`Server::with_config`](Server::with_config), e.g. DSCP marking or `SO_REUSEPORT`. <br>
+    /// They are ignored by custom transceivers. The default leaves every option at the OS default.
+    #[cfg(not(target_family = "wasm"))]
+    pub fn socket_options(mut self, socket_options: SocketOptions) -> Self {
+        self.socket_options = socket_options;
+        self
+    }
+    /// Set a hook that receives every raw packet the server sends and receives, for debugging. <br>
+    /// Received packets are logged before they are filtered or decrypted, so rejected packets are logged as well.
+    /// Use a [`PcapWriter`](crate::PcapWriter) to write them to a capture file.
+    pub fn packet_logger(mut self, logger: impl PacketLogger + Send + Sync + 'static) -> Self {
+        self.packet_logger = Some(Box::new(logger));
+        self
+    }
+    /// Record the datagrams the server receives (before they are decrypted), the time of each update and the randomness the server draws,
+    /// to reproduce a bug report later with [`Server::replay`](Server::replay). <br>
+    /// Only what reaches the server from the network is recorded: a replay has to repeat the calls the application made on the
+    /// server (e.g. [`send`](Server::send) or [`disconnect`](Server::disconnect)) to reproduce their effects. See [`Recorder`](Recorder).
+    /// Nothing is recorded by default.
+    pub fn record(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+    /// Listen for [admin commands](Server::admin_command) on a Unix domain socket at `path`, e.g. to kick a client with
+    /// `echo "kick 3" | socat - UNIX-CONNECT:/run/game/admin.sock`. <br>
+    /// Commands are read and answered from [`update`](Server::update). Anyone who can write to the socket can run them,
+    /// so restrict access with the permissions of its directory. A socket file left behind by a server that crashed is replaced,
+    /// creating the server fails if another server still listens on it. The socket file is removed when the server is dropped. <br>
+    /// There is no admin socket by default.
+    #[cfg(unix)]
+    pub fn admin_socket(mut self, path: impl Into<PathBuf>) -> Self {
+        self.admin_socket = Some(path.into());
+        self
+    }
+    /// Set the wall clock the server checks connect token expiry against, e.g. a [`ManualClock`](crate::ManualClock)
+    /// to test or replay token expiry without waiting for it. <br>
+    /// Tokens from [`Server::token`](Server::token) are created at its time, and the blocking [`Server::run_pacer`](Server::run_pacer)
+    /// and [`Server::shutdown`](Server::shutdown) measure time with it. <br>
+    /// The default is the [`SystemClock`](SystemClock).
+    pub fn clock(mut self, clock: impl Clock + Send + Sync + 'static) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+    /// Set the RNG the server generates its challenge keys with, e.g. a `testing::SeededRng` (with the `testing` feature)
+    /// so the challenge tokens sent to clients are the same on every run of a test or fuzz case. <br>
+    /// The default is the operating system's RNG, which production servers should keep.
+    pub fn rng(mut self, rng: impl CryptoRng + RngCore + Send + Sync + 'static) -> Self {
+        self.rng = Box::new(rng);
+        self
+    }
+    /// Set where the server remembers the connect tokens that were already used, e.g. a store shared by every server
+    /// process behind the same public address, see [`TokenReplayStore`](TokenReplayStore). <br>
+    /// The default is in memory, and is lost when the server restarts.
+    pub fn token_replay_store(
+        mut self,
+        store: impl TokenReplayStore + Send + Sync + 'static,
+    ) -> Self {
+        self.token_replay_store = Box::new(store);
+        self
+    }
+    /// Provide a callback that decides whether a client may connect, e.g. by looking it up in a ban list or an entitlement service. <br>
+    /// The callback will be called with the client id, the client's address, the user data from its connect token and the context,
+    /// once the token was decrypted and checked, and before the client is sent a challenge or given a slot.
+    /// It is called again for each connection request the client resends until it is answered, so cache slow lookups in the context. <br>
+    /// The default is to accept every client with a valid connect token.
+    ///
+    /// # Example
+    /// ```
+    /// use std::collections::HashSet;
+    /// use netcode::{ConnectDecision, ServerConfig};
+    ///
+    /// let banned: HashSet<u64> = [13, 666].into();
+    /// let cfg = ServerConfig::with_context(banned).connect_filter(|client_id, addr, _user_data, banned| {
+    ///     if banned.contains(&client_id) {
+    ///         ConnectDecision::Deny(format!("client id {client_id} from {addr} is banned"))
+    ///     } else {
+    ///         ConnectDecision::Accept
+    ///     }
+    /// });
+    /// ```
+    pub fn connect_filter<F>(mut self, cb: F) -> Self
+    where
+        F: FnMut(ClientId, SocketAddr, &[u8; USER_DATA_BYTES], &mut Ctx) -> ConnectDecision
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.connect_filter = Some(Box::new(cb));
+        self
+    }
     /// Provide a callback that will be called when a client is connected to the server. <br>
     /// The callback will be called with the client index and the context that was provided (provide a `None` context if you don't need one).
     ///
@@ -297,6 +1118,122 @@ impl<Ctx> ServerConfig<Ctx> {
         self.on_disconnect = Some(Box::new(cb));
         self
     }
+    /// Provide a callback that makes the custom data the server attaches to a challenge token, which comes back with the client's challenge response. <br>
+    /// The callback will be called with the client id, the user data from its connect token and the context, whenever a challenge is sent.
+    /// The data is encrypted with the server's challenge key, so the client can't read or change it,
+    /// and is passed to the [`on_connect_with_data`](ServerConfig::on_connect_with_data) callback once the client is connected.
+    /// This carries state across the handshake (e.g. a shard id or a queue position) without the server keeping it per pending client. <br>
+    /// The default is all zeros.
+    ///
+    /// # Example
+    /// ```
+    /// use netcode::{ServerConfig, CHALLENGE_DATA_BYTES};
+    ///
+    /// let cfg = ServerConfig::with_context(0u32)
+    ///     .challenge_data(|_client_id, _user_data, queue_len| {
+    ///         *queue_len += 1;
+    ///         let mut data = [0; CHALLENGE_DATA_BYTES];
+    ///         data[..4].copy_from_slice(&queue_len.to_le_bytes());
+    ///         data
+    ///     })
+    ///     .on_connect_with_data(|idx, data, _| {
+    ///         let queue_position = u32::from_le_bytes(data[..4].try_into().unwrap());
+    ///         println!("client {idx} connected, it was number {queue_position} in the queue");
+    ///     });
+    /// ```
+    pub fn challenge_data<F>(mut self, cb: F) -> Self
+    where
+        F: FnMut(ClientId, &[u8; USER_DATA_BYTES], &mut Ctx) -> [u8; CHALLENGE_DATA_BYTES]
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.challenge_data = Some(Box::new(cb));
+        self
+    }
+    /// Provide a callback that will be called when a client is connected to the server, right after [`on_connect`](ServerConfig::on_connect). <br>
+    /// The callback will be called with the client index, the custom data of the client's challenge token
+    /// (see [`challenge_data`](ServerConfig::challenge_data)) and the context.
+    pub fn on_connect_with_data<F>(mut self, cb: F) -> Self
+    where
+        F: FnMut(ClientIndex, &[u8; CHALLENGE_DATA_BYTES], &mut Ctx) + Send + Sync + 'static,
+    {
+        self.on_connect_with_data = Some(Box::new(cb));
+        self
+    }
+    /// Provide a callback that will be called with the heartbeat of every keep-alive packet from a connected client that carries one,
+    /// see [`Client::set_heartbeat_payload`](crate::Client::set_heartbeat_payload). <br>
+    /// The callback will be called with the client index, the heartbeat and the context. Keep-alives without a heartbeat don't call it.
+    pub fn on_heartbeat<F>(mut self, cb: F) -> Self
+    where
+        F: FnMut(ClientIndex, &[u8], &mut Ctx) + Send + Sync + 'static,
+    {
+        self.on_heartbeat = Some(Box::new(cb));
+        self
+    }
+    /// Check that the options fit together, e.g. that the timeout is longer than the keep-alive interval
+    /// and that payloads still fit in the max payload size after the enabled headers. <br>
+    /// The server checks its configuration when it is created, call this to check one earlier.
+    ///
+    /// # Example
+    /// ```
+    /// use netcode::{ConfigError, Error, ServerConfig};
+    ///
+    /// let cfg = ServerConfig::default().keep_alive_interval(10.0).timeout_seconds(5);
+    /// assert!(matches!(
+    ///     cfg.validate(),
+    ///     Err(Error::InvalidConfig(ConfigError::TimeoutTooShort { .. }))
+    /// ));
+    /// ```
+    pub fn validate(&self) -> Result<()> {
+        config::check_positive("keep_alive_send_rate", self.keep_alive_send_rate)?;
+        for (option, value) in [
+            ("keep_alive_interval", self.keep_alive_interval),
+            ("pending_timeout", self.pending_timeout),
+            ("rekey_interval", self.rekey_interval),
+            ("tick_rate", self.tick_rate),
+            (
+                "pace_packets",
+                self.pacing.map(|(tick_interval, _)| tick_interval),
+            ),
+        ] {
+            if let Some(value) = value {
+                config::check_positive(option, value)?;
+            }
+        }
+        match self.keep_alive_interval {
+            Some(interval) => {
+                config::check_timeout(self.timeout_seconds, "keep_alive_interval", interval)?
+            }
+            None => config::check_timeout(
+                self.timeout_seconds,
+                "keep_alive_send_rate",
+                self.keep_alive_send_rate,
+            )?,
+        }
+        let mut overhead = 0;
+        if self.ack_payloads {
+            overhead += ack::HEADER_SIZE;
+        }
+        if !self.padding_buckets.is_empty() {
+            overhead += padding::HEADER_SIZE;
+        }
+        #[cfg(feature = "compression")]
+        if self.compression_threshold.is_some() {
+            overhead += compression::HEADER_SIZE;
+        }
+        if self.coalesce_payloads {
+            overhead += MESSAGE_HEADER_SIZE;
+        }
+        config::check_payload_size(self.max_payload_size, overhead, &self.padding_buckets)?;
+        if self.max_pending_connections == 0 {
+            return Err(config::ConfigError::NoPendingConnections.into());
+        }
+        if self.max_clients > MAX_CLIENTS_LIMIT {
+            return Err(Error::MaxClients(self.max_clients));
+        }
+        Ok(())
+    }
 }
 
 /// The `netcode` server.
@@ -332,44 +1269,68 @@ impl<Ctx> ServerConfig<Ctx> {
 pub struct Server<T: Transceiver, Ctx = ()> {
     transceiver: T,
     time: f64,
-    private_key: Key,
+    private_key: Zeroizing<Key>,
+    // Kept after a rotation to accept the tokens issued before it.
+    previous_private_key: Option<Zeroizing<Key>>,
     sequence: u64,
     token_sequence: u64,
     challenge_sequence: u64,
-    challenge_key: Key,
+    challenge_key: Zeroizing<Key>,
+    // Kept after a rotation to decrypt the responses to challenges sent just before it.
+    previous_challenge_key: Option<Zeroizing<Key>>,
     protocol_id: u64,
+    max_clients: usize,
+    stats: ServerStats,
+    protocol_stats: HashMap<u64, ProtocolStats>,
     conn_cache: ConnectionCache,
-    token_entries: TokenEntries,
+    shutdown: Option<Shutdown>,
+    migrations: HashMap<SocketAddr, PendingMigration>,
+    migration_probes: usize,
+    // slots held for clients about to connect, and the server time the reservations expire at
+    reservations: HashMap<ClientId, f64>,
+    query_limit: RateLimit,
+    // packets the transceiver would block on, sent again before any new ones
+    send_backlog: SendBacklog,
+    // packets waiting to be sent later in the tick, see `ServerConfig::pace_packets`
+    pacer: Option<Pacer>,
+    // client ids denied from connecting, see `Server::ban`
+    banned: HashSet<ClientId>,
+    // whether the packet logger runs, see `Server::set_packet_logging`
+    packet_logging: bool,
+    #[cfg(unix)]
+    admin: Option<AdminSocket>,
+    // the relay each relayed client was last heard through, and when
+    relay_routes: HashMap<SocketAddr, (SocketAddr, f64)>,
+    // the tick set with `set_tick` and the server time it was set at, see `ServerConfig::tick_sync`
+    tick_anchor: (u64, f64),
+    // the cluster this server is a shard of
+    cluster: Option<Shard>,
+    // created by the first call to `handle`, the receiver is in a mutex to keep the server `Sync`
+    commands: Option<(ServerHandle, Mutex<Receiver<Command>>, ClientIds)>,
     cfg: ServerConfig<Ctx>,
 }
 
+#[cfg(not(target_family = "wasm"))]
 impl Server<NetcodeSocket> {
     /// Create a new server with a default configuration.
     ///
     /// For a custom configuration, use [`Server::with_config`](Server::with_config) instead.
     pub fn new(bind_addr: impl ToSocketAddrs, protocol_id: u64, private_key: Key) -> Result<Self> {
-        let server: Server<_, ()> = Server {
-            transceiver: NetcodeSocket::new(bind_addr, SEND_BUF_SIZE, RECV_BUF_SIZE)?,
-            time: 0.0,
-            private_key,
+        let socket = NetcodeSocket::new(bind_addr, SEND_BUF_SIZE, RECV_BUF_SIZE)?;
+        Server::with_config_and_transceiver(
             protocol_id,
-            sequence: 1 << 63,
-            token_sequence: 0,
-            challenge_sequence: 0,
-            challenge_key: crypto::try_generate_key()?,
-            conn_cache: ConnectionCache::new(0.0),
-            token_entries: TokenEntries::new(),
-            cfg: ServerConfig::default(),
-        };
-        log::info!("server started on {}", server.transceiver.addr());
-        Ok(server)
+            private_key,
+            ServerConfig::default(),
+            socket,
+        )
     }
 }
 
+#[cfg(not(target_family = "wasm"))]
 impl<Ctx> Server<NetcodeSocket, Ctx> {
     /// Create a new server with a custom configuration. <br>
     /// Callbacks with context can be registered with the server to be notified when the server changes states. <br>
-    /// See [`ServerConfig`](ServerConfig) for more details.
+    /// See [`ServerConfig`](ServerConfig) for more details, an invalid configuration returns the error of [`ServerConfig::validate`](ServerConfig::validate).
     ///
     /// # Example
     /// ```
@@ -390,41 +1351,251 @@ impl<Ctx> Server<NetcodeSocket, Ctx> {
         private_key: Key,
         cfg: ServerConfig<Ctx>,
     ) -> Result<Self> {
-        let server = Server {
-            transceiver: NetcodeSocket::new(bind_addr, SEND_BUF_SIZE, RECV_BUF_SIZE)?,
-            time: 0.0,
-            private_key,
-            protocol_id,
-            sequence: 1 << 63,
-            token_sequence: 0,
-            challenge_sequence: 0,
-            challenge_key: crypto::try_generate_key()?,
-            conn_cache: ConnectionCache::new(0.0),
-            token_entries: TokenEntries::new(),
+        let socket = NetcodeSocket::with_options(
+            bind_addr,
+            SEND_BUF_SIZE,
+            RECV_BUF_SIZE,
+            &cfg.socket_options,
+        )?;
+        Server::with_config_and_transceiver(protocol_id, private_key, cfg, socket)
+    }
+    /// Create a shard of a [`ServerCluster`](ServerCluster), bound to the cluster's address with `SO_REUSEPORT`. <br>
+    /// The shard uses the cluster's protocol id and private key, and denies new clients once the cluster is full.
+    /// See [`ServerCluster`](ServerCluster) for more details.
+    ///
+    /// # Panics
+    /// Panics if `shard` is out of range.
+    pub fn with_cluster(
+        cluster: &ServerCluster,
+        shard: usize,
+        cfg: ServerConfig<Ctx>,
+    ) -> Result<Self> {
+        assert!(
+            shard < cluster.num_shards(),
+            "shard {shard} is out of range for a cluster of {} shards",
+            cluster.num_shards()
+        );
+        let options = cfg.socket_options.clone().reuse_port(true);
+        let socket = cluster.bind(|addr| {
+            Ok(NetcodeSocket::with_options(
+                addr,
+                SEND_BUF_SIZE,
+                RECV_BUF_SIZE,
+                &options,
+            )?)
+        })?;
+        let mut server = Server::with_config_and_transceiver(
+            cluster.protocol_id(),
+            cluster.private_key(),
             cfg,
-        };
-        log::info!("server started on {}", server.addr());
+            socket,
+        )?;
+        server.cluster = Some(Shard::new(cluster.clone(), shard));
         Ok(server)
     }
 }
 
-impl<T: Transceiver, S> Server<T, S> {
-    const ALLOWED_PACKETS: u8 = 1 << Packet::REQUEST
-        | 1 << Packet::RESPONSE
-        | 1 << Packet::KEEP_ALIVE
-        | 1 << Packet::PAYLOAD
-        | 1 << Packet::DISCONNECT;
-    fn on_connect(&mut self, client_idx: ClientIndex) {
-        if let Some(cb) = self.cfg.on_connect.as_mut() {
-            cb(client_idx, &mut self.cfg.context)
-        }
+impl Server<ReplayTransceiver> {
+    /// Create a server that replays a [`Recording`](Recording), with a default configuration.
+    ///
+    /// For the configuration the server was recorded with, use [`Server::replay_with_config`](Server::replay_with_config) instead.
+    pub fn replay(recording: Recording, private_key: Key) -> Result<Self> {
+        Server::replay_with_config(recording, private_key, ServerConfig::default())
     }
-    fn on_disconnect(&mut self, client_idx: ClientIndex) {
-        if let Some(cb) = self.cfg.on_disconnect.as_mut() {
-            cb(client_idx, &mut self.cfg.context)
-        }
+}
+
+impl<Ctx> Server<ReplayTransceiver, Ctx> {
+    /// Create a server that replays a [`Recording`](Recording) of a server, see [`ServerConfig::record`](ServerConfig::record).
+    ///
+    /// The server gets the recorded protocol id and address, and the same `private_key` and `cfg` as the recorded server
+    /// should be passed, so it makes the same decisions. Its wall clock and RNG are replaced by the recorded ones.
+    /// Drive the replay with [`replay_update`](Server::replay_update) instead of [`update`](Server::update),
+    /// the packets it sends are dropped (but still passed to a [`packet_logger`](ServerConfig::packet_logger)).
+    ///
+    /// # Example
+    /// ```no_run
+    /// use netcode::{Recording, Server};
+    ///
+    /// # let private_key = [42u8; 32];
+    /// let recording = Recording::open("server.rec").unwrap();
+    /// let mut server = Server::replay(recording, private_key).unwrap();
+    /// while let Some(time) = server.replay_update().unwrap() {
+    ///     while let Some((payload, client_idx)) = server.recv() {
+    ///         // feed the payloads to the game logic, like the recorded server did
+    ///     }
+    ///     if time > 43.0 * 60.0 {
+    ///         println!("{} clients connected", server.num_connected_clients());
+    ///         break;
+    ///     }
+    /// }
+    /// ```
+    pub fn replay_with_config(
+        recording: Recording,
+        private_key: Key,
+        cfg: ServerConfig<Ctx>,
+    ) -> Result<Self> {
+        let protocol_id = recording.protocol_id();
+        let (trx, clock, rng) = ReplayTransceiver::new(recording);
+        let cfg = cfg.clock(clock).rng(rng);
+        Server::with_config_and_transceiver(protocol_id, private_key, cfg, trx)
     }
-    fn touch_client(&mut self, client_idx: Option<ClientIndex>) -> Result<()> {
+    /// Runs the next recorded update: the server receives the datagrams recorded in it and is [updated](Server::try_update)
+    /// with the recorded time, which is returned. Returns `None` once the whole recording was replayed.
+    pub fn replay_update(&mut self) -> Result<Option<f64>> {
+        let Some(time) = self.transceiver.next_update() else {
+            return Ok(None);
+        };
+        self.try_update(time)?;
+        Ok(Some(time))
+    }
+}
+
+impl<T: Transceiver, S> Server<T, S> {
+    // Whether there is no slot left for `client_id`, the slots reserved for other clients are taken.
+    fn is_full(&self, client_id: ClientId) -> bool {
+        self.sync_cluster();
+        let reserved = self.num_reserved_slots()
+            - usize::from(
+                self.reservations
+                    .get(&client_id)
+                    .is_some_and(|&expires| expires > self.time),
+            );
+        self.cluster.as_ref().is_some_and(Shard::is_cluster_full)
+            || self.num_connected_clients() + reserved >= self.max_clients
+    }
+    // Whether a client connecting with `client_id` takes over the slot of a connected client, see `DuplicateClientId::KickExisting`.
+    fn replaces_client(&self, client_id: ClientId) -> bool {
+        self.cfg.duplicate_client_id == DuplicateClientId::KickExisting
+            && self.conn_cache.find_connected_by_id(client_id).is_some()
+    }
+    fn num_reserved_slots(&self) -> usize {
+        self.reservations
+            .values()
+            .filter(|&&expires| expires > self.time)
+            .count()
+    }
+    fn run_commands(&mut self) -> Result<()> {
+        let Some((_, rx, _)) = self.commands.as_mut() else {
+            return Ok(());
+        };
+        let rx = rx.get_mut().unwrap_or_else(PoisonError::into_inner);
+        let commands: Vec<_> = rx.try_iter().collect();
+        for command in commands {
+            let (Command::Send(_, client_idx, client_id)
+            | Command::Disconnect(client_idx, client_id)) = command;
+            let still_connected = self
+                .conn_cache
+                .clients
+                .get(client_idx.0)
+                .is_some_and(|conn| conn.is_connected() && conn.client_id == client_id);
+            if !still_connected {
+                log::debug!(
+                    "server dropped a queued call for client {client_idx}, client {client_id} left"
+                );
+                continue;
+            }
+            let result = match command {
+                Command::Send(buf, client_idx, _) => self.send(&buf, client_idx).map(drop),
+                Command::Disconnect(client_idx, _) => self.disconnect(client_idx),
+            };
+            match result {
+                Err(
+                    err @ (Error::ClientNotFound
+                    | Error::ClientNotConnected
+                    | Error::SizeMismatch(..)),
+                ) => log::debug!("server dropped a queued call: {err}"),
+                result => result?,
+            }
+        }
+        Ok(())
+    }
+    // Publishes the ids of the connected clients to the server's handles, see `ServerHandle`.
+    fn sync_handle(&self) {
+        if let Some((_, _, ids)) = &self.commands {
+            ids.set(
+                self.conn_cache
+                    .clients
+                    .iter()
+                    .filter(|(_, conn)| conn.is_connected())
+                    .map(|(idx, conn)| (ClientIndex(idx), conn.client_id)),
+            );
+        }
+    }
+    fn sync_cluster(&self) {
+        if let Some(shard) = &self.cluster {
+            shard.set_connected_clients(self.num_connected_clients());
+        }
+    }
+    fn on_connect(&mut self, client_idx: ClientIndex, challenge_data: &[u8; CHALLENGE_DATA_BYTES]) {
+        if let Some(cb) = self.cfg.on_connect.as_mut() {
+            cb(client_idx, &mut self.cfg.context)
+        }
+        if let Some(cb) = self.cfg.on_connect_with_data.as_mut() {
+            cb(client_idx, challenge_data, &mut self.cfg.context)
+        }
+    }
+    fn on_disconnect(&mut self, client_idx: ClientIndex) {
+        if let Some(addr) = self
+            .conn_cache
+            .clients
+            .get(client_idx.0)
+            .map(|conn| conn.addr)
+        {
+            if let Err(e) = self
+                .transceiver
+                .close_connection(addr)
+                .map_err(Into::<Error>::into)
+            {
+                log::debug!("server failed to close the connection to {addr}: {e}");
+            }
+        }
+        if let Some(cb) = self.cfg.on_disconnect.as_mut() {
+            cb(client_idx, &mut self.cfg.context)
+        }
+    }
+    fn on_heartbeat(&mut self, client_idx: Option<ClientIndex>, heartbeat: &[u8]) {
+        let Some(idx) = client_idx else {
+            return;
+        };
+        let is_connected = self
+            .conn_cache
+            .clients
+            .get(idx.0)
+            .is_some_and(|conn| conn.is_connected());
+        if heartbeat.is_empty() || !is_connected {
+            return;
+        }
+        if let Some(cb) = self.cfg.on_heartbeat.as_mut() {
+            cb(idx, heartbeat, &mut self.cfg.context)
+        }
+    }
+    // Takes the stamp in front of a client's heartbeat if tick sync is enabled, returns the heartbeat after it.
+    fn receive_tick_stamp<'a>(
+        &mut self,
+        client_idx: Option<ClientIndex>,
+        heartbeat: &'a [u8],
+    ) -> &'a [u8] {
+        if !self.is_tick_sync() {
+            return heartbeat;
+        }
+        let Some((client_time, heartbeat)) = tick_sync::read_client_stamp(heartbeat) else {
+            return heartbeat;
+        };
+        if let Some(conn) = client_idx.and_then(|idx| self.conn_cache.clients.get_mut(idx.0)) {
+            conn.client_stamp = client_time;
+            conn.client_stamp_time = self.time;
+        }
+        heartbeat
+    }
+    fn is_tick_sync(&self) -> bool {
+        self.cfg.tick_rate.is_some() && !self.cfg.strict_netcode_1_02
+    }
+    // The server's tick with the fraction of the tick that passed.
+    fn precise_tick(&self) -> f64 {
+        let (tick, time) = self.tick_anchor;
+        tick as f64 + (self.time - time) * self.cfg.tick_rate.unwrap_or_default()
+    }
+    fn touch_client(&mut self, client_idx: Option<ClientIndex>) -> Result<()> {
         let Some(idx) = client_idx else {
             return Ok(());
         };
@@ -438,11 +1609,15 @@ impl<T: Transceiver, S> Server<T, S> {
         }
         Ok(())
     }
+    // Whether a connect token listing `addr` was issued for this server, see `ServerConfig::public_addrs`.
+    fn is_own_addr(&self, addr: SocketAddr) -> bool {
+        addr == self.transceiver.addr() || self.cfg.public_addrs.contains(&addr)
+    }
     fn process_packet(&mut self, addr: SocketAddr, packet: Packet) -> Result<()> {
         let client_idx = self.conn_cache.find_by_addr(&addr).map(|(idx, _)| idx);
         log::trace!(
             "server received {} from {}",
-            packet.to_string(),
+            packet,
             client_idx
                 .map(|idx| format!("client {idx}"))
                 .unwrap_or_else(|| addr.to_string())
@@ -450,66 +1625,332 @@ impl<T: Transceiver, S> Server<T, S> {
         match packet {
             Packet::Request(packet) => self.process_connection_request(addr, packet),
             Packet::Response(packet) => self.process_connection_response(addr, packet),
-            Packet::KeepAlive(_) => self.touch_client(client_idx),
-            Packet::Payload(packet) => {
+            Packet::KeepAlive(packet) => {
                 self.touch_client(client_idx)?;
-                if let Some(idx) = client_idx {
-                    self.conn_cache
-                        .packet_queue
-                        .push_back((packet.buf.to_vec(), idx));
-                }
+                let heartbeat = self.receive_tick_stamp(client_idx, packet.heartbeat);
+                self.on_heartbeat(client_idx, heartbeat);
                 Ok(())
             }
+            Packet::Payload(packet) => {
+                self.touch_client(client_idx)?;
+                let Some(idx) = client_idx else {
+                    return Ok(());
+                };
+                self.stats.payload_received(packet.buf.len());
+                self.recv_payload_packet(packet.buf, idx)
+            }
             Packet::Disconnect(_) => {
                 if let Some(idx) = client_idx {
                     log::debug!("server disconnected client {idx}");
+                    trace::event!(INFO, client_index = idx.0, %addr, "client disconnected");
                     self.on_disconnect(idx);
                     self.conn_cache.remove(idx);
                 }
                 Ok(())
             }
-            _ => unreachable!("packet should have been filtered out by `ALLOWED_PACKETS`"),
+            _ => unreachable!("packet should have been filtered out by the allow-list"),
+        }
+    }
+    fn recv_payload_packet(&mut self, buf: &[u8], idx: ClientIndex) -> Result<()> {
+        let buf = if self.cfg.padding_buckets.is_empty() {
+            buf
+        } else {
+            let Some(buf) = padding::unpad(buf) else {
+                log::debug!(
+                    "server dropped payload with an invalid padding header from client {idx}"
+                );
+                return Ok(());
+            };
+            buf
+        };
+        let buf = if self.cfg.ack_payloads {
+            let acks = self.conn_cache.acks.entry(idx).or_default();
+            let Some(buf) = acks.recv(buf, self.time) else {
+                log::debug!("server dropped payload without an ack header from client {idx}");
+                return Ok(());
+            };
+            buf
+        } else {
+            buf
+        };
+        #[cfg(feature = "compression")]
+        if self.cfg.compression_threshold.is_some() {
+            let Some(payload) = compression::decode(buf) else {
+                log::debug!("server dropped payload from client {idx} that failed to decompress");
+                return Ok(());
+            };
+            return self.recv_messages(&payload, idx);
+        }
+        self.recv_messages(buf, idx)
+    }
+    fn recv_messages(&mut self, buf: &[u8], idx: ClientIndex) -> Result<()> {
+        if !self.cfg.coalesce_payloads {
+            return self.recv_payload(buf, idx);
+        }
+        for msg in coalesce::messages(buf) {
+            self.recv_payload(msg, idx)?;
+        }
+        Ok(())
+    }
+    fn recv_payload(&mut self, buf: &[u8], idx: ClientIndex) -> Result<()> {
+        let is_connected = self.conn_cache.clients[idx.0].is_connected();
+        let max_payload_size = self.max_message_size();
+        match self.cfg.echo_mode.echo(buf, self.time, max_payload_size) {
+            Some(echoed) if is_connected && echoed.len() <= max_payload_size => {
+                self.send(&echoed, idx)?;
+            }
+            Some(_) => {}
+            None => {
+                if !self.conn_cache.packet_queue.push(idx.0, buf) {
+                    self.stats.recv_queue_overflows += 1;
+                }
+            }
         }
+        Ok(())
     }
-    fn send_to_addr(&mut self, packet: Packet, addr: SocketAddr, key: Key) -> Result<()> {
+    fn send_to_addr(
+        &mut self,
+        packet: Packet,
+        addr: SocketAddr,
+        key: &Key,
+        protocol_id: u64,
+    ) -> Result<()> {
         let mut buf = [0u8; MAX_PKT_BUF_SIZE];
-        let size = packet.write(&mut buf, self.sequence, &key, self.protocol_id)?;
-        self.transceiver
-            .send(&buf[..size], addr)
-            .map_err(|e| e.into())?;
+        let size = packet.write(&mut buf, self.sequence, key, protocol_id)?;
+        self.send_datagram(&buf[..size], addr)?;
+        metrics::packet_sent(Side::Server, packet.kind(), size);
+        self.log_packet(PacketDirection::Sent, addr, &buf[..size], None);
         self.sequence += 1;
         Ok(())
     }
-    fn send_to_client(&mut self, packet: Packet, idx: ClientIndex) -> Result<()> {
+    // Denies a pending connection with the key of its connect token.
+    fn send_denied_to_pending(&mut self, idx: ClientIndex, addr: SocketAddr) -> Result<()> {
+        let conn = &self.conn_cache.clients[idx.0];
+        let (send_key, protocol_id) = (conn.send_key.clone(), conn.protocol_id);
+        self.send_to_addr(DeniedPacket::create(), addr, &send_key, protocol_id)
+    }
+    fn answer_query(&mut self, buf: &[u8], addr: SocketAddr) -> Result<()> {
+        let Some(cfg) = self.cfg.queries.as_ref() else {
+            return Ok(());
+        };
+        let Some((protocol_id, nonce)) = query::read_request(buf)
+            .filter(|(protocol_id, _)| self.protocol_stats.contains_key(protocol_id))
+        else {
+            log::debug!("server ignored invalid query from {addr}");
+            return Ok(());
+        };
+        if !self.query_limit.allow(self.time, cfg.rate_limit) {
+            log::trace!("server ignored query from {addr}: rate limited");
+            self.stats.queries_rate_limited += 1;
+            return Ok(());
+        }
+        let response = query::write_response(
+            cfg,
+            (protocol_id, nonce),
+            self.num_connected_clients(),
+            self.max_clients,
+            &self.private_key,
+        )?;
+        self.send_datagram(&response, addr)?;
+        self.stats.queries_answered += 1;
+        Ok(())
+    }
+    // Moves a client's sequence to the next epoch when the rekey interval passed, before a session packet takes it.
+    fn rekey_client(&mut self, idx: ClientIndex) {
+        if !self.cfg.rekey_sessions {
+            return;
+        }
+        let conn = &mut self.conn_cache.clients[idx.0];
+        let (sequence, rekeyed) =
+            conn.rekey
+                .next_sequence(conn.sequence, self.time, self.cfg.rekey_interval);
+        conn.sequence = sequence;
+        if rekeyed {
+            log::debug!("server rekeyed the session of client {idx} at sequence {sequence}");
+            self.stats.rekeys += 1;
+        }
+    }
+    fn send_to_client(&mut self, packet: Packet, idx: ClientIndex) -> Result<SendOutcome> {
+        let addr = self.conn_cache.clients[idx.0].addr;
+        self.send_to_client_at(packet, idx, addr)
+    }
+    // Sends a packet of a client's session to `addr`, which is not the client's address while it is migrating.
+    fn send_to_client_at(
+        &mut self,
+        packet: Packet,
+        idx: ClientIndex,
+        addr: SocketAddr,
+    ) -> Result<SendOutcome> {
         let mut buf = [0u8; MAX_PKT_BUF_SIZE];
+        let size = self.write_client_packet(&packet, idx, &mut buf)?;
+        let outcome = self.send_datagram(&buf[..size], addr)?;
+        metrics::packet_sent(Side::Server, packet.kind(), size);
         let conn = &mut self.conn_cache.clients[idx.0];
-        let size = packet.write(&mut buf, conn.sequence, &conn.send_key, self.protocol_id)?;
-        self.transceiver
-            .send(&buf[..size], conn.addr)
-            .map_err(|e| e.into())?;
         conn.last_access_time = self.time;
         conn.last_send_time = self.time;
         conn.sequence += 1;
+        let payload = match &packet {
+            Packet::Payload(PayloadPacket { buf }) => Some(*buf),
+            _ => None,
+        };
+        self.log_packet(PacketDirection::Sent, addr, &buf[..size], payload);
+        Ok(outcome)
+    }
+    // Encrypts a packet of a client's session with its current sequence, which the caller moves on once it is sent.
+    fn write_client_packet(
+        &mut self,
+        packet: &Packet,
+        idx: ClientIndex,
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        if packet.kind() >= Packet::KEEP_ALIVE {
+            self.rekey_client(idx);
+        }
+        let conn = &self.conn_cache.clients[idx.0];
+        let (sequence, protocol_id) = (conn.sequence, conn.protocol_id);
+        let cipher = &mut self.conn_cache.cipher(idx).send;
+        packet.write_with(buf, sequence, cipher, protocol_id)
+    }
+    // Sends the redundant disconnect packets of a client, those of the same size as one batch of segments,
+    // see `Transceiver::send_segments`.
+    fn send_disconnect_packets(&mut self, idx: ClientIndex) -> Result<()> {
+        let addr = self.conn_cache.clients[idx.0].addr;
+        if self.cfg.relay.is_some() && self.relay_routes.contains_key(&addr) {
+            // each relayed packet is wrapped on its own
+            for _ in 0..self.cfg.num_disconnect_packets {
+                self.send_to_client(DisconnectPacket::create(), idx)?;
+            }
+            return Ok(());
+        }
+        let packet = DisconnectPacket::create();
+        let mut batch = Vec::new();
+        let mut segment_size = 0;
+        for _ in 0..self.cfg.num_disconnect_packets {
+            let mut buf = [0u8; MAX_PKT_BUF_SIZE];
+            let size = self.write_client_packet(&packet, idx, &mut buf)?;
+            // the sequence is one byte longer every 256 times over, which starts a new batch
+            if size != segment_size && !batch.is_empty() {
+                self.send_batch(&packet, &batch, segment_size, addr)?;
+                batch.clear();
+            }
+            segment_size = size;
+            batch.extend_from_slice(&buf[..size]);
+            let conn = &mut self.conn_cache.clients[idx.0];
+            conn.last_access_time = self.time;
+            conn.last_send_time = self.time;
+            conn.sequence += 1;
+        }
+        if !batch.is_empty() {
+            self.send_batch(&packet, &batch, segment_size, addr)?;
+        }
+        Ok(())
+    }
+    // Sends packets of the same kind and size to `addr` at once, see `Transceiver::send_segments`.
+    fn send_batch(
+        &mut self,
+        packet: &Packet,
+        batch: &[u8],
+        segment_size: usize,
+        addr: SocketAddr,
+    ) -> Result<()> {
+        if let Err(e) = self.transceiver.send_segments(batch, segment_size, addr) {
+            let e: Error = e.into();
+            // the packets are redundant, those that don't fit in the send buffer are lost like any other
+            if !e.is_would_block() {
+                return Err(e);
+            }
+        }
+        for raw in batch.chunks(segment_size) {
+            metrics::packet_sent(Side::Server, packet.kind(), raw.len());
+            self.log_packet(PacketDirection::Sent, addr, raw, None);
+        }
         Ok(())
     }
+    // Sends a datagram to `addr`, through the relay the client at `addr` was last heard through, if any.
+    fn send_datagram(&mut self, buf: &[u8], addr: SocketAddr) -> Result<SendOutcome> {
+        let route = self
+            .relay_routes
+            .get(&addr)
+            .map(|(relay_addr, _)| *relay_addr);
+        match (self.cfg.relay.as_ref(), route) {
+            (Some(relay), Some(relay_addr)) => {
+                let datagram = relay::wrap_for_client(&relay.key, addr, buf)?;
+                self.transmit(&datagram, relay_addr)
+            }
+            _ => self.transmit(buf, addr),
+        }
+    }
+    // Sends a datagram right away, or queues it if packets are paced.
+    fn transmit(&mut self, buf: &[u8], addr: SocketAddr) -> Result<SendOutcome> {
+        let Some(pacer) = self.pacer.as_mut() else {
+            return self.send_backlog.send(&self.transceiver, buf, addr);
+        };
+        if !pacer.push(buf, addr) {
+            self.stats.pacing_drops += 1;
+            return Ok(SendOutcome::DroppedOverBudget);
+        }
+        Ok(SendOutcome::Paced)
+    }
+    fn log_packet(
+        &mut self,
+        direction: PacketDirection,
+        addr: SocketAddr,
+        raw: &[u8],
+        payload: Option<&[u8]>,
+    ) {
+        if let Some(logger) = self
+            .cfg
+            .packet_logger
+            .as_mut()
+            .filter(|_| self.packet_logging)
+        {
+            let local_addr = self.transceiver.addr();
+            logger.log(&PacketRecord::new(
+                direction, self.time, local_addr, addr, raw, payload,
+            ));
+        }
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "server_connection_request", level = "debug", skip_all, fields(from = %from_addr))
+    )]
     fn process_connection_request(
         &mut self,
         from_addr: SocketAddr,
         mut packet: RequestPacket,
     ) -> Result<()> {
         let mut reader = std::io::Cursor::new(&mut packet.token_data[..]);
-        let Ok(token) = ConnectTokenPrivate::read_from(&mut reader) else {
+        let Ok(mut token) = ConnectTokenPrivate::read_from(&mut reader) else {
             log::debug!("server ignored connection request. failed to read connect token");
+            trace::event!(
+                DEBUG,
+                reason = "failed to read connect token",
+                "connect token rejected"
+            );
+            metrics::connect_failed(Side::Server, "failed to read connect token");
             return Ok(());
         };
+        if self.cfg.strict_netcode_1_02 {
+            token.server_addresses = token.server_addresses.swap_ipv6_segments();
+        }
         if !token
             .server_addresses
             .iter()
-            .any(|(_, addr)| addr == self.transceiver.addr())
+            .any(|(_, addr)| self.is_own_addr(addr))
         {
             log::debug!(
                 "server ignored connection request. server address not in connect token whitelist"
             );
+            trace::event!(
+                DEBUG,
+                client_id = token.client_id,
+                reason = "server address not in connect token whitelist",
+                "connect token rejected"
+            );
+            metrics::connect_failed(
+                Side::Server,
+                "server address not in connect token whitelist",
+            );
             return Ok(());
         };
         if self
@@ -518,49 +1959,153 @@ impl<T: Transceiver, S> Server<T, S> {
             .is_some_and(|(_, conn)| conn.is_connected())
         {
             log::debug!("server ignored connection request. a client with this address is already connected");
+            trace::event!(
+                DEBUG,
+                client_id = token.client_id,
+                reason = "a client with this address is already connected",
+                "connection request ignored"
+            );
+            metrics::connect_failed(
+                Side::Server,
+                "a client with this address is already connected",
+            );
             return Ok(());
         };
-        if self
-            .conn_cache
-            .find_by_id(token.client_id)
-            .is_some_and(|(_, conn)| conn.is_connected())
+        if self.cfg.duplicate_client_id == DuplicateClientId::RejectNew
+            && self
+                .conn_cache
+                .find_connected_by_id(token.client_id)
+                .is_some()
         {
             log::debug!(
                 "server ignored connection request. a client with this id is already connected"
             );
+            trace::event!(
+                DEBUG,
+                client_id = token.client_id,
+                reason = "a client with this id is already connected",
+                "connection request ignored"
+            );
+            metrics::connect_failed(Side::Server, "a client with this id is already connected");
             return Ok(());
         };
-        let entry = TokenEntry {
-            time: self.time,
-            addr: from_addr,
-            mac: packet.token_data
-                [ConnectTokenPrivate::SIZE - MAC_BYTES..ConnectTokenPrivate::SIZE]
-                .try_into()
-                .expect("valid MAC size"),
-        };
-        if !self.token_entries.find_or_insert(entry) {
+        let mac = packet.token_data
+            [ConnectTokenPrivate::SIZE - MAC_BYTES..ConnectTokenPrivate::SIZE]
+            .try_into()
+            .expect("valid MAC size");
+        let first_use =
+            match self
+                .cfg
+                .token_replay_store
+                .record_use(mac, from_addr, packet.expire_timestamp)
+            {
+                Ok(first_use) => first_use,
+                Err(e) => {
+                    log::warn!(
+                    "server ignored connection request. failed to record connect token use: {e}"
+                );
+                    metrics::connect_failed(Side::Server, "failed to record connect token use");
+                    return Ok(());
+                }
+            };
+        if !first_use {
             log::debug!("server ignored connection request. connect token has already been used");
+            trace::event!(
+                DEBUG,
+                client_id = token.client_id,
+                reason = "connect token has already been used",
+                "connect token rejected"
+            );
+            metrics::connect_failed(Side::Server, "connect token has already been used");
             return Ok(());
         };
-        if self.num_connected_clients() >= MAX_CLIENTS {
+        if self.banned.contains(&token.client_id) {
+            log::debug!("server denied connection request. client id is banned");
+            trace::event!(
+                INFO,
+                client_id = token.client_id,
+                reason = "client id is banned",
+                "connection request denied"
+            );
+            metrics::connect_failed(Side::Server, "client id is banned");
+            self.send_to_addr(
+                DeniedPacket::create(),
+                from_addr,
+                &token.server_to_client_key,
+                packet.protocol_id,
+            )?;
+            return Ok(());
+        }
+        if let Some(filter) = self.cfg.connect_filter.as_mut() {
+            if let ConnectDecision::Deny(reason) = filter(
+                token.client_id,
+                from_addr,
+                &token.user_data,
+                &mut self.cfg.context,
+            ) {
+                log::debug!("server denied connection request. {reason}");
+                trace::event!(
+                    INFO,
+                    client_id = token.client_id,
+                    reason = %reason,
+                    "connection request denied"
+                );
+                metrics::connect_failed(Side::Server, "denied by connect filter");
+                self.send_to_addr(
+                    DeniedPacket::create(),
+                    from_addr,
+                    &token.server_to_client_key,
+                    packet.protocol_id,
+                )?;
+                return Ok(());
+            }
+        }
+        if !self.replaces_client(token.client_id) && self.is_full(token.client_id) {
             log::debug!("server denied connection request. server is full");
+            trace::event!(
+                INFO,
+                client_id = token.client_id,
+                reason = "server is full",
+                "connection request denied"
+            );
+            metrics::connect_failed(Side::Server, "server is full");
             self.send_to_addr(
                 DeniedPacket::create(),
                 from_addr,
-                token.server_to_client_key,
+                &token.server_to_client_key,
+                packet.protocol_id,
             )?;
             return Ok(());
         };
+        if self.conn_cache.find_by_addr(&from_addr).is_none() && !self.make_pending_room() {
+            log::debug!("server ignored connection request. no room for pending connections");
+            trace::event!(
+                DEBUG,
+                client_id = token.client_id,
+                reason = "no room for pending connections",
+                "connection request ignored"
+            );
+            metrics::connect_failed(Side::Server, "no room for pending connections");
+            return Ok(());
+        }
         self.conn_cache.add(
-            token.client_id,
             from_addr,
-            token.timeout_seconds,
-            token.server_to_client_key,
-            token.client_to_server_key,
+            &token,
+            self.cfg.timeout_seconds.unwrap_or(token.timeout_seconds),
+            packet.expire_timestamp,
+            packet.protocol_id,
         );
+        if self.challenge_sequence == CHALLENGE_SEQUENCE_LIMIT {
+            self.rotate_challenge_key()?;
+        }
+        let custom_data = match self.cfg.challenge_data.as_mut() {
+            Some(cb) => cb(token.client_id, &token.user_data, &mut self.cfg.context),
+            None => [0; CHALLENGE_DATA_BYTES],
+        };
         let Ok(challenge_token_encrypted) = ChallengeToken {
             client_id: token.client_id,
             user_data: token.user_data,
+            custom_data,
         }
         .encrypt(self.challenge_sequence, &self.challenge_key) else {
             log::debug!("server ignored connection request. failed to encrypt challenge token");
@@ -569,44 +2114,162 @@ impl<T: Transceiver, S> Server<T, S> {
         self.send_to_addr(
             ChallengePacket::create(self.challenge_sequence, challenge_token_encrypted),
             from_addr,
-            token.server_to_client_key,
+            &token.server_to_client_key,
+            packet.protocol_id,
         )?;
         log::debug!("server sent connection challenge packet");
+        trace::event!(
+            DEBUG,
+            client_id = token.client_id,
+            challenge_sequence = self.challenge_sequence,
+            "connection challenge sent"
+        );
         self.challenge_sequence += 1;
         Ok(())
     }
+    // Evicts pending connections until a new one fits the pending limit, returns false if it can't.
+    fn make_pending_room(&mut self) -> bool {
+        while self.conn_cache.num_pending() >= self.cfg.max_pending_connections {
+            let Some((client_id, addr)) = self.conn_cache.evict_pending(self.cfg.pending_eviction)
+            else {
+                return false;
+            };
+            log::debug!(
+                "server evicted the pending connection of client id {client_id} from {addr}"
+            );
+            trace::event!(
+                DEBUG,
+                client_id = client_id,
+                reason = "evicted from pending connections",
+                "pending connection dropped"
+            );
+            metrics::connect_failed(Side::Server, "evicted from pending connections");
+            self.stats.pending_evictions += 1;
+        }
+        true
+    }
+    fn rotate_challenge_key(&mut self) -> Result<()> {
+        let key = crypto::try_generate_key_with(&mut *self.cfg.rng)?;
+        self.previous_challenge_key = Some(std::mem::replace(
+            &mut self.challenge_key,
+            Zeroizing::new(key),
+        ));
+        self.challenge_sequence = 0;
+        self.stats.challenge_key_rotations += 1;
+        log::debug!("server rotated the challenge key");
+        Ok(())
+    }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "server_connection_response", level = "debug", skip_all, fields(from = %from_addr))
+    )]
     fn process_connection_response(
         &mut self,
         from_addr: SocketAddr,
         mut packet: ResponsePacket,
     ) -> Result<()> {
-        let Ok(challenge_token) =
-            ChallengeToken::decrypt(&mut packet.token, packet.sequence, &self.challenge_key)
+        // The tag is checked before decrypting in place, so a failed attempt leaves the token intact for the next key.
+        let Some(challenge_token) = std::iter::once(&self.challenge_key)
+            .chain(self.previous_challenge_key.as_ref())
+            .find_map(|key| ChallengeToken::decrypt(&mut packet.token, packet.sequence, key).ok())
         else {
             log::debug!("server ignored connection response. failed to decrypt challenge token");
+            trace::event!(
+                DEBUG,
+                challenge_sequence = packet.sequence,
+                reason = "failed to decrypt challenge token",
+                "challenge token rejected"
+            );
+            metrics::connect_failed(Side::Server, "failed to decrypt challenge token");
             return Ok(());
         };
-        let Some((idx, conn)) = self.conn_cache.find_by_id(challenge_token.client_id) else {
+        // the pending connection of the address the response came from, whose key decrypted it
+        let Some((idx, connected, protocol_id)) = self
+            .conn_cache
+            .find_by_addr(&from_addr)
+            .filter(|(_, conn)| conn.client_id == challenge_token.client_id)
+            .map(|(idx, conn)| (idx, conn.is_connected(), conn.protocol_id))
+        else {
+            self.stats.pending_misses += 1;
             log::debug!("server ignored connection response. no packet send key");
+            trace::event!(
+                DEBUG,
+                client_id = challenge_token.client_id,
+                reason = "no packet send key",
+                "connection response ignored"
+            );
+            metrics::connect_failed(Side::Server, "no packet send key");
             return Ok(());
         };
-        if conn.is_connected() {
+        if connected {
             log::debug!(
                 "server ignored connection request. a client with this id is already connected"
             );
             return Ok(());
         };
-        if self.num_connected_clients() >= MAX_CLIENTS {
+        self.stats.pending_hits += 1;
+        // banned while the connection was pending
+        if self.banned.contains(&challenge_token.client_id) {
+            log::debug!("server denied connection response. client id is banned");
+            trace::event!(
+                INFO,
+                client_id = challenge_token.client_id,
+                reason = "client id is banned",
+                "connection response denied"
+            );
+            metrics::connect_failed(Side::Server, "client id is banned");
+            self.send_denied_to_pending(idx, from_addr)?;
+            return Ok(());
+        }
+        if let Some(existing) = self
+            .conn_cache
+            .find_connected_by_id(challenge_token.client_id)
+        {
+            match self.cfg.duplicate_client_id {
+                DuplicateClientId::KickExisting => {
+                    log::debug!(
+                        "server replacing client {existing} with a new connection from {from_addr}"
+                    );
+                    trace::event!(
+                        INFO,
+                        client_index = existing.0,
+                        client_id = challenge_token.client_id,
+                        "client replaced by a new connection"
+                    );
+                    self.disconnect(existing)?;
+                }
+                DuplicateClientId::AllowBoth => {}
+                _ => {
+                    log::debug!(
+                        "server ignored connection response. a client with this id is already connected"
+                    );
+                    return Ok(());
+                }
+            }
+        }
+        if self.is_full(challenge_token.client_id) {
             log::debug!("server denied connection response. server is full");
-            self.send_to_addr(
-                DeniedPacket::create(),
-                from_addr,
-                self.conn_cache.clients[idx.0].send_key,
-            )?;
+            trace::event!(
+                INFO,
+                client_id = challenge_token.client_id,
+                reason = "server is full",
+                "connection response denied"
+            );
+            metrics::connect_failed(Side::Server, "server is full");
+            self.send_denied_to_pending(idx, from_addr)?;
             return Ok(());
         };
+        if let Some(stats) = self.protocol_stats.get_mut(&protocol_id) {
+            stats.connections_accepted += 1;
+        }
+        self.reservations.remove(&challenge_token.client_id);
+        let keep_alive_interval_resends = match self.cfg.keep_alive_interval {
+            Some(_) if !self.cfg.strict_netcode_1_02 => NUM_KEEP_ALIVE_INTERVAL_PACKETS,
+            _ => 0,
+        };
         let client = &mut self.conn_cache.clients[idx.0];
         client.connect();
+        client.keep_alive_interval_resends = keep_alive_interval_resends;
         client.last_send_time = self.time;
         client.last_receive_time = self.time;
         log::debug!(
@@ -614,104 +2277,478 @@ impl<T: Transceiver, S> Server<T, S> {
             idx,
             challenge_token.client_id
         );
+        trace::event!(
+            INFO,
+            client_index = idx.0,
+            client_id = challenge_token.client_id,
+            "client connected"
+        );
+        metrics::connect_succeeded(Side::Server);
         self.send_to_client(
-            KeepAlivePacket::create(idx.0 as i32, MAX_CLIENTS as i32),
+            KeepAlivePacket::create(idx.0 as i32, self.max_clients as i32),
             idx,
         )?;
-        self.on_connect(idx);
+        self.sync_handle();
+        self.on_connect(idx, &challenge_token.custom_data);
+        self.sync_cluster();
         Ok(())
     }
     fn check_for_timeouts(&mut self) {
-        for idx in 0..MAX_CLIENTS {
+        for idx in 0..self.conn_cache.clients.slots() {
             let Some(client) = self.conn_cache.clients.get_mut(idx) else {
                 continue;
             };
             let idx = ClientIndex(idx);
             if !client.is_connected() {
+                let timeout = match self.cfg.pending_timeout {
+                    Some(timeout) => timeout,
+                    None if client.timeout.is_positive() => client.timeout as f64,
+                    None => continue,
+                };
+                if client.last_access_time + timeout < self.time {
+                    log::debug!(
+                        "server timed out the pending connection of client id {} from {}",
+                        client.client_id,
+                        client.addr
+                    );
+                    trace::event!(
+                        DEBUG,
+                        client_id = client.client_id,
+                        reason = "pending connection timed out",
+                        "pending connection dropped"
+                    );
+                    metrics::connect_failed(Side::Server, "pending connection timed out");
+                    self.stats.pending_timeouts += 1;
+                    self.conn_cache.remove_pending(idx.0);
+                }
                 continue;
             }
             if client.timeout.is_positive()
                 && client.last_receive_time + (client.timeout as f64) < self.time
             {
                 log::debug!("server timed out client {idx}");
+                trace::event!(
+                    INFO,
+                    client_index = idx.0,
+                    client_id = client.client_id,
+                    timeout = client.timeout,
+                    "client timed out"
+                );
                 self.on_disconnect(idx);
                 self.conn_cache.remove(idx);
             }
         }
     }
     fn send_packets(&mut self) -> Result<()> {
-        for idx in 0..MAX_CLIENTS {
+        let is_tick_sync = self.is_tick_sync();
+        for idx in 0..self.conn_cache.clients.slots() {
             let Some(client) = self.conn_cache.clients.get_mut(idx) else {
                 continue;
             };
             if !client.is_connected() {
                 continue;
             }
-            if client.last_send_time + self.cfg.keep_alive_send_rate >= self.time {
+            let stamp_due =
+                is_tick_sync && client.last_stamp_time + tick_sync::SYNC_INTERVAL_SEC <= self.time;
+            if client.last_send_time + self.cfg.keep_alive_send_rate >= self.time && !stamp_due {
                 continue;
             }
 
-            self.send_to_client(
-                KeepAlivePacket::create(idx as i32, MAX_CLIENTS as i32),
-                ClientIndex(idx),
-            )?;
+            let resend_limit = match client.max_payload_size {
+                Some(max_payload_size) if client.payload_limit_resends > 0 => {
+                    client.payload_limit_resends -= 1;
+                    Some(max_payload_size)
+                }
+                _ => None,
+            };
+            let resend_interval = match self.cfg.keep_alive_interval {
+                Some(interval) if client.keep_alive_interval_resends > 0 => {
+                    client.keep_alive_interval_resends -= 1;
+                    Some(interval)
+                }
+                _ => None,
+            };
+
+            self.send_keep_alive(ClientIndex(idx))?;
             log::trace!("server sent connection keep-alive packet to client {idx}");
+            if let Some(max_payload_size) = resend_limit {
+                self.send_to_client(
+                    PayloadLimitPacket::create(max_payload_size),
+                    ClientIndex(idx),
+                )?;
+            }
+            if let Some(interval) = resend_interval {
+                let interval_ms = (interval * 1000.0).round() as u32;
+                self.send_to_client(
+                    KeepAliveIntervalPacket::create(interval_ms),
+                    ClientIndex(idx),
+                )?;
+            }
+            self.send_transfer(ClientIndex(idx))?;
+        }
+        Ok(())
+    }
+    // Sends the fragments of a client's transfer token, if it has one left to send.
+    fn send_transfer(&mut self, client_idx: ClientIndex) -> Result<()> {
+        let Some(mut transfer) = self.conn_cache.transfers.remove(&client_idx) else {
+            return Ok(());
+        };
+        for (fragment, data) in transfer::fragments(&transfer.token) {
+            self.send_to_client(TransferPacket::create(fragment, data), client_idx)?;
+        }
+        transfer.sends_left -= 1;
+        if transfer.sends_left > 0 {
+            self.conn_cache.transfers.insert(client_idx, transfer);
         }
         Ok(())
     }
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "server_recv_packet", level = "trace", skip_all, fields(from = %addr, len = buf.len()))
+    )]
     fn recv_packet(&mut self, buf: &mut [u8], now: u64, addr: SocketAddr) -> Result<()> {
         if buf.len() <= 1 {
             // Too small to be a packet
             return Ok(());
         }
-        let (key, replay_protection) = match self.conn_cache.find_by_addr(&addr) {
+        if self.cfg.queries.is_some() && query::is_query(buf) {
+            return self.answer_query(buf, addr);
+        }
+        if self.shutdown.is_some() {
+            return self.recv_shutdown_packet(buf, now, addr);
+        }
+        let (phase, client_idx) = match self.conn_cache.find_by_addr(&addr) {
+            Some((client_idx, conn)) if conn.is_connected() => {
+                (ConnectionPhase::Connected, Some(client_idx))
+            }
+            Some((client_idx, _)) => (ConnectionPhase::Pending, Some(client_idx)),
+            None => (ConnectionPhase::Unconnected, None),
+        };
+        if client_idx.is_none() && buf[0] & 0xF == Packet::RESPONSE {
+            // a challenge response from an address without a pending connection, e.g. one that was evicted
+            self.stats.pending_misses += 1;
+        }
+        let mut request_cipher;
+        let (cipher, protocol_id, replay_protection) = match client_idx {
             // Regardless of whether an entry in the connection cache exists for the client or not,
             // if the packet is a connection request we need to use the server's private key to decrypt it.
-            _ if buf[0] == Packet::REQUEST => (self.private_key, None),
-            Some((client_idx, _)) => (
-                // If the packet is not a connection request, use the receive key to decrypt it.
-                self.conn_cache.clients[client_idx.0].receive_key,
+            _ if buf[0] == Packet::REQUEST => {
+                request_cipher = Cipher::new(self.request_key(buf));
+                (&mut request_cipher, self.request_protocol_id(buf), None)
+            }
+            Some(client_idx) => (
+                // If the packet is not a connection request, use the receive key
+                // and the protocol id the client connected with to decrypt it.
+                &mut self
+                    .conn_cache
+                    .ciphers
+                    .get_mut(&client_idx)
+                    .expect("every connection has its ciphers")
+                    .receive,
+                self.conn_cache.clients[client_idx.0].protocol_id,
                 self.conn_cache.replay_protection.get_mut(&client_idx),
             ),
+            None if self.cfg.allow_migration => {
+                return self.recv_migration_packet(buf, now, addr);
+            }
             None => {
                 // Not a connection request packet, and not a known client, so ignore
                 log::debug!(
                     "server ignored non-connection-request packet from unknown address {addr}"
                 );
+                metrics::packet_dropped(Side::Server, "unknown address");
                 return Ok(());
             }
         };
-        let packet = match Packet::read(
+        let len = buf.len();
+        // the packet is decrypted in place, keep a copy of the raw bytes for the logger
+        let raw = self.cfg.packet_logger.is_some().then(|| buf.to_vec());
+        let previous_sequence = replay_protection
+            .as_ref()
+            .and_then(|replay_protection| replay_protection.most_recent_sequence());
+        let result = Packet::read_with(
             buf,
-            self.protocol_id,
+            protocol_id,
             now,
-            key,
+            cipher,
             replay_protection,
-            Self::ALLOWED_PACKETS,
-        ) {
+            self.cfg.allowed_packets.for_phase(phase).bits(),
+            self.cfg.strict_netcode_1_02,
+        );
+        if let Some(raw) = raw {
+            let payload = match &result {
+                Ok(Packet::Payload(PayloadPacket { buf })) => Some(*buf),
+                _ => None,
+            };
+            self.log_packet(PacketDirection::Received, addr, &raw, payload);
+        }
+        let packet = match result {
             Ok(packet) => packet,
+            Err(Error::Packet(crate::packet::Error::NotAllowed(kind))) => {
+                log::debug!(
+                    "server ignored packet of type {kind} from {addr}, not allowed while {phase:?}"
+                );
+                trace::event!(DEBUG, from = %addr, kind = kind, phase = ?phase, "server packet out of phase");
+                metrics::packet_dropped(Side::Server, "out of phase");
+                self.stats.out_of_phase.increment(kind);
+                return Ok(());
+            }
             Err(Error::Crypto(_)) => {
                 log::debug!("server ignored packet because it failed to decrypt");
+                trace::event!(DEBUG, from = %addr, "server packet decryption failed");
+                metrics::packet_dropped(Side::Server, "decryption failed");
+                return Ok(());
+            }
+            Err(Error::Packet(crate::packet::Error::AlreadyReceived(_sequence))) => {
+                log::debug!("server ignored packet because it was already received");
+                trace::event!(DEBUG, from = %addr, sequence = _sequence, "server packet replay rejected");
+                metrics::packet_dropped(Side::Server, "already received");
                 return Ok(());
             }
             Err(e) => {
                 log::error!("server ignored packet: {e}");
+                trace::event!(DEBUG, from = %addr, error = %e, "server ignored invalid packet");
+                metrics::packet_dropped(Side::Server, "invalid");
                 return Ok(());
             }
         };
-        self.process_packet(addr, packet)
-    }
-    fn recv_packets(&mut self) -> Result<()> {
-        let mut buf = [0u8; MAX_PACKET_SIZE];
-        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
-        while let Some((size, addr)) = self.transceiver.recv(&mut buf).map_err(|e| e.into())? {
-            self.recv_packet(&mut buf[..size], now, addr)?;
+        metrics::packet_received(Side::Server, packet.kind(), len);
+        if let Some(stats) = self.protocol_stats.get_mut(&protocol_id) {
+            stats.packet_received(len);
         }
-        Ok(())
-    }
-    /// Creates a new server instance with the given configuration and transceiver.
-    ///
-    /// This is useful if you want to use a custom transceiver implementation,
+        let most_recent_sequence = client_idx
+            .and_then(|idx| self.conn_cache.replay_protection.get(&idx))
+            .and_then(ReplayProtection::most_recent_sequence);
+        let gap_client =
+            client_idx.filter(|_| is_sequence_gap(previous_sequence, most_recent_sequence));
+        if let Some(idx) = client_idx.filter(|_| self.cfg.rekey_sessions) {
+            if rekey::is_new_epoch(previous_sequence, most_recent_sequence) {
+                log::debug!("client {idx} rekeyed its session");
+                self.stats.rekeys += 1;
+            }
+        }
+        self.process_packet(addr, packet)?;
+        if let Some(idx) = gap_client {
+            self.stats.sequence_gaps += 1;
+            let is_connected = self
+                .conn_cache
+                .clients
+                .get(idx.0)
+                .is_some_and(|conn| conn.is_connected());
+            if self.cfg.ack_on_sequence_gap && is_connected {
+                log::trace!(
+                    "server sending immediate keep-alive to client {idx} after a sequence gap"
+                );
+                self.send_keep_alive(idx)?;
+            }
+        }
+        Ok(())
+    }
+    // The key the connect token of a connection request is encrypted with: the well-known key of insecure connects
+    // if they are allowed and the token decrypts with it, or the previous private key if the token was issued before a rotation.
+    fn request_key(&self, buf: &[u8]) -> Key {
+        let decrypts_with = |key: Key| {
+            RequestPacket::read_from(&mut std::io::Cursor::new(&buf[1..]))
+                .is_ok_and(|mut packet| packet.decrypt_token_data(key).is_ok())
+        };
+        #[cfg(feature = "insecure")]
+        if self.cfg.allow_insecure && decrypts_with(crypto::INSECURE_KEY) {
+            log::debug!("server received an insecure connection request");
+            return crypto::INSECURE_KEY;
+        }
+        match &self.previous_private_key {
+            Some(previous) if !decrypts_with(*self.private_key) && decrypts_with(**previous) => {
+                **previous
+            }
+            _ => *self.private_key,
+        }
+    }
+    /// The protocol id a connection request was sent with if the server accepts it,
+    /// otherwise the server's own protocol id so the request fails validation.
+    fn request_protocol_id(&self, buf: &[u8]) -> u64 {
+        let offset = size_of::<u8>() + NETCODE_VERSION.len();
+        buf.get(offset..offset + size_of::<u64>())
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+            .filter(|protocol_id| self.protocol_stats.contains_key(protocol_id))
+            .unwrap_or(self.protocol_id)
+    }
+    // Finds the connected client that sent a packet from an unknown address, by the key the packet decrypts with.
+    fn recv_migration_packet(&mut self, buf: &mut [u8], now: u64, addr: SocketAddr) -> Result<()> {
+        if self.migration_probes >= MAX_MIGRATION_PROBES_PER_UPDATE {
+            metrics::packet_dropped(Side::Server, "unknown address");
+            return Ok(());
+        }
+        self.migration_probes += 1;
+        let allowed = self
+            .cfg
+            .allowed_packets
+            .for_phase(ConnectionPhase::Connected)
+            .bits()
+            | ServerPhaseTable::MIGRATION.bits();
+        let candidates: Vec<ClientIndex> = match self.migrations.get(&addr) {
+            Some(pending) => vec![pending.client_idx],
+            None => self
+                .conn_cache
+                .clients
+                .iter()
+                .filter(|(_, conn)| conn.is_connected())
+                .map(|(idx, _)| ClientIndex(idx))
+                .collect(),
+        };
+        let mut attempt = [0u8; MAX_PKT_BUF_SIZE];
+        for idx in candidates {
+            let Some(conn) = self.conn_cache.clients.get(idx.0) else {
+                continue;
+            };
+            let (protocol_id, client_id) = (conn.protocol_id, conn.client_id);
+            // the packet is decrypted in place, so every attempt starts from the received bytes
+            let attempt = &mut attempt[..buf.len()];
+            attempt.copy_from_slice(buf);
+            let Ok(packet) = Packet::read_with(
+                attempt,
+                protocol_id,
+                now,
+                &mut self
+                    .conn_cache
+                    .ciphers
+                    .get_mut(&idx)
+                    .expect("every connection has its ciphers")
+                    .receive,
+                self.conn_cache.replay_protection.get_mut(&idx),
+                allowed,
+                self.cfg.strict_netcode_1_02,
+            ) else {
+                continue;
+            };
+            let response = match packet {
+                Packet::PathResponse(PathResponsePacket { nonce }) => Some(nonce),
+                _ => None,
+            };
+            match self.migrations.get(&addr) {
+                Some(pending)
+                    if pending.client_id == client_id && response == Some(pending.nonce) =>
+                {
+                    self.migrations.remove(&addr);
+                    log::debug!("server migrated client {idx} to {addr}");
+                    trace::event!(INFO, client_index = idx.0, to = %addr, "client migrated");
+                    let conn = &mut self.conn_cache.clients[idx.0];
+                    conn.addr = addr;
+                    conn.last_receive_time = self.time;
+                    self.stats.migrations += 1;
+                }
+                // the challenge may have been lost, repeat it at most once per update
+                Some(pending)
+                    if pending.client_id == client_id && pending.sent_time < self.time =>
+                {
+                    let nonce = pending.nonce;
+                    if let Some(pending) = self.migrations.get_mut(&addr) {
+                        pending.sent_time = self.time;
+                    }
+                    self.send_to_client_at(PathChallengePacket::create(nonce), idx, addr)?;
+                }
+                Some(_) => {}
+                None => {
+                    log::debug!("server challenging new address {addr} of client {idx}");
+                    let nonce = self.cfg.rng.next_u64();
+                    self.migrations.insert(
+                        addr,
+                        PendingMigration {
+                            client_idx: idx,
+                            client_id,
+                            nonce,
+                            challenge_time: self.time,
+                            sent_time: self.time,
+                        },
+                    );
+                    self.send_to_client_at(PathChallengePacket::create(nonce), idx, addr)?;
+                }
+            }
+            return Ok(());
+        }
+        log::debug!("server ignored packet from unknown address {addr}, no client's key matches");
+        metrics::packet_dropped(Side::Server, "unknown address");
+        Ok(())
+    }
+    // While shutting down, the only packets the server cares about are the disconnect packets of the draining clients.
+    fn recv_shutdown_packet(&mut self, buf: &mut [u8], now: u64, addr: SocketAddr) -> Result<()> {
+        let Some(shutdown) = self.shutdown.as_mut() else {
+            return Ok(());
+        };
+        let Some(client) = shutdown.draining.get_mut(&addr) else {
+            log::debug!("server ignored packet from {addr}, shutting down");
+            metrics::packet_dropped(Side::Server, "shutting down");
+            return Ok(());
+        };
+        let result = Packet::read_with(
+            buf,
+            client.protocol_id,
+            now,
+            &mut client.receive,
+            Some(&mut client.replay_protection),
+            PacketAllowList::new(&[PacketType::Disconnect]).bits(),
+            self.cfg.strict_netcode_1_02,
+        );
+        if let Ok(Packet::Disconnect(_)) = result {
+            log::debug!("client at {addr} acknowledged the server shutdown");
+            shutdown.draining.remove(&addr);
+            shutdown.acknowledged += 1;
+        }
+        Ok(())
+    }
+    fn recv_packets(&mut self) -> Result<()> {
+        let mut buf = [0u8; MAX_PKT_BUF_SIZE + relay::MAX_HEADER_BYTES];
+        let now = self.cfg.clock.unix_time();
+        if let Some(recorder) = self.cfg.recorder.as_ref() {
+            recorder.update(self.time, now);
+        }
+        loop {
+            let (size, addr) = match self.transceiver.recv(&mut buf).map_err(Into::<Error>::into) {
+                Ok(Some(received)) => received,
+                Ok(None) => {
+                    if let Some(recorder) = self.cfg.recorder.as_ref() {
+                        recorder.flush();
+                    }
+                    return Ok(());
+                }
+                Err(e) if e.is_port_unreachable() => {
+                    // a client went away, which doesn't keep the server from receiving from the others
+                    log::debug!("server ignored port unreachable for an earlier send: {e}");
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if let Some(recorder) = self.cfg.recorder.as_ref() {
+                recorder.datagram(addr, &buf[..size]);
+            }
+            let Some(relay) = self.cfg.relay.as_ref() else {
+                self.recv_packet(&mut buf[..size], now, addr)?;
+                continue;
+            };
+            if !relay::is_relayed(&buf[..size]) {
+                if relay.allow_direct {
+                    self.recv_packet(&mut buf[..size], now, addr)?;
+                } else {
+                    log::debug!("server ignored packet from {addr} without a relay header");
+                    metrics::packet_dropped(Side::Server, "not relayed");
+                }
+                continue;
+            }
+            match relay::unwrap_for_server(&relay.key, &buf[..size]) {
+                Ok((client_addr, packet)) => {
+                    self.relay_routes.insert(client_addr, (addr, self.time));
+                    self.recv_packet(&mut buf[packet], now, client_addr)?;
+                }
+                Err(e) => {
+                    log::debug!("server ignored relayed packet from {addr}: {e}");
+                    metrics::packet_dropped(Side::Server, "invalid relay header");
+                    self.stats.invalid_relay_headers += 1;
+                }
+            }
+        }
+    }
+    /// Creates a new server instance with the given configuration and transceiver.
+    ///
+    /// This is useful if you want to use a custom transceiver implementation,
     /// in any other case you should use [`Server::new`](Server::new) or [`Server::with_config`](Server::with_config).
     ///
     /// # Examples
@@ -741,20 +2778,59 @@ impl<T: Transceiver, S> Server<T, S> {
     pub fn with_config_and_transceiver(
         protocol_id: u64,
         private_key: Key,
-        cfg: ServerConfig<S>,
+        mut cfg: ServerConfig<S>,
         trx: T,
     ) -> Result<Self> {
+        cfg.validate()?;
+        if let Some(recorder) = cfg.recorder.clone() {
+            recorder.start(protocol_id, trx.addr());
+            let rng = std::mem::replace(&mut cfg.rng, Box::new(OsRng));
+            cfg.rng = Box::new(recorder.rng(rng));
+        }
+        let challenge_key = crypto::try_generate_key_with(&mut *cfg.rng)?;
         let server = Server {
             transceiver: trx,
             time: 0.0,
-            private_key,
+            private_key: Zeroizing::new(private_key),
+            previous_private_key: None,
             protocol_id,
             sequence: 1 << 63,
             token_sequence: 0,
             challenge_sequence: 0,
-            challenge_key: crypto::try_generate_key()?,
-            conn_cache: ConnectionCache::new(0.0),
-            token_entries: TokenEntries::new(),
+            challenge_key: Zeroizing::new(challenge_key),
+            previous_challenge_key: None,
+            max_clients: cfg.max_clients,
+            stats: ServerStats::new(cfg.max_payload_size),
+            protocol_stats: std::iter::once(protocol_id)
+                .chain(cfg.protocol_ids.iter().copied())
+                .map(|protocol_id| (protocol_id, ProtocolStats::default()))
+                .collect(),
+            conn_cache: ConnectionCache::new(
+                0.0,
+                PayloadQueues::new(cfg.recv_queue_depth, cfg.recv_queue_overflow),
+                cfg.rekey_sessions,
+            ),
+            shutdown: None,
+            migrations: HashMap::new(),
+            migration_probes: 0,
+            reservations: HashMap::new(),
+            query_limit: RateLimit::default(),
+            send_backlog: SendBacklog::default(),
+            pacer: cfg
+                .pacing
+                .map(|(tick_interval, max_packets)| Pacer::new(tick_interval, max_packets)),
+            banned: HashSet::new(),
+            packet_logging: true,
+            #[cfg(unix)]
+            admin: cfg
+                .admin_socket
+                .as_deref()
+                .map(AdminSocket::bind)
+                .transpose()?,
+            relay_routes: HashMap::new(),
+            tick_anchor: (0, 0.0),
+            cluster: None,
+            commands: None,
             cfg,
         };
         log::info!("server started on {}", server.addr());
@@ -779,12 +2855,40 @@ impl<T: Transceiver, S> Server<T, S> {
     /// The fallible version of [`update`](Server::update).
     ///
     /// Returns an error if the server can't send or receive packets.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "server_update", level = "trace", skip_all, fields(time = time))
+    )]
     pub fn try_update(&mut self, time: f64) -> Result<()> {
+        let _timer = metrics::UpdateTimer::start(Side::Server);
         self.time = time;
+        if let Some(pacer) = self.pacer.as_mut() {
+            for (buf, addr) in pacer.start_tick(time) {
+                self.send_backlog.send(&self.transceiver, &buf, addr)?;
+            }
+        }
         self.conn_cache.update(self.time);
+        self.migration_probes = 0;
+        let time = self.time;
+        self.migrations
+            .retain(|_, pending| pending.challenge_time + MIGRATION_TIMEOUT_SEC > time);
+        self.reservations.retain(|_, expires| *expires > time);
+        let conn_cache = &self.conn_cache;
+        self.relay_routes.retain(|addr, (_, last_seen)| {
+            *last_seen + RELAY_ROUTE_TIMEOUT_SEC > time
+                || conn_cache
+                    .find_by_addr(addr)
+                    .is_some_and(|(_, conn)| conn.is_connected())
+        });
         self.recv_packets()?;
+        self.run_commands()?;
+        #[cfg(unix)]
+        self.run_admin_socket();
+        self.flush()?;
         self.send_packets()?;
         self.check_for_timeouts();
+        self.sync_handle();
+        self.sync_cluster();
         Ok(())
     }
     /// Receives a packet from a client, if one is available in the queue.
@@ -813,50 +2917,355 @@ impl<T: Transceiver, S> Server<T, S> {
     ///    # break;
     /// }
     pub fn recv(&mut self) -> Option<(Vec<u8>, ClientIndex)> {
-        self.conn_cache.packet_queue.pop_front()
+        self.conn_cache
+            .packet_queue
+            .pop()
+            .map(|(idx, packet)| (packet, ClientIndex(idx)))
+    }
+    /// Receives a packet from one client, if one is available in its queue.
+    ///
+    /// Every client has its own queue, so the packets of each client can be processed separately (e.g. a bounded number per tick),
+    /// and a client that sends a lot can't hold up the processing of the others. [`recv`](Server::recv) returns the packets of all clients
+    /// in the order they arrived, skipping those already received with `recv_from`. <br>
+    /// Queues are bounded with [`ServerConfig::recv_queue_depth`](ServerConfig::recv_queue_depth).
+    ///
+    /// # Example
+    /// ```
+    /// # use netcode::Server;
+    /// # let mut server = Server::new("127.0.0.1:0", 0x11, [42u8; 32]).unwrap();
+    /// # let players: Vec<netcode::ClientIndex> = Vec::new();
+    /// server.update(0.0);
+    /// // e.g. the clients passed to `ServerConfig::on_connect`
+    /// for &client_idx in &players {
+    ///     // at most 8 packets per client per tick
+    ///     for _ in 0..8 {
+    ///         let Some(packet) = server.recv_from(client_idx) else { break };
+    ///         // ...
+    ///     }
+    /// }
+    /// ```
+    pub fn recv_from(&mut self, client_idx: ClientIndex) -> Option<Vec<u8>> {
+        self.conn_cache.packet_queue.pop_key(client_idx.0)
+    }
+    /// Receives a packet from a client into `buf`, if one is available in the queue.
+    ///
+    /// Returns the client index of the sender and the length of the packet. Unlike [`recv`](Server::recv),
+    /// this doesn't hand out a new `Vec<u8>` per packet: the queue's buffers are reused for later packets,
+    /// so a server that only receives this way stops allocating for received packets. <br>
+    /// `buf` should be at least as large as the [max payload size](ServerConfig::max_payload_size), a longer packet is truncated.
+    ///
+    /// If no packet is available, `None` will be returned.
+    ///
+    /// # Example
+    /// ```
+    /// # use netcode::Server;
+    /// # let mut server = Server::new("127.0.0.1:0", 0x11, [42u8; 32]).unwrap();
+    /// let mut buf = [0u8; netcode::MAX_PACKET_SIZE];
+    /// server.update(0.0);
+    /// while let Some((client_idx, len)) = server.recv_into(&mut buf) {
+    ///     let packet = &buf[..len];
+    ///     // ...
+    /// }
+    /// ```
+    pub fn recv_into(&mut self, buf: &mut [u8]) -> Option<(ClientIndex, usize)> {
+        self.conn_cache
+            .packet_queue
+            .pop_into(buf)
+            .map(|(idx, len)| (ClientIndex(idx), len))
     }
     /// Sends a packet to a client.
     ///
-    /// The provided buffer must be smaller than [`MAX_PACKET_SIZE`](crate::MAX_PACKET_SIZE).
-    pub fn send(&mut self, buf: &[u8], client_idx: ClientIndex) -> Result<()> {
-        if buf.len() > MAX_PACKET_SIZE {
-            return Err(Error::SizeMismatch(MAX_PACKET_SIZE, buf.len()));
+    /// The provided buffer must not be larger than the configured [max payload size](ServerConfig::max_payload_size),
+    /// [`MAX_PACKET_SIZE`](crate::MAX_PACKET_SIZE) by default, minus 2 bytes if [payloads are coalesced](ServerConfig::coalesce_payloads),
+    /// 1 byte if they are compressed, 12 bytes if they are [acked](ServerConfig::ack_payloads) and 2 bytes if they are [padded](ServerConfig::pad_payloads).
+    ///
+    /// If payloads are coalesced, the packet is queued until the next [`update`](Server::update) or [`flush`](Server::flush).
+    ///
+    /// Returns how the packet was sent, see [`SendOutcome`], e.g. [`SendOutcome::NotConnected`] if the client
+    /// disconnected since its index was received.
+    pub fn send(&mut self, buf: &[u8], client_idx: ClientIndex) -> Result<SendOutcome> {
+        let max_message_size = self.max_message_size();
+        if buf.len() > max_message_size {
+            return Err(Error::SizeMismatch(max_message_size, buf.len()));
         }
-        let Some(conn) = self.conn_cache.clients.get_mut(client_idx.0) else {
+        let is_connected = self
+            .conn_cache
+            .clients
+            .get(client_idx.0)
+            .is_some_and(|conn| conn.is_connected());
+        if !is_connected {
+            return Ok(SendOutcome::NotConnected);
+        }
+        self.send_payload(buf, client_idx)
+    }
+    /// Sets a small application heartbeat (e.g. the server tick or its load) that is attached to every keep-alive packet
+    /// sent to a client from now on, and delivered to [`ClientConfig::on_heartbeat`](crate::ClientConfig::on_heartbeat). <br>
+    /// Keep-alives are sent anyway, so this costs no extra packets. An empty payload stops attaching one.
+    /// The heartbeat is dropped when the client disconnects.
+    ///
+    /// Returns an error if the payload is larger than [`MAX_HEARTBEAT_BYTES`](crate::MAX_HEARTBEAT_BYTES)
+    /// (minus 24 bytes with [tick sync](ServerConfig::tick_sync)) or the client isn't connected.
+    /// Heartbeats aren't attached with [`strict_netcode_1_02`](ServerConfig::strict_netcode_1_02), as the reference implementation
+    /// rejects keep-alives with trailing bytes.
+    pub fn set_heartbeat_payload(&mut self, client_idx: ClientIndex, payload: &[u8]) -> Result<()> {
+        let max_heartbeat_bytes = if self.is_tick_sync() {
+            MAX_HEARTBEAT_BYTES - tick_sync::SERVER_STAMP_BYTES
+        } else {
+            MAX_HEARTBEAT_BYTES
+        };
+        if payload.len() > max_heartbeat_bytes {
+            return Err(Error::SizeMismatch(max_heartbeat_bytes, payload.len()));
+        }
+        let Some(conn) = self.conn_cache.clients.get(client_idx.0) else {
             return Err(Error::ClientNotFound);
         };
         if !conn.is_connected() {
-            // since there is no way to obtain a client index of clients that are not connected,
-            // there is no straight-forward way for a user to send a packet to a non-connected client.
-            // still, in case a user somehow manages to obtain such index, we'll return an error.
             return Err(Error::ClientNotConnected);
         }
-        if !conn.is_confirmed() {
-            // send a keep-alive packet to the client to confirm the connection
-            self.send_to_client(
-                KeepAlivePacket::create(client_idx.0 as i32, MAX_CLIENTS as i32),
-                client_idx,
-            )?;
+        if payload.is_empty() || self.cfg.strict_netcode_1_02 {
+            self.conn_cache.heartbeats.remove(&client_idx);
+        } else {
+            self.conn_cache
+                .heartbeats
+                .insert(client_idx, payload.to_vec());
         }
-        let packet = PayloadPacket::create(buf);
-        self.send_to_client(packet, client_idx)
+        Ok(())
     }
     /// Sends a packet to all connected clients.
     ///
-    /// The provided buffer must be smaller than [`MAX_PACKET_SIZE`](crate::MAX_PACKET_SIZE).
+    /// The provided buffer must not be larger than the configured [max payload size](ServerConfig::max_payload_size).
     pub fn send_all(&mut self, buf: &[u8]) -> Result<()> {
-        for idx in 0..MAX_CLIENTS {
-            match self.send(buf, ClientIndex(idx)) {
-                Ok(_) | Err(Error::ClientNotConnected) | Err(Error::ClientNotFound) => continue,
-                Err(e) => return Err(e),
+        self.broadcast(buf)
+    }
+    /// Sends a packet to all connected clients.
+    ///
+    /// The provided buffer must not be larger than the configured [max payload size](ServerConfig::max_payload_size).
+    /// The size is checked once up front, then each client is sent its own packet encrypted with that client's key.
+    pub fn broadcast(&mut self, buf: &[u8]) -> Result<()> {
+        self.send_to_matching(buf, |_| true)
+    }
+    /// Sends a packet to all connected clients except one, e.g. to relay a packet received from that client to everyone else.
+    ///
+    /// The provided buffer must not be larger than the configured [max payload size](ServerConfig::max_payload_size).
+    pub fn broadcast_except(&mut self, client_idx: ClientIndex, buf: &[u8]) -> Result<()> {
+        self.send_to_matching(buf, |idx| idx != client_idx)
+    }
+    /// Sends a packet to a group of clients.
+    ///
+    /// The provided buffer must not be larger than the configured [max payload size](ServerConfig::max_payload_size).
+    /// Clients in the group that are not connected (anymore) are skipped, and a client listed more than once is only sent one packet.
+    pub fn send_to_group(&mut self, group: &[ClientIndex], buf: &[u8]) -> Result<()> {
+        self.send_to_matching(buf, |idx| group.contains(&idx))
+    }
+    fn send_to_matching(&mut self, buf: &[u8], filter: impl Fn(ClientIndex) -> bool) -> Result<()> {
+        let max_message_size = self.max_message_size();
+        if buf.len() > max_message_size {
+            return Err(Error::SizeMismatch(max_message_size, buf.len()));
+        }
+        for idx in 0..self.conn_cache.clients.slots() {
+            let Some(conn) = self.conn_cache.clients.get(idx) else {
+                continue;
+            };
+            let idx = ClientIndex(idx);
+            if !conn.is_connected() || !filter(idx) {
+                continue;
             }
+            self.send_payload(buf, idx)?;
         }
         Ok(())
     }
+    /// Sends the [paced](ServerConfig::pace_packets) packets that are due at `time`, on the same clock as the time passed to
+    /// [`update`](Server::update). <br>
+    /// Returns the time the next packet is due, or `None` if no packets are queued (or packets aren't paced).
+    /// Call it between updates, sleeping until the returned time, or use [`run_pacer`](Server::run_pacer) to do both.
+    ///
+    /// # Example
+    /// ```
+    /// use netcode::{Server, ServerConfig};
+    ///
+    /// let tick_interval = 1.0 / 60.0;
+    /// let cfg = ServerConfig::default().pace_packets(tick_interval, 1024);
+    /// let mut server = Server::with_config("127.0.0.1:0", 0x11223344, netcode::generate_key(), cfg).unwrap();
+    /// let start = std::time::Instant::now();
+    /// server.update(start.elapsed().as_secs_f64());
+    /// // send this tick's snapshots, then spread them over the tick
+    /// while let Some(due) = server.pace(start.elapsed().as_secs_f64()).unwrap() {
+    ///     # break;
+    ///     // sleep until `due`
+    /// }
+    /// ```
+    pub fn pace(&mut self, time: f64) -> Result<Option<f64>> {
+        let Some(pacer) = self.pacer.as_mut() else {
+            return Ok(None);
+        };
+        while let Some((buf, addr)) = pacer.pop_due(time) {
+            self.send_backlog.send(&self.transceiver, &buf, addr)?;
+        }
+        Ok(pacer.next_release())
+    }
+    /// Blocks while it sends the [paced](ServerConfig::pace_packets) packets of the current tick, each on time with a precision of
+    /// microseconds (the OS sleeps until shortly before a packet is due and the thread spins for the rest). <br>
+    /// `time` is the current time on the clock passed to [`update`](Server::update). Returns once all packets are sent,
+    /// by the end of the tick unless more are sent in the meantime, e.g. from a [`ServerHandle`](crate::ServerHandle). <br>
+    /// The time passing is measured with the server's [`clock`](ServerConfig::clock), which has to advance on its own,
+    /// e.g. from another thread for a [`ManualClock`](crate::ManualClock).
+    pub fn run_pacer(&mut self, time: f64) -> Result<()> {
+        let start = self.cfg.clock.monotonic_time();
+        loop {
+            let now = time + self.cfg.clock.monotonic_time() - start;
+            let Some(due) = self.pace(now)? else {
+                return Ok(());
+            };
+            pacing::sleep_until(Instant::now() + Duration::from_secs_f64((due - now).max(0.0)));
+        }
+    }
+    /// Sends the payloads queued for all clients right away, instead of from the next [`update`](Server::update). <br>
+    /// Only needed if [payloads are coalesced](ServerConfig::coalesce_payloads) or a send returned
+    /// [`SendOutcome::QueuedWouldBlock`], otherwise packets are never queued.
+    pub fn flush(&mut self) -> Result<()> {
+        self.send_backlog.flush(&self.transceiver)?;
+        let queued: Vec<_> = self
+            .conn_cache
+            .send_queues
+            .iter_mut()
+            .filter_map(|(idx, queue)| Some((*idx, queue.take()?)))
+            .collect();
+        for (idx, payload) in queued {
+            self.send_payload_packet(&payload, idx)?;
+        }
+        Ok(())
+    }
+    // The largest payload before compression, acks and padding (if enabled) add their headers.
+    fn max_uncompressed_size(&self) -> usize {
+        let mut max_size = self.cfg.max_payload_size;
+        if self.cfg.ack_payloads {
+            max_size = max_size.saturating_sub(ack::HEADER_SIZE);
+        }
+        if !self.cfg.padding_buckets.is_empty() {
+            max_size = max_size.saturating_sub(padding::HEADER_SIZE);
+        }
+        #[cfg(feature = "compression")]
+        if self.cfg.compression_threshold.is_some() {
+            max_size = max_size.saturating_sub(compression::HEADER_SIZE);
+        }
+        max_size
+    }
+    // The largest payload that can be sent, less the message header if payloads are coalesced.
+    fn max_message_size(&self) -> usize {
+        if self.cfg.coalesce_payloads {
+            self.max_uncompressed_size()
+                .saturating_sub(MESSAGE_HEADER_SIZE)
+        } else {
+            self.max_uncompressed_size()
+        }
+    }
+    fn send_payload(&mut self, buf: &[u8], client_idx: ClientIndex) -> Result<SendOutcome> {
+        if buf.is_empty() && self.cfg.strict_netcode_1_02 {
+            return Err(crate::packet::Error::TooSmall.into());
+        }
+        if let Some(budget) = self.conn_cache.bandwidth_limits.get_mut(&client_idx) {
+            if !budget.take(buf.len(), self.time) {
+                log::trace!(
+                    "server dropped a payload over the bandwidth limit of client {client_idx}"
+                );
+                self.stats.bandwidth_limit_drops += 1;
+                return Ok(SendOutcome::DroppedOverBudget);
+            }
+        }
+        if self.cfg.coalesce_payloads {
+            let max_size = self.max_uncompressed_size();
+            let full = self
+                .conn_cache
+                .send_queues
+                .entry(client_idx)
+                .or_default()
+                .push(buf, max_size);
+            return match full {
+                Some(payload) => self.send_payload_packet(&payload, client_idx),
+                None => Ok(SendOutcome::Coalesced),
+            };
+        }
+        self.send_payload_packet(buf, client_idx)
+    }
+    fn send_keep_alive(&mut self, client_idx: ClientIndex) -> Result<()> {
+        let heartbeat = self
+            .conn_cache
+            .heartbeats
+            .remove(&client_idx)
+            .unwrap_or_default();
+        let mut stamped = Vec::new();
+        let attached = if self.is_tick_sync() {
+            let tick = self.precise_tick();
+            let conn = &mut self.conn_cache.clients[client_idx.0];
+            conn.last_stamp_time = self.time;
+            let stamp = ServerStamp {
+                tick,
+                client_time: conn.client_stamp,
+                hold: self.time - conn.client_stamp_time,
+            };
+            stamp.write(&heartbeat, &mut stamped);
+            &stamped
+        } else {
+            &heartbeat
+        };
+        let result = self.send_to_client(
+            KeepAlivePacket::with_heartbeat(client_idx.0 as i32, self.max_clients as i32, attached),
+            client_idx,
+        );
+        if !heartbeat.is_empty() {
+            self.conn_cache.heartbeats.insert(client_idx, heartbeat);
+        }
+        result.map(drop)
+    }
+    fn send_payload_packet(&mut self, buf: &[u8], client_idx: ClientIndex) -> Result<SendOutcome> {
+        if !self.conn_cache.clients[client_idx.0].is_confirmed() {
+            // send a keep-alive packet to the client to confirm the connection
+            self.send_keep_alive(client_idx)?;
+        }
+        #[cfg(feature = "compression")]
+        if let Some(threshold) = self.cfg.compression_threshold {
+            let payload = compression::encode(buf, threshold);
+            return self.send_acked_payload(&payload, client_idx);
+        }
+        self.send_acked_payload(buf, client_idx)
+    }
+    fn send_acked_payload(&mut self, buf: &[u8], client_idx: ClientIndex) -> Result<SendOutcome> {
+        if !self.cfg.ack_payloads {
+            return self.send_padded_payload(buf, client_idx);
+        }
+        let header = self
+            .conn_cache
+            .replay_protection
+            .get(&client_idx)
+            .map(ack::header)
+            .unwrap_or_else(|| ack::header(&ReplayProtection::new()));
+        let payload = [&header[..], buf].concat();
+        // the payload takes the sequence after a rekey
+        self.rekey_client(client_idx);
+        let sequence = self.conn_cache.clients[client_idx.0].sequence;
+        self.conn_cache
+            .acks
+            .entry(client_idx)
+            .or_default()
+            .sent(sequence, self.time);
+        self.send_padded_payload(&payload, client_idx)
+    }
+    fn send_padded_payload(&mut self, buf: &[u8], client_idx: ClientIndex) -> Result<SendOutcome> {
+        if self.cfg.padding_buckets.is_empty() {
+            return self.send_to_client(PayloadPacket::create(buf), client_idx);
+        }
+        let payload = padding::pad(buf, &self.cfg.padding_buckets, self.cfg.max_payload_size);
+        self.send_to_client(PayloadPacket::create(&payload), client_idx)
+    }
     /// Creates a connect token builder for a given client ID.
     /// The builder can be used to configure the token with additional data before generating the final token.
     /// The `generate` method must be called on the builder to generate the final token.
     ///
+    /// The token lists the address the server is bound to. A server bound to an unspecified address
+    /// (e.g. a [dual-stack](crate::NetcodeSocket::dual_stack) server) accepts tokens listing its
+    /// [public addresses](ServerConfig::public_addrs), so its tokens should be built with [`ConnectToken::build`](ConnectToken::build)
+    /// to list those instead.
+    ///
     /// # Example
     ///
     /// ```
@@ -876,17 +3285,183 @@ impl<T: Transceiver, S> Server<T, S> {
     ///     .unwrap();
     /// ```
     ///
+    /// The token is created at the current time of the server's [`clock`](ServerConfig::clock).
     /// See [`ConnectTokenBuilder`](ConnectTokenBuilder) for more options.
     pub fn token(&mut self, client_id: ClientId) -> ConnectTokenBuilder<SocketAddr> {
         let token_builder = ConnectToken::build(
             self.transceiver.addr(),
             self.protocol_id,
             client_id,
-            self.private_key,
-        );
+            *self.private_key,
+        )
+        .strict_netcode_1_02(self.cfg.strict_netcode_1_02)
+        .created_at(self.cfg.clock.unix_time());
         self.token_sequence += 1;
         token_builder
     }
+    /// Replaces the private key with the one `provider` fetches, e.g. after it was rotated in a secrets manager. <br>
+    /// Connect tokens issued with the previous key are still accepted until the next rotation, so they should expire by then.
+    /// Clients that are already connected aren't affected, their session keys are separate.
+    ///
+    /// If the provider fails, the server keeps its current key and the error is returned.
+    /// See [`KeyProvider`](crate::KeyProvider) for an example.
+    pub fn rotate_private_key(&mut self, provider: &mut impl KeyProvider) -> Result<()> {
+        let key = Zeroizing::new(provider.private_key()?);
+        if key != self.private_key {
+            self.previous_private_key = Some(std::mem::replace(&mut self.private_key, key));
+            log::debug!("server rotated the private key");
+        }
+        Ok(())
+    }
+    /// Disconnects the clients connected with `client_id` and denies its connection requests from now on,
+    /// e.g. from the [admin console](Server::admin_command). Lift the ban with [`unban`](Server::unban). <br>
+    /// Bans are kept until the server is dropped, check a persistent ban list in [`ServerConfig::connect_filter`] instead.
+    pub fn ban(&mut self, client_id: ClientId) -> Result<()> {
+        self.banned.insert(client_id);
+        while let Some(idx) = self.conn_cache.find_connected_by_id(client_id) {
+            log::debug!("server disconnecting banned client {client_id}");
+            self.disconnect(idx)?;
+        }
+        Ok(())
+    }
+    /// Lets a [banned](Server::ban) client id connect again, returns `false` if it wasn't banned.
+    pub fn unban(&mut self, client_id: ClientId) -> bool {
+        self.banned.remove(&client_id)
+    }
+    /// Pauses or resumes the [packet logger](ServerConfig::packet_logger), e.g. to capture packets only while a bug is being
+    /// reproduced. The logger runs from the start.
+    pub fn set_packet_logging(&mut self, enabled: bool) {
+        self.packet_logging = enabled;
+    }
+    /// Runs a command of the admin console and returns its answer: the output of the command (if any)
+    /// and a last line of `ok` or `error: <reason>`. <br>
+    /// Commands are read from the [admin socket](ServerConfig::admin_socket) if one is configured,
+    /// call this to take them from another control plane (e.g. the server's stdin or an authenticated RPC).
+    ///
+    /// | Command | |
+    /// |---|---|
+    /// | `help` | Lists the commands. |
+    /// | `clients` | Lists the connected clients: the client index, id and address, the protocol id, the seconds since the last packet, the round-trip time and packet loss if [payloads are acked](ServerConfig::ack_payloads), and the bandwidth limit if one is set. |
+    /// | `stats` | Prints the [`ServerStats`]. |
+    /// | `kick <client index>` | [Disconnects](Server::disconnect) a client. |
+    /// | `ban <client id>` | [Bans](Server::ban) a client id. |
+    /// | `unban <client id>` | [Lifts](Server::unban) a ban. |
+    /// | `pace <packets per tick>` | Sets the budget of [paced packets](ServerConfig::pace_packets) per tick. |
+    /// | `limit <client index> <bytes per second>\|off` | [Limits](Server::set_client_bandwidth_limit) the payload bytes sent to a client, or lifts the limit. |
+    /// | `rotate-keys` | Replaces the key challenge tokens are encrypted with, pending connections have to start over. |
+    /// | `log on\|off` | [Resumes or pauses](Server::set_packet_logging) the packet logger. |
+    ///
+    /// # Example
+    /// ```
+    /// use netcode::Server;
+    ///
+    /// let mut server = Server::new("127.0.0.1:0", 0x11223344, netcode::generate_key()).unwrap();
+    /// assert_eq!(server.admin_command("kick 3"), "error: no client is connected at index 3\n");
+    /// assert_eq!(server.admin_command("ban 42"), "ok\n");
+    /// ```
+    pub fn admin_command(&mut self, line: &str) -> String {
+        match AdminCommand::parse(line).and_then(|command| self.run_admin_command(command)) {
+            Ok(output) => format!("{output}ok\n"),
+            Err(reason) => format!("error: {reason}\n"),
+        }
+    }
+    fn run_admin_command(&mut self, command: AdminCommand) -> std::result::Result<String, String> {
+        use std::fmt::Write;
+
+        let mut output = String::new();
+        match command {
+            AdminCommand::Help => output.push_str(admin::HELP),
+            AdminCommand::Clients => {
+                for (idx, conn) in self.conn_cache.clients.iter() {
+                    if !conn.is_connected() {
+                        continue;
+                    }
+                    write!(
+                        output,
+                        "{idx} id={} addr={} protocol={:#x} idle={:.1}s",
+                        conn.client_id,
+                        conn.addr,
+                        conn.protocol_id,
+                        self.time - conn.last_receive_time
+                    )
+                    .ok();
+                    if let Some(acks) = self.conn_cache.acks.get(&ClientIndex(idx)) {
+                        if let Some(rtt) = acks.rtt() {
+                            write!(output, " rtt={:.1}ms", rtt * 1000.0).ok();
+                        }
+                        write!(output, " loss={:.1}%", acks.packet_loss() * 100.0).ok();
+                    }
+                    if let Some(limit) = self.client_bandwidth_limit(ClientIndex(idx)) {
+                        write!(output, " limit={limit}B/s").ok();
+                    }
+                    output.push('\n');
+                }
+            }
+            AdminCommand::Stats => writeln!(output, "{:#?}", self.stats()).unwrap_or_default(),
+            AdminCommand::Kick(idx) => {
+                let is_connected =
+                    (self.conn_cache.clients.get(idx)).is_some_and(|conn| conn.is_connected());
+                if !is_connected {
+                    return Err(format!("no client is connected at index {idx}"));
+                }
+                self.disconnect(ClientIndex(idx))
+                    .map_err(|e| e.to_string())?;
+            }
+            AdminCommand::Ban(client_id) => self.ban(client_id).map_err(|e| e.to_string())?,
+            AdminCommand::Unban(client_id) => {
+                if !self.unban(client_id) {
+                    return Err(format!("client id {client_id} isn't banned"));
+                }
+            }
+            AdminCommand::Pace(max_packets) => {
+                let Some(pacer) = self.pacer.as_mut() else {
+                    return Err("packets aren't paced".to_string());
+                };
+                pacer.set_max_packets_per_tick(max_packets.max(1));
+            }
+            AdminCommand::Limit(idx, bytes_per_second) => {
+                let is_connected =
+                    (self.conn_cache.clients.get(idx)).is_some_and(|conn| conn.is_connected());
+                if !is_connected {
+                    return Err(format!("no client is connected at index {idx}"));
+                }
+                self.set_client_bandwidth_limit(ClientIndex(idx), bytes_per_second);
+            }
+            AdminCommand::RotateKeys => self.rotate_challenge_key().map_err(|e| e.to_string())?,
+            AdminCommand::Log(enabled) => {
+                if self.cfg.packet_logger.is_none() {
+                    return Err("no packet logger is set".to_string());
+                }
+                self.set_packet_logging(enabled);
+            }
+        }
+        log::info!("server ran admin command {command:?}");
+        Ok(output)
+    }
+    // Answers the commands received on the admin socket, see `ServerConfig::admin_socket`.
+    #[cfg(unix)]
+    fn run_admin_socket(&mut self) {
+        let Some(lines) = self.admin.as_mut().map(AdminSocket::poll) else {
+            return;
+        };
+        for (conn, line) in lines {
+            let answer = self.admin_command(&line);
+            if let Some(admin) = self.admin.as_mut() {
+                admin.respond(conn, &answer);
+            }
+        }
+    }
+    /// Gets a cloneable [`ServerHandle`](ServerHandle) that other threads can queue sends and disconnects with,
+    /// which run on the next [`update`](Server::update). All handles share the same queue.
+    pub fn handle(&mut self) -> ServerHandle {
+        if self.commands.is_none() {
+            let (handle, rx, ids) = ServerHandle::channel();
+            self.commands = Some((handle, Mutex::new(rx), ids));
+            self.sync_handle();
+        }
+        let (handle, _, _) = self.commands.as_ref().expect("created above");
+        handle.clone()
+    }
     /// Disconnects a client.
     ///
     /// The server will send a number of redundant disconnect packets to the client, and then remove its connection info.
@@ -897,18 +3472,30 @@ impl<T: Transceiver, S> Server<T, S> {
         if !conn.is_connected() {
             return Ok(());
         }
-        log::debug!("server disconnecting client {client_idx}");
-        for _ in 0..self.cfg.num_disconnect_packets {
-            self.send_to_client(DisconnectPacket::create(), client_idx)?;
+        if let Some(payload) = self
+            .conn_cache
+            .send_queues
+            .get_mut(&client_idx)
+            .and_then(SendQueue::take)
+        {
+            self.send_payload_packet(&payload, client_idx)?;
         }
+        log::debug!("server disconnecting client {client_idx}");
+        trace::event!(
+            INFO,
+            client_index = client_idx.0,
+            "server disconnecting client"
+        );
+        self.send_disconnect_packets(client_idx)?;
         self.on_disconnect(client_idx);
         self.conn_cache.remove(client_idx);
+        self.sync_cluster();
         Ok(())
     }
     /// Disconnects all clients.
     pub fn disconnect_all(&mut self) -> Result<()> {
         log::debug!("server disconnecting all clients");
-        for idx in 0..MAX_CLIENTS {
+        for idx in 0..self.conn_cache.clients.slots() {
             let Some(conn) = self.conn_cache.clients.get_mut(idx) else {
                 continue;
             };
@@ -918,6 +3505,185 @@ impl<T: Transceiver, S> Server<T, S> {
         }
         Ok(())
     }
+    /// Begins shutting the server down, without blocking: all connected clients are disconnected
+    /// (each is sent the configured number of redundant disconnect packets), and new connections are ignored.
+    ///
+    /// The server keeps being updated as usual to drain the clients, until they all confirmed the disconnect
+    /// or `drain_seconds` of server time have passed, see [`shutdown_report`](Server::shutdown_report).
+    /// Calling it again while the server is shutting down does nothing.
+    ///
+    /// Use [`shutdown`](Server::shutdown) to do all of this in one blocking call.
+    pub fn begin_shutdown(&mut self, drain_seconds: f64) -> Result<()> {
+        if self.shutdown.is_some() {
+            return Ok(());
+        }
+        log::info!("server shutting down");
+        let mut draining = HashMap::new();
+        for idx in 0..self.conn_cache.clients.slots() {
+            let Some(conn) = self.conn_cache.clients.get(idx) else {
+                continue;
+            };
+            if !conn.is_connected() {
+                continue;
+            }
+            let idx = ClientIndex(idx);
+            let replay_protection = self
+                .conn_cache
+                .replay_protection
+                .get(&idx)
+                .cloned()
+                .unwrap_or_else(ReplayProtection::new);
+            draining.insert(
+                conn.addr,
+                DrainingClient {
+                    receive: Cipher::with_rekeying(*conn.receive_key, self.cfg.rekey_sessions),
+                    protocol_id: conn.protocol_id,
+                    replay_protection,
+                },
+            );
+            self.disconnect(idx)?;
+        }
+        self.shutdown = Some(Shutdown {
+            deadline: self.time + drain_seconds,
+            draining,
+            acknowledged: 0,
+        });
+        Ok(())
+    }
+    /// Returns true if the server began shutting down, see [`begin_shutdown`](Server::begin_shutdown).
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown.is_some()
+    }
+    /// Gets how many clients acknowledged the shutdown and how many didn't,
+    /// once every client acknowledged it or the drain period ended. <br>
+    /// Returns `None` if the server isn't shutting down, or is still draining.
+    pub fn shutdown_report(&self) -> Option<ShutdownReport> {
+        self.shutdown
+            .as_ref()
+            .filter(|shutdown| shutdown.is_done(self.time))
+            .map(Shutdown::report)
+    }
+    /// Shuts the server down gracefully and closes its socket.
+    ///
+    /// Blocks while it disconnects all clients (see [`begin_shutdown`](Server::begin_shutdown)) and drains them for at most `drain`,
+    /// updating the server with its [`clock`](ServerConfig::clock) in the meantime, so clients don't have to wait for their full timeout.
+    /// The clock has to advance on its own, e.g. from another thread for a [`ManualClock`](crate::ManualClock).
+    ///
+    /// Returns how many clients acknowledged the disconnect and how many timed out.
+    /// Acknowledging is an extension of the protocol: only clients of this crate answer the disconnect packets,
+    /// clients of other implementations (and [strict](crate::ClientConfig::strict_netcode_1_02) clients) always count as timed out.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::Duration;
+    /// use netcode::Server;
+    ///
+    /// let server = Server::new("127.0.0.1:0", 0x11223344, netcode::generate_key()).unwrap();
+    /// let report = server.shutdown(Duration::from_millis(100)).unwrap();
+    /// assert_eq!(report.acknowledged + report.timed_out, 0);
+    /// ```
+    pub fn shutdown(mut self, drain: Duration) -> Result<ShutdownReport> {
+        let start = self.cfg.clock.monotonic_time();
+        let start_time = self.time;
+        self.begin_shutdown(drain.as_secs_f64())?;
+        loop {
+            let elapsed = self.cfg.clock.monotonic_time() - start;
+            self.try_update(start_time + elapsed)?;
+            if let Some(report) = self.shutdown_report() {
+                log::info!(
+                    "server shut down, {} clients acknowledged, {} timed out",
+                    report.acknowledged,
+                    report.timed_out
+                );
+                return Ok(report);
+            }
+            std::thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+    }
+    /// Sets the maximum number of clients that can be connected at the same time.
+    ///
+    /// Can be called at any time: raising the limit allows new connections right away,
+    /// while lowering it below the number of connected clients preserves the existing sessions
+    /// and denies new connections until enough clients have left. <br>
+    /// A limit of `0` denies all new connections (e.g. for draining a server before shutting it down).
+    ///
+    /// Connection slots are allocated as clients connect, so the limit can be raised past [`MAX_CLIENTS`](MAX_CLIENTS), the default.
+    /// Returns [`Error::MaxClients`](Error::MaxClients) and keeps the current limit if `max_clients` doesn't fit the `i32` of keep-alive packets.
+    /// The initial limit is set with [`ServerConfig::max_clients`](ServerConfig::max_clients).
+    ///
+    /// # Example
+    /// ```
+    /// # use netcode::Server;
+    /// let mut server = Server::new("127.0.0.1:0", 0x11223344, netcode::generate_key()).unwrap();
+    /// assert_eq!(server.max_clients(), netcode::MAX_CLIENTS);
+    ///
+    /// server.set_max_clients(64).unwrap();
+    /// assert_eq!(server.max_clients(), 64);
+    /// server.set_max_clients(4 * netcode::MAX_CLIENTS).unwrap();
+    /// assert_eq!(server.max_clients(), 4 * netcode::MAX_CLIENTS);
+    /// assert!(server.set_max_clients(usize::MAX).is_err());
+    /// ```
+    pub fn set_max_clients(&mut self, max_clients: usize) -> Result<()> {
+        if max_clients > MAX_CLIENTS_LIMIT {
+            return Err(Error::MaxClients(max_clients));
+        }
+        log::info!(
+            "server max clients changed from {} to {max_clients}",
+            self.max_clients
+        );
+        self.max_clients = max_clients;
+        Ok(())
+    }
+    /// Gets the maximum number of clients that can be connected at the same time.
+    pub fn max_clients(&self) -> usize {
+        self.max_clients
+    }
+    /// Gets the server's tick, which advances at the rate set with [`ServerConfig::tick_sync`](ServerConfig::tick_sync)
+    /// from the time passed to [`update`](Server::update), starting at 0. <br>
+    /// Clients estimate it with [`Client::estimated_server_tick`](crate::Client::estimated_server_tick). Without tick sync it stays put.
+    pub fn tick(&self) -> u64 {
+        self.precise_tick().max(0.0) as u64
+    }
+    /// Sets the server's tick as of the last update, e.g. to match the tick of the game simulation, see [`tick`](Server::tick). <br>
+    /// Clients follow the change smoothly unless it is larger than a quarter of a second.
+    ///
+    /// # Example
+    /// ```
+    /// # let cfg = netcode::ServerConfig::default().tick_sync(60.0);
+    /// # let mut server = netcode::Server::with_config("127.0.0.1:0", 0, [0; 32], cfg).unwrap();
+    /// server.update(1.0);
+    /// server.set_tick(1000);
+    /// server.update(1.5);
+    /// assert_eq!(server.tick(), 1030);
+    /// ```
+    pub fn set_tick(&mut self, tick: u64) {
+        self.tick_anchor = (tick, self.time);
+    }
+    /// Gets the statistics collected by the server since it was created.
+    pub fn stats(&self) -> ServerStats {
+        ServerStats {
+            pending_connections: self.conn_cache.num_pending(),
+            packets_would_block: self.send_backlog.queued,
+            would_block_drops: self.send_backlog.dropped,
+            ..self.stats
+        }
+    }
+    /// Gets the protocol ids accepted by the server, starting with its own,
+    /// followed by the ones added with [`ServerConfig::accept_protocol_id`](ServerConfig::accept_protocol_id).
+    pub fn protocol_ids(&self) -> impl Iterator<Item = u64> + '_ {
+        std::iter::once(self.protocol_id).chain(self.cfg.protocol_ids.iter().copied())
+    }
+    /// Gets the statistics for one of the protocol ids accepted by the server, or `None` if the server doesn't accept it.
+    pub fn protocol_stats(&self, protocol_id: u64) -> Option<ProtocolStats> {
+        let mut stats = *self.protocol_stats.get(&protocol_id)?;
+        stats.connected_clients = self
+            .conn_cache
+            .clients
+            .iter()
+            .filter(|(_, c)| c.is_connected() && c.protocol_id == protocol_id)
+            .count();
+        Some(stats)
+    }
     /// Gets the local `SocketAddr` this server is bound to.
     pub fn addr(&self) -> SocketAddr {
         self.transceiver.addr()
@@ -930,6 +3696,59 @@ impl<T: Transceiver, S> Server<T, S> {
             .filter(|(_, c)| c.is_connected())
             .count()
     }
+    /// Gets the number of connected clients, the same as [`num_connected_clients`](Server::num_connected_clients).
+    pub fn connected_count(&self) -> usize {
+        self.num_connected_clients()
+    }
+    /// Gets the number of clients that can still connect or be [reserved a slot](Server::reserve_slot):
+    /// the [max clients](Server::max_clients) minus the connected clients and the reserved slots.
+    pub fn capacity(&self) -> usize {
+        self.max_clients
+            .saturating_sub(self.num_connected_clients() + self.num_reserved_slots())
+    }
+    /// Reserves a slot for a client for `ttl_seconds` (in server time, see [`update`](Server::update)),
+    /// e.g. when a matchmaker issues the client its connect token. <br>
+    /// Other clients are denied as if the server was full while only reserved slots are left, so a matchmaker that reserves
+    /// a slot for each player it sends to the server never sends more players than fit.
+    /// The reservation is released when the client connects, or when it expires if the client never completes the handshake.
+    ///
+    /// Reserving a slot again for the same client extends the reservation. Nothing is reserved for a client that is already connected.
+    ///
+    /// Returns [`Error::ServerFull`](Error::ServerFull) if there is no slot left, see [`capacity`](Server::capacity).
+    ///
+    /// # Example
+    /// ```
+    /// # use netcode::Server;
+    /// let mut server = Server::new("127.0.0.1:0", 0x11223344, netcode::generate_key()).unwrap();
+    /// server.set_max_clients(2).unwrap();
+    ///
+    /// server.reserve_slot(1, 30.0).unwrap();
+    /// server.reserve_slot(2, 30.0).unwrap();
+    /// assert_eq!(server.capacity(), 0);
+    /// assert!(server.reserve_slot(3, 30.0).is_err());
+    /// // the token sent to each player along with the reservation
+    /// let token = server.token(1).expire_seconds(30).generate().unwrap();
+    /// ```
+    pub fn reserve_slot(&mut self, client_id: ClientId, ttl_seconds: f64) -> Result<()> {
+        if self
+            .conn_cache
+            .find_by_id(client_id)
+            .is_some_and(|(_, conn)| conn.is_connected())
+        {
+            return Ok(());
+        }
+        if self.is_full(client_id) {
+            return Err(Error::ServerFull);
+        }
+        log::debug!("server reserved a slot for client id {client_id} for {ttl_seconds} seconds");
+        self.reservations.insert(client_id, self.time + ttl_seconds);
+        Ok(())
+    }
+    /// Gets the number of clients that were sent a challenge, but haven't answered it yet,
+    /// see [`ServerConfig::max_pending_connections`](ServerConfig::max_pending_connections).
+    pub fn num_pending_connections(&self) -> usize {
+        self.conn_cache.num_pending()
+    }
     /// Gets the [`ClientId`](ClientId) of a client.
     pub fn client_id(&self, client_idx: ClientIndex) -> Option<ClientId> {
         self.conn_cache
@@ -937,6 +3756,181 @@ impl<T: Transceiver, S> Server<T, S> {
             .get(client_idx.0)
             .map(|c| c.client_id)
     }
+    /// Gets the expire timestamp (seconds since the unix epoch) of the connect token a client connected with,
+    /// or `u64::MAX` if the token never expires.
+    ///
+    /// Returns `None` if the client is not connected.
+    pub fn client_token_expiry(&self, client_idx: ClientIndex) -> Option<u64> {
+        self.conn_cache
+            .clients
+            .get(client_idx.0)
+            .filter(|c| c.is_connected())
+            .map(|c| c.expire_timestamp)
+    }
+    /// Gets the protocol id a client connected with, see [`ServerConfig::accept_protocol_id`](ServerConfig::accept_protocol_id).
+    ///
+    /// Returns `None` if the client is not connected.
+    pub fn client_protocol_id(&self, client_idx: ClientIndex) -> Option<u64> {
+        self.conn_cache
+            .clients
+            .get(client_idx.0)
+            .filter(|c| c.is_connected())
+            .map(|c| c.protocol_id)
+    }
+    /// Lowers the max payload size of a connected client, e.g. for a client on a path known to fragment large datagrams.
+    ///
+    /// The client rejects larger payloads in [`Client::send`](crate::Client::send) and reports the limit from
+    /// [`Client::max_payload_size`](crate::Client::max_payload_size). It can never raise the client above its own configured
+    /// [max payload size](crate::ClientConfig::max_payload_size), so passing [`MAX_PACKET_SIZE`](crate::MAX_PACKET_SIZE) lifts a previous limit. <br>
+    /// The limit is sent right away and repeated with the next keep-alives in case it is lost, and only lasts for the current connection.
+    /// This is an extension of the netcode protocol: other implementations, or clients in
+    /// [strict netcode 1.02](crate::ClientConfig::strict_netcode_1_02) mode, ignore it.
+    ///
+    /// `max_payload_size` is clamped to `1..=MAX_PACKET_SIZE`. Does nothing if the client is not connected.
+    pub fn set_client_max_payload_size(
+        &mut self,
+        client_idx: ClientIndex,
+        max_payload_size: usize,
+    ) -> Result<()> {
+        let Some(conn) = self.conn_cache.clients.get_mut(client_idx.0) else {
+            return Ok(());
+        };
+        if !conn.is_connected() {
+            return Ok(());
+        }
+        let max_payload_size = max_payload_size.clamp(1, MAX_PACKET_SIZE) as u16;
+        conn.max_payload_size = Some(max_payload_size);
+        conn.payload_limit_resends = NUM_PAYLOAD_LIMIT_PACKETS;
+        log::debug!("server limiting client {client_idx} to payloads of {max_payload_size} bytes");
+        self.send_to_client(PayloadLimitPacket::create(max_payload_size), client_idx)?;
+        Ok(())
+    }
+    /// Hands a connected client over to another server, e.g. when a player crosses into a zone run by another process.
+    ///
+    /// `token` is a connect token for the other server, generated with its private key for the client's id, and the client
+    /// uses it to connect there (see [`Client::take_transfer_token`](crate::Client::take_transfer_token)) without a round trip
+    /// to the matchmaker. Keep its expiry short, e.g. a few seconds, since the client connects right away. <br>
+    /// The token is sent over the encrypted session right away and repeated with the next keep-alives in case it is lost.
+    /// The client stays connected until it moves, and its disconnect frees the slot like any other.
+    /// This is an extension of the netcode protocol: other implementations, or clients in
+    /// [strict netcode 1.02](crate::ClientConfig::strict_netcode_1_02) mode, ignore it.
+    ///
+    /// Does nothing if the client is not connected.
+    ///
+    /// # Example
+    /// ```
+    /// use netcode::{ConnectToken, Server};
+    ///
+    /// let protocol_id = 0x11223344;
+    /// let mut server = Server::new("127.0.0.1:0", protocol_id, netcode::generate_key()).unwrap();
+    /// // the address and private key of the server running the zone clients move to
+    /// let (zone_addr, zone_key) = ("127.0.0.1:40010", netcode::generate_key());
+    ///
+    /// server.update(0.0);
+    /// while let Some((payload, client_idx)) = server.recv() {
+    ///     if payload != b"enter zone" {
+    ///         continue;
+    ///     }
+    ///     let Some(client_id) = server.client_id(client_idx) else {
+    ///         continue;
+    ///     };
+    ///     let token = ConnectToken::build(zone_addr, protocol_id, client_id, zone_key)
+    ///         .expire_seconds(10)
+    ///         .generate()
+    ///         .unwrap();
+    ///     server.transfer_client(client_idx, token).unwrap();
+    /// }
+    /// ```
+    pub fn transfer_client(&mut self, client_idx: ClientIndex, token: ConnectToken) -> Result<()> {
+        if !self
+            .conn_cache
+            .clients
+            .get(client_idx.0)
+            .is_some_and(|conn| conn.is_connected())
+        {
+            return Ok(());
+        }
+        let server = token.server_addresses().next();
+        let transfer = Transfer {
+            token: Box::new(token.try_into_bytes()?),
+            sends_left: NUM_TRANSFER_PACKETS,
+        };
+        log::debug!("server transferring client {client_idx} to {server:?}");
+        self.conn_cache.transfers.insert(client_idx, transfer);
+        self.send_transfer(client_idx)
+    }
+    /// Gets the max payload size set for a client with [`set_client_max_payload_size`](Server::set_client_max_payload_size).
+    ///
+    /// Returns `None` if no limit was set or the client is not connected.
+    pub fn client_max_payload_size(&self, client_idx: ClientIndex) -> Option<usize> {
+        self.conn_cache
+            .clients
+            .get(client_idx.0)
+            .filter(|c| c.is_connected())
+            .and_then(|c| c.max_payload_size)
+            .map(usize::from)
+    }
+    /// Limits the payload bytes per second sent to a connected client, e.g. to keep one client from taking the server's uplink,
+    /// or lifts the limit with `None`.
+    ///
+    /// Payloads over the limit are dropped with [`SendOutcome::DroppedOverBudget`] and counted in
+    /// [`ServerStats::bandwidth_limit_drops`]. Up to a second's worth can be sent in a burst.
+    /// Keep-alives and the other packets of the protocol don't count. <br>
+    /// The limit only lasts for the current connection. Does nothing if the client is not connected.
+    pub fn set_client_bandwidth_limit(
+        &mut self,
+        client_idx: ClientIndex,
+        bytes_per_second: Option<usize>,
+    ) {
+        let is_connected =
+            (self.conn_cache.clients.get(client_idx.0)).is_some_and(|conn| conn.is_connected());
+        if !is_connected {
+            return;
+        }
+        match bytes_per_second {
+            Some(bytes_per_second) => {
+                log::debug!(
+                    "server limiting client {client_idx} to {bytes_per_second} bytes per second"
+                );
+                self.conn_cache
+                    .bandwidth_limits
+                    .insert(client_idx, ByteBudget::new(bytes_per_second, self.time));
+            }
+            None => {
+                self.conn_cache.bandwidth_limits.remove(&client_idx);
+            }
+        }
+    }
+    /// Gets the bandwidth limit set for a client with [`set_client_bandwidth_limit`](Server::set_client_bandwidth_limit),
+    /// in payload bytes per second.
+    ///
+    /// Returns `None` if no limit was set or the client is not connected.
+    pub fn client_bandwidth_limit(&self, client_idx: ClientIndex) -> Option<usize> {
+        self.conn_cache
+            .bandwidth_limits
+            .get(&client_idx)
+            .map(ByteBudget::bytes_per_second)
+    }
+    /// Takes the sequences of the payload packets a client acknowledged since the last call, oldest first. <br>
+    /// Only payloads sent while [`ServerConfig::ack_payloads`](ServerConfig::ack_payloads) is enabled are acked,
+    /// get the sequence of a payload with [`last_payload_sequence`](Server::last_payload_sequence) after sending it.
+    /// Payloads that aren't acked shortly after newer ones are, were lost (or are still in flight out of order).
+    pub fn acks(&mut self, client_idx: ClientIndex) -> impl Iterator<Item = u64> + '_ {
+        self.conn_cache
+            .acks
+            .get_mut(&client_idx)
+            .into_iter()
+            .flat_map(AckTracker::drain)
+    }
+    /// Gets the sequence of the last payload packet sent to a client while [`ServerConfig::ack_payloads`](ServerConfig::ack_payloads)
+    /// is enabled, to match it with the sequences from [`acks`](Server::acks). <br>
+    /// If [payloads are coalesced](ServerConfig::coalesce_payloads), payloads get their sequence when the queue is sent.
+    pub fn last_payload_sequence(&self, client_idx: ClientIndex) -> Option<u64> {
+        self.conn_cache
+            .acks
+            .get(&client_idx)
+            .and_then(AckTracker::last_sent)
+    }
     /// Gets the address of a client.
     pub fn client_addr(&self, client_idx: ClientIndex) -> Option<SocketAddr> {
         self.conn_cache.clients.get(client_idx.0).map(|c| c.addr)
@@ -964,5 +3958,18 @@ pub mod tests {
                 .filter(|(_, c)| c.is_connected())
                 .map(|(idx, _)| ClientIndex(idx))
         }
+        pub(crate) fn num_client_slots(&self) -> usize {
+            self.conn_cache.clients.slots()
+        }
+        pub(crate) fn set_challenge_sequence(&mut self, sequence: u64) {
+            self.challenge_sequence = sequence;
+        }
+        pub(crate) fn challenge_sequence(&self) -> u64 {
+            self.challenge_sequence
+        }
+        // as if another client took over the slot
+        pub(crate) fn set_client_id(&mut self, client_idx: ClientIndex, client_id: ClientId) {
+            self.conn_cache.clients[client_idx.0].client_id = client_id;
+        }
     }
 }
@@ -0,0 +1,140 @@
+use std::net::SocketAddr;
+
+use crate::{crypto::Key, server::ClientId, token::ConnectTokenBuilder, ConnectToken};
+
+/// Maps clients to the shards of a thread-per-core deployment.
+///
+/// In a thread-per-core setup (e.g. with `glommio` or `monoio`, or plain pinned threads) every core owns
+/// its own [`Server`](crate::Server) bound to its own socket, and nothing is shared between cores.
+/// The shard map decides which shard a client belongs to with a consistent hash of its [`ClientId`](ClientId),
+/// and the tokens it builds only contain that shard's address, so the client can only ever connect to its own shard.
+///
+/// Growing the map from `n` to `n + 1` shards only moves about `1 / (n + 1)` of the clients, all of them to the new shard.
+/// For shards sharing one address instead, see [`ServerCluster`](crate::ServerCluster).
+///
+/// # Example
+/// ```
+/// use std::net::SocketAddr;
+/// use netcode::ShardMap;
+///
+/// let addrs = (0..4).map(|i| SocketAddr::from(([127, 0, 0, 1], 40000 + i)));
+/// let shards = ShardMap::new(addrs);
+///
+/// let private_key = netcode::generate_key();
+/// let client_id = 123u64;
+/// let token = shards
+///     .token(client_id, 0x11223344, private_key)
+///     .expire_seconds(60)
+///     .generate()
+///     .unwrap();
+///
+/// // the shard at `shards.addr(shards.shard(client_id))` will accept this token
+/// ```
+#[derive(Debug, Clone)]
+pub struct ShardMap {
+    addrs: Vec<SocketAddr>,
+}
+
+impl ShardMap {
+    /// Create a shard map from the public addresses of the shards, in shard order.
+    ///
+    /// # Panics
+    /// Panics if `addrs` is empty.
+    pub fn new(addrs: impl IntoIterator<Item = SocketAddr>) -> Self {
+        let addrs: Vec<_> = addrs.into_iter().collect();
+        assert!(!addrs.is_empty(), "a shard map needs at least one shard");
+        Self { addrs }
+    }
+    /// Gets the number of shards.
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+    /// Returns true if the map has no shards, which can't happen since [`new`](ShardMap::new) requires at least one.
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+    /// Gets the shard a client belongs to.
+    pub fn shard(&self, client_id: ClientId) -> usize {
+        jump_hash(client_id, self.addrs.len() as u32) as usize
+    }
+    /// Gets the public address of a shard.
+    ///
+    /// # Panics
+    /// Panics if `shard` is out of range.
+    pub fn addr(&self, shard: usize) -> SocketAddr {
+        self.addrs[shard]
+    }
+    /// Creates a connect token builder for a client, directing it to the shard it belongs to.
+    pub fn token(
+        &self,
+        client_id: ClientId,
+        protocol_id: u64,
+        private_key: Key,
+    ) -> ConnectTokenBuilder<SocketAddr> {
+        let addr = self.addr(self.shard(client_id));
+        ConnectToken::build(addr, protocol_id, client_id, private_key)
+    }
+}
+
+/// Jump consistent hash (Lamping & Veach), with the key pre-mixed so sequential client ids spread evenly.
+fn jump_hash(key: u64, num_buckets: u32) -> u32 {
+    // splitmix64 finalizer
+    let mut key = key.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    key = (key ^ (key >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    key = (key ^ (key >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    key ^= key >> 31;
+
+    let (mut bucket, mut next) = (-1i64, 0i64);
+    while next < num_buckets as i64 {
+        bucket = next;
+        key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    bucket as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jump_hash_is_balanced_and_consistent() {
+        let num_keys = 10_000u64;
+        let mut counts = [0usize; 4];
+        for key in 0..num_keys {
+            counts[jump_hash(key, 4) as usize] += 1;
+        }
+        assert!(
+            counts.iter().all(|&c| (2000..3000).contains(&c)),
+            "{counts:?}"
+        );
+
+        // going from 4 to 5 buckets only moves keys to the new bucket
+        let mut moved = 0;
+        for key in 0..num_keys {
+            let (before, after) = (jump_hash(key, 4), jump_hash(key, 5));
+            if before != after {
+                assert_eq!(after, 4);
+                moved += 1;
+            }
+        }
+        assert!((1500..2500).contains(&moved), "{moved}");
+    }
+
+    #[test]
+    fn tokens_point_to_the_clients_shard() {
+        let addrs: Vec<_> = (0..3)
+            .map(|i| SocketAddr::from(([127, 0, 0, 1], 40000 + i)))
+            .collect();
+        let shards = ShardMap::new(addrs.clone());
+        for client_id in 0..10 {
+            let token = shards
+                .token(client_id, 0, crate::generate_key())
+                .generate()
+                .unwrap();
+            let shard = shards.shard(client_id);
+            assert_eq!(token.server_addresses.len(), 1);
+            assert_eq!(token.server_addresses[0], addrs[shard]);
+        }
+    }
+}
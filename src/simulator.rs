@@ -1,8 +1,8 @@
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     collections::HashMap,
-    io::{self, ErrorKind},
-    net::{Ipv4Addr, SocketAddr},
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     rc::Rc,
     sync::mpsc::{self, Receiver, Sender},
 };
@@ -28,6 +28,8 @@ pub struct SimulationConfig {
     pub jitter_ms: f64,
     pub packet_loss_percent: f64,
     pub duplicate_packet_percent: f64,
+    /// Report packets sent to a port nobody listens on as an ICMP port unreachable on the next `recv`, like Windows does.
+    pub port_unreachable: bool,
 }
 
 impl Default for SimulationConfig {
@@ -37,15 +39,102 @@ impl Default for SimulationConfig {
             jitter_ms: 250.0,
             packet_loss_percent: 5.0,
             duplicate_packet_percent: 10.0,
+            port_unreachable: false,
+        }
+    }
+}
+
+/// Decides which sent packets are lost.
+#[derive(Debug, Clone)]
+pub enum LossModel {
+    /// Every packet is lost independently with `SimulationConfig::packet_loss_percent`.
+    Uniform,
+    /// Two-state Markov chain that produces bursts of loss.
+    GilbertElliott(GilbertElliott),
+    /// Replays a recorded loss trace (`true` means lost), looping when it runs out.
+    Trace(Vec<bool>),
+}
+
+/// Parameters of the Gilbert–Elliott model, all probabilities are in `0.0..=1.0`.
+///
+/// Before each packet the channel moves between the good and bad state with the transition probabilities,
+/// then the packet is lost with the loss probability of the current state.
+#[derive(Debug, Clone, Copy)]
+pub struct GilbertElliott {
+    pub p_good_to_bad: f64,
+    pub p_bad_to_good: f64,
+    pub loss_good: f64,
+    pub loss_bad: f64,
+}
+
+impl LossModel {
+    /// Parses a loss trace where each `1` is a lost packet and each `0` a delivered one, whitespace is ignored.
+    pub fn from_trace(trace: &str) -> Result<Self, io::Error> {
+        let trace = trace
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|c| match c {
+                '0' => Ok(false),
+                '1' => Ok(true),
+                c => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid loss trace character {c:?}"),
+                )),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if trace.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "empty loss trace",
+            ));
+        }
+        Ok(LossModel::Trace(trace))
+    }
+}
+
+struct LossState {
+    model: LossModel,
+    bad: bool,
+    trace_pos: usize,
+}
+
+impl LossState {
+    fn is_lost(&mut self, packet_loss_percent: f64) -> bool {
+        match &self.model {
+            LossModel::Uniform => rand_float(0.0..100.) < packet_loss_percent,
+            LossModel::GilbertElliott(ge) => {
+                let p_switch = if self.bad {
+                    ge.p_bad_to_good
+                } else {
+                    ge.p_good_to_bad
+                };
+                if rand_float(0.0..1.) < p_switch {
+                    self.bad = !self.bad;
+                }
+                let loss = if self.bad { ge.loss_bad } else { ge.loss_good };
+                rand_float(0.0..1.) < loss
+            }
+            LossModel::Trace(trace) => {
+                let lost = trace[self.trace_pos % trace.len()];
+                self.trace_pos += 1;
+                lost
+            }
         }
     }
 }
 
 pub struct NetworkSimulator {
+    pub ip: IpAddr,
     pub port: u16,
     pub time: f64,
     pub cfg: SimulationConfig,
     pub routing_table: Rc<RefCell<HashMap<u16, Channel>>>,
+    loss: RefCell<LossState>,
+    unreachable: Cell<bool>,
+    // makes sends and receives fail until the endpoint rebinds, like a network interface that went down
+    pub interface_down: Cell<bool>,
+    // makes sends would block, like a full socket send buffer
+    pub send_buffer_full: Cell<bool>,
 }
 
 impl NetworkSimulator {
@@ -53,12 +142,29 @@ impl NetworkSimulator {
         let (tx, rx) = mpsc::channel::<PacketEntry>();
         table.borrow_mut().insert(port, Channel { tx, rx });
         Self {
+            ip: Ipv4Addr::LOCALHOST.into(),
             port,
             time: 0.0,
             cfg: SimulationConfig::default(),
             routing_table: table,
+            loss: RefCell::new(LossState {
+                model: LossModel::Uniform,
+                bad: false,
+                trace_pos: 0,
+            }),
+            unreachable: Cell::new(false),
+            interface_down: Cell::new(false),
+            send_buffer_full: Cell::new(false),
         }
     }
+    /// Replaces the loss model of packets sent from this endpoint, restarting it from the good state / trace start.
+    pub fn set_loss_model(&mut self, model: LossModel) {
+        *self.loss.get_mut() = LossState {
+            model,
+            bad: false,
+            trace_pos: 0,
+        };
+    }
 }
 
 fn rand_float(range: std::ops::Range<f64>) -> f64 {
@@ -71,10 +177,16 @@ impl Transceiver for NetworkSimulator {
     type IntoError = io::Error;
 
     fn addr(&self) -> SocketAddr {
-        SocketAddr::from((Ipv4Addr::LOCALHOST, self.port))
+        SocketAddr::new(self.ip, self.port)
     }
 
     fn recv(&self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>, Self::IntoError> {
+        if self.unreachable.take() {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+        if self.interface_down.get() {
+            return Err(io::ErrorKind::NetworkDown.into());
+        }
         // routing table -> given an addr of self, look through the table for the receiving endpoint
         // if no entry is found, return early
         let table = self.routing_table.borrow();
@@ -83,10 +195,7 @@ impl Transceiver for NetworkSimulator {
         };
         if let Ok(entry) = rx.try_recv() {
             if entry.to != self.addr() {
-                return Err(io::Error::new(
-                    ErrorKind::Other,
-                    "received packet for wrong address",
-                ));
+                return Err(io::Error::other("received packet for wrong address"));
             }
             let len = entry.packet.len();
             buf[..len].copy_from_slice(&entry.packet[..len]);
@@ -95,15 +204,22 @@ impl Transceiver for NetworkSimulator {
         Ok(None)
     }
     fn send(&self, buf: &[u8], addr: SocketAddr) -> Result<usize, Self::IntoError> {
+        if self.interface_down.get() {
+            return Err(io::ErrorKind::NetworkDown.into());
+        }
+        if self.send_buffer_full.get() {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
         // routing table -> given an addr, look through the table for the sending endpoint
         // if no entry is found, return early
         let table = self.routing_table.borrow();
         let Some(tx) = table.get(&addr.port()).map(|c| &c.tx) else {
-            return Ok(0);
+            self.unreachable.set(self.cfg.port_unreachable);
+            return Ok(buf.len());
         };
-        if rand_float(0.0..100.) < self.cfg.packet_loss_percent {
+        if self.loss.borrow_mut().is_lost(self.cfg.packet_loss_percent) {
             // log::error!("packet lost {}", buf[0] & 0xF);
-            return Ok(0);
+            return Ok(buf.len());
         }
         let mut delay = self.cfg.latency_ms / 1000.0;
         if self.cfg.jitter_ms > 0.0 {
@@ -123,15 +239,35 @@ impl Transceiver for NetworkSimulator {
         }
         Ok(buf.len())
     }
+    fn rebind(&mut self) -> Result<bool, Self::IntoError> {
+        let mut table = self.routing_table.borrow_mut();
+        let port = (self.port + 1..)
+            .find(|port| !table.contains_key(port))
+            .unwrap();
+        table.remove(&self.port);
+        let (tx, rx) = mpsc::channel::<PacketEntry>();
+        table.insert(port, Channel { tx, rx });
+        self.port = port;
+        self.interface_down.set(false);
+        Ok(true)
+    }
 }
 
 mod tests {
     use crate::{
-        client::{Client, ClientState},
+        client::{Client, ClientConfig, ClientState},
         generate_key,
-        server::{ClientIndex, MAX_CLIENTS},
+        query::{self, QueryConfig},
+        relay::{self, RelayConfig},
+        server::{
+            ClientIndex, ConnectDecision, DuplicateClientId, PendingEviction, ServerConfig,
+            MAX_CLIENTS,
+        },
         token::ConnectToken,
-        CONNECTION_TIMEOUT_SEC,
+        ConnectConfig, ConnectionPhase, ConnectionQuality, EchoMode, LinkCheckConfig, ManualClock,
+        MultiClient, PacketAllowList, PacketDirection, PacketRecord, PacketType, QueueOverflow,
+        Recorder, Recording, SendOutcome, CHALLENGE_DATA_BYTES, CONNECTION_TIMEOUT_SEC,
+        MAX_HEARTBEAT_BYTES, MAX_PACKET_SIZE,
     };
 
     use super::*;
@@ -796,4 +932,3698 @@ mod tests {
         assert!(last_client.state() == ClientState::ConnectionDenied);
         assert!(server.num_connected_clients() == MAX_CLIENTS);
     }
+
+    #[test]
+    fn set_max_clients_at_runtime() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let server_sim = NetworkSimulator::new(50000, routing_table.clone());
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let cfg = ServerConfig::default().max_clients(2);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        assert!(server.set_max_clients(usize::MAX).is_err());
+        assert_eq!(server.max_clients(), 2);
+
+        let mut clients = Vec::new();
+        for i in 0..2 {
+            let token = server.token(i as u64).generate().unwrap();
+            let client_sim = NetworkSimulator::new(40000 + i as u16, routing_table.clone());
+            let mut client = Client::with_simulator(token, client_sim).unwrap();
+            client.connect();
+            clients.push(client);
+        }
+
+        // connect clients
+        loop {
+            for client in clients.iter_mut() {
+                client.update(time);
+            }
+            server.update(time);
+
+            if clients.iter().all(|c| c.is_connected()) {
+                break;
+            }
+
+            time += delta;
+        }
+
+        // shrink below the number of connected clients, existing sessions are preserved
+        server.set_max_clients(1).unwrap();
+
+        let token = server.token(2u64).generate().unwrap();
+        let client_sim = NetworkSimulator::new(40002, routing_table.clone());
+        let mut denied_client = Client::with_simulator(token, client_sim).unwrap();
+        denied_client.connect();
+        loop {
+            for client in clients.iter_mut() {
+                client.update(time);
+            }
+            denied_client.update(time);
+            server.update(time);
+
+            if denied_client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert_eq!(denied_client.state(), ClientState::ConnectionDenied);
+        assert!(clients.iter().all(|c| c.is_connected()));
+        assert_eq!(server.num_connected_clients(), 2);
+
+        // grow again, a new client can connect
+        server.set_max_clients(3).unwrap();
+
+        let token = server.token(3u64).generate().unwrap();
+        let client_sim = NetworkSimulator::new(40003, routing_table.clone());
+        let mut new_client = Client::with_simulator(token, client_sim).unwrap();
+        new_client.connect();
+        loop {
+            for client in clients.iter_mut() {
+                client.update(time);
+            }
+            new_client.update(time);
+            server.update(time);
+
+            if new_client.is_connected() || new_client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(new_client.is_connected());
+        assert!(clients.iter().all(|c| c.is_connected()));
+        assert_eq!(server.num_connected_clients(), 3);
+    }
+
+    #[test]
+    fn raise_max_clients_past_default() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let server_sim = NetworkSimulator::new(50000, routing_table.clone());
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+        server.set_max_clients(MAX_CLIENTS + 1).unwrap();
+
+        let mut clients = Vec::new();
+        for i in 0..MAX_CLIENTS + 1 {
+            let token = server.token(i as u64).generate().unwrap();
+            let client_sim = NetworkSimulator::new(40000 + i as u16, routing_table.clone());
+            let mut client = Client::with_simulator(token, client_sim).unwrap();
+            client.connect();
+            clients.push(client);
+        }
+
+        // connect clients
+        loop {
+            for client in clients.iter_mut() {
+                client.update(time);
+            }
+            server.update(time);
+
+            if clients.iter().all(|c| c.is_connected() || c.is_error()) {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(clients.iter().all(|c| c.is_connected()));
+        assert_eq!(server.num_connected_clients(), MAX_CLIENTS + 1);
+
+        // the client past the default limit is sent payloads like any other
+        let outcome = server.send(b"hello", ClientIndex(MAX_CLIENTS));
+        assert!(matches!(outcome, Ok(SendOutcome::Sent(_))));
+        time += delta;
+        let mut received = 0;
+        for client in clients.iter_mut() {
+            client.update(time);
+            while let Some(payload) = client.recv() {
+                assert_eq!(payload, b"hello");
+                received += 1;
+            }
+        }
+        assert_eq!(received, 1);
+
+        // disconnected clients free their slots
+        for client in clients.iter_mut() {
+            client.disconnect().unwrap();
+        }
+        server.update(time);
+        assert_eq!(server.num_connected_clients(), 0);
+        assert_eq!(server.num_client_slots(), 0);
+    }
+
+    #[test]
+    fn token_time_remaining_and_renew() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let server_sim = NetworkSimulator::new(50000, routing_table.clone());
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+        let token = server.token(123u64).expire_seconds(10).generate().unwrap();
+        let expire_timestamp = token.expire_timestamp;
+
+        let renewals = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let cfg =
+            ClientConfig::with_context(renewals.clone()).on_token_renew(5.0, |remaining, ctx| {
+                assert!(remaining <= 5.0);
+                ctx.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            });
+        let token_bytes = token.try_into_bytes().unwrap();
+        let mut client =
+            Client::with_config_and_transceiver(&token_bytes, cfg, client_sim).unwrap();
+        assert_eq!(client.token_time_remaining(), 10.0);
+
+        client.connect();
+        loop {
+            client.update(time);
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+        assert!(client.token_time_remaining() > 5.0);
+        let idx = server.iter_clients().next().unwrap();
+        assert_eq!(server.client_token_expiry(idx), Some(expire_timestamp));
+        assert_eq!(renewals.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        // the renew callback fires once, and the established connection outlives the token
+        while time < 20.0 {
+            client.update(time);
+            server.update(time);
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+        assert_eq!(client.token_time_remaining(), 0.0);
+        assert_eq!(renewals.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn link_check_against_echo_server() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let server_sim = NetworkSimulator::new(50000, routing_table.clone());
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let cfg = ServerConfig::default().echo_mode(EchoMode::EchoWithTimestamp);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+
+        let token = server.token(123u64).generate().unwrap();
+
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+
+        client.connect();
+
+        // connect client
+        loop {
+            client.update(time);
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+
+        let num_probes = 20;
+        client.start_link_check(
+            LinkCheckConfig::new()
+                .num_probes(num_probes)
+                .probe_interval(delta)
+                .timeout(1.0),
+        );
+        while client.is_link_check_running() {
+            client.update(time);
+            server.update(time);
+
+            time += delta;
+        }
+
+        let report = client.link_check_report().unwrap();
+        assert_eq!(report.sent, num_probes);
+        assert!(report.received > 0);
+        assert!(report.rtt_min > 0.0);
+        assert!(report.throughput_bytes_per_sec > 0.0);
+        assert!(report.server_time_offset.is_some());
+
+        // echoed payloads are not delivered to the application on either side
+        assert!(server.recv().is_none());
+        assert!(client.recv().is_none());
+    }
+
+    fn received_payloads(receiver: &NetworkSimulator) -> Vec<u8> {
+        let mut buf = [0; 8];
+        let mut received = Vec::new();
+        while let Some((len, _)) = receiver.recv(&mut buf).unwrap() {
+            received.push(buf[..len][0]);
+        }
+        received
+    }
+
+    #[test]
+    fn loss_trace_replay() {
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut sender = NetworkSimulator::new(40000, routing_table.clone());
+        let receiver = NetworkSimulator::new(50000, routing_table.clone());
+        sender.cfg.duplicate_packet_percent = 0.0;
+
+        assert!(LossModel::from_trace("10x1").is_err());
+        assert!(LossModel::from_trace(" \n").is_err());
+        sender.set_loss_model(LossModel::from_trace("11\n01").unwrap());
+
+        for i in 0..10u8 {
+            sender.send(&[i], receiver.addr()).unwrap();
+        }
+        // the trace loops: lost, lost, delivered, lost, ...
+        assert_eq!(received_payloads(&receiver), vec![2, 6]);
+    }
+
+    #[test]
+    fn gilbert_elliott_burst_loss() {
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut sender = NetworkSimulator::new(40000, routing_table.clone());
+        let receiver = NetworkSimulator::new(50000, routing_table.clone());
+        sender.cfg.duplicate_packet_percent = 0.0;
+        sender.set_loss_model(LossModel::GilbertElliott(GilbertElliott {
+            p_good_to_bad: 0.05,
+            p_bad_to_good: 0.25,
+            loss_good: 0.0,
+            loss_bad: 1.0,
+        }));
+
+        let num_packets = 10_000;
+        let mut lost = Vec::with_capacity(num_packets);
+        let mut buf = [0; 8];
+        for _ in 0..num_packets {
+            sender.send(&[0], receiver.addr()).unwrap();
+            lost.push(receiver.recv(&mut buf).unwrap().is_none());
+        }
+
+        // steady state loss is p_good_to_bad / (p_good_to_bad + p_bad_to_good) = 1/6
+        let loss_rate = lost.iter().filter(|&&l| l).count() as f64 / num_packets as f64;
+        assert!((0.1..0.25).contains(&loss_rate), "loss rate {loss_rate}");
+
+        // losses come in bursts with an average length of 1 / p_bad_to_good = 4 packets
+        let bursts = lost.windows(2).filter(|w| !w[0] && w[1]).count();
+        let avg_burst_len = lost.iter().filter(|&&l| l).count() as f64 / bursts as f64;
+        assert!(avg_burst_len > 2.5, "average burst length {avg_burst_len}");
+    }
+
+    #[test]
+    fn client_server_connect_with_burst_loss() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        let burst_loss = LossModel::GilbertElliott(GilbertElliott {
+            p_good_to_bad: 0.1,
+            p_bad_to_good: 0.3,
+            loss_good: 0.01,
+            loss_bad: 0.9,
+        });
+        client_sim.set_loss_model(burst_loss.clone());
+        server_sim.set_loss_model(burst_loss);
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+        let token = server.token(123u64).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+
+        loop {
+            client.update(time);
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+    }
+
+    #[test]
+    fn broadcast_and_groups() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+
+        let mut clients = Vec::new();
+        for i in 0..3 {
+            let token = server.token(i as u64).generate().unwrap();
+            let mut client_sim = NetworkSimulator::new(40000 + i as u16, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            let mut client = Client::with_simulator(token, client_sim).unwrap();
+            client.connect();
+            clients.push(client);
+        }
+
+        loop {
+            for client in clients.iter_mut() {
+                client.update(time);
+            }
+            server.update(time);
+
+            if clients.iter().all(|c| c.is_connected()) {
+                break;
+            }
+
+            time += delta;
+        }
+
+        let idx_of = |server: &Server<NetworkSimulator>, client_id: u64| {
+            server
+                .iter_clients()
+                .find(|&idx| server.client_id(idx) == Some(client_id))
+                .unwrap()
+        };
+        let (first, second) = (idx_of(&server, 0), idx_of(&server, 1));
+
+        assert!(server.broadcast(&[0; MAX_PACKET_SIZE + 1]).is_err());
+        server.broadcast(b"all").unwrap();
+        server.broadcast_except(first, b"except").unwrap();
+        server.send_to_group(&[second, second], b"group").unwrap();
+
+        time += delta;
+        for client in clients.iter_mut() {
+            client.update(time);
+        }
+        let received: Vec<Vec<Vec<u8>>> = clients
+            .iter_mut()
+            .map(|c| std::iter::from_fn(|| c.recv()).collect())
+            .collect();
+
+        assert_eq!(received[0], vec![b"all".to_vec()]);
+        assert_eq!(
+            received[1],
+            vec![b"all".to_vec(), b"except".to_vec(), b"group".to_vec()]
+        );
+        assert_eq!(received[2], vec![b"all".to_vec(), b"except".to_vec()]);
+    }
+
+    #[test]
+    fn out_of_phase_packets_are_dropped_and_counted() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let server_sim = NetworkSimulator::new(50000, routing_table.clone());
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let no_payloads = PacketAllowList::new(&[PacketType::KeepAlive, PacketType::Disconnect]);
+        let cfg = ServerConfig::default().allowed_packets(ConnectionPhase::Connected, no_payloads);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let token = server
+            .token(123u64)
+            .generate()
+            .unwrap()
+            .try_into_bytes()
+            .unwrap();
+        let cfg = ClientConfig::default().allowed_packets(ClientState::Connected, no_payloads);
+        let mut client = Client::with_config_and_transceiver(&token, cfg, client_sim).unwrap();
+        client.connect();
+
+        loop {
+            client.update(time);
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+
+        let idx = server.iter_clients().next().unwrap();
+        for _ in 0..10 {
+            client.send(b"hello").unwrap();
+            server.send(b"hello", idx).unwrap();
+            client.update(time);
+            server.update(time);
+
+            time += delta;
+        }
+
+        // payloads are dropped, keep-alives still keep the connection alive
+        assert!(client.is_connected());
+        assert!(server.recv().is_none());
+        assert!(client.recv().is_none());
+        assert!(server.stats().out_of_phase.get(PacketType::Payload) > 0);
+        assert!(client.stats().out_of_phase.get(PacketType::Payload) > 0);
+        assert_eq!(client.stats().out_of_phase.get(PacketType::KeepAlive), 0);
+    }
+
+    #[test]
+    fn max_payload_size() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.packet_loss_percent = 0.0;
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+        let token = server
+            .token(123u64)
+            .generate()
+            .unwrap()
+            .try_into_bytes()
+            .unwrap();
+        let cfg = ClientConfig::default().max_payload_size(500);
+        let mut client = Client::with_config_and_transceiver(&token, cfg, client_sim).unwrap();
+        client.connect();
+
+        loop {
+            client.update(time);
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+        assert_eq!(client.stats().max_payload_size, 500);
+        assert_eq!(server.stats().max_payload_size, MAX_PACKET_SIZE);
+
+        assert!(matches!(
+            client.send(&[0; 501]),
+            Err(crate::Error::SizeMismatch(500, 501))
+        ));
+        client.send(&[1; 500]).unwrap();
+        // full size payloads are received intact
+        let idx = server.iter_clients().next().unwrap();
+        server.send(&[2; MAX_PACKET_SIZE], idx).unwrap();
+
+        time += delta;
+        client.update(time);
+        server.update(time);
+
+        assert_eq!(server.recv(), Some((vec![1; 500], idx)));
+        assert_eq!(client.recv(), Some(vec![2; MAX_PACKET_SIZE]));
+        assert_eq!(server.stats().largest_payload_received, 500);
+        assert_eq!(client.stats().largest_payload_received, MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    fn timeout_config_overrides_token() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let server_sim = NetworkSimulator::new(50000, routing_table.clone());
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        // the token times out after 1 second, but both sides disable timeouts
+        let cfg = ServerConfig::default().disable_timeout();
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let token = server.token(123u64).timeout_seconds(1).generate().unwrap();
+        let token = token.try_into_bytes().unwrap();
+        let cfg = ClientConfig::default().disable_timeout();
+        let mut client = Client::with_config_and_transceiver(&token, cfg, client_sim).unwrap();
+        client.connect();
+
+        loop {
+            client.update(time);
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+
+        // pause the client (as if on a breakpoint), then the server
+        for _ in 0..50 {
+            server.update(time);
+            time += delta;
+        }
+        for _ in 0..50 {
+            client.update(time);
+            time += delta;
+        }
+        assert!(client.is_connected());
+        assert_eq!(server.num_connected_clients(), 1);
+    }
+
+    #[test]
+    fn server_timeout_config() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let server_sim = NetworkSimulator::new(50000, routing_table.clone());
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let cfg = ServerConfig::default().timeout_seconds(1);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let token = server.token(123u64).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+
+        loop {
+            client.update(time);
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert_eq!(server.num_connected_clients(), 1);
+
+        // the server times out the client well before the token's timeout
+        for _ in 0..20 {
+            server.update(time);
+            time += delta;
+        }
+        assert_eq!(server.num_connected_clients(), 0);
+    }
+
+    #[test]
+    fn packet_logger_sees_raw_and_decrypted_packets() {
+        enable_logging();
+
+        type Log = std::sync::Arc<
+            std::sync::Mutex<Vec<(PacketDirection, Option<PacketType>, Option<Vec<u8>>)>>,
+        >;
+        fn logger(log: Log) -> impl FnMut(&PacketRecord) + Send + Sync + 'static {
+            move |record: &PacketRecord| {
+                log.lock().unwrap().push((
+                    record.direction,
+                    record.packet_type,
+                    record.payload.map(<[u8]>::to_vec),
+                ))
+            }
+        }
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let client_log = Log::default();
+        let server_log = Log::default();
+        let cfg = ServerConfig::default().packet_logger(logger(server_log.clone()));
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let token = server
+            .token(123u64)
+            .generate()
+            .unwrap()
+            .try_into_bytes()
+            .unwrap();
+        let cfg = ClientConfig::default().packet_logger(logger(client_log.clone()));
+        let mut client = Client::with_config_and_transceiver(&token, cfg, client_sim).unwrap();
+        client.connect();
+
+        loop {
+            client.update(time);
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+
+        client.send(b"hello").unwrap();
+        time += delta;
+        client.update(time);
+        server.update(time);
+        assert_eq!(server.recv().unwrap().0, b"hello");
+
+        let client_log = client_log.lock().unwrap();
+        let server_log = server_log.lock().unwrap();
+        assert_eq!(
+            client_log[0],
+            (PacketDirection::Sent, Some(PacketType::Request), None)
+        );
+        assert!(client_log.contains(&(
+            PacketDirection::Received,
+            Some(PacketType::Challenge),
+            None
+        )));
+        assert!(client_log.contains(&(
+            PacketDirection::Sent,
+            Some(PacketType::Payload),
+            Some(b"hello".to_vec())
+        )));
+        assert!(server_log.contains(&(PacketDirection::Sent, Some(PacketType::Challenge), None)));
+        assert!(server_log.contains(&(
+            PacketDirection::Received,
+            Some(PacketType::Payload),
+            Some(b"hello".to_vec())
+        )));
+        // every packet the client sent was received by the server on the lossless link
+        let sent = client_log
+            .iter()
+            .filter(|(direction, ..)| *direction == PacketDirection::Sent)
+            .count();
+        let received = server_log
+            .iter()
+            .filter(|(direction, ..)| *direction == PacketDirection::Received)
+            .count();
+        assert_eq!(sent, received);
+    }
+
+    #[test]
+    fn frame_driven_sends() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.packet_loss_percent = 0.0;
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+        let token = server
+            .token(123u64)
+            .generate()
+            .unwrap()
+            .try_into_bytes()
+            .unwrap();
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = sent.clone();
+        let cfg = ClientConfig::default().send_on_update(false).packet_logger(
+            move |record: &PacketRecord| {
+                if record.direction == PacketDirection::Sent {
+                    log.lock().unwrap().push(record.packet_type.unwrap());
+                }
+            },
+        );
+        let mut client = Client::with_config_and_transceiver(&token, cfg, client_sim).unwrap();
+        client.connect();
+
+        // update alone never sends
+        for _ in 0..5 {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        assert!(sent.lock().unwrap().is_empty());
+        assert_eq!(client.state(), ClientState::SendingConnectionRequest);
+
+        loop {
+            client.update(time);
+            client.flush(time).unwrap();
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+
+        // a payload sent from the frame callback replaces the keep-alive
+        sent.lock().unwrap().clear();
+        time += delta;
+        client.update(time);
+        client.send_at(b"input", time + 0.0005).unwrap();
+        client.flush(time + 0.001).unwrap();
+        assert_eq!(*sent.lock().unwrap(), [PacketType::Payload]);
+        assert_eq!(client.next_send_time(), time + 0.0005 + delta);
+
+        server.update(time + 0.001);
+        assert_eq!(server.recv().unwrap().0, b"input");
+
+        // without payloads, flush keeps the connection alive once it is due
+        client.flush(client.next_send_time() + 0.001).unwrap();
+        assert_eq!(
+            *sent.lock().unwrap(),
+            [PacketType::Payload, PacketType::KeepAlive]
+        );
+    }
+
+    #[test]
+    fn server_accepts_multiple_protocol_ids() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+
+        let (old_protocol, new_protocol, unknown_protocol) = (0x0100, 0x0200, 0x0300);
+        let private_key = generate_key();
+        let cfg = ServerConfig::default().accept_protocol_id(old_protocol);
+        let mut server =
+            Server::with_config_and_transceiver(new_protocol, private_key, cfg, server_sim)
+                .unwrap();
+        assert_eq!(
+            server.protocol_ids().collect::<Vec<_>>(),
+            [new_protocol, old_protocol]
+        );
+
+        let mut clients: Vec<_> = [new_protocol, old_protocol, unknown_protocol]
+            .into_iter()
+            .enumerate()
+            .map(|(i, protocol_id)| {
+                let mut sim = NetworkSimulator::new(40000 + i as u16, routing_table.clone());
+                sim.cfg.packet_loss_percent = 0.0;
+                let token = ConnectToken::build(server.addr(), protocol_id, i as u64, private_key)
+                    .generate()
+                    .unwrap()
+                    .try_into_bytes()
+                    .unwrap();
+                let mut client =
+                    Client::with_config_and_transceiver(&token, ClientConfig::default(), sim)
+                        .unwrap();
+                client.connect();
+                client
+            })
+            .collect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        for _ in 0..20 {
+            for client in clients.iter_mut() {
+                client.update(time);
+            }
+            server.update(time);
+            time += delta;
+        }
+        assert!(clients[0].is_connected());
+        assert!(clients[1].is_connected());
+        assert!(!clients[2].is_connected());
+        assert_eq!(server.num_connected_clients(), 2);
+
+        // payloads are exchanged with the protocol id each client connected with
+        clients[1].send(b"old build").unwrap();
+        server.update(time);
+        let (payload, idx) = server.recv().unwrap();
+        assert_eq!(payload, b"old build");
+        assert_eq!(server.client_protocol_id(idx), Some(old_protocol));
+        server.send(b"still here", idx).unwrap();
+        clients[1].update(time);
+        assert_eq!(clients[1].recv().unwrap(), b"still here");
+
+        let new_stats = server.protocol_stats(new_protocol).unwrap();
+        let old_stats = server.protocol_stats(old_protocol).unwrap();
+        assert_eq!(new_stats.connected_clients, 1);
+        assert_eq!(old_stats.connected_clients, 1);
+        assert_eq!(old_stats.connections_accepted, 1);
+        assert!(old_stats.packets_received > 0);
+        assert!(old_stats.bytes_received > old_stats.packets_received);
+        assert_eq!(server.protocol_stats(unknown_protocol), None);
+    }
+
+    #[test]
+    fn ack_on_sequence_gap() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = sent.clone();
+        let cfg = ServerConfig::default()
+            .ack_on_sequence_gap(true)
+            .packet_logger(move |record: &PacketRecord| {
+                if record.direction == PacketDirection::Sent {
+                    log.lock().unwrap().push(record.packet_type.unwrap());
+                }
+            });
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let token = server.token(123u64).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+
+        loop {
+            client.update(time);
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+        client.send(b"first").unwrap();
+        server.update(time);
+        assert_eq!(server.recv().unwrap().0, b"first");
+        assert_eq!(server.stats().sequence_gaps, 0);
+
+        // the first payload is dropped before it reaches the server
+        client.send(b"lost").unwrap();
+        while routing_table.borrow()[&50000].rx.try_recv().is_ok() {}
+        client.send(b"received").unwrap();
+
+        // the server is updated at the same time, so no keep-alive is due and only the immediate one is sent
+        sent.lock().unwrap().clear();
+        server.update(time);
+        assert_eq!(server.recv().unwrap().0, b"received");
+        assert_eq!(server.stats().sequence_gaps, 1);
+        assert_eq!(*sent.lock().unwrap(), [PacketType::KeepAlive]);
+
+        // packets in sequence don't trigger it
+        sent.lock().unwrap().clear();
+        client.send(b"next").unwrap();
+        server.update(time);
+        assert_eq!(server.stats().sequence_gaps, 1);
+        assert!(sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn server_limits_client_payload_size() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.packet_loss_percent = 0.0;
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+        let token = server.token(123u64).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+
+        loop {
+            client.update(time);
+            server.update(time);
+
+            if client.is_connected() || client.is_error() {
+                break;
+            }
+
+            time += delta;
+        }
+        assert!(client.is_connected());
+        assert_eq!(client.max_payload_size(), MAX_PACKET_SIZE);
+
+        let client_idx = ClientIndex(0);
+        server.set_client_max_payload_size(client_idx, 500).unwrap();
+        assert_eq!(server.client_max_payload_size(client_idx), Some(500));
+        time += delta;
+        client.update(time);
+        assert_eq!(client.max_payload_size(), 500);
+        assert_eq!(client.stats().max_payload_size, 500);
+        assert!(client.send(&[0; 500]).is_ok());
+        assert!(matches!(
+            client.send(&[0; 501]),
+            Err(crate::Error::SizeMismatch(500, 501))
+        ));
+
+        // a limit above the client's own max lifts it
+        server
+            .set_client_max_payload_size(client_idx, MAX_PACKET_SIZE + 1)
+            .unwrap();
+        time += delta;
+        client.update(time);
+        assert_eq!(client.max_payload_size(), MAX_PACKET_SIZE);
+
+        // the limit is cleared on disconnect
+        server.set_client_max_payload_size(client_idx, 500).unwrap();
+        time += delta;
+        client.update(time);
+        assert_eq!(client.max_payload_size(), 500);
+        client.disconnect().unwrap();
+        assert_eq!(client.max_payload_size(), MAX_PACKET_SIZE);
+    }
+
+    #[test]
+    fn challenge_key_rotation() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+        // as if the server had already sent billions of challenges
+        server.set_challenge_sequence(u64::MAX - 1);
+
+        let mut clients = [40000, 40001].map(|port| {
+            let mut sim = NetworkSimulator::new(port, routing_table.clone());
+            // exactly one connection request per client must reach the server in the first update
+            sim.cfg.packet_loss_percent = 0.0;
+            sim.cfg.duplicate_packet_percent = 0.0;
+            let token = server.token(port as u64).generate().unwrap();
+            let mut client = Client::with_simulator(token, sim).unwrap();
+            client.connect();
+            client
+        });
+
+        let mut time = 0.0;
+        // the first challenge uses the last sequence of the old key, the second one rotates the key
+        for client in &mut clients {
+            client.update(time);
+        }
+        server.update(time);
+        assert_eq!(server.stats().challenge_key_rotations, 1);
+        assert_eq!(server.challenge_sequence(), 1);
+
+        for _ in 0..100 {
+            time += 0.1;
+            for client in &mut clients {
+                client.update(time);
+            }
+            server.update(time);
+            if clients.iter().all(|client| client.is_connected()) {
+                break;
+            }
+        }
+        assert!(clients.iter().all(|client| client.is_connected()));
+        assert_eq!(server.num_connected_clients(), 2);
+        assert_eq!(server.stats().challenge_key_rotations, 1);
+    }
+
+    #[test]
+    fn server_checks_token_expiry_against_its_clock() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        let server_sim = NetworkSimulator::new(50000, routing_table.clone());
+
+        let clock = ManualClock::new(1_000_000);
+        let cfg = ServerConfig::default().clock(clock.clone());
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let token = server.token(123u64).expire_seconds(30).generate().unwrap();
+        assert_eq!(token.create_timestamp(), 1_000_000);
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+
+        // the token expired on the server's clock, so its connection requests are ignored
+        clock.advance(31);
+        client.connect();
+        let mut time = 0.0;
+        for _ in 0..20 {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        assert!(!client.is_connected());
+        assert_eq!(server.num_connected_clients(), 0);
+
+        // turning the clock back makes the token valid again, without any real time passing
+        clock.set(1_000_010);
+        for _ in 0..100 {
+            client.update(time);
+            server.update(time);
+            if client.is_connected() {
+                break;
+            }
+            time += 0.1;
+        }
+        assert!(client.is_connected());
+        assert_eq!(server.num_connected_clients(), 1);
+    }
+
+    #[test]
+    fn server_shutdown_drains_clients() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+
+        // the second client behaves like the reference implementation, which doesn't confirm the disconnect
+        let mut clients = [40000, 40001, 40002].map(|port| {
+            let mut sim = NetworkSimulator::new(port, routing_table.clone());
+            sim.cfg.packet_loss_percent = 0.0;
+            sim.cfg.duplicate_packet_percent = 0.0;
+            let token = server.token(port as u64).generate().unwrap();
+            let cfg = ClientConfig::default().strict_netcode_1_02(port == 40001);
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, sim).unwrap()
+        });
+        let (connected, late) = clients.split_at_mut(2);
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        for client in connected.iter_mut() {
+            client.connect();
+        }
+        for _ in 0..100 {
+            for client in connected.iter_mut() {
+                client.update(time);
+            }
+            server.update(time);
+            if connected.iter().all(|client| client.is_connected()) {
+                break;
+            }
+            time += delta;
+        }
+        assert_eq!(server.num_connected_clients(), 2);
+
+        server.begin_shutdown(1.0).unwrap();
+        assert!(server.is_shutting_down());
+        assert_eq!(server.num_connected_clients(), 0);
+        assert_eq!(server.shutdown_report(), None);
+
+        // only the first client acknowledges, and new clients can't connect
+        late[0].connect();
+        while server.shutdown_report().is_none() {
+            time += delta;
+            for client in connected.iter_mut() {
+                client.update(time);
+            }
+            late[0].update(time);
+            server.update(time);
+        }
+        assert!(connected.iter().all(|client| client.is_disconnected()));
+        assert!(!late[0].is_connected());
+        assert_eq!(server.num_connected_clients(), 0);
+        let report = server.shutdown_report().unwrap();
+        assert_eq!(report.acknowledged, 1);
+        assert_eq!(report.timed_out, 1);
+    }
+
+    #[test]
+    fn server_shutdown_drains_on_its_clock() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+
+        let clock = ManualClock::new(1_000_000);
+        let cfg = ServerConfig::default().clock(clock.clone());
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let token = server.token(123u64).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+        let mut time = 0.0;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+
+        // the client is gone, so the drain period only ends once the clock passes it
+        drop(client);
+        let advance = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            clock.advance(60);
+        });
+        let report = server.shutdown(std::time::Duration::from_secs(30)).unwrap();
+        advance.join().unwrap();
+        assert_eq!(report.acknowledged, 0);
+        assert_eq!(report.timed_out, 1);
+    }
+
+    #[test]
+    fn client_reconnect_with_fresh_token() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        let mut servers = [50000, 50001].map(|port| {
+            let mut sim = NetworkSimulator::new(port, routing_table.clone());
+            sim.cfg.packet_loss_percent = 0.0;
+            Server::with_simulator(sim, None).unwrap()
+        });
+
+        let token = servers[0].token(123u64).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        let addr = client.addr();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        let mut run = |client: &mut Client<NetworkSimulator>, servers: &mut [Server<_>; 2]| {
+            for _ in 0..100 {
+                client.update(time);
+                for server in servers.iter_mut() {
+                    server.update(time);
+                }
+                if client.is_connected() {
+                    break;
+                }
+                time += delta;
+            }
+        };
+        client.connect();
+        run(&mut client, &mut servers);
+        assert!(client.is_connected());
+        assert_eq!(servers[0].num_connected_clients(), 1);
+
+        // an invalid token leaves the connection alone
+        assert!(client.reconnect(&[0; crate::CONNECT_TOKEN_BYTES]).is_err());
+        assert!(client.is_connected());
+
+        let token = servers[1].token(123u64).generate().unwrap();
+        client.reconnect(&token.try_into_bytes().unwrap()).unwrap();
+        assert!(client.is_pending());
+        run(&mut client, &mut servers);
+        assert!(client.is_connected());
+        assert_eq!(client.addr(), addr);
+        assert_eq!(servers[0].num_connected_clients(), 0);
+        assert_eq!(servers[1].num_connected_clients(), 1);
+        let idx = servers[1].iter_clients().next().unwrap();
+        assert_eq!(servers[1].client_addr(idx), Some(addr));
+    }
+
+    #[test]
+    fn client_migrates_to_new_address() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        let cfg = ServerConfig::default().allow_migration(true);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+
+        let token = server.token(123u64).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        let idx = server.iter_clients().next().unwrap();
+
+        // the client's address changes, e.g. it switched networks
+        let mut new_sim = NetworkSimulator::new(40001, routing_table.clone());
+        new_sim.cfg.packet_loss_percent = 0.0;
+        client.replace_simulator(new_sim);
+        for _ in 0..10 {
+            time += delta;
+            client.update(time);
+            server.update(time);
+        }
+        assert!(client.is_connected());
+        assert_eq!(server.num_connected_clients(), 1);
+        assert_eq!(server.client_addr(idx), Some(client.addr()));
+        assert_eq!(server.stats().migrations, 1);
+
+        client.send(b"still here").unwrap();
+        server.update(time);
+        assert_eq!(server.recv(), Some((b"still here".to_vec(), idx)));
+    }
+
+    #[test]
+    fn migration_requires_the_challenge_echo() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        let server_addr = server_sim.addr();
+        let cfg = ServerConfig::default().allow_migration(true);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+
+        let token = server.token(123u64).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        let idx = server.iter_clients().next().unwrap();
+        let client_addr = server.client_addr(idx).unwrap();
+
+        // an attacker on the path captures the client's packets and forwards them from its own address
+        let mut attacker = NetworkSimulator::new(40005, routing_table.clone());
+        attacker.cfg.packet_loss_percent = 0.0;
+        for _ in 0..10 {
+            time += delta;
+            client.send(b"hi").unwrap();
+            client.update(time);
+            let captured: Vec<_> = routing_table.borrow()[&50000].rx.try_iter().collect();
+            for entry in captured {
+                attacker.send(&entry.packet, server_addr).unwrap();
+            }
+            server.update(time);
+        }
+        assert_eq!(server.client_addr(idx), Some(client_addr));
+        assert_eq!(server.stats().migrations, 0);
+        // the path challenges went to the attacker, which can't answer them
+        let challenges: Vec<_> = routing_table.borrow()[&40005].rx.try_iter().collect();
+        assert!(!challenges.is_empty());
+    }
+
+    #[test]
+    fn migration_is_opt_in() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+
+        let token = server.token(123u64).timeout_seconds(1).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        client.replace_simulator(NetworkSimulator::new(40001, routing_table.clone()));
+        for _ in 0..20 {
+            time += delta;
+            client.update(time);
+            server.update(time);
+        }
+        assert_eq!(server.num_connected_clients(), 0);
+        assert_eq!(server.stats().migrations, 0);
+    }
+
+    #[test]
+    fn coalesced_payloads() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        client_sim.cfg.latency_ms = 0.0;
+        client_sim.cfg.jitter_ms = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        server_sim.cfg.latency_ms = 0.0;
+        server_sim.cfg.jitter_ms = 0.0;
+        let cfg = ServerConfig::default().coalesce_payloads(true);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+
+        let token = server.token(123u64).generate().unwrap();
+        let cfg = ClientConfig::default().coalesce_payloads(true);
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, client_sim)
+                .unwrap();
+        client.connect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        let idx = server.iter_clients().next().unwrap();
+
+        assert!(client.send(&[0; MAX_PACKET_SIZE - 1]).is_err());
+        let packets_before = server.protocol_stats(0).unwrap().packets_received;
+        for i in 0..10u8 {
+            client.send(&[i; 100]).unwrap();
+        }
+        // nothing is sent until the queue is flushed
+        server.update(time);
+        assert_eq!(server.recv(), None);
+        client.flush(time).unwrap();
+        server.update(time);
+        for i in 0..10u8 {
+            assert_eq!(server.recv(), Some((vec![i; 100], idx)));
+        }
+        assert_eq!(server.recv(), None);
+        // 1000 bytes of payloads fit in one packet
+        assert_eq!(
+            server.protocol_stats(0).unwrap().packets_received,
+            packets_before + 1
+        );
+
+        // a payload that doesn't fit in the queued packet goes into the next one
+        server.send(&[1; 1000], idx).unwrap();
+        server.send(&[2; 1000], idx).unwrap();
+        server.send(b"", idx).unwrap();
+        time += delta;
+        server.update(time);
+        client.update(time);
+        assert_eq!(client.recv(), Some(vec![1; 1000]));
+        assert_eq!(client.recv(), Some(vec![2; 1000]));
+        assert_eq!(client.recv(), Some(vec![]));
+        assert_eq!(client.recv(), None);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_payloads() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let cfg = ServerConfig::default()
+            .compress_payloads(64)
+            .coalesce_payloads(true);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+
+        let token = server.token(123u64).generate().unwrap();
+        let cfg = ClientConfig::default()
+            .compress_payloads(64)
+            .coalesce_payloads(true);
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, client_sim)
+                .unwrap();
+        client.connect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        let idx = server.iter_clients().next().unwrap();
+
+        assert!(server.send(&[0; MAX_PACKET_SIZE], idx).is_err());
+        let snapshot: Vec<u8> = (0..1000u32).map(|i| (i / 50) as u8).collect();
+        server.send(&snapshot, idx).unwrap();
+        server.send(b"small", idx).unwrap();
+        let mut received = Vec::new();
+        for _ in 0..10 {
+            time += delta;
+            server.update(time);
+            client.update(time);
+            received.extend(core::iter::from_fn(|| client.recv()));
+        }
+        assert_eq!(received, [snapshot.clone(), b"small".to_vec()]);
+        assert!(client.stats().largest_payload_received < 200);
+
+        // payloads are coalesced before they are compressed
+        let bytes_before = server.protocol_stats(0).unwrap().bytes_received;
+        client.send(&snapshot).unwrap();
+        client.send(&snapshot[..500]).unwrap();
+        client.flush(time).unwrap();
+        let mut received = Vec::new();
+        for _ in 0..10 {
+            time += delta;
+            server.update(time);
+            received.extend(core::iter::from_fn(|| server.recv()));
+        }
+        assert_eq!(
+            received,
+            [(snapshot.clone(), idx), (snapshot[..500].to_vec(), idx)]
+        );
+        assert!(server.protocol_stats(0).unwrap().bytes_received - bytes_before < 1000);
+    }
+
+    #[test]
+    fn payload_acks() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let cfg = ServerConfig::default().ack_payloads(true);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+
+        let token = server.token(123u64).generate().unwrap();
+        let cfg = ClientConfig::default().ack_payloads(true);
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, client_sim)
+                .unwrap();
+        client.connect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        let idx = server.iter_clients().next().unwrap();
+        assert_eq!(client.last_payload_sequence(), None);
+        assert!(client.send(&[0; MAX_PACKET_SIZE - 11]).is_err());
+
+        client.send(b"input").unwrap();
+        let input_sequence = client.last_payload_sequence().unwrap();
+        // the server acks with its next payload
+        let mut received = Vec::new();
+        let mut acked = Vec::new();
+        for _ in 0..10 {
+            time += delta;
+            server.update(time);
+            received.extend(core::iter::from_fn(|| server.recv()));
+            if received.len() == 1 && server.last_payload_sequence(idx).is_none() {
+                server.send(b"snapshot", idx).unwrap();
+            }
+            client.update(time);
+            acked.extend(client.acks());
+        }
+        assert_eq!(received, [(b"input".to_vec(), idx)]);
+        assert_eq!(acked, [input_sequence]);
+        assert_eq!(client.recv(), Some(b"snapshot".to_vec()));
+        assert_eq!(server.acks(idx).count(), 0);
+
+        let snapshot_sequence = server.last_payload_sequence(idx).unwrap();
+        client.send(b"input").unwrap();
+        for _ in 0..10 {
+            time += delta;
+            client.update(time);
+            server.update(time);
+            acked.extend(server.acks(idx));
+        }
+        assert_eq!(acked, [input_sequence, snapshot_sequence]);
+    }
+
+    #[test]
+    fn server_handle_queues_calls() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server = Server::with_simulator(server_sim, None).unwrap();
+        let token = server.token(123u64).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        let idx = server.iter_clients().next().unwrap();
+
+        let handle = server.handle();
+        std::thread::scope(|scope| {
+            let handle = handle.clone();
+            scope.spawn(move || {
+                for i in 0..3u8 {
+                    handle.send(&[i; 10], idx).unwrap();
+                }
+            });
+        });
+        // nothing is sent until the server updates
+        client.update(time);
+        assert_eq!(client.recv(), None);
+        for _ in 0..10 {
+            server.update(time);
+            client.update(time);
+            time += delta;
+        }
+        for i in 0..3u8 {
+            assert_eq!(client.recv(), Some(vec![i; 10]));
+        }
+
+        // calls queued for a client don't reach the next client in its slot
+        handle.send(b"not for you", idx).unwrap();
+        handle.disconnect(idx).unwrap();
+        server.set_client_id(idx, 456);
+        for _ in 0..3 {
+            server.update(time);
+            client.update(time);
+            time += delta;
+        }
+        assert_eq!(client.recv(), None);
+        assert_eq!(server.num_connected_clients(), 1);
+        server.set_client_id(idx, 123);
+        server.update(time);
+
+        handle.disconnect(idx).unwrap();
+        // queued after the disconnect, dropped without failing the update
+        handle.send(b"too late", idx).unwrap();
+        server.try_update(time).unwrap();
+        assert_eq!(server.num_connected_clients(), 0);
+
+        drop(server);
+        assert!(matches!(
+            handle.send(b"", idx),
+            Err(crate::Error::ServerDropped)
+        ));
+    }
+
+    #[test]
+    fn connection_quality_follows_rtt() {
+        enable_logging();
+
+        // the simulator delivers packets on the next update, so the RTT follows the tick rate
+        for (delta, expected) in [
+            (1. / 60., ConnectionQuality::Good),
+            (0.3, ConnectionQuality::Bad),
+        ] {
+            let routing_table = Rc::new(RefCell::new(HashMap::new()));
+            let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+            server_sim.cfg.packet_loss_percent = 0.0;
+            server_sim.cfg.duplicate_packet_percent = 0.0;
+            let cfg = ServerConfig::default().ack_payloads(true);
+            let mut server =
+                Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+
+            let token = server.token(123u64).generate().unwrap();
+            let cfg = ClientConfig::default().ack_payloads(true);
+            let mut client = Client::with_config_and_transceiver(
+                &token.try_into_bytes().unwrap(),
+                cfg,
+                client_sim,
+            )
+            .unwrap();
+            client.connect();
+
+            let mut time = 0.0;
+            while !client.is_connected() {
+                client.update(time);
+                server.update(time);
+                time += delta;
+            }
+            assert_eq!(client.connection_quality(), ConnectionQuality::Good);
+            let idx = server.iter_clients().next().unwrap();
+            for _ in 0..120 {
+                client.send(b"input").unwrap();
+                server.send(b"snapshot", idx).unwrap();
+                client.update(time);
+                server.update(time);
+                time += delta;
+            }
+            let rtt = client.rtt().unwrap();
+            assert!((delta..delta * 2.0).contains(&rtt), "{rtt}");
+            assert_eq!(client.packet_loss(), 0.0);
+            assert_eq!(client.connection_quality(), expected);
+            let budget = match expected {
+                ConnectionQuality::Good => 30.0,
+                _ => 10.0,
+            };
+            assert_eq!(client.send_budget(), budget);
+        }
+    }
+
+    #[test]
+    fn sessions_are_rekeyed() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let cfg = ServerConfig::default()
+            .ack_payloads(true)
+            .rekey_interval(1.0);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+
+        let token = server.token(123u64).generate().unwrap();
+        let cfg = ClientConfig::default()
+            .ack_payloads(true)
+            .rekey_interval(1.0);
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, client_sim)
+                .unwrap();
+        client.connect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        let idx = server.iter_clients().next().unwrap();
+        for i in 0..50u8 {
+            client.send(&[i; 10]).unwrap();
+            server.send(&[i; 20], idx).unwrap();
+            client.update(time);
+            server.update(time);
+            time += delta;
+            // every payload arrives with the key of its epoch
+            if i > 0 {
+                assert_eq!(client.recv(), Some(vec![i - 1; 20]));
+            }
+            assert_eq!(server.recv(), Some((vec![i; 10], idx)));
+        }
+        assert!(client.is_connected());
+        assert!(client.last_payload_sequence().unwrap() > 4 << 32);
+        // each side rekeyed its own session and followed the other's
+        assert!(client.stats().rekeys >= 8, "{}", client.stats().rekeys);
+        assert!(server.stats().rekeys >= 8, "{}", server.stats().rekeys);
+        // jumping to the next epoch isn't packet loss
+        assert_eq!(client.stats().sequence_gaps, 0);
+        assert_eq!(server.stats().sequence_gaps, 0);
+        assert!(client.acks().next().is_some());
+    }
+
+    #[test]
+    fn rekeyed_packets_need_both_sides_to_rekey() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let cfg = ServerConfig::default().rekey_interval(1.0);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+
+        // like a client of the reference implementation
+        let token = server.token(123u64).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        let idx = server.iter_clients().next().unwrap();
+        let mut received = 0;
+        for i in 0..20u8 {
+            client.send(&[i; 10]).unwrap();
+            server.send(&[i; 20], idx).unwrap();
+            client.update(time);
+            server.update(time);
+            time += delta;
+            received += usize::from(client.recv().is_some());
+            assert_eq!(server.recv(), Some((vec![i; 10], idx)));
+        }
+        // the server's payloads stop arriving once it rekeyed, the client's keep arriving with the session key
+        assert!(received < 15, "{received}");
+        assert!(received > 0);
+        assert_eq!(client.stats().rekeys, 0);
+        assert!(server.stats().rekeys > 0);
+    }
+
+    #[test]
+    fn rekeyed_sessions_migrate_and_drain() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let cfg = ServerConfig::default()
+            .allow_migration(true)
+            .rekey_interval(1.0);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+
+        let token = server.token(123u64).generate().unwrap();
+        let cfg = ClientConfig::default().rekey_interval(1.0);
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, client_sim)
+                .unwrap();
+        client.connect();
+
+        let mut time = 0.0;
+        let delta = 1. / 10.;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += delta;
+        }
+        let idx = server.iter_clients().next().unwrap();
+        for i in 0..30u8 {
+            client.send(&[i; 10]).unwrap();
+            client.update(time);
+            server.update(time);
+            time += delta;
+            assert_eq!(server.recv(), Some((vec![i; 10], idx)));
+        }
+        assert!(client.stats().rekeys >= 2, "{}", client.stats().rekeys);
+
+        // the path response is sent in the client's current epoch
+        let mut new_sim = NetworkSimulator::new(40001, routing_table.clone());
+        new_sim.cfg.packet_loss_percent = 0.0;
+        new_sim.cfg.duplicate_packet_percent = 0.0;
+        client.replace_simulator(new_sim);
+        for _ in 0..10 {
+            time += delta;
+            client.update(time);
+            server.update(time);
+        }
+        assert_eq!(server.stats().migrations, 1);
+        assert_eq!(server.client_addr(idx), Some(client.addr()));
+
+        // and so is the disconnect confirmation
+        server.begin_shutdown(1.0).unwrap();
+        while server.shutdown_report().is_none() {
+            time += delta;
+            client.update(time);
+            server.update(time);
+        }
+        assert!(client.is_disconnected());
+        let report = server.shutdown_report().unwrap();
+        assert_eq!(report.acknowledged, 1);
+        assert_eq!(report.timed_out, 0);
+    }
+
+    #[test]
+    fn pending_connection_eviction() {
+        enable_logging();
+
+        for (eviction, evicted) in [
+            (PendingEviction::Oldest, 0),
+            (PendingEviction::LeastRecentlyUsed, 1),
+        ] {
+            let routing_table = Rc::new(RefCell::new(HashMap::new()));
+            let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+            server_sim.cfg.packet_loss_percent = 0.0;
+            server_sim.cfg.duplicate_packet_percent = 0.0;
+            let cfg = ServerConfig::default()
+                .max_pending_connections(2)
+                .pending_eviction(eviction);
+            let mut server =
+                Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+            let mut clients: Vec<_> = (0..3u16)
+                .map(|i| {
+                    let mut sim = NetworkSimulator::new(40000 + i, routing_table.clone());
+                    sim.cfg.packet_loss_percent = 0.0;
+                    sim.cfg.duplicate_packet_percent = 0.0;
+                    let token = server.token(i as u64).generate().unwrap();
+                    let mut cfg = ClientConfig::default();
+                    if i == 0 {
+                        // never sees its challenge, so it keeps sending connection requests
+                        cfg = cfg.allowed_packets(
+                            ClientState::SendingConnectionRequest,
+                            PacketAllowList::NONE,
+                        );
+                    }
+                    let mut client = Client::with_config_and_transceiver(
+                        &token.try_into_bytes().unwrap(),
+                        cfg,
+                        sim,
+                    )
+                    .unwrap();
+                    client.connect();
+                    client
+                })
+                .collect();
+
+            // the first client is pending the longest, the second one sent its last request the longest ago
+            for (time, i) in [(0.0, 0), (0.1, 1), (0.2, 0)] {
+                clients[i].update(time);
+                server.update(time);
+            }
+            assert_eq!(server.num_pending_connections(), 2);
+            clients[2].update(0.3);
+            server.update(0.3);
+            assert_eq!(server.num_pending_connections(), 2);
+            assert_eq!(server.stats().pending_evictions, 1);
+
+            // the evicted client's challenge response is ignored
+            let mut time = 0.4;
+            for _ in 0..10 {
+                for client in &mut clients[1..] {
+                    client.update(time);
+                }
+                server.update(time);
+                time += 0.1;
+            }
+            assert_eq!(clients[1].is_connected(), evicted != 1, "{eviction:?}");
+            assert!(clients[2].is_connected());
+            assert_eq!(
+                server.num_connected_clients(),
+                if evicted == 1 { 1 } else { 2 }
+            );
+            // only the evicted second client answers a challenge that is gone
+            let stats = server.stats();
+            assert_eq!(stats.pending_misses > 0, evicted == 1, "{eviction:?}");
+            assert!(stats.pending_hits >= 1);
+            assert_eq!(stats.pending_hit_rate() < 1.0, evicted == 1);
+        }
+    }
+
+    #[test]
+    fn connect_config_timeouts() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        // the servers are never updated, so the client never hears back
+        let server1 =
+            Server::with_simulator(NetworkSimulator::new(50000, routing_table.clone()), None)
+                .unwrap();
+        let server2 =
+            Server::with_simulator(NetworkSimulator::new(50001, routing_table.clone()), None)
+                .unwrap();
+        let token =
+            ConnectToken::build(&[server1.addr(), server2.addr()][..], 0, 0, generate_key())
+                .generate()
+                .unwrap();
+
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = sent.clone();
+        let cfg = ClientConfig::default()
+            .connect_config(
+                ConnectConfig::new()
+                    .resend_interval(0.5)
+                    .address_timeout(1.0)
+                    .total_timeout(1.5),
+            )
+            .packet_logger(move |record: &PacketRecord| {
+                log.lock().unwrap().push((record.time, record.remote_addr));
+            });
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, client_sim)
+                .unwrap();
+        client.connect();
+
+        let mut failed_at = None;
+        for tick in 0..30 {
+            let time = tick as f64 / 10.0;
+            client.update(time);
+            if client.is_error() {
+                failed_at = Some(time);
+                break;
+            }
+        }
+        // the connect attempt gives up on its total timeout, with an address left to try
+        assert_eq!(client.state(), ClientState::ConnectionRequestTimedOut);
+        assert!((1.5..=1.6).contains(&failed_at.unwrap()), "{failed_at:?}");
+
+        let sent = sent.lock().unwrap();
+        for addr in [server1.addr(), server2.addr()] {
+            let times: Vec<f64> = sent
+                .iter()
+                .filter(|(_, to)| *to == addr)
+                .map(|(time, _)| *time)
+                .collect();
+            assert!(!times.is_empty());
+            // requests are resent at the configured interval, not the packet send rate
+            assert!(
+                times.windows(2).all(|pair| pair[1] - pair[0] > 0.5),
+                "{times:?}"
+            );
+        }
+        // the client moved on to the second address after the address timeout
+        let first_to_second = sent.iter().find(|(_, to)| *to == server2.addr()).unwrap().0;
+        assert!((1.0..=1.1).contains(&first_to_second), "{first_to_second}");
+    }
+
+    #[test]
+    fn server_queries() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let private_key = generate_key();
+        let mut sims: Vec<_> = [50000, 40000, 45000]
+            .into_iter()
+            .map(|port| {
+                let mut sim = NetworkSimulator::new(port, routing_table.clone());
+                sim.cfg.packet_loss_percent = 0.0;
+                sim.cfg.duplicate_packet_percent = 0.0;
+                sim
+            })
+            .collect();
+        let browser = sims.pop().unwrap();
+        let client_sim = sims.pop().unwrap();
+        let cfg = ServerConfig::default().queries(QueryConfig::new("sim").rate_limit(2));
+        let mut server =
+            Server::with_config_and_transceiver(7, private_key, cfg, sims.pop().unwrap()).unwrap();
+        let token = server.token(1).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+        let mut time = 0.0;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+
+        let mut buf = [0; query::QUERY_REQUEST_BYTES];
+        // queries with an unknown protocol id aren't answered
+        browser.send(&query::request(8, 1), server.addr()).unwrap();
+        for nonce in 1..=3 {
+            browser
+                .send(&query::request(7, nonce), server.addr())
+                .unwrap();
+        }
+        server.update(time);
+        let mut answered = Vec::new();
+        while let Some((len, _)) = browser.recv(&mut buf).unwrap() {
+            answered.push(buf[..len].to_vec());
+        }
+        // the third query in the same second is rate limited
+        assert_eq!(answered.len(), 2);
+        assert_eq!(server.stats().queries_answered, 2);
+        assert_eq!(server.stats().queries_rate_limited, 1);
+        let info = query::read_response(&answered[0], 1, Some(&private_key)).unwrap();
+        assert_eq!(info.name, "sim");
+        assert_eq!(
+            (info.protocol_id, info.num_clients, info.max_clients),
+            (7, 1, MAX_CLIENTS)
+        );
+        assert!(query::read_response(&answered[1], 2, Some(&generate_key())).is_err());
+        // the client is unaffected
+        assert!(client.is_connected());
+
+        // servers that don't answer queries drop them
+        let mut server =
+            Server::with_simulator(NetworkSimulator::new(50001, routing_table.clone()), None)
+                .unwrap();
+        browser.send(&query::request(0, 1), server.addr()).unwrap();
+        server.update(0.0);
+        assert!(browser.recv(&mut buf).unwrap().is_none());
+        assert_eq!(server.stats().queries_answered, 0);
+    }
+
+    #[cfg(feature = "insecure")]
+    #[test]
+    fn insecure_connect() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        for allow_insecure in [true, false] {
+            let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+            server_sim.cfg.packet_loss_percent = 0.0;
+            server_sim.cfg.duplicate_packet_percent = 0.0;
+            let cfg = ServerConfig::default().allow_insecure(allow_insecure);
+            let mut server =
+                Server::with_config_and_transceiver(7, generate_key(), cfg, server_sim).unwrap();
+
+            // the token `Client::connect_insecure` makes
+            let token = ConnectToken::build(server.addr(), 7, 123, crate::crypto::INSECURE_KEY)
+                .generate()
+                .unwrap();
+            let mut client = Client::with_simulator(token, client_sim).unwrap();
+            client.connect();
+            let mut time = 0.0;
+            for _ in 0..10 {
+                client.update(time);
+                server.update(time);
+                time += 0.1;
+            }
+            assert_eq!(client.is_connected(), allow_insecure);
+            assert_eq!(server.num_connected_clients(), allow_insecure as usize);
+        }
+    }
+
+    #[test]
+    fn relayed_connection() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut sims: Vec<_> = [50000, 45000, 40000]
+            .into_iter()
+            .map(|port| {
+                let mut sim = NetworkSimulator::new(port, routing_table.clone());
+                sim.cfg.packet_loss_percent = 0.0;
+                sim.cfg.duplicate_packet_percent = 0.0;
+                sim
+            })
+            .collect();
+        let client_sim = sims.pop().unwrap();
+        let relay = sims.pop().unwrap();
+        let (private_key, relay_key) = (generate_key(), generate_key());
+        let cfg = ServerConfig::default().relay(RelayConfig::new(relay_key).allow_direct(false));
+        let mut server =
+            Server::with_config_and_transceiver(0, private_key, cfg, sims.pop().unwrap()).unwrap();
+        // the client only knows the relay
+        let token = ConnectToken::build(relay.addr(), 0, 1, private_key)
+            .internal_addresses(server.addr())
+            .unwrap()
+            .generate()
+            .unwrap();
+        let client_addr = client_sim.addr();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+
+        let mut buf = [0; 2048];
+        let mut time = 0.0;
+        for _ in 0..10 {
+            client.update(time);
+            while let Some((len, from)) = relay.recv(&mut buf).unwrap() {
+                if from == server.addr() {
+                    let (to, packet) = relay::unwrap_from_server(&relay_key, &buf[..len]).unwrap();
+                    relay.send(packet, to).unwrap();
+                } else {
+                    let datagram = relay::wrap_for_server(&relay_key, from, &buf[..len]).unwrap();
+                    relay.send(&datagram, server.addr()).unwrap();
+                }
+            }
+            server.update(time);
+            while let Some((len, from)) = relay.recv(&mut buf).unwrap() {
+                assert_eq!(from, server.addr());
+                let (to, packet) = relay::unwrap_from_server(&relay_key, &buf[..len]).unwrap();
+                relay.send(packet, to).unwrap();
+            }
+            time += 0.1;
+        }
+        assert!(client.is_connected());
+        // the session keys off the client's address, not the relay's
+        let idx = server.iter_clients().next().unwrap();
+        assert_eq!(server.client_addr(idx), Some(client_addr));
+
+        // headers made with another key are dropped
+        let datagram = relay::wrap_for_server(&generate_key(), client_addr, &[0; 100]).unwrap();
+        relay.send(&datagram, server.addr()).unwrap();
+        server.update(time);
+        assert_eq!(server.stats().invalid_relay_headers, 1);
+        assert_eq!(server.num_connected_clients(), 1);
+    }
+
+    #[test]
+    fn happy_eyeballs_connect() {
+        enable_logging();
+
+        for happy_eyeballs in [true, false] {
+            let routing_table = Rc::new(RefCell::new(HashMap::new()));
+            let mut sims: Vec<_> = [50000, 50001, 40000]
+                .into_iter()
+                .map(|port| {
+                    let mut sim = NetworkSimulator::new(port, routing_table.clone());
+                    sim.cfg.packet_loss_percent = 0.0;
+                    sim.cfg.duplicate_packet_percent = 0.0;
+                    sim
+                })
+                .collect();
+            let client_sim = sims.pop().unwrap();
+            let v4_sim = sims.pop().unwrap();
+            let mut v6_sim = sims.pop().unwrap();
+            v6_sim.ip = std::net::Ipv6Addr::LOCALHOST.into();
+            let private_key = generate_key();
+            // the IPv6 path is broken, so that server is never updated
+            let v6_server = Server::with_config_and_transceiver(
+                0,
+                private_key,
+                ServerConfig::default(),
+                v6_sim,
+            )
+            .unwrap();
+            let mut v4_server = Server::with_config_and_transceiver(
+                0,
+                private_key,
+                ServerConfig::default(),
+                v4_sim,
+            )
+            .unwrap();
+            let token =
+                ConnectToken::build(&[v6_server.addr(), v4_server.addr()][..], 0, 1, private_key)
+                    .generate()
+                    .unwrap();
+            let mut connect_config = ConnectConfig::new();
+            if happy_eyeballs {
+                connect_config = connect_config.happy_eyeballs(0.25);
+            }
+            let cfg = ClientConfig::default().connect_config(connect_config);
+            let mut client = Client::with_config_and_transceiver(
+                &token.try_into_bytes().unwrap(),
+                cfg,
+                client_sim,
+            )
+            .unwrap();
+            client.connect();
+
+            let mut time = 0.0;
+            while !client.is_connected() && time < 20.0 {
+                client.update(time);
+                v4_server.update(time);
+                time += 0.1;
+            }
+            assert!(client.is_connected());
+            assert_eq!(v4_server.num_connected_clients(), 1);
+            if happy_eyeballs {
+                assert!(time < 1.0, "{time}");
+            } else {
+                // the IPv4 address is only tried once the IPv6 one timed out
+                assert!(time > CONNECTION_TIMEOUT_SEC as f64, "{time}");
+            }
+        }
+    }
+
+    #[test]
+    fn recv_into_caller_buffers() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let private_key = generate_key();
+        let mut server = Server::with_config_and_transceiver(
+            0,
+            private_key,
+            ServerConfig::default(),
+            server_sim,
+        )
+        .unwrap();
+        let token = server.token(1).generate().unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+        let mut time = 0.0;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        let client_idx = ClientIndex(0);
+
+        let mut buf = [0; MAX_PACKET_SIZE];
+        for round in 0..3u8 {
+            client.send(&[round; 10]).unwrap();
+            client.send(&[round; 1000]).unwrap();
+            server.send(&[round; 20], client_idx).unwrap();
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+
+            assert_eq!(server.recv_into(&mut buf), Some((client_idx, 10)));
+            assert_eq!(&buf[..10], &[round; 10]);
+            // a buffer that is too small gets the start of the packet
+            let mut small = [0; 4];
+            assert_eq!(server.recv_into(&mut small), Some((client_idx, 4)));
+            assert_eq!(small, [round; 4]);
+            assert_eq!(server.recv_into(&mut buf), None);
+
+            client.update(time);
+            assert_eq!(client.recv_into(&mut buf), Some(20));
+            assert_eq!(&buf[..20], &[round; 20]);
+            assert_eq!(client.recv_into(&mut buf), None);
+        }
+    }
+
+    #[test]
+    fn per_client_recv_queues() {
+        enable_logging();
+
+        for overflow in [QueueOverflow::DropNewest, QueueOverflow::DropOldest] {
+            let routing_table = Rc::new(RefCell::new(HashMap::new()));
+            let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+            server_sim.cfg.packet_loss_percent = 0.0;
+            server_sim.cfg.duplicate_packet_percent = 0.0;
+            let cfg = ServerConfig::default()
+                .recv_queue_depth(4)
+                .recv_queue_overflow(overflow);
+            let mut server =
+                Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+            let mut clients = Vec::new();
+            for (client_id, port) in [(1, 40000), (2, 40001)] {
+                let mut client_sim = NetworkSimulator::new(port, routing_table.clone());
+                client_sim.cfg.packet_loss_percent = 0.0;
+                client_sim.cfg.duplicate_packet_percent = 0.0;
+                let token = server.token(client_id).generate().unwrap();
+                let mut client = Client::with_simulator(token, client_sim).unwrap();
+                client.connect();
+                clients.push(client);
+            }
+            let mut time = 0.0;
+            while !clients.iter().all(|client| client.is_connected()) {
+                for client in &mut clients {
+                    client.update(time);
+                }
+                server.update(time);
+                time += 0.1;
+            }
+            let chatty = ClientIndex(0);
+            let quiet = ClientIndex(1);
+            assert_eq!(server.client_id(chatty), Some(1));
+
+            for i in 0..10u8 {
+                clients[0].send(&[i]).unwrap();
+            }
+            clients[1].send(b"quiet").unwrap();
+            server.update(time);
+
+            // the chatty client's queue overflowed, without affecting the quiet one
+            assert_eq!(server.stats().recv_queue_overflows, 6);
+            assert_eq!(server.recv_from(quiet), Some(b"quiet".to_vec()));
+            assert_eq!(server.recv_from(quiet), None);
+            let kept: Vec<u8> = match overflow {
+                QueueOverflow::DropNewest => vec![0, 1, 2, 3],
+                _ => vec![6, 7, 8, 9],
+            };
+            assert_eq!(server.recv_from(chatty), Some(vec![kept[0]]));
+            // `recv` returns the rest in order, skipping what was already received
+            let rest: Vec<_> = std::iter::from_fn(|| server.recv()).collect();
+            assert_eq!(
+                rest,
+                kept[1..]
+                    .iter()
+                    .map(|&i| (vec![i], chatty))
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn challenge_data_passthrough() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let connected = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cfg = ServerConfig::with_context(connected.clone())
+            .challenge_data(|client_id, user_data, _| {
+                // e.g. the shard picked from the user data
+                let mut data = [0; CHALLENGE_DATA_BYTES];
+                data[0] = user_data[0];
+                data[1..9].copy_from_slice(&client_id.to_le_bytes());
+                data
+            })
+            .on_connect_with_data(|idx, data, connected| {
+                connected.lock().unwrap().push((idx, *data));
+            });
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let token = server
+            .token(42)
+            .user_data([7; crate::USER_DATA_BYTES])
+            .generate()
+            .unwrap();
+        let mut client = Client::with_simulator(token, client_sim).unwrap();
+        client.connect();
+        let mut time = 0.0;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+
+        let mut expected = [0; CHALLENGE_DATA_BYTES];
+        expected[0] = 7;
+        expected[1..9].copy_from_slice(&42u64.to_le_bytes());
+        assert_eq!(*connected.lock().unwrap(), [(ClientIndex(0), expected)]);
+    }
+
+    #[test]
+    fn private_key_rotation() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let keys = [generate_key(), generate_key(), generate_key()];
+        let mut server =
+            Server::with_config_and_transceiver(0, keys[0], ServerConfig::default(), server_sim)
+                .unwrap();
+        let addr = server.addr();
+        let token = |client_id, key| {
+            ConnectToken::build(addr, 0, client_id, key)
+                .generate()
+                .unwrap()
+        };
+        let tokens = [token(1, keys[0]), token(2, keys[1]), token(3, keys[2])];
+        let mut time = 0.0;
+        let mut connects = |server: &mut Server<NetworkSimulator>, token, port| {
+            let mut client_sim = NetworkSimulator::new(port, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            let mut client = Client::with_simulator(token, client_sim).unwrap();
+            client.connect();
+            for _ in 0..20 {
+                client.update(time);
+                server.update(time);
+                time += 0.1;
+            }
+            client.is_connected()
+        };
+
+        let mut rotations = keys[1..].iter().copied();
+        let mut provider = || rotations.next().ok_or(crate::Error::InvalidKey);
+        server.rotate_private_key(&mut provider).unwrap();
+        let [first, second, third] = tokens;
+        // tokens issued with the previous key are still accepted, until the next rotation
+        assert!(connects(&mut server, first, 40000));
+        server.rotate_private_key(&mut provider).unwrap();
+        assert!(connects(&mut server, second, 40001));
+        assert!(connects(&mut server, third, 40002));
+        // a provider that fails leaves the key as it is
+        assert!(server.rotate_private_key(&mut provider).is_err());
+        let first = token(5, keys[0]);
+        assert!(!connects(&mut server, first, 40003));
+        let current = server.token(6).generate().unwrap();
+        assert!(connects(&mut server, current, 40004));
+    }
+
+    #[test]
+    fn heartbeat_payloads() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let from_clients = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cfg = ServerConfig::with_context(from_clients.clone()).on_heartbeat(
+            |idx, heartbeat, from_clients| {
+                from_clients.lock().unwrap().push((idx, heartbeat.to_vec()));
+            },
+        );
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let token_bytes = server
+            .token(7)
+            .generate()
+            .unwrap()
+            .try_into_bytes()
+            .unwrap();
+        let from_server = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cfg = ClientConfig::with_context(from_server.clone()).on_heartbeat(
+            |heartbeat, from_server| {
+                from_server.lock().unwrap().push(heartbeat.to_vec());
+            },
+        );
+        let mut client =
+            Client::with_config_and_transceiver(&token_bytes, cfg, client_sim).unwrap();
+        assert!(server
+            .set_heartbeat_payload(ClientIndex(0), b"load 3")
+            .is_err());
+        client.connect();
+        let mut time = 0.0;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        // keep-alives without a heartbeat don't call the callbacks
+        for _ in 0..5 {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        assert!(from_clients.lock().unwrap().is_empty());
+        assert!(from_server.lock().unwrap().is_empty());
+
+        assert!(client
+            .set_heartbeat_payload(&[0; MAX_HEARTBEAT_BYTES + 1])
+            .is_err());
+        client.set_heartbeat_payload(b"tick 99").unwrap();
+        server
+            .set_heartbeat_payload(ClientIndex(0), b"load 3")
+            .unwrap();
+        for _ in 0..5 {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        let from_clients = core::mem::take(&mut *from_clients.lock().unwrap());
+        assert!(!from_clients.is_empty());
+        assert!(from_clients
+            .iter()
+            .all(|(idx, heartbeat)| *idx == ClientIndex(0) && heartbeat == b"tick 99"));
+        let from_server = core::mem::take(&mut *from_server.lock().unwrap());
+        assert!(!from_server.is_empty());
+        assert!(from_server.iter().all(|heartbeat| heartbeat == b"load 3"));
+    }
+
+    #[test]
+    fn reserved_slots() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server = Server::with_config_and_transceiver(
+            0,
+            generate_key(),
+            ServerConfig::default(),
+            server_sim,
+        )
+        .unwrap();
+        server.set_max_clients(2).unwrap();
+        server.reserve_slot(1, 2.0).unwrap();
+        server.reserve_slot(2, 2.0).unwrap();
+        assert_eq!(server.capacity(), 0);
+        assert!(matches!(
+            server.reserve_slot(3, 2.0),
+            Err(crate::Error::ServerFull)
+        ));
+
+        let mut time = 0.0;
+        let connect = |server: &mut Server<NetworkSimulator>, time: &mut f64, client_id, port| {
+            let mut client_sim = NetworkSimulator::new(port, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            let token = server.token(client_id).generate().unwrap();
+            let mut client = Client::with_simulator(token, client_sim).unwrap();
+            client.connect();
+            for _ in 0..5 {
+                client.update(*time);
+                server.update(*time);
+                *time += 0.1;
+            }
+            client.state()
+        };
+        // only reserved slots are left
+        assert_eq!(
+            connect(&mut server, &mut time, 3, 40000),
+            ClientState::ConnectionDenied
+        );
+        assert_eq!(
+            connect(&mut server, &mut time, 1, 40001),
+            ClientState::Connected
+        );
+        assert_eq!(server.connected_count(), 1);
+        assert_eq!(server.capacity(), 0);
+        // the reservation of a client that never connects expires
+        while time < 2.5 {
+            server.update(time);
+            time += 0.1;
+        }
+        assert_eq!(server.capacity(), 1);
+        assert_eq!(
+            connect(&mut server, &mut time, 3, 40002),
+            ClientState::Connected
+        );
+        assert_eq!(server.connected_count(), 2);
+    }
+
+    #[test]
+    fn port_unreachable() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        server_sim.cfg.port_unreachable = true;
+        let private_key = generate_key();
+        let mut server = Server::with_config_and_transceiver(
+            0,
+            private_key,
+            ServerConfig::default(),
+            server_sim,
+        )
+        .unwrap();
+        let nobody = SocketAddr::from(([127, 0, 0, 1], 50001));
+        let new_client = |port, addrs: &[SocketAddr]| {
+            let mut client_sim = NetworkSimulator::new(port, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            client_sim.cfg.port_unreachable = true;
+            let token = ConnectToken::build(addrs, 0, port as u64, private_key)
+                .generate()
+                .unwrap();
+            let mut client = Client::with_simulator(token, client_sim).unwrap();
+            client.connect();
+            client
+        };
+
+        // fails right away instead of after the connect timeout
+        let mut client = new_client(40000, &[nobody]);
+        client.update(0.0);
+        client.update(0.1);
+        assert_eq!(client.state(), ClientState::ServerUnreachable);
+        assert!(client.state().is_error());
+
+        // moves on to the next server address
+        let mut client = new_client(40001, &[nobody, server.addr()]);
+        let mut time = 0.0;
+        for _ in 0..10 {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        assert!(client.is_connected());
+
+        // a client that went away doesn't fail the server, and a server that went away is noticed by its clients
+        routing_table.borrow_mut().remove(&40001);
+        let mut other = new_client(40002, &[server.addr()]);
+        for _ in 0..10 {
+            other.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        assert!(other.is_connected());
+        routing_table.borrow_mut().remove(&50000);
+        other.update(time + 1.0);
+        other.update(time + 1.1);
+        assert_eq!(other.state(), ClientState::ServerUnreachable);
+    }
+
+    #[test]
+    fn multi_client_sessions() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut servers: Vec<_> = [50000, 50001]
+            .into_iter()
+            .map(|port| {
+                let mut server_sim = NetworkSimulator::new(port, routing_table.clone());
+                server_sim.cfg.packet_loss_percent = 0.0;
+                server_sim.cfg.duplicate_packet_percent = 0.0;
+                Server::with_config_and_transceiver(
+                    0,
+                    generate_key(),
+                    ServerConfig::default(),
+                    server_sim,
+                )
+                .unwrap()
+            })
+            .collect();
+        let mut client = MultiClient::with_transceiver(client_sim);
+        let handles: Vec<_> = servers
+            .iter_mut()
+            .map(|server| {
+                let token = server
+                    .token(7)
+                    .generate()
+                    .unwrap()
+                    .try_into_bytes()
+                    .unwrap();
+                client.connect(&token, ClientConfig::default()).unwrap()
+            })
+            .collect();
+        let mut time = 0.0;
+        let mut update = |client: &mut MultiClient<NetworkSimulator>,
+                          servers: &mut Vec<Server<NetworkSimulator>>| {
+            client.update(time);
+            for server in servers.iter_mut() {
+                server.update(time);
+            }
+            time += 0.1;
+        };
+        for _ in 0..10 {
+            update(&mut client, &mut servers);
+        }
+        assert!(handles
+            .iter()
+            .all(|&handle| client.session(handle).unwrap().is_connected()));
+
+        // each session has its own keys and sequence numbers, payloads only reach their own server
+        for (i, &handle) in handles.iter().enumerate() {
+            let session = client.session_mut(handle).unwrap();
+            session.send(format!("to {i}").as_bytes()).unwrap();
+        }
+        servers[1].send(b"from 1", ClientIndex(0)).unwrap();
+        update(&mut client, &mut servers);
+        update(&mut client, &mut servers);
+        for (i, server) in servers.iter_mut().enumerate() {
+            let (payload, _) = server.recv().unwrap();
+            assert_eq!(payload, format!("to {i}").as_bytes());
+            assert!(server.recv().is_none());
+        }
+        assert!(client.session_mut(handles[0]).unwrap().recv().is_none());
+        assert_eq!(
+            client.session_mut(handles[1]).unwrap().recv().unwrap(),
+            b"from 1"
+        );
+
+        client.disconnect(handles[0]).unwrap();
+        assert!(client.session(handles[0]).is_none());
+        for _ in 0..3 {
+            update(&mut client, &mut servers);
+        }
+        assert_eq!(servers[0].num_connected_clients(), 0);
+        assert_eq!(servers[1].num_connected_clients(), 1);
+        assert!(client.session(handles[1]).unwrap().is_connected());
+    }
+
+    #[test]
+    fn seeded_rng_reproduces_handshake() {
+        use crate::testing::SeededRng;
+
+        fn first_challenge(seed: u64) -> Vec<u8> {
+            let routing_table = Rc::new(RefCell::new(HashMap::new()));
+            let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+            let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            server_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            server_sim.cfg.duplicate_packet_percent = 0.0;
+
+            let challenges = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+            let log = challenges.clone();
+            let cfg = ServerConfig::default()
+                .rng(SeededRng::new(seed))
+                .packet_logger(move |record: &PacketRecord| {
+                    if record.direction == PacketDirection::Sent
+                        && record.packet_type == Some(PacketType::Challenge)
+                    {
+                        log.lock().unwrap().push(record.raw.to_vec());
+                    }
+                });
+            let private_key = crate::generate_key_with(&mut SeededRng::new(seed));
+            let mut server =
+                Server::with_config_and_transceiver(0, private_key, cfg, server_sim).unwrap();
+            let token = server
+                .token(123)
+                .expire_seconds(-1)
+                .generate_at_with_rng(1_700_000_000, &mut SeededRng::new(seed))
+                .unwrap();
+            let mut client = Client::with_simulator(token, client_sim).unwrap();
+            client.connect();
+
+            let mut time = 0.0;
+            while !client.is_connected() && time < 5.0 {
+                client.update(time);
+                server.update(time);
+                time += 1. / 10.;
+            }
+            assert!(client.is_connected());
+            let challenges = challenges.lock().unwrap();
+            challenges[0].clone()
+        }
+
+        assert_eq!(first_challenge(1), first_challenge(1));
+        assert_ne!(first_challenge(1), first_challenge(2));
+    }
+
+    #[test]
+    fn token_replay_store_survives_restart() {
+        enable_logging();
+
+        type Used = std::sync::Arc<std::sync::Mutex<HashMap<[u8; 16], SocketAddr>>>;
+        fn server_cfg(used: Option<Used>) -> ServerConfig<()> {
+            let Some(used) = used else {
+                return ServerConfig::default();
+            };
+            ServerConfig::default().token_replay_store(
+                move |mac: &[u8; 16], addr: SocketAddr, _expire_timestamp: u64| {
+                    Ok(*used.lock().unwrap().entry(*mac).or_insert(addr) == addr)
+                },
+            )
+        }
+        // connects a client at `port` to a freshly started server, as if the previous one was restarted
+        let connects = |port: u16, token: &[u8], private_key, cfg| {
+            let routing_table = Rc::new(RefCell::new(HashMap::new()));
+            let mut client_sim = NetworkSimulator::new(port, routing_table.clone());
+            let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            server_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            server_sim.cfg.duplicate_packet_percent = 0.0;
+            let mut server =
+                Server::with_config_and_transceiver(0, private_key, cfg, server_sim).unwrap();
+            let mut client =
+                Client::with_config_and_transceiver(token, ClientConfig::default(), client_sim)
+                    .unwrap();
+            client.connect();
+            let mut time = 0.0;
+            while !client.is_connected() && time < 3.0 {
+                client.update(time);
+                server.update(time);
+                time += 1. / 10.;
+            }
+            client.is_connected()
+        };
+
+        let private_key = generate_key();
+        let token = ConnectToken::build("127.0.0.1:50000", 0, 123, private_key)
+            .generate()
+            .unwrap()
+            .try_into_bytes()
+            .unwrap();
+
+        // the in-memory store is lost with the server, the token can be replayed from another address
+        assert!(connects(40000, &token, private_key, server_cfg(None)));
+        assert!(connects(40001, &token, private_key, server_cfg(None)));
+
+        let used = Used::default();
+        assert!(connects(
+            40000,
+            &token,
+            private_key,
+            server_cfg(Some(used.clone()))
+        ));
+        assert!(!connects(
+            40001,
+            &token,
+            private_key,
+            server_cfg(Some(used.clone()))
+        ));
+        // the client it was issued to can still use it
+        assert!(connects(
+            40000,
+            &token,
+            private_key,
+            server_cfg(Some(used.clone()))
+        ));
+        assert_eq!(used.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn connect_filter_denies_before_challenge() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let seen = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cfg = ServerConfig::with_context(seen.clone()).connect_filter(
+            |client_id, addr, user_data, seen| {
+                seen.lock()
+                    .unwrap()
+                    .push((client_id, addr.port(), user_data[0]));
+                match user_data[0] {
+                    0 => ConnectDecision::Deny(format!("client {client_id} is banned")),
+                    _ => ConnectDecision::Accept,
+                }
+            },
+        );
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let mut clients = [(1, 0, 40000), (2, 1, 40001)].map(|(client_id, user_data, port)| {
+            let token = server
+                .token(client_id)
+                .user_data([user_data; crate::USER_DATA_BYTES])
+                .generate()
+                .unwrap();
+            let mut client_sim = NetworkSimulator::new(port, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            let mut client = Client::with_simulator(token, client_sim).unwrap();
+            client.connect();
+            client
+        });
+        let mut time = 0.0;
+        for _ in 0..20 {
+            for client in clients.iter_mut() {
+                client.update(time);
+            }
+            server.update(time);
+            time += 0.1;
+        }
+
+        assert_eq!(clients[0].state(), ClientState::ConnectionDenied);
+        assert!(clients[1].is_connected());
+        assert_eq!(server.num_connected_clients(), 1);
+        assert_eq!(seen.lock().unwrap()[..2], [(1, 40000, 0), (2, 40001, 1)]);
+    }
+
+    #[test]
+    fn transfer_to_another_server() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let sim = |port| {
+            let mut sim = NetworkSimulator::new(port, routing_table.clone());
+            sim.cfg.packet_loss_percent = 0.0;
+            sim.cfg.duplicate_packet_percent = 0.0;
+            sim
+        };
+        let zone_key = generate_key();
+        let mut gateway = Server::with_config_and_transceiver(
+            0,
+            generate_key(),
+            ServerConfig::default(),
+            sim(50000),
+        )
+        .unwrap();
+        let mut zone =
+            Server::with_config_and_transceiver(0, zone_key, ServerConfig::default(), sim(50001))
+                .unwrap();
+        let transferred = std::sync::Arc::new(std::sync::Mutex::new(0));
+        let cfg = ClientConfig::with_context(transferred.clone()).on_transfer(|_, transferred| {
+            *transferred.lock().unwrap() += 1;
+        });
+        let token = gateway.token(42).generate().unwrap();
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, sim(40000))
+                .unwrap();
+        client.connect();
+        let mut time = 0.0;
+        let mut update =
+            |client: &mut Client<_, _>, gateway: &mut Server<_>, zone: &mut Server<_>| {
+                client.update(time);
+                gateway.update(time);
+                zone.update(time);
+                time += 0.1;
+            };
+        while !client.is_connected() {
+            update(&mut client, &mut gateway, &mut zone);
+        }
+
+        let client_idx = gateway.iter_clients().next().unwrap();
+        let token = ConnectToken::build(zone.addr(), 0, 42, zone_key)
+            .expire_seconds(10)
+            .generate()
+            .unwrap();
+        gateway.transfer_client(client_idx, token).unwrap();
+        for _ in 0..5 {
+            update(&mut client, &mut gateway, &mut zone);
+        }
+        // the token is repeated with the keep-alives, but only handed over once
+        assert_eq!(*transferred.lock().unwrap(), 1);
+        assert!(client.is_connected());
+        let token = client.take_transfer_token().unwrap();
+        assert_eq!(client.take_transfer_token(), None);
+
+        client.reconnect(&token).unwrap();
+        for _ in 0..10 {
+            update(&mut client, &mut gateway, &mut zone);
+        }
+        assert!(client.is_connected());
+        let zone_idx = zone.iter_clients().next().unwrap();
+        assert_eq!(zone.client_id(zone_idx), Some(42));
+        assert_eq!(gateway.num_connected_clients(), 0);
+    }
+
+    #[test]
+    fn client_estimates_the_server_tick() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let sim = |port| {
+            let mut sim = NetworkSimulator::new(port, routing_table.clone());
+            sim.cfg.packet_loss_percent = 0.0;
+            sim.cfg.duplicate_packet_percent = 0.0;
+            sim.cfg.latency_ms = 50.0;
+            sim.cfg.jitter_ms = 0.0;
+            sim
+        };
+        let heartbeat = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cfg = ServerConfig::with_context(heartbeat.clone())
+            .tick_sync(60.0)
+            .on_heartbeat(|_, payload, heartbeat| *heartbeat.lock().unwrap() = payload.to_vec());
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, sim(50000)).unwrap();
+        let token = server.token(1).generate().unwrap();
+        let cfg = ClientConfig::default().tick_sync(60.0);
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, sim(40000))
+                .unwrap();
+        // the stamp takes part of the heartbeat
+        assert!(client
+            .set_heartbeat_payload(&[7; MAX_HEARTBEAT_BYTES])
+            .is_err());
+        client.set_heartbeat_payload(b"client").unwrap();
+        client.connect();
+        let mut time = 0.0;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += 1.0 / 60.0;
+        }
+        let client_idx = ClientIndex(0);
+        assert!(server
+            .set_heartbeat_payload(client_idx, &[7; MAX_HEARTBEAT_BYTES])
+            .is_err());
+        server.set_tick(5000);
+
+        // payloads every tick leave no room for regular keep-alives, stamped ones are sent anyway
+        for _ in 0..300 {
+            client.send(b"input").unwrap();
+            server.send(b"snapshot", client_idx).unwrap();
+            client.update(time);
+            server.update(time);
+            while server.recv().is_some() {}
+            while client.recv().is_some() {}
+            time += 1.0 / 60.0;
+        }
+        let estimate = client.estimated_server_tick().unwrap();
+        assert!((estimate - server.tick() as f64).abs() < 2.0);
+        assert!(server.tick() > 5290);
+        assert_eq!(*heartbeat.lock().unwrap(), b"client");
+    }
+
+    #[test]
+    fn padded_payloads() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let sim = |port| {
+            let mut sim = NetworkSimulator::new(port, routing_table.clone());
+            sim.cfg.packet_loss_percent = 0.0;
+            sim.cfg.duplicate_packet_percent = 0.0;
+            sim
+        };
+        let buckets = [128, 512];
+        let cfg = ServerConfig::default().pad_payloads(&buckets);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, sim(50000)).unwrap();
+        let token = server.token(1).generate().unwrap();
+        let sizes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = sizes.clone();
+        let cfg = ClientConfig::default()
+            .pad_payloads(&buckets)
+            .packet_logger(move |record: &PacketRecord| {
+                if let (PacketDirection::Sent, Some(payload)) = (record.direction, record.payload) {
+                    log.lock().unwrap().push(payload.len());
+                }
+            });
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, sim(40000))
+                .unwrap();
+        client.connect();
+        let mut time = 0.0;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        assert_eq!(client.max_payload_size(), MAX_PACKET_SIZE);
+        assert!(client.send(&[1; MAX_PACKET_SIZE - 1]).is_err());
+
+        let payloads = [vec![1; 5], vec![2; 300], vec![3; MAX_PACKET_SIZE - 2]];
+        for payload in &payloads {
+            client.send(payload).unwrap();
+        }
+        server.send(b"snapshot", ClientIndex(0)).unwrap();
+        for _ in 0..5 {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        // each payload is padded to the bucket it fits in, or to the max payload size
+        assert_eq!(*sizes.lock().unwrap(), [128, 512, MAX_PACKET_SIZE]);
+        for payload in &payloads {
+            assert_eq!(server.recv().unwrap().0, *payload);
+        }
+        assert_eq!(client.recv().unwrap(), b"snapshot");
+    }
+
+    #[test]
+    fn server_recommends_keep_alive_interval() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let sim = |port| {
+            let mut sim = NetworkSimulator::new(port, routing_table.clone());
+            sim.cfg.packet_loss_percent = 0.0;
+            sim.cfg.duplicate_packet_percent = 0.0;
+            sim
+        };
+        let cfg = ServerConfig::default().keep_alive_interval(2.0);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, sim(50000)).unwrap();
+        let keep_alives = std::sync::Arc::new(std::sync::Mutex::new(0));
+        let log = keep_alives.clone();
+        let cfg = ClientConfig::default().packet_logger(move |record: &PacketRecord| {
+            if let (PacketDirection::Sent, Some(PacketType::KeepAlive)) =
+                (record.direction, record.packet_type)
+            {
+                *log.lock().unwrap() += 1;
+            }
+        });
+        let token = server.token(1).generate().unwrap();
+        let mut desktop =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, sim(40000))
+                .unwrap();
+        // a mobile client keeps a shorter interval than the server recommends
+        let cfg = ClientConfig::default().keep_alive_interval(0.5);
+        let token = server.token(2).generate().unwrap();
+        let mut mobile =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, sim(40001))
+                .unwrap();
+        desktop.connect();
+        mobile.connect();
+        let mut time = 0.0;
+        while !(desktop.is_connected() && mobile.is_connected()) {
+            desktop.update(time);
+            mobile.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        assert_eq!(desktop.keep_alive_interval(), crate::PACKET_SEND_RATE_SEC);
+        for _ in 0..10 {
+            desktop.update(time);
+            mobile.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        assert_eq!(desktop.keep_alive_interval(), 2.0);
+        assert_eq!(mobile.keep_alive_interval(), 0.5);
+
+        *keep_alives.lock().unwrap() = 0;
+        for _ in 0..200 {
+            desktop.update(time);
+            mobile.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        // 20 seconds at the recommended interval, instead of 200 keep-alives at the packet send rate
+        assert!((10..=11).contains(&*keep_alives.lock().unwrap()));
+        assert!(desktop.is_connected() && mobile.is_connected());
+
+        desktop.disconnect().unwrap();
+        assert_eq!(desktop.keep_alive_interval(), crate::PACKET_SEND_RATE_SEC);
+    }
+
+    #[test]
+    fn replay_recorded_server() {
+        enable_logging();
+
+        struct SharedBuf(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+        impl std::io::Write for SharedBuf {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let sim = |port| {
+            let mut sim = NetworkSimulator::new(port, routing_table.clone());
+            sim.cfg.packet_loss_percent = 10.0;
+            sim.cfg.duplicate_packet_percent = 0.0;
+            sim
+        };
+        let private_key = generate_key();
+        let buf = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let cfg = ServerConfig::default().record(Recorder::new(SharedBuf(buf.clone())));
+        let mut server =
+            Server::with_config_and_transceiver(0, private_key, cfg, sim(50000)).unwrap();
+        let mut clients = (0..3)
+            .map(|i| {
+                let token = server.token(i).generate().unwrap();
+                let mut client = Client::with_config_and_transceiver(
+                    &token.try_into_bytes().unwrap(),
+                    ClientConfig::default(),
+                    sim(40000 + i as u16),
+                )
+                .unwrap();
+                client.connect();
+                client
+            })
+            .collect::<Vec<_>>();
+
+        let mut received = Vec::new();
+        let mut time = 0.0;
+        for step in 0..100u8 {
+            for (i, client) in clients.iter_mut().enumerate() {
+                client.update(time);
+                if client.is_connected() {
+                    client.send(&[i as u8, step]).unwrap();
+                }
+            }
+            server.update(time);
+            while let Some((payload, client_idx)) = server.recv() {
+                received.push((step, payload, client_idx));
+            }
+            time += 1.0 / 30.0;
+        }
+        assert_eq!(server.num_connected_clients(), 3);
+        assert!(received.len() > 100);
+
+        let recording = Recording::read_from(&buf.lock().unwrap()[..]).unwrap();
+        assert_eq!(recording.num_updates(), 100);
+        let mut replay = Server::replay(recording, private_key).unwrap();
+        let mut replayed = Vec::new();
+        let mut step = 0;
+        while replay.replay_update().unwrap().is_some() {
+            while let Some((payload, client_idx)) = replay.recv() {
+                replayed.push((step, payload, client_idx));
+            }
+            step += 1;
+        }
+        assert_eq!(replayed, received);
+        assert_eq!(replay.num_connected_clients(), 3);
+    }
+
+    #[test]
+    fn client_rebinds_after_socket_failure() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let sim = |port| {
+            let mut sim = NetworkSimulator::new(port, routing_table.clone());
+            sim.cfg.packet_loss_percent = 0.0;
+            sim.cfg.duplicate_packet_percent = 0.0;
+            sim
+        };
+        let cfg = ServerConfig::default().allow_migration(true);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, sim(50000)).unwrap();
+        let token = server.token(1).generate().unwrap();
+        let rebinds = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = rebinds.clone();
+        let cfg = ClientConfig::default().on_rebind(move |old, new, _| {
+            log.lock().unwrap().push((old, new));
+        });
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, sim(40000))
+                .unwrap();
+        client.connect();
+        let mut time = 0.0;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+
+        // the interface goes down: the client moves to a new port and keeps its session
+        client.transceiver().interface_down.set(true);
+        client.try_update(time).unwrap();
+        let new_addr = client.addr();
+        assert_eq!(
+            *rebinds.lock().unwrap(),
+            [("127.0.0.1:40000".parse().unwrap(), new_addr)]
+        );
+        assert_eq!(client.stats().rebinds, 1);
+        for _ in 0..5 {
+            server.update(time);
+            client.update(time);
+            time += 0.1;
+        }
+        assert!(client.is_connected());
+        assert_eq!(server.client_addr(ClientIndex(0)), Some(new_addr));
+        client.send(b"still here").unwrap();
+        server.update(time);
+        assert_eq!(server.recv().unwrap().0, b"still here");
+
+        // without rebinding the error fails the update
+        let token = server.token(2).generate().unwrap();
+        let cfg = ClientConfig::default().rebind_on_error(false);
+        let mut client =
+            Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, sim(40010))
+                .unwrap();
+        client.connect();
+        client.transceiver().interface_down.set(true);
+        assert!(client.try_update(time).is_err());
+    }
+
+    #[test]
+    fn pending_connections_time_out() {
+        enable_logging();
+
+        for (pending_timeout, timed_out_at) in [(Some(1.0), 1.1), (None, 2.1)] {
+            let routing_table = Rc::new(RefCell::new(HashMap::new()));
+            let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+            server_sim.cfg.packet_loss_percent = 0.0;
+            server_sim.cfg.duplicate_packet_percent = 0.0;
+            let mut cfg = ServerConfig::default();
+            if let Some(timeout) = pending_timeout {
+                cfg = cfg.pending_timeout(timeout);
+            }
+            let mut server =
+                Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+            let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            // without a pending timeout, the token's timeout is used
+            let token = server.token(1u64).timeout_seconds(2).generate().unwrap();
+            let mut client = Client::with_config_and_transceiver(
+                &token.try_into_bytes().unwrap(),
+                ClientConfig::default(),
+                client_sim,
+            )
+            .unwrap();
+            client.connect();
+
+            // the client gives up after its first connection request
+            client.update(0.0);
+            server.update(0.0);
+            assert_eq!(server.stats().pending_connections, 1);
+            server.update(timed_out_at - 0.2);
+            assert_eq!(server.num_pending_connections(), 1, "{pending_timeout:?}");
+            server.update(timed_out_at);
+            let stats = server.stats();
+            assert_eq!(stats.pending_connections, 0, "{pending_timeout:?}");
+            assert_eq!(stats.pending_timeouts, 1);
+            assert_eq!(stats.pending_evictions, 0);
+        }
+    }
+
+    #[test]
+    fn duplicate_client_id_policies() {
+        enable_logging();
+
+        for (policy, max_clients, first_connected, second_connected) in [
+            (DuplicateClientId::RejectNew, 2, true, false),
+            // the second client takes over the slot of the first one, even though the server is full
+            (DuplicateClientId::KickExisting, 1, false, true),
+            (DuplicateClientId::AllowBoth, 2, true, true),
+        ] {
+            let routing_table = Rc::new(RefCell::new(HashMap::new()));
+            let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+            server_sim.cfg.packet_loss_percent = 0.0;
+            server_sim.cfg.duplicate_packet_percent = 0.0;
+            let cfg = ServerConfig::default().duplicate_client_id(policy);
+            let mut server =
+                Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+            server.set_max_clients(max_clients).unwrap();
+            let disconnects = std::sync::Arc::new(std::sync::Mutex::new(0));
+            let mut clients: Vec<_> = (0..2u16)
+                .map(|i| {
+                    let mut sim = NetworkSimulator::new(40000 + i, routing_table.clone());
+                    sim.cfg.packet_loss_percent = 0.0;
+                    sim.cfg.duplicate_packet_percent = 0.0;
+                    // both clients connect with the same client id, e.g. a game that restarted after a crash
+                    let token = server.token(7u64).generate().unwrap();
+                    let disconnects = disconnects.clone();
+                    let cfg = ClientConfig::default().on_state_change(move |_, to, _| {
+                        if to == ClientState::Disconnected {
+                            *disconnects.lock().unwrap() += 1;
+                        }
+                    });
+                    Client::with_config_and_transceiver(&token.try_into_bytes().unwrap(), cfg, sim)
+                        .unwrap()
+                })
+                .collect();
+
+            let mut time = 0.0;
+            clients[0].connect();
+            for _ in 0..10 {
+                clients[0].update(time);
+                server.update(time);
+                time += 0.1;
+            }
+            assert!(clients[0].is_connected());
+            clients[1].connect();
+            for _ in 0..20 {
+                for client in &mut clients {
+                    client.update(time);
+                }
+                server.update(time);
+                time += 0.1;
+            }
+            assert_eq!(clients[0].is_connected(), first_connected, "{policy:?}");
+            assert_eq!(clients[1].is_connected(), second_connected, "{policy:?}");
+            assert_eq!(
+                server.num_connected_clients(),
+                usize::from(first_connected) + usize::from(second_connected)
+            );
+            // the kicked client is told it was disconnected
+            assert_eq!(*disconnects.lock().unwrap(), usize::from(!first_connected));
+        }
+    }
+
+    #[test]
+    fn send_outcomes() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let mut server = Server::with_config_and_transceiver(
+            0,
+            generate_key(),
+            ServerConfig::default(),
+            server_sim,
+        )
+        .unwrap();
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let token = server.token(1u64).generate().unwrap();
+        let mut client = Client::with_config_and_transceiver(
+            &token.try_into_bytes().unwrap(),
+            ClientConfig::default(),
+            client_sim,
+        )
+        .unwrap();
+        assert_eq!(client.send(b"early").unwrap(), SendOutcome::NotConnected);
+        client.connect();
+        let mut time = 0.0;
+        while !client.is_connected() {
+            client.update(time);
+            server.update(time);
+            time += 0.1;
+        }
+        let idx = ClientIndex(0);
+        assert!(matches!(client.send(b"hello").unwrap(), SendOutcome::Sent(len) if len > 5));
+        assert!(matches!(server.send(b"hello", idx).unwrap(), SendOutcome::Sent(len) if len > 5));
+        assert_eq!(
+            server.send(b"nobody", ClientIndex(1)).unwrap(),
+            SendOutcome::NotConnected
+        );
+
+        // the send buffer fills up: packets are queued and sent in order once it drains
+        client.transceiver().send_buffer_full.set(true);
+        for payload in [b"one", b"two"] {
+            assert_eq!(client.send(payload).unwrap(), SendOutcome::QueuedWouldBlock);
+        }
+        client.transceiver().send_buffer_full.set(false);
+        assert!(matches!(
+            client.send(b"three").unwrap(),
+            SendOutcome::Sent(_)
+        ));
+        server.update(time + 1.0);
+        let received: Vec<_> = std::iter::from_fn(|| server.recv().map(|(p, _)| p)).collect();
+        assert_eq!(received, [&b"hello"[..], b"one", b"two", b"three"]);
+        assert_eq!(client.stats().packets_would_block, 2);
+
+        // only the newest packets are kept while the buffer stays full
+        client.transceiver().send_buffer_full.set(true);
+        for i in 0..100u8 {
+            assert_eq!(client.send(&[i]).unwrap(), SendOutcome::QueuedWouldBlock);
+        }
+        client.transceiver().send_buffer_full.set(false);
+        client.flush(time + 1.0).unwrap();
+        server.update(time + 2.0);
+        let received: Vec<_> = std::iter::from_fn(|| server.recv().map(|(p, _)| p)).collect();
+        assert_eq!(received.len(), 64);
+        assert_eq!(received[0], [36]);
+        let stats = client.stats();
+        assert_eq!(
+            (stats.packets_would_block, stats.would_block_drops),
+            (102, 36)
+        );
+    }
+
+    #[test]
+    fn paced_packets() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let tick_interval = 0.05;
+        let cfg = ServerConfig::default().pace_packets(tick_interval, 4);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let mut client_sim = NetworkSimulator::new(40000, routing_table.clone());
+        client_sim.cfg.packet_loss_percent = 0.0;
+        client_sim.cfg.duplicate_packet_percent = 0.0;
+        let token = server.token(1u64).generate().unwrap();
+        let mut client = Client::with_config_and_transceiver(
+            &token.try_into_bytes().unwrap(),
+            ClientConfig::default(),
+            client_sim,
+        )
+        .unwrap();
+        client.connect();
+        let mut time = 0.0;
+        // until the server heard a keep-alive from the client, payloads are sent with one
+        while !client.is_connected() || time < 0.5 {
+            client.update(time);
+            server.update(time);
+            server.run_pacer(time).unwrap();
+            time += tick_interval;
+        }
+        client.update(time);
+        while client.recv().is_some() {}
+
+        // packets are held back and leave one at a time over the tick
+        server.update(time);
+        let outcomes: Vec<_> = (0..5u8)
+            .map(|i| server.send(&[i], ClientIndex(0)).unwrap())
+            .collect();
+        assert_eq!(outcomes[0], SendOutcome::Paced);
+        assert_eq!(outcomes[4], SendOutcome::DroppedOverBudget);
+        assert_eq!(server.stats().pacing_drops, 1);
+        let paced = outcomes
+            .iter()
+            .filter(|&&outcome| outcome == SendOutcome::Paced)
+            .count();
+        client.update(time);
+        assert_eq!(client.recv(), None);
+        let mut due = server.pace(time).unwrap();
+        let mut releases = 1;
+        while let Some(next) = due {
+            assert!(next > time && next < time + tick_interval);
+            due = server.pace(next).unwrap();
+            releases += 1;
+        }
+        assert!(releases >= paced);
+        client.update(time);
+        let received: Vec<_> = std::iter::from_fn(|| client.recv()).collect();
+        assert_eq!(received.len(), paced);
+
+        // packets left at the end of a tick are sent when the next one starts
+        server.update(time + tick_interval);
+        assert_eq!(
+            server.send(b"late", ClientIndex(0)).unwrap(),
+            SendOutcome::Paced
+        );
+        server.update(time + 2.0 * tick_interval);
+        client.update(time + 2.0 * tick_interval);
+        assert_eq!(client.recv().unwrap(), b"late");
+    }
+
+    #[test]
+    fn admin_console() {
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let mut server_sim = NetworkSimulator::new(50000, routing_table.clone());
+        server_sim.cfg.packet_loss_percent = 0.0;
+        server_sim.cfg.duplicate_packet_percent = 0.0;
+        let logged = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let count = logged.clone();
+        let cfg = ServerConfig::default().packet_logger(move |_: &PacketRecord| {
+            count.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        let mut time = 0.0;
+        let connect = |server: &mut Server<NetworkSimulator>, time: &mut f64, port| {
+            let mut client_sim = NetworkSimulator::new(port, routing_table.clone());
+            client_sim.cfg.packet_loss_percent = 0.0;
+            client_sim.cfg.duplicate_packet_percent = 0.0;
+            let token = server.token(7u64).generate().unwrap();
+            let mut client = Client::with_config_and_transceiver(
+                &token.try_into_bytes().unwrap(),
+                ClientConfig::default(),
+                client_sim,
+            )
+            .unwrap();
+            client.connect();
+            let start = *time;
+            while *time < start + 1.0 && !client.is_connected() {
+                client.update(*time);
+                server.update(*time);
+                *time += 0.1;
+            }
+            client.update(*time);
+            client
+        };
+
+        let client = connect(&mut server, &mut time, 40000);
+        assert!(client.is_connected());
+        let clients = server.admin_command("clients");
+        assert!(clients.starts_with("0 id=7 addr=127.0.0.1:40000 "));
+        assert!(clients.ends_with("\nok\n"));
+        assert!(server.admin_command("help").contains("rotate-keys"));
+        assert_eq!(
+            server.admin_command("kick 1"),
+            "error: no client is connected at index 1\n"
+        );
+        assert_eq!(
+            server.admin_command("pace 10"),
+            "error: packets aren't paced\n"
+        );
+
+        // a bandwidth limit drops payloads over it
+        assert_eq!(server.admin_command("limit 0 100"), "ok\n");
+        assert!(server.admin_command("clients").contains(" limit=100B/s\n"));
+        let idx = server.iter_clients().next().unwrap();
+        assert!(matches!(
+            server.send(&[0; 80], idx),
+            Ok(SendOutcome::Sent(_))
+        ));
+        assert_eq!(
+            server.send(&[0; 80], idx).unwrap(),
+            SendOutcome::DroppedOverBudget
+        );
+        assert_eq!(server.stats().bandwidth_limit_drops, 1);
+        assert_eq!(server.admin_command("limit 0 off"), "ok\n");
+        assert_eq!(server.client_bandwidth_limit(idx), None);
+
+        // the packet logger can be paused
+        assert_eq!(server.admin_command("log off"), "ok\n");
+        let before = logged.load(std::sync::atomic::Ordering::Relaxed);
+        time += 1.0;
+        server.update(time);
+        assert_eq!(logged.load(std::sync::atomic::Ordering::Relaxed), before);
+        assert_eq!(server.admin_command("log on"), "ok\n");
+        time += 1.0;
+        server.update(time);
+        assert!(logged.load(std::sync::atomic::Ordering::Relaxed) > before);
+
+        // a ban disconnects the client and denies it from connecting again
+        assert_eq!(server.admin_command("ban 7"), "ok\n");
+        assert_eq!(server.num_connected_clients(), 0);
+        assert_eq!(server.admin_command("clients"), "ok\n");
+        let client = connect(&mut server, &mut time, 40001);
+        assert_eq!(client.state(), ClientState::ConnectionDenied);
+        assert_eq!(server.admin_command("unban 7"), "ok\n");
+        assert_eq!(
+            server.admin_command("unban 7"),
+            "error: client id 7 isn't banned\n"
+        );
+        let client = connect(&mut server, &mut time, 40002);
+        assert!(client.is_connected());
+        assert_eq!(server.admin_command("kick 0"), "ok\n");
+        assert_eq!(server.num_connected_clients(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn admin_socket() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::os::unix::net::UnixStream;
+
+        enable_logging();
+
+        let routing_table = Rc::new(RefCell::new(HashMap::new()));
+        let server_sim = NetworkSimulator::new(50000, routing_table);
+        let path = std::env::temp_dir().join(format!("netcode-admin-{}.sock", std::process::id()));
+        let cfg = ServerConfig::default().admin_socket(&path);
+        let mut server =
+            Server::with_config_and_transceiver(0, generate_key(), cfg, server_sim).unwrap();
+        // a second server can't take over the socket
+        let cfg = ServerConfig::default().admin_socket(&path);
+        let other_sim = NetworkSimulator::new(50001, Rc::new(RefCell::new(HashMap::new())));
+        assert!(Server::with_config_and_transceiver(0, generate_key(), cfg, other_sim).is_err());
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(b"ban 9\nkick 0\n").unwrap();
+        server.update(0.0);
+        let mut reader = BufReader::new(stream);
+        let mut answer = String::new();
+        reader.read_line(&mut answer).unwrap();
+        assert_eq!(answer, "ok\n");
+        answer.clear();
+        reader.read_line(&mut answer).unwrap();
+        assert_eq!(answer, "error: no client is connected at index 0\n");
+        assert!(!server.unban(10));
+        assert!(server.unban(9));
+
+        // answers larger than the socket's buffer are sent over several updates
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(&b"help\n".repeat(1000)).unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let reader = std::thread::spawn(move || {
+            let mut answers = String::new();
+            std::io::Read::read_to_string(&mut stream, &mut answers).unwrap();
+            answers
+        });
+        let mut time = 0.0;
+        while !reader.is_finished() {
+            time += 0.01;
+            server.update(time);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let answers = reader.join().unwrap();
+        assert_eq!(answers, format!("{}ok\n", crate::admin::HELP).repeat(1000));
+
+        drop(server);
+        assert!(!path.exists());
+
+        // a misconfigured path isn't deleted
+        let file = std::env::temp_dir().join(format!("netcode-admin-{}.txt", std::process::id()));
+        std::fs::write(&file, "not a socket").unwrap();
+        let cfg = ServerConfig::default().admin_socket(&file);
+        let sim = NetworkSimulator::new(50002, Rc::new(RefCell::new(HashMap::new())));
+        assert!(Server::with_config_and_transceiver(0, generate_key(), cfg, sim).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "not a socket");
+        std::fs::remove_file(&file).unwrap();
+    }
 }
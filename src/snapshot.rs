@@ -0,0 +1,139 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Tracks which snapshots the other end of a connection has received, so new snapshots can be delta-compressed
+/// against one it is known to have.
+///
+/// Payloads are unreliable, and a delta against a snapshot that was lost can't be decoded. A channel prefixes each snapshot
+/// with its sequence and the sequence of the latest snapshot received from the other end ([`HEADER_SIZE`](SnapshotChannel::HEADER_SIZE) bytes).
+/// [`acked`](SnapshotChannel::acked) is then the latest of the channel's own snapshots that the other end reported back,
+/// the baseline to delta-compress the next snapshot against.
+///
+/// Both ends need a channel (the server one per client), and acks travel with the snapshots sent the other way.
+/// An end with nothing to send (e.g. a spectator) acks by writing empty snapshots. <br>
+/// Snapshots that arrive out of order are dropped, since a newer one was already received.
+/// For acks of individual payload packets instead, see [`ClientConfig::ack_payloads`](crate::ClientConfig::ack_payloads).
+///
+/// # Example
+/// ```
+/// use netcode::SnapshotChannel;
+///
+/// let mut server_channel = SnapshotChannel::new();
+/// let mut client_channel = SnapshotChannel::new();
+///
+/// // the server sends snapshot 1, which is lost, and snapshot 2
+/// let _lost = server_channel.write(b"snapshot 1");
+/// let payload = server_channel.write(b"snapshot 2");
+/// let received = client_channel.read(&payload).unwrap();
+/// assert_eq!((received.sequence, received.data), (2, &b"snapshot 2"[..]));
+///
+/// // the client acks with its next input
+/// let payload = client_channel.write(b"input");
+/// server_channel.read(&payload).unwrap();
+/// assert_eq!(server_channel.acked(), Some(2)); // delta-compress against snapshot 2 from now on
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SnapshotChannel {
+    // sequences start at 1, 0 means none
+    sequence: u32,
+    received: u32,
+    acked: u32,
+}
+
+/// A snapshot read by a [`SnapshotChannel`](SnapshotChannel).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ReceivedSnapshot<'p> {
+    /// The sequence the other end wrote the snapshot with.
+    pub sequence: u32,
+    /// The snapshot, without the channel's header.
+    pub data: &'p [u8],
+}
+
+impl SnapshotChannel {
+    /// The bytes a channel adds to each snapshot.
+    pub const HEADER_SIZE: usize = 2 * size_of::<u32>();
+
+    /// Creates a channel that hasn't sent or received any snapshot.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Gets the sequence the next [written](SnapshotChannel::write) snapshot will have, to keep it as a baseline until it is acked.
+    pub fn next_sequence(&self) -> u32 {
+        self.sequence.wrapping_add(1).max(1)
+    }
+    /// Prefixes `snapshot` with the channel's header, returning the payload to send to the other end.
+    pub fn write(&mut self, snapshot: &[u8]) -> Vec<u8> {
+        self.sequence = self.next_sequence();
+        let mut payload = Vec::with_capacity(Self::HEADER_SIZE + snapshot.len());
+        payload.extend_from_slice(&self.sequence.to_le_bytes());
+        payload.extend_from_slice(&self.received.to_le_bytes());
+        payload.extend_from_slice(snapshot);
+        payload
+    }
+    /// Reads a payload written by the other end's channel, recording its ack.
+    ///
+    /// Returns `None` if the payload is too small to have been written by a channel,
+    /// or if the snapshot is not newer than the last one received.
+    pub fn read<'p>(&mut self, payload: &'p [u8]) -> Option<ReceivedSnapshot<'p>> {
+        let (header, data) = payload.split_at_checked(Self::HEADER_SIZE)?;
+        let sequence = u32::from_le_bytes(header[..4].try_into().ok()?);
+        let ack = u32::from_le_bytes(header[4..].try_into().ok()?);
+        // an ack for a snapshot that wasn't sent yet is bogus
+        if ack > self.acked && ack <= self.sequence {
+            self.acked = ack;
+        }
+        if sequence <= self.received {
+            return None;
+        }
+        self.received = sequence;
+        Some(ReceivedSnapshot { sequence, data })
+    }
+    /// Gets the sequence of the latest snapshot the other end acknowledged, `None` if it hasn't acknowledged any yet.
+    pub fn acked(&self) -> Option<u32> {
+        (self.acked != 0).then_some(self.acked)
+    }
+    /// Gets the sequence of the latest snapshot received from the other end, `None` if none was received yet.
+    pub fn received(&self) -> Option<u32> {
+        (self.received != 0).then_some(self.received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acks_follow_received_snapshots() {
+        let mut a = SnapshotChannel::new();
+        let mut b = SnapshotChannel::new();
+        assert_eq!(a.next_sequence(), 1);
+
+        let first = a.write(b"1");
+        let second = a.write(b"2");
+        assert_eq!(second.len(), SnapshotChannel::HEADER_SIZE + 1);
+        assert_eq!(b.read(&second).unwrap().data, b"2");
+        // the first snapshot arrives late and is dropped
+        assert_eq!(b.read(&first), None);
+        assert_eq!(b.read(&second), None);
+        assert_eq!(b.received(), Some(2));
+
+        // nothing was acked until b writes back
+        assert_eq!(a.acked(), None);
+        let ack = b.write(&[]);
+        assert_eq!(a.read(&ack).unwrap().data, b"");
+        assert_eq!(a.acked(), Some(2));
+
+        // a stale ack doesn't move the baseline back, a bogus one is ignored
+        let mut stale = b.write(&[]);
+        stale[4..8].copy_from_slice(&1u32.to_le_bytes());
+        a.read(&stale).unwrap();
+        assert_eq!(a.acked(), Some(2));
+        let mut bogus = b.write(&[]);
+        bogus[4..8].copy_from_slice(&100u32.to_le_bytes());
+        a.read(&bogus).unwrap();
+        assert_eq!(a.acked(), Some(2));
+
+        assert_eq!(a.read(&[0; 7]), None);
+    }
+}
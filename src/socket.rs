@@ -1,16 +1,179 @@
 use std::io::{self};
-use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs, UdpSocket};
+#[cfg(any(target_os = "linux", windows))]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex, PoisonError,
+};
 
 use socket2::{Domain, Protocol, Socket, Type};
 
+#[cfg(any(target_os = "linux", windows))]
+use crate::offload::{self, Coalesced};
 use crate::transceiver::Transceiver;
 
+/// An error from the UDP socket, see [`Error::Socket`](crate::Error::Socket).
 #[derive(thiserror::Error, Debug)]
 #[error("failed to create and bind udp socket: {0}")]
 pub struct Error(#[from] std::io::Error);
 
+impl Error {
+    /// Gets the underlying I/O error.
+    pub fn io_error(&self) -> &std::io::Error {
+        &self.0
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Socket options applied when a [`NetcodeSocket`](NetcodeSocket) is created, see [`NetcodeSocket::with_options`](NetcodeSocket::with_options).
+///
+/// * `tos` - The type of service (IPv4) or traffic class (IPv6) byte of sent packets, for DSCP marking.
+/// * `ttl` - The time to live (IPv4) or hop limit (IPv6) of sent packets.
+/// * `reuse_port` - Whether several sockets can bind the same address (`SO_REUSEPORT`), unix only.
+/// * `device` - The network interface the socket is bound to (`SO_BINDTODEVICE`), Linux only.
+/// * `segmentation_offload` - Whether batches of packets are sent and received with one syscall, Linux and Windows only.
+///
+/// Options that aren't supported on the platform make the socket creation fail, except `segmentation_offload`,
+/// which falls back to a syscall per packet. Unset options are left at the OS defaults.
+///
+/// # Example
+/// ```
+/// use netcode::{ServerConfig, SocketOptions};
+///
+/// // mark packets as expedited forwarding (DSCP 46)
+/// let cfg = ServerConfig::default().socket_options(SocketOptions::new().dscp(46));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketOptions {
+    tos: Option<u8>,
+    ttl: Option<u32>,
+    reuse_port: bool,
+    device: Option<String>,
+    segmentation_offload: bool,
+}
+
+impl SocketOptions {
+    /// Creates options that leave everything at the OS defaults.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Set the type of service (IPv4) or traffic class (IPv6) byte of sent packets. <br>
+    /// The upper six bits are the DSCP and the lower two the ECN, see [`SocketOptions::dscp`](SocketOptions::dscp).
+    /// The default is the OS default (usually 0).
+    pub fn tos(mut self, tos: u8) -> Self {
+        self.tos = Some(tos);
+        self
+    }
+    /// Set the DSCP of sent packets, e.g. 46 (EF) or 34 (AF41) for latency-sensitive game traffic. <br>
+    /// Shorthand for `tos(dscp << 2)`, only the lower six bits of `dscp` are used.
+    pub fn dscp(self, dscp: u8) -> Self {
+        self.tos((dscp & 0x3f) << 2)
+    }
+    /// Set the time to live (IPv4) or hop limit (IPv6) of sent packets. <br>
+    /// The default is the OS default (usually 64).
+    pub fn ttl(mut self, ttl: u32) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+    /// Set whether several sockets (e.g. one per process or thread) can bind the same address, with the OS spreading
+    /// the incoming packets between them (`SO_REUSEPORT`). <br>
+    /// Every socket sharing the address has to set it. Only supported on unix.
+    /// The default is `false`.
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = reuse_port;
+        self
+    }
+    /// Bind the socket to a network interface, e.g. `"eth0"`, so it only sends and receives through it (`SO_BINDTODEVICE`). <br>
+    /// Only supported on Linux and Android, and usually needs `CAP_NET_RAW`.
+    /// The default is no interface.
+    pub fn device(mut self, device: impl Into<String>) -> Self {
+        self.device = Some(device.into());
+        self
+    }
+    /// Send batches of equally sized packets to one address, e.g. the redundant disconnect packets of a server,
+    /// with one syscall (`UDP_SEGMENT` on Linux, `UDP_SEND_MSG_SIZE` on Windows), and receive packets the OS coalesced
+    /// with one syscall (`UDP_GRO` on Linux, `UDP_RECV_MAX_COALESCED_SIZE` on Windows),
+    /// see [`Transceiver::send_segments`](Transceiver::send_segments). <br>
+    /// The OS's support is detected when the socket is created, sockets fall back to a syscall per packet without it
+    /// (Linux before 4.18 and 5.0 respectively, Windows before 10 version 2004, and other platforms),
+    /// or if the network device rejects a batch.
+    /// Not supported by [`IoUringSocket`](crate::IoUringSocket), which ignores it.
+    /// The default is `false`.
+    pub fn segmentation_offload(mut self, segmentation_offload: bool) -> Self {
+        self.segmentation_offload = segmentation_offload;
+        self
+    }
+    fn apply(&self, socket: &Socket, ipv6: bool) -> io::Result<()> {
+        if let Some(tos) = self.tos {
+            set_tos(socket, tos, ipv6)?;
+        }
+        if let Some(ttl) = self.ttl {
+            if ipv6 {
+                socket.set_unicast_hops_v6(ttl)?;
+            } else {
+                socket.set_ttl(ttl)?;
+            }
+        }
+        if self.reuse_port {
+            set_reuse_port(socket)?;
+        }
+        if let Some(device) = &self.device {
+            bind_device(socket, device)?;
+        }
+        Ok(())
+    }
+}
+
+// Unused on platforms that support every option.
+#[allow(dead_code)]
+fn unsupported(option: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("{option} is not supported on this platform"),
+    )
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn set_tos(socket: &Socket, tos: u8, ipv6: bool) -> io::Result<()> {
+    if ipv6 {
+        socket.set_tclass_v6(tos as u32)
+    } else {
+        socket.set_tos(tos as u32)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn set_tos(socket: &Socket, tos: u8, ipv6: bool) -> io::Result<()> {
+    if ipv6 {
+        return Err(unsupported("the IPv6 traffic class"));
+    }
+    #[cfg(any(unix, windows))]
+    return socket.set_tos(tos as u32);
+    #[cfg(not(any(unix, windows)))]
+    return Err(unsupported("the type of service"));
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+fn set_reuse_port(socket: &Socket) -> io::Result<()> {
+    socket.set_reuse_port(true)
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos")))))]
+fn set_reuse_port(_: &Socket) -> io::Result<()> {
+    Err(unsupported("SO_REUSEPORT"))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_device(socket: &Socket, device: &str) -> io::Result<()> {
+    socket.bind_device(Some(device.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_device(_: &Socket, _: &str) -> io::Result<()> {
+    Err(unsupported("binding to a device"))
+}
+
 /// A wrapper around `UdpSocket` that implements the `Transceiver` trait for use in the netcode protocol.
 ///
 /// `NetcodeSocket` is responsible for creating and managing a UDP socket, handling non-blocking
@@ -33,13 +196,53 @@ pub type Result<T> = std::result::Result<T, Error>;
 /// let recv_buf_size = 256 * 1024;
 /// let socket = NetcodeSocket::new(addr, send_buf_size, recv_buf_size).unwrap();
 /// ```
-pub struct NetcodeSocket(UdpSocket);
+pub struct NetcodeSocket {
+    pub(crate) socket: UdpSocket,
+    pub(crate) dual_stack: bool,
+    // kept to create the same kind of socket again, see `rebind`
+    send_buf_size: usize,
+    recv_buf_size: usize,
+    options: SocketOptions,
+    // whether batches are sent with `UDP_SEGMENT`, cleared if the device rejects one
+    #[cfg(any(target_os = "linux", windows))]
+    gso: AtomicBool,
+    // the coalesced datagram being returned a segment at a time, if `UDP_GRO` is enabled
+    #[cfg(any(target_os = "linux", windows))]
+    gro: Option<Mutex<Coalesced>>,
+}
 
 impl NetcodeSocket {
+    /// Creates a socket bound to `addr`.
+    ///
+    /// An IPv6 socket only sends and receives IPv6 traffic, see [`NetcodeSocket::dual_stack`](NetcodeSocket::dual_stack)
+    /// for a socket that serves both address families.
     pub fn new(
         addr: impl ToSocketAddrs,
         send_buf_size: usize,
         recv_buf_size: usize,
+    ) -> Result<Self> {
+        Self::with_options(
+            addr,
+            send_buf_size,
+            recv_buf_size,
+            &SocketOptions::default(),
+        )
+    }
+    /// Creates a socket bound to `addr`, with additional [`SocketOptions`](SocketOptions).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use netcode::{NetcodeSocket, SocketOptions};
+    ///
+    /// let options = SocketOptions::new().dscp(46).ttl(32);
+    /// let socket = NetcodeSocket::with_options("127.0.0.1:0", 256 * 1024, 256 * 1024, &options).unwrap();
+    /// ```
+    pub fn with_options(
+        addr: impl ToSocketAddrs,
+        send_buf_size: usize,
+        recv_buf_size: usize,
+        options: &SocketOptions,
     ) -> Result<Self> {
         let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
             io::Error::new(io::ErrorKind::InvalidInput, "no socket addresses found")
@@ -48,11 +251,97 @@ impl NetcodeSocket {
         if addr.is_ipv6() {
             socket.set_only_v6(true)?;
         }
+        Self::bind(socket, addr, send_buf_size, recv_buf_size, options, false)
+    }
+    /// Creates a dual-stack socket bound to `port` on all IPv4 and IPv6 interfaces (`[::]:port`).
+    ///
+    /// IPv4 peers are seen with their IPv4 addresses (the IPv4-mapped IPv6 addresses of the OS are normalized),
+    /// and can be sent to with them, so one server can serve clients of both address families. <br>
+    /// The server's connect tokens should then list an address of each family it is reachable on, e.g.
+    /// `ConnectToken::build(&[v4_addr, v6_addr][..], ...)`, and the server has to be told about them with
+    /// [`ServerConfig::public_addrs`](crate::ServerConfig::public_addrs), it only accepts tokens for its own addresses.
+    ///
+    /// Fails if the host has no IPv6 support.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use netcode::{NetcodeSocket, Server, ServerConfig};
+    ///
+    /// let socket = NetcodeSocket::dual_stack(0, 256 * 1024, 256 * 1024).unwrap();
+    /// let server = Server::with_config_and_transceiver(0x11, netcode::generate_key(), ServerConfig::default(), socket).unwrap();
+    /// ```
+    pub fn dual_stack(port: u16, send_buf_size: usize, recv_buf_size: usize) -> Result<Self> {
+        let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+        let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_only_v6(false)?;
+        Self::bind(
+            socket,
+            addr,
+            send_buf_size,
+            recv_buf_size,
+            &SocketOptions::default(),
+            true,
+        )
+    }
+    fn bind(
+        socket: Socket,
+        addr: SocketAddr,
+        send_buf_size: usize,
+        recv_buf_size: usize,
+        options: &SocketOptions,
+        dual_stack: bool,
+    ) -> Result<Self> {
         socket.set_send_buffer_size(send_buf_size)?;
         socket.set_recv_buffer_size(recv_buf_size)?;
+        options.apply(&socket, addr.is_ipv6())?;
         socket.bind(&addr.into())?;
         socket.set_nonblocking(true)?;
-        Ok(NetcodeSocket(socket.into()))
+        let socket: UdpSocket = socket.into();
+        #[cfg(any(target_os = "linux", windows))]
+        let (gso, gro) = if options.segmentation_offload {
+            let (gso, gro) = (offload::supports_gso(&socket), offload::enable_gro(&socket));
+            log::debug!("segmentation offload of {addr}: send {gso}, receive {gro}");
+            (gso, gro)
+        } else {
+            (false, false)
+        };
+        Ok(NetcodeSocket {
+            socket,
+            dual_stack,
+            send_buf_size,
+            recv_buf_size,
+            options: options.clone(),
+            #[cfg(any(target_os = "linux", windows))]
+            gso: AtomicBool::new(gso),
+            #[cfg(any(target_os = "linux", windows))]
+            gro: gro.then(|| Mutex::new(Coalesced::new())),
+        })
+    }
+    /// Returns true if batches of packets are sent with segmentation offload, see [`SocketOptions::segmentation_offload`](SocketOptions::segmentation_offload).
+    pub fn segmentation_offload(&self) -> bool {
+        #[cfg(any(target_os = "linux", windows))]
+        return self.gso.load(Ordering::Relaxed);
+        #[cfg(not(any(target_os = "linux", windows)))]
+        false
+    }
+    fn peer_addr(&self, addr: SocketAddr) -> SocketAddr {
+        if self.dual_stack {
+            v4_mapped(addr)
+        } else {
+            addr
+        }
+    }
+}
+
+pub(crate) fn canonical(addr: SocketAddr) -> SocketAddr {
+    SocketAddr::new(addr.ip().to_canonical(), addr.port())
+}
+
+pub(crate) fn v4_mapped(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V4(v4) => SocketAddrV6::new(v4.ip().to_ipv6_mapped(), v4.port(), 0, 0).into(),
+        SocketAddr::V6(_) => addr,
     }
 }
 
@@ -60,11 +349,22 @@ impl Transceiver for NetcodeSocket {
     type IntoError = Error;
 
     fn addr(&self) -> SocketAddr {
-        self.0.local_addr().expect("address should be bound")
+        self.socket.local_addr().expect("address should be bound")
     }
 
     fn recv(&self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
-        match self.0.recv_from(buf) {
+        #[cfg(any(target_os = "linux", windows))]
+        if let Some(coalesced) = &self.gro {
+            let mut coalesced = coalesced.lock().unwrap_or_else(PoisonError::into_inner);
+            return match coalesced.recv(&self.socket, buf) {
+                Ok(Some((len, addr))) if self.dual_stack => Ok(Some((len, canonical(addr)))),
+                Ok(received) => Ok(received),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+                Err(e) => Err(Error::from(e)),
+            };
+        }
+        match self.socket.recv_from(buf) {
+            Ok((len, addr)) if len > 0 && self.dual_stack => Ok(Some((len, canonical(addr)))),
             Ok((len, addr)) if len > 0 => Ok(Some((len, addr))),
             Ok(_) => Ok(None),
             Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
@@ -73,10 +373,221 @@ impl Transceiver for NetcodeSocket {
     }
 
     fn send(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
-        match self.0.send_to(buf, addr) {
+        match self.socket.send_to(buf, self.peer_addr(addr)) {
             Ok(len) => Ok(len),
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
             Err(e) => Err(Error::from(e)),
         }
     }
+
+    fn rebind(&mut self) -> Result<bool> {
+        let addr = SocketAddr::new(self.addr().ip(), 0);
+        let rebound = if self.dual_stack {
+            Self::dual_stack(0, self.send_buf_size, self.recv_buf_size)
+        } else {
+            Self::with_options(addr, self.send_buf_size, self.recv_buf_size, &self.options).or_else(
+                |e| {
+                    // the address may be gone with the interface it was on
+                    let unspecified = match addr {
+                        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+                    };
+                    if addr == unspecified {
+                        return Err(e);
+                    }
+                    Self::with_options(
+                        unspecified,
+                        self.send_buf_size,
+                        self.recv_buf_size,
+                        &self.options,
+                    )
+                },
+            )
+        };
+        *self = rebound?;
+        Ok(true)
+    }
+
+    #[cfg(any(target_os = "linux", windows))]
+    fn send_segments(&self, buf: &[u8], segment_size: usize, addr: SocketAddr) -> Result<usize> {
+        let mut sent = 0;
+        if self.gso.load(Ordering::Relaxed)
+            && (1..=offload::MAX_BATCH_BYTES).contains(&segment_size)
+            && buf.len() > segment_size
+        {
+            let batch_size =
+                segment_size * offload::MAX_SEGMENTS.min(offload::MAX_BATCH_BYTES / segment_size);
+            for batch in buf.chunks(batch_size) {
+                match offload::send_segments(
+                    &self.socket,
+                    batch,
+                    segment_size as u16,
+                    self.peer_addr(addr),
+                ) {
+                    Ok(len) => sent += len,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(sent),
+                    Err(e) if offload::is_unsupported(&e) => {
+                        log::debug!("segmentation offload failed, sending packets one by one: {e}");
+                        self.gso.store(false, Ordering::Relaxed);
+                        break;
+                    }
+                    Err(e) => return Err(Error::from(e)),
+                }
+            }
+        }
+        for segment in buf[sent..].chunks(segment_size) {
+            match self.send(segment, addr) {
+                Ok(len) => sent += len,
+                Err(e) if e.io_error().kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dual_stack_serves_both_families() {
+        let Ok(server) = NetcodeSocket::dual_stack(0, 64 * 1024, 64 * 1024) else {
+            // no IPv6 on this host
+            return;
+        };
+        let port = server.addr().port();
+        let v4 = NetcodeSocket::new("127.0.0.1:0", 64 * 1024, 64 * 1024).unwrap();
+        let mut buf = [0; 16];
+        v4.send(b"ping", SocketAddr::from(([127, 0, 0, 1], port)))
+            .unwrap();
+        let from = loop {
+            if let Some((len, from)) = server.recv(&mut buf).unwrap() {
+                assert_eq!(&buf[..len], b"ping");
+                break from;
+            }
+        };
+        assert_eq!(from, v4.addr());
+        server.send(b"pong", from).unwrap();
+        loop {
+            if let Some((len, _)) = v4.recv(&mut buf).unwrap() {
+                assert_eq!(&buf[..len], b"pong");
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn dual_stack_server_accepts_tokens_for_both_families() {
+        use crate::{Client, ConnectToken, Server, ServerConfig};
+
+        let Ok(socket) = NetcodeSocket::dual_stack(0, 64 * 1024, 64 * 1024) else {
+            return;
+        };
+        let port = socket.addr().port();
+        let key = crate::generate_key();
+        let addrs = [
+            SocketAddr::from(([127, 0, 0, 1], port)),
+            SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+        ];
+        let cfg = ServerConfig::default().public_addrs(&addrs);
+        let mut server = Server::with_config_and_transceiver(0, key, cfg, socket).unwrap();
+        // a token for another address on the same port isn't accepted
+        let other = SocketAddr::from(([127, 0, 0, 2], port));
+        let token = ConnectToken::build(other, 0, 1, key).generate().unwrap();
+        let mut client = Client::new(&token.try_into_bytes().unwrap()).unwrap();
+        client.connect();
+        for step in 0..50 {
+            client.update(f64::from(step) * 0.01);
+            server.update(f64::from(step) * 0.01);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(server.num_connected_clients(), 0);
+
+        let token = ConnectToken::build(&addrs[..], 0, 1, key)
+            .generate()
+            .unwrap();
+        let mut client = Client::new(&token.try_into_bytes().unwrap()).unwrap();
+        client.connect();
+        let mut time = 0.5;
+        while !client.is_connected() && time < 5.5 {
+            client.update(time);
+            server.update(time);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            time += 0.01;
+        }
+        assert!(client.is_connected());
+        assert_eq!(server.num_connected_clients(), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn options_are_applied() {
+        let options = SocketOptions::new().dscp(46).ttl(32).reuse_port(true);
+        let a = NetcodeSocket::with_options("127.0.0.1:0", 64 * 1024, 64 * 1024, &options).unwrap();
+        assert_eq!(a.socket.ttl().unwrap(), 32);
+        assert_eq!(socket2::SockRef::from(&a.socket).tos().unwrap(), 46 << 2);
+        // a second socket can share the address
+        let b = NetcodeSocket::with_options(a.addr(), 64 * 1024, 64 * 1024, &options).unwrap();
+        assert_eq!(a.addr(), b.addr());
+        assert!(NetcodeSocket::new(a.addr(), 64 * 1024, 64 * 1024).is_err());
+    }
+
+    #[test]
+    fn segments_arrive_as_separate_packets() {
+        let options = SocketOptions::new().segmentation_offload(true);
+        let a =
+            NetcodeSocket::with_options("127.0.0.1:0", 256 * 1024, 256 * 1024, &options).unwrap();
+        let b =
+            NetcodeSocket::with_options("127.0.0.1:0", 256 * 1024, 256 * 1024, &options).unwrap();
+        // 100 segments of 100 bytes and a shorter last one, more than fit in one offloaded send
+        let batch: Vec<u8> = (0..10_040).map(|i| (i / 100) as u8).collect();
+        assert_eq!(a.send_segments(&batch, 100, b.addr()).unwrap(), batch.len());
+        b.send(b"after", a.addr()).unwrap();
+
+        let mut buf = [0; 1500];
+        let mut received = Vec::new();
+        let start = std::time::Instant::now();
+        while received.len() < 101 && start.elapsed().as_secs() < 5 {
+            if let Some((len, from)) = b.recv(&mut buf).unwrap() {
+                assert_eq!(from, a.addr());
+                received.push(buf[..len].to_vec());
+            }
+        }
+        assert_eq!(received.len(), 101);
+        for (i, packet) in received.iter().enumerate() {
+            let len = if i == 100 { 40 } else { 100 };
+            assert_eq!(packet, &vec![i as u8; len]);
+        }
+        loop {
+            if let Some((len, _)) = a.recv(&mut buf).unwrap() {
+                assert_eq!(&buf[..len], b"after");
+                break;
+            }
+        }
+    }
+
+    #[test]
+    fn rebind_moves_to_a_new_port() {
+        let options = SocketOptions::new().ttl(32);
+        let mut socket =
+            NetcodeSocket::with_options("127.0.0.1:0", 64 * 1024, 64 * 1024, &options).unwrap();
+        let old_addr = socket.addr();
+        assert!(socket.rebind().unwrap());
+        let new_addr = socket.addr();
+        assert_eq!(new_addr.ip(), old_addr.ip());
+        assert_ne!(new_addr.port(), old_addr.port());
+        assert_eq!(socket.socket.ttl().unwrap(), 32);
+
+        let peer = NetcodeSocket::new("127.0.0.1:0", 64 * 1024, 64 * 1024).unwrap();
+        socket.send(b"hello", peer.addr()).unwrap();
+        let mut buf = [0; 16];
+        for _ in 0..100 {
+            if let Some((len, from)) = peer.recv(&mut buf).unwrap() {
+                assert_eq!((&buf[..len], from), (&b"hello"[..], new_addr));
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        panic!("no packet received from the rebound socket");
+    }
 }
@@ -0,0 +1,183 @@
+use crate::{packet::PacketKind, phase::PacketType};
+
+/// Packet counters by [`PacketType`](PacketType).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PacketCounts([u64; PacketType::COUNT]);
+
+impl PacketCounts {
+    /// Gets the count for packets of type `ty`.
+    pub fn get(&self, ty: PacketType) -> u64 {
+        self.0[ty.kind() as usize]
+    }
+    /// Gets the count for packets of all types.
+    pub fn total(&self) -> u64 {
+        self.0.iter().sum()
+    }
+    pub(crate) fn increment(&mut self, kind: PacketKind) {
+        if let Some(ty) = PacketType::from_kind(kind) {
+            self.0[ty.kind() as usize] += 1;
+        }
+    }
+}
+
+/// Statistics collected by a client, see [`Client::stats`](crate::Client::stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ClientStats {
+    /// The largest payload the client sends, see [`ClientConfig::max_payload_size`](crate::ClientConfig::max_payload_size).
+    pub max_payload_size: usize,
+    /// The largest payload received from the server.
+    pub largest_payload_received: usize,
+    /// Packets dropped because their type is not allowed in the client's current state,
+    /// see [`ClientConfig::allowed_packets`](crate::ClientConfig::allowed_packets).
+    pub out_of_phase: PacketCounts,
+    /// The number of times a packet from the server skipped ahead in sequence, see [`ClientConfig::ack_on_sequence_gap`](crate::ClientConfig::ack_on_sequence_gap).
+    pub sequence_gaps: u64,
+    /// The number of times the client or the server replaced the key of the session, see [`ClientConfig::rekey_sessions`](crate::ClientConfig::rekey_sessions).
+    pub rekeys: u64,
+    /// The number of times the client replaced its failed socket, see [`ClientConfig::rebind_on_error`](crate::ClientConfig::rebind_on_error).
+    pub rebinds: u64,
+    /// The number of packets queued because the transceiver would block, e.g. because the socket's send buffer was full,
+    /// see [`SendOutcome::QueuedWouldBlock`](crate::SendOutcome::QueuedWouldBlock).
+    pub packets_would_block: u64,
+    /// The number of queued packets dropped because more were queued while the transceiver kept blocking.
+    pub would_block_drops: u64,
+}
+
+/// Statistics collected by a server, see [`Server::stats`](crate::Server::stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ServerStats {
+    /// The largest payload the server sends, see [`ServerConfig::max_payload_size`](crate::ServerConfig::max_payload_size).
+    pub max_payload_size: usize,
+    /// The largest payload received from any client.
+    pub largest_payload_received: usize,
+    /// Packets dropped because their type is not allowed in the sender's [`ConnectionPhase`](crate::ConnectionPhase),
+    /// see [`ServerConfig::allowed_packets`](crate::ServerConfig::allowed_packets).
+    pub out_of_phase: PacketCounts,
+    /// The number of times a packet from any client skipped ahead in sequence, see [`ServerConfig::ack_on_sequence_gap`](crate::ServerConfig::ack_on_sequence_gap).
+    pub sequence_gaps: u64,
+    /// The number of times the key used to encrypt challenge tokens was replaced because the challenge sequence ran out.
+    pub challenge_key_rotations: u64,
+    /// The number of times a connected client's session moved to a new address, see [`ServerConfig::allow_migration`](crate::ServerConfig::allow_migration).
+    pub migrations: u64,
+    /// The number of pending connections dropped to make room for new connection requests,
+    /// see [`ServerConfig::max_pending_connections`](crate::ServerConfig::max_pending_connections).
+    pub pending_evictions: u64,
+    /// The number of pending connections dropped because the client sent nothing for too long,
+    /// see [`ServerConfig::pending_timeout`](crate::ServerConfig::pending_timeout).
+    pub pending_timeouts: u64,
+    /// The number of connections currently pending, i.e. clients that were sent a challenge but haven't answered it yet.
+    pub pending_connections: usize,
+    /// The number of challenge responses that found the pending connection (and its session keys) they answer.
+    pub pending_hits: u64,
+    /// The number of challenge responses dropped because their pending connection was gone, e.g. because it was evicted
+    /// or timed out. Responses from unknown addresses are counted before they are authenticated, so spoofed packets count too.
+    pub pending_misses: u64,
+    /// The number of times the server or a client replaced the key of a session, see [`ServerConfig::rekey_sessions`](crate::ServerConfig::rekey_sessions).
+    pub rekeys: u64,
+    /// The number of out-of-band queries answered, see [`ServerConfig::queries`](crate::ServerConfig::queries).
+    pub queries_answered: u64,
+    /// The number of queries dropped because more than the configured [`rate_limit`](crate::query::QueryConfig::rate_limit)
+    /// arrived in one second.
+    pub queries_rate_limited: u64,
+    /// The number of datagrams dropped because their relay header was invalid or made with another key,
+    /// see [`ServerConfig::relay`](crate::ServerConfig::relay).
+    pub invalid_relay_headers: u64,
+    /// The number of payloads dropped because their sender's receive queue was full,
+    /// see [`ServerConfig::recv_queue_depth`](crate::ServerConfig::recv_queue_depth).
+    pub recv_queue_overflows: u64,
+    /// The number of packets dropped because more than the budget of a tick were sent,
+    /// see [`ServerConfig::pace_packets`](crate::ServerConfig::pace_packets).
+    pub pacing_drops: u64,
+    /// The number of payloads dropped because they were over their client's bandwidth limit,
+    /// see [`Server::set_client_bandwidth_limit`](crate::Server::set_client_bandwidth_limit).
+    pub bandwidth_limit_drops: u64,
+    /// The number of packets queued because the transceiver would block, e.g. because the socket's send buffer was full,
+    /// see [`SendOutcome::QueuedWouldBlock`](crate::SendOutcome::QueuedWouldBlock).
+    pub packets_would_block: u64,
+    /// The number of queued packets dropped because more were queued while the transceiver kept blocking.
+    pub would_block_drops: u64,
+}
+
+/// Statistics for one of the protocol ids accepted by a server, see [`Server::protocol_stats`](crate::Server::protocol_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProtocolStats {
+    /// The number of clients currently connected with this protocol id.
+    pub connected_clients: usize,
+    /// The number of connections accepted with this protocol id since the server was created.
+    pub connections_accepted: u64,
+    /// The number of packets received with this protocol id (including connection requests).
+    pub packets_received: u64,
+    /// The total size in bytes of the packets received with this protocol id.
+    pub bytes_received: u64,
+}
+
+/// Statistics collected by a [`JitterBuffer`](crate::JitterBuffer), see [`JitterBuffer::stats`](crate::JitterBuffer::stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct JitterStats {
+    /// The number of payloads pushed, including the ones dropped.
+    pub received: u64,
+    /// The number of payloads released.
+    pub released: u64,
+    /// The number of payloads dropped because their tick was already released.
+    pub late: u64,
+    /// The number of payloads dropped because a payload with the same tick was already held.
+    pub duplicates: u64,
+    /// The number of ticks released without a payload, because it was lost or hadn't arrived yet.
+    pub missing: u64,
+    /// The number of ticks the buffer waited, after the first payload was received or because it ran out of payloads.
+    pub underruns: u64,
+    /// The number of times the buffer skipped ahead because it fell more than twice its delay behind.
+    pub resyncs: u64,
+    /// The number of payloads dropped when skipping ahead.
+    pub skipped: u64,
+}
+
+impl ClientStats {
+    pub(crate) fn new(max_payload_size: usize) -> Self {
+        Self {
+            max_payload_size,
+            ..Default::default()
+        }
+    }
+    pub(crate) fn payload_received(&mut self, len: usize) {
+        self.largest_payload_received = self.largest_payload_received.max(len);
+    }
+}
+
+#[cfg(feature = "std")]
+impl ProtocolStats {
+    pub(crate) fn packet_received(&mut self, len: usize) {
+        self.packets_received += 1;
+        self.bytes_received += len as u64;
+    }
+}
+
+impl ServerStats {
+    #[cfg(feature = "std")]
+    pub(crate) fn new(max_payload_size: usize) -> Self {
+        Self {
+            max_payload_size,
+            ..Default::default()
+        }
+    }
+    #[cfg(feature = "std")]
+    pub(crate) fn payload_received(&mut self, len: usize) {
+        self.largest_payload_received = self.largest_payload_received.max(len);
+    }
+    /// The share of challenge responses that found their pending connection, `1.0` if none were received. <br>
+    /// A low rate under connection churn means pending connections are evicted or time out before clients answer,
+    /// raise [`ServerConfig::max_pending_connections`](crate::ServerConfig::max_pending_connections) or
+    /// [`ServerConfig::pending_timeout`](crate::ServerConfig::pending_timeout).
+    pub fn pending_hit_rate(&self) -> f64 {
+        let lookups = self.pending_hits + self.pending_misses;
+        if lookups == 0 {
+            1.0
+        } else {
+            self.pending_hits as f64 / lookups as f64
+        }
+    }
+}
@@ -0,0 +1,226 @@
+//! Utilities for testing code built on `netcode` without real sockets, available with the `testing` feature.
+//!
+//! Nothing in here is meant for production builds, in particular [`SeededRng`] makes every key predictable.
+
+use std::{io, net::SocketAddr};
+
+use chacha20poly1305::aead::rand_core::{self, CryptoRng, RngCore};
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::{
+    crypto::{self, Key},
+    transceiver::Transceiver,
+};
+
+/// One end of an in-memory link, see [`channel_pair`](channel_pair).
+///
+/// Packets sent to the address of the other end are delivered to it in order, without loss.
+/// Packets sent to any other address are dropped, like UDP packets to an address nobody listens on. <br>
+/// The ends are backed by crossbeam channels, so either end can be moved to or shared with another thread.
+#[derive(Debug)]
+pub struct ChannelTransceiver {
+    addr: SocketAddr,
+    peer: SocketAddr,
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+}
+
+impl ChannelTransceiver {
+    /// Gets the address of the other end of the link.
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.peer
+    }
+}
+
+impl Transceiver for ChannelTransceiver {
+    type IntoError = io::Error;
+
+    fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+    fn recv(&self, buf: &mut [u8]) -> io::Result<Option<(usize, SocketAddr)>> {
+        let Ok(packet) = self.rx.try_recv() else {
+            return Ok(None);
+        };
+        // like a UDP socket, the rest of a packet that doesn't fit in the buffer is discarded
+        let len = packet.len().min(buf.len());
+        buf[..len].copy_from_slice(&packet[..len]);
+        Ok(Some((len, self.peer)))
+    }
+    fn send(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        if addr == self.peer {
+            // the other end may have been dropped, its packets are lost
+            self.tx.send(buf.to_vec()).ok();
+        }
+        Ok(buf.len())
+    }
+}
+
+/// Creates two linked in-memory [`Transceivers`](Transceiver), to run a [`Server`](crate::Server) and a [`Client`](crate::Client)
+/// in the same process without binding UDP ports.
+///
+/// The first end has the address `127.0.0.1:40000` and the second `127.0.0.1:50000`,
+/// use [`channel_pair_with_addrs`](channel_pair_with_addrs) to pick others.
+///
+/// # Example
+/// ```
+/// use netcode::{testing::channel_pair, Client, ClientConfig, Server, ServerConfig};
+///
+/// let (server_trx, client_trx) = channel_pair();
+/// let mut server =
+///     Server::with_config_and_transceiver(0x11, netcode::generate_key(), ServerConfig::default(), server_trx).unwrap();
+/// let token = server.token(123).generate().unwrap().try_into_bytes().unwrap();
+/// let mut client = Client::with_config_and_transceiver(&token, ClientConfig::default(), client_trx).unwrap();
+///
+/// client.connect();
+/// let mut time = 0.0;
+/// while !client.is_connected() {
+///     client.update(time);
+///     server.update(time);
+///     time += 1.0 / 60.0;
+/// }
+/// assert_eq!(server.num_connected_clients(), 1);
+/// ```
+pub fn channel_pair() -> (ChannelTransceiver, ChannelTransceiver) {
+    channel_pair_with_addrs(
+        SocketAddr::from(([127, 0, 0, 1], 40000)),
+        SocketAddr::from(([127, 0, 0, 1], 50000)),
+    )
+}
+
+/// Creates two linked in-memory [`Transceivers`](Transceiver) with the given addresses, see [`channel_pair`](channel_pair).
+pub fn channel_pair_with_addrs(
+    a: SocketAddr,
+    b: SocketAddr,
+) -> (ChannelTransceiver, ChannelTransceiver) {
+    let (a_tx, b_rx) = crossbeam_channel::unbounded();
+    let (b_tx, a_rx) = crossbeam_channel::unbounded();
+    let a_end = ChannelTransceiver {
+        addr: a,
+        peer: b,
+        tx: a_tx,
+        rx: a_rx,
+    };
+    let b_end = ChannelTransceiver {
+        addr: b,
+        peer: a,
+        tx: b_tx,
+        rx: b_rx,
+    };
+    (a_end, b_end)
+}
+
+/// A deterministic RNG for reproducible tests and fuzz cases, e.g. for [`generate_key_with`](crate::generate_key_with),
+/// [`ConnectTokenBuilder::generate_at_with_rng`](crate::ConnectTokenBuilder::generate_at_with_rng) or [`ServerConfig::rng`](crate::ServerConfig::rng).
+///
+/// It outputs the ChaCha20 keystream of its seed, so the same seed always gives the same keys and nonces.
+///
+/// # Security
+/// **It is not for production keys.** It implements [`CryptoRng`] only so it can stand in for the
+/// operating system's RNG in the APIs above, and anyone who knows (or guesses) the seed can predict every key, nonce and
+/// challenge it produces. That's why it's only available with the `testing` feature, which belongs in `[dev-dependencies]`.
+///
+/// # Example
+/// ```
+/// use netcode::{testing::SeededRng, ConnectToken};
+///
+/// let private_key = netcode::generate_key();
+/// let token = |seed| {
+///     ConnectToken::build("127.0.0.1:40000", 0x11, 123, private_key)
+///         .generate_at_with_rng(1_700_000_000, &mut SeededRng::new(seed))
+///         .unwrap()
+///         .try_into_bytes()
+///         .unwrap()
+/// };
+/// assert_eq!(token(1), token(1));
+/// assert_ne!(token(1), token(2));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SeededRng {
+    seed: Key,
+    block: u64,
+    buf: Key,
+    pos: usize,
+}
+
+impl SeededRng {
+    /// Create an RNG from a seed.
+    pub fn new(seed: u64) -> Self {
+        let mut key: Key = [0; crate::PRIVATE_KEY_BYTES];
+        key[..8].copy_from_slice(&seed.to_le_bytes());
+        Self::from_seed(key)
+    }
+    /// Create an RNG from a 32-byte seed, e.g. one recorded with a failing fuzz case.
+    pub fn from_seed(seed: Key) -> Self {
+        Self {
+            seed,
+            block: 0,
+            buf: [0; crate::PRIVATE_KEY_BYTES],
+            pos: crate::PRIVATE_KEY_BYTES,
+        }
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for byte in dest {
+            if self.pos == self.buf.len() {
+                self.buf = crypto::derive_key(&self.seed, b"netcode test rng", self.block)
+                    .expect("a keystream block fits its buffer");
+                self.block += 1;
+                self.pos = 0;
+            }
+            *byte = self.buf[self.pos];
+            self.pos += 1;
+        }
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl CryptoRng for SeededRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_reach_the_peer_only() {
+        let (a, b) = channel_pair();
+        let mut buf = [0u8; 8];
+        assert_eq!(a.send(b"hello", b.addr()).unwrap(), 5);
+        assert_eq!(
+            a.send(b"lost", SocketAddr::from(([10, 0, 0, 1], 1)))
+                .unwrap(),
+            4
+        );
+        assert_eq!(b.recv(&mut buf).unwrap(), Some((5, a.addr())));
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(b.recv(&mut buf).unwrap(), None);
+        assert_eq!(a.recv(&mut buf).unwrap(), None);
+
+        drop(b);
+        assert_eq!(a.send(b"hello", a.peer_addr()).unwrap(), 5);
+    }
+
+    #[test]
+    fn seeded_rng_is_reproducible() {
+        let mut a = SeededRng::new(1);
+        let mut b = SeededRng::new(1);
+        let (mut x, mut y) = ([0u8; 100], [0u8; 100]);
+        a.fill_bytes(&mut x[..7]);
+        a.fill_bytes(&mut x[7..]);
+        b.fill_bytes(&mut y);
+        assert_eq!(x, y);
+        assert_ne!(x[..32], x[32..64]);
+        assert_ne!(SeededRng::new(2).next_u64(), SeededRng::new(1).next_u64());
+    }
+}
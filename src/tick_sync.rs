@@ -0,0 +1,209 @@
+//! Sharing the server's simulation tick with its clients, see [`Client::estimated_server_tick`](crate::Client::estimated_server_tick).
+//!
+//! With tick sync enabled on both ends, keep-alives carry a stamp in front of their heartbeat. Clients stamp theirs with
+//! their time, and the server answers with its tick, the last client time it received and how long it held it.
+//! Like NTP, the client takes the round trip from that and estimates the server's tick as the stamped tick plus half the round trip.
+//! A stamped keep-alive is sent each way at least every [`SYNC_INTERVAL_SEC`], even while payloads are flowing.
+
+use alloc::vec::Vec;
+
+/// How often (in seconds) clients and the server send a stamped keep-alive, at least.
+pub(crate) const SYNC_INTERVAL_SEC: f64 = 1.0;
+/// The size of a client's stamp: its time.
+pub(crate) const CLIENT_STAMP_BYTES: usize = 8;
+/// The size of the server's stamp: its tick, the client time it echoes and how long it held it.
+pub(crate) const SERVER_STAMP_BYTES: usize = 24;
+// Drift is corrected by speeding up or slowing down the estimate by at most this fraction, so it moves smoothly.
+const MAX_SLEW: f64 = 0.05;
+// Errors larger than this (in seconds) are corrected at once instead, e.g. on the first sample or after a server hitch.
+const SNAP_SEC: f64 = 0.25;
+// The weight of a new sample in the smoothed offset.
+const SMOOTHING: f64 = 0.2;
+
+/// Writes a client's stamp followed by its heartbeat.
+pub(crate) fn write_client_stamp(time: f64, heartbeat: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    out.extend_from_slice(&time.to_le_bytes());
+    out.extend_from_slice(heartbeat);
+}
+
+/// Reads a client's stamp, returns the client time and the heartbeat after it.
+#[cfg(any(test, feature = "std"))]
+pub(crate) fn read_client_stamp(buf: &[u8]) -> Option<(f64, &[u8])> {
+    let (stamp, heartbeat) = buf.split_first_chunk::<CLIENT_STAMP_BYTES>()?;
+    Some((f64::from_le_bytes(*stamp), heartbeat))
+}
+
+/// The stamp the server puts on its keep-alives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ServerStamp {
+    /// The server's tick when the keep-alive was sent, with the fraction of the tick that passed.
+    pub(crate) tick: f64,
+    /// The time of the last stamp received from the client (on the client's clock), `NaN` if none was.
+    pub(crate) client_time: f64,
+    /// How long (in seconds) the server held the client's stamp before sending this one.
+    pub(crate) hold: f64,
+}
+
+impl ServerStamp {
+    /// Writes the stamp followed by the heartbeat.
+    #[cfg(any(test, feature = "std"))]
+    pub(crate) fn write(&self, heartbeat: &[u8], out: &mut Vec<u8>) {
+        out.clear();
+        for value in [self.tick, self.client_time, self.hold] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(heartbeat);
+    }
+    /// Reads a stamp, returns it and the heartbeat after it.
+    pub(crate) fn read(buf: &[u8]) -> Option<(Self, &[u8])> {
+        let (stamp, heartbeat) = buf.split_first_chunk::<SERVER_STAMP_BYTES>()?;
+        let [tick, client_time, hold] =
+            [0, 8, 16].map(|at| f64::from_le_bytes(stamp[at..at + 8].try_into().expect("8 bytes")));
+        let stamp = Self {
+            tick,
+            client_time,
+            hold,
+        };
+        Some((stamp, heartbeat))
+    }
+}
+
+/// A client's estimate of the server's tick.
+#[derive(Debug, Clone)]
+pub(crate) struct TickSync {
+    tick_rate: f64,
+    last_stamp_time: f64,
+    // the server's tick minus the client's time in ticks: smoothed from the samples, and as currently applied
+    target: Option<f64>,
+    offset: f64,
+    last_update: f64,
+}
+
+impl TickSync {
+    pub(crate) fn new(tick_rate: f64) -> Self {
+        Self {
+            tick_rate,
+            last_stamp_time: f64::NEG_INFINITY,
+            target: None,
+            offset: 0.0,
+            last_update: 0.0,
+        }
+    }
+    /// Returns true if the client should send a stamped keep-alive, even if it is sending payloads.
+    pub(crate) fn is_due(&self, time: f64) -> bool {
+        time - self.last_stamp_time >= SYNC_INTERVAL_SEC
+    }
+    /// Writes the client's stamp and heartbeat, for a keep-alive sent now.
+    pub(crate) fn stamp(&mut self, time: f64, heartbeat: &[u8], out: &mut Vec<u8>) {
+        self.last_stamp_time = time;
+        write_client_stamp(time, heartbeat, out);
+    }
+    /// Takes a sample from a server stamp received at `time`.
+    pub(crate) fn receive(&mut self, time: f64, stamp: ServerStamp) {
+        let rtt = time - stamp.client_time - stamp.hold;
+        if !(stamp.tick.is_finite() && rtt.is_finite() && rtt >= 0.0) {
+            // the server hasn't received a stamp yet, or the stamps are garbled
+            return;
+        }
+        let sample = stamp.tick + rtt / 2.0 * self.tick_rate - time * self.tick_rate;
+        let snap = SNAP_SEC * self.tick_rate;
+        let target = match self.target {
+            Some(target) if (sample - target).abs() <= snap => {
+                target + SMOOTHING * (sample - target)
+            }
+            _ => sample,
+        };
+        if self.target.is_none() || (target - self.offset).abs() > snap {
+            self.offset = target;
+        }
+        self.target = Some(target);
+        self.last_update = time;
+    }
+    /// Moves the applied offset towards the smoothed one, by at most [`MAX_SLEW`] of the time passed.
+    pub(crate) fn update(&mut self, time: f64) {
+        let elapsed = (time - self.last_update).max(0.0);
+        self.last_update = time;
+        let Some(target) = self.target else {
+            return;
+        };
+        let max_step = elapsed * self.tick_rate * MAX_SLEW;
+        self.offset += (target - self.offset).clamp(-max_step, max_step);
+    }
+    /// Gets the estimated tick of the server at `time`, `None` until a sample was taken.
+    pub(crate) fn estimated_tick(&self, time: f64) -> Option<f64> {
+        self.target?;
+        Some(time * self.tick_rate + self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stamps_round_trip() {
+        let mut buf = Vec::new();
+        write_client_stamp(1.5, b"hb", &mut buf);
+        assert_eq!(read_client_stamp(&buf), Some((1.5, &b"hb"[..])));
+        assert_eq!(read_client_stamp(&buf[..7]), None);
+
+        let stamp = ServerStamp {
+            tick: 600.25,
+            client_time: 1.5,
+            hold: 0.01,
+        };
+        stamp.write(b"", &mut buf);
+        assert_eq!(buf.len(), SERVER_STAMP_BYTES);
+        assert_eq!(ServerStamp::read(&buf), Some((stamp, &b""[..])));
+    }
+
+    #[test]
+    fn estimate_follows_the_server_smoothly() {
+        // a 60Hz server 1000 ticks ahead of the client's clock, with 100ms of round trip
+        let server_tick = |time: f64| 1000.0 + time * 60.0;
+        let stamp = |sent: f64, received: f64| ServerStamp {
+            tick: server_tick(received - 0.05),
+            client_time: sent,
+            hold: 0.0,
+        };
+        let mut sync = TickSync::new(60.0);
+        assert_eq!(sync.estimated_tick(0.0), None);
+        sync.receive(0.1, stamp(0.0, 0.1));
+        assert!((sync.estimated_tick(0.1).unwrap() - server_tick(0.1)).abs() < 1e-9);
+
+        // the server's clock runs 1% fast: the estimate catches up without jumping
+        let fast_tick = |time: f64| server_tick(time) + (time - 0.1) * 0.6;
+        let mut last = sync.estimated_tick(0.1).unwrap();
+        for step in 1..=600 {
+            let time = 0.1 + step as f64 / 60.0;
+            if step % 60 == 0 {
+                sync.receive(
+                    time,
+                    ServerStamp {
+                        tick: fast_tick(time - 0.05),
+                        client_time: time - 0.1,
+                        hold: 0.0,
+                    },
+                );
+            }
+            sync.update(time);
+            let estimate = sync.estimated_tick(time).unwrap();
+            assert!(estimate > last && estimate - last < 1.1);
+            last = estimate;
+        }
+        // the smoothing lags a steady drift by a few ticks
+        assert!((last - fast_tick(10.1)).abs() < 3.0);
+
+        // samples without an echoed client time are ignored
+        sync.receive(
+            11.0,
+            ServerStamp {
+                tick: 0.0,
+                client_time: f64::NAN,
+                hold: 0.0,
+            },
+        );
+        assert_eq!(sync.estimated_tick(10.1), Some(last));
+    }
+}
@@ -1,27 +1,41 @@
-use byteorder::{LittleEndian, WriteBytesExt};
-use chacha20poly1305::{aead::OsRng, AeadCore, XChaCha20Poly1305, XNonce};
+use alloc::format;
+use byteorder::LittleEndian;
+use chacha20poly1305::{
+    aead::{
+        rand_core::{CryptoRng, RngCore},
+        OsRng,
+    },
+    XNonce,
+};
 use thiserror::Error;
+use zeroize::Zeroizing;
 
 use crate::{
     bytes::Bytes,
     crypto::{self, Key},
     error::Error,
     free_list::{FreeList, FreeListIter},
-    CONNECTION_TIMEOUT_SEC, CONNECT_TOKEN_BYTES, NETCODE_VERSION, PRIVATE_KEY_BYTES,
-    USER_DATA_BYTES,
+    io::{self, ReadBytesExt, ToSocketAddrs, WriteBytesExt},
+    token_crypto, CHALLENGE_DATA_BYTES, CONNECTION_TIMEOUT_SEC, CONNECT_TOKEN_BYTES,
+    NETCODE_VERSION, PRIVATE_KEY_BYTES, USER_DATA_BYTES,
 };
 
-use std::{
-    io::{self, Write},
+use core::{
     mem::size_of,
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
 };
 
 const MAX_SERVERS_PER_CONNECT: usize = 32;
 const TOKEN_EXPIRE_SEC: i32 = 30;
+// The header of a connect token file, see `ConnectToken::write_to`.
+#[cfg(feature = "std")]
+const FILE_MAGIC: [u8; 8] = *b"NETCTOKN";
+#[cfg(feature = "std")]
+const FILE_VERSION: u16 = 1;
 
 /// An error that can occur when de-serializing a connect token from bytes.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum InvalidTokenError {
     #[error("address list length is out of range 1-32: {0}")]
     AddressListLength(u32),
@@ -33,6 +47,14 @@ pub enum InvalidTokenError {
     InvalidVersion,
     #[error("io error: {0}")]
     Io(#[from] io::Error),
+    #[error("not a connect token file")]
+    InvalidMagic,
+    #[error("unsupported connect token file version: {0}")]
+    UnsupportedFileVersion(u16),
+    #[error("connect token file holds {0} bytes, expected {CONNECT_TOKEN_BYTES}")]
+    InvalidFileLength(u32),
+    #[error("connect token file checksum mismatch, the token is corrupted")]
+    ChecksumMismatch,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -44,32 +66,43 @@ impl AddressList {
     const IPV4: u8 = 1;
     const IPV6: u8 = 2;
     pub fn new(addrs: impl ToSocketAddrs) -> Result<Self, Error> {
+        Ok(Self::from_addrs(addrs.to_socket_addrs()?))
+    }
+    /// Creates a list of the first `MAX_SERVERS_PER_CONNECT` addresses.
+    pub(crate) fn from_addrs(addrs: impl IntoIterator<Item = SocketAddr>) -> Self {
         let mut server_addresses = FreeList::new();
-
-        for (i, addr) in addrs.to_socket_addrs()?.enumerate() {
-            if i >= MAX_SERVERS_PER_CONNECT {
-                break;
-            }
-
+        for addr in addrs.into_iter().take(MAX_SERVERS_PER_CONNECT) {
             server_addresses.insert(addr);
         }
-
-        Ok(AddressList {
+        AddressList {
             addrs: server_addresses,
-        })
+        }
     }
     pub fn len(&self) -> usize {
         self.addrs.len()
     }
-    pub fn iter(&self) -> FreeListIter<SocketAddr, MAX_SERVERS_PER_CONNECT> {
-        FreeListIter {
-            free_list: &self.addrs,
-            index: 0,
+    pub fn iter(&self) -> FreeListIter<'_, SocketAddr, MAX_SERVERS_PER_CONNECT> {
+        self.addrs.iter()
+    }
+    /// Converts the IPv6 addresses between the two wire encodings:
+    /// this crate writes the 16 address octets in network order, while the reference implementation writes
+    /// 8 little-endian 16-bit segments. Swapping the bytes of each segment converts one into the other (in both directions).
+    pub(crate) fn swap_ipv6_segments(&self) -> Self {
+        let mut addrs = FreeList::new();
+        for (_, addr) in self.iter() {
+            addrs.insert(match addr {
+                SocketAddr::V6(addr_v6) => {
+                    let segments = addr_v6.ip().segments().map(u16::swap_bytes);
+                    SocketAddr::from((Ipv6Addr::from(segments), addr_v6.port()))
+                }
+                addr => addr,
+            });
         }
+        Self { addrs }
     }
 }
 
-impl std::ops::Index<usize> for AddressList {
+impl core::ops::Index<usize> for AddressList {
     type Output = SocketAddr;
 
     fn index(&self, index: usize) -> &Self::Output {
@@ -99,7 +132,7 @@ impl Bytes for AddressList {
         Ok(())
     }
 
-    fn read_from(reader: &mut impl byteorder::ReadBytesExt) -> Result<Self, InvalidTokenError> {
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, InvalidTokenError> {
         let len = reader.read_u32::<LittleEndian>()?;
 
         if !(1..=MAX_SERVERS_PER_CONNECT as u32).contains(&len) {
@@ -136,24 +169,12 @@ pub struct ConnectTokenPrivate {
     pub client_id: u64,
     pub timeout_seconds: i32,
     pub server_addresses: AddressList,
-    pub client_to_server_key: Key,
-    pub server_to_client_key: Key,
+    pub client_to_server_key: Zeroizing<Key>,
+    pub server_to_client_key: Zeroizing<Key>,
     pub user_data: [u8; USER_DATA_BYTES],
 }
 
 impl ConnectTokenPrivate {
-    fn aead(
-        protocol_id: u64,
-        expire_timestamp: u64,
-    ) -> Result<[u8; NETCODE_VERSION.len() + std::mem::size_of::<u64>() * 2], Error> {
-        let mut aead = [0; NETCODE_VERSION.len() + std::mem::size_of::<u64>() * 2];
-        let mut cursor = io::Cursor::new(&mut aead[..]);
-        cursor.write_all(NETCODE_VERSION)?;
-        cursor.write_u64::<LittleEndian>(protocol_id)?;
-        cursor.write_u64::<LittleEndian>(expire_timestamp)?;
-        Ok(aead)
-    }
-
     pub fn encrypt(
         &self,
         protocol_id: u64,
@@ -161,7 +182,7 @@ impl ConnectTokenPrivate {
         nonce: XNonce,
         private_key: &Key,
     ) -> Result<[u8; Self::SIZE], Error> {
-        let aead = Self::aead(protocol_id, expire_timestamp)?;
+        let aead = token_crypto::connect_token_aad(protocol_id, expire_timestamp);
         let mut buf = [0u8; Self::SIZE]; // NOTE: token buffer needs 16-bytes overhead for auth tag
         let mut cursor = io::Cursor::new(&mut buf[..]);
         self.write_to(&mut cursor)?;
@@ -176,7 +197,7 @@ impl ConnectTokenPrivate {
         nonce: XNonce,
         private_key: &Key,
     ) -> Result<Self, Error> {
-        let aead = Self::aead(protocol_id, expire_timestamp)?;
+        let aead = token_crypto::connect_token_aad(protocol_id, expire_timestamp);
         crypto::xchacha_decrypt(encrypted, Some(&aead), nonce, private_key)?;
         let mut cursor = io::Cursor::new(encrypted);
         Ok(Self::read_from(&mut cursor)?)
@@ -191,24 +212,23 @@ impl Bytes for ConnectTokenPrivate {
         buf.write_i32::<LittleEndian>(self.timeout_seconds)?;
         self.server_addresses
             .write_to(buf)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-        buf.write_all(&self.client_to_server_key)?;
-        buf.write_all(&self.server_to_client_key)?;
+            .map_err(io::Error::other)?;
+        buf.write_all(&self.client_to_server_key[..])?;
+        buf.write_all(&self.server_to_client_key[..])?;
         buf.write_all(&self.user_data)?;
         Ok(())
     }
 
-    fn read_from(reader: &mut impl byteorder::ReadBytesExt) -> Result<Self, io::Error> {
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
         let client_id = reader.read_u64::<LittleEndian>()?;
         let timeout_seconds = reader.read_i32::<LittleEndian>()?;
-        let server_addresses =
-            AddressList::read_from(reader).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let server_addresses = AddressList::read_from(reader).map_err(io::Error::other)?;
 
-        let mut client_to_server_key = [0; PRIVATE_KEY_BYTES];
-        reader.read_exact(&mut client_to_server_key)?;
+        let mut client_to_server_key = Zeroizing::new([0; PRIVATE_KEY_BYTES]);
+        reader.read_exact(client_to_server_key.as_mut())?;
 
-        let mut server_to_client_key = [0; PRIVATE_KEY_BYTES];
-        reader.read_exact(&mut server_to_client_key)?;
+        let mut server_to_client_key = Zeroizing::new([0; PRIVATE_KEY_BYTES]);
+        reader.read_exact(server_to_client_key.as_mut())?;
 
         let mut user_data = [0; USER_DATA_BYTES];
         reader.read_exact(&mut user_data)?;
@@ -227,8 +247,13 @@ impl Bytes for ConnectTokenPrivate {
 pub struct ChallengeToken {
     pub client_id: u64,
     pub user_data: [u8; USER_DATA_BYTES],
+    // in what the reference implementation leaves as zero padding, so tokens without it are identical
+    pub custom_data: [u8; CHALLENGE_DATA_BYTES],
 }
 
+// the custom data fills the padding up to the MAC
+const _: () = assert!(<ChallengeToken as Bytes>::SIZE + crate::MAC_BYTES == ChallengeToken::SIZE);
+
 impl ChallengeToken {
     pub const SIZE: usize = 300;
     pub fn encrypt(&self, sequence: u64, private_key: &Key) -> Result<[u8; Self::SIZE], Error> {
@@ -251,21 +276,25 @@ impl ChallengeToken {
 }
 
 impl Bytes for ChallengeToken {
-    const SIZE: usize = size_of::<u64>() + USER_DATA_BYTES;
+    const SIZE: usize = size_of::<u64>() + USER_DATA_BYTES + CHALLENGE_DATA_BYTES;
     type Error = io::Error;
     fn write_to(&self, buf: &mut impl io::Write) -> Result<(), io::Error> {
         buf.write_u64::<LittleEndian>(self.client_id)?;
         buf.write_all(&self.user_data)?;
+        buf.write_all(&self.custom_data)?;
         Ok(())
     }
 
-    fn read_from(reader: &mut impl byteorder::ReadBytesExt) -> Result<Self, io::Error> {
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, io::Error> {
         let client_id = reader.read_u64::<LittleEndian>()?;
         let mut user_data = [0; USER_DATA_BYTES];
         reader.read_exact(&mut user_data)?;
+        let mut custom_data = [0; CHALLENGE_DATA_BYTES];
+        reader.read_exact(&mut custom_data)?;
         Ok(Self {
             client_id,
             user_data,
+            custom_data,
         })
     }
 }
@@ -280,7 +309,7 @@ impl Bytes for ChallengeToken {
 /// use netcode::ConnectToken;
 ///
 /// // mandatory fields
-/// let server_address = "crates.io:12345"; // the server's public address (can also be multiple addresses)
+/// let server_address = "127.0.0.1:12345"; // the server's public address (can also be multiple addresses)
 /// let private_key = netcode::generate_key(); // 32-byte private key, used to encrypt the token
 /// let protocol_id = 0x11223344; // must match the server's protocol id - unique to your app/game
 /// let client_id = 123; // globally unique identifier for an authenticated client
@@ -312,8 +341,9 @@ pub struct ConnectToken {
     pub(crate) private_data: [u8; ConnectTokenPrivate::SIZE],
     pub(crate) timeout_seconds: i32,
     pub(crate) server_addresses: AddressList,
-    pub(crate) client_to_server_key: Key,
-    pub(crate) server_to_client_key: Key,
+    pub(crate) client_to_server_key: Zeroizing<Key>,
+    pub(crate) server_to_client_key: Zeroizing<Key>,
+    pub(crate) strict_netcode_1_02: bool,
 }
 
 /// A builder that can be used to generate a connect token.
@@ -321,11 +351,15 @@ pub struct ConnectTokenBuilder<A: ToSocketAddrs> {
     protocol_id: u64,
     client_id: u64,
     expire_seconds: i32,
-    private_key: Key,
+    private_key: Zeroizing<Key>,
     timeout_seconds: i32,
     public_server_addresses: A,
     internal_server_addresses: Option<AddressList>,
     user_data: [u8; USER_DATA_BYTES],
+    strict_netcode_1_02: bool,
+    // the creation time `generate` uses instead of the system clock, see `Server::token`
+    #[cfg(feature = "std")]
+    create_timestamp: Option<u64>,
 }
 
 impl<A: ToSocketAddrs> ConnectTokenBuilder<A> {
@@ -334,13 +368,21 @@ impl<A: ToSocketAddrs> ConnectTokenBuilder<A> {
             protocol_id,
             client_id,
             expire_seconds: TOKEN_EXPIRE_SEC,
-            private_key,
+            private_key: Zeroizing::new(private_key),
             timeout_seconds: CONNECTION_TIMEOUT_SEC,
             public_server_addresses: server_addresses,
             internal_server_addresses: None,
             user_data: [0; USER_DATA_BYTES],
+            strict_netcode_1_02: false,
+            #[cfg(feature = "std")]
+            create_timestamp: None,
         }
     }
+    #[cfg(feature = "std")]
+    pub(crate) fn created_at(mut self, now: u64) -> Self {
+        self.create_timestamp = Some(now);
+        self
+    }
     /// Sets the time in seconds that the token will be valid for.
     ///
     /// Negative values will disable expiry.
@@ -372,11 +414,46 @@ impl<A: ToSocketAddrs> ConnectTokenBuilder<A> {
         self.internal_server_addresses = Some(AddressList::new(internal_addresses)?);
         Ok(self)
     }
-    /// Generates the token and consumes the builder.
+    /// Encode IPv6 server addresses the way the netcode 1.02 reference implementation does (8 little-endian 16-bit segments),
+    /// for tokens consumed by C clients and servers. <br>
+    /// Tokens for clients and servers of this crate should only be strict if they are configured with
+    /// [`ClientConfig::strict_netcode_1_02`](crate::ClientConfig::strict_netcode_1_02) / [`ServerConfig::strict_netcode_1_02`](crate::ServerConfig::strict_netcode_1_02).
+    /// Tokens with only IPv4 addresses are identical either way. The default is `false`.
+    pub fn strict_netcode_1_02(mut self, strict: bool) -> Self {
+        self.strict_netcode_1_02 = strict;
+        self
+    }
+    /// Generates the token at the current time and consumes the builder.
+    ///
+    /// The time is taken from the system clock, or from the server's [`clock`](crate::ServerConfig::clock)
+    /// for a builder from [`Server::token`](crate::Server::token).
+    #[cfg(feature = "std")]
     pub fn generate(self) -> Result<ConnectToken, Error> {
-        let now = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)?
-            .as_secs();
+        let now = match self.create_timestamp {
+            Some(now) => now,
+            None => std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+        };
+        self.generate_at(now)
+    }
+    /// Generates the token as if it was created at `now` (in seconds since the unix epoch) and consumes the builder.
+    ///
+    /// The token expires [`expire_seconds`](ConnectTokenBuilder::expire_seconds) after `now`.
+    /// Use it where the system clock is not available (e.g. without the `std` feature), see [`generate`](ConnectTokenBuilder::generate) otherwise.
+    pub fn generate_at(self, now: u64) -> Result<ConnectToken, Error> {
+        self.generate_at_with_rng(now, &mut OsRng)
+    }
+    /// Generates the token like [`generate_at`](ConnectTokenBuilder::generate_at), with its packet keys and nonce taken from `rng`
+    /// instead of the operating system's RNG, and consumes the builder.
+    ///
+    /// With a seeded RNG, such as `testing::SeededRng` (with the `testing` feature), the same inputs always give the same token bytes,
+    /// for reproducible tests and fuzz cases. Tokens for real clients should use the operating system's RNG.
+    pub fn generate_at_with_rng<R: CryptoRng + RngCore + ?Sized>(
+        self,
+        now: u64,
+        rng: &mut R,
+    ) -> Result<ConnectToken, Error> {
         let expire_timestamp = if self.expire_seconds < 0 {
             u64::MAX
         } else {
@@ -387,16 +464,20 @@ impl<A: ToSocketAddrs> ConnectTokenBuilder<A> {
             Some(addresses) => addresses,
             None => public_server_addresses,
         };
-        let client_to_server_key = crypto::generate_key();
-        let server_to_client_key = crypto::generate_key();
-        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let client_to_server_key = Zeroizing::new(crypto::try_generate_key_with(rng)?);
+        let server_to_client_key = Zeroizing::new(crypto::try_generate_key_with(rng)?);
+        let nonce = XNonce::from(token_crypto::generate_nonce_with(rng));
 
         let private_data = ConnectTokenPrivate {
             client_id: self.client_id,
             timeout_seconds: self.timeout_seconds,
-            server_addresses: internal_server_addresses,
-            client_to_server_key,
-            server_to_client_key,
+            server_addresses: if self.strict_netcode_1_02 {
+                internal_server_addresses.swap_ipv6_segments()
+            } else {
+                internal_server_addresses
+            },
+            client_to_server_key: client_to_server_key.clone(),
+            server_to_client_key: server_to_client_key.clone(),
             user_data: self.user_data,
         }
         .encrypt(self.protocol_id, expire_timestamp, nonce, &self.private_key)?;
@@ -412,6 +493,7 @@ impl<A: ToSocketAddrs> ConnectTokenBuilder<A> {
             server_addresses: public_server_addresses,
             client_to_server_key,
             server_to_client_key,
+            strict_netcode_1_02: self.strict_netcode_1_02,
         })
     }
 }
@@ -426,19 +508,114 @@ impl ConnectToken {
     ) -> ConnectTokenBuilder<A> {
         ConnectTokenBuilder::new(server_addresses, protocol_id, client_id, private_key)
     }
+    /// The protocol id the token was generated for.
+    pub fn protocol_id(&self) -> u64 {
+        self.protocol_id
+    }
+    /// When the token was generated, in seconds since the unix epoch.
+    pub fn create_timestamp(&self) -> u64 {
+        self.create_timestamp
+    }
+    /// When the token expires, in seconds since the unix epoch (`u64::MAX` if it never expires).
+    pub fn expire_timestamp(&self) -> u64 {
+        self.expire_timestamp
+    }
+    /// The connection timeout of clients using the token, negative for no timeout.
+    pub fn timeout_seconds(&self) -> i32 {
+        self.timeout_seconds
+    }
+    /// The **public** server addresses the client will try to connect to, in order.
+    pub fn server_addresses(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.server_addresses.iter().map(|(_, addr)| addr)
+    }
 
     /// Tries to convert the token into a 2048-byte array.
     pub fn try_into_bytes(self) -> Result<[u8; CONNECT_TOKEN_BYTES], io::Error> {
         let mut buf = [0u8; CONNECT_TOKEN_BYTES];
         let mut cursor = io::Cursor::new(&mut buf[..]);
-        self.write_to(&mut cursor).map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("failed to write token to buffer: {}", e),
-            )
-        })?;
+        Bytes::write_to(&self, &mut cursor)
+            .map_err(|e| io::Error::other(format!("failed to write token to buffer: {}", e)))?;
         Ok(buf)
     }
+    /// Writes the token to `writer` as a connect token file, e.g. to store it on disk or pass it to a launched game. <br>
+    /// The 2048 bytes of the token are prefixed with a header (all little endian), so [`read_from`](ConnectToken::read_from)
+    /// can tell a token from other data, detect corruption and read tokens written by later versions of the format:
+    ///
+    /// | Field | Size |
+    /// |-------|------|
+    /// | magic `NETCTOKN` | 8 bytes |
+    /// | format version (currently 1) | 2 bytes |
+    /// | length of the token | 4 bytes |
+    /// | CRC-32 of the token | 4 bytes |
+    /// | token | length bytes |
+    ///
+    /// # Example
+    /// ```
+    /// use netcode::ConnectToken;
+    ///
+    /// let token = ConnectToken::build("127.0.0.1:40000", 0x11223344, 123, netcode::generate_key())
+    ///     .generate()
+    ///     .unwrap();
+    /// let mut file = Vec::new();
+    /// token.write_to(&mut file).unwrap();
+    ///
+    /// let read = ConnectToken::read_from(&file[..]).unwrap();
+    /// assert_eq!(read.try_into_bytes().unwrap(), token.try_into_bytes().unwrap());
+    /// ```
+    #[cfg(feature = "std")]
+    pub fn write_to(&self, mut writer: impl std::io::Write) -> Result<(), Error> {
+        let mut buf = Zeroizing::new([0u8; CONNECT_TOKEN_BYTES]);
+        Bytes::write_to(self, &mut io::Cursor::new(&mut buf[..])).map_err(Error::InvalidToken)?;
+        writer.write_all(&FILE_MAGIC)?;
+        writer.write_u16::<LittleEndian>(FILE_VERSION)?;
+        writer.write_u32::<LittleEndian>(CONNECT_TOKEN_BYTES as u32)?;
+        writer.write_u32::<LittleEndian>(crc32(&buf[..]))?;
+        writer.write_all(&buf[..])?;
+        Ok(())
+    }
+    /// Reads a token from a connect token file written by [`write_to`](ConnectToken::write_to). <br>
+    /// Returns an [`InvalidToken`](Error::InvalidToken) error if the data isn't a connect token file, was written in an
+    /// unsupported version of the format or is corrupted, and an [`Io`](Error::Io) error if `reader` fails or ends early.
+    #[cfg(feature = "std")]
+    pub fn read_from(mut reader: impl std::io::Read) -> Result<Self, Error> {
+        let mut magic = [0; FILE_MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != FILE_MAGIC {
+            return Err(Error::InvalidToken(InvalidTokenError::InvalidMagic));
+        }
+        let version = reader.read_u16::<LittleEndian>()?;
+        if version != FILE_VERSION {
+            return Err(Error::InvalidToken(
+                InvalidTokenError::UnsupportedFileVersion(version),
+            ));
+        }
+        let len = reader.read_u32::<LittleEndian>()?;
+        if len as usize != CONNECT_TOKEN_BYTES {
+            return Err(Error::InvalidToken(InvalidTokenError::InvalidFileLength(
+                len,
+            )));
+        }
+        let checksum = reader.read_u32::<LittleEndian>()?;
+        let mut buf = Zeroizing::new([0u8; CONNECT_TOKEN_BYTES]);
+        reader.read_exact(&mut buf[..])?;
+        if crc32(&buf[..]) != checksum {
+            return Err(Error::InvalidToken(InvalidTokenError::ChecksumMismatch));
+        }
+        <ConnectToken as Bytes>::read_from(&mut &buf[..]).map_err(Error::InvalidToken)
+    }
+}
+
+// The CRC-32 (IEEE, as used by zip and PNG) of a token in a connect token file.
+#[cfg(feature = "std")]
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
 }
 
 impl Bytes for ConnectToken {
@@ -452,13 +629,17 @@ impl Bytes for ConnectToken {
         buf.write_all(&self.nonce)?;
         buf.write_all(&self.private_data)?;
         buf.write_i32::<LittleEndian>(self.timeout_seconds)?;
-        self.server_addresses.write_to(buf)?;
-        buf.write_all(&self.client_to_server_key)?;
-        buf.write_all(&self.server_to_client_key)?;
+        if self.strict_netcode_1_02 {
+            self.server_addresses.swap_ipv6_segments().write_to(buf)?;
+        } else {
+            self.server_addresses.write_to(buf)?;
+        }
+        buf.write_all(&self.client_to_server_key[..])?;
+        buf.write_all(&self.server_to_client_key[..])?;
         Ok(())
     }
 
-    fn read_from(reader: &mut impl byteorder::ReadBytesExt) -> Result<Self, Self::Error> {
+    fn read_from(reader: &mut impl ReadBytesExt) -> Result<Self, Self::Error> {
         let mut version_info = [0; NETCODE_VERSION.len()];
         reader.read_exact(&mut version_info)?;
 
@@ -477,7 +658,7 @@ impl Bytes for ConnectToken {
 
         let mut nonce = [0; size_of::<XNonce>()];
         reader.read_exact(&mut nonce)?;
-        let nonce = XNonce::from_slice(&nonce).to_owned();
+        let nonce = XNonce::clone_from_slice(&nonce);
 
         let mut private_data = [0; ConnectTokenPrivate::SIZE];
         reader.read_exact(&mut private_data)?;
@@ -486,11 +667,11 @@ impl Bytes for ConnectToken {
 
         let server_addresses = AddressList::read_from(reader)?;
 
-        let mut client_to_server_key = [0; PRIVATE_KEY_BYTES];
-        reader.read_exact(&mut client_to_server_key)?;
+        let mut client_to_server_key = Zeroizing::new([0; PRIVATE_KEY_BYTES]);
+        reader.read_exact(client_to_server_key.as_mut())?;
 
-        let mut server_to_client_key = [0; PRIVATE_KEY_BYTES];
-        reader.read_exact(&mut server_to_client_key)?;
+        let mut server_to_client_key = Zeroizing::new([0; PRIVATE_KEY_BYTES]);
+        reader.read_exact(server_to_client_key.as_mut())?;
 
         Ok(Self {
             version_info,
@@ -503,12 +684,14 @@ impl Bytes for ConnectToken {
             server_addresses,
             client_to_server_key,
             server_to_client_key,
+            strict_netcode_1_02: false,
         })
     }
 }
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chacha20poly1305::{AeadCore, XChaCha20Poly1305};
 
     #[test]
     fn encrypt_decrypt_private_token() {
@@ -534,8 +717,8 @@ mod tests {
             timeout_seconds,
             server_addresses,
             user_data,
-            client_to_server_key: crypto::generate_key(),
-            server_to_client_key: crypto::generate_key(),
+            client_to_server_key: crypto::generate_key().into(),
+            server_to_client_key: crypto::generate_key().into(),
         };
 
         let mut encrypted = private_token
@@ -578,9 +761,12 @@ mod tests {
         let client_id = 2;
         let user_data = [0x11; USER_DATA_BYTES];
 
+        let custom_data = [0x22; CHALLENGE_DATA_BYTES];
+
         let challenge_token = ChallengeToken {
             client_id,
             user_data,
+            custom_data,
         };
 
         let mut encrypted = challenge_token.encrypt(sequence, &private_key).unwrap();
@@ -590,6 +776,7 @@ mod tests {
 
         assert_eq!(challenge_token.client_id, client_id);
         assert_eq!(challenge_token.user_data, user_data);
+        assert_eq!(challenge_token.custom_data, custom_data);
     }
 
     #[test]
@@ -616,8 +803,8 @@ mod tests {
             timeout_seconds,
             server_addresses,
             user_data,
-            client_to_server_key: crypto::generate_key(),
-            server_to_client_key: crypto::generate_key(),
+            client_to_server_key: crypto::generate_key().into(),
+            server_to_client_key: crypto::generate_key().into(),
         };
 
         let mut encrypted = private_token
@@ -648,12 +835,13 @@ mod tests {
             server_addresses,
             client_to_server_key: private_token.client_to_server_key,
             server_to_client_key: private_token.server_to_client_key,
+            strict_netcode_1_02: false,
         };
 
         let mut buf = Vec::new();
-        connect_token.write_to(&mut buf).unwrap();
+        Bytes::write_to(&connect_token, &mut buf).unwrap();
 
-        let connect_token = ConnectToken::read_from(&mut buf.as_slice()).unwrap();
+        let connect_token = <ConnectToken as Bytes>::read_from(&mut buf.as_slice()).unwrap();
 
         assert_eq!(connect_token.version_info, *NETCODE_VERSION);
         assert_eq!(connect_token.protocol_id, protocol_id);
@@ -688,16 +876,14 @@ mod tests {
         .expire_seconds(6)
         .internal_addresses("0.0.0.0:0")
         .expect("failed to parse address")
-        .generate()
+        .generate_at(1_000)
         .unwrap();
 
         assert_eq!(connect_token.version_info, *NETCODE_VERSION);
         assert_eq!(connect_token.protocol_id, protocol_id);
         assert_eq!(connect_token.timeout_seconds, 5);
-        assert_eq!(
-            connect_token.expire_timestamp,
-            connect_token.create_timestamp + 6
-        );
+        assert_eq!(connect_token.create_timestamp, 1_000);
+        assert_eq!(connect_token.expire_timestamp, 1_006);
         connect_token
             .server_addresses
             .iter()
@@ -706,4 +892,58 @@ mod tests {
                 assert_eq!(have, expected);
             });
     }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn token_file_round_trip() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let token = ConnectToken::build("127.0.0.1:40000", 1, 2, [0x42; PRIVATE_KEY_BYTES])
+            .generate_at(1_000)
+            .unwrap();
+        let mut file = Vec::new();
+        token.write_to(&mut file).unwrap();
+        assert_eq!(file.len(), 18 + CONNECT_TOKEN_BYTES);
+        assert_eq!(&file[..8], b"NETCTOKN");
+
+        let read = ConnectToken::read_from(&file[..]).unwrap();
+        assert_eq!(read.client_to_server_key, token.client_to_server_key);
+        assert_eq!(
+            read.try_into_bytes().unwrap(),
+            token.try_into_bytes().unwrap()
+        );
+
+        let invalid = |file: &[u8]| match ConnectToken::read_from(file) {
+            Err(Error::InvalidToken(e)) => e,
+            Err(e) => panic!("expected an invalid token, got {e}"),
+            Ok(_) => panic!("expected an invalid token"),
+        };
+        let mut corrupted = file.clone();
+        corrupted[100] ^= 1;
+        assert!(matches!(
+            invalid(&corrupted),
+            InvalidTokenError::ChecksumMismatch
+        ));
+        let mut newer = file.clone();
+        newer[8] = 2;
+        assert!(matches!(
+            invalid(&newer),
+            InvalidTokenError::UnsupportedFileVersion(2)
+        ));
+        let mut longer = file.clone();
+        longer[12] = 1;
+        assert!(matches!(
+            invalid(&longer),
+            InvalidTokenError::InvalidFileLength(0x0001_0800)
+        ));
+        // a raw token without the header
+        assert!(matches!(
+            invalid(&file[18..]),
+            InvalidTokenError::InvalidMagic
+        ));
+        assert!(matches!(
+            ConnectToken::read_from(&file[..file.len() - 1]).err(),
+            Some(Error::Io(_))
+        ));
+    }
 }
@@ -0,0 +1,279 @@
+//! The encryption of connect and challenge tokens on their own, without a [`ConnectToken`](crate::ConnectToken).
+//!
+//! [`ConnectToken::build`](crate::ConnectToken::build) is the way to issue tokens. These building blocks are for setups it
+//! doesn't cover, e.g. a matchmaker that assembles the public part of the token itself (or in another language)
+//! and only needs the private part encrypted, or tests that validate such a matchmaker against this crate.
+//! See the upstream [specification](https://github.com/networkprotocol/netcode/blob/master/STANDARD.md) for the formats.
+//!
+//! # Example
+//! ```
+//! use netcode::token_crypto::{self, PrivateConnectToken};
+//! use std::net::SocketAddr;
+//!
+//! let private_key = netcode::generate_key();
+//! let (protocol_id, expire_timestamp) = (0x11223344, 1_700_000_030);
+//! let token = PrivateConnectToken::new(123, vec![SocketAddr::from(([127, 0, 0, 1], 40000))]);
+//!
+//! let nonce = token_crypto::generate_nonce();
+//! let encrypted = token.encrypt(protocol_id, expire_timestamp, &nonce, &private_key).unwrap();
+//! let decrypted = PrivateConnectToken::decrypt(&encrypted, protocol_id, expire_timestamp, &nonce, &private_key).unwrap();
+//! assert_eq!(decrypted, token);
+//! ```
+
+use alloc::vec::Vec;
+use core::{mem::size_of, net::SocketAddr};
+
+use chacha20poly1305::{
+    aead::{
+        rand_core::{CryptoRng, RngCore},
+        OsRng,
+    },
+    XNonce,
+};
+use zeroize::Zeroizing;
+
+use crate::{
+    bytes::Bytes,
+    crypto::{self, Key},
+    error::Result,
+    token::{AddressList, ChallengeToken as ChallengeTokenPrivate, ConnectTokenPrivate},
+    CHALLENGE_DATA_BYTES, CONNECTION_TIMEOUT_SEC, NETCODE_VERSION, USER_DATA_BYTES,
+};
+
+/// The size of an encrypted [`PrivateConnectToken`](PrivateConnectToken), including the 16 byte MAC.
+pub const PRIVATE_CONNECT_TOKEN_BYTES: usize = ConnectTokenPrivate::SIZE;
+/// The size of an encrypted [`ChallengeToken`](ChallengeToken), including the 16 byte MAC.
+pub const CHALLENGE_TOKEN_BYTES: usize = ChallengeTokenPrivate::SIZE;
+/// The size of the nonce a private connect token is encrypted with.
+pub const NONCE_BYTES: usize = 24;
+/// The size of the additional data a private connect token is encrypted with, see [`connect_token_aad`](connect_token_aad).
+pub const CONNECT_TOKEN_AAD_BYTES: usize = NETCODE_VERSION.len() + 2 * size_of::<u64>();
+
+/// The private part of a connect token, which only the server can decrypt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PrivateConnectToken {
+    /// The globally unique id of the client.
+    pub client_id: u64,
+    /// The connection timeout in seconds, negative for no timeout.
+    pub timeout_seconds: i32,
+    /// The server addresses the token is valid for (at most 32), the server has to be bound to one of them.
+    pub server_addresses: Vec<SocketAddr>,
+    /// The key the client encrypts its packets with.
+    pub client_to_server_key: Key,
+    /// The key the server encrypts its packets with.
+    pub server_to_client_key: Key,
+    /// Data passed from the matchmaker to the server.
+    pub user_data: [u8; USER_DATA_BYTES],
+}
+
+impl PrivateConnectToken {
+    /// Create a token for `client_id` with freshly generated packet keys, the default timeout and no user data.
+    pub fn new(client_id: u64, server_addresses: Vec<SocketAddr>) -> Self {
+        Self {
+            client_id,
+            timeout_seconds: CONNECTION_TIMEOUT_SEC,
+            server_addresses,
+            client_to_server_key: crypto::generate_key(),
+            server_to_client_key: crypto::generate_key(),
+            user_data: [0; USER_DATA_BYTES],
+        }
+    }
+    /// Encrypts the token with the server's private key, the nonce and the [additional data](connect_token_aad) built from
+    /// `protocol_id` and `expire_timestamp`, which the public part of the token has to carry as well.
+    pub fn encrypt(
+        &self,
+        protocol_id: u64,
+        expire_timestamp: u64,
+        nonce: &[u8; NONCE_BYTES],
+        private_key: &Key,
+    ) -> Result<[u8; PRIVATE_CONNECT_TOKEN_BYTES]> {
+        let token = ConnectTokenPrivate {
+            client_id: self.client_id,
+            timeout_seconds: self.timeout_seconds,
+            server_addresses: AddressList::from_addrs(self.server_addresses.iter().copied()),
+            client_to_server_key: Zeroizing::new(self.client_to_server_key),
+            server_to_client_key: Zeroizing::new(self.server_to_client_key),
+            user_data: self.user_data,
+        };
+        token.encrypt(
+            protocol_id,
+            expire_timestamp,
+            *XNonce::from_slice(nonce),
+            private_key,
+        )
+    }
+    /// Decrypts a token encrypted with [`encrypt`](PrivateConnectToken::encrypt),
+    /// failing if it was encrypted with another key, nonce or additional data, or was tampered with.
+    pub fn decrypt(
+        encrypted: &[u8; PRIVATE_CONNECT_TOKEN_BYTES],
+        protocol_id: u64,
+        expire_timestamp: u64,
+        nonce: &[u8; NONCE_BYTES],
+        private_key: &Key,
+    ) -> Result<Self> {
+        let mut buf = *encrypted;
+        let token = ConnectTokenPrivate::decrypt(
+            &mut buf,
+            protocol_id,
+            expire_timestamp,
+            *XNonce::from_slice(nonce),
+            private_key,
+        )?;
+        Ok(Self {
+            client_id: token.client_id,
+            timeout_seconds: token.timeout_seconds,
+            server_addresses: token
+                .server_addresses
+                .iter()
+                .map(|(_, addr)| addr)
+                .collect(),
+            client_to_server_key: *token.client_to_server_key,
+            server_to_client_key: *token.server_to_client_key,
+            user_data: token.user_data,
+        })
+    }
+}
+
+/// The token a server sends in a challenge packet, and the client echoes back in its response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ChallengeToken {
+    /// The id of the client being challenged.
+    pub client_id: u64,
+    /// The user data from the client's connect token.
+    pub user_data: [u8; USER_DATA_BYTES],
+    /// Custom data the server attached to the token, see [`ServerConfig::challenge_data`](crate::ServerConfig::challenge_data).
+    pub custom_data: [u8; CHALLENGE_DATA_BYTES],
+}
+
+impl ChallengeToken {
+    /// Create a challenge token for a client, without custom data.
+    pub fn new(client_id: u64, user_data: [u8; USER_DATA_BYTES]) -> Self {
+        Self {
+            client_id,
+            user_data,
+            custom_data: [0; CHALLENGE_DATA_BYTES],
+        }
+    }
+    /// Set the custom data attached to the token. <br>
+    /// The default is all zeros, which is what the reference implementation puts there.
+    pub fn custom_data(mut self, custom_data: [u8; CHALLENGE_DATA_BYTES]) -> Self {
+        self.custom_data = custom_data;
+        self
+    }
+    /// Encrypts the token with the server's challenge key, using the challenge sequence as the nonce
+    /// (see [`challenge_token_nonce`](challenge_token_nonce)).
+    pub fn encrypt(&self, sequence: u64, key: &Key) -> Result<[u8; CHALLENGE_TOKEN_BYTES]> {
+        ChallengeTokenPrivate {
+            client_id: self.client_id,
+            user_data: self.user_data,
+            custom_data: self.custom_data,
+        }
+        .encrypt(sequence, key)
+    }
+    /// Decrypts a token encrypted with [`encrypt`](ChallengeToken::encrypt),
+    /// failing if it was encrypted with another key or sequence, or was tampered with.
+    pub fn decrypt(
+        encrypted: &[u8; CHALLENGE_TOKEN_BYTES],
+        sequence: u64,
+        key: &Key,
+    ) -> Result<Self> {
+        let mut buf = *encrypted;
+        let token = ChallengeTokenPrivate::decrypt(&mut buf, sequence, key)?;
+        Ok(Self {
+            client_id: token.client_id,
+            user_data: token.user_data,
+            custom_data: token.custom_data,
+        })
+    }
+}
+
+/// Generates a random nonce to encrypt a private connect token with.
+pub fn generate_nonce() -> [u8; NONCE_BYTES] {
+    generate_nonce_with(&mut OsRng)
+}
+
+/// Generates a nonce with the given RNG instead of the operating system's, e.g. a seeded one for reproducible tests.
+pub fn generate_nonce_with<R: CryptoRng + RngCore + ?Sized>(rng: &mut R) -> [u8; NONCE_BYTES] {
+    let mut nonce = [0; NONCE_BYTES];
+    rng.fill_bytes(&mut nonce);
+    nonce
+}
+
+/// Builds the additional data a private connect token is encrypted with:
+/// the netcode version, the protocol id and the expire timestamp (little-endian).
+pub fn connect_token_aad(protocol_id: u64, expire_timestamp: u64) -> [u8; CONNECT_TOKEN_AAD_BYTES] {
+    let mut aad = [0; CONNECT_TOKEN_AAD_BYTES];
+    let (version, rest) = aad.split_at_mut(NETCODE_VERSION.len());
+    version.copy_from_slice(NETCODE_VERSION);
+    rest[..8].copy_from_slice(&protocol_id.to_le_bytes());
+    rest[8..].copy_from_slice(&expire_timestamp.to_le_bytes());
+    aad
+}
+
+/// Builds the 12 byte nonce a challenge token (and every packet) is encrypted with from its sequence:
+/// 4 zero bytes followed by the sequence (little-endian).
+pub fn challenge_token_nonce(sequence: u64) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&sequence.to_le_bytes());
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{crypto::chacha_decrypt, ConnectToken};
+
+    #[test]
+    fn decrypts_builder_tokens() {
+        let private_key = crypto::generate_key();
+        let server_addr: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let token = ConnectToken::build(server_addr, 0x11223344, 123, private_key)
+            .user_data([7; USER_DATA_BYTES])
+            .generate_at(1_700_000_000)
+            .unwrap();
+
+        let private = PrivateConnectToken::decrypt(
+            &token.private_data,
+            token.protocol_id,
+            token.expire_timestamp,
+            &token.nonce.into(),
+            &private_key,
+        )
+        .unwrap();
+        assert_eq!(private.client_id, 123);
+        assert_eq!(private.server_addresses, [server_addr]);
+        assert_eq!(private.client_to_server_key, *token.client_to_server_key);
+        assert_eq!(private.user_data, [7; USER_DATA_BYTES]);
+
+        // the additional data binds the token to its public part
+        assert!(PrivateConnectToken::decrypt(
+            &token.private_data,
+            token.protocol_id,
+            token.expire_timestamp + 1,
+            &token.nonce.into(),
+            &private_key,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn challenge_token_roundtrip() {
+        let key = crypto::generate_key();
+        let token =
+            ChallengeToken::new(123, [7; USER_DATA_BYTES]).custom_data([9; CHALLENGE_DATA_BYTES]);
+        let encrypted = token.encrypt(42, &key).unwrap();
+        assert_eq!(
+            ChallengeToken::decrypt(&encrypted, 42, &key).unwrap(),
+            token
+        );
+        assert!(ChallengeToken::decrypt(&encrypted, 43, &key).is_err());
+
+        // the nonce helper matches the one packets are encrypted with
+        let mut buf = encrypted;
+        chacha_decrypt(&mut buf, None, 42, &key).unwrap();
+        assert_eq!(challenge_token_nonce(42)[4..], 42u64.to_le_bytes());
+        assert_eq!(buf[..8], 123u64.to_le_bytes());
+    }
+}
@@ -0,0 +1,137 @@
+//! Tracking which connect tokens were already used, see [`TokenReplayStore`](TokenReplayStore).
+
+use std::net::SocketAddr;
+
+use crate::error::Result;
+
+/// Where the server remembers the connect tokens it has already accepted, so a captured token can't be replayed
+/// from another address.
+///
+/// A token is identified by the 16 byte MAC of its private part. The default store is in memory, so a token can be replayed
+/// after the server restarts, or against another server process behind the same public address (e.g. a fleet behind
+/// a load balancer). Back the store with something shared between them, such as Redis or shared memory,
+/// and set it with [`ServerConfig::token_replay_store`](crate::ServerConfig::token_replay_store).
+///
+/// Implemented for closures with the signature of [`record_use`](TokenReplayStore::record_use).
+///
+/// # Example
+/// ```
+/// use std::{collections::HashMap, net::SocketAddr};
+/// use netcode::{ServerConfig, TokenReplayStore};
+///
+/// struct Redis {
+///     // a client for the shared store
+/// #   used: HashMap<[u8; 16], SocketAddr>,
+/// }
+/// # impl Redis {
+/// #     fn connect(_: &str) -> Self { Redis { used: HashMap::new() } }
+/// # }
+///
+/// impl TokenReplayStore for Redis {
+///     fn record_use(&mut self, mac: &[u8; 16], addr: SocketAddr, expire_timestamp: u64) -> netcode::Result<bool> {
+///         // SET mac addr NX EXAT expire_timestamp, then GET mac if it was already set
+///         # let used = *self.used.entry(*mac).or_insert(addr);
+///         Ok(used == addr)
+///     }
+/// }
+///
+/// let cfg = ServerConfig::default().token_replay_store(Redis::connect("redis://tokens.internal"));
+/// ```
+pub trait TokenReplayStore {
+    /// Records that the connect token with the MAC `mac` was used by a client at `addr`.
+    ///
+    /// Returns `true` if the token may be used, i.e. it wasn't used before or only by the same address
+    /// (a client resends its connection requests until it is answered), and `false` if it was used from another address.
+    /// The token doesn't need to be remembered after `expire_timestamp` (in seconds since the unix epoch),
+    /// expired tokens are rejected anyway.
+    ///
+    /// If an error is returned, the connection request is ignored.
+    fn record_use(
+        &mut self,
+        mac: &[u8; 16],
+        addr: SocketAddr,
+        expire_timestamp: u64,
+    ) -> Result<bool>;
+}
+
+impl<F> TokenReplayStore for F
+where
+    F: FnMut(&[u8; 16], SocketAddr, u64) -> Result<bool>,
+{
+    fn record_use(
+        &mut self,
+        mac: &[u8; 16],
+        addr: SocketAddr,
+        expire_timestamp: u64,
+    ) -> Result<bool> {
+        self(mac, addr, expire_timestamp)
+    }
+}
+
+pub(crate) type BoxedTokenReplayStore = Box<dyn TokenReplayStore + Send + Sync + 'static>;
+
+#[derive(Clone, Copy)]
+struct TokenEntry {
+    time: u64,
+    mac: [u8; 16],
+    addr: SocketAddr,
+}
+
+/// The default, in-memory [`TokenReplayStore`](TokenReplayStore) of a server.
+pub(crate) struct TokenEntries {
+    inner: Vec<TokenEntry>,
+    uses: u64,
+}
+
+impl TokenEntries {
+    pub(crate) fn new() -> Self {
+        Self {
+            inner: Vec::new(),
+            uses: 0,
+        }
+    }
+    fn find_or_insert(&mut self, entry: TokenEntry) -> bool {
+        let (mut oldest, mut matching) = (None, None);
+        let mut oldest_time = u64::MAX;
+        // Perform a linear search for the oldest and matching entries at the same time
+        for (idx, saved_entry) in self.inner.iter().enumerate() {
+            if entry.time < oldest_time {
+                oldest_time = saved_entry.time;
+                oldest = Some(idx);
+            }
+            if entry.mac == saved_entry.mac {
+                matching = Some(idx);
+            }
+        }
+        let Some(oldest) = oldest else {
+            // If there is no oldest entry then the list is empty, so just insert the entry
+            self.inner.push(entry);
+            return true;
+        };
+        if let Some(matching) = matching {
+            // Allow reusing tokens only if the address matches
+            self.inner[matching].addr == entry.addr
+        } else {
+            // If there is no matching entry, replace the oldest one
+            self.inner[oldest] = entry;
+            true
+        }
+    }
+}
+
+impl TokenReplayStore for TokenEntries {
+    fn record_use(
+        &mut self,
+        mac: &[u8; 16],
+        addr: SocketAddr,
+        _expire_timestamp: u64,
+    ) -> Result<bool> {
+        // the order of use is all that matters to find the oldest entry
+        self.uses += 1;
+        Ok(self.find_or_insert(TokenEntry {
+            time: self.uses,
+            mac: *mac,
+            addr,
+        }))
+    }
+}
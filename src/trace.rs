@@ -0,0 +1,22 @@
+//! Structured diagnostics for the `tracing` ecosystem.
+//!
+//! The crate logs through the [`log`] facade unconditionally. When the `tracing` feature is enabled,
+//! the client and server state machines additionally open spans and emit structured events
+//! (connection attempts, token rejections, decryption failures, replays and timeouts),
+//! so a subscriber can answer questions like "why won't this client connect" without a custom build.
+//!
+//! Without the feature, [`event!`] expands to nothing and the span attributes are not applied.
+
+/// Emits a `tracing` event at the given level, compiled out unless the `tracing` feature is enabled.
+///
+/// ```ignore
+/// trace::event!(DEBUG, %addr, reason = "token already used", "connection request rejected");
+/// ```
+macro_rules! event {
+    ($lvl:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        ::tracing::event!(::tracing::Level::$lvl, $($arg)+);
+    };
+}
+
+pub(crate) use event;
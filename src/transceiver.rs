@@ -1,7 +1,11 @@
-use std::net::SocketAddr;
+use alloc::{collections::VecDeque, vec::Vec};
+use core::net::SocketAddr;
 
 use crate::error::Error;
 
+/// The number of packets kept to be sent again after the transceiver would block, the oldest is dropped when more are queued.
+const MAX_BLOCKED_PACKETS: usize = 64;
+
 /// A trait for sending and receiving data.
 ///
 /// Both the server and client use a statically dispatched generic type `T: Transceiver` to send and receive data,
@@ -19,8 +23,141 @@ pub trait Transceiver {
     ///
     /// Should **NOT** block if no packet is available.
     fn recv(&self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>, Self::IntoError>;
-    /// Sends a packet to the specified address.
+    /// Sends a packet to the specified address, returns the number of bytes sent.
     ///
-    /// Should **NOT** block if the packet cannot be sent.
+    /// Should **NOT** block if the packet cannot be sent. If it would block (e.g. the socket's send buffer is full),
+    /// return `Ok(0)` or an `io::ErrorKind::WouldBlock` error:
+    /// the server or client queues the packet, sends it again later and reports [`SendOutcome::QueuedWouldBlock`].
     fn send(&self, buf: &[u8], addr: SocketAddr) -> Result<usize, Self::IntoError>;
+    /// Sends several packets to the specified address, stored back to back in `buf`,
+    /// each `segment_size` bytes long except the last, which may be shorter.
+    ///
+    /// The default sends them one at a time with [`send`](Transceiver::send), a [`NetcodeSocket`](crate::NetcodeSocket)
+    /// with [segmentation offload](crate::SocketOptions::segmentation_offload) sends them with one syscall.
+    /// Returns the number of bytes sent. Panics if `segment_size` is 0.
+    ///
+    /// Should **NOT** block if the packets cannot be sent.
+    fn send_segments(
+        &self,
+        buf: &[u8],
+        segment_size: usize,
+        addr: SocketAddr,
+    ) -> Result<usize, Self::IntoError> {
+        let mut sent = 0;
+        for segment in buf.chunks(segment_size) {
+            sent += self.send(segment, addr)?;
+        }
+        Ok(sent)
+    }
+    /// Replaces the socket with a new one bound to another local port, after an error the current socket won't recover from
+    /// (e.g. its network interface went down, or the device moved to another network).
+    ///
+    /// Returns `false` if the transceiver can't rebind, which the default does. A [`NetcodeSocket`](crate::NetcodeSocket)
+    /// binds a new socket to the same address with an ephemeral port. Clients rebind on such errors,
+    /// see [`ClientConfig::rebind_on_error`](crate::ClientConfig::rebind_on_error).
+    fn rebind(&mut self) -> Result<bool, Self::IntoError> {
+        Ok(false)
+    }
+    /// Sets up a connection to `addr`, for transceivers over a connection-oriented transport
+    /// (e.g. a `QuicTransceiver` with the `quic` feature). <br>
+    /// Clients call it before they send the first packet to each server they try, servers don't call it: they accept
+    /// the connections of their clients. The default does nothing, datagrams need no connection.
+    ///
+    /// Should **NOT** block: until the connection is set up, [`send`](Transceiver::send) should return that it would block,
+    /// so the packets are queued and sent again later.
+    fn open_connection(&self, addr: SocketAddr) -> Result<(), Self::IntoError> {
+        let _ = addr;
+        Ok(())
+    }
+    /// Tears down the connection to `addr`, once a client is done with the server at `addr` (it disconnected, was denied or
+    /// moves on to the next server) or a server disconnected the client at `addr` (or the client timed out). <br>
+    /// The packets sent to `addr` right before (e.g. the disconnect packets) should still be delivered.
+    /// The default does nothing.
+    fn close_connection(&self, addr: SocketAddr) -> Result<(), Self::IntoError> {
+        let _ = addr;
+        Ok(())
+    }
+}
+
+/// The outcome of sending a payload with [`Client::send`](crate::Client::send) or [`Server::send`](crate::Server::send),
+/// to decide whether to go on, back off or count the payload as lost.
+///
+/// Errors that retrying doesn't fix (e.g. a payload larger than the max payload size or a failed socket) are returned as errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum SendOutcome {
+    /// The payload was sent in a packet of this many bytes, including the packet header and the MAC.
+    Sent(usize),
+    /// The payload was queued to be sent with others in one packet from the next `update` or `flush`,
+    /// see [`ClientConfig::coalesce_payloads`](crate::ClientConfig::coalesce_payloads).
+    Coalesced,
+    /// The transceiver would block (e.g. the socket's send buffer is full), so the packet was queued and is sent before any
+    /// other from the next send, `update` or `flush`. <br>
+    /// Back off if this keeps happening: only the last 64 queued packets are kept, older ones are dropped.
+    QueuedWouldBlock,
+    /// The packet was queued to be sent later in the tick, see [`ServerConfig::pace_packets`](crate::ServerConfig::pace_packets).
+    Paced,
+    /// The payload was dropped without being sent, because the tick's budget of packets is used up
+    /// (see [`ServerConfig::pace_packets`](crate::ServerConfig::pace_packets)) or it is over the client's bandwidth limit
+    /// (see [`Server::set_client_bandwidth_limit`](crate::Server::set_client_bandwidth_limit)).
+    DroppedOverBudget,
+    /// The payload was dropped because the client (or the client at the index) isn't connected (anymore).
+    NotConnected,
+}
+
+/// Packets the transceiver couldn't send because it would block, sent again in order before any new packet.
+#[derive(Default)]
+pub(crate) struct SendBacklog {
+    packets: VecDeque<(Vec<u8>, SocketAddr)>,
+    /// The number of packets queued because the transceiver would block.
+    pub(crate) queued: u64,
+    /// The number of queued packets dropped because more than `MAX_BLOCKED_PACKETS` were waiting.
+    pub(crate) dropped: u64,
+}
+
+impl SendBacklog {
+    /// Sends a packet after the queued ones, or queues it if the transceiver would block.
+    pub(crate) fn send<T: Transceiver>(
+        &mut self,
+        transceiver: &T,
+        buf: &[u8],
+        addr: SocketAddr,
+    ) -> Result<SendOutcome, Error> {
+        if self.flush(transceiver)? {
+            if let Some(len) = try_send(transceiver, buf, addr)? {
+                return Ok(SendOutcome::Sent(len));
+            }
+        }
+        if self.packets.len() == MAX_BLOCKED_PACKETS {
+            self.packets.pop_front();
+            self.dropped += 1;
+        }
+        self.packets.push_back((buf.to_vec(), addr));
+        self.queued += 1;
+        Ok(SendOutcome::QueuedWouldBlock)
+    }
+    /// Sends the queued packets in order, returns `false` if the transceiver would block before all of them are sent.
+    pub(crate) fn flush<T: Transceiver>(&mut self, transceiver: &T) -> Result<bool, Error> {
+        while let Some((buf, addr)) = self.packets.front() {
+            if try_send(transceiver, buf, *addr)?.is_none() {
+                return Ok(false);
+            }
+            self.packets.pop_front();
+        }
+        Ok(true)
+    }
+}
+
+// Sends a packet, returns `None` if the transceiver would block.
+fn try_send<T: Transceiver>(
+    transceiver: &T,
+    buf: &[u8],
+    addr: SocketAddr,
+) -> Result<Option<usize>, Error> {
+    match transceiver.send(buf, addr).map_err(Into::into) {
+        Ok(0) if !buf.is_empty() => Ok(None),
+        Ok(len) => Ok(Some(len)),
+        Err(e) if e.is_would_block() => Ok(None),
+        Err(e) => Err(e),
+    }
 }
@@ -0,0 +1,100 @@
+//! Handing a connected client over to another server, see [`Server::transfer_client`](crate::Server::transfer_client).
+//!
+//! The server sends the client a connect token for the other server over the session, split in fragments since a
+//! whole token doesn't fit in a packet. The fragments are resent with the next keep-alives, the client puts them
+//! back together and ignores the copies of a token it already has.
+
+use alloc::boxed::Box;
+
+use crate::CONNECT_TOKEN_BYTES;
+
+pub(crate) const NUM_FRAGMENTS: usize = 2;
+pub(crate) const FRAGMENT_BYTES: usize = CONNECT_TOKEN_BYTES / NUM_FRAGMENTS;
+const ALL_FRAGMENTS: u8 = (1 << NUM_FRAGMENTS) - 1;
+
+/// Gets the fragments of a connect token, with their index.
+#[cfg(any(test, feature = "std"))]
+pub(crate) fn fragments(token: &[u8; CONNECT_TOKEN_BYTES]) -> impl Iterator<Item = (u8, &[u8])> {
+    token
+        .chunks(FRAGMENT_BYTES)
+        .enumerate()
+        .map(|(idx, data)| (idx as u8, data))
+}
+
+/// Puts the fragments of a transfer token back together on the client.
+pub(crate) struct TransferAssembler {
+    token: Box<[u8; CONNECT_TOKEN_BYTES]>,
+    // a bit for each fragment received
+    received: u8,
+}
+
+impl TransferAssembler {
+    pub(crate) fn new() -> Self {
+        Self {
+            token: Box::new([0; CONNECT_TOKEN_BYTES]),
+            received: 0,
+        }
+    }
+    /// Adds a fragment, returns the token once the last of its fragments arrived.
+    ///
+    /// A fragment that is already held is a resend and ignored. One that differs from the held fragment
+    /// belongs to a new token, which replaces the one being put together.
+    pub(crate) fn receive(
+        &mut self,
+        fragment: u8,
+        data: &[u8],
+    ) -> Option<&[u8; CONNECT_TOKEN_BYTES]> {
+        let idx = fragment as usize;
+        if idx >= NUM_FRAGMENTS || data.len() != FRAGMENT_BYTES {
+            log::debug!("client ignored invalid transfer fragment {fragment}");
+            return None;
+        }
+        let bit = 1 << idx;
+        let range = idx * FRAGMENT_BYTES..(idx + 1) * FRAGMENT_BYTES;
+        if self.received & bit != 0 {
+            if self.token[range.clone()] == *data {
+                return None;
+            }
+            self.received = 0;
+        }
+        self.token[range].copy_from_slice(data);
+        self.received |= bit;
+        (self.received == ALL_FRAGMENTS).then_some(&*self.token)
+    }
+    pub(crate) fn clear(&mut self) {
+        self.received = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_each_token_once() {
+        let token = |byte| {
+            let mut token = [byte; CONNECT_TOKEN_BYTES];
+            token[FRAGMENT_BYTES] = byte + 1;
+            token
+        };
+        let (first, second) = (token(1), token(3));
+        let mut assembler = TransferAssembler::new();
+
+        let [(0, head), (1, tail)] = fragments(&first).collect::<Vec<_>>()[..] else {
+            panic!("a token is sent in two fragments");
+        };
+        assert_eq!(assembler.receive(1, tail), None);
+        assert_eq!(assembler.receive(1, tail), None);
+        assert_eq!(assembler.receive(0, head), Some(&first));
+        // resends of a token are ignored
+        assert_eq!(assembler.receive(0, head), None);
+        assert_eq!(assembler.receive(1, tail), None);
+
+        for (fragment, data) in fragments(&second) {
+            let assembled = assembler.receive(fragment, data).copied();
+            assert_eq!(assembled, (fragment == 1).then_some(second));
+        }
+        assert_eq!(assembler.receive(2, head), None);
+        assert_eq!(assembler.receive(0, &head[1..]), None);
+    }
+}
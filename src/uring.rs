@@ -0,0 +1,648 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr::{self, addr_of, addr_of_mut};
+use std::slice;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use socket2::SockAddr;
+
+use crate::socket::{canonical, v4_mapped, Error, NetcodeSocket, Result, SocketOptions};
+use crate::transceiver::Transceiver;
+
+// Receives are posted to the ring at all times, sends wait for a free slot.
+const RECV_SLOTS: usize = 256;
+const SEND_SLOTS: usize = 256;
+const SLOTS: usize = RECV_SLOTS + SEND_SLOTS;
+// Larger than any netcode packet, even with a relay header.
+const SLOT_BYTES: usize = 2048;
+const CANCEL: u64 = u64::MAX;
+
+// From linux/io_uring.h
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x8000000;
+const IORING_OFF_SQES: libc::off_t = 0x10000000;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OP_SENDMSG: u8 = 9;
+const IORING_OP_RECVMSG: u8 = 10;
+const IORING_OP_ASYNC_CANCEL: u8 = 14;
+
+// The kernel's structs, not all fields are used.
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    msg_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+#[allow(dead_code)]
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mmap {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> io::Result<Self> {
+        // SAFETY: a new shared mapping of the ring's memory, which nothing else points to.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr.cast(),
+            len,
+        })
+    }
+    fn at<T>(&self, offset: u32) -> *mut T {
+        self.ptr.wrapping_add(offset as usize).cast()
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created in `Mmap::new` and is only unmapped here.
+        unsafe { libc::munmap(self.ptr.cast(), self.len) };
+    }
+}
+
+/// The submission and completion queues, shared with the kernel.
+struct Ring {
+    fd: OwnedFd,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sq_array: *mut u32,
+    sqes: *mut Sqe,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    _maps: [Mmap; 3],
+}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        // SAFETY: `params` is a valid `io_uring_params`.
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, entries, addr_of_mut!(params)) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the ring's file descriptor was just created and is owned by nothing else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let sq = Mmap::new(
+            fd.as_raw_fd(),
+            params.sq_off.array as usize + params.sq_entries as usize * mem::size_of::<u32>(),
+            IORING_OFF_SQ_RING,
+        )?;
+        let cq = Mmap::new(
+            fd.as_raw_fd(),
+            params.cq_off.cqes as usize + params.cq_entries as usize * mem::size_of::<Cqe>(),
+            IORING_OFF_CQ_RING,
+        )?;
+        let sqes = Mmap::new(
+            fd.as_raw_fd(),
+            params.sq_entries as usize * mem::size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+        // SAFETY: the offsets the kernel returned are within the mappings.
+        let (sq_mask, cq_mask) = unsafe {
+            (
+                *sq.at::<u32>(params.sq_off.ring_mask),
+                *cq.at::<u32>(params.cq_off.ring_mask),
+            )
+        };
+        Ok(Self {
+            sq_head: sq.at(params.sq_off.head),
+            sq_tail: sq.at(params.sq_off.tail),
+            sq_mask,
+            sq_entries: params.sq_entries,
+            sq_array: sq.at(params.sq_off.array),
+            sqes: sqes.at(0),
+            cq_head: cq.at(params.cq_off.head),
+            cq_tail: cq.at(params.cq_off.tail),
+            cq_mask,
+            cqes: cq.at(params.cq_off.cqes),
+            _maps: [sq, cq, sqes],
+            fd,
+        })
+    }
+
+    /// Queues a submission, returns `false` if the submission queue is full.
+    fn push(&self, sqe: Sqe) -> bool {
+        // SAFETY: the ring's pointers are valid while it is mapped, and this is the queue's only producer.
+        unsafe {
+            let head = (*self.sq_head).load(Ordering::Acquire);
+            let tail = (*self.sq_tail).load(Ordering::Relaxed);
+            if tail.wrapping_sub(head) == self.sq_entries {
+                return false;
+            }
+            let idx = tail & self.sq_mask;
+            self.sqes.add(idx as usize).write(sqe);
+            self.sq_array.add(idx as usize).write(idx);
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        true
+    }
+
+    /// Takes the next completion, if there is one.
+    fn pop(&self) -> Option<Cqe> {
+        // SAFETY: the ring's pointers are valid while it is mapped, and this is the queue's only consumer.
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            if head == (*self.cq_tail).load(Ordering::Acquire) {
+                return None;
+            }
+            let cqe = self.cqes.add((head & self.cq_mask) as usize).read();
+            (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+            Some(cqe)
+        }
+    }
+
+    fn unsubmitted(&self) -> u32 {
+        // SAFETY: the ring's pointers are valid while it is mapped.
+        unsafe {
+            let head = (*self.sq_head).load(Ordering::Acquire);
+            (*self.sq_tail).load(Ordering::Relaxed).wrapping_sub(head)
+        }
+    }
+
+    /// Submits the queued submissions and, with `IORING_ENTER_GETEVENTS`, waits for `min_complete` completions.
+    fn enter(&self, min_complete: u32, flags: u32) -> io::Result<()> {
+        loop {
+            // SAFETY: no signal mask is passed, the submissions point to memory owned by the socket.
+            let ret = unsafe {
+                libc::syscall(
+                    libc::SYS_io_uring_enter,
+                    self.fd.as_raw_fd(),
+                    self.unsubmitted(),
+                    min_complete,
+                    flags,
+                    ptr::null::<libc::sigset_t>(),
+                    0usize,
+                )
+            };
+            if ret >= 0 {
+                return Ok(());
+            }
+            match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => continue,
+                // the completion queue is full, the submissions are retried on the next call
+                e if matches!(e.raw_os_error(), Some(libc::EAGAIN | libc::EBUSY)) => return Ok(()),
+                e => return Err(e),
+            }
+        }
+    }
+}
+
+/// A packet buffer and the message header that points to it.
+#[repr(C)]
+struct Slot {
+    msg: libc::msghdr,
+    iov: libc::iovec,
+    addr: libc::sockaddr_storage,
+    buf: [u8; SLOT_BYTES],
+}
+
+struct State {
+    // receive slots whose completions haven't been returned yet, with their results
+    received: VecDeque<(usize, i32)>,
+    free_sends: Vec<usize>,
+    in_flight: usize,
+}
+
+/// A UDP socket that sends and receives through an [io_uring](https://man7.org/linux/man-pages/man7/io_uring.7.html),
+/// for servers that handle so many packets that the `recv_from` and `send_to` syscalls of [`NetcodeSocket`](NetcodeSocket) become the bottleneck.
+///
+/// A receive is posted to the ring for each of a fixed set of buffers allocated up front, so the kernel fills them as packets
+/// arrive and [`recv`](Transceiver::recv) only makes a syscall once there are no more completed receives to return.
+/// Sends are copied to free buffers of the same kind and submitted right away.
+///
+/// It has the same constructors as `NetcodeSocket` and is a drop-in replacement for it, passed to
+/// [`Server::with_config_and_transceiver`](crate::Server::with_config_and_transceiver) or
+/// [`Client::with_config_and_transceiver`](crate::Client::with_config_and_transceiver).
+/// Needs Linux 5.7 or later, creating one fails if io_uring isn't available (e.g. disabled by a container's seccomp profile).
+///
+/// # Example
+///
+/// ```
+/// use netcode::{IoUringSocket, Server, ServerConfig};
+///
+/// # let Ok(_) = IoUringSocket::new("127.0.0.1:0", 0, 0) else { return };
+/// let socket = IoUringSocket::new("127.0.0.1:0", 4 * 1024 * 1024, 4 * 1024 * 1024).unwrap();
+/// let server = Server::with_config_and_transceiver(0x11, netcode::generate_key(), ServerConfig::default(), socket).unwrap();
+/// ```
+pub struct IoUringSocket {
+    socket: NetcodeSocket,
+    ring: Ring,
+    // `SLOTS` slots, the first `RECV_SLOTS` receive and the rest send
+    slots: *mut Slot,
+    state: RefCell<State>,
+}
+
+// SAFETY: the ring and the slots are owned by the socket. A thread that exits cancels the receives it posted,
+// which are then posted again by whichever thread uses the socket.
+unsafe impl Send for IoUringSocket {}
+
+impl IoUringSocket {
+    /// Creates a socket bound to `addr`, see [`NetcodeSocket::new`](NetcodeSocket::new).
+    pub fn new(
+        addr: impl ToSocketAddrs,
+        send_buf_size: usize,
+        recv_buf_size: usize,
+    ) -> Result<Self> {
+        Self::with_socket(NetcodeSocket::new(addr, send_buf_size, recv_buf_size)?)
+    }
+    /// Creates a socket bound to `addr`, with additional [`SocketOptions`](SocketOptions), see [`NetcodeSocket::with_options`](NetcodeSocket::with_options).
+    ///
+    /// [`segmentation_offload`](SocketOptions::segmentation_offload) is ignored, the ring's buffers only hold one packet each.
+    pub fn with_options(
+        addr: impl ToSocketAddrs,
+        send_buf_size: usize,
+        recv_buf_size: usize,
+        options: &SocketOptions,
+    ) -> Result<Self> {
+        Self::with_socket(NetcodeSocket::with_options(
+            addr,
+            send_buf_size,
+            recv_buf_size,
+            &options.clone().segmentation_offload(false),
+        )?)
+    }
+    /// Creates a dual-stack socket bound to `port` on all IPv4 and IPv6 interfaces, see [`NetcodeSocket::dual_stack`](NetcodeSocket::dual_stack).
+    pub fn dual_stack(port: u16, send_buf_size: usize, recv_buf_size: usize) -> Result<Self> {
+        Self::with_socket(NetcodeSocket::dual_stack(
+            port,
+            send_buf_size,
+            recv_buf_size,
+        )?)
+    }
+    fn with_socket(socket: NetcodeSocket) -> Result<Self> {
+        // the ring waits for the socket to be readable itself, posted receives on a non-blocking socket would fail instead
+        socket.socket.set_nonblocking(false)?;
+        let ring = Ring::new(SLOTS as u32)?;
+        // SAFETY: all zeros is a valid (empty) message header, address and buffer.
+        let slots: Box<[Slot]> = (0..SLOTS).map(|_| unsafe { mem::zeroed() }).collect();
+        let socket = Self {
+            socket,
+            ring,
+            slots: Box::into_raw(slots).cast(),
+            state: RefCell::new(State {
+                received: VecDeque::with_capacity(RECV_SLOTS),
+                free_sends: (RECV_SLOTS..SLOTS).collect(),
+                in_flight: 0,
+            }),
+        };
+        {
+            let mut state = socket.state.borrow_mut();
+            for idx in 0..RECV_SLOTS {
+                socket.post_recv(&mut state, idx)?;
+            }
+        }
+        socket.ring.enter(0, 0)?;
+        Ok(socket)
+    }
+
+    fn slot(&self, idx: usize) -> *mut Slot {
+        assert!(idx < SLOTS);
+        self.slots.wrapping_add(idx)
+    }
+
+    /// Points the slot's message header at its buffer and address, for a packet of `len` bytes.
+    fn prepare(&self, idx: usize, len: usize, addr_len: libc::socklen_t) -> u64 {
+        let slot = self.slot(idx);
+        // SAFETY: the slot isn't in flight, so the kernel doesn't access it.
+        unsafe {
+            (*slot).iov.iov_base = addr_of_mut!((*slot).buf).cast();
+            (*slot).iov.iov_len = len;
+            (*slot).msg.msg_name = addr_of_mut!((*slot).addr).cast();
+            (*slot).msg.msg_namelen = addr_len;
+            (*slot).msg.msg_iov = addr_of_mut!((*slot).iov);
+            (*slot).msg.msg_iovlen = 1;
+            addr_of_mut!((*slot).msg) as u64
+        }
+    }
+
+    fn submit(&self, state: &mut State, sqe: Sqe) -> io::Result<()> {
+        if !self.ring.push(sqe) {
+            self.ring.enter(0, 0)?;
+            if !self.ring.push(sqe) {
+                return Err(io::Error::other("the io_uring submission queue is full"));
+            }
+        }
+        state.in_flight += 1;
+        Ok(())
+    }
+
+    fn post_recv(&self, state: &mut State, idx: usize) -> io::Result<()> {
+        let msg = self.prepare(
+            idx,
+            SLOT_BYTES,
+            mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t,
+        );
+        let sqe = Sqe {
+            opcode: IORING_OP_RECVMSG,
+            fd: self.socket.socket.as_raw_fd(),
+            addr: msg,
+            len: 1,
+            user_data: idx as u64,
+            ..Default::default()
+        };
+        self.submit(state, sqe)
+    }
+
+    /// Moves the completions out of the completion queue.
+    fn reap(&self, state: &mut State) {
+        while let Some(cqe) = self.ring.pop() {
+            state.in_flight -= 1;
+            match cqe.user_data {
+                CANCEL => {}
+                idx if (idx as usize) < RECV_SLOTS => {
+                    state.received.push_back((idx as usize, cqe.res))
+                }
+                idx => {
+                    if cqe.res < 0 {
+                        log::debug!(
+                            "io_uring send failed: {}",
+                            io::Error::from_raw_os_error(-cqe.res)
+                        );
+                    }
+                    state.free_sends.push(idx as usize);
+                }
+            }
+        }
+    }
+
+    fn take_received(
+        &self,
+        idx: usize,
+        res: i32,
+        buf: &mut [u8],
+    ) -> io::Result<Option<(usize, SocketAddr)>> {
+        if res < 0 {
+            return match -res {
+                libc::EAGAIN | libc::EINTR | libc::ECANCELED => Ok(None),
+                errno => Err(io::Error::from_raw_os_error(errno)),
+            };
+        }
+        let slot = self.slot(idx);
+        let len = (res as usize).min(buf.len());
+        // SAFETY: the receive completed, so the kernel is done with the slot, and wrote `res` bytes and the sender's address to it.
+        let addr = unsafe {
+            buf[..len].copy_from_slice(slice::from_raw_parts(addr_of!((*slot).buf).cast(), len));
+            SockAddr::new((*slot).addr, (*slot).msg.msg_namelen)
+        };
+        match addr.as_socket() {
+            Some(addr) if len > 0 && self.socket.dual_stack => Ok(Some((len, canonical(addr)))),
+            Some(addr) if len > 0 => Ok(Some((len, addr))),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Transceiver for IoUringSocket {
+    type IntoError = Error;
+
+    fn addr(&self) -> SocketAddr {
+        self.socket.addr()
+    }
+
+    fn recv(&self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        let mut state = self.state.borrow_mut();
+        self.reap(&mut state);
+        if state.received.is_empty() {
+            // submits the receives posted again, and runs the kernel's pending completions
+            self.ring.enter(0, IORING_ENTER_GETEVENTS)?;
+            self.reap(&mut state);
+        }
+        while let Some((idx, res)) = state.received.pop_front() {
+            let received = self.take_received(idx, res, buf);
+            self.post_recv(&mut state, idx)?;
+            if let Some(received) = received? {
+                return Ok(Some(received));
+            }
+        }
+        Ok(None)
+    }
+
+    fn send(&self, buf: &[u8], addr: SocketAddr) -> Result<usize> {
+        if buf.len() > SLOT_BYTES {
+            return Err(Error::from(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "packet is larger than an io_uring buffer",
+            )));
+        }
+        let addr = SockAddr::from(if self.socket.dual_stack {
+            v4_mapped(addr)
+        } else {
+            addr
+        });
+        let mut state = self.state.borrow_mut();
+        if state.free_sends.is_empty() {
+            self.ring.enter(0, IORING_ENTER_GETEVENTS)?;
+            self.reap(&mut state);
+        }
+        // like a full socket buffer, the packet is dropped
+        let Some(idx) = state.free_sends.pop() else {
+            return Ok(0);
+        };
+        let addr_len = addr.len();
+        let slot = self.slot(idx);
+        // SAFETY: the slot isn't in flight, so the kernel doesn't access it.
+        unsafe {
+            (*slot).addr = addr.as_storage();
+            ptr::copy_nonoverlapping(buf.as_ptr(), addr_of_mut!((*slot).buf).cast(), buf.len());
+        }
+        let sqe = Sqe {
+            opcode: IORING_OP_SENDMSG,
+            fd: self.socket.socket.as_raw_fd(),
+            addr: self.prepare(idx, buf.len(), addr_len),
+            len: 1,
+            user_data: idx as u64,
+            ..Default::default()
+        };
+        self.submit(&mut state, sqe)?;
+        self.ring.enter(0, 0)?;
+        Ok(buf.len())
+    }
+}
+
+impl Drop for IoUringSocket {
+    fn drop(&mut self) {
+        // The kernel writes to the buffers of posted receives, so they are canceled,
+        // and the buffers only freed after every submission completed.
+        let mut state = self.state.borrow_mut();
+        let _ = self.ring.enter(0, 0);
+        for idx in 0..RECV_SLOTS {
+            let cancel = Sqe {
+                opcode: IORING_OP_ASYNC_CANCEL,
+                addr: idx as u64,
+                user_data: CANCEL,
+                ..Default::default()
+            };
+            if self.submit(&mut state, cancel).is_err() {
+                break;
+            }
+        }
+        while state.in_flight > 0 {
+            if self.ring.enter(1, IORING_ENTER_GETEVENTS).is_err() {
+                break;
+            }
+            self.reap(&mut state);
+        }
+        if state.in_flight == 0 {
+            // SAFETY: the slots were allocated as a boxed slice of `SLOTS` in `with_socket`, and the kernel is done with them.
+            drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(self.slots, SLOTS)) });
+        } else {
+            // leaked rather than freed while the kernel may still write to them
+            log::error!("failed to cancel the io_uring receives, leaking their buffers");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_and_receives_through_the_ring() {
+        let Ok(socket) = IoUringSocket::new("127.0.0.1:0", 256 * 1024, 256 * 1024) else {
+            // io_uring is disabled on this host
+            return;
+        };
+        let peer = NetcodeSocket::new("127.0.0.1:0", 256 * 1024, 256 * 1024).unwrap();
+        let mut buf = [0; 64];
+        // more packets than slots, in both directions
+        for i in 0..(SLOTS * 2) as u32 {
+            peer.send(&i.to_le_bytes(), socket.addr()).unwrap();
+            let (len, from) = loop {
+                if let Some(received) = socket.recv(&mut buf).unwrap() {
+                    break received;
+                }
+            };
+            assert_eq!((&buf[..len], from), (&i.to_le_bytes()[..], peer.addr()));
+
+            assert_eq!(socket.send(&i.to_be_bytes(), peer.addr()).unwrap(), 4);
+            let (len, from) = loop {
+                if let Some(received) = peer.recv(&mut buf).unwrap() {
+                    break received;
+                }
+            };
+            assert_eq!((&buf[..len], from), (&i.to_be_bytes()[..], socket.addr()));
+        }
+        assert!(socket.recv(&mut buf).unwrap().is_none());
+        assert!(socket.send(&[0; SLOT_BYTES + 1], peer.addr()).is_err());
+    }
+
+    #[test]
+    fn server_accepts_clients() {
+        use crate::{Client, ConnectToken, Server, ServerConfig};
+
+        let Ok(socket) = IoUringSocket::new("127.0.0.1:0", 256 * 1024, 256 * 1024) else {
+            return;
+        };
+        let addr = socket.addr();
+        let key = crate::generate_key();
+        let mut server =
+            Server::with_config_and_transceiver(0, key, ServerConfig::default(), socket).unwrap();
+        let token = ConnectToken::build(addr, 0, 1, key).generate().unwrap();
+        let mut client = Client::new(&token.try_into_bytes().unwrap()).unwrap();
+        client.connect();
+        let mut time = 0.0;
+        while !client.is_connected() && time < 5.0 {
+            client.update(time);
+            server.update(time);
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            time += 0.01;
+        }
+        assert!(client.is_connected());
+        client.send(b"hello").unwrap();
+        let mut received = None;
+        while received.is_none() && time < 10.0 {
+            server.update(time);
+            received = server.recv();
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            time += 0.01;
+        }
+        assert_eq!(received.unwrap().0, b"hello");
+    }
+}
@@ -0,0 +1,14 @@
+#!/bin/sh
+# Builds vectors.c against a checkout of the netcode reference implementation (https://github.com/mas-bandwidth/netcode)
+# and writes the vectors to stdout. Needs a C compiler and libsodium (e.g. the `libsodium-dev` package).
+# The output is committed as vectors.txt in this directory, which `cargo test` checks the crate against:
+#
+#     tests/netcode_c/generate.sh path/to/netcode > tests/netcode_c/vectors.txt
+set -eu
+
+netcode=${1:?usage: generate.sh path/to/netcode}
+out=$(mktemp -d)
+trap 'rm -rf "$out"' EXIT
+
+cc -std=gnu99 -O1 -I"$netcode" -o "$out/vectors" "$(dirname "$0")/vectors.c" -lsodium -lm
+"$out/vectors"
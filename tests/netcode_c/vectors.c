@@ -0,0 +1,177 @@
+/*
+    Writes the wire-format vectors checked by `src/compat.rs` with the netcode 1.02 reference implementation,
+    one `name hex` line per vector, from the same inputs as the tests there.
+
+    Built and run by `generate.sh` in this directory:
+
+        tests/netcode_c/generate.sh path/to/netcode > tests/netcode_c/vectors.txt
+        cargo test --lib compat::
+*/
+
+#include "netcode.c"
+
+#include <stdio.h>
+
+#define PROTOCOL_ID 0x1122334455667788ULL
+#define CREATE_TIMESTAMP 1700000000ULL
+#define EXPIRE_TIMESTAMP 1700000030ULL
+#define TIMEOUT_SECONDS 15
+#define CLIENT_ID 0x0102030405060708ULL
+
+static void key( uint8_t seed, uint8_t * out )
+{
+    for ( int i = 0; i < NETCODE_KEY_BYTES; i++ )
+        out[i] = (uint8_t) ( seed + i );
+}
+
+static void token_nonce( uint8_t * out )
+{
+    for ( int i = 0; i < NETCODE_CONNECT_TOKEN_NONCE_BYTES; i++ )
+        out[i] = (uint8_t) ( 0xA0 + i );
+}
+
+static void user_data( uint8_t * out )
+{
+    for ( int i = 0; i < NETCODE_USER_DATA_BYTES; i++ )
+        out[i] = (uint8_t) i;
+}
+
+static int addresses( struct netcode_address_t * out )
+{
+    static const uint16_t ipv6[8] = { 0xfe80, 0, 0, 0, 0x0202, 0xb3ff, 0xfe1e, 0x8329 };
+    memset( out, 0, 2 * sizeof( struct netcode_address_t ) );
+    out[0].type = NETCODE_ADDRESS_IPV4;
+    out[0].data.ipv4[0] = 127;
+    out[0].data.ipv4[3] = 1;
+    out[0].port = 40000;
+    out[1].type = NETCODE_ADDRESS_IPV6;
+    memcpy( out[1].data.ipv6, ipv6, sizeof( ipv6 ) );
+    out[1].port = 50000;
+    return 2;
+}
+
+static void print_vector( const char * name, const uint8_t * data, int length )
+{
+    printf( "%s ", name );
+    for ( int i = 0; i < length; i++ )
+        printf( "%02x", data[i] );
+    printf( "\n" );
+}
+
+static void print_packet( const char * name, void * packet, uint64_t sequence, uint8_t seed )
+{
+    uint8_t packet_key[NETCODE_KEY_BYTES];
+    uint8_t buffer[NETCODE_MAX_PACKET_BYTES];
+    key( seed, packet_key );
+    int bytes = netcode_write_packet( packet, buffer, sizeof( buffer ), sequence, packet_key, PROTOCOL_ID );
+    if ( bytes <= 0 )
+    {
+        fprintf( stderr, "failed to write %s\n", name );
+        exit( 1 );
+    }
+    print_vector( name, buffer, bytes );
+}
+
+int main( void )
+{
+    if ( netcode_init() != NETCODE_OK )
+    {
+        fprintf( stderr, "failed to initialize netcode\n" );
+        return 1;
+    }
+
+    print_vector( "version", NETCODE_VERSION_INFO, NETCODE_VERSION_INFO_BYTES );
+
+    uint8_t nonce[NETCODE_CONNECT_TOKEN_NONCE_BYTES];
+    uint8_t private_key[NETCODE_KEY_BYTES];
+    token_nonce( nonce );
+    key( 0x70, private_key );
+
+    struct netcode_connect_token_private_t private_token;
+    memset( &private_token, 0, sizeof( private_token ) );
+    private_token.client_id = CLIENT_ID;
+    private_token.timeout_seconds = TIMEOUT_SECONDS;
+    private_token.num_server_addresses = addresses( private_token.server_addresses );
+    key( 0x10, private_token.client_to_server_key );
+    key( 0x40, private_token.server_to_client_key );
+    user_data( private_token.user_data );
+    uint8_t private_data[NETCODE_CONNECT_TOKEN_PRIVATE_BYTES];
+    memset( private_data, 0, sizeof( private_data ) );
+    netcode_write_connect_token_private( &private_token, private_data, sizeof( private_data ) );
+    if ( netcode_encrypt_connect_token_private( private_data, sizeof( private_data ), NETCODE_VERSION_INFO, PROTOCOL_ID, EXPIRE_TIMESTAMP, nonce, private_key ) != NETCODE_OK )
+    {
+        fprintf( stderr, "failed to encrypt the private connect token\n" );
+        return 1;
+    }
+    print_vector( "connect_token_private", private_data, sizeof( private_data ) );
+
+    // the public token and the request carry a fixed private part, so they don't depend on the encryption above
+    struct netcode_connect_token_t connect_token;
+    memset( &connect_token, 0, sizeof( connect_token ) );
+    memcpy( connect_token.version_info, NETCODE_VERSION_INFO, NETCODE_VERSION_INFO_BYTES );
+    connect_token.protocol_id = PROTOCOL_ID;
+    connect_token.create_timestamp = CREATE_TIMESTAMP;
+    connect_token.expire_timestamp = EXPIRE_TIMESTAMP;
+    memcpy( connect_token.nonce, nonce, sizeof( nonce ) );
+    memset( connect_token.private_data, 0x5A, sizeof( connect_token.private_data ) );
+    connect_token.timeout_seconds = TIMEOUT_SECONDS;
+    connect_token.num_server_addresses = addresses( connect_token.server_addresses );
+    key( 0x10, connect_token.client_to_server_key );
+    key( 0x40, connect_token.server_to_client_key );
+    uint8_t connect_token_data[NETCODE_CONNECT_TOKEN_BYTES];
+    memset( connect_token_data, 0, sizeof( connect_token_data ) );
+    netcode_write_connect_token( &connect_token, connect_token_data, sizeof( connect_token_data ) );
+    print_vector( "connect_token", connect_token_data, sizeof( connect_token_data ) );
+
+    struct netcode_challenge_token_t challenge_token;
+    memset( &challenge_token, 0, sizeof( challenge_token ) );
+    challenge_token.client_id = CLIENT_ID;
+    user_data( challenge_token.user_data );
+    uint8_t challenge_token_data[NETCODE_CHALLENGE_TOKEN_BYTES];
+    memset( challenge_token_data, 0, sizeof( challenge_token_data ) );
+    netcode_write_challenge_token( &challenge_token, challenge_token_data, sizeof( challenge_token_data ) );
+    if ( netcode_encrypt_challenge_token( challenge_token_data, sizeof( challenge_token_data ), 0x0A0B, private_key ) != NETCODE_OK )
+    {
+        fprintf( stderr, "failed to encrypt the challenge token\n" );
+        return 1;
+    }
+    print_vector( "challenge_token", challenge_token_data, sizeof( challenge_token_data ) );
+
+    struct netcode_connection_request_packet_t request;
+    memset( &request, 0, sizeof( request ) );
+    request.packet_type = NETCODE_CONNECTION_REQUEST_PACKET;
+    memcpy( request.version_info, NETCODE_VERSION_INFO, NETCODE_VERSION_INFO_BYTES );
+    request.protocol_id = PROTOCOL_ID;
+    request.connect_token_expire_timestamp = EXPIRE_TIMESTAMP;
+    memcpy( request.connect_token_nonce, nonce, sizeof( nonce ) );
+    memset( request.connect_token_data, 0x5A, sizeof( request.connect_token_data ) );
+    print_packet( "request_packet", &request, 0, 0 );
+
+    struct netcode_connection_challenge_packet_t challenge;
+    challenge.packet_type = NETCODE_CONNECTION_CHALLENGE_PACKET;
+    challenge.challenge_token_sequence = 77;
+    memset( challenge.challenge_token_data, 0x3C, sizeof( challenge.challenge_token_data ) );
+    print_packet( "challenge_packet", &challenge, 9, 0x40 );
+
+    static const char payload[] = "netcode interop";
+    struct netcode_connection_payload_packet_t * payload_packet = (struct netcode_connection_payload_packet_t *)
+        malloc( sizeof( struct netcode_connection_payload_packet_t ) + sizeof( payload ) );
+    payload_packet->packet_type = NETCODE_CONNECTION_PAYLOAD_PACKET;
+    payload_packet->payload_bytes = sizeof( payload ) - 1;
+    memcpy( payload_packet->payload_data, payload, sizeof( payload ) - 1 );
+    print_packet( "payload_packet", payload_packet, 0x030201, 0x10 );
+    free( payload_packet );
+
+    struct netcode_connection_keep_alive_packet_t keep_alive;
+    keep_alive.packet_type = NETCODE_CONNECTION_KEEP_ALIVE_PACKET;
+    keep_alive.client_index = 3;
+    keep_alive.max_clients = 64;
+    print_packet( "keep_alive_packet", &keep_alive, 0, 0x40 );
+
+    struct netcode_connection_disconnect_packet_t disconnect;
+    disconnect.packet_type = NETCODE_CONNECTION_DISCONNECT_PACKET;
+    print_packet( "disconnect_packet", &disconnect, 1, 0x40 );
+
+    netcode_term();
+    return 0;
+}
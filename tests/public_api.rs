@@ -0,0 +1,694 @@
+//! Fixtures of the public API as a downstream crate sees it.
+//!
+//! Everything here only uses the public API, so a change that would break downstream builds
+//! (a removed or renamed item, a changed signature, a new variant in an exhaustive enum) fails to compile here first.
+//! Additions are fine: configs are built with setters, and the enums and stats structs are `#[non_exhaustive]`.
+#![cfg(feature = "std")]
+
+use std::net::SocketAddr;
+
+use netcode::query::{self, QueryConfig, ServerInfo};
+use netcode::relay::{self, RelayConfig};
+use netcode::{
+    Client, ClientConfig, ClientIndex, ClientState, ClientStats, Clock, ConfigError, ConnectConfig,
+    ConnectDecision, ConnectToken, ConnectTokenBuilder, ConnectionPhase, ConnectionQuality,
+    CryptoError, DuplicateClientId, EchoMode, Error, InvalidTokenError, JitterBuffer, JitterStats,
+    Key, KeyExt, KeyProvider, LinkCheckConfig, LinkCheckReport, ManualClock, MultiClient,
+    NetcodeSocket, PacketAllowList, PacketCounts, PacketDirection, PacketError, PacketRecord,
+    PacketType, PendingEviction, ProtocolStats, QueueOverflow, ReceivedSnapshot, Recorder,
+    Recording, ReplayTransceiver, Result, SendOutcome, Server, ServerCluster, ServerConfig,
+    ServerHandle, ServerStats, SessionHandle, SessionTransceiver, ShutdownReport, SnapshotChannel,
+    SocketError, SocketOptions, SystemClock, TokenReplayStore, Transceiver, CHALLENGE_DATA_BYTES,
+    CONNECT_TOKEN_BYTES, MAX_CLIENTS, MAX_HEARTBEAT_BYTES, MAX_PACKET_SIZE, NETCODE_VERSION,
+    PRIVATE_KEY_BYTES, USER_DATA_BYTES,
+};
+
+#[test]
+fn constants() {
+    let _: usize = PRIVATE_KEY_BYTES;
+    let _: usize = USER_DATA_BYTES;
+    let _: usize = CHALLENGE_DATA_BYTES;
+    let _: usize = CONNECT_TOKEN_BYTES;
+    let _: usize = MAX_PACKET_SIZE;
+    let _: usize = MAX_HEARTBEAT_BYTES;
+    let _: usize = MAX_CLIENTS;
+    let _: &[u8; 13] = NETCODE_VERSION;
+    let _: fn() -> Key = netcode::generate_key;
+    let _: Key = netcode::try_generate_key().unwrap();
+    let _: Key = netcode::generate_key_with(&mut netcode::rand_core::OsRng);
+    let _: Key = netcode::try_generate_key_with(&mut netcode::rand_core::OsRng).unwrap();
+    let _: [u8; 24] = netcode::token_crypto::generate_nonce_with(&mut netcode::rand_core::OsRng);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn testing_utilities() {
+    use netcode::testing::{self, ChannelTransceiver, SeededRng};
+
+    let mut rng = SeededRng::new(7);
+    let _: Key = netcode::generate_key_with(&mut rng);
+    let _: [u8; 24] = netcode::token_crypto::generate_nonce_with(&mut rng);
+    let _ = SeededRng::from_seed([1; PRIVATE_KEY_BYTES]);
+    let _: (ChannelTransceiver, ChannelTransceiver) = testing::channel_pair();
+}
+
+#[test]
+fn multi_client_signatures() {
+    type Session = Client<SessionTransceiver<NetcodeSocket>>;
+    let _: fn() -> netcode::Result<MultiClient<NetcodeSocket>> = MultiClient::new;
+    let _: fn(NetcodeSocket) -> MultiClient<NetcodeSocket> = MultiClient::with_transceiver;
+    let _ =
+        |client: &mut MultiClient<NetcodeSocket>, token: &[u8]| -> netcode::Result<SessionHandle> {
+            client.connect(token, ClientConfig::default())
+        };
+    let _: fn(&mut MultiClient<NetcodeSocket>, f64) = MultiClient::update;
+    let _: fn(&mut MultiClient<NetcodeSocket>, f64) -> netcode::Result<()> =
+        MultiClient::try_update;
+    let _: fn(&MultiClient<NetcodeSocket>, SessionHandle) -> Option<&Session> =
+        MultiClient::session;
+    let _: fn(&mut MultiClient<NetcodeSocket>, SessionHandle) -> Option<&mut Session> =
+        MultiClient::session_mut;
+    let _ = |client: &MultiClient<NetcodeSocket>| client.sessions().collect::<Vec<SessionHandle>>();
+    let _: fn(&mut MultiClient<NetcodeSocket>, SessionHandle) -> netcode::Result<()> =
+        MultiClient::disconnect;
+    let _: fn(&mut MultiClient<NetcodeSocket>, SessionHandle) -> Option<Session> =
+        MultiClient::remove;
+    let _: fn(&MultiClient<NetcodeSocket>) -> usize = MultiClient::len;
+    let _: fn(&MultiClient<NetcodeSocket>) -> SocketAddr = MultiClient::addr;
+}
+
+#[test]
+fn client_signatures() {
+    let _: fn(&[u8]) -> netcode::Result<Client<NetcodeSocket>> = Client::new;
+    let _: fn(&[u8], ClientConfig<()>) -> netcode::Result<Client<NetcodeSocket>> =
+        Client::with_config;
+    let _: fn(&mut Client<NetcodeSocket>) = Client::connect;
+    let _: fn(&mut Client<NetcodeSocket>, f64) = Client::update;
+    let _: fn(&mut Client<NetcodeSocket>, f64) -> netcode::Result<()> = Client::try_update;
+    let _: fn(&mut Client<NetcodeSocket>) -> Option<Vec<u8>> = Client::recv;
+    let _: fn(&mut Client<NetcodeSocket>, &mut [u8]) -> Option<usize> = Client::recv_into;
+    let _: fn(&mut Client<NetcodeSocket>, &[u8]) -> netcode::Result<SendOutcome> = Client::send;
+    let _: fn(&mut Client<NetcodeSocket>, f64) -> netcode::Result<()> = Client::flush;
+    let _: fn(&mut Client<NetcodeSocket>, &[u8]) -> netcode::Result<()> =
+        Client::set_heartbeat_payload;
+    let _: fn(&mut Client<NetcodeSocket>) -> netcode::Result<()> = Client::disconnect;
+    let _: fn(&Client<NetcodeSocket>) -> ClientState = Client::state;
+    let _: fn(&Client<NetcodeSocket>) -> ClientStats = Client::stats;
+    let _: fn(&Client<NetcodeSocket>) -> SocketAddr = Client::addr;
+    let _: fn(&Client<NetcodeSocket>) -> Option<LinkCheckReport> = Client::link_check_report;
+    let _: fn(&Client<NetcodeSocket>) -> Option<u64> = Client::last_payload_sequence;
+    let _ = |client: &mut Client<NetcodeSocket>| client.acks().collect::<Vec<u64>>();
+    let _: fn(&Client<NetcodeSocket>) -> Option<f64> = Client::rtt;
+    let _: fn(&Client<NetcodeSocket>) -> f64 = Client::packet_loss;
+    let _: fn(&Client<NetcodeSocket>) -> ConnectionQuality = Client::connection_quality;
+    let _: fn(&Client<NetcodeSocket>) -> f64 = Client::send_budget;
+    let _: fn(&mut Client<NetcodeSocket>) -> Option<Vec<u8>> = Client::take_transfer_token;
+    let _: fn(&Client<NetcodeSocket>) -> Option<f64> = Client::estimated_server_tick;
+    let _: fn(&Client<NetcodeSocket>) -> f64 = Client::keep_alive_interval;
+}
+
+#[allow(clippy::type_complexity)]
+#[test]
+fn server_signatures() {
+    let _: fn(SocketAddr, u64, Key) -> netcode::Result<Server<NetcodeSocket>> = Server::new;
+    let _: fn(&mut Server<NetcodeSocket>, f64) = Server::update;
+    let _: fn(&mut Server<NetcodeSocket>, f64) -> netcode::Result<()> = Server::try_update;
+    let _: fn(&mut Server<NetcodeSocket>) -> Option<(Vec<u8>, ClientIndex)> = Server::recv;
+    let _: fn(&mut Server<NetcodeSocket>, &mut [u8]) -> Option<(ClientIndex, usize)> =
+        Server::recv_into;
+    let _: fn(&mut Server<NetcodeSocket>, ClientIndex) -> Option<Vec<u8>> = Server::recv_from;
+    let _: fn(&mut Server<NetcodeSocket>, &[u8], ClientIndex) -> netcode::Result<SendOutcome> =
+        Server::send;
+    let _: fn(&mut Server<NetcodeSocket>, u64) -> ConnectTokenBuilder<SocketAddr> = Server::token;
+    let _: fn(&mut Server<NetcodeSocket>, ClientIndex) -> netcode::Result<()> = Server::disconnect;
+    let _: fn(&mut Server<NetcodeSocket>) -> netcode::Result<()> = Server::flush;
+    let _: fn(&mut Server<NetcodeSocket>, f64) -> netcode::Result<Option<f64>> = Server::pace;
+    let _: fn(&mut Server<NetcodeSocket>, f64) -> netcode::Result<()> = Server::run_pacer;
+    let _: fn(&Server<NetcodeSocket>) -> ServerStats = Server::stats;
+    let _: fn(&mut Server<NetcodeSocket>, u64) -> netcode::Result<()> = Server::ban;
+    let _: fn(&mut Server<NetcodeSocket>, u64) -> bool = Server::unban;
+    let _: fn(&mut Server<NetcodeSocket>, bool) = Server::set_packet_logging;
+    let _: fn(&mut Server<NetcodeSocket>, &str) -> String = Server::admin_command;
+    let _: fn(&Server<NetcodeSocket>) -> usize = Server::num_pending_connections;
+    let _: fn(&Server<NetcodeSocket>) -> usize = Server::connected_count;
+    let _: fn(&Server<NetcodeSocket>) -> usize = Server::capacity;
+    let _: fn(&mut Server<NetcodeSocket>, u64, f64) -> netcode::Result<()> = Server::reserve_slot;
+    let _: fn(&Server<NetcodeSocket>, u64) -> Option<ProtocolStats> = Server::protocol_stats;
+    let _: fn(&Server<NetcodeSocket>, ClientIndex) -> Option<u64> = Server::client_id;
+    let _: fn(&mut Server<NetcodeSocket>, ClientIndex, ConnectToken) -> netcode::Result<()> =
+        Server::transfer_client;
+    let _: fn(&Server<NetcodeSocket>) -> u64 = Server::tick;
+    let _: fn(&mut Server<NetcodeSocket>, u64) = Server::set_tick;
+    let _: fn(&Server<NetcodeSocket>, ClientIndex) -> Option<u64> = Server::last_payload_sequence;
+    let _ = |server: &mut Server<NetcodeSocket>, idx| server.acks(idx).collect::<Vec<u64>>();
+    let _: fn(Server<NetcodeSocket>, std::time::Duration) -> netcode::Result<ShutdownReport> =
+        Server::shutdown;
+    let _: fn(&ServerCluster, usize, ServerConfig<()>) -> netcode::Result<Server<NetcodeSocket>> =
+        Server::with_cluster;
+    let _: fn(&mut Server<NetcodeSocket>, &mut Key) -> netcode::Result<()> =
+        Server::rotate_private_key;
+    let _: fn(&mut Server<NetcodeSocket>) -> ServerHandle = Server::handle;
+    let _: fn(&mut Server<NetcodeSocket>, ClientIndex, &[u8]) -> netcode::Result<()> =
+        Server::set_heartbeat_payload;
+    let _: fn(&ServerHandle, &[u8], ClientIndex) -> netcode::Result<()> = ServerHandle::send;
+    let _: fn(&ServerHandle, ClientIndex) -> netcode::Result<()> = ServerHandle::disconnect;
+    fn send_sync<T: Send + Sync + Clone>() {}
+    send_sync::<ServerHandle>();
+    send_sync::<Recorder>();
+}
+
+#[test]
+fn replay_signatures() {
+    let _: fn(std::fs::File) -> Recorder = Recorder::new;
+    let _: fn(&'static str) -> std::io::Result<Recorder> = Recorder::create;
+    let _: fn(&'static str) -> std::io::Result<Recording> = Recording::open;
+    let _: fn(&'static [u8]) -> std::io::Result<Recording> = Recording::read_from;
+    let _: fn(&Recording) -> u64 = Recording::protocol_id;
+    let _: fn(&Recording) -> SocketAddr = Recording::addr;
+    let _: fn(&Recording) -> usize = Recording::num_updates;
+    let _: fn(&Recording) -> usize = Recording::num_datagrams;
+    let _: fn(&Recording) -> Option<f64> = Recording::end_time;
+    let _: fn(Recording, Key) -> netcode::Result<Server<ReplayTransceiver>> = Server::replay;
+    let _: fn(Recording, Key, ServerConfig<()>) -> netcode::Result<Server<ReplayTransceiver>> =
+        Server::replay_with_config;
+    let _: fn(&mut Server<ReplayTransceiver>) -> netcode::Result<Option<f64>> =
+        Server::replay_update;
+    let _: fn(&ReplayTransceiver) -> usize = ReplayTransceiver::updates_left;
+}
+
+#[test]
+fn server_cluster() {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+    let cluster = ServerCluster::new(addr, 0x11, netcode::generate_key(), 2);
+    cluster.set_max_clients(100);
+    assert_eq!(cluster.clone().addr(), addr);
+    assert_eq!(cluster.protocol_id(), 0x11);
+    assert_eq!(cluster.num_shards(), 2);
+    assert_eq!(cluster.max_clients(), 100);
+    assert_eq!(cluster.num_connected_clients(), 0);
+    assert_eq!(cluster.shard_connected_clients(1), 0);
+    assert!(!cluster.is_full());
+    let _: ConnectTokenBuilder<SocketAddr> = cluster.token(42);
+}
+
+#[test]
+fn configs_are_built_with_setters() {
+    let _ = ClientConfig::default()
+        .num_disconnect_packets(5)
+        .packet_send_rate(0.1)
+        .keep_alive_interval(5.0)
+        .send_on_update(true)
+        .timeout_seconds(5)
+        .rekey_sessions(true)
+        .rekey_interval(3600.0)
+        .rebind_on_error(true)
+        .connect_config(
+            ConnectConfig::new()
+                .resend_interval(0.25)
+                .address_timeout(10.0)
+                .total_timeout(30.0)
+                .happy_eyeballs(0.25),
+        )
+        .max_payload_size(1000)
+        .coalesce_payloads(true)
+        .ack_payloads(true)
+        .tick_sync(60.0)
+        .pad_payloads(&[128, 512, 1200])
+        .congestion_send_rates(30.0, 10.0)
+        .socket_options(SocketOptions::new())
+        .allowed_packets(ClientState::Connected, PacketAllowList::NONE)
+        .strict_netcode_1_02(false)
+        .ack_on_sequence_gap(true)
+        .packet_logger(|_: &PacketRecord| {})
+        .on_state_change(|_, _, _| {})
+        .on_token_renew(5.0, |_, _| {})
+        .on_heartbeat(|_, _| {})
+        .on_transfer(|_, _| {})
+        .on_rebind(|_, _, _| {});
+    let _ = ClientConfig::with_context(0u32).disable_timeout();
+
+    let _ = ServerConfig::default()
+        .num_disconnect_packets(5)
+        .keep_alive_send_rate(0.1)
+        .keep_alive_interval(2.0)
+        .timeout_seconds(5)
+        .rekey_sessions(true)
+        .rekey_interval(3600.0)
+        .max_clients(128)
+        .max_pending_connections(64)
+        .pending_eviction(PendingEviction::LeastRecentlyUsed)
+        .pending_timeout(10.0)
+        .duplicate_client_id(DuplicateClientId::KickExisting)
+        .queries(QueryConfig::new("server").rate_limit(50))
+        .public_addrs(&[SocketAddr::from(([203, 0, 113, 7], 40000))])
+        .relay(RelayConfig::new(netcode::generate_key()).allow_direct(false))
+        .max_payload_size(1000)
+        .recv_queue_depth(64)
+        .recv_queue_overflow(QueueOverflow::DropOldest)
+        .echo_mode(EchoMode::Echo)
+        .allowed_packets(ConnectionPhase::Connected, PacketAllowList::NONE)
+        .accept_protocol_id(1)
+        .strict_netcode_1_02(false)
+        .ack_on_sequence_gap(true)
+        .allow_migration(true)
+        .coalesce_payloads(true)
+        .pace_packets(1.0 / 60.0, 256)
+        .ack_payloads(true)
+        .tick_sync(60.0)
+        .pad_payloads(&[128, 512, 1200])
+        .socket_options(SocketOptions::new())
+        .packet_logger(|_: &PacketRecord| {})
+        .record(Recorder::new(std::io::sink()))
+        .clock(SystemClock)
+        .rng(netcode::rand_core::OsRng)
+        .token_replay_store(|_: &[u8; 16], _: SocketAddr, _: u64| Ok(true))
+        .connect_filter(|_, _, _, _| ConnectDecision::Accept)
+        .on_connect(|_, _| {})
+        .on_disconnect(|_, _| {})
+        .challenge_data(|_, _, _| [0; CHALLENGE_DATA_BYTES])
+        .on_connect_with_data(|_, _, _| {})
+        .on_heartbeat(|_, _, _| {});
+    let _ = ServerConfig::with_context(0u32).disable_timeout();
+    #[cfg(unix)]
+    let _ = ServerConfig::default().admin_socket("/run/game/admin.sock");
+
+    let _: ServerConfig<()> = ServerConfig::competitive().tick_sync(60.0);
+    let _: ServerConfig<()> = ServerConfig::casual();
+    let _: ClientConfig<()> = ClientConfig::competitive().tick_sync(60.0);
+    let _: ClientConfig<()> = ClientConfig::casual();
+    let _: fn(&ServerConfig<u32>) -> Result<()> = ServerConfig::validate;
+    let _: fn(&ClientConfig<u32>) -> Result<()> = ClientConfig::validate;
+
+    let _ = LinkCheckConfig::new()
+        .num_probes(10)
+        .probe_size(100)
+        .probe_interval(0.1)
+        .timeout(1.0);
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compression_is_configured_with_setters() {
+    let _ = ClientConfig::default().compress_payloads(64);
+    let _ = ServerConfig::default().compress_payloads(64);
+}
+
+#[cfg(feature = "insecure")]
+#[test]
+fn insecure_connects() {
+    let _ = ServerConfig::default().allow_insecure(true);
+    let _: fn(SocketAddr, u64, u64) -> Result<Client<NetcodeSocket>> = Client::connect_insecure;
+}
+
+#[test]
+fn socket_constructors() {
+    let _ = |addr: SocketAddr| NetcodeSocket::new(addr, 1024, 1024).map(|socket| socket.addr());
+    let _ = |port: u16| NetcodeSocket::dual_stack(port, 1024, 1024).map(|socket| socket.addr());
+    let options = SocketOptions::new()
+        .tos(0xb8)
+        .dscp(46)
+        .ttl(64)
+        .reuse_port(true)
+        .device("eth0")
+        .segmentation_offload(true);
+    let _ = |addr: SocketAddr| NetcodeSocket::with_options(addr, 1024, 1024, &options).is_ok();
+    let _ = |socket: &NetcodeSocket| -> bool { socket.segmentation_offload() };
+}
+
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+#[test]
+fn io_uring_socket_constructors() {
+    use netcode::IoUringSocket;
+
+    let _ = |addr: SocketAddr| IoUringSocket::new(addr, 1024, 1024).map(|socket| socket.addr());
+    let _ = |port: u16| IoUringSocket::dual_stack(port, 1024, 1024).map(|socket| socket.addr());
+    let _ = |addr: SocketAddr| {
+        IoUringSocket::with_options(addr, 1024, 1024, &SocketOptions::new()).is_ok()
+    };
+    let _ = |socket: IoUringSocket| {
+        Server::with_config_and_transceiver(
+            0x11,
+            netcode::generate_key(),
+            ServerConfig::default(),
+            socket,
+        )
+    };
+}
+
+#[cfg(feature = "quic")]
+#[test]
+fn quic_transceiver_constructors() {
+    use netcode::QuicTransceiver;
+
+    let _ = |addr: SocketAddr, cfg: quinn::ServerConfig| {
+        QuicTransceiver::server(addr, cfg).map(|quic| quic.addr())
+    };
+    let _ = |addr: SocketAddr, cfg: quinn::ClientConfig| {
+        QuicTransceiver::client(addr, cfg, "localhost").map(|quic| quic.addr())
+    };
+}
+
+#[test]
+fn connect_token_accessors() {
+    let addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+    let token = ConnectToken::build(addr, 0x11, 42, netcode::generate_key())
+        .expire_seconds(30)
+        .timeout_seconds(10)
+        .generate_at(1_000)
+        .unwrap();
+    let _ = ConnectToken::build(addr, 0x11, 42, netcode::generate_key())
+        .generate_at_with_rng(1_000, &mut netcode::rand_core::OsRng)
+        .unwrap();
+    assert_eq!(token.protocol_id(), 0x11);
+    assert_eq!(token.create_timestamp(), 1_000);
+    assert_eq!(token.expire_timestamp(), 1_030);
+    assert_eq!(token.timeout_seconds(), 10);
+    assert_eq!(token.server_addresses().collect::<Vec<_>>(), [addr]);
+    let mut file = Vec::new();
+    let _: Result<()> = token.write_to(&mut file);
+    let _: Result<ConnectToken> = ConnectToken::read_from(std::io::Cursor::new(&file));
+    let bytes: [u8; CONNECT_TOKEN_BYTES] = token.try_into_bytes().unwrap();
+    assert_eq!(bytes.len(), CONNECT_TOKEN_BYTES);
+}
+
+#[test]
+fn token_crypto() {
+    use netcode::token_crypto::{self, ChallengeToken, PrivateConnectToken};
+
+    let _: usize = token_crypto::PRIVATE_CONNECT_TOKEN_BYTES;
+    let _: usize = token_crypto::CHALLENGE_TOKEN_BYTES;
+    let _: [u8; token_crypto::NONCE_BYTES] = token_crypto::generate_nonce();
+    let _: [u8; token_crypto::CONNECT_TOKEN_AAD_BYTES] = token_crypto::connect_token_aad(0x11, 0);
+    let _: [u8; 12] = token_crypto::challenge_token_nonce(0);
+
+    let key = netcode::generate_key();
+    let nonce = token_crypto::generate_nonce();
+    let mut token = PrivateConnectToken::new(1, vec![SocketAddr::from(([127, 0, 0, 1], 0))]);
+    token.timeout_seconds = -1;
+    token.user_data = [1; USER_DATA_BYTES];
+    let encrypted = token.encrypt(0x11, 0, &nonce, &key).unwrap();
+    let decrypted = PrivateConnectToken::decrypt(&encrypted, 0x11, 0, &nonce, &key).unwrap();
+    let _: (u64, Vec<SocketAddr>, Key, Key) = (
+        decrypted.client_id,
+        decrypted.server_addresses,
+        decrypted.client_to_server_key,
+        decrypted.server_to_client_key,
+    );
+
+    let challenge =
+        ChallengeToken::new(1, [0; USER_DATA_BYTES]).custom_data([1; CHALLENGE_DATA_BYTES]);
+    let encrypted = challenge.encrypt(0, &key).unwrap();
+    let decrypted = ChallengeToken::decrypt(&encrypted, 0, &key).unwrap();
+    let _: (u64, [u8; CHALLENGE_DATA_BYTES]) = (decrypted.client_id, decrypted.custom_data);
+}
+
+#[test]
+fn server_queries() {
+    let _: usize = query::QUERY_REQUEST_BYTES;
+    let _: usize = query::MAX_SERVER_NAME_BYTES;
+    let request: [u8; query::QUERY_REQUEST_BYTES] = query::request(0x11, 1);
+    let info: Result<ServerInfo> = query::read_response(&request, 1, None);
+    assert!(matches!(info, Err(Error::SizeMismatch(..))));
+    let _ = |info: ServerInfo| {
+        (
+            info.name,
+            info.protocol_id,
+            info.num_clients,
+            info.max_clients,
+        )
+    };
+    let _ = query::read_response(&request, 1, Some(&netcode::generate_key()));
+}
+
+#[test]
+fn lan_discovery() {
+    use netcode::discovery::{self, Beacon, BeaconConfig, Listener, ServerAd};
+    use std::time::Duration;
+
+    let _: u16 = discovery::DISCOVERY_PORT;
+    let cfg = BeaconConfig::new("lan")
+        .interval(0.5)
+        .broadcast_addr(SocketAddr::from(([127, 0, 0, 1], 0)))
+        .issue_tokens(true);
+    let beacon = Beacon::new(cfg).unwrap();
+    let _: SocketAddr = beacon.addr().unwrap();
+    let _: fn(&mut Beacon, &mut Server<NetcodeSocket>, f64) -> Result<()> = Beacon::update;
+    let _: fn(u16) -> Result<Listener> = Listener::bind;
+    let _: fn(&mut Listener, Duration) -> Result<Vec<ServerAd>> = Listener::recv_ads;
+    let _: fn(Duration) -> Result<Vec<ServerAd>> = discovery::discover;
+    let _: fn(&ServerAd, u64, Duration) -> Result<[u8; CONNECT_TOKEN_BYTES]> =
+        discovery::request_token;
+    let _ = |ad: ServerAd| {
+        (
+            ad.addr,
+            ad.beacon_addr,
+            ad.name,
+            ad.protocol_id,
+            ad.num_clients,
+            ad.max_clients,
+        )
+    };
+}
+
+#[test]
+fn relay_headers() {
+    let key = netcode::generate_key();
+    let addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+    let _: usize = relay::MAX_HEADER_BYTES;
+    let _: usize = relay::header_bytes(addr);
+    let datagram: Vec<u8> = relay::wrap_for_server(&key, addr, b"packet").unwrap();
+    let unwrapped: Result<(SocketAddr, &[u8])> = relay::unwrap_from_server(&key, &datagram);
+    assert!(unwrapped.is_err());
+}
+
+#[test]
+fn snapshot_channel() {
+    let mut channel = SnapshotChannel::new();
+    let _: u32 = channel.next_sequence();
+    let payload: Vec<u8> = channel.write(b"snapshot");
+    let _: usize = SnapshotChannel::HEADER_SIZE;
+    let received: Option<ReceivedSnapshot> = SnapshotChannel::new().read(&payload);
+    assert_eq!(received.map(|snapshot| snapshot.sequence), Some(1));
+    let _: (Option<u32>, Option<u32>) = (channel.acked(), channel.received());
+}
+
+#[test]
+fn jitter_buffer() {
+    let mut buffer = JitterBuffer::new(2);
+    let _: bool = buffer.push(1, b"snapshot");
+    let _: Option<(u32, Vec<u8>)> = buffer.pop();
+    buffer.set_delay(3);
+    let _: (u32, Option<u32>) = (buffer.delay(), buffer.next_tick());
+    let _: (usize, bool) = (buffer.len(), buffer.is_empty());
+    let _: JitterStats = buffer.stats();
+    buffer.clear();
+}
+
+// Downstream matches on the non-exhaustive enums need a wildcard arm, so adding variants isn't breaking.
+#[allow(unreachable_patterns)]
+#[test]
+fn enums_are_matched_with_wildcards() {
+    let describe = |state: ClientState| match state {
+        ClientState::Connected => "connected",
+        state if state.is_error() => "error",
+        state if state.is_pending() => "pending",
+        _ => "other",
+    };
+    assert_eq!(describe(ClientState::Connected), "connected");
+    assert_eq!(describe(ClientState::ConnectionDenied), "error");
+    assert_eq!(describe(ClientState::SendingChallengeResponse), "pending");
+    assert_eq!(describe(ClientState::Disconnected), "other");
+    assert!(ClientState::Disconnected.is_disconnected());
+
+    match PacketDirection::Sent {
+        PacketDirection::Sent => {}
+        _ => unreachable!(),
+    }
+
+    for ty in [PacketType::Request, PacketType::Payload] {
+        match ty {
+            PacketType::Payload | PacketType::Request => {}
+            _ => unreachable!(),
+        }
+    }
+    match ConnectionPhase::Pending {
+        ConnectionPhase::Pending => {}
+        _ => unreachable!(),
+    }
+    match ConnectionQuality::default() {
+        ConnectionQuality::Good => {}
+        _ => unreachable!(),
+    }
+    match PendingEviction::default() {
+        PendingEviction::Oldest => {}
+        _ => unreachable!(),
+    }
+    match DuplicateClientId::default() {
+        DuplicateClientId::RejectNew => {}
+        _ => unreachable!(),
+    }
+    match ConnectDecision::Deny("banned".to_string()) {
+        ConnectDecision::Accept => unreachable!(),
+        ConnectDecision::Deny(reason) => assert_eq!(reason, "banned"),
+        _ => unreachable!(),
+    }
+    match QueueOverflow::default() {
+        QueueOverflow::DropNewest => {}
+        _ => unreachable!(),
+    }
+    match EchoMode::default() {
+        EchoMode::Off => {}
+        _ => unreachable!(),
+    }
+    match SendOutcome::Sent(64) {
+        SendOutcome::Sent(len) => assert_eq!(len, 64),
+        SendOutcome::QueuedWouldBlock | SendOutcome::NotConnected => unreachable!(),
+        _ => unreachable!(),
+    }
+    let invalid_version = |err: &Error| match err {
+        Error::InvalidToken(InvalidTokenError::InvalidVersion) => true,
+        Error::InvalidToken(_) => false,
+        _ => false,
+    };
+    let err = Client::new(&[0; CONNECT_TOKEN_BYTES]).err().unwrap();
+    assert!(invalid_version(&err));
+}
+
+#[test]
+fn errors_are_categorized() {
+    let _: fn(&Error) -> u16 = Error::code;
+    let _: fn(&Error) -> bool = Error::is_transient;
+    let _: fn(&Error) -> bool = Error::is_remote;
+    let _ = |e: &Error| match e {
+        Error::Crypto(CryptoError::Failed(_)) => true,
+        Error::Packet(PacketError::AlreadyReceived(_)) => true,
+        Error::Socket(e) => e.io_error().kind() == std::io::ErrorKind::WouldBlock,
+        Error::InvalidConfig(ConfigError::TimeoutTooShort {
+            timeout_seconds, ..
+        }) => *timeout_seconds > 0,
+        Error::InvalidConfig(_) => false,
+        _ => false,
+    };
+    let _: fn(SocketError) -> Error = Error::from;
+}
+
+#[test]
+fn stats_fields_are_readable() {
+    fn client(stats: ClientStats) -> u64 {
+        stats.max_payload_size as u64
+            + stats.largest_payload_received as u64
+            + stats.out_of_phase.total()
+            + stats.sequence_gaps
+            + stats.rekeys
+            + stats.rebinds
+            + stats.packets_would_block
+            + stats.would_block_drops
+    }
+    fn server(stats: ServerStats) -> u64 {
+        stats.max_payload_size as u64
+            + stats.largest_payload_received as u64
+            + stats.out_of_phase.get(PacketType::KeepAlive)
+            + stats.sequence_gaps
+            + stats.challenge_key_rotations
+            + stats.migrations
+            + stats.pending_evictions
+            + stats.pending_timeouts
+            + stats.pending_connections as u64
+            + stats.pending_hits
+            + stats.pending_misses
+            + stats.rekeys
+            + stats.queries_answered
+            + stats.queries_rate_limited
+            + stats.invalid_relay_headers
+            + stats.recv_queue_overflows
+            + stats.pacing_drops
+            + stats.bandwidth_limit_drops
+            + stats.packets_would_block
+            + stats.would_block_drops
+    }
+    fn protocol(stats: ProtocolStats) -> u64 {
+        stats.connected_clients as u64
+            + stats.connections_accepted
+            + stats.packets_received
+            + stats.bytes_received
+    }
+    fn report(report: LinkCheckReport) -> f64 {
+        report.sent as f64
+            + report.received as f64
+            + report.loss_percent
+            + report.rtt_min
+            + report.rtt_avg
+            + report.rtt_max
+            + report.throughput_bytes_per_sec
+            + report.server_time_offset.unwrap_or_default()
+    }
+    let _: fn(PacketCounts) -> u64 = |counts| counts.total();
+    fn jitter(stats: JitterStats) -> u64 {
+        stats.received
+            + stats.released
+            + stats.late
+            + stats.duplicates
+            + stats.missing
+            + stats.underruns
+            + stats.resyncs
+            + stats.skipped
+    }
+    let _ = (client, server, protocol, report, jitter);
+    let _: fn(&ServerStats) -> f64 = ServerStats::pending_hit_rate;
+}
+
+#[test]
+fn extension_traits() {
+    struct NullTransceiver;
+    impl Transceiver for NullTransceiver {
+        type IntoError = std::io::Error;
+        fn addr(&self) -> SocketAddr {
+            SocketAddr::from(([127, 0, 0, 1], 0))
+        }
+        fn recv(&self, _: &mut [u8]) -> std::io::Result<Option<(usize, SocketAddr)>> {
+            Ok(None)
+        }
+        fn send(&self, buf: &[u8], _: SocketAddr) -> std::io::Result<usize> {
+            Ok(buf.len())
+        }
+    }
+    let server = Server::with_config_and_transceiver(
+        0x11,
+        netcode::generate_key(),
+        ServerConfig::default().clock(ManualClock::new(0)),
+        NullTransceiver,
+    )
+    .unwrap();
+    assert_eq!(server.num_connected_clients(), 0);
+    let addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+    assert_eq!(
+        NullTransceiver.send_segments(&[0; 25], 10, addr).unwrap(),
+        25
+    );
+    NullTransceiver.open_connection(addr).unwrap();
+    NullTransceiver.close_connection(addr).unwrap();
+
+    let clock = || 1_000u64;
+    assert_eq!(clock.unix_time(), 1_000);
+
+    struct Vault(Key);
+    impl KeyProvider for Vault {
+        fn private_key(&mut self) -> netcode::Result<Key> {
+            Ok(self.0)
+        }
+    }
+    let key = Vault([7; PRIVATE_KEY_BYTES]).private_key().unwrap();
+    assert_eq!(Key::from_base64(&key.to_base64()).unwrap(), key);
+    let _ = |path: &std::path::Path| -> netcode::Result<Key> { Key::from_file(path) };
+    let mut from_env = || Key::from_base64(&std::env::var("KEY").unwrap_or_default());
+    assert!(from_env.private_key().is_err());
+
+    struct SharedStore;
+    impl TokenReplayStore for SharedStore {
+        fn record_use(&mut self, _: &[u8; 16], _: SocketAddr, _: u64) -> netcode::Result<bool> {
+            Ok(false)
+        }
+    }
+    let addr = SocketAddr::from(([127, 0, 0, 1], 40000));
+    assert!(!SharedStore.record_use(&[0; 16], addr, 0).unwrap());
+}